@@ -95,7 +95,8 @@ echo "Instance will be named: $FINAL_INSTANCE_NAME"
 #########################################
 if [ -f "src/nautilus-server/allowed_endpoints.yaml" ]; then
     # Use a small Python snippet to parse the YAML and emit space-separated endpoints
-    ENDPOINTS=$(yq e '.endpoints | join(" ")' src/nautilus-server/allowed_endpoints.yaml 2>/dev/null)
+    # Entries are either a host or a map with a `host` key.
+    ENDPOINTS=$(yq e '[.endpoints[] | (select(tag == "!!str"), (select(tag == "!!map") | .host))] | join(" ")' src/nautilus-server/allowed_endpoints.yaml 2>/dev/null)
     if [ -n "$ENDPOINTS" ]; then
         echo "Endpoints found in src/nautilus-server/allowed_endpoints.yaml (before region patching):"
         echo "$ENDPOINTS"
@@ -421,21 +422,21 @@ done
 echo "Adding the following endpoint configuration to src/nautilus-server/run.sh:"
 echo "$endpoints_config"
 
-# Remove any existing endpoint lines (except the first localhost line)
+# Remove any existing endpoint lines (the localhost line is restored below)
 if [[ "$(uname)" == "Darwin" ]]; then
     # Remove only the IP mapping lines, preserving comments
     sed -i '' '/echo "127.0.0.[0-9]*   .*" >> \/etc\/hosts/d' src/nautilus-server/run.sh
     # Restore the localhost line if it was removed
-    if ! grep -q "echo \"127.0.0.1   localhost\" > /etc/hosts" src/nautilus-server/run.sh; then
+    if ! grep -q "echo \"127.0.0.1   localhost\" >> /etc/hosts" src/nautilus-server/run.sh; then
         sed -i '' '/# Add a hosts record/a\
-echo "127.0.0.1   localhost" > /etc/hosts' src/nautilus-server/run.sh
+echo "127.0.0.1   localhost" >> /etc/hosts' src/nautilus-server/run.sh
     fi
 else
     # Remove only the IP mapping lines, preserving comments
     sed -i '/echo "127.0.0.[0-9]*   .*" >> \/etc\/hosts/d' src/nautilus-server/run.sh
     # Restore the localhost line if it was removed
-    if ! grep -q "echo \"127.0.0.1   localhost\" > /etc/hosts" src/nautilus-server/run.sh; then
-        sed -i '/# Add a hosts record/a\echo "127.0.0.1   localhost" > /etc/hosts' src/nautilus-server/run.sh
+    if ! grep -q "echo \"127.0.0.1   localhost\" >> /etc/hosts" src/nautilus-server/run.sh; then
+        sed -i '/# Add a hosts record/a\echo "127.0.0.1   localhost" >> /etc/hosts' src/nautilus-server/run.sh
     fi
 fi
 
@@ -445,9 +446,9 @@ echo "$endpoints_config" > "$tmp_hosts"
 
 # Insert after the localhost line
 if [[ "$(uname)" == "Darwin" ]]; then
-    sed -i '' "/echo \"127.0.0.1   localhost\" > \/etc\/hosts/ r $tmp_hosts" src/nautilus-server/run.sh
+    sed -i '' "/echo \"127.0.0.1   localhost\" >> \/etc\/hosts/ r $tmp_hosts" src/nautilus-server/run.sh
 else
-    sed -i "/echo \"127.0.0.1   localhost\" > \/etc\/hosts/ r $tmp_hosts" src/nautilus-server/run.sh
+    sed -i "/echo \"127.0.0.1   localhost\" >> \/etc\/hosts/ r $tmp_hosts" src/nautilus-server/run.sh
 fi
 rm "$tmp_hosts"
 
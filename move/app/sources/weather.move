@@ -5,6 +5,7 @@ module app::weather;
 
 use enclave::enclave::{Self, Enclave};
 use std::string::String;
+use sui::clock::Clock;
 
 /// ====
 /// Core onchain app logic, replace it with your own.
@@ -65,6 +66,35 @@ public fun update_weather<T>(
     }
 }
 
+/// Same as `update_weather`, for enclaves started with `SIGN_EXPIRY_MS`.
+/// Aborts if the reading has expired.
+public fun update_weather_with_expiry<T>(
+    location: String,
+    temperature: u64,
+    timestamp_ms: u64,
+    expires_at_ms: u64,
+    sig: &vector<u8>,
+    enclave: &Enclave<T>,
+    clock: &Clock,
+    ctx: &mut TxContext,
+): WeatherNFT {
+    let res = enclave.verify_signature_with_expiry(
+        WEATHER_INTENT,
+        timestamp_ms,
+        expires_at_ms,
+        WeatherResponse { location, temperature },
+        sig,
+        clock,
+    );
+    assert!(res, EInvalidSignature);
+    WeatherNFT {
+        id: object::new(ctx),
+        location,
+        temperature,
+        timestamp_ms,
+    }
+}
+
 #[test]
 fun test_weather_flow() {
     use sui::test_scenario::{Self, ctx, next_tx};
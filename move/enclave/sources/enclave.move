@@ -7,11 +7,20 @@ module enclave::enclave;
 
 use std::bcs;
 use std::string::String;
+use sui::clock::Clock;
 use sui::ed25519;
 use sui::nitro_attestation::NitroAttestationDocument;
 
 const EInvalidPCRs: u64 = 0;
 const EInvalidConfigVersion: u64 = 1;
+const EExpired: u64 = 2;
+
+// Layout versions of messages with an expiry or a sequence number,
+// `EXPIRY_LAYOUT` and `SEQUENCE_LAYOUT` in `nautilus_verifier::intent`. The
+// version follows the intent so that a message signed in one layout never
+// verifies in another, e.g. an expiry as a sequence number.
+const EXPIRY_LAYOUT: u8 = 1;
+const SEQUENCE_LAYOUT: u8 = 2;
 
 // The expected PCRs.
 // - We only define the first 3 PCRs. One can define other
@@ -47,6 +56,58 @@ public struct IntentMessage<T: drop> has copy, drop {
     payload: T,
 }
 
+// An intent message that also commits to when it expires, signed by enclaves
+// started with `SIGN_EXPIRY_MS`. `version` and `expires_at_ms` are always set;
+// they are Options so the BCS bytes match the Rust `Option` fields.
+public struct IntentMessageWithExpiry<T: drop> has copy, drop {
+    intent: u8,
+    version: Option<u8>,
+    timestamp_ms: u64,
+    expires_at_ms: Option<u64>,
+    payload: T,
+}
+
+fun create_intent_message_with_expiry<P: drop>(
+    intent: u8,
+    timestamp_ms: u64,
+    expires_at_ms: u64,
+    payload: P,
+): IntentMessageWithExpiry<P> {
+    IntentMessageWithExpiry {
+        intent,
+        version: option::some(EXPIRY_LAYOUT),
+        timestamp_ms,
+        expires_at_ms: option::some(expires_at_ms),
+        payload,
+    }
+}
+
+// An intent message that also commits to its sequence number, signed by
+// enclaves started with `SIGN_SEQUENCE`. Like `expires_at_ms`, `version` and
+// `sequence` are always set and are Options to match the Rust fields.
+public struct IntentMessageWithSequence<T: drop> has copy, drop {
+    intent: u8,
+    version: Option<u8>,
+    timestamp_ms: u64,
+    sequence: Option<u64>,
+    payload: T,
+}
+
+fun create_intent_message_with_sequence<P: drop>(
+    intent: u8,
+    timestamp_ms: u64,
+    sequence: u64,
+    payload: P,
+): IntentMessageWithSequence<P> {
+    IntentMessageWithSequence {
+        intent,
+        version: option::some(SEQUENCE_LAYOUT),
+        timestamp_ms,
+        sequence: option::some(sequence),
+        payload,
+    }
+}
+
 fun create_intent_message<P: drop>(intent: u8, timestamp_ms: u64, payload: P): IntentMessage<P> {
     IntentMessage {
         intent,
@@ -118,6 +179,53 @@ public fun verify_signature<T, P: drop>(
     return ed25519::ed25519_verify(signature, &enclave.pk, &payload)
 }
 
+// Verify a message signed with an expiry, aborting once `clock` has reached
+// `expires_at_ms`. Use this instead of `verify_signature` for enclaves
+// started with `SIGN_EXPIRY_MS`, whose messages have the extra field.
+public fun verify_signature_with_expiry<T, P: drop>(
+    enclave: &Enclave<T>,
+    intent_scope: u8,
+    timestamp_ms: u64,
+    expires_at_ms: u64,
+    payload: P,
+    signature: &vector<u8>,
+    clock: &Clock,
+): bool {
+    assert!(clock.timestamp_ms() < expires_at_ms, EExpired);
+    let intent_message = create_intent_message_with_expiry(
+        intent_scope,
+        timestamp_ms,
+        expires_at_ms,
+        payload,
+    );
+    let payload = bcs::to_bytes(&intent_message);
+    return ed25519::ed25519_verify(signature, &enclave.pk, &payload)
+}
+
+// Verify a message signed with its sequence number. Use this instead of
+// `verify_signature` for enclaves started with `SIGN_SEQUENCE`. Checking that
+// sequence numbers arrive in order is left to the caller, since they restart
+// from zero whenever the enclave, and so its key, changes. The server refuses
+// `SIGN_SEQUENCE` with a provisioned `SIGNING_KEY_PATH`, whose key outlives a
+// restart.
+public fun verify_signature_with_sequence<T, P: drop>(
+    enclave: &Enclave<T>,
+    intent_scope: u8,
+    timestamp_ms: u64,
+    sequence: u64,
+    payload: P,
+    signature: &vector<u8>,
+): bool {
+    let intent_message = create_intent_message_with_sequence(
+        intent_scope,
+        timestamp_ms,
+        sequence,
+        payload,
+    );
+    let payload = bcs::to_bytes(&intent_message);
+    return ed25519::ed25519_verify(signature, &enclave.pk, &payload)
+}
+
 public fun update_pcrs<T: drop>(
     config: &mut EnclaveConfig<T>,
     _cap: &Cap<T>,
@@ -177,7 +285,8 @@ public struct SigningPayload has copy, drop {
 
 #[test]
 fun test_serde() {
-    // serialization should be consistent with rust test see `fn test_serde` in `src/nautilus-server/app.rs`.
+    // serialization should be consistent with the test vectors generated by
+    // `src/nautilus-server/src/vectors.rs`, also checked by `fn test_serde`.
     use std::string;
 
     let scope = 0;
@@ -191,5 +300,137 @@ fun test_serde() {
         },
     );
     let bytes = bcs::to_bytes(&signing_payload);
-    assert!(bytes == x"0020b1d110960100000d53616e204672616e636973636f0d00000000000000", 0);
+    assert!(bytes == enclave::test_vectors::weather_payload(), 0);
+    assert!(
+        ed25519::ed25519_verify(
+            &enclave::test_vectors::weather_signature(),
+            &enclave::test_vectors::public_key(),
+            &bytes,
+        ),
+        1,
+    );
+}
+
+#[test]
+fun test_serde_with_expiry() {
+    // serialization should be consistent with the test vectors, also checked
+    // by `fn test_serde_with_expiry`.
+    use std::string;
+
+    let signing_payload = create_intent_message_with_expiry(
+        0,
+        1744038900000,
+        1744038960000,
+        SigningPayload {
+            location: string::utf8(b"San Francisco"),
+            temperature: 13,
+        },
+    );
+    let bytes = bcs::to_bytes(&signing_payload);
+    assert!(bytes == enclave::test_vectors::weather_expiring_payload(), 0);
+    assert!(
+        ed25519::ed25519_verify(
+            &enclave::test_vectors::weather_expiring_signature(),
+            &enclave::test_vectors::public_key(),
+            &bytes,
+        ),
+        1,
+    );
+}
+
+#[test]
+fun test_serde_with_sequence() {
+    // serialization should be consistent with the test vectors, also checked
+    // by `fn test_serde_with_sequence`.
+    use std::string;
+
+    let signing_payload = create_intent_message_with_sequence(
+        0,
+        1744038900000,
+        7,
+        SigningPayload {
+            location: string::utf8(b"San Francisco"),
+            temperature: 13,
+        },
+    );
+    let bytes = bcs::to_bytes(&signing_payload);
+    assert!(bytes == enclave::test_vectors::weather_sequenced_payload(), 0);
+    assert!(
+        ed25519::ed25519_verify(
+            &enclave::test_vectors::weather_sequenced_signature(),
+            &enclave::test_vectors::public_key(),
+            &bytes,
+        ),
+        1,
+    );
+}
+
+#[test]
+fun test_layouts_do_not_collide() {
+    // A message signed with an expiry doesn't verify as one with a sequence
+    // number of the same value, or the other way around. Also checked by
+    // `fn test_layouts_do_not_collide` in nautilus-verifier.
+    use std::string;
+
+    let payload = SigningPayload {
+        location: string::utf8(b"San Francisco"),
+        temperature: 13,
+    };
+    let as_sequenced = create_intent_message_with_sequence(
+        0,
+        1744038900000,
+        1744038960000,
+        payload,
+    );
+    assert!(
+        !ed25519::ed25519_verify(
+            &enclave::test_vectors::weather_expiring_signature(),
+            &enclave::test_vectors::public_key(),
+            &bcs::to_bytes(&as_sequenced),
+        ),
+        0,
+    );
+    let as_expiring = create_intent_message_with_expiry(0, 1744038900000, 7, payload);
+    assert!(
+        !ed25519::ed25519_verify(
+            &enclave::test_vectors::weather_sequenced_signature(),
+            &enclave::test_vectors::public_key(),
+            &bcs::to_bytes(&as_expiring),
+        ),
+        1,
+    );
+}
+
+#[test_only]
+public struct CompressedPayload has copy, drop {
+    payload_intent: u8,
+    algorithm: u8,
+    uncompressed_len: u64,
+    compressed_sha256: vector<u8>,
+}
+
+#[test]
+fun test_serde_compressed() {
+    // a commitment to a compressed payload, see `compression.rs` in
+    // nautilus-verifier. Only the commitment is verified on-chain.
+    let signing_payload = create_intent_message(
+        5,
+        1744038900000,
+        CompressedPayload {
+            payload_intent: 16,
+            algorithm: 1,
+            uncompressed_len: 4096,
+            compressed_sha256: x"cdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcd",
+        },
+    );
+    let bytes = bcs::to_bytes(&signing_payload);
+    assert!(bytes == enclave::test_vectors::compressed_payload(), 0);
+    assert!(
+        ed25519::ed25519_verify(
+            &enclave::test_vectors::compressed_signature(),
+            &enclave::test_vectors::public_key(),
+            &bytes,
+        ),
+        1,
+    );
 }
@@ -0,0 +1,204 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+// Generated by `cargo run --bin nautilus-vectors` in `src/nautilus-server`
+// from `src/vectors.rs`. Do not edit.
+
+#[test_only]
+module enclave::test_vectors;
+
+const PUBLIC_KEY: vector<u8> = x"ea4a6c63e29c520abef5507b132ec5f9954776aebebe7b92421eea691446d22c";
+
+// WeatherResponse under intent scope 0
+const WEATHER_PAYLOAD: vector<u8> = x"0020b1d110960100000d53616e204672616e636973636f0d00000000000000";
+const WEATHER_SIGNATURE: vector<u8> = x"fc1583d7db7a7a5d91f475ade340f02e7ec0a09e6f3a4b2786d99bca32bab9ffd6750f48db7b534e00c4d0d49ef0a3c8371c8f411ce80fae2041fe76d5754403";
+
+// WeatherResponse under intent scope 0
+const WEATHER_EXPIRING_PAYLOAD: vector<u8> = x"00010120b1d1109601000001809bd210960100000d53616e204672616e636973636f0d00000000000000";
+const WEATHER_EXPIRING_SIGNATURE: vector<u8> = x"7d3ffa24bf6e8d0b9a6ef16d4e2981556579253c1e70ba02ee44872b3b9c55cf268492f325647a6b01341fd25bf01882f92d1f4c6cdd4e568fb417fd3906fd0c";
+
+// WeatherResponse under intent scope 0
+const WEATHER_SEQUENCED_PAYLOAD: vector<u8> = x"00010220b1d110960100000107000000000000000d53616e204672616e636973636f0d00000000000000";
+const WEATHER_SEQUENCED_SIGNATURE: vector<u8> = x"5aa9d43de58d0b812601f50964d1f8dadcbf430bc6267ac82e8d2e19d88956018dc5fc31648e444f66f09cf3ba0459edf11966eb378a7e112dd5dfaf7af7860d";
+
+// WeatherResponseWithAttestationDigest under intent scope 1
+const WEATHER_WITH_ATTESTATION_DIGEST_PAYLOAD: vector<u8> = x"0120b1d110960100000d53616e204672616e636973636f0d0000000000000030abababababababababababababababababababababababababababababababababababababababababababababababab";
+const WEATHER_WITH_ATTESTATION_DIGEST_SIGNATURE: vector<u8> = x"26f5dca7136b0d345e6e4cfb6a393eec74ff5927ff4aacc8b0cb5ec8fe1268c305f8e5e475da87ceb4ea3eadb3ce730e9a660e60b92d1a38cf75220debd2730f";
+
+// WeatherResponseWithAttestationDigest under intent scope 1
+const WEATHER_WITH_ATTESTATION_DIGEST_EXPIRING_PAYLOAD: vector<u8> = x"01010120b1d1109601000001809bd210960100000d53616e204672616e636973636f0d0000000000000030abababababababababababababababababababababababababababababababababababababababababababababababab";
+const WEATHER_WITH_ATTESTATION_DIGEST_EXPIRING_SIGNATURE: vector<u8> = x"bd851dbe8d599cd17ef0f30ffc4b2b0c4974e0cb5be63c34eb36b62bdb054d3a3ea4395dc6a6e52630fadd66080be07c7a24fc2263f22e534a1f890f3446e305";
+
+// WeatherResponseWithAttestationDigest under intent scope 1
+const WEATHER_WITH_ATTESTATION_DIGEST_SEQUENCED_PAYLOAD: vector<u8> = x"01010220b1d110960100000107000000000000000d53616e204672616e636973636f0d0000000000000030abababababababababababababababababababababababababababababababababababababababababababababababab";
+const WEATHER_WITH_ATTESTATION_DIGEST_SEQUENCED_SIGNATURE: vector<u8> = x"9e6fbb9c94d72b7356c1c2d23fcca6831e70c57eae0ba3c16fbca4b7688392dd6e0fc0a99da2b4bb48419a1e23db204c0bed5bd118cf62c1fbbe0532d85d650d";
+
+// WeatherResponseWithUpstreamLatency under intent scope 2
+const WEATHER_WITH_UPSTREAM_LATENCY_PAYLOAD: vector<u8> = x"0220b1d110960100000d53616e204672616e636973636f0d00000000000000fa00000000000000";
+const WEATHER_WITH_UPSTREAM_LATENCY_SIGNATURE: vector<u8> = x"fe68a89288d10e956e5e0b95ed2f6d7f2bc6a30a0416a6235deeb8c6a7fa85babbba93047e602d92340a6a77694c0eb80b7f2c603898668d4a1a6bcf703a0d0f";
+
+// WeatherResponseWithUpstreamLatency under intent scope 2
+const WEATHER_WITH_UPSTREAM_LATENCY_EXPIRING_PAYLOAD: vector<u8> = x"02010120b1d1109601000001809bd210960100000d53616e204672616e636973636f0d00000000000000fa00000000000000";
+const WEATHER_WITH_UPSTREAM_LATENCY_EXPIRING_SIGNATURE: vector<u8> = x"433d5b04027bf99503a762d186bfa0c7e46ed40b3e44de0cf1abf5f5e10d504f18eef9a456ddabaab9ad084c64f81871e071c75d5e2b263377c2dcb8a28a1b0d";
+
+// WeatherResponseWithUpstreamLatency under intent scope 2
+const WEATHER_WITH_UPSTREAM_LATENCY_SEQUENCED_PAYLOAD: vector<u8> = x"02010220b1d110960100000107000000000000000d53616e204672616e636973636f0d00000000000000fa00000000000000";
+const WEATHER_WITH_UPSTREAM_LATENCY_SEQUENCED_SIGNATURE: vector<u8> = x"a7aa23119cacc96265800261a16a871d1b610daac0fc245e0d5cc781ba6f20013a95561fd58bb98141547517019480bc08d8f9c648964bf8215be126b3cf2c08";
+
+// WeatherFields under intent scope 3
+const WEATHER_FIELDS_PAYLOAD: vector<u8> = x"0320b1d11096010000021163757272656e742e636f6e646974696f6e1c7b22636f6465223a313030302c2274657874223a2253756e6e79227d0d6c6f636174696f6e2e6e616d650f2253616e204672616e636973636f22";
+const WEATHER_FIELDS_SIGNATURE: vector<u8> = x"91ff0ea1455f03404b106349bea5683be25771b19d4305a48a2ffa45cfa29073961b6522d5985bcf6e1e128433e5eb43a9e84940187d7396eb7d37f045e58800";
+
+// WeatherFields under intent scope 3
+const WEATHER_FIELDS_EXPIRING_PAYLOAD: vector<u8> = x"03010120b1d1109601000001809bd21096010000021163757272656e742e636f6e646974696f6e1c7b22636f6465223a313030302c2274657874223a2253756e6e79227d0d6c6f636174696f6e2e6e616d650f2253616e204672616e636973636f22";
+const WEATHER_FIELDS_EXPIRING_SIGNATURE: vector<u8> = x"2187b491f3f17f490cca95efbb6ebff85d9d72881e8c22d85d55fb969037364ce72c8ebb89248abeaa5be92d5a21c3ad6647f01f6826569ac128b3cbaef2980d";
+
+// WeatherFields under intent scope 3
+const WEATHER_FIELDS_SEQUENCED_PAYLOAD: vector<u8> = x"03010220b1d11096010000010700000000000000021163757272656e742e636f6e646974696f6e1c7b22636f6465223a313030302c2274657874223a2253756e6e79227d0d6c6f636174696f6e2e6e616d650f2253616e204672616e636973636f22";
+const WEATHER_FIELDS_SEQUENCED_SIGNATURE: vector<u8> = x"04c7685c1af9d847d52089942cb5b9f5ecf279591e6efbc8800aa9eeefea86b2b27ec9dd0b090fba431b21d4064476b98bf2f926222e6804c039a54d399c7201";
+
+// PriceResponse under intent scope 4
+const PRICE_PAYLOAD: vector<u8> = x"0420b1d11096010000034254430355534440c08bfdd405000008";
+const PRICE_SIGNATURE: vector<u8> = x"0d5a93b9e314831b6a81e959b16a88f5eddc5b8aab1a3a4b7d1d071c36c7ce363f9b58566bfdd9f44374978b3438994de53130f2a7eba9f7ba45245def34100a";
+
+// PriceResponse under intent scope 4
+const PRICE_EXPIRING_PAYLOAD: vector<u8> = x"04010120b1d1109601000001809bd21096010000034254430355534440c08bfdd405000008";
+const PRICE_EXPIRING_SIGNATURE: vector<u8> = x"99834eab82d12d5f045ef52f376e17e9eb33f6b0d791f70c48bcdfba55e25a12235bce1ec1b88046add004b2b8979729a2d1e78cf9bd5aa2d31325bf1a44e906";
+
+// PriceResponse under intent scope 4
+const PRICE_SEQUENCED_PAYLOAD: vector<u8> = x"04010220b1d11096010000010700000000000000034254430355534440c08bfdd405000008";
+const PRICE_SEQUENCED_SIGNATURE: vector<u8> = x"917d403f15424b13449b5605e272db36243649c44e4426006fada2de57ca9232f2a168d4b48ee127f432f11a2fe72d85b98cddd8553e0246595fa81b91c5c404";
+
+// CompressedPayload under intent scope 5
+const COMPRESSED_PAYLOAD: vector<u8> = x"0520b1d110960100001001001000000000000020cdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcd";
+const COMPRESSED_SIGNATURE: vector<u8> = x"f378b617fd21e181962e1e9abf62d7509a4063cbb4a667b72fb337b8e460659cfc72189a07a6c3d3df93e133f1af400707649df821e622fe300bd5030f23b002";
+
+// CompressedPayload under intent scope 5
+const COMPRESSED_EXPIRING_PAYLOAD: vector<u8> = x"05010120b1d1109601000001809bd210960100001001001000000000000020cdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcd";
+const COMPRESSED_EXPIRING_SIGNATURE: vector<u8> = x"c41f49839772688fb1fdf30df0bc9c6de381a988c7c2c0f6fc46604c89242927a8f065a5a354bf5b6aa5eb007d2ad4e0c7943e048b450eb6b9480d1646537909";
+
+// CompressedPayload under intent scope 5
+const COMPRESSED_SEQUENCED_PAYLOAD: vector<u8> = x"05010220b1d110960100000107000000000000001001001000000000000020cdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcdcd";
+const COMPRESSED_SEQUENCED_SIGNATURE: vector<u8> = x"83a7f46b940c1947b7a6d11c7f378fd433e15516f3d8d223ee4599bcdfe517d0ad0aea1fa0c44d935d670f4a3b3419c106925cfd87f1e551278aefdd1ef6de00";
+
+// GithubCommit under intent scope 6
+const GITHUB_COMMIT_PAYLOAD: vector<u8> = x"0620b1d11096010000136f63746f6361742f48656c6c6f2d576f726c642836646362303962356235373837356633333466363161656265643639356532653431393364623565104d6f6e616c697361204f63746f636174684fbe542f010000";
+const GITHUB_COMMIT_SIGNATURE: vector<u8> = x"7e7615b8b24b8764ad4c80befdcbcce8b7306ce36fa561b68a6dd9114c1f5caa09b7b145cc04b7c37f17f5d6d9296f426eeb9f2a43704ded8700683a42f3e10a";
+
+// GithubCommit under intent scope 6
+const GITHUB_COMMIT_EXPIRING_PAYLOAD: vector<u8> = x"06010120b1d1109601000001809bd21096010000136f63746f6361742f48656c6c6f2d576f726c642836646362303962356235373837356633333466363161656265643639356532653431393364623565104d6f6e616c697361204f63746f636174684fbe542f010000";
+const GITHUB_COMMIT_EXPIRING_SIGNATURE: vector<u8> = x"ce80f65db466456e2713b5ff06a1ab5e6abc4d6408fc09b19deb3e58d7538deb20deaed0cefd05ae3a6660fa80c7bac9cd1250d55eb86483892cc96815c46a04";
+
+// GithubCommit under intent scope 6
+const GITHUB_COMMIT_SEQUENCED_PAYLOAD: vector<u8> = x"06010220b1d11096010000010700000000000000136f63746f6361742f48656c6c6f2d576f726c642836646362303962356235373837356633333466363161656265643639356532653431393364623565104d6f6e616c697361204f63746f636174684fbe542f010000";
+const GITHUB_COMMIT_SEQUENCED_SIGNATURE: vector<u8> = x"bf18d3fd7e18d6e0f9d58dfc031f5a533744dea403851977fe47bd6503a84aa611eb548ff3a1f69e9e8b6d38371a3b96bdf0c6a64a8ef286cdbe5591ec87900a";
+
+// TransactionApproval under intent scope 7
+const SUI_TRANSACTION_PAYLOAD: vector<u8> = x"0720b1d1109601000010eeeeeeeeeeeeeeeeeeeeeeeeeeeeeeee";
+const SUI_TRANSACTION_SIGNATURE: vector<u8> = x"b36297348f686aa01e488920aba4b19a5388beac9d94bad6e47e69d17aa5fdeeccc312c05441597dd4c961b2e07f1f7219d4ece8619d6829050dac280c1c4903";
+
+// TransactionApproval under intent scope 7
+const SUI_TRANSACTION_EXPIRING_PAYLOAD: vector<u8> = x"07010120b1d1109601000001809bd2109601000010eeeeeeeeeeeeeeeeeeeeeeeeeeeeeeee";
+const SUI_TRANSACTION_EXPIRING_SIGNATURE: vector<u8> = x"c4188e4ee7351bbd667ad0024358f114ce289ed295ec65a943f8ed5cba70beb71aaf272e322056b5fe7e6bbf213f96ed65d74d7f035d209117b0198ae94fe70f";
+
+// TransactionApproval under intent scope 7
+const SUI_TRANSACTION_SEQUENCED_PAYLOAD: vector<u8> = x"07010220b1d1109601000001070000000000000010eeeeeeeeeeeeeeeeeeeeeeeeeeeeeeee";
+const SUI_TRANSACTION_SEQUENCED_SIGNATURE: vector<u8> = x"15de2cc2b23d601e2b9f6ed989433c6ca0f5afcd4310237c7fa1e28023dfcef1b7072a22f5ce221dae6c3cd77b42017cbb5123c9263182fcac2d9274de415108";
+
+public fun public_key(): vector<u8> { PUBLIC_KEY }
+
+public fun weather_payload(): vector<u8> { WEATHER_PAYLOAD }
+
+public fun weather_signature(): vector<u8> { WEATHER_SIGNATURE }
+
+public fun weather_expiring_payload(): vector<u8> { WEATHER_EXPIRING_PAYLOAD }
+
+public fun weather_expiring_signature(): vector<u8> { WEATHER_EXPIRING_SIGNATURE }
+
+public fun weather_sequenced_payload(): vector<u8> { WEATHER_SEQUENCED_PAYLOAD }
+
+public fun weather_sequenced_signature(): vector<u8> { WEATHER_SEQUENCED_SIGNATURE }
+
+public fun weather_with_attestation_digest_payload(): vector<u8> { WEATHER_WITH_ATTESTATION_DIGEST_PAYLOAD }
+
+public fun weather_with_attestation_digest_signature(): vector<u8> { WEATHER_WITH_ATTESTATION_DIGEST_SIGNATURE }
+
+public fun weather_with_attestation_digest_expiring_payload(): vector<u8> { WEATHER_WITH_ATTESTATION_DIGEST_EXPIRING_PAYLOAD }
+
+public fun weather_with_attestation_digest_expiring_signature(): vector<u8> { WEATHER_WITH_ATTESTATION_DIGEST_EXPIRING_SIGNATURE }
+
+public fun weather_with_attestation_digest_sequenced_payload(): vector<u8> { WEATHER_WITH_ATTESTATION_DIGEST_SEQUENCED_PAYLOAD }
+
+public fun weather_with_attestation_digest_sequenced_signature(): vector<u8> { WEATHER_WITH_ATTESTATION_DIGEST_SEQUENCED_SIGNATURE }
+
+public fun weather_with_upstream_latency_payload(): vector<u8> { WEATHER_WITH_UPSTREAM_LATENCY_PAYLOAD }
+
+public fun weather_with_upstream_latency_signature(): vector<u8> { WEATHER_WITH_UPSTREAM_LATENCY_SIGNATURE }
+
+public fun weather_with_upstream_latency_expiring_payload(): vector<u8> { WEATHER_WITH_UPSTREAM_LATENCY_EXPIRING_PAYLOAD }
+
+public fun weather_with_upstream_latency_expiring_signature(): vector<u8> { WEATHER_WITH_UPSTREAM_LATENCY_EXPIRING_SIGNATURE }
+
+public fun weather_with_upstream_latency_sequenced_payload(): vector<u8> { WEATHER_WITH_UPSTREAM_LATENCY_SEQUENCED_PAYLOAD }
+
+public fun weather_with_upstream_latency_sequenced_signature(): vector<u8> { WEATHER_WITH_UPSTREAM_LATENCY_SEQUENCED_SIGNATURE }
+
+public fun weather_fields_payload(): vector<u8> { WEATHER_FIELDS_PAYLOAD }
+
+public fun weather_fields_signature(): vector<u8> { WEATHER_FIELDS_SIGNATURE }
+
+public fun weather_fields_expiring_payload(): vector<u8> { WEATHER_FIELDS_EXPIRING_PAYLOAD }
+
+public fun weather_fields_expiring_signature(): vector<u8> { WEATHER_FIELDS_EXPIRING_SIGNATURE }
+
+public fun weather_fields_sequenced_payload(): vector<u8> { WEATHER_FIELDS_SEQUENCED_PAYLOAD }
+
+public fun weather_fields_sequenced_signature(): vector<u8> { WEATHER_FIELDS_SEQUENCED_SIGNATURE }
+
+public fun price_payload(): vector<u8> { PRICE_PAYLOAD }
+
+public fun price_signature(): vector<u8> { PRICE_SIGNATURE }
+
+public fun price_expiring_payload(): vector<u8> { PRICE_EXPIRING_PAYLOAD }
+
+public fun price_expiring_signature(): vector<u8> { PRICE_EXPIRING_SIGNATURE }
+
+public fun price_sequenced_payload(): vector<u8> { PRICE_SEQUENCED_PAYLOAD }
+
+public fun price_sequenced_signature(): vector<u8> { PRICE_SEQUENCED_SIGNATURE }
+
+public fun compressed_payload(): vector<u8> { COMPRESSED_PAYLOAD }
+
+public fun compressed_signature(): vector<u8> { COMPRESSED_SIGNATURE }
+
+public fun compressed_expiring_payload(): vector<u8> { COMPRESSED_EXPIRING_PAYLOAD }
+
+public fun compressed_expiring_signature(): vector<u8> { COMPRESSED_EXPIRING_SIGNATURE }
+
+public fun compressed_sequenced_payload(): vector<u8> { COMPRESSED_SEQUENCED_PAYLOAD }
+
+public fun compressed_sequenced_signature(): vector<u8> { COMPRESSED_SEQUENCED_SIGNATURE }
+
+public fun github_commit_payload(): vector<u8> { GITHUB_COMMIT_PAYLOAD }
+
+public fun github_commit_signature(): vector<u8> { GITHUB_COMMIT_SIGNATURE }
+
+public fun github_commit_expiring_payload(): vector<u8> { GITHUB_COMMIT_EXPIRING_PAYLOAD }
+
+public fun github_commit_expiring_signature(): vector<u8> { GITHUB_COMMIT_EXPIRING_SIGNATURE }
+
+public fun github_commit_sequenced_payload(): vector<u8> { GITHUB_COMMIT_SEQUENCED_PAYLOAD }
+
+public fun github_commit_sequenced_signature(): vector<u8> { GITHUB_COMMIT_SEQUENCED_SIGNATURE }
+
+public fun sui_transaction_payload(): vector<u8> { SUI_TRANSACTION_PAYLOAD }
+
+public fun sui_transaction_signature(): vector<u8> { SUI_TRANSACTION_SIGNATURE }
+
+public fun sui_transaction_expiring_payload(): vector<u8> { SUI_TRANSACTION_EXPIRING_PAYLOAD }
+
+public fun sui_transaction_expiring_signature(): vector<u8> { SUI_TRANSACTION_EXPIRING_SIGNATURE }
+
+public fun sui_transaction_sequenced_payload(): vector<u8> { SUI_TRANSACTION_SEQUENCED_PAYLOAD }
+
+public fun sui_transaction_sequenced_signature(): vector<u8> { SUI_TRANSACTION_SEQUENCED_SIGNATURE }
@@ -22,7 +22,7 @@ fn nitro_heartbeat() {
         read(fd, buf.as_ptr() as _, 1);
         close(fd);
     }
-    dmesg(format!("Sent NSM heartbeat"));
+    dmesg("Sent NSM heartbeat".to_string());
 }
 
 // Get entropy sample from Nitro device
@@ -60,7 +60,7 @@ pub fn init_platform() {
     nitro_heartbeat();
 
     match insmod("/nsm.ko") {
-        Ok(()) => dmesg(format!("Loaded nsm.ko")),
+        Ok(()) => dmesg("Loaded nsm.ko".to_string()),
         Err(e) => eprintln!("{}", e),
     };
 }
@@ -0,0 +1,272 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::config::Config;
+use crate::logsink;
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::process::Child;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use system::{boot_time, dmesg, SystemError};
+
+// The application's stdout and stderr are piped through init, which prefixes
+// each line with `[app]` so it can be told apart from init's own messages on
+// the console. The last `nautilus.app_log_lines` lines (default 0, disabled)
+// are kept in memory so the watchdog can print them when the application hangs.
+
+pub const PREFIX: &str = "[app]";
+
+// Lines longer than this are split so a missing newline can't grow the buffer
+const MAX_LINE: usize = 4096;
+
+// How long to wait for output still in the pipes once the child has exited.
+// Background processes started by run.sh may keep the pipes open indefinitely.
+pub const DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+// Number of lines kept for the watchdog, or 0 to keep none
+pub fn ring_lines(config: &Config) -> Result<usize, SystemError> {
+    match config.get("nautilus.app_log_lines") {
+        None => Ok(0),
+        Some(value) => value.parse().map_err(|_| SystemError {
+            message: format!("Invalid nautilus.app_log_lines: {}", value),
+        }),
+    }
+}
+
+// Splits a byte stream into lines, buffering partial lines between reads
+#[derive(Default)]
+pub struct LineFramer {
+    partial: Vec<u8>,
+}
+
+impl LineFramer {
+    // Complete lines in `bytes`, without their line endings
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<String> {
+        let mut lines = Vec::new();
+        for &byte in bytes {
+            if byte == b'\n' {
+                lines.push(self.take());
+            } else {
+                self.partial.push(byte);
+                if self.partial.len() >= MAX_LINE {
+                    lines.push(self.take());
+                }
+            }
+        }
+        lines
+    }
+
+    // The trailing partial line, once the stream has ended
+    pub fn finish(&mut self) -> Option<String> {
+        if self.partial.is_empty() {
+            None
+        } else {
+            Some(self.take())
+        }
+    }
+
+    fn take(&mut self) -> String {
+        let line = std::mem::take(&mut self.partial);
+        let line = line.strip_suffix(b"\r").unwrap_or(&line);
+        String::from_utf8_lossy(line).into_owned()
+    }
+}
+
+// Most recent lines of application output
+pub struct Ring {
+    capacity: usize,
+    lines: VecDeque<String>,
+}
+
+impl Ring {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            lines: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub fn push(&mut self, line: String) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.lines.len() == self.capacity {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(line);
+    }
+
+    pub fn lines(&self) -> impl Iterator<Item = &String> {
+        self.lines.iter()
+    }
+}
+
+// Copy `reader` to `sink` line by line with the `[app]` prefix, until the
+// stream ends or fails. A trailing partial line is written too.
+pub fn copy_lines(mut reader: impl Read, sink: &mut dyn Write, ring: &Mutex<Ring>) {
+    let mut framer = LineFramer::default();
+    let mut buf = [0u8; 4096];
+    let emit = |sink: &mut dyn Write, line: String| {
+        let _ = writeln!(sink, "{} {} {}", boot_time(), PREFIX, line);
+        logsink::send_app(&line);
+        ring.lock().unwrap().push(line);
+    };
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => {
+                for line in framer.push(&buf[..n]) {
+                    emit(sink, line);
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(_) => break,
+        }
+    }
+    if let Some(line) = framer.finish() {
+        emit(sink, line);
+    }
+    let _ = sink.flush();
+}
+
+// Copier threads for one run of the application
+pub struct AppOutput {
+    done: Receiver<()>,
+    copiers: usize,
+}
+
+impl AppOutput {
+    // Take the child's piped stdout and stderr and start copying them
+    pub fn capture(child: &mut Child, ring: &Arc<Mutex<Ring>>) -> Self {
+        let (tx, done) = channel();
+        let mut copiers = 0;
+        if let Some(stdout) = child.stdout.take() {
+            spawn_copier(stdout, std::io::stdout, ring.clone(), tx.clone());
+            copiers += 1;
+        }
+        if let Some(stderr) = child.stderr.take() {
+            spawn_copier(stderr, std::io::stderr, ring.clone(), tx);
+            copiers += 1;
+        }
+        Self { done, copiers }
+    }
+
+    // Wait up to `timeout` for the copiers to write out what is left in the
+    // pipes after the child exited
+    pub fn drain(self, timeout: Duration) {
+        let deadline = Instant::now() + timeout;
+        for _ in 0..self.copiers {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if self.done.recv_timeout(remaining).is_err() {
+                dmesg("Application output still open, no longer waiting for it".to_string());
+                return;
+            }
+        }
+    }
+}
+
+fn spawn_copier<R, W>(reader: R, sink: fn() -> W, ring: Arc<Mutex<Ring>>, done: Sender<()>)
+where
+    R: Read + Send + 'static,
+    W: Write + 'static,
+{
+    thread::spawn(move || {
+        copy_lines(reader, &mut sink(), &ring);
+        let _ = done.send(());
+    });
+}
+
+// Print the lines kept in the ring to the console
+pub fn dump(ring: &Mutex<Ring>) {
+    let ring = ring.lock().unwrap();
+    let mut lines = ring.lines().peekable();
+    if lines.peek().is_none() {
+        return;
+    }
+    dmesg("Last application output:".to_string());
+    for line in lines {
+        dmesg(format!("{} {}", PREFIX, line));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_framing() {
+        let mut framer = LineFramer::default();
+        assert_eq!(framer.push(b"hel"), Vec::<String>::new());
+        assert_eq!(framer.push(b"lo\nwor"), ["hello"]);
+        assert_eq!(framer.push(b"ld\r\n\nlast"), ["world", ""]);
+        assert_eq!(framer.finish(), Some("last".to_string()));
+        assert_eq!(framer.finish(), None);
+
+        assert_eq!(
+            framer.push(b"bad \xff\xfe bytes\n"),
+            ["bad \u{fffd}\u{fffd} bytes"]
+        );
+
+        let long = vec![b'x'; MAX_LINE + 10];
+        let lines = framer.push(&long);
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].len(), MAX_LINE);
+        assert_eq!(framer.finish(), Some("x".repeat(10)));
+    }
+
+    // Reader returning its data in small chunks, like a pipe
+    struct Chunked<'a>(&'a [u8], usize);
+
+    impl Read for Chunked<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = self.0.len().min(self.1).min(buf.len());
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_copy_lines() {
+        let ring = Mutex::new(Ring::new(2));
+        let mut sink = Vec::new();
+        copy_lines(
+            Chunked(b"listening on 3000\nrequest 1\nrequest 2\npartial", 3),
+            &mut sink,
+            &ring,
+        );
+        let output = String::from_utf8(sink).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(
+            lines[0].ends_with("] [app] listening on 3000"),
+            "{}",
+            lines[0]
+        );
+        assert!(lines[3].ends_with("] [app] partial"), "{}", lines[3]);
+
+        let ring = ring.lock().unwrap();
+        let kept: Vec<&String> = ring.lines().collect();
+        assert_eq!(kept, ["request 2", "partial"]);
+    }
+
+    #[test]
+    fn test_disabled_ring() {
+        let mut ring = Ring::new(0);
+        ring.push("line".to_string());
+        assert_eq!(ring.lines().count(), 0);
+    }
+
+    #[test]
+    fn test_ring_lines_config() {
+        assert_eq!(ring_lines(&Config::parse("")).unwrap(), 0);
+        assert_eq!(
+            ring_lines(&Config::parse("nautilus.app_log_lines=200")).unwrap(),
+            200
+        );
+        assert!(ring_lines(&Config::parse("nautilus.app_log_lines=all")).is_err());
+    }
+}
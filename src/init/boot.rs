@@ -0,0 +1,641 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::boottime::BootTimer;
+use crate::config::Config;
+use crate::netconf::{NetConfig, HOSTS_PATH, RESOLV_CONF_PATH};
+use crate::sysops::SysOps;
+use crate::{logsink, pseudofs, readonly, sysctl, tmpfs};
+use std::path::Path;
+use system::{dmesg, SystemError};
+
+// The boot sequence init runs as PID 1 before starting nautilus-supervisor:
+// filesystems, console, kernel tunables, network files, platform and entropy.
+// Everything about the application is left to the supervisor.
+
+// A filesystem mounted before anything else
+struct RootfsMount {
+    // Name of its `nautilus.mount.<name>.options` key
+    name: &'static str,
+    source: &'static str,
+    target: &'static str,
+    fstype: &'static str,
+    flags: libc::c_ulong,
+    data: &'static str,
+    // Whether boot can't go on without it
+    required: bool,
+}
+
+const NO_DSE: libc::c_ulong = libc::MS_NODEV | libc::MS_NOSUID | libc::MS_NOEXEC;
+const NO_SE: libc::c_ulong = libc::MS_NOSUID | libc::MS_NOEXEC;
+
+// Device nodes, the cmdline and process info, and the sysfs tree holding
+// cgroup2 are needed by every later step, so failing to mount them stops boot.
+// Without devpts only pseudo-terminals are missing. They are mounted with these
+// conservative defaults before the cmdline can be read, and remounted once it
+// is when `nautilus.mount.<name>.options` replaces their data, e.g.
+// `nautilus.mount.proc.options=hidepid=1`.
+const ROOTFS_MOUNTS: [RootfsMount; 4] = [
+    RootfsMount {
+        name: "devtmpfs",
+        source: "devtmpfs",
+        target: "/dev",
+        fstype: "devtmpfs",
+        flags: NO_SE,
+        data: "mode=0755",
+        required: true,
+    },
+    RootfsMount {
+        name: "devpts",
+        source: "devpts",
+        target: "/dev/pts",
+        fstype: "devpts",
+        flags: NO_SE,
+        data: "",
+        required: false,
+    },
+    RootfsMount {
+        name: "proc",
+        source: "proc",
+        target: "/proc",
+        fstype: "proc",
+        flags: NO_DSE,
+        data: "hidepid=2",
+        required: true,
+    },
+    RootfsMount {
+        name: "sysfs",
+        source: "sysfs",
+        target: "/sys",
+        fstype: "sysfs",
+        flags: NO_DSE,
+        data: "",
+        required: true,
+    },
+];
+
+// Log a failed mount of `target`, and turn it into the error that stops boot
+// when the mount is required
+fn mount_failed(target: &str, required: bool, e: SystemError) -> Result<(), SystemError> {
+    if required {
+        dmesg(format!(
+            "FATAL: required mount {} failed: {}",
+            target, e.message
+        ));
+        return Err(SystemError {
+            message: format!("Failed to mount {}: {}", target, e.message),
+        });
+    }
+    dmesg(format!(
+        "WARNING: optional mount {} failed, continuing without it: {}",
+        target, e.message
+    ));
+    Ok(())
+}
+
+// Referenced from: https://git.distrust.co/public/enclaveos/src/branch/master/src/init/init.rs
+// Mount common filesystems with conservative permissions
+fn init_rootfs(sys: &dyn SysOps) -> Result<(), SystemError> {
+    for mount in &ROOTFS_MOUNTS {
+        if !sys.exists(mount.target) {
+            match sys.create_dir_all(mount.target) {
+                Ok(()) => dmesg(format!("Created mount point {}", mount.target)),
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+        match sys.mount(
+            mount.source,
+            mount.target,
+            mount.fstype,
+            mount.flags,
+            mount.data,
+        ) {
+            Ok(()) => dmesg(format!("Mounted {}", mount.target)),
+            Err(e) => mount_failed(mount.target, mount.required, e)?,
+        }
+    }
+    Ok(())
+}
+
+// Remount the rootfs mounts whose data the cmdline replaces. The defaults stay
+// in place when the options are invalid or the remount fails, so neither
+// stops boot.
+fn init_rootfs_options(sys: &dyn SysOps, config: &Config) {
+    for mount in &ROOTFS_MOUNTS {
+        let data = match pseudofs::options(config, mount.name) {
+            Ok(Some(data)) => data,
+            Ok(None) => continue,
+            Err(e) => {
+                dmesg(format!(
+                    "WARNING: {}, keeping the defaults for {}",
+                    e.message, mount.target
+                ));
+                continue;
+            }
+        };
+        match sys.mount(
+            mount.source,
+            mount.target,
+            mount.fstype,
+            libc::MS_REMOUNT | mount.flags,
+            data,
+        ) {
+            Ok(()) => dmesg(format!("Remounted {} with {}", mount.target, data)),
+            Err(e) => dmesg(format!(
+                "WARNING: remounting {} with {} failed, keeping the defaults: {}",
+                mount.target, data, e.message
+            )),
+        }
+    }
+}
+
+// Mount the size-capped tmpfs filesystems, falling back to a mount's defaults
+// when its cmdline options are invalid. A required one failing stops boot.
+fn init_tmpfs(sys: &dyn SysOps, config: &Config) -> Result<(), SystemError> {
+    for tmpfs in &tmpfs::MOUNTS {
+        let options = tmpfs.options(config).unwrap_or_else(|e| {
+            dmesg(format!("{}, using defaults for {}", e, tmpfs.target));
+            tmpfs
+                .options(&Config::default())
+                .expect("default tmpfs options are valid")
+        });
+        if let Err(e) = sys.create_dir_all(tmpfs.target) {
+            eprintln!("{}", e);
+        }
+        match sys.mount(
+            tmpfs.source,
+            tmpfs.target,
+            "tmpfs",
+            options.flags,
+            &options.data,
+        ) {
+            Ok(()) => dmesg(format!("Mounted {} ({})", tmpfs.target, options.data)),
+            Err(e) => mount_failed(tmpfs.target, tmpfs.required, e)?,
+        }
+    }
+    Ok(())
+}
+
+// Apply `sysctl.<key>=<value>` cmdline options to /proc/sys. Failures are
+// warnings, or fatal with `nautilus.sysctl_strict=1`.
+fn init_sysctl(config: &Config) -> Result<(), SystemError> {
+    let strict = config.get_bool("nautilus.sysctl_strict", false)?;
+    sysctl::apply(
+        Path::new(sysctl::PROC_SYS),
+        config.prefixed("sysctl."),
+        strict,
+    )
+}
+
+// Set the hostname and write /etc/hosts and /etc/resolv.conf to tmpfs,
+// falling back to the defaults when the cmdline options are invalid
+fn init_netconf(sys: &dyn SysOps, config: &Config) {
+    let netconf = NetConfig::from_config(config).unwrap_or_else(|e| {
+        dmesg(format!("{}, using default host and DNS settings", e));
+        NetConfig::default()
+    });
+    match sys.sethostname(&netconf.hostname) {
+        Ok(()) => dmesg(format!("Hostname set to {}", netconf.hostname)),
+        Err(e) => eprintln!("{}", e),
+    }
+    for (path, contents) in [
+        (HOSTS_PATH, netconf.hosts()),
+        (RESOLV_CONF_PATH, netconf.resolv_conf()),
+    ] {
+        match readonly::mount_writable_file(sys, path, &contents) {
+            Ok(()) => dmesg(format!("Wrote {}", path)),
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+}
+
+// Where stdin, stdout and stderr are opened, in order of preference. Some
+// kernel configs have no /dev/console, so stdout and stderr fall back to the
+// serial port and then the kernel log, and stdin to the serial port and then
+// /dev/null.
+const CONSOLE_TARGETS: [(libc::c_int, &str, [&str; 3]); 3] = [
+    (0, "r", ["/dev/console", "/dev/ttyS0", "/dev/null"]),
+    (1, "w", ["/dev/console", "/dev/ttyS0", "/dev/kmsg"]),
+    (2, "w", ["/dev/console", "/dev/ttyS0", "/dev/kmsg"]),
+];
+
+// The target each of stdin, stdout and stderr was opened on, None if every
+// one failed, and the errors on the way
+struct ConsoleSetup {
+    opened: [Option<&'static str>; 3],
+    errors: Vec<String>,
+}
+
+impl ConsoleSetup {
+    // What happened, or None if everything is on /dev/console
+    fn summary(&self) -> Option<String> {
+        if self.errors.is_empty() {
+            return None;
+        }
+        let opened: Vec<String> = ["stdin", "stdout", "stderr"]
+            .iter()
+            .zip(self.opened)
+            .map(|(name, target)| format!("{}={}", name, target.unwrap_or("none")))
+            .collect();
+        Some(format!(
+            "Console fallback: {} ({})",
+            opened.join(" "),
+            self.errors.join("; ")
+        ))
+    }
+}
+
+fn open_console(sys: &dyn SysOps) -> ConsoleSetup {
+    let mut setup = ConsoleSetup {
+        opened: [None; 3],
+        errors: Vec::new(),
+    };
+    for (fd, mode, targets) in CONSOLE_TARGETS {
+        for target in targets {
+            match sys.freopen(target, mode, fd) {
+                Ok(()) => {
+                    setup.opened[fd as usize] = Some(target);
+                    break;
+                }
+                Err(e) => setup.errors.push(format!("fd {}: {}", fd, e.message)),
+            }
+        }
+    }
+    setup
+}
+
+// Initialize console with stdin/stdout/stderr, reporting any fallback on
+// whichever of stdout and stderr was opened
+fn init_console(sys: &dyn SysOps) {
+    let setup = open_console(sys);
+    if let Some(summary) = setup.summary() {
+        if setup.opened[1].is_some() {
+            dmesg(summary);
+        } else {
+            eprintln!("{}", summary);
+        }
+    }
+}
+
+// Remount / read-only unless disabled with `nautilus.readonly_root=0`. Failing to
+// do so is a warning, or fatal with `nautilus.readonly_root_strict=1`.
+fn init_readonly_root(sys: &dyn SysOps, config: &Config) -> Result<(), SystemError> {
+    if !config.get_bool("nautilus.readonly_root", true)? {
+        dmesg("Leaving root filesystem writable".to_string());
+        return Ok(());
+    }
+    let strict = config.get_bool("nautilus.readonly_root_strict", false)?;
+    match readonly::remount_root_readonly(sys) {
+        Ok(()) => {
+            dmesg("Remounted root filesystem read-only".to_string());
+            Ok(())
+        }
+        Err(e) if strict => Err(e),
+        Err(e) => {
+            eprintln!("{}", e);
+            dmesg("WARNING: root filesystem remains writable".to_string());
+            Ok(())
+        }
+    }
+}
+
+// State accumulated by the boot steps
+#[derive(Default)]
+pub struct Boot {
+    pub config: Config,
+    // Started when boot begins, passed on to the supervisor which stops it
+    // once the application is spawned
+    pub timer: BootTimer,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum BootStep {
+    Rootfs,
+    Console,
+    Config,
+    RootfsOptions,
+    Sysctl,
+    Tmpfs,
+    PseudoFs,
+    Network,
+    Platform,
+    Entropy,
+    ReadOnlyRoot,
+}
+
+impl BootStep {
+    // Name of the step in the boot timings
+    fn name(self) -> &'static str {
+        match self {
+            BootStep::Rootfs => "rootfs",
+            BootStep::Console => "console",
+            BootStep::Config => "config",
+            BootStep::RootfsOptions => "rootfs_options",
+            BootStep::Sysctl => "sysctl",
+            BootStep::Tmpfs => "tmpfs",
+            BootStep::PseudoFs => "pseudofs",
+            BootStep::Network => "network",
+            BootStep::Platform => "platform",
+            BootStep::Entropy => "entropy",
+            BootStep::ReadOnlyRoot => "readonly_root",
+        }
+    }
+}
+
+// Filesystems are mounted first since every later step needs /dev, /proc or
+// /sys, and the cmdline config is only readable once /proc is mounted. Those
+// are remounted with any options the config sets as soon as it is read, then
+// kernel tunables are applied. The tmpfs mounts follow,
+// sized by the config, and the generated /etc files are written to them. The
+// other pseudo-filesystems, including cgroup2, also take their options from
+// the config. The root filesystem is made read-only last, after every step
+// that may still write to it.
+const BOOT_SEQUENCE: [BootStep; 11] = [
+    BootStep::Rootfs,
+    BootStep::Console,
+    BootStep::Config,
+    BootStep::RootfsOptions,
+    BootStep::Sysctl,
+    BootStep::Tmpfs,
+    BootStep::PseudoFs,
+    BootStep::Network,
+    BootStep::Platform,
+    BootStep::Entropy,
+    BootStep::ReadOnlyRoot,
+];
+
+fn run_boot_step(sys: &dyn SysOps, step: BootStep, boot: &mut Boot) -> Result<(), SystemError> {
+    match step {
+        BootStep::Rootfs => init_rootfs(sys)?,
+        BootStep::Console => init_console(sys),
+        BootStep::Config => {
+            boot.config = Config::load(sys);
+            logsink::start(&boot.config, "init");
+        }
+        BootStep::RootfsOptions => init_rootfs_options(sys, &boot.config),
+        BootStep::Sysctl => init_sysctl(&boot.config)?,
+        BootStep::Tmpfs => init_tmpfs(sys, &boot.config)?,
+        BootStep::PseudoFs => pseudofs::mount_all(sys, &boot.config)?,
+        BootStep::Network => init_netconf(sys, &boot.config),
+        BootStep::Platform => sys.init_platform(),
+        BootStep::Entropy => match sys.seed_entropy(4096) {
+            Ok(size) => dmesg(format!("Seeded kernel with entropy: {}", size)),
+            Err(e) => eprintln!("{}", e),
+        },
+        BootStep::ReadOnlyRoot => init_readonly_root(sys, &boot.config)?,
+    }
+    Ok(())
+}
+
+// Run the boot sequence, timing each step
+pub fn boot(sys: &dyn SysOps) -> Result<Boot, SystemError> {
+    let mut boot = Boot::default();
+    for step in BOOT_SEQUENCE {
+        run_boot_step(sys, step, &mut boot)?;
+        boot.timer.step(step.name());
+    }
+    Ok(boot)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sysops::fake::Recorder;
+
+    fn position(step: BootStep) -> usize {
+        BOOT_SEQUENCE.iter().position(|s| *s == step).unwrap()
+    }
+
+    #[test]
+    fn test_boot_sequence_mounts_first() {
+        assert_eq!(BOOT_SEQUENCE[0], BootStep::Rootfs);
+        assert!(position(BootStep::Rootfs) < position(BootStep::Config));
+        assert!(position(BootStep::Config) < position(BootStep::Sysctl));
+        assert!(position(BootStep::Config) < position(BootStep::Tmpfs));
+        assert!(position(BootStep::Tmpfs) < position(BootStep::Network));
+        assert!(position(BootStep::Config) < position(BootStep::PseudoFs));
+    }
+
+    #[test]
+    fn test_boot_sequence_readonly_root_last() {
+        assert_eq!(BOOT_SEQUENCE.last(), Some(&BootStep::ReadOnlyRoot));
+        for step in BOOT_SEQUENCE {
+            if step != BootStep::ReadOnlyRoot {
+                assert!(position(step) < position(BootStep::ReadOnlyRoot));
+            }
+        }
+    }
+
+    #[test]
+    fn test_boot_sequence_runs_each_step_once() {
+        for step in BOOT_SEQUENCE {
+            assert_eq!(BOOT_SEQUENCE.iter().filter(|s| **s == step).count(), 1);
+        }
+    }
+
+    // Position of the first recorded call starting with `prefix`
+    fn call_index(sys: &Recorder, prefix: &str) -> usize {
+        sys.calls
+            .borrow()
+            .iter()
+            .position(|call| call.starts_with(prefix))
+            .unwrap_or_else(|| panic!("no call to {}", prefix))
+    }
+
+    fn mount_targets(sys: &Recorder) -> Vec<String> {
+        sys.calls_to("mount")
+            .iter()
+            .map(|call| call.split(' ').nth(2).unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_rootfs_mount_order() {
+        let mut sys = Recorder::default();
+        // An optional mount failing doesn't stop the ones after it
+        sys.failing.insert("/dev/pts".to_string());
+        init_rootfs(&sys).unwrap();
+        assert_eq!(mount_targets(&sys), ["/dev", "/dev/pts", "/proc", "/sys"]);
+        // Missing mount points are created first
+        assert_eq!(sys.calls_to("mkdir").len(), ROOTFS_MOUNTS.len());
+    }
+
+    #[test]
+    fn test_required_mount_failure() {
+        for target in ["/dev", "/proc", "/sys"] {
+            let mut sys = Recorder::default();
+            sys.failing.insert(target.to_string());
+            let err = init_rootfs(&sys).unwrap_err();
+            assert_eq!(
+                err.message,
+                format!("Failed to mount {}: Failed on {}", target, target)
+            );
+            // Nothing is mounted after it
+            assert_eq!(mount_targets(&sys).last().map(String::as_str), Some(target));
+        }
+
+        // Without /proc boot stops before reading the cmdline
+        let mut sys = Recorder::default();
+        sys.failing.insert("/proc".to_string());
+        assert!(boot(&sys).is_err());
+        assert!(sys.calls_to("read /proc/cmdline").is_empty());
+        assert!(sys.calls_to("freopen").is_empty());
+    }
+
+    #[test]
+    fn test_rootfs_options() {
+        // Nothing is remounted with the defaults
+        let sys = Recorder::default();
+        init_rootfs_options(&sys, &Config::default());
+        assert!(sys.calls_to("mount").is_empty());
+
+        let mut sys = Recorder::default();
+        sys.failing.insert("/dev/pts".to_string());
+        let config = Config::parse(
+            "nautilus.mount.proc.options=hidepid=1 \
+             nautilus.mount.devpts.options=gid=5,mode=620 \
+             nautilus.mount.devtmpfs.options=mode=0755;x",
+        );
+        init_rootfs_options(&sys, &config);
+        // The invalid /dev options and the failed /dev/pts remount keep their
+        // defaults without stopping the rest
+        assert_eq!(
+            sys.calls_to("mount"),
+            [
+                format!(
+                    "mount devpts /dev/pts devpts {:#x} gid=5,mode=620",
+                    libc::MS_REMOUNT | NO_SE
+                ),
+                format!(
+                    "mount proc /proc proc {:#x} hidepid=1",
+                    libc::MS_REMOUNT | NO_DSE
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tmpfs_mount_failure() {
+        let mut sys = Recorder::default();
+        sys.failing.insert("/dev/shm".to_string());
+        init_tmpfs(&sys, &Config::default()).unwrap();
+        assert_eq!(mount_targets(&sys), ["/dev/shm", "/run", "/tmp"]);
+
+        for target in ["/run", "/tmp"] {
+            let mut sys = Recorder::default();
+            sys.failing.insert(target.to_string());
+            assert!(init_tmpfs(&sys, &Config::default()).is_err(), "{}", target);
+        }
+    }
+
+    #[test]
+    fn test_console_fallback() {
+        // Everything on /dev/console reports nothing
+        let sys = Recorder::default();
+        let setup = open_console(&sys);
+        assert_eq!(setup.opened, [Some("/dev/console"); 3]);
+        assert!(setup.summary().is_none());
+
+        let mut sys = Recorder::default();
+        sys.failing.insert("/dev/console:0".to_string());
+        sys.failing.insert("/dev/ttyS0:0".to_string());
+        sys.failing.insert("/dev/console:1".to_string());
+        for target in ["/dev/console", "/dev/ttyS0", "/dev/kmsg"] {
+            sys.failing.insert(format!("{}:2", target));
+        }
+        let setup = open_console(&sys);
+        assert_eq!(
+            sys.calls_to("freopen"),
+            [
+                "freopen /dev/console r 0",
+                "freopen /dev/ttyS0 r 0",
+                "freopen /dev/null r 0",
+                "freopen /dev/console w 1",
+                "freopen /dev/ttyS0 w 1",
+                "freopen /dev/console w 2",
+                "freopen /dev/ttyS0 w 2",
+                "freopen /dev/kmsg w 2",
+            ]
+        );
+        assert_eq!(setup.opened, [Some("/dev/null"), Some("/dev/ttyS0"), None]);
+        assert_eq!(
+            setup.summary().unwrap(),
+            "Console fallback: stdin=/dev/null stdout=/dev/ttyS0 stderr=none \
+             (fd 0: Failed on /dev/console:0; fd 0: Failed on /dev/ttyS0:0; \
+             fd 1: Failed on /dev/console:1; fd 2: Failed on /dev/console:2; \
+             fd 2: Failed on /dev/ttyS0:2; fd 2: Failed on /dev/kmsg:2)"
+        );
+    }
+
+    #[test]
+    fn test_boot_runs_steps_in_order() {
+        let sys = Recorder::with_files(&[
+            (
+                "/proc/cmdline",
+                "console=ttyS0 nautilus.hostname=weather-1 \
+                 nautilus.mount.proc.options=hidepid=1\n",
+            ),
+            (
+                "/proc/mounts",
+                "tmpfs /tmp tmpfs rw 0 0\n\
+                 tmpfs /run tmpfs rw 0 0\n\
+                 shm /dev/shm tmpfs rw 0 0\n\
+                 tmpfs /etc/hosts tmpfs rw 0 0\n",
+            ),
+        ]);
+        let boot = boot(&sys).unwrap();
+        assert_eq!(boot.config.get("nautilus.hostname"), Some("weather-1"));
+        // The application's environment is left to the supervisor
+        assert!(sys.calls_to("setenv").is_empty());
+        assert!(sys.calls_to("read /env.manifest").is_empty());
+
+        let remount_proc = format!(
+            "mount proc /proc proc {:#x} hidepid=1",
+            libc::MS_REMOUNT | NO_DSE
+        );
+        let order = [
+            "mount devtmpfs /dev ",
+            "freopen /dev/console",
+            "read /proc/cmdline",
+            remount_proc.as_str(),
+            "mount tmpfs /tmp ",
+            "sethostname weather-1",
+            "write /run/rw/etc/hosts",
+            "init_platform",
+            "seed_entropy",
+            "read /proc/mounts",
+        ];
+        for pair in order.windows(2) {
+            assert!(
+                call_index(&sys, pair[0]) < call_index(&sys, pair[1]),
+                "{} should come before {}",
+                pair[0],
+                pair[1]
+            );
+        }
+        let remount = format!("mount  /  {:#x} ", libc::MS_REMOUNT | libc::MS_RDONLY);
+        assert_eq!(sys.calls.borrow().last(), Some(&remount));
+
+        let timings = boot.timer.finish();
+        let steps: Vec<&str> = timings
+            .steps
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect();
+        let expected: Vec<&str> = BOOT_SEQUENCE.iter().map(|step| step.name()).collect();
+        assert_eq!(steps, expected);
+    }
+
+    #[test]
+    fn test_boot_stops_at_fatal_step() {
+        let mut sys = Recorder::default();
+        sys.failing.insert("/dev/mqueue".to_string());
+        assert!(boot(&sys).is_err());
+        // Nothing runs after the failed step
+        assert_eq!(
+            sys.calls.borrow().last().map(String::as_str),
+            Some(format!("mount mqueue /dev/mqueue mqueue {:#x} ", pseudofs::FLAGS).as_str())
+        );
+        assert!(sys.calls_to("sethostname").is_empty());
+    }
+}
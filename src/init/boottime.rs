@@ -0,0 +1,233 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::sysops::SysOps;
+use std::time::{Duration, Instant};
+use system::dmesg;
+
+// Where the boot timings are written as JSON for the application, e.g.
+// {"steps":[{"name":"rootfs","ms":12},{"name":"app_spawn","ms":9}],"total_ms":21}
+pub const BOOT_TIMINGS_PATH: &str = "/run/boot_timings.json";
+
+// Passes the steps init timed to nautilus-supervisor, which adds its own and
+// reports them all once the application is spawned
+pub const BOOT_STEPS_ENV: &str = "NAUTILUS_BOOT_STEPS";
+
+// Measures consecutive boot steps on the monotonic clock. Each step lasts from
+// the end of the previous one, so the steps always add up to the total.
+pub struct BootTimer {
+    started: Instant,
+    last: Instant,
+    steps: Vec<(String, Duration)>,
+}
+
+impl Default for BootTimer {
+    fn default() -> Self {
+        let now = Instant::now();
+        Self {
+            started: now,
+            last: now,
+            steps: Vec::new(),
+        }
+    }
+}
+
+impl BootTimer {
+    // Record `name` as ending now
+    pub fn step(&mut self, name: &str) {
+        let now = Instant::now();
+        self.steps.push((name.to_string(), now - self.last));
+        self.last = now;
+    }
+
+    // The steps so far as `name=microseconds,...`, for BOOT_STEPS_ENV
+    pub fn export(&self) -> String {
+        let steps: Vec<String> = self
+            .steps
+            .iter()
+            .map(|(name, duration)| format!("{}={}", name, duration.as_micros()))
+            .collect();
+        steps.join(",")
+    }
+
+    // Continue the steps from `export` in another process. The time between
+    // the export and now isn't counted, so the steps still add up to the
+    // total. None if `exported` is malformed.
+    pub fn resume(exported: &str) -> Option<Self> {
+        let mut steps = Vec::new();
+        for step in exported.split(',').filter(|step| !step.is_empty()) {
+            let (name, micros) = step.split_once('=')?;
+            if name.is_empty() || !name.bytes().all(|b| b.is_ascii_lowercase() || b == b'_') {
+                return None;
+            }
+            steps.push((
+                name.to_string(),
+                Duration::from_micros(micros.parse().ok()?),
+            ));
+        }
+        let now = Instant::now();
+        let elapsed: Duration = steps.iter().map(|(_, duration)| *duration).sum();
+        Some(Self {
+            started: now.checked_sub(elapsed)?,
+            last: now,
+            steps,
+        })
+    }
+
+    pub fn finish(self) -> BootTimings {
+        BootTimings {
+            steps: self.steps,
+            total: self.last - self.started,
+        }
+    }
+}
+
+pub struct BootTimings {
+    pub steps: Vec<(String, Duration)>,
+    pub total: Duration,
+}
+
+impl BootTimings {
+    // The dmesg line, e.g. `boot timings: rootfs=12ms app_spawn=9ms total=21ms`
+    pub fn summary(&self) -> String {
+        let steps: Vec<String> = self
+            .steps
+            .iter()
+            .map(|(name, duration)| format!("{}={}ms", name, duration.as_millis()))
+            .collect();
+        format!(
+            "boot timings: {} total={}ms",
+            steps.join(" "),
+            self.total.as_millis()
+        )
+    }
+
+    // Step names are fixed identifiers, so nothing needs escaping
+    pub fn to_json(&self) -> String {
+        let steps: Vec<String> = self
+            .steps
+            .iter()
+            .map(|(name, duration)| {
+                format!(r#"{{"name":"{}","ms":{}}}"#, name, duration.as_millis())
+            })
+            .collect();
+        format!(
+            r#"{{"steps":[{}],"total_ms":{}}}"#,
+            steps.join(","),
+            self.total.as_millis()
+        )
+    }
+}
+
+// Log the timings and write them to BOOT_TIMINGS_PATH. Failing to write them
+// doesn't affect the application.
+pub fn report(sys: &dyn SysOps, timings: &BootTimings) {
+    dmesg(timings.summary());
+    if let Err(e) = sys.write_file(BOOT_TIMINGS_PATH, &timings.to_json()) {
+        eprintln!("Failed to write {}: {}", BOOT_TIMINGS_PATH, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sysops::fake::Recorder;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn test_summary_and_json() {
+        let timings = BootTimings {
+            steps: vec![
+                ("rootfs".to_string(), ms(12)),
+                ("platform".to_string(), ms(340) + Duration::from_micros(900)),
+                ("app_spawn".to_string(), ms(9)),
+            ],
+            total: ms(361),
+        };
+        assert_eq!(
+            timings.summary(),
+            "boot timings: rootfs=12ms platform=340ms app_spawn=9ms total=361ms"
+        );
+        assert_eq!(
+            timings.to_json(),
+            r#"{"steps":[{"name":"rootfs","ms":12},{"name":"platform","ms":340},{"name":"app_spawn","ms":9}],"total_ms":361}"#
+        );
+    }
+
+    #[test]
+    fn test_steps_sum_to_total() {
+        let mut timer = BootTimer::default();
+        for (name, delay) in [("rootfs", 3), ("console", 0), ("entropy", 5)] {
+            std::thread::sleep(ms(delay));
+            timer.step(name);
+        }
+        let timings = timer.finish();
+        let names: Vec<&str> = timings
+            .steps
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect();
+        assert_eq!(names, ["rootfs", "console", "entropy"]);
+        assert!(timings.steps[0].1 >= ms(3));
+        assert!(timings.steps[2].1 >= ms(5));
+        let sum: Duration = timings.steps.iter().map(|(_, duration)| *duration).sum();
+        assert_eq!(sum, timings.total);
+
+        // Rounded to milliseconds, each step loses less than 1ms
+        let sum_ms: u128 = timings
+            .steps
+            .iter()
+            .map(|(_, duration)| duration.as_millis())
+            .sum();
+        let total_ms = timings.total.as_millis();
+        assert!(sum_ms <= total_ms && total_ms - sum_ms < timings.steps.len() as u128);
+    }
+
+    #[test]
+    fn test_export_and_resume() {
+        let mut timer = BootTimer::default();
+        std::thread::sleep(ms(2));
+        timer.step("rootfs");
+        timer.step("supervisor_spawn");
+        let exported = timer.export();
+        assert!(exported.starts_with("rootfs="), "{}", exported);
+
+        let mut resumed = BootTimer::resume(&exported).unwrap();
+        resumed.step("app_spawn");
+        let timings = resumed.finish();
+        let names: Vec<&str> = timings
+            .steps
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect();
+        assert_eq!(names, ["rootfs", "supervisor_spawn", "app_spawn"]);
+        assert!(timings.steps[0].1 >= ms(2));
+        let sum: Duration = timings.steps.iter().map(|(_, duration)| *duration).sum();
+        assert_eq!(sum, timings.total);
+
+        assert!(BootTimer::resume("").is_some());
+        for malformed in ["rootfs", "rootfs=x", "=1", "Root fs=1"] {
+            assert!(BootTimer::resume(malformed).is_none(), "{}", malformed);
+        }
+    }
+
+    #[test]
+    fn test_report_writes_json() {
+        let sys = Recorder::default();
+        let timings = BootTimings {
+            steps: vec![("rootfs".to_string(), ms(1))],
+            total: ms(1),
+        };
+        report(&sys, &timings);
+        assert_eq!(
+            sys.files
+                .borrow()
+                .get(BOOT_TIMINGS_PATH)
+                .map(String::as_str),
+            Some(r#"{"steps":[{"name":"rootfs","ms":1}],"total_ms":1}"#)
+        );
+    }
+}
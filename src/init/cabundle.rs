@@ -0,0 +1,166 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::config::Config;
+use crate::sha256;
+use system::SystemError;
+
+// CA bundle exported to the application as SSL_CERT_FILE
+pub const CA_BUNDLE_PATH: &str = "/ca-certificates.crt";
+
+// The bundle must be a non-empty list of complete PEM certificates. With
+// `nautilus.ca_bundle_sha256=<hex>` its SHA-256 must also match, which puts
+// the trust store in the measured cmdline. A bundle failing either check is
+// a warning, or fatal with `nautilus.ca_bundle_strict=1`.
+pub struct Expected {
+    pub sha256: Option<String>,
+    pub strict: bool,
+}
+
+impl Expected {
+    pub fn from_config(config: &Config) -> Result<Self, SystemError> {
+        let sha256 = match config.get("nautilus.ca_bundle_sha256") {
+            Some(hex) if hex.len() == 64 && hex.chars().all(|c| c.is_ascii_hexdigit()) => {
+                Some(hex.to_ascii_lowercase())
+            }
+            Some(hex) => {
+                return Err(SystemError {
+                    message: format!(
+                        "Invalid nautilus.ca_bundle_sha256 {}: expected 64 hex digits",
+                        hex
+                    ),
+                })
+            }
+            None => None,
+        };
+        Ok(Self {
+            sha256,
+            strict: config.get_bool("nautilus.ca_bundle_strict", false)?,
+        })
+    }
+}
+
+fn is_base64(line: &str) -> bool {
+    line.bytes()
+        .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'+' | b'/' | b'='))
+}
+
+// Number of PEM certificates in `bundle`. Text outside the blocks, such as
+// the comments between certificates, is ignored, but a block that isn't
+// closed or isn't base64 makes the whole bundle invalid.
+pub fn count_certificates(bundle: &str) -> Result<usize, String> {
+    let mut count = 0;
+    // Length of the base64 body of the open block
+    let mut body: Option<usize> = None;
+    for (number, line) in bundle.lines().enumerate().map(|(i, l)| (i + 1, l.trim())) {
+        match (body, line) {
+            (None, "-----BEGIN CERTIFICATE-----") => body = Some(0),
+            (None, _) => {}
+            (Some(0), "-----END CERTIFICATE-----") => {
+                return Err(format!("empty certificate ending on line {}", number))
+            }
+            (Some(len), "-----END CERTIFICATE-----") if len % 4 != 0 => {
+                return Err(format!("truncated certificate ending on line {}", number))
+            }
+            (Some(_), "-----END CERTIFICATE-----") => {
+                count += 1;
+                body = None;
+            }
+            (Some(len), line) if is_base64(line) => body = Some(len + line.len()),
+            (Some(_), _) => return Err(format!("invalid base64 on line {}", number)),
+        }
+    }
+    if body.is_some() {
+        return Err("last certificate is not terminated".to_string());
+    }
+    Ok(count)
+}
+
+// Check `bundle` against `expected`, returning the number of certificates
+pub fn verify(bundle: &str, expected: &Expected) -> Result<usize, SystemError> {
+    let invalid = |reason: String| SystemError {
+        message: format!("Invalid CA bundle {}: {}", CA_BUNDLE_PATH, reason),
+    };
+    if bundle.is_empty() {
+        return Err(invalid("empty file".to_string()));
+    }
+    let count = count_certificates(bundle).map_err(invalid)?;
+    if count == 0 {
+        return Err(invalid("no PEM certificates".to_string()));
+    }
+    if let Some(expected) = &expected.sha256 {
+        let actual = sha256::hex(&sha256::digest(bundle.as_bytes()));
+        if actual != *expected {
+            return Err(invalid(format!(
+                "SHA-256 {} does not match nautilus.ca_bundle_sha256 {}",
+                actual, expected
+            )));
+        }
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BUNDLE: &str = include_str!("testdata/ca-bundle.pem");
+    const TRUNCATED: &str = include_str!("testdata/ca-bundle-truncated.pem");
+    const BUNDLE_SHA256: &str = "4aaef062a525fa4be662dfffd1218478061edde14ae04795db9443badc0dca69";
+
+    fn expected(sha256: Option<&str>) -> Expected {
+        Expected {
+            sha256: sha256.map(str::to_string),
+            strict: false,
+        }
+    }
+
+    #[test]
+    fn test_count_certificates() {
+        assert_eq!(count_certificates(BUNDLE), Ok(2));
+        assert_eq!(count_certificates("# no certificates\n"), Ok(0));
+        assert_eq!(
+            count_certificates(TRUNCATED),
+            Err("last certificate is not terminated".to_string())
+        );
+        assert!(count_certificates(&BUNDLE.replacen("MII", "M!I", 1)).is_err());
+        assert!(
+            count_certificates("-----BEGIN CERTIFICATE-----\n-----END CERTIFICATE-----\n").is_err()
+        );
+    }
+
+    #[test]
+    fn test_verify_digest() {
+        assert_eq!(verify(BUNDLE, &expected(None)).unwrap(), 2);
+        assert_eq!(verify(BUNDLE, &expected(Some(BUNDLE_SHA256))).unwrap(), 2);
+
+        // One changed byte outside the certificates still changes the digest
+        let edited = BUNDLE.replacen("Test CA 2", "Test CA 3", 1);
+        assert_eq!(count_certificates(&edited), Ok(2));
+        let err = verify(&edited, &expected(Some(BUNDLE_SHA256))).unwrap_err();
+        assert!(err.message.contains("does not match"), "{}", err.message);
+
+        assert!(verify("", &expected(None)).is_err());
+        assert!(verify("# no certificates\n", &expected(None)).is_err());
+        assert!(verify(TRUNCATED, &expected(None)).is_err());
+    }
+
+    #[test]
+    fn test_expected_from_config() {
+        let upper = BUNDLE_SHA256.to_ascii_uppercase();
+        let config = Config::parse(&format!(
+            "nautilus.ca_bundle_sha256={} nautilus.ca_bundle_strict",
+            upper
+        ));
+        let expected = Expected::from_config(&config).unwrap();
+        assert_eq!(expected.sha256.as_deref(), Some(BUNDLE_SHA256));
+        assert!(expected.strict);
+
+        let expected = Expected::from_config(&Config::default()).unwrap();
+        assert!(expected.sha256.is_none());
+        assert!(!expected.strict);
+
+        let config = Config::parse("nautilus.ca_bundle_sha256=abc");
+        assert!(Expected::from_config(&config).is_err());
+    }
+}
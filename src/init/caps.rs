@@ -0,0 +1,328 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::config::Config;
+use crate::forward::{Endpoint, Rule};
+use std::fmt;
+use std::io;
+use system::SystemError;
+
+// Capabilities kept by the application. Between fork and exec init drops every
+// capability outside the allowlist from the bounding and inheritable sets,
+// clears the ambient set and sets no_new_privs, so run.sh and its children,
+// although running as root, can't use or regain anything else. The allowlist
+// is `nautilus.caps`, e.g. `nautilus.caps=net_admin,net_raw` (empty by
+// default). `net_bind_service` is added when a `nautilus.vsock_forward` rule
+// targets a port below 1024, which the application then has to bind.
+
+// Names in capability number order, as in linux/capability.h
+const NAMES: &[&str] = &[
+    "chown",
+    "dac_override",
+    "dac_read_search",
+    "fowner",
+    "fsetid",
+    "kill",
+    "setgid",
+    "setuid",
+    "setpcap",
+    "linux_immutable",
+    "net_bind_service",
+    "net_broadcast",
+    "net_admin",
+    "net_raw",
+    "ipc_lock",
+    "ipc_owner",
+    "sys_module",
+    "sys_rawio",
+    "sys_chroot",
+    "sys_ptrace",
+    "sys_pacct",
+    "sys_admin",
+    "sys_boot",
+    "sys_nice",
+    "sys_resource",
+    "sys_time",
+    "sys_tty_config",
+    "mknod",
+    "lease",
+    "audit_write",
+    "audit_control",
+    "setfcap",
+    "mac_override",
+    "mac_admin",
+    "syslog",
+    "wake_alarm",
+    "block_suspend",
+    "audit_read",
+    "perfmon",
+    "bpf",
+    "checkpoint_restore",
+];
+
+const CAP_NET_BIND_SERVICE: u32 = 10;
+
+// Highest capability to drop when the kernel doesn't say
+const DEFAULT_LAST_CAP: u32 = 40;
+
+// Set of capabilities as a bit mask
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CapSet(u64);
+
+impl CapSet {
+    pub fn contains(self, cap: u32) -> bool {
+        cap < 64 && self.0 & (1 << cap) != 0
+    }
+
+    fn insert(&mut self, cap: u32) {
+        self.0 |= 1 << cap;
+    }
+}
+
+impl fmt::Display for CapSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names: Vec<&str> = (0..NAMES.len() as u32)
+            .filter(|&cap| self.contains(cap))
+            .map(|cap| NAMES[cap as usize])
+            .collect();
+        if names.is_empty() {
+            write!(f, "none")
+        } else {
+            write!(f, "{}", names.join(","))
+        }
+    }
+}
+
+fn parse_name(name: &str) -> Option<u32> {
+    let name = name.to_ascii_lowercase();
+    let name = name.strip_prefix("cap_").unwrap_or(&name);
+    NAMES.iter().position(|n| *n == name).map(|cap| cap as u32)
+}
+
+pub fn from_config(config: &Config, rules: &[Rule]) -> Result<CapSet, SystemError> {
+    let mut caps = CapSet::default();
+    for name in config
+        .get("nautilus.caps")
+        .unwrap_or("")
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+    {
+        let cap = parse_name(name).ok_or_else(|| SystemError {
+            message: format!("Invalid nautilus.caps: unknown capability {}", name),
+        })?;
+        caps.insert(cap);
+    }
+    let privileged_port = rules.iter().any(|rule| match (rule.listen, rule.target) {
+        (Endpoint::Vsock { .. }, Endpoint::Tcp(addr)) => addr.port() < 1024,
+        _ => false,
+    });
+    if privileged_port {
+        caps.insert(CAP_NET_BIND_SERVICE);
+    }
+    Ok(caps)
+}
+
+// Highest capability the running kernel knows, read before fork since
+// dropping an unknown capability fails
+pub fn last_cap() -> u32 {
+    std::fs::read_to_string("/proc/sys/kernel/cap_last_cap")
+        .ok()
+        .and_then(|last| last.trim().parse().ok())
+        .unwrap_or(DEFAULT_LAST_CAP)
+}
+
+// System calls made by `apply`, so the sequence can be tested without root
+pub trait Sys {
+    fn drop_bounding(&mut self, cap: u32) -> io::Result<()>;
+    fn set_inheritable(&mut self, caps: CapSet) -> io::Result<()>;
+    fn clear_ambient(&mut self) -> io::Result<()>;
+    fn set_no_new_privs(&mut self) -> io::Result<()>;
+}
+
+pub struct Libc;
+
+// struct __user_cap_header_struct
+#[repr(C)]
+struct CapHeader {
+    version: u32,
+    pid: libc::c_int,
+}
+
+// struct __user_cap_data_struct, two of which hold 64 capabilities
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct CapData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+fn check(ret: libc::c_long) -> io::Result<()> {
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+impl Sys for Libc {
+    fn drop_bounding(&mut self, cap: u32) -> io::Result<()> {
+        check(unsafe { libc::prctl(libc::PR_CAPBSET_DROP, cap as libc::c_ulong, 0, 0, 0) }.into())
+    }
+
+    fn set_inheritable(&mut self, caps: CapSet) -> io::Result<()> {
+        let mut header = CapHeader {
+            version: LINUX_CAPABILITY_VERSION_3,
+            pid: 0,
+        };
+        let mut data = [CapData::default(); 2];
+        check(unsafe { libc::syscall(libc::SYS_capget, &mut header, data.as_mut_ptr()) })?;
+        data[0].inheritable &= caps.0 as u32;
+        data[1].inheritable &= (caps.0 >> 32) as u32;
+        check(unsafe { libc::syscall(libc::SYS_capset, &mut header, data.as_ptr()) })
+    }
+
+    fn clear_ambient(&mut self) -> io::Result<()> {
+        check(
+            unsafe {
+                libc::prctl(
+                    libc::PR_CAP_AMBIENT,
+                    libc::PR_CAP_AMBIENT_CLEAR_ALL as libc::c_ulong,
+                    0,
+                    0,
+                    0,
+                )
+            }
+            .into(),
+        )
+    }
+
+    fn set_no_new_privs(&mut self) -> io::Result<()> {
+        check(unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) }.into())
+    }
+}
+
+// Restrict the process to `keep`. Runs in the forked child before exec, so it
+// must not allocate. The bounding set is dropped first, while init's
+// CAP_SETPCAP still allows it; ambient capabilities must also be inheritable,
+// so lowering the inheritable set comes before clearing what is left of them.
+pub fn apply(keep: CapSet, last_cap: u32, sys: &mut dyn Sys) -> io::Result<()> {
+    for cap in 0..=last_cap {
+        if !keep.contains(cap) {
+            sys.drop_bounding(cap)?;
+        }
+    }
+    sys.set_inheritable(keep)?;
+    sys.clear_ambient()?;
+    sys.set_no_new_privs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::forward::rules_from_config;
+
+    fn caps(cmdline: &str) -> Result<CapSet, SystemError> {
+        let config = Config::parse(cmdline);
+        from_config(&config, &rules_from_config(&config).unwrap())
+    }
+
+    #[test]
+    fn test_from_config() {
+        assert_eq!(caps("").unwrap().to_string(), "none");
+        assert_eq!(
+            caps("nautilus.caps=NET_RAW,cap_net_admin")
+                .unwrap()
+                .to_string(),
+            "net_admin,net_raw"
+        );
+        assert!(caps("nautilus.caps=net_admin,net_magic").is_err());
+        assert_eq!(NAMES.len() as u32, DEFAULT_LAST_CAP + 1);
+    }
+
+    #[test]
+    fn test_privileged_port() {
+        assert_eq!(
+            caps("nautilus.vsock_forward=3000:127.0.0.1:3000")
+                .unwrap()
+                .to_string(),
+            "none"
+        );
+        assert_eq!(
+            caps("nautilus.vsock_forward=3000:127.0.0.1:3000,443:127.0.0.1:443")
+                .unwrap()
+                .to_string(),
+            "net_bind_service"
+        );
+        // The application connects to outbound forwards rather than binding them
+        assert_eq!(
+            caps("nautilus.tcp_forward=127.0.0.64:443:3:8101")
+                .unwrap()
+                .to_string(),
+            "none"
+        );
+    }
+
+    #[derive(Default)]
+    struct Recorder {
+        calls: Vec<String>,
+        fail_bounding: Option<u32>,
+    }
+
+    impl Sys for Recorder {
+        fn drop_bounding(&mut self, cap: u32) -> io::Result<()> {
+            if self.fail_bounding == Some(cap) {
+                return Err(io::Error::from_raw_os_error(libc::EPERM));
+            }
+            self.calls.push(format!("drop {}", NAMES[cap as usize]));
+            Ok(())
+        }
+
+        fn set_inheritable(&mut self, caps: CapSet) -> io::Result<()> {
+            self.calls.push(format!("inheritable {}", caps));
+            Ok(())
+        }
+
+        fn clear_ambient(&mut self) -> io::Result<()> {
+            self.calls.push("clear ambient".to_string());
+            Ok(())
+        }
+
+        fn set_no_new_privs(&mut self) -> io::Result<()> {
+            self.calls.push("no_new_privs".to_string());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_apply_order() {
+        let keep = caps("nautilus.caps=kill").unwrap();
+        let mut sys = Recorder::default();
+        apply(keep, 8, &mut sys).unwrap();
+        assert_eq!(
+            sys.calls,
+            [
+                "drop chown",
+                "drop dac_override",
+                "drop dac_read_search",
+                "drop fowner",
+                "drop fsetid",
+                "drop setgid",
+                "drop setuid",
+                "drop setpcap",
+                "inheritable kill",
+                "clear ambient",
+                "no_new_privs",
+            ]
+        );
+
+        let mut sys = Recorder {
+            fail_bounding: Some(1),
+            ..Recorder::default()
+        };
+        assert!(apply(keep, 8, &mut sys).is_err());
+        assert_eq!(sys.calls, ["drop chown"]);
+    }
+}
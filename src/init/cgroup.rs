@@ -0,0 +1,217 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::config::Config;
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+use system::SystemError;
+
+// Mount point of the unified (v2) cgroup hierarchy
+pub const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+// Child group the application is placed into
+const APP_CGROUP: &str = "nautilus.slice";
+
+// Resource limits for the application cgroup, e.g.
+// `nautilus.cgroup.memory_max=256M nautilus.cgroup.pids_max=512`
+#[derive(Debug, Default, PartialEq)]
+pub struct CgroupLimits {
+    // Written to memory.max: bytes with an optional K/M/G/T suffix, or `max`
+    pub memory_max: Option<String>,
+    // Written to pids.max: a process count, or `max`
+    pub pids_max: Option<String>,
+}
+
+impl CgroupLimits {
+    pub fn from_config(config: &Config) -> Result<Self, SystemError> {
+        Ok(Self {
+            memory_max: config
+                .get("nautilus.cgroup.memory_max")
+                .map(parse_memory_max)
+                .transpose()?,
+            pids_max: config
+                .get("nautilus.cgroup.pids_max")
+                .map(parse_pids_max)
+                .transpose()?,
+        })
+    }
+
+    // Controllers the parent must delegate for these limits to apply
+    fn controllers(&self) -> Vec<&'static str> {
+        let mut controllers = Vec::new();
+        if self.memory_max.is_some() {
+            controllers.push("+memory");
+        }
+        if self.pids_max.is_some() {
+            controllers.push("+pids");
+        }
+        controllers
+    }
+}
+
+fn parse_memory_max(value: &str) -> Result<String, SystemError> {
+    let digits = value.trim_end_matches(['K', 'M', 'G', 'T', 'k', 'm', 'g', 't']);
+    let suffix_len = value.len() - digits.len();
+    if value == "max"
+        || (suffix_len <= 1 && !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()))
+    {
+        Ok(value.to_string())
+    } else {
+        Err(SystemError {
+            message: format!("Invalid nautilus.cgroup.memory_max: {}", value),
+        })
+    }
+}
+
+fn parse_pids_max(value: &str) -> Result<String, SystemError> {
+    if value == "max" || value.parse::<u64>().is_ok() {
+        Ok(value.to_string())
+    } else {
+        Err(SystemError {
+            message: format!("Invalid nautilus.cgroup.pids_max: {}", value),
+        })
+    }
+}
+
+fn write(path: &Path, value: &str) -> Result<(), SystemError> {
+    fs::write(path, value).map_err(|e| SystemError {
+        message: format!("Failed to write {} to {}: {}", value, path.display(), e),
+    })
+}
+
+// Application cgroup created under a cgroup2 root
+pub struct Cgroup {
+    path: PathBuf,
+}
+
+impl Cgroup {
+    // Delegate the needed controllers from `root` and create the limited child group
+    pub fn create(root: &Path, limits: &CgroupLimits) -> Result<Self, SystemError> {
+        let controllers = limits.controllers();
+        if !controllers.is_empty() {
+            write(&root.join("cgroup.subtree_control"), &controllers.join(" "))?;
+        }
+        let path = root.join(APP_CGROUP);
+        fs::create_dir_all(&path).map_err(|e| SystemError {
+            message: format!("Failed to create cgroup {}: {}", path.display(), e),
+        })?;
+        if let Some(memory_max) = &limits.memory_max {
+            write(&path.join("memory.max"), memory_max)?;
+        }
+        if let Some(pids_max) = &limits.pids_max {
+            write(&path.join("pids.max"), pids_max)?;
+        }
+        Ok(Self { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Open cgroup.procs for writing. Writing "0" to it from the forked child
+    // before exec moves the child into this group.
+    pub fn procs_file(&self) -> Result<File, SystemError> {
+        let path = self.path.join("cgroup.procs");
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .map_err(|e| SystemError {
+                message: format!("Failed to open {}: {}", path.display(), e),
+            })
+    }
+
+    // Number of processes in this group killed by the OOM killer so far
+    pub fn oom_kills(&self) -> u64 {
+        fs::read_to_string(self.path.join("memory.events"))
+            .ok()
+            .and_then(|events| {
+                events.lines().find_map(|line| {
+                    line.strip_prefix("oom_kill ")
+                        .and_then(|count| count.trim().parse().ok())
+                })
+            })
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn test_limits_from_config() {
+        let config = Config::parse("nautilus.cgroup.memory_max=256M nautilus.cgroup.pids_max=max");
+        assert_eq!(
+            CgroupLimits::from_config(&config).unwrap(),
+            CgroupLimits {
+                memory_max: Some("256M".to_string()),
+                pids_max: Some("max".to_string()),
+            }
+        );
+        assert_eq!(
+            CgroupLimits::from_config(&Config::parse("")).unwrap(),
+            CgroupLimits::default()
+        );
+    }
+
+    #[test]
+    fn test_limits_reject_invalid_values() {
+        for cmdline in [
+            "nautilus.cgroup.memory_max=256MB",
+            "nautilus.cgroup.memory_max=M",
+            "nautilus.cgroup.memory_max=-1",
+            "nautilus.cgroup.pids_max=lots",
+        ] {
+            assert!(CgroupLimits::from_config(&Config::parse(cmdline)).is_err());
+        }
+    }
+
+    #[test]
+    fn test_create_writes_limits() {
+        let root = TempDir::new("cgroup-limits");
+        let limits = CgroupLimits {
+            memory_max: Some("128M".to_string()),
+            pids_max: Some("64".to_string()),
+        };
+        let cgroup = Cgroup::create(root.path(), &limits).unwrap();
+        let read = |path: PathBuf| fs::read_to_string(path).unwrap();
+        assert_eq!(
+            read(root.path().join("cgroup.subtree_control")),
+            "+memory +pids"
+        );
+        assert_eq!(read(cgroup.path().join("memory.max")), "128M");
+        assert_eq!(read(cgroup.path().join("pids.max")), "64");
+    }
+
+    #[test]
+    fn test_create_without_limits_skips_controllers() {
+        let root = TempDir::new("cgroup-nolimits");
+        let cgroup = Cgroup::create(root.path(), &CgroupLimits::default()).unwrap();
+        assert!(cgroup.path().is_dir());
+        assert!(!root.path().join("cgroup.subtree_control").exists());
+        assert!(!cgroup.path().join("memory.max").exists());
+    }
+
+    #[test]
+    fn test_oom_kills() {
+        let root = TempDir::new("cgroup-oom");
+        let cgroup = Cgroup::create(root.path(), &CgroupLimits::default()).unwrap();
+        assert_eq!(cgroup.oom_kills(), 0);
+        fs::write(
+            cgroup.path().join("memory.events"),
+            "low 0\nhigh 0\nmax 3\noom 1\noom_kill 1\noom_group_kill 0\n",
+        )
+        .unwrap();
+        assert_eq!(cgroup.oom_kills(), 1);
+    }
+
+    #[test]
+    fn test_procs_file() {
+        let root = TempDir::new("cgroup-procs");
+        let cgroup = Cgroup::create(root.path(), &CgroupLimits::default()).unwrap();
+        assert!(cgroup.procs_file().is_err());
+        fs::write(cgroup.path().join("cgroup.procs"), "").unwrap();
+        assert!(cgroup.procs_file().is_ok());
+    }
+}
@@ -0,0 +1,111 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::sysops::SysOps;
+use std::collections::BTreeMap;
+use system::SystemError;
+
+// Boot options are read from the kernel command line baked into the EIF (see the
+// `--cmdline` passed to eif_build in the Containerfile). Keys use dotted names such
+// as `nautilus.cgroup.memory_max=256M`: the kernel treats dotted parameters as
+// module options, so they stay out of init's argv/environment but remain visible
+// in /proc/cmdline. Values cannot contain whitespace.
+const CMDLINE_PATH: &str = "/proc/cmdline";
+
+// Key/value options parsed from the kernel command line
+#[derive(Debug, Default)]
+pub struct Config {
+    params: BTreeMap<String, String>,
+}
+
+impl Config {
+    // Read options from /proc/cmdline, falling back to defaults if unavailable
+    pub fn load(sys: &dyn SysOps) -> Self {
+        match sys.read_file(CMDLINE_PATH) {
+            Ok(cmdline) => Self::parse(&cmdline),
+            Err(e) => {
+                eprintln!("Failed to read {}: {}", CMDLINE_PATH, e);
+                Self::default()
+            }
+        }
+    }
+
+    // Parse a command line; bare flags are stored with an empty value
+    pub fn parse(cmdline: &str) -> Self {
+        let params = cmdline
+            .split_whitespace()
+            .map(|param| match param.split_once('=') {
+                Some((key, value)) => (key.to_string(), value.to_string()),
+                None => (param.to_string(), String::new()),
+            })
+            .collect();
+        Self { params }
+    }
+
+    // Raw value of an option, if present
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.params.get(key).map(String::as_str)
+    }
+
+    // Options whose key starts with `prefix`, in key order and with the prefix
+    // removed
+    pub fn prefixed<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = (&'a str, &'a str)> {
+        self.params
+            .iter()
+            .filter_map(move |(key, value)| Some((key.strip_prefix(prefix)?, value.as_str())))
+    }
+
+    // Boolean option; a bare flag counts as enabled
+    pub fn get_bool(&self, key: &str, default: bool) -> Result<bool, SystemError> {
+        match self.get(key) {
+            None => Ok(default),
+            Some("" | "1" | "true" | "yes" | "on") => Ok(true),
+            Some("0" | "false" | "no" | "off") => Ok(false),
+            Some(value) => Err(SystemError {
+                message: format!("Invalid boolean for {}: {}", key, value),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let config =
+            Config::parse("reboot=k console=ttyS0 nautilus.cgroup.memory_max=256M i8042.noaux\n");
+        assert_eq!(config.get("console"), Some("ttyS0"));
+        assert_eq!(config.get("nautilus.cgroup.memory_max"), Some("256M"));
+        assert_eq!(config.get("i8042.noaux"), Some(""));
+        assert_eq!(config.get("nautilus.cgroup.pids_max"), None);
+    }
+
+    #[test]
+    fn test_get_bool() {
+        let config = Config::parse("a b=1 c=off d=maybe");
+        assert!(config.get_bool("a", false).unwrap());
+        assert!(config.get_bool("b", false).unwrap());
+        assert!(!config.get_bool("c", true).unwrap());
+        assert!(config.get_bool("d", true).is_err());
+        assert!(config.get_bool("missing", true).unwrap());
+    }
+
+    #[test]
+    fn test_prefixed() {
+        let config =
+            Config::parse("sysctl.vm.swappiness=10 console=ttyS0 sysctl.net.core.somaxconn=4096");
+        assert_eq!(
+            config.prefixed("sysctl.").collect::<Vec<_>>(),
+            [("net.core.somaxconn", "4096"), ("vm.swappiness", "10")]
+        );
+        assert_eq!(config.prefixed("nautilus.").count(), 0);
+    }
+
+    #[test]
+    fn test_parse_keeps_last_duplicate() {
+        let config = Config::parse("nautilus.cgroup.pids_max=1 nautilus.cgroup.pids_max=2");
+        assert_eq!(config.get("nautilus.cgroup.pids_max"), Some("2"));
+    }
+}
@@ -0,0 +1,267 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::{BTreeMap, BTreeSet};
+use system::SystemError;
+
+// Optional manifest of environment variables for the application, baked into
+// the image. Each line is `KEY=VALUE`; blank lines and lines starting with `#`
+// are ignored. Values may be quoted TOML-style, so a flat TOML file of string
+// keys (`KEY = "value" # comment`) is also accepted. `"..."` supports the
+// usual backslash escapes and `'...'` is taken literally; unquoted values run
+// to the end of the line. Values may reference secrets received over vsock
+// with `$VSOCK_SECRET(<name>)`.
+pub const ENV_MANIFEST_PATH: &str = "/env.manifest";
+
+const PLACEHOLDER_PREFIX: &str = "$VSOCK_SECRET(";
+
+#[derive(Debug, PartialEq)]
+pub struct EnvVar {
+    pub key: String,
+    pub value: String,
+    // 1-based line in the manifest, for error messages
+    pub line: usize,
+}
+
+fn line_error(line: usize, message: &str) -> SystemError {
+    SystemError {
+        message: format!("{} line {}: {}", ENV_MANIFEST_PATH, line, message),
+    }
+}
+
+// Parse the manifest, naming the first offending line on error
+pub fn parse(text: &str) -> Result<Vec<EnvVar>, SystemError> {
+    let mut vars = Vec::new();
+    for (i, raw) in text.lines().enumerate() {
+        let line = i + 1;
+        let entry = raw.trim();
+        if entry.is_empty() || entry.starts_with('#') {
+            continue;
+        }
+        if entry.starts_with('[') {
+            return Err(line_error(line, "TOML tables are not supported"));
+        }
+        let (key, value) = entry
+            .split_once('=')
+            .ok_or_else(|| line_error(line, "expected KEY=VALUE"))?;
+        let key = key.trim();
+        if !is_valid_key(key) {
+            return Err(line_error(
+                line,
+                &format!("invalid variable name {:?}", key),
+            ));
+        }
+        vars.push(EnvVar {
+            key: key.to_string(),
+            value: parse_value(value.trim()).map_err(|e| line_error(line, e))?,
+            line,
+        });
+    }
+    Ok(vars)
+}
+
+pub fn is_valid_key(key: &str) -> bool {
+    let mut chars = key.chars();
+    matches!(chars.next(), Some(c) if c == '_' || c.is_ascii_alphabetic())
+        && chars.all(|c| c == '_' || c.is_ascii_alphanumeric())
+}
+
+pub fn parse_value(value: &str) -> Result<String, &'static str> {
+    let (quote, rest) = match value.chars().next() {
+        Some(quote @ ('"' | '\'')) => (quote, &value[1..]),
+        _ => return Ok(value.to_string()),
+    };
+    let mut parsed = String::new();
+    let mut chars = rest.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            c if c == quote => {
+                let trailing = rest[i + 1..].trim_start();
+                return if trailing.is_empty() || trailing.starts_with('#') {
+                    Ok(parsed)
+                } else {
+                    Err("unexpected text after closing quote")
+                };
+            }
+            '\\' if quote == '"' => parsed.push(match chars.next() {
+                Some((_, '"')) => '"',
+                Some((_, '\\')) => '\\',
+                Some((_, 'n')) => '\n',
+                Some((_, 't')) => '\t',
+                Some((_, 'r')) => '\r',
+                _ => return Err("invalid escape sequence"),
+            }),
+            c => parsed.push(c),
+        }
+    }
+    Err("unterminated quoted value")
+}
+
+// Whether any variable not overridden by `cmdline_env` references a vsock secret
+pub fn needs_secrets(vars: &[EnvVar], cmdline_env: &BTreeSet<String>) -> bool {
+    vars.iter()
+        .any(|var| !cmdline_env.contains(&var.key) && var.value.contains(PLACEHOLDER_PREFIX))
+}
+
+// Substitute `$VSOCK_SECRET(<name>)` placeholders in a value
+fn resolve_value(
+    var: &EnvVar,
+    secrets: Option<&BTreeMap<String, String>>,
+) -> Result<String, SystemError> {
+    let mut resolved = String::new();
+    let mut rest = var.value.as_str();
+    while let Some(start) = rest.find(PLACEHOLDER_PREFIX) {
+        resolved.push_str(&rest[..start]);
+        let after = &rest[start + PLACEHOLDER_PREFIX.len()..];
+        let end = after
+            .find(')')
+            .ok_or_else(|| line_error(var.line, "unterminated $VSOCK_SECRET placeholder"))?;
+        let name = &after[..end];
+        let secrets = secrets.ok_or_else(|| {
+            line_error(
+                var.line,
+                "$VSOCK_SECRET used but no secrets channel is configured (nautilus.secrets_port)",
+            )
+        })?;
+        let secret = secrets
+            .get(name)
+            .ok_or_else(|| line_error(var.line, &format!("secret {:?} was not provided", name)))?;
+        resolved.push_str(secret);
+        rest = &after[end + 1..];
+    }
+    resolved.push_str(rest);
+    Ok(resolved)
+}
+
+// Resolve the manifest into the variables to set on the application. Later
+// lines override earlier ones, and variables in `cmdline_env` (passed to init
+// by the kernel command line) take precedence over the manifest.
+pub fn resolve(
+    vars: &[EnvVar],
+    secrets: Option<&BTreeMap<String, String>>,
+    cmdline_env: &BTreeSet<String>,
+) -> Result<BTreeMap<String, String>, SystemError> {
+    let mut env = BTreeMap::new();
+    for var in vars {
+        if cmdline_env.contains(&var.key) {
+            continue;
+        }
+        env.insert(var.key.clone(), resolve_value(var, secrets)?);
+    }
+    Ok(env)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MANIFEST: &str = "\
+# Weather example
+API_KEY=$VSOCK_SECRET(API_KEY)
+RUST_LOG = \"info\" # TOML-style
+GREETING='hello \\n world'
+ESCAPED=\"a\\\"b\\tc\"
+URL=https://example.com/?a=b#frag
+
+DNS_CACHE_TTL_SECS=30
+DNS_CACHE_TTL_SECS=60
+";
+
+    fn env(vars: &[(&str, &str)]) -> BTreeMap<String, String> {
+        vars.iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        let vars = parse(MANIFEST).unwrap();
+        assert_eq!(
+            vars[0],
+            EnvVar {
+                key: "API_KEY".to_string(),
+                value: "$VSOCK_SECRET(API_KEY)".to_string(),
+                line: 2,
+            }
+        );
+        let values: Vec<(&str, &str)> = vars
+            .iter()
+            .map(|var| (var.key.as_str(), var.value.as_str()))
+            .collect();
+        assert_eq!(
+            values[1..],
+            [
+                ("RUST_LOG", "info"),
+                ("GREETING", "hello \\n world"),
+                ("ESCAPED", "a\"b\tc"),
+                ("URL", "https://example.com/?a=b#frag"),
+                ("DNS_CACHE_TTL_SECS", "30"),
+                ("DNS_CACHE_TTL_SECS", "60"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_errors_name_line() {
+        for (manifest, line) in [
+            ("A=1\nnot a variable\n", 2),
+            ("A=1\n\n1A=2\n", 3),
+            ("[server]\nA=1\n", 1),
+            ("A=\"unterminated\n", 1),
+            ("A=\"1\" 2\n", 1),
+            ("A=\"\\q\"\n", 1),
+        ] {
+            let err = parse(manifest).unwrap_err();
+            assert!(
+                err.message.contains(&format!("line {}:", line)),
+                "{}: {}",
+                manifest,
+                err.message
+            );
+        }
+    }
+
+    #[test]
+    fn test_resolve_placeholders() {
+        let vars = parse(
+            "API_KEY=$VSOCK_SECRET(API_KEY)\nDSN=postgres://$VSOCK_SECRET(USER):$VSOCK_SECRET(PASS)@db\n",
+        )
+        .unwrap();
+        assert!(needs_secrets(&vars, &BTreeSet::new()));
+        let secrets = env(&[("API_KEY", "k"), ("USER", "u"), ("PASS", "p")]);
+        assert_eq!(
+            resolve(&vars, Some(&secrets), &BTreeSet::new()).unwrap(),
+            env(&[("API_KEY", "k"), ("DSN", "postgres://u:p@db")])
+        );
+    }
+
+    #[test]
+    fn test_resolve_placeholder_errors() {
+        let vars = parse("A=1\nAPI_KEY=$VSOCK_SECRET(API_KEY)\n").unwrap();
+        let no_channel = resolve(&vars, None, &BTreeSet::new()).unwrap_err();
+        assert!(no_channel.message.contains("line 2:"));
+        let missing = resolve(&vars, Some(&BTreeMap::new()), &BTreeSet::new()).unwrap_err();
+        assert!(missing.message.contains("\"API_KEY\" was not provided"));
+        let unterminated = parse("A=$VSOCK_SECRET(API_KEY\n").unwrap();
+        assert!(resolve(&unterminated, Some(&BTreeMap::new()), &BTreeSet::new()).is_err());
+        assert!(!needs_secrets(
+            &parse("A=$HOME\n").unwrap(),
+            &BTreeSet::new()
+        ));
+    }
+
+    #[test]
+    fn test_cmdline_env_takes_precedence() {
+        let vars = parse("RUST_LOG=info\nDNS_CACHE_TTL_SECS=30\n").unwrap();
+        let cmdline_env = BTreeSet::from(["RUST_LOG".to_string()]);
+        assert_eq!(
+            resolve(&vars, None, &cmdline_env).unwrap(),
+            env(&[("DNS_CACHE_TTL_SECS", "30")])
+        );
+        // A placeholder overridden from the cmdline needs no secrets channel
+        let vars = parse("API_KEY=$VSOCK_SECRET(API_KEY)\n").unwrap();
+        let cmdline_env = BTreeSet::from(["API_KEY".to_string()]);
+        assert!(!needs_secrets(&vars, &cmdline_env));
+        assert!(resolve(&vars, None, &cmdline_env).unwrap().is_empty());
+    }
+}
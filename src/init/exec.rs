@@ -0,0 +1,156 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::config::Config;
+use std::collections::BTreeMap;
+use std::process::Command;
+use system::SystemError;
+
+// How init starts the application, chosen with `nautilus.exec`:
+// - `script` (the default) runs `/sh /run.sh`, which sets up the network and
+//   environment and starts the server
+// - `direct` execs the server binary itself with no shell in between, taking
+//   its environment from /env.manifest, forwarding from `nautilus.tcp_forward`
+//   and hosts from `nautilus.hosts` and `nautilus.host`, so the image needs neither busybox nor
+//   run.sh. The binary is `nautilus.exec.path` (default /nautilus-server) and
+//   its arguments are the comma-separated `nautilus.exec.args`.
+
+pub const DEFAULT_SERVER_PATH: &str = "/nautilus-server";
+
+#[derive(Debug, PartialEq)]
+pub enum AppExec {
+    Script,
+    Direct { path: String, args: Vec<String> },
+}
+
+impl AppExec {
+    pub fn from_config(config: &Config) -> Result<Self, SystemError> {
+        let invalid = |key: &str, value: &str| SystemError {
+            message: format!("Invalid {}: {}", key, value),
+        };
+        match config.get("nautilus.exec").unwrap_or("script") {
+            "script" => Ok(Self::Script),
+            "direct" => {
+                let path = config
+                    .get("nautilus.exec.path")
+                    .unwrap_or(DEFAULT_SERVER_PATH);
+                if !path.starts_with('/') {
+                    return Err(invalid("nautilus.exec.path", path));
+                }
+                let args = config
+                    .get("nautilus.exec.args")
+                    .unwrap_or("")
+                    .split(',')
+                    .filter(|arg| !arg.is_empty())
+                    .map(str::to_string)
+                    .collect();
+                Ok(Self::Direct {
+                    path: path.to_string(),
+                    args,
+                })
+            }
+            exec => Err(invalid("nautilus.exec", exec)),
+        }
+    }
+
+    // Name used in log messages
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Script => "run.sh",
+            Self::Direct { path, .. } => path,
+        }
+    }
+
+    // Command starting the application with `app_env` on top of init's environment
+    pub fn command(&self, app_env: &BTreeMap<String, String>) -> Command {
+        let mut command = match self {
+            Self::Script => {
+                let mut command = Command::new("/sh");
+                command.arg("/run.sh");
+                command
+            }
+            Self::Direct { path, args } => {
+                let mut command = Command::new(path);
+                command.args(args);
+                command
+            }
+        };
+        command.envs(app_env);
+        command
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn app_exec(cmdline: &str) -> Result<AppExec, SystemError> {
+        AppExec::from_config(&Config::parse(cmdline))
+    }
+
+    // Program, arguments and `KEY=VALUE` variables set on a command
+    fn describe(command: &Command) -> (String, Vec<String>, Vec<String>) {
+        let text = |s: &std::ffi::OsStr| s.to_string_lossy().into_owned();
+        (
+            text(command.get_program()),
+            command.get_args().map(text).collect(),
+            command
+                .get_envs()
+                .map(|(key, value)| {
+                    format!("{}={}", text(key), value.map(text).unwrap_or_default())
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_script_command() {
+        let exec = app_exec("").unwrap();
+        assert_eq!(exec, AppExec::Script);
+        let env = BTreeMap::from([("API_KEY".to_string(), "k".to_string())]);
+        assert_eq!(
+            describe(&exec.command(&env)),
+            (
+                "/sh".to_string(),
+                vec!["/run.sh".to_string()],
+                vec!["API_KEY=k".to_string()]
+            )
+        );
+        assert_eq!(app_exec("nautilus.exec=script").unwrap(), AppExec::Script);
+    }
+
+    #[test]
+    fn test_direct_command() {
+        let exec = app_exec("nautilus.exec=direct").unwrap();
+        assert_eq!(
+            exec,
+            AppExec::Direct {
+                path: DEFAULT_SERVER_PATH.to_string(),
+                args: Vec::new()
+            }
+        );
+        let exec = app_exec(
+            "nautilus.exec=direct nautilus.exec.path=/bin/server nautilus.exec.args=--port,3000",
+        )
+        .unwrap();
+        let env = BTreeMap::from([
+            ("API_KEY".to_string(), "k".to_string()),
+            ("RUST_LOG".to_string(), "info".to_string()),
+        ]);
+        assert_eq!(
+            describe(&exec.command(&env)),
+            (
+                "/bin/server".to_string(),
+                vec!["--port".to_string(), "3000".to_string()],
+                vec!["API_KEY=k".to_string(), "RUST_LOG=info".to_string()]
+            )
+        );
+        assert_eq!(exec.name(), "/bin/server");
+    }
+
+    #[test]
+    fn test_invalid_exec() {
+        assert!(app_exec("nautilus.exec=shell").is_err());
+        assert!(app_exec("nautilus.exec=direct nautilus.exec.path=nautilus-server").is_err());
+    }
+}
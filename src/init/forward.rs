@@ -0,0 +1,364 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::config::Config;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::mem::{size_of, zeroed};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::os::unix::io::FromRawFd;
+use std::os::unix::net::UnixStream;
+use std::thread;
+use std::time::Duration;
+use system::{dmesg, socket_connect, socket_listen, SystemError};
+
+// Byte-stream forwarding between vsock and local TCP, replacing the socat
+// processes run.sh used to start. Rules come from the kernel command line as
+// comma-separated lists, e.g.
+// `nautilus.vsock_forward=3000:127.0.0.1:3000` forwards connections on vsock
+// port 3000 to the server on localhost:3000, and
+// `nautilus.tcp_forward=127.0.0.64:443:3:8101` forwards connections to a local
+// address to vsock port 8101 on the parent (CID 3).
+
+// Delay before listening again after a listener fails
+const RELISTEN_DELAY: Duration = Duration::from_secs(1);
+
+// A connected stream that can be split into two directions
+pub trait Stream: Read + Write + Send + Sized + 'static {
+    fn try_clone(&self) -> io::Result<Self>;
+    fn shutdown_write(&self);
+}
+
+impl Stream for TcpStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        TcpStream::try_clone(self)
+    }
+
+    fn shutdown_write(&self) {
+        let _ = self.shutdown(Shutdown::Write);
+    }
+}
+
+// Vsock connections are held as UnixStream, whose read, write, shutdown and
+// dup calls work on any stream socket
+impl Stream for UnixStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        UnixStream::try_clone(self)
+    }
+
+    fn shutdown_write(&self) {
+        let _ = self.shutdown(Shutdown::Write);
+    }
+}
+
+// Source of incoming connections, returning each with a peer description
+pub trait Listener: Send + 'static {
+    type Stream: Stream;
+    fn accept(&self) -> io::Result<(Self::Stream, String)>;
+}
+
+impl Listener for TcpListener {
+    type Stream = TcpStream;
+
+    fn accept(&self) -> io::Result<(TcpStream, String)> {
+        let (stream, peer) = TcpListener::accept(self)?;
+        Ok((stream, peer.to_string()))
+    }
+}
+
+// Listening vsock socket
+pub struct VsockListener {
+    fd: UnixStream,
+}
+
+impl VsockListener {
+    pub fn bind(port: u32) -> Result<Self, SystemError> {
+        let fd = socket_listen(port, 128)?;
+        // Safety: fd is a listening socket owned by nothing else
+        Ok(Self {
+            fd: unsafe { UnixStream::from_raw_fd(fd) },
+        })
+    }
+}
+
+impl Listener for VsockListener {
+    type Stream = UnixStream;
+
+    fn accept(&self) -> io::Result<(UnixStream, String)> {
+        use libc::{accept, sockaddr, sockaddr_vm};
+        use std::os::unix::io::AsRawFd;
+        let mut peer: sockaddr_vm = unsafe { zeroed() };
+        let mut len = size_of::<sockaddr_vm>() as _;
+        let conn = unsafe {
+            accept(
+                self.fd.as_raw_fd(),
+                &mut peer as *mut _ as *mut sockaddr,
+                &mut len,
+            )
+        };
+        if conn < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // Safety: conn is a connected socket owned by nothing else
+        let stream = unsafe { UnixStream::from_raw_fd(conn) };
+        Ok((stream, format!("vsock {}:{}", peer.svm_cid, peer.svm_port)))
+    }
+}
+
+// One end of a forwarding rule
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Endpoint {
+    Vsock { cid: u32, port: u32 },
+    Tcp(SocketAddr),
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Endpoint::Vsock { cid, port } => write!(f, "vsock {}:{}", cid, port),
+            Endpoint::Tcp(addr) => write!(f, "tcp {}", addr),
+        }
+    }
+}
+
+// Forward connections accepted on `listen` to `target`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rule {
+    pub listen: Endpoint,
+    pub target: Endpoint,
+}
+
+fn invalid(key: &str, rule: &str) -> SystemError {
+    SystemError {
+        message: format!("Invalid {} rule: {}", key, rule),
+    }
+}
+
+// Parse `nautilus.vsock_forward` (`<vsock port>:<tcp addr>:<tcp port>`) and
+// `nautilus.tcp_forward` (`<tcp addr>:<tcp port>:<cid>:<vsock port>`) rules
+pub fn rules_from_config(config: &Config) -> Result<Vec<Rule>, SystemError> {
+    let mut rules = Vec::new();
+    let key = "nautilus.vsock_forward";
+    for rule in config
+        .get(key)
+        .unwrap_or("")
+        .split(',')
+        .filter(|r| !r.is_empty())
+    {
+        let (port, target) = rule.split_once(':').ok_or_else(|| invalid(key, rule))?;
+        rules.push(Rule {
+            listen: Endpoint::Vsock {
+                cid: libc::VMADDR_CID_ANY,
+                port: port.parse().map_err(|_| invalid(key, rule))?,
+            },
+            target: Endpoint::Tcp(target.parse().map_err(|_| invalid(key, rule))?),
+        });
+    }
+    let key = "nautilus.tcp_forward";
+    for rule in config
+        .get(key)
+        .unwrap_or("")
+        .split(',')
+        .filter(|r| !r.is_empty())
+    {
+        let mut fields = rule.rsplitn(3, ':');
+        let (port, cid, listen) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(port), Some(cid), Some(listen)) => (port, cid, listen),
+            _ => return Err(invalid(key, rule)),
+        };
+        rules.push(Rule {
+            listen: Endpoint::Tcp(listen.parse().map_err(|_| invalid(key, rule))?),
+            target: Endpoint::Vsock {
+                cid: cid.parse().map_err(|_| invalid(key, rule))?,
+                port: port.parse().map_err(|_| invalid(key, rule))?,
+            },
+        });
+    }
+    Ok(rules)
+}
+
+// Copy bytes in both directions until both sides have closed
+pub fn proxy<A: Stream, B: Stream>(client: A, upstream: B) -> io::Result<()> {
+    let mut client_read = client.try_clone()?;
+    let mut upstream_write = upstream.try_clone()?;
+    let to_upstream = thread::spawn(move || {
+        let copied = io::copy(&mut client_read, &mut upstream_write);
+        upstream_write.shutdown_write();
+        copied
+    });
+    let (mut upstream_read, mut client_write) = (upstream, client);
+    let from_upstream = io::copy(&mut upstream_read, &mut client_write);
+    client_write.shutdown_write();
+    let to_upstream = to_upstream
+        .join()
+        .map_err(|_| io::Error::new(io::ErrorKind::Other, "forwarding thread panicked"))?;
+    to_upstream.and(from_upstream).map(|_| ())
+}
+
+// Accept connections until the listener fails, forwarding each on its own thread
+pub fn accept_loop<L, S, F>(name: &str, listener: &L, connect: &F) -> io::Error
+where
+    L: Listener,
+    S: Stream,
+    F: Fn() -> io::Result<S> + Clone + Send + 'static,
+{
+    loop {
+        let (client, peer) = match listener.accept() {
+            Ok(accepted) => accepted,
+            Err(e) => return e,
+        };
+        let name = name.to_string();
+        let connect = connect.clone();
+        thread::spawn(move || match connect() {
+            Ok(upstream) => {
+                dmesg(format!("{}: connection from {}", name, peer));
+                match proxy(client, upstream) {
+                    Ok(()) => dmesg(format!("{}: connection from {} closed", name, peer)),
+                    Err(e) => eprintln!("{}: connection from {} failed: {}", name, peer, e),
+                }
+            }
+            Err(e) => eprintln!("{}: dropping connection from {}: {}", name, peer, e),
+        });
+    }
+}
+
+// Listen and forward forever, listening again whenever the listener fails
+pub fn serve<L, S, B, F>(name: &str, bind: B, connect: F) -> !
+where
+    L: Listener,
+    S: Stream,
+    B: Fn() -> io::Result<L>,
+    F: Fn() -> io::Result<S> + Clone + Send + 'static,
+{
+    loop {
+        match bind() {
+            Ok(listener) => {
+                dmesg(format!("{}: listening", name));
+                let e = accept_loop(name, &listener, &connect);
+                eprintln!("{}: listener failed: {}", name, e);
+            }
+            Err(e) => eprintln!("{}: failed to listen: {}", name, e),
+        }
+        thread::sleep(RELISTEN_DELAY);
+    }
+}
+
+// Connect to vsock `port` on `cid`, e.g. the parent's (3)
+pub fn connect_vsock(cid: u32, port: u32) -> io::Result<UnixStream> {
+    let fd = socket_connect(libc::AF_VSOCK, port, cid)
+        .map_err(|e| io::Error::new(io::ErrorKind::ConnectionRefused, e.message))?;
+    // Safety: fd is a connected socket owned by nothing else
+    Ok(unsafe { UnixStream::from_raw_fd(fd) })
+}
+
+// Run a forwarding rule on a background thread
+pub fn spawn(rule: Rule) {
+    let name = format!("forward {} -> {}", rule.listen, rule.target);
+    thread::spawn(move || match (rule.listen, rule.target) {
+        (Endpoint::Vsock { port, .. }, Endpoint::Tcp(target)) => serve(
+            &name,
+            move || {
+                VsockListener::bind(port)
+                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e.message))
+            },
+            move || TcpStream::connect(target),
+        ),
+        (Endpoint::Tcp(listen), Endpoint::Vsock { cid, port }) => serve(
+            &name,
+            move || TcpListener::bind(listen),
+            move || connect_vsock(cid, port),
+        ),
+        _ => eprintln!("{}: unsupported rule", name),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn echo_server() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                thread::spawn(move || {
+                    let mut reader = stream.try_clone().unwrap();
+                    io::copy(&mut reader, &mut stream).unwrap();
+                });
+            }
+        });
+        addr
+    }
+
+    // Forward from a loopback listener standing in for the vsock listener
+    fn forwarder(target: SocketAddr) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || accept_loop("test", &listener, &move || TcpStream::connect(target)));
+        addr
+    }
+
+    #[test]
+    fn test_forwards_both_directions() {
+        let addr = forwarder(echo_server());
+        for _ in 0..2 {
+            let mut client = TcpStream::connect(addr).unwrap();
+            client.write_all(b"ping").unwrap();
+            client.shutdown(Shutdown::Write).unwrap();
+            let mut reply = String::new();
+            client.read_to_string(&mut reply).unwrap();
+            assert_eq!(reply, "ping");
+        }
+    }
+
+    #[test]
+    fn test_unreachable_target_closes_connection() {
+        let closed = TcpListener::bind("127.0.0.1:0").unwrap();
+        let target = closed.local_addr().unwrap();
+        drop(closed);
+        let mut client = TcpStream::connect(forwarder(target)).unwrap();
+        let mut reply = Vec::new();
+        client.read_to_end(&mut reply).unwrap();
+        assert!(reply.is_empty());
+    }
+
+    #[test]
+    fn test_rules_from_config() {
+        let config = Config::parse(
+            "nautilus.vsock_forward=3000:127.0.0.1:3000,3001:127.0.0.1:3001 \
+             nautilus.tcp_forward=127.0.0.64:443:3:8101",
+        );
+        let rules = rules_from_config(&config).unwrap();
+        assert_eq!(rules.len(), 3);
+        assert_eq!(
+            rules[0].target,
+            Endpoint::Tcp("127.0.0.1:3000".parse().unwrap())
+        );
+        assert_eq!(
+            rules[2],
+            Rule {
+                listen: Endpoint::Tcp("127.0.0.64:443".parse().unwrap()),
+                target: Endpoint::Vsock { cid: 3, port: 8101 },
+            }
+        );
+        assert!(rules_from_config(&Config::parse("")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_rules_reject_invalid() {
+        for cmdline in [
+            "nautilus.vsock_forward=3000",
+            "nautilus.vsock_forward=x:127.0.0.1:3000",
+            "nautilus.vsock_forward=3000:localhost:3000",
+            "nautilus.tcp_forward=127.0.0.64:443:3",
+            "nautilus.tcp_forward=127.0.0.64:443:parent:8101",
+        ] {
+            assert!(
+                rules_from_config(&Config::parse(cmdline)).is_err(),
+                "{}",
+                cmdline
+            );
+        }
+    }
+}
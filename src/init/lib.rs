@@ -0,0 +1,38 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+// Shared by the two binaries: init, which boots the enclave and stays PID 1,
+// and nautilus-supervisor, which it runs to start the application. See
+// boot.rs, pid1.rs and supervisor.rs.
+
+pub mod applog;
+pub mod boot;
+pub mod boottime;
+pub mod cabundle;
+pub mod caps;
+pub mod cgroup;
+pub mod config;
+pub mod env_manifest;
+pub mod exec;
+pub mod forward;
+pub mod lifecycle;
+pub mod logsink;
+pub mod netconf;
+pub mod netwait;
+pub mod pid1;
+pub mod preflight;
+pub mod pseudofs;
+pub mod readonly;
+pub mod rlimit;
+#[cfg(feature = "seccomp")]
+pub mod seccomp;
+pub mod secrets;
+pub mod services;
+pub mod sha256;
+pub mod supervisor;
+pub mod sysctl;
+pub mod sysops;
+#[cfg(test)]
+mod testing;
+pub mod tmpfs;
+pub mod watchdog;
@@ -0,0 +1,207 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::config::Config;
+use crate::sysops::SysOps;
+use system::{dmesg, SystemError};
+
+// What init does once run.sh exits. Rebooting a Nitro enclave restarts it, so a
+// deliberate exit powers off instead of looping:
+// - exit code `nautilus.shutdown_exit_code` (default 42, an operator-requested
+//   shutdown) always powers off
+// - a clean exit (status 0) takes `nautilus.on_clean_exit`: `poweroff`
+//   (default) or `reboot`
+// - any other exit restarts run.sh until `nautilus.restart_budget` restarts
+//   (default 0) are used up, then reboots
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EndAction {
+    Restart,
+    Reboot,
+    Poweroff,
+}
+
+// Exit codes nautilus-supervisor reports its final action to init with. Any
+// other exit, including a panic or a signal, makes init restart it.
+const POWEROFF_EXIT_CODE: i32 = 80;
+const REBOOT_EXIT_CODE: i32 = 81;
+
+impl EndAction {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            EndAction::Poweroff => POWEROFF_EXIT_CODE,
+            EndAction::Reboot | EndAction::Restart => REBOOT_EXIT_CODE,
+        }
+    }
+
+    pub fn from_exit_code(code: i32) -> Option<Self> {
+        match code {
+            POWEROFF_EXIT_CODE => Some(EndAction::Poweroff),
+            REBOOT_EXIT_CODE => Some(EndAction::Reboot),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExitPolicy {
+    pub on_clean_exit: EndAction,
+    pub shutdown_exit_code: i32,
+    pub restart_budget: u32,
+}
+
+impl Default for ExitPolicy {
+    fn default() -> Self {
+        Self {
+            on_clean_exit: EndAction::Poweroff,
+            shutdown_exit_code: 42,
+            restart_budget: 0,
+        }
+    }
+}
+
+impl ExitPolicy {
+    pub fn from_config(config: &Config) -> Result<Self, SystemError> {
+        let default = Self::default();
+        let on_clean_exit = match config.get("nautilus.on_clean_exit") {
+            None => default.on_clean_exit,
+            Some("poweroff") => EndAction::Poweroff,
+            Some("reboot") => EndAction::Reboot,
+            Some(value) => {
+                return Err(SystemError {
+                    message: format!("Invalid nautilus.on_clean_exit: {}", value),
+                })
+            }
+        };
+        Ok(Self {
+            on_clean_exit,
+            shutdown_exit_code: parse(
+                config,
+                "nautilus.shutdown_exit_code",
+                default.shutdown_exit_code,
+            )?,
+            restart_budget: parse(config, "nautilus.restart_budget", default.restart_budget)?,
+        })
+    }
+}
+
+fn parse<T: std::str::FromStr>(config: &Config, key: &str, default: T) -> Result<T, SystemError> {
+    match config.get(key) {
+        None => Ok(default),
+        Some(value) => value.parse().map_err(|_| SystemError {
+            message: format!("Invalid {}: {}", key, value),
+        }),
+    }
+}
+
+// Decide what to do after run.sh exits with `code` (None if it was killed by a
+// signal or could not be run), given the restarts already made
+pub fn decide(policy: &ExitPolicy, code: Option<i32>, restarts: u32) -> EndAction {
+    match code {
+        Some(code) if code == policy.shutdown_exit_code => EndAction::Poweroff,
+        Some(0) => policy.on_clean_exit,
+        _ if restarts < policy.restart_budget => EndAction::Restart,
+        _ => EndAction::Reboot,
+    }
+}
+
+// Reboot or power off the enclave
+pub fn finish(sys: &dyn SysOps, action: EndAction) {
+    match action {
+        EndAction::Poweroff => {
+            dmesg("Powering off".to_string());
+            sys.poweroff();
+        }
+        EndAction::Reboot | EndAction::Restart => {
+            dmesg("Rebooting".to_string());
+            sys.reboot();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sysops::fake::Recorder;
+
+    #[test]
+    fn test_decide() {
+        let policy = ExitPolicy {
+            restart_budget: 2,
+            ..ExitPolicy::default()
+        };
+        let cases = [
+            // (code, restarts, action)
+            (Some(0), 0, EndAction::Poweroff),
+            (Some(0), 2, EndAction::Poweroff),
+            (Some(42), 0, EndAction::Poweroff),
+            (Some(1), 0, EndAction::Restart),
+            (Some(1), 1, EndAction::Restart),
+            (Some(1), 2, EndAction::Reboot),
+            (None, 1, EndAction::Restart),
+            (None, 2, EndAction::Reboot),
+        ];
+        for (code, restarts, action) in cases {
+            assert_eq!(decide(&policy, code, restarts), action, "{:?}", code);
+        }
+
+        let policy = ExitPolicy {
+            on_clean_exit: EndAction::Reboot,
+            shutdown_exit_code: 3,
+            restart_budget: 0,
+        };
+        assert_eq!(decide(&policy, Some(0), 0), EndAction::Reboot);
+        assert_eq!(decide(&policy, Some(3), 0), EndAction::Poweroff);
+        assert_eq!(decide(&policy, Some(42), 0), EndAction::Reboot);
+    }
+
+    #[test]
+    fn test_from_config() {
+        assert_eq!(
+            ExitPolicy::from_config(&Config::parse("")).unwrap(),
+            ExitPolicy::default()
+        );
+        let config = Config::parse(
+            "nautilus.on_clean_exit=reboot nautilus.shutdown_exit_code=7 nautilus.restart_budget=5",
+        );
+        assert_eq!(
+            ExitPolicy::from_config(&config).unwrap(),
+            ExitPolicy {
+                on_clean_exit: EndAction::Reboot,
+                shutdown_exit_code: 7,
+                restart_budget: 5,
+            }
+        );
+        assert!(ExitPolicy::from_config(&Config::parse("nautilus.on_clean_exit=halt")).is_err());
+        assert!(ExitPolicy::from_config(&Config::parse("nautilus.restart_budget=-1")).is_err());
+    }
+
+    #[test]
+    fn test_exit_codes() {
+        for action in [EndAction::Poweroff, EndAction::Reboot] {
+            assert_eq!(EndAction::from_exit_code(action.exit_code()), Some(action));
+        }
+        assert_eq!(
+            EndAction::Restart.exit_code(),
+            EndAction::Reboot.exit_code()
+        );
+        // A crash or an ordinary failure isn't a decision
+        for code in [0, 1, 101] {
+            assert_eq!(EndAction::from_exit_code(code), None);
+        }
+    }
+
+    #[test]
+    fn test_finish() {
+        for (action, call) in [
+            (EndAction::Poweroff, "poweroff"),
+            (EndAction::Reboot, "reboot"),
+            // A restart left over when the loop ends still reboots
+            (EndAction::Restart, "reboot"),
+        ] {
+            let sys = Recorder::default();
+            finish(&sys, action);
+            assert_eq!(*sys.calls.borrow(), [call], "{:?}", action);
+        }
+    }
+}
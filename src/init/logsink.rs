@@ -0,0 +1,206 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::config::Config;
+use crate::forward::connect_vsock;
+use std::io::Write;
+use std::os::unix::io::AsRawFd;
+use std::sync::Mutex;
+use std::time::Duration;
+use system::{boot_time_micros, dmesg, set_dmesg_hook, SystemError};
+
+// With `nautilus.log_vsock=<cid>:<port>` (e.g. `3:9100`), every console line
+// of init, the supervisor and the application is also sent as a JSON line to
+// a listener on that vsock address, for log aggregation on the host:
+// {"boot_us":12345678,"source":"init","message":"Mounted /dev"}
+// `source` is `init` or `supervisor` for their own messages and `app` for
+// lines the application wrote. The console still gets every line. If the
+// sink can't be reached, or stops accepting lines, a warning is logged and
+// the process carries on logging to the console only.
+
+// How long a line may wait for the host to read it before the sink is dropped
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+// Destination of the JSON lines and where they come from
+pub struct Sink<W> {
+    source: &'static str,
+    writer: W,
+}
+
+impl<W: Write> Sink<W> {
+    pub fn new(source: &'static str, writer: W) -> Self {
+        Self { source, writer }
+    }
+
+    // Send `message` as coming from `source`, or from this process if None
+    pub fn send(&mut self, source: Option<&str>, message: &str) -> std::io::Result<()> {
+        let line = record(boot_time_micros(), source.unwrap_or(self.source), message);
+        self.writer.write_all(line.as_bytes())?;
+        self.writer.flush()
+    }
+}
+
+// The sink of this process, once started
+static SINK: Mutex<Option<Sink<Box<dyn Write + Send>>>> = Mutex::new(None);
+
+// Parse `nautilus.log_vsock` into a CID and port, None when unset
+pub fn address(config: &Config) -> Result<Option<(u32, u32)>, SystemError> {
+    let value = match config.get("nautilus.log_vsock") {
+        None => return Ok(None),
+        Some(value) => value,
+    };
+    let invalid = || SystemError {
+        message: format!(
+            "Invalid nautilus.log_vsock: {}, expected <cid>:<port>",
+            value
+        ),
+    };
+    let (cid, port) = value.split_once(':').ok_or_else(invalid)?;
+    let cid = cid.parse().map_err(|_| invalid())?;
+    let port = port.parse().map_err(|_| invalid())?;
+    Ok(Some((cid, port)))
+}
+
+// Connect this process's sink, with its messages attributed to `source`
+pub fn start(config: &Config, source: &'static str) {
+    let (cid, port) = match address(config) {
+        Ok(Some(address)) => address,
+        Ok(None) => return,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+    let stream = match connect_vsock(cid, port) {
+        Ok(stream) => stream,
+        Err(e) => {
+            dmesg(format!(
+                "WARNING: Failed to connect to log sink on vsock {}:{}: {}, logging to the console only",
+                cid, port, e
+            ));
+            return;
+        }
+    };
+    // Not for the processes this one starts to inherit
+    unsafe {
+        libc::fcntl(stream.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC);
+    }
+    let _ = stream.set_write_timeout(Some(WRITE_TIMEOUT));
+    *SINK.lock().unwrap_or_else(|e| e.into_inner()) = Some(Sink::new(source, Box::new(stream)));
+    set_dmesg_hook(send_own);
+    dmesg(format!("Sending logs to vsock {}:{}", cid, port));
+}
+
+// A line the application wrote
+pub fn send_app(line: &str) {
+    send(Some("app"), line);
+}
+
+// A dmesg message of this process
+fn send_own(message: &str) {
+    send(None, message);
+}
+
+fn send(source: Option<&str>, message: &str) {
+    let mut sink = SINK.lock().unwrap_or_else(|e| e.into_inner());
+    let result = match sink.as_mut() {
+        None => return,
+        Some(sink) => sink.send(source, message),
+    };
+    if let Err(e) = result {
+        *sink = None;
+        // Released first, as dmesg sends to the sink too
+        drop(sink);
+        dmesg(format!(
+            "WARNING: Log sink failed: {}, logging to the console only",
+            e
+        ));
+    }
+}
+
+// One JSON line for `message`
+pub fn record(boot_us: u64, source: &str, message: &str) -> String {
+    format!(
+        "{{\"boot_us\":{},\"source\":{},\"message\":{}}}\n",
+        boot_us,
+        json_string(source),
+        json_string(message)
+    )
+}
+
+// `value` as a JSON string literal
+fn json_string(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_address() {
+        assert_eq!(address(&Config::default()).unwrap(), None);
+        let config = Config::parse("nautilus.log_vsock=3:9100");
+        assert_eq!(address(&config).unwrap(), Some((3, 9100)));
+        for value in ["3", "3:", ":9100", "parent:9100", "3:9100:1"] {
+            let config = Config::parse(&format!("nautilus.log_vsock={}", value));
+            assert!(address(&config).is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn test_record() {
+        assert_eq!(
+            record(12345678, "init", "Mounted /dev"),
+            "{\"boot_us\":12345678,\"source\":\"init\",\"message\":\"Mounted /dev\"}\n"
+        );
+        assert_eq!(
+            record(0, "app", "{\"level\":\"INFO\"}\tdone\\\u{1b}[0m"),
+            "{\"boot_us\":0,\"source\":\"app\",\
+             \"message\":\"{\\\"level\\\":\\\"INFO\\\"}\\tdone\\\\\\u001b[0m\"}\n"
+        );
+    }
+
+    struct Broken;
+
+    impl Write for Broken {
+        fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_sink() {
+        let mut sink = Sink::new("supervisor", Vec::new());
+        sink.send(None, "Application started").unwrap();
+        sink.send(Some("app"), "listening on 0.0.0.0:3000").unwrap();
+        let written = String::from_utf8(sink.writer).unwrap();
+        let lines: Vec<&str> = written.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(
+            lines[0].ends_with(",\"source\":\"supervisor\",\"message\":\"Application started\"}")
+        );
+        assert!(lines[1].contains(",\"source\":\"app\","));
+
+        let mut sink = Sink::new("init", Broken);
+        assert!(sink.send(None, "lost").is_err());
+    }
+}
@@ -0,0 +1,18 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use init::boottime::{BootTimer, BOOT_STEPS_ENV};
+use init::supervisor;
+use init::sysops::Kernel;
+
+// Started by init once the enclave has booted, exiting with the code of what
+// init should do next, see lifecycle.rs
+fn main() {
+    let timer = std::env::var(BOOT_STEPS_ENV)
+        .ok()
+        .and_then(|steps| BootTimer::resume(&steps));
+    // Not for the application to inherit
+    std::env::remove_var(BOOT_STEPS_ENV);
+    let action = supervisor::run(&Kernel, timer);
+    std::process::exit(action.exit_code());
+}
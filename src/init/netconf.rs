@@ -0,0 +1,289 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::config::Config;
+use std::net::IpAddr;
+use system::SystemError;
+
+// Host identity and name resolution, set up at boot so tools find `localhost`
+// and the DNS configuration is visible rather than whatever the image baked in:
+// - the hostname is `nautilus.hostname` (default `nautilus-enclave`)
+// - /etc/hosts maps localhost and the hostname to loopback, plus the static
+//   entries in `nautilus.hosts`, e.g.
+//   `nautilus.hosts=api.weatherapi.com=127.0.0.64,kms.us-east-1.amazonaws.com=127.0.0.65`,
+//   and in `nautilus.host`, which takes the same pins as `<name>:<ip>`, e.g.
+//   `nautilus.host=api.weatherapi.com:10.0.0.5`
+// - /etc/resolv.conf lists the DNS forwarders in `nautilus.dns`, e.g.
+//   `nautilus.dns=127.0.0.53`, and no name server when unset, with the search
+//   domains in `nautilus.search`. Queries travel over vsock to the forwarder
+//   on the parent, so the per-query `nautilus.dns_timeout` (default 2s) and
+//   `nautilus.dns_attempts` (default 2) are shorter than glibc's 5s and 2 per
+//   server, and a dead forwarder fails a lookup within seconds.
+//   `nautilus.dns_tcp=1` sends queries over TCP, for forwarders reached
+//   through a TCP-only proxy such as vsock-proxy.
+// Both files are written to tmpfs and bind-mounted over /etc, so they can
+// still be changed once the root filesystem is read-only.
+
+pub const DEFAULT_HOSTNAME: &str = "nautilus-enclave";
+
+pub const HOSTS_PATH: &str = "/etc/hosts";
+pub const RESOLV_CONF_PATH: &str = "/etc/resolv.conf";
+
+// Limits glibc applies to resolv.conf
+const MAX_SEARCH_DOMAINS: usize = 6;
+const MAX_DNS_TIMEOUT: u32 = 30;
+const MAX_DNS_ATTEMPTS: u32 = 5;
+
+#[derive(Debug, PartialEq)]
+pub struct NetConfig {
+    pub hostname: String,
+    pub hosts: Vec<(String, IpAddr)>,
+    pub nameservers: Vec<IpAddr>,
+    pub search: Vec<String>,
+    pub dns_timeout: u32,
+    pub dns_attempts: u32,
+    pub dns_tcp: bool,
+}
+
+impl Default for NetConfig {
+    fn default() -> Self {
+        Self {
+            hostname: DEFAULT_HOSTNAME.to_string(),
+            hosts: Vec::new(),
+            nameservers: Vec::new(),
+            search: Vec::new(),
+            dns_timeout: 2,
+            dns_attempts: 2,
+            dns_tcp: false,
+        }
+    }
+}
+
+// A hostname of dot-separated labels of letters, digits and inner hyphens
+fn is_valid_hostname(name: &str) -> bool {
+    name.len() <= 253
+        && name.split('.').all(|label| {
+            (1..=63).contains(&label.len())
+                && label
+                    .bytes()
+                    .all(|b| b == b'-' || b.is_ascii_alphanumeric())
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+        })
+}
+
+fn list(config: &Config, key: &str) -> Vec<String> {
+    config
+        .get(key)
+        .unwrap_or("")
+        .split(',')
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+// A number in `1..=max`, or `default` when unset
+fn bounded(config: &Config, key: &str, default: u32, max: u32) -> Result<u32, SystemError> {
+    match config.get(key) {
+        None => Ok(default),
+        Some(value) => match value.parse() {
+            Ok(n) if (1..=max).contains(&n) => Ok(n),
+            _ => Err(SystemError {
+                message: format!("Invalid {}: {}, expected 1 to {}", key, value, max),
+            }),
+        },
+    }
+}
+
+impl NetConfig {
+    pub fn from_config(config: &Config) -> Result<Self, SystemError> {
+        let default = Self::default();
+        let invalid = |key: &str, value: &str| SystemError {
+            message: format!("Invalid {}: {}", key, value),
+        };
+        let hostname = config.get("nautilus.hostname").unwrap_or(DEFAULT_HOSTNAME);
+        // The kernel keeps at most 64 bytes
+        if hostname.len() > 64 || !is_valid_hostname(hostname) {
+            return Err(invalid("nautilus.hostname", hostname));
+        }
+        let mut hosts = Vec::new();
+        for entry in list(config, "nautilus.hosts") {
+            match entry.split_once('=') {
+                Some((name, ip)) if is_valid_hostname(name) => match ip.parse() {
+                    Ok(ip) => hosts.push((name.to_string(), ip)),
+                    Err(_) => return Err(invalid("nautilus.hosts", &entry)),
+                },
+                _ => return Err(invalid("nautilus.hosts", &entry)),
+            }
+        }
+        // Names can't contain a colon, so an IPv6 address follows the first
+        for entry in list(config, "nautilus.host") {
+            match entry.split_once(':') {
+                Some((name, ip)) if is_valid_hostname(name) => match ip.parse() {
+                    Ok(ip) => hosts.push((name.to_string(), ip)),
+                    Err(_) => return Err(invalid("nautilus.host", &entry)),
+                },
+                _ => return Err(invalid("nautilus.host", &entry)),
+            }
+        }
+        let nameservers = list(config, "nautilus.dns")
+            .iter()
+            .map(|ip| ip.parse().map_err(|_| invalid("nautilus.dns", ip)))
+            .collect::<Result<_, _>>()?;
+        let search = list(config, "nautilus.search");
+        if let Some(domain) = search.iter().find(|domain| !is_valid_hostname(domain)) {
+            return Err(invalid("nautilus.search", domain));
+        }
+        if search.len() > MAX_SEARCH_DOMAINS {
+            return Err(SystemError {
+                message: format!(
+                    "Invalid nautilus.search: at most {} domains",
+                    MAX_SEARCH_DOMAINS
+                ),
+            });
+        }
+        Ok(Self {
+            hostname: hostname.to_string(),
+            hosts,
+            nameservers,
+            search,
+            dns_timeout: bounded(
+                config,
+                "nautilus.dns_timeout",
+                default.dns_timeout,
+                MAX_DNS_TIMEOUT,
+            )?,
+            dns_attempts: bounded(
+                config,
+                "nautilus.dns_attempts",
+                default.dns_attempts,
+                MAX_DNS_ATTEMPTS,
+            )?,
+            dns_tcp: config.get_bool("nautilus.dns_tcp", default.dns_tcp)?,
+        })
+    }
+
+    // Contents of /etc/hosts
+    pub fn hosts(&self) -> String {
+        let mut hosts = format!(
+            "127.0.0.1\tlocalhost\n::1\tlocalhost ip6-localhost ip6-loopback\n127.0.1.1\t{}\n",
+            self.hostname
+        );
+        for (name, ip) in &self.hosts {
+            hosts.push_str(&format!("{}\t{}\n", ip, name));
+        }
+        hosts
+    }
+
+    // Contents of /etc/resolv.conf
+    pub fn resolv_conf(&self) -> String {
+        if self.nameservers.is_empty() {
+            return "# No DNS forwarder configured, see nautilus.dns\n".to_string();
+        }
+        let mut resolv_conf = String::new();
+        for ip in &self.nameservers {
+            resolv_conf.push_str(&format!("nameserver {}\n", ip));
+        }
+        if !self.search.is_empty() {
+            resolv_conf.push_str(&format!("search {}\n", self.search.join(" ")));
+        }
+        resolv_conf.push_str(&format!(
+            "options timeout:{} attempts:{}{}\n",
+            self.dns_timeout,
+            self.dns_attempts,
+            if self.dns_tcp { " use-vc" } else { "" }
+        ));
+        resolv_conf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn net_config(cmdline: &str) -> Result<NetConfig, SystemError> {
+        NetConfig::from_config(&Config::parse(cmdline))
+    }
+
+    #[test]
+    fn test_defaults() {
+        let config = net_config("").unwrap();
+        assert_eq!(config, NetConfig::default());
+        assert_eq!(
+            config.hosts(),
+            "127.0.0.1\tlocalhost\n\
+             ::1\tlocalhost ip6-localhost ip6-loopback\n\
+             127.0.1.1\tnautilus-enclave\n"
+        );
+        assert_eq!(
+            config.resolv_conf(),
+            "# No DNS forwarder configured, see nautilus.dns\n"
+        );
+    }
+
+    #[test]
+    fn test_configured_files() {
+        let config = net_config(
+            "nautilus.hostname=weather-1 \
+             nautilus.hosts=api.weatherapi.com=127.0.0.64,kms.us-east-1.amazonaws.com=127.0.0.65 \
+             nautilus.host=metadata.internal:10.0.0.5,v6.internal:fd00::5 \
+             nautilus.dns=127.0.0.53,fd00::53 nautilus.search=svc.internal,internal \
+             nautilus.dns_timeout=1 nautilus.dns_attempts=4 nautilus.dns_tcp",
+        )
+        .unwrap();
+        assert_eq!(
+            config.hosts(),
+            "127.0.0.1\tlocalhost\n\
+             ::1\tlocalhost ip6-localhost ip6-loopback\n\
+             127.0.1.1\tweather-1\n\
+             127.0.0.64\tapi.weatherapi.com\n\
+             127.0.0.65\tkms.us-east-1.amazonaws.com\n\
+             10.0.0.5\tmetadata.internal\n\
+             fd00::5\tv6.internal\n"
+        );
+        assert_eq!(
+            config.resolv_conf(),
+            "nameserver 127.0.0.53\n\
+             nameserver fd00::53\n\
+             search svc.internal internal\n\
+             options timeout:1 attempts:4 use-vc\n"
+        );
+
+        // The proxy-path defaults
+        let config = net_config("nautilus.dns=127.0.0.53").unwrap();
+        assert_eq!(
+            config.resolv_conf(),
+            "nameserver 127.0.0.53\noptions timeout:2 attempts:2\n"
+        );
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        for cmdline in [
+            "nautilus.hostname=-enclave",
+            "nautilus.hostname=enclave_1",
+            "nautilus.hostname=a..b",
+            "nautilus.hosts=api.weatherapi.com",
+            "nautilus.hosts=api.weatherapi.com=127.0.0",
+            "nautilus.hosts=bad/name=127.0.0.64",
+            "nautilus.dns=localhost",
+            "nautilus.dns=127.0.0.53:53",
+            "nautilus.host=api.weatherapi.com=10.0.0.5",
+            "nautilus.host=api.weatherapi.com:10.0.0",
+            "nautilus.host=:10.0.0.5",
+            "nautilus.search=svc..internal",
+            "nautilus.search=a,b,c,d,e,f,g",
+            "nautilus.dns_timeout=0",
+            "nautilus.dns_timeout=31",
+            "nautilus.dns_attempts=6",
+        ] {
+            match net_config(cmdline) {
+                Ok(_) => panic!("{} should be rejected", cmdline),
+                Err(e) => assert!(e.message.starts_with("Invalid nautilus."), "{}", e),
+            }
+        }
+        let long = format!("nautilus.hostname={}", "a".repeat(65));
+        assert!(net_config(&long).is_err());
+        assert!(net_config("nautilus.dns_tcp=maybe").is_err());
+    }
+}
@@ -0,0 +1,232 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::config::Config;
+use crate::forward::connect_vsock;
+use std::fmt;
+use std::io;
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+use system::{dmesg, SystemError};
+
+// Optional wait for egress before the application starts, enabled with
+// `nautilus.net_wait=<target>`, so its first outbound requests don't fail
+// while the parent's vsock proxy comes up. The target is connected to every
+// half second until a connection succeeds or `nautilus.net_wait.timeout_secs`
+// (default 30) pass, and the application is started either way:
+// - `vsock:<cid>:<port>`, e.g. `vsock:3:8101`, connects to the proxy on the
+//   parent directly
+// - `<ip>:<port>` connects over TCP. A `nautilus.tcp_forward` address accepts
+//   as soon as its forwarder listens, so use the vsock target to check the
+//   proxy behind it.
+
+// Delay between connection attempts
+const RETRY_INTERVAL: Duration = Duration::from_millis(500);
+
+// Longest a single TCP attempt may take
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Target {
+    Tcp(SocketAddr),
+    Vsock { cid: u32, port: u32 },
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Target::Tcp(addr) => write!(f, "{}", addr),
+            Target::Vsock { cid, port } => write!(f, "vsock:{}:{}", cid, port),
+        }
+    }
+}
+
+impl Target {
+    fn parse(value: &str) -> Option<Self> {
+        match value.strip_prefix("vsock:") {
+            Some(address) => {
+                let (cid, port) = address.split_once(':')?;
+                Some(Target::Vsock {
+                    cid: cid.parse().ok()?,
+                    port: port.parse().ok()?,
+                })
+            }
+            None => value.parse().ok().map(Target::Tcp),
+        }
+    }
+
+    // Open and close one connection, giving up after `timeout` over TCP
+    pub fn connect(&self, timeout: Duration) -> io::Result<()> {
+        match self {
+            Target::Tcp(addr) => TcpStream::connect_timeout(addr, timeout).map(drop),
+            Target::Vsock { cid, port } => connect_vsock(*cid, *port).map(drop),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct NetWait {
+    pub target: Target,
+    pub timeout: Duration,
+}
+
+impl NetWait {
+    // Wait settings, or None if the wait is disabled
+    pub fn from_config(config: &Config) -> Result<Option<Self>, SystemError> {
+        let value = match config.get("nautilus.net_wait") {
+            None => return Ok(None),
+            Some(value) => value,
+        };
+        let target = Target::parse(value).ok_or_else(|| SystemError {
+            message: format!(
+                "Invalid nautilus.net_wait: {}, expected <ip>:<port> or vsock:<cid>:<port>",
+                value
+            ),
+        })?;
+        let timeout = match config.get("nautilus.net_wait.timeout_secs") {
+            None => 30,
+            Some(value) => value.parse().map_err(|_| SystemError {
+                message: format!("Invalid nautilus.net_wait.timeout_secs: {}", value),
+            })?,
+        };
+        Ok(Some(Self {
+            target,
+            timeout: Duration::from_secs(timeout),
+        }))
+    }
+
+    // Wait for the target to accept a connection, logging how long it took
+    // or that it never did. True if it did.
+    pub fn run(&self) -> bool {
+        dmesg(format!(
+            "Waiting up to {}s for {} to accept connections",
+            self.timeout.as_secs(),
+            self.target
+        ));
+        let started = Instant::now();
+        let result = wait(self.timeout, RETRY_INTERVAL, |remaining| {
+            self.target.connect(remaining.min(CONNECT_TIMEOUT))
+        });
+        let waited = started.elapsed().as_millis();
+        match result {
+            Ok(attempts) => {
+                dmesg(format!(
+                    "Network ready: connected to {} after {}ms ({} attempt(s))",
+                    self.target, waited, attempts
+                ));
+                true
+            }
+            Err((e, attempts)) => {
+                dmesg(format!(
+                    "WARNING: Network not ready: {} unreachable after {}ms ({} attempt(s)): {}, starting the application anyway",
+                    self.target, waited, attempts, e
+                ));
+                false
+            }
+        }
+    }
+}
+
+// Call `connect` with the time left until it succeeds, sleeping `interval`
+// between attempts, or until `timeout` passes. Returns the number of attempts,
+// with the last error on failure. Always makes at least one attempt.
+pub fn wait(
+    timeout: Duration,
+    interval: Duration,
+    mut connect: impl FnMut(Duration) -> io::Result<()>,
+) -> Result<u32, (io::Error, u32)> {
+    let deadline = Instant::now() + timeout;
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let e = match connect(remaining.max(Duration::from_millis(1))) {
+            Ok(()) => return Ok(attempts),
+            Err(e) => e,
+        };
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err((e, attempts));
+        }
+        thread::sleep(interval.min(remaining));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_from_config() {
+        assert_eq!(NetWait::from_config(&Config::default()).unwrap(), None);
+        let config = Config::parse("nautilus.net_wait=vsock:3:8101");
+        assert_eq!(
+            NetWait::from_config(&config).unwrap(),
+            Some(NetWait {
+                target: Target::Vsock { cid: 3, port: 8101 },
+                timeout: Duration::from_secs(30),
+            })
+        );
+        let config =
+            Config::parse("nautilus.net_wait=127.0.0.64:443 nautilus.net_wait.timeout_secs=5");
+        let net_wait = NetWait::from_config(&config).unwrap().unwrap();
+        assert_eq!(net_wait.target.to_string(), "127.0.0.64:443");
+        assert_eq!(net_wait.timeout, Duration::from_secs(5));
+
+        for cmdline in [
+            "nautilus.net_wait",
+            "nautilus.net_wait=api.weatherapi.com:443",
+            "nautilus.net_wait=vsock:3",
+            "nautilus.net_wait=vsock:parent:8101",
+            "nautilus.net_wait=127.0.0.1:443 nautilus.net_wait.timeout_secs=soon",
+        ] {
+            assert!(
+                NetWait::from_config(&Config::parse(cmdline)).is_err(),
+                "{}",
+                cmdline
+            );
+        }
+    }
+
+    #[test]
+    fn test_wait() {
+        let refused = || io::Error::from(io::ErrorKind::ConnectionRefused);
+
+        // Succeeds on the third attempt
+        let mut calls = 0;
+        let result = wait(Duration::from_secs(5), Duration::from_millis(1), |_| {
+            calls += 1;
+            if calls < 3 {
+                Err(refused())
+            } else {
+                Ok(())
+            }
+        });
+        assert_eq!(result.unwrap(), 3);
+
+        // Gives up with the last error once the timeout passes
+        let (e, attempts) = wait(Duration::from_millis(50), Duration::from_millis(10), |_| {
+            Err(refused())
+        })
+        .unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::ConnectionRefused);
+        assert!(attempts > 1, "{}", attempts);
+
+        // Even without time left, one attempt is made
+        assert_eq!(
+            wait(Duration::ZERO, Duration::from_millis(1), |_| Ok(())).unwrap(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_tcp_target() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let target = Target::Tcp(listener.local_addr().unwrap());
+        target.connect(Duration::from_secs(1)).unwrap();
+        drop(listener);
+        assert!(target.connect(Duration::from_secs(1)).is_err());
+    }
+}
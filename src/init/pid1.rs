@@ -0,0 +1,257 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::lifecycle::EndAction;
+use crate::sysops::SysOps;
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
+use system::dmesg;
+
+// What init does as PID 1 once booted: run nautilus-supervisor, reap every
+// process reparented to it, and restart the supervisor with a backoff when it
+// dies without saying whether to power off or reboot.
+
+pub const SUPERVISOR_PATH: &str = "/nautilus-supervisor";
+
+// How long the processes a dead supervisor left behind get to die
+const ORPHAN_TIMEOUT: Duration = Duration::from_secs(1);
+
+// Delay before each restart of the supervisor, doubling up to `max` and
+// starting over at `initial` once a run has lasted `stable`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+    pub stable: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(30),
+            stable: Duration::from_secs(60),
+        }
+    }
+}
+
+impl Backoff {
+    // Delay before the next restart, after a run that lasted `ran` and
+    // followed a delay of `previous`, None for the first restart
+    fn delay(&self, previous: Option<Duration>, ran: Duration) -> Duration {
+        match previous {
+            Some(previous) if ran < self.stable => (previous * 2).min(self.max),
+            _ => self.initial,
+        }
+    }
+}
+
+// How a child ended, from the status set by waitpid
+fn describe(status: libc::c_int) -> (Option<i32>, String) {
+    if libc::WIFEXITED(status) {
+        let code = libc::WEXITSTATUS(status);
+        (Some(code), format!("exit status: {}", code))
+    } else if libc::WIFSIGNALED(status) {
+        (None, format!("signal: {}", libc::WTERMSIG(status)))
+    } else {
+        (None, format!("status: {:#x}", status))
+    }
+}
+
+// Reap children until `pid` exits, returning its waitpid status. Anything else
+// reaped is an orphan reparented to init. None if there is no such child.
+fn wait_for(pid: libc::pid_t) -> Option<libc::c_int> {
+    loop {
+        let mut status = 0;
+        let reaped = unsafe { libc::waitpid(-1, &mut status, 0) };
+        if reaped == pid {
+            return Some(status);
+        }
+        if reaped < 0 && std::io::Error::last_os_error().kind() != std::io::ErrorKind::Interrupted {
+            return None;
+        }
+    }
+}
+
+// Reap the children that have already exited
+fn reap() {
+    while unsafe { libc::waitpid(-1, std::ptr::null_mut(), libc::WNOHANG) } > 0 {}
+}
+
+// Processes whose parent is this one, from /proc
+fn children() -> Vec<libc::pid_t> {
+    let parent = std::process::id().to_string();
+    let entries = match std::fs::read_dir("/proc") {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    entries
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+        .filter(|pid: &libc::pid_t| {
+            // The parent follows the state after the command name, which may
+            // itself contain spaces and parentheses
+            std::fs::read_to_string(format!("/proc/{}/stat", pid))
+                .ok()
+                .and_then(|stat| {
+                    let (_, fields) = stat.rsplit_once(')')?;
+                    Some(fields.split_whitespace().nth(1)? == parent)
+                })
+                .unwrap_or(false)
+        })
+        .collect()
+}
+
+// Kill what a dead supervisor left behind, services included, so the next
+// one starts them afresh rather than alongside stale copies
+fn kill_orphans() {
+    let deadline = Instant::now() + ORPHAN_TIMEOUT;
+    loop {
+        reap();
+        let orphans = children();
+        if orphans.is_empty() {
+            return;
+        }
+        if Instant::now() >= deadline {
+            eprintln!("{} process(es) survived SIGKILL", orphans.len());
+            return;
+        }
+        for pid in orphans {
+            unsafe {
+                libc::kill(pid, libc::SIGKILL);
+            }
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+// Run the supervisor built by `command` until it exits with the code of an
+// EndAction, restarting it after `backoff` otherwise, and return that action
+pub fn supervise(
+    sys: &dyn SysOps,
+    backoff: &Backoff,
+    mut command: impl FnMut() -> Command,
+) -> EndAction {
+    let mut delay = None;
+    loop {
+        let started = Instant::now();
+        let ended = match sys.spawn(command()) {
+            Ok(child) => {
+                dmesg(format!("Spawned nautilus-supervisor ({})", child.id()));
+                match wait_for(child.id() as libc::pid_t).map(describe) {
+                    Some((code, status)) => {
+                        if let Some(action) = code.and_then(EndAction::from_exit_code) {
+                            return action;
+                        }
+                        format!("exited with {}", status)
+                    }
+                    None => "could not be waited for".to_string(),
+                }
+            }
+            Err(e) => format!("failed to start: {}", e),
+        };
+        kill_orphans();
+        let next = backoff.delay(delay, started.elapsed());
+        dmesg(format!(
+            "nautilus-supervisor {}, restarting in {}ms",
+            ended,
+            next.as_millis()
+        ));
+        thread::sleep(next);
+        delay = Some(next);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sysops::Kernel;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn test_backoff() {
+        let backoff = Backoff::default();
+        let quick = Duration::from_secs(1);
+        let mut delays = Vec::new();
+        let mut delay = None;
+        for _ in 0..7 {
+            let next = backoff.delay(delay, quick);
+            delays.push(next.as_secs());
+            delay = Some(next);
+        }
+        assert_eq!(delays, [1, 2, 4, 8, 16, 30, 30]);
+
+        // A run that lasted starts over
+        assert_eq!(
+            backoff.delay(delay, Duration::from_secs(60)),
+            Duration::from_secs(1)
+        );
+    }
+
+    // Set in the copy of the test binary that stands in for PID 1
+    const PID1_ENV: &str = "NAUTILUS_TEST_PID1";
+
+    // Runs as PID 1 in a child of the test binary, since waitpid(-1) there
+    // would reap the other tests' processes. The supervisor is a script that
+    // leaves an orphan behind and is killed on its first run, then asks for a
+    // poweroff on its second.
+    fn pid1(dir: &str) {
+        assert_eq!(unsafe { libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1) }, 0);
+        let script = "echo run >> DIR/runs
+             if [ ! -e DIR/orphan ]; then
+               sleep 30 &
+               echo $! > DIR/orphan
+               kill -9 $$
+             fi
+             exit 80"
+            .replace("DIR", dir);
+        let backoff = Backoff {
+            initial: Duration::from_millis(10),
+            ..Backoff::default()
+        };
+        let action = supervise(&Kernel, &backoff, || {
+            let mut command = Command::new("sh");
+            command.arg("-c").arg(&script);
+            command
+        });
+        assert_eq!(action, EndAction::Poweroff);
+
+        let runs = std::fs::read_to_string(format!("{}/runs", dir)).unwrap();
+        assert_eq!(runs.lines().count(), 2);
+        // The orphan was killed and reaped, leaving no children
+        let orphan: libc::pid_t = std::fs::read_to_string(format!("{}/orphan", dir))
+            .unwrap()
+            .trim()
+            .parse()
+            .unwrap();
+        assert_ne!(unsafe { libc::kill(orphan, 0) }, 0);
+        assert!(children().is_empty());
+        assert_eq!(
+            unsafe { libc::waitpid(-1, std::ptr::null_mut(), libc::WNOHANG) },
+            -1
+        );
+    }
+
+    #[test]
+    fn test_supervisor_restart_loop() {
+        if let Ok(dir) = std::env::var(PID1_ENV) {
+            pid1(&dir);
+            return;
+        }
+        let dir = TempDir::new("pid1");
+        let output = Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "pid1::tests::test_supervisor_restart_loop"])
+            .args(["--nocapture", "--test-threads=1"])
+            .env(PID1_ENV, dir.path())
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stdout)
+        );
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(stdout.contains("1 passed"), "{}", stdout);
+        assert!(stdout.contains("nautilus-supervisor exited with signal: 9"));
+    }
+}
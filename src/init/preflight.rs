@@ -0,0 +1,177 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::cabundle::CA_BUNDLE_PATH;
+use crate::config::Config;
+use crate::exec::{AppExec, DEFAULT_SERVER_PATH};
+use crate::sysops::SysOps;
+use system::{dmesg, SystemError};
+
+// Files the application needs, checked before run.sh or the server is
+// spawned so that an image missing one fails with its name in the console
+// log instead of a bare spawn error or a server that dies at its first TLS
+// connection. Each path can be changed with `nautilus.preflight.<name>=<path>`,
+// and an empty path skips its check:
+// - `shell` (/sh) and `run_script` (/run.sh), executable, with `nautilus.exec=script`
+// - `server`, executable, by default `nautilus.exec.path` with
+//   `nautilus.exec=direct` and /nautilus-server otherwise
+// - `ca_bundle` (/ca-certificates.crt), which only has to exist
+// Every missing file is logged before the check fails.
+
+#[derive(Debug, PartialEq)]
+pub struct Check {
+    pub name: &'static str,
+    pub path: String,
+    pub executable: bool,
+}
+
+// The checks for starting the application with `exec`
+pub fn checks(config: &Config, exec: &AppExec) -> Result<Vec<Check>, SystemError> {
+    let mut defaults = Vec::new();
+    let server = match exec {
+        AppExec::Script => {
+            defaults.push(("shell", "/sh", true));
+            defaults.push(("run_script", "/run.sh", true));
+            DEFAULT_SERVER_PATH
+        }
+        AppExec::Direct { path, .. } => path.as_str(),
+    };
+    defaults.push(("server", server, true));
+    defaults.push(("ca_bundle", CA_BUNDLE_PATH, false));
+
+    let mut checks = Vec::new();
+    for (name, default, executable) in defaults {
+        let key = format!("nautilus.preflight.{}", name);
+        let path = config.get(&key).unwrap_or(default);
+        if path.is_empty() {
+            continue;
+        }
+        if !path.starts_with('/') {
+            return Err(SystemError {
+                message: format!("Invalid {}: {}", key, path),
+            });
+        }
+        checks.push(Check {
+            name,
+            path: path.to_string(),
+            executable,
+        });
+    }
+    Ok(checks)
+}
+
+// Run `checks`, logging each file that is missing or not executable
+pub fn run(sys: &dyn SysOps, checks: &[Check]) -> Result<(), SystemError> {
+    let mut failed = 0;
+    for check in checks {
+        let problem = if !sys.exists(&check.path) {
+            "is missing"
+        } else if check.executable && !sys.is_executable(&check.path) {
+            "is not executable"
+        } else {
+            continue;
+        };
+        dmesg(format!(
+            "FATAL: {} ({}) {}, set nautilus.preflight.{} if it lives elsewhere",
+            check.path, check.name, problem, check.name
+        ));
+        failed += 1;
+    }
+    if failed > 0 {
+        return Err(SystemError {
+            message: format!(
+                "Preflight check failed for {} of {} file(s)",
+                failed,
+                checks.len()
+            ),
+        });
+    }
+    dmesg(format!(
+        "Preflight check passed for {} file(s)",
+        checks.len()
+    ));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sysops::fake::Recorder;
+
+    fn paths(checks: &[Check]) -> Vec<(&str, &str, bool)> {
+        checks
+            .iter()
+            .map(|check| (check.name, check.path.as_str(), check.executable))
+            .collect()
+    }
+
+    #[test]
+    fn test_checks() {
+        let found = checks(&Config::default(), &AppExec::Script).unwrap();
+        assert_eq!(
+            paths(&found),
+            [
+                ("shell", "/sh", true),
+                ("run_script", "/run.sh", true),
+                ("server", "/nautilus-server", true),
+                ("ca_bundle", "/ca-certificates.crt", false),
+            ]
+        );
+
+        // Direct exec has no shell or run.sh, and checks the binary it execs
+        let exec = AppExec::Direct {
+            path: "/app".to_string(),
+            args: vec![],
+        };
+        let found = checks(&Config::default(), &exec).unwrap();
+        assert_eq!(
+            paths(&found),
+            [
+                ("server", "/app", true),
+                ("ca_bundle", "/ca-certificates.crt", false),
+            ]
+        );
+
+        // Paths can be moved or skipped
+        let config =
+            Config::parse("nautilus.preflight.server=/bin/server nautilus.preflight.shell=");
+        let found = checks(&config, &AppExec::Script).unwrap();
+        assert_eq!(
+            paths(&found),
+            [
+                ("run_script", "/run.sh", true),
+                ("server", "/bin/server", true),
+                ("ca_bundle", "/ca-certificates.crt", false),
+            ]
+        );
+
+        let config = Config::parse("nautilus.preflight.run_script=run.sh");
+        assert!(checks(&config, &AppExec::Script).is_err());
+    }
+
+    #[test]
+    fn test_run() {
+        let found = checks(&Config::default(), &AppExec::Script).unwrap();
+        let mut sys = Recorder::with_files(&[
+            ("/sh", ""),
+            ("/run.sh", ""),
+            ("/nautilus-server", ""),
+            ("/ca-certificates.crt", ""),
+        ]);
+        sys.executable = ["/sh", "/run.sh", "/nautilus-server"]
+            .iter()
+            .map(|path| path.to_string())
+            .collect();
+        run(&sys, &found).unwrap();
+
+        // The CA bundle needs no executable bit, the server does
+        sys.executable.remove("/nautilus-server");
+        let e = run(&sys, &found).unwrap_err();
+        assert_eq!(e.message, "Preflight check failed for 1 of 4 file(s)");
+
+        sys.files.borrow_mut().remove("/run.sh");
+        sys.files.borrow_mut().remove("/ca-certificates.crt");
+        let e = run(&sys, &found).unwrap_err();
+        assert_eq!(e.message, "Preflight check failed for 3 of 4 file(s)");
+    }
+}
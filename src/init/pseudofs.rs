@@ -0,0 +1,225 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::cgroup::CGROUP_ROOT;
+use crate::config::Config;
+use crate::sysops::SysOps;
+use system::{dmesg, SystemError};
+
+// Pseudo-filesystems the runtime expects beyond /dev, /proc and /sys. They are
+// mounted once the cmdline config has been read, and each accepts, under
+// `nautilus.mount.<name>`:
+// - `.options`: the mount data replacing the default, comma-separated, e.g.
+//   `nautilus.mount.cgroup2.options=nsdelegate,memory_recursiveprot`
+// Optional mounts are only made when enabled, e.g. `nautilus.mount.debugfs=1`,
+// and failing to mount them is a warning. A required mount failing stops boot.
+
+// A pseudo-filesystem mount and its defaults
+pub struct Mount {
+    pub name: &'static str,
+    pub target: &'static str,
+    pub fstype: &'static str,
+    data: &'static str,
+    pub required: bool,
+}
+
+pub const MOUNTS: [Mount; 4] = [
+    Mount {
+        name: "mqueue",
+        target: "/dev/mqueue",
+        fstype: "mqueue",
+        data: "",
+        required: true,
+    },
+    Mount {
+        name: "cgroup2",
+        target: CGROUP_ROOT,
+        fstype: "cgroup2",
+        data: "nsdelegate",
+        required: true,
+    },
+    Mount {
+        name: "binfmt_misc",
+        target: "/proc/sys/fs/binfmt_misc",
+        fstype: "binfmt_misc",
+        data: "",
+        required: false,
+    },
+    Mount {
+        name: "debugfs",
+        target: "/sys/kernel/debug",
+        fstype: "debugfs",
+        data: "",
+        required: false,
+    },
+];
+
+// None of them needs devices, setuid or executables
+pub const FLAGS: libc::c_ulong = libc::MS_NODEV | libc::MS_NOSUID | libc::MS_NOEXEC;
+
+impl Mount {
+    // Whether the mount is made: always when required, otherwise when enabled
+    pub fn enabled(&self, config: &Config) -> Result<bool, SystemError> {
+        if self.required {
+            return Ok(true);
+        }
+        config.get_bool(&format!("nautilus.mount.{}", self.name), false)
+    }
+
+    // Mount data from the config, or an error naming the invalid key
+    pub fn data<'a>(&'a self, config: &'a Config) -> Result<&'a str, SystemError> {
+        Ok(options(config, self.name)?.unwrap_or(self.data))
+    }
+}
+
+// The validated `nautilus.mount.<name>.options` of a mount, or None if unset.
+// Shared with the /dev, /proc and /sys mounts in boot.rs.
+pub fn options<'a>(config: &'a Config, name: &str) -> Result<Option<&'a str>, SystemError> {
+    let key = format!("nautilus.mount.{}.options", name);
+    let data = match config.get(&key) {
+        None => return Ok(None),
+        Some(data) => data,
+    };
+    let valid = data.split(',').all(|option| {
+        !option.is_empty()
+            && option
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'_' | b'-' | b'='))
+    });
+    if !data.is_empty() && !valid {
+        return Err(SystemError {
+            message: format!("Invalid {}: {}", key, data),
+        });
+    }
+    Ok(Some(data))
+}
+
+// Mount every enabled entry of MOUNTS
+pub fn mount_all(sys: &dyn SysOps, config: &Config) -> Result<(), SystemError> {
+    for mount in &MOUNTS {
+        if let Err(e) = mount_one(sys, config, mount) {
+            if mount.required {
+                return Err(e);
+            }
+            eprintln!("{}, skipping optional {}", e, mount.target);
+        }
+    }
+    Ok(())
+}
+
+fn mount_one(sys: &dyn SysOps, config: &Config, mount: &Mount) -> Result<(), SystemError> {
+    if !mount.enabled(config)? {
+        return Ok(());
+    }
+    let data = mount.data(config)?;
+    if !sys.exists(mount.target) {
+        if let Err(e) = sys.create_dir_all(mount.target) {
+            eprintln!("{}", e);
+        }
+    }
+    sys.mount(mount.fstype, mount.target, mount.fstype, FLAGS, data)?;
+    dmesg(format!("Mounted {} ({})", mount.target, mount.fstype));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sysops::fake::Recorder;
+
+    fn mount_calls(sys: &Recorder) -> Vec<String> {
+        sys.calls_to("mount")
+    }
+
+    #[test]
+    fn test_mount_table() {
+        let enable_all = "nautilus.mount.binfmt_misc nautilus.mount.debugfs=1";
+        // (cmdline, target, (fstype, data)), None when the entry isn't mounted
+        let cases = [
+            ("", "/dev/mqueue", Some(("mqueue", ""))),
+            ("", CGROUP_ROOT, Some(("cgroup2", "nsdelegate"))),
+            ("", "/proc/sys/fs/binfmt_misc", None),
+            ("", "/sys/kernel/debug", None),
+            (
+                enable_all,
+                "/proc/sys/fs/binfmt_misc",
+                Some(("binfmt_misc", "")),
+            ),
+            (enable_all, "/sys/kernel/debug", Some(("debugfs", ""))),
+            (
+                "nautilus.mount.cgroup2.options=nsdelegate,memory_recursiveprot",
+                CGROUP_ROOT,
+                Some(("cgroup2", "nsdelegate,memory_recursiveprot")),
+            ),
+            // Required mounts can't be switched off
+            (
+                "nautilus.mount.mqueue=0",
+                "/dev/mqueue",
+                Some(("mqueue", "")),
+            ),
+        ];
+        for (cmdline, target, expected) in cases {
+            let sys = Recorder::default();
+            mount_all(&sys, &Config::parse(cmdline)).unwrap();
+            let call = mount_calls(&sys)
+                .into_iter()
+                .find(|call| call.split(' ').nth(2) == Some(target));
+            let expected = expected.map(|(fstype, data)| {
+                format!(
+                    "mount {} {} {} {:#x} {}",
+                    fstype, target, fstype, FLAGS, data
+                )
+            });
+            assert_eq!(call, expected, "{} with `{}`", target, cmdline);
+        }
+    }
+
+    #[test]
+    fn test_required_mount_failure() {
+        let mut sys = Recorder::default();
+        sys.failing.insert("/dev/mqueue".to_string());
+        assert!(mount_all(&sys, &Config::default()).is_err());
+
+        // An optional mount failing doesn't stop the rest
+        let mut sys = Recorder::default();
+        sys.failing.insert("/proc/sys/fs/binfmt_misc".to_string());
+        let config = Config::parse("nautilus.mount.binfmt_misc=1 nautilus.mount.debugfs=1");
+        mount_all(&sys, &config).unwrap();
+        let targets: Vec<String> = mount_calls(&sys)
+            .iter()
+            .map(|call| call.split(' ').nth(2).unwrap().to_string())
+            .collect();
+        assert_eq!(
+            targets,
+            [
+                "/dev/mqueue",
+                CGROUP_ROOT,
+                "/proc/sys/fs/binfmt_misc",
+                "/sys/kernel/debug"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invalid_options() {
+        let sys = Recorder::default();
+        let config = Config::parse("nautilus.mount.cgroup2.options=nsdelegate,,x");
+        let err = mount_all(&sys, &config).unwrap_err();
+        assert!(
+            err.message
+                .starts_with("Invalid nautilus.mount.cgroup2.options"),
+            "{}",
+            err
+        );
+        assert!(MOUNTS[1]
+            .data(&Config::parse("nautilus.mount.cgroup2.options=a;b"))
+            .is_err());
+
+        // An invalid switch for an optional mount only skips it
+        let sys = Recorder::default();
+        mount_all(&sys, &Config::parse("nautilus.mount.debugfs=maybe")).unwrap();
+        assert!(!mount_calls(&sys)
+            .iter()
+            .any(|call| call.contains("debugfs")));
+    }
+}
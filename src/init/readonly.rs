@@ -0,0 +1,144 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::sysops::SysOps;
+use std::fs;
+use std::path::Path;
+use system::SystemError;
+
+// Paths the application writes to at runtime. Each must be its own tmpfs
+// mount before the root filesystem is made read-only.
+pub const WRITABLE_MOUNTS: [&str; 3] = ["/tmp", "/run", "/dev/shm"];
+
+// Files on the root filesystem that run.sh still rewrites at runtime. Unless
+// init already mounted a generated one, they are copied to tmpfs and
+// bind-mounted back in place so they stay writable.
+pub const WRITABLE_FILES: [&str; 1] = ["/etc/hosts"];
+
+// Directory on /run holding the writable copies of WRITABLE_FILES
+const WRITABLE_FILES_DIR: &str = "/run/rw";
+
+// Paths from `required` that are not mounted as tmpfs according to `mounts`,
+// the contents of /proc/mounts
+pub fn missing_tmpfs<'a>(mounts: &str, required: &[&'a str]) -> Vec<&'a str> {
+    required
+        .iter()
+        .filter(|path| {
+            !mounts.lines().any(|line| {
+                let mut fields = line.split_whitespace().skip(1);
+                fields.next() == Some(**path) && fields.next() == Some("tmpfs")
+            })
+        })
+        .copied()
+        .collect()
+}
+
+// Copy `path` (or an empty file if it doesn't exist) under `dir`, returning the copy
+pub fn writable_copy(path: &str, dir: &Path) -> Result<String, SystemError> {
+    let copy = dir.join(path.trim_start_matches('/'));
+    let io_err = |e: std::io::Error| SystemError {
+        message: format!("Failed to create writable copy of {}: {}", path, e),
+    };
+    if let Some(parent) = copy.parent() {
+        fs::create_dir_all(parent).map_err(io_err)?;
+    }
+    fs::write(&copy, fs::read(path).unwrap_or_default()).map_err(io_err)?;
+    Ok(copy.to_string_lossy().into_owned())
+}
+
+// Write `contents` to a tmpfs file under /run and bind-mount it over `path`,
+// which stays writable once / is read-only
+pub fn mount_writable_file(
+    sys: &dyn SysOps,
+    path: &str,
+    contents: &str,
+) -> Result<(), SystemError> {
+    let copy = Path::new(WRITABLE_FILES_DIR).join(path.trim_start_matches('/'));
+    let copy = copy.to_string_lossy();
+    let io_err = |e: std::io::Error| SystemError {
+        message: format!("Failed to write {}: {}", path, e),
+    };
+    if let Some(parent) = Path::new(copy.as_ref()).parent() {
+        sys.create_dir_all(&parent.to_string_lossy())
+            .map_err(io_err)?;
+    }
+    sys.write_file(&copy, contents).map_err(io_err)?;
+    if !sys.exists(path) {
+        if let Some(parent) = Path::new(path).parent() {
+            sys.create_dir_all(&parent.to_string_lossy())
+                .map_err(io_err)?;
+        }
+        sys.write_file(path, "").map_err(io_err)?;
+    }
+    sys.mount(&copy, path, "", libc::MS_BIND, "")
+}
+
+// Verify the writable tmpfs mounts, keep WRITABLE_FILES writable and remount / read-only
+pub fn remount_root_readonly(sys: &dyn SysOps) -> Result<(), SystemError> {
+    use libc::{MS_BIND, MS_RDONLY, MS_REMOUNT};
+
+    let mounts = sys.read_file("/proc/mounts").map_err(|e| SystemError {
+        message: format!("Failed to read /proc/mounts: {}", e),
+    })?;
+    let missing = missing_tmpfs(&mounts, &WRITABLE_MOUNTS);
+    if !missing.is_empty() {
+        return Err(SystemError {
+            message: format!(
+                "Not remounting / read-only, not on tmpfs: {}",
+                missing.join(", ")
+            ),
+        });
+    }
+    for path in missing_tmpfs(&mounts, &WRITABLE_FILES) {
+        let copy = writable_copy(path, Path::new(WRITABLE_FILES_DIR))?;
+        if !sys.exists(path) {
+            sys.write_file(path, "").map_err(|e| SystemError {
+                message: format!("Failed to create {}: {}", path, e),
+            })?;
+        }
+        sys.mount(&copy, path, "", MS_BIND, "")?;
+    }
+    sys.mount("", "/", "", MS_REMOUNT | MS_RDONLY, "")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    const MOUNTS: &str = "\
+rootfs / rootfs rw 0 0
+devtmpfs /dev devtmpfs rw,nosuid,noexec,mode=755 0 0
+shm /dev/shm tmpfs rw,nosuid,nodev,noexec,mode=755 0 0
+proc /proc proc rw,nosuid,nodev,noexec,hidepid=2 0 0
+tmpfs /run tmpfs rw,nosuid,nodev,noexec,mode=755 0 0
+sysfs /sys sysfs rw,nosuid,nodev,noexec 0 0
+";
+
+    #[test]
+    fn test_missing_tmpfs() {
+        assert_eq!(missing_tmpfs(MOUNTS, &WRITABLE_MOUNTS), vec!["/tmp"]);
+        let with_tmp = format!("{}tmpfs /tmp tmpfs rw,nosuid,nodev,noexec 0 0\n", MOUNTS);
+        assert!(missing_tmpfs(&with_tmp, &WRITABLE_MOUNTS).is_empty());
+    }
+
+    #[test]
+    fn test_missing_tmpfs_requires_tmpfs_type() {
+        let mounts = "devtmpfs /dev devtmpfs rw 0 0\n";
+        assert_eq!(missing_tmpfs(mounts, &["/dev"]), vec!["/dev"]);
+    }
+
+    #[test]
+    fn test_writable_copy() {
+        let dir = TempDir::new("readonly-copy");
+        let original = dir.path().join("hosts");
+        fs::write(&original, "127.0.0.1   localhost\n").unwrap();
+        let copy = writable_copy(original.to_str().unwrap(), &dir.path().join("rw")).unwrap();
+        assert!(copy.starts_with(dir.path().join("rw").to_str().unwrap()));
+        assert_eq!(fs::read_to_string(copy).unwrap(), "127.0.0.1   localhost\n");
+
+        let missing = dir.path().join("resolv.conf");
+        let copy = writable_copy(missing.to_str().unwrap(), &dir.path().join("rw")).unwrap();
+        assert_eq!(fs::read_to_string(copy).unwrap(), "");
+    }
+}
@@ -0,0 +1,250 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::config::Config;
+use std::fmt;
+use std::io;
+use system::SystemError;
+
+// Resource limits applied to the application between fork and exec. Core dumps
+// are always disabled so a crash can't write the ephemeral private key to a
+// file. The open file limit defaults to `DEFAULT_NOFILE`, well above the
+// kernel's 1024, so connection-heavy servers don't run out of fds. It and the
+// process and memory limits can be set with e.g. `nautilus.rlimit.nofile=4096
+// nautilus.rlimit.nproc=512 nautilus.rlimit.as=2G nautilus.rlimit.data=1G`,
+// with sizes in bytes with an optional K/M/G/T suffix, or `max`. The kernel
+// only enforces `nproc` on processes without CAP_SYS_RESOURCE or
+// CAP_SYS_ADMIN, see caps.rs.
+
+// Value meaning no limit
+pub const INFINITY: u64 = u64::MAX;
+
+// Open file limit unless `nautilus.rlimit.nofile` is set
+pub const DEFAULT_NOFILE: u64 = 65536;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Resource {
+    Core,
+    Nofile,
+    Nproc,
+    As,
+    Data,
+}
+
+impl Resource {
+    fn name(self) -> &'static str {
+        match self {
+            Resource::Core => "core",
+            Resource::Nofile => "nofile",
+            Resource::Nproc => "nproc",
+            Resource::As => "as",
+            Resource::Data => "data",
+        }
+    }
+}
+
+// Soft and hard limit for a resource, which are set to the same value
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Limit {
+    pub resource: Resource,
+    pub value: u64,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.value == INFINITY {
+            write!(f, "{}=max", self.resource.name())
+        } else {
+            write!(f, "{}={}", self.resource.name(), self.value)
+        }
+    }
+}
+
+// Limits applied unless configured, and when the configured ones are invalid
+pub fn defaults() -> Vec<Limit> {
+    vec![
+        Limit {
+            resource: Resource::Core,
+            value: 0,
+        },
+        Limit {
+            resource: Resource::Nofile,
+            value: DEFAULT_NOFILE,
+        },
+    ]
+}
+
+pub fn from_config(config: &Config) -> Result<Vec<Limit>, SystemError> {
+    let mut limits = defaults();
+    for (resource, is_size) in [
+        (Resource::Nofile, false),
+        (Resource::Nproc, false),
+        (Resource::As, true),
+        (Resource::Data, true),
+    ] {
+        let key = format!("nautilus.rlimit.{}", resource.name());
+        if let Some(value) = config.get(&key) {
+            let value = parse_value(value, is_size).ok_or_else(|| SystemError {
+                message: format!("Invalid {}: {}", key, value),
+            })?;
+            let limit = Limit { resource, value };
+            match limits.iter_mut().find(|limit| limit.resource == resource) {
+                Some(default) => *default = limit,
+                None => limits.push(limit),
+            }
+        }
+    }
+    Ok(limits)
+}
+
+fn parse_value(value: &str, is_size: bool) -> Option<u64> {
+    if value == "max" {
+        return Some(INFINITY);
+    }
+    let (digits, shift) = match value.char_indices().last()? {
+        (i, 'K' | 'k') if is_size => (&value[..i], 10),
+        (i, 'M' | 'm') if is_size => (&value[..i], 20),
+        (i, 'G' | 'g') if is_size => (&value[..i], 30),
+        (i, 'T' | 't') if is_size => (&value[..i], 40),
+        _ => (value, 0),
+    };
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse::<u64>().ok()?.checked_mul(1 << shift)
+}
+
+// System calls made by `apply`, so the sequence can be tested without
+// changing the test process's own limits
+pub trait Sys {
+    fn setrlimit(&mut self, limit: Limit) -> io::Result<()>;
+    fn set_nondumpable(&mut self) -> io::Result<()>;
+}
+
+pub struct Libc;
+
+impl Sys for Libc {
+    fn setrlimit(&mut self, limit: Limit) -> io::Result<()> {
+        let resource = match limit.resource {
+            Resource::Core => libc::RLIMIT_CORE,
+            Resource::Nofile => libc::RLIMIT_NOFILE,
+            Resource::Nproc => libc::RLIMIT_NPROC,
+            Resource::As => libc::RLIMIT_AS,
+            Resource::Data => libc::RLIMIT_DATA,
+        };
+        let value = if limit.value == INFINITY {
+            libc::RLIM_INFINITY
+        } else {
+            limit.value as libc::rlim_t
+        };
+        let rlim = libc::rlimit {
+            rlim_cur: value,
+            rlim_max: value,
+        };
+        if unsafe { libc::setrlimit(resource, &rlim) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    fn set_nondumpable(&mut self) -> io::Result<()> {
+        if unsafe { libc::prctl(libc::PR_SET_DUMPABLE, 0, 0, 0, 0) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+// Apply `limits` and mark the process non-dumpable. Runs in the forked child
+// before exec, so it must not allocate. execve resets the dumpable flag for
+// ordinary binaries, so RLIMIT_CORE=0 is what keeps the application from
+// dumping core; the flag covers the child until exec.
+pub fn apply(limits: &[Limit], sys: &mut dyn Sys) -> io::Result<()> {
+    for limit in limits {
+        sys.setrlimit(*limit)?;
+    }
+    sys.set_nondumpable()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_config() {
+        let limits = from_config(&Config::parse("")).unwrap();
+        assert_eq!(limits, defaults());
+        assert_eq!(limits[1].to_string(), "nofile=65536");
+
+        let config = Config::parse(
+            "nautilus.rlimit.nofile=4096 nautilus.rlimit.nproc=512 nautilus.rlimit.as=2G \
+             nautilus.rlimit.data=max",
+        );
+        let limits: Vec<String> = from_config(&config)
+            .unwrap()
+            .iter()
+            .map(Limit::to_string)
+            .collect();
+        assert_eq!(
+            limits,
+            [
+                "core=0",
+                "nofile=4096",
+                "nproc=512",
+                "as=2147483648",
+                "data=max"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_from_config_rejects_invalid_values() {
+        for cmdline in [
+            "nautilus.rlimit.nofile=4K",
+            "nautilus.rlimit.nofile=-1",
+            "nautilus.rlimit.nproc=1K",
+            "nautilus.rlimit.as=2GB",
+            "nautilus.rlimit.as=G",
+            "nautilus.rlimit.data=99999999999T",
+        ] {
+            assert!(from_config(&Config::parse(cmdline)).is_err(), "{}", cmdline);
+        }
+    }
+
+    #[derive(Default)]
+    struct Recorder {
+        calls: Vec<String>,
+        fail: Option<Resource>,
+    }
+
+    impl Sys for Recorder {
+        fn setrlimit(&mut self, limit: Limit) -> io::Result<()> {
+            if self.fail == Some(limit.resource) {
+                return Err(io::Error::from_raw_os_error(1));
+            }
+            self.calls.push(limit.to_string());
+            Ok(())
+        }
+
+        fn set_nondumpable(&mut self) -> io::Result<()> {
+            self.calls.push("nondumpable".to_string());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_apply() {
+        let config = Config::parse("nautilus.rlimit.nofile=1024");
+        let limits = from_config(&config).unwrap();
+        let mut sys = Recorder::default();
+        apply(&limits, &mut sys).unwrap();
+        assert_eq!(sys.calls, ["core=0", "nofile=1024", "nondumpable"]);
+
+        let mut sys = Recorder {
+            fail: Some(Resource::Nofile),
+            ..Recorder::default()
+        };
+        assert!(apply(&limits, &mut sys).is_err());
+        assert_eq!(sys.calls, ["core=0"]);
+    }
+}
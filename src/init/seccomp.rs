@@ -0,0 +1,387 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::config::Config;
+use std::io;
+use system::SystemError;
+
+// Optional seccomp-bpf allowlist applied to run.sh, and so to everything it
+// starts, just before exec. Built with the `seccomp` feature and selected with
+// `nautilus.seccomp`:
+// - `off` (default) installs no filter
+// - `audit` allows every system call but logs those outside the allowlist to
+//   the kernel log, to find what an application needs
+// - `enforce` kills the process making a system call outside the allowlist
+//   with SIGSYS, which init reports when run.sh exits
+// Calls made with a foreign architecture's numbering are always killed.
+
+#[cfg(not(target_arch = "x86_64"))]
+compile_error!("the seccomp allowlist only covers x86_64");
+
+// Architecture tag the kernel reports for x86_64 system calls
+const AUDIT_ARCH_X86_64: u32 = 0xc000_003e;
+
+const SECCOMP_RET_KILL_PROCESS: u32 = 0x8000_0000;
+const SECCOMP_RET_LOG: u32 = 0x7ffc_0000;
+const SECCOMP_RET_ALLOW: u32 = 0x7fff_0000;
+
+// Offsets into struct seccomp_data
+const NR_OFFSET: u32 = 0;
+const ARCH_OFFSET: u32 = 4;
+
+// Classic BPF opcodes used by the program
+const BPF_LD_W_ABS: u16 = 0x20;
+const BPF_JEQ_K: u16 = 0x15;
+const BPF_RET_K: u16 = 0x06;
+
+// System calls made by the server (tokio, hyper, reqwest, the NSM ioctl) and by
+// the busybox tools, socat and python that run.sh uses
+const ALLOWED: &[libc::c_long] = &[
+    // memory and threads
+    libc::SYS_brk,
+    libc::SYS_mmap,
+    libc::SYS_munmap,
+    libc::SYS_mprotect,
+    libc::SYS_mremap,
+    libc::SYS_madvise,
+    libc::SYS_futex,
+    libc::SYS_set_robust_list,
+    libc::SYS_set_tid_address,
+    libc::SYS_rseq,
+    libc::SYS_sched_yield,
+    libc::SYS_sched_getaffinity,
+    libc::SYS_arch_prctl,
+    libc::SYS_prctl,
+    libc::SYS_prlimit64,
+    libc::SYS_getrlimit,
+    // processes
+    libc::SYS_clone,
+    libc::SYS_clone3,
+    libc::SYS_fork,
+    libc::SYS_vfork,
+    libc::SYS_execve,
+    libc::SYS_wait4,
+    libc::SYS_waitid,
+    libc::SYS_exit,
+    libc::SYS_exit_group,
+    libc::SYS_kill,
+    libc::SYS_tgkill,
+    libc::SYS_getpid,
+    libc::SYS_getppid,
+    libc::SYS_gettid,
+    libc::SYS_getpgrp,
+    libc::SYS_setpgid,
+    libc::SYS_getuid,
+    libc::SYS_geteuid,
+    libc::SYS_getgid,
+    libc::SYS_getegid,
+    libc::SYS_getgroups,
+    libc::SYS_uname,
+    libc::SYS_sysinfo,
+    libc::SYS_umask,
+    // signals
+    libc::SYS_rt_sigaction,
+    libc::SYS_rt_sigprocmask,
+    libc::SYS_rt_sigreturn,
+    // made by the kernel to resume a sleep or poll interrupted by a signal
+    libc::SYS_restart_syscall,
+    libc::SYS_rt_sigsuspend,
+    libc::SYS_sigaltstack,
+    // time
+    libc::SYS_clock_gettime,
+    libc::SYS_clock_getres,
+    libc::SYS_clock_nanosleep,
+    libc::SYS_nanosleep,
+    libc::SYS_gettimeofday,
+    // files
+    libc::SYS_read,
+    libc::SYS_write,
+    libc::SYS_readv,
+    libc::SYS_writev,
+    libc::SYS_pread64,
+    libc::SYS_pwrite64,
+    libc::SYS_open,
+    libc::SYS_openat,
+    libc::SYS_close,
+    libc::SYS_stat,
+    libc::SYS_fstat,
+    libc::SYS_lstat,
+    libc::SYS_newfstatat,
+    libc::SYS_statx,
+    libc::SYS_statfs,
+    libc::SYS_fstatfs,
+    libc::SYS_lseek,
+    libc::SYS_access,
+    libc::SYS_faccessat,
+    libc::SYS_readlink,
+    libc::SYS_readlinkat,
+    libc::SYS_getdents64,
+    libc::SYS_getcwd,
+    libc::SYS_chdir,
+    libc::SYS_unlink,
+    libc::SYS_unlinkat,
+    libc::SYS_rename,
+    libc::SYS_mkdir,
+    libc::SYS_ftruncate,
+    libc::SYS_fsync,
+    libc::SYS_fcntl,
+    libc::SYS_dup,
+    libc::SYS_dup2,
+    libc::SYS_dup3,
+    libc::SYS_pipe,
+    libc::SYS_pipe2,
+    // the NSM driver is used through ioctl on /dev/nsm
+    libc::SYS_ioctl,
+    libc::SYS_getrandom,
+    // polling
+    libc::SYS_poll,
+    libc::SYS_ppoll,
+    libc::SYS_select,
+    libc::SYS_pselect6,
+    libc::SYS_epoll_create1,
+    libc::SYS_epoll_ctl,
+    libc::SYS_epoll_wait,
+    libc::SYS_epoll_pwait,
+    libc::SYS_eventfd2,
+    // sockets
+    libc::SYS_socket,
+    libc::SYS_socketpair,
+    libc::SYS_connect,
+    libc::SYS_accept,
+    libc::SYS_accept4,
+    libc::SYS_bind,
+    libc::SYS_listen,
+    libc::SYS_shutdown,
+    libc::SYS_getsockname,
+    libc::SYS_getpeername,
+    libc::SYS_setsockopt,
+    libc::SYS_getsockopt,
+    libc::SYS_sendto,
+    libc::SYS_recvfrom,
+    libc::SYS_sendmsg,
+    libc::SYS_recvmsg,
+    libc::SYS_sendmmsg,
+    libc::SYS_recvmmsg,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    Off,
+    Audit,
+    Enforce,
+}
+
+impl Mode {
+    pub fn from_config(config: &Config) -> Result<Self, SystemError> {
+        match config.get("nautilus.seccomp") {
+            None | Some("off") => Ok(Mode::Off),
+            Some("audit") => Ok(Mode::Audit),
+            Some("enforce") => Ok(Mode::Enforce),
+            Some(value) => Err(SystemError {
+                message: format!("Invalid nautilus.seccomp: {}", value),
+            }),
+        }
+    }
+}
+
+// One classic BPF instruction, laid out as struct sock_filter
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Insn {
+    code: u16,
+    jt: u8,
+    jf: u8,
+    k: u32,
+}
+
+impl Insn {
+    fn load(offset: u32) -> Self {
+        Self {
+            code: BPF_LD_W_ABS,
+            jt: 0,
+            jf: 0,
+            k: offset,
+        }
+    }
+
+    fn jump_eq(value: u32, jt: u8, jf: u8) -> Self {
+        Self {
+            code: BPF_JEQ_K,
+            jt,
+            jf,
+            k: value,
+        }
+    }
+
+    fn ret(action: u32) -> Self {
+        Self {
+            code: BPF_RET_K,
+            jt: 0,
+            jf: 0,
+            k: action,
+        }
+    }
+}
+
+// struct sock_fprog
+#[repr(C)]
+struct Fprog {
+    len: libc::c_ushort,
+    filter: *const Insn,
+}
+
+pub struct Filter {
+    mode: Mode,
+    program: Vec<Insn>,
+}
+
+impl Filter {
+    // The filter for `mode`, or None when it is off
+    pub fn new(mode: Mode) -> Option<Self> {
+        let default = match mode {
+            Mode::Off => return None,
+            Mode::Audit => SECCOMP_RET_LOG,
+            Mode::Enforce => SECCOMP_RET_KILL_PROCESS,
+        };
+        Some(Self {
+            mode,
+            program: program(ALLOWED, default),
+        })
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    // Install the filter on the calling process. Runs in the forked child
+    // before exec, so it must not allocate. no_new_privs is required to install
+    // a filter without CAP_SYS_ADMIN.
+    pub fn install(&self) -> io::Result<()> {
+        let fprog = Fprog {
+            len: self.program.len() as libc::c_ushort,
+            filter: self.program.as_ptr(),
+        };
+        unsafe {
+            if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if libc::prctl(
+                libc::PR_SET_SECCOMP,
+                libc::SECCOMP_MODE_FILTER,
+                &fprog as *const Fprog,
+            ) < 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+}
+
+// Build the program: check the architecture, then compare the system call
+// number with each allowed one in turn. Each comparison jumps over the allow
+// that follows it unless it matches, so no jump exceeds the 8-bit offsets.
+fn program(allowed: &[libc::c_long], default: u32) -> Vec<Insn> {
+    let mut program = vec![
+        Insn::load(ARCH_OFFSET),
+        Insn::jump_eq(AUDIT_ARCH_X86_64, 1, 0),
+        Insn::ret(SECCOMP_RET_KILL_PROCESS),
+        Insn::load(NR_OFFSET),
+    ];
+    for &nr in allowed {
+        program.push(Insn::jump_eq(nr as u32, 0, 1));
+        program.push(Insn::ret(SECCOMP_RET_ALLOW));
+    }
+    program.push(Insn::ret(default));
+    program
+}
+
+// Whether run.sh exiting with `status` means the filter killed it or, since
+// the shell reports a killed child as 128 + signal, one of its children
+pub fn killed_by_filter(signal: Option<i32>, code: Option<i32>) -> bool {
+    signal == Some(libc::SIGSYS) || code == Some(128 + libc::SIGSYS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Run `program` on a system call, as the kernel would
+    fn run(program: &[Insn], arch: u32, nr: u32) -> u32 {
+        let mut pc = 0;
+        let mut acc = 0;
+        loop {
+            let insn = program[pc];
+            pc += 1;
+            match insn.code {
+                BPF_LD_W_ABS if insn.k == ARCH_OFFSET => acc = arch,
+                BPF_LD_W_ABS if insn.k == NR_OFFSET => acc = nr,
+                BPF_JEQ_K if acc == insn.k => pc += insn.jt as usize,
+                BPF_JEQ_K => pc += insn.jf as usize,
+                BPF_RET_K => return insn.k,
+                _ => panic!("unexpected instruction {:?}", insn),
+            }
+        }
+    }
+
+    #[test]
+    fn test_program() {
+        let program = program(&[0, 60, 231], SECCOMP_RET_KILL_PROCESS);
+        assert_eq!(program.len(), 4 + 3 * 2 + 1);
+        for nr in [0, 60, 231] {
+            assert_eq!(run(&program, AUDIT_ARCH_X86_64, nr), SECCOMP_RET_ALLOW);
+        }
+        for nr in [1, 59, 232, 0x4000_0000] {
+            assert_eq!(
+                run(&program, AUDIT_ARCH_X86_64, nr),
+                SECCOMP_RET_KILL_PROCESS
+            );
+        }
+        // i386 numbering, where 0 is restart_syscall
+        assert_eq!(run(&program, 0x4000_0003, 0), SECCOMP_RET_KILL_PROCESS);
+    }
+
+    #[test]
+    fn test_modes() {
+        let mode = |cmdline| Mode::from_config(&Config::parse(cmdline));
+        assert_eq!(mode("").unwrap(), Mode::Off);
+        assert_eq!(mode("nautilus.seccomp=off").unwrap(), Mode::Off);
+        assert_eq!(mode("nautilus.seccomp=audit").unwrap(), Mode::Audit);
+        assert_eq!(mode("nautilus.seccomp=enforce").unwrap(), Mode::Enforce);
+        assert!(mode("nautilus.seccomp=on").is_err());
+
+        assert!(Filter::new(Mode::Off).is_none());
+        let audit = Filter::new(Mode::Audit).unwrap();
+        assert_eq!(
+            run(&audit.program, AUDIT_ARCH_X86_64, libc::SYS_ptrace as u32),
+            SECCOMP_RET_LOG
+        );
+        let enforce = Filter::new(Mode::Enforce).unwrap();
+        assert_eq!(
+            run(&enforce.program, AUDIT_ARCH_X86_64, libc::SYS_ptrace as u32),
+            SECCOMP_RET_KILL_PROCESS
+        );
+        assert_eq!(
+            run(&enforce.program, AUDIT_ARCH_X86_64, libc::SYS_ioctl as u32),
+            SECCOMP_RET_ALLOW
+        );
+        // An interrupted nanosleep or poll is resumed with restart_syscall
+        assert_eq!(
+            run(
+                &enforce.program,
+                AUDIT_ARCH_X86_64,
+                libc::SYS_restart_syscall as u32
+            ),
+            SECCOMP_RET_ALLOW
+        );
+        assert!(enforce.program.len() < 4096);
+    }
+
+    #[test]
+    fn test_killed_by_filter() {
+        assert!(killed_by_filter(Some(libc::SIGSYS), None));
+        assert!(killed_by_filter(None, Some(159)));
+        assert!(!killed_by_filter(Some(libc::SIGKILL), None));
+        assert!(!killed_by_filter(None, Some(1)));
+    }
+}
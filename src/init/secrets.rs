@@ -0,0 +1,163 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
+use std::iter::Peekable;
+use std::os::unix::io::FromRawFd;
+use std::str::Chars;
+use system::{socket_accept, SystemError};
+
+// Receive the secrets JSON sent by expose_enclave.sh, e.g. `{"API_KEY": "..."}`,
+// from a single connection to the given vsock port
+pub fn receive(port: u32) -> Result<BTreeMap<String, String>, SystemError> {
+    let fd = socket_accept(port)?;
+    // Safety: fd is a connected socket owned by nothing else
+    let mut conn = unsafe { File::from_raw_fd(fd) };
+    let mut json = String::new();
+    conn.read_to_string(&mut json).map_err(|e| SystemError {
+        message: format!("Failed to read secrets from vsock port {}: {}", port, e),
+    })?;
+    parse(&json)
+}
+
+// Parse a flat JSON object with string values
+pub fn parse(json: &str) -> Result<BTreeMap<String, String>, SystemError> {
+    let mut parser = Parser {
+        chars: json.chars().peekable(),
+    };
+    let secrets = parser.object()?;
+    parser.skip_whitespace();
+    match parser.chars.next() {
+        None => Ok(secrets),
+        Some(c) => Err(parser.error(&format!("unexpected {:?} after object", c))),
+    }
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str) -> SystemError {
+        SystemError {
+            message: format!("Invalid secrets JSON: {}", message),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|c| c.is_ascii_whitespace()).is_some() {}
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), SystemError> {
+        self.skip_whitespace();
+        match self.chars.next() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => Err(self.error(&format!("expected {:?}, found {:?}", expected, c))),
+            None => Err(self.error(&format!("expected {:?}, found end of input", expected))),
+        }
+    }
+
+    fn object(&mut self) -> Result<BTreeMap<String, String>, SystemError> {
+        let mut secrets = BTreeMap::new();
+        self.expect('{')?;
+        self.skip_whitespace();
+        if self.chars.next_if_eq(&'}').is_some() {
+            return Ok(secrets);
+        }
+        loop {
+            let key = self.string()?;
+            self.expect(':')?;
+            let value = self.string()?;
+            secrets.insert(key, value);
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some(',') => continue,
+                Some('}') => return Ok(secrets),
+                _ => return Err(self.error("expected ',' or '}'")),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, SystemError> {
+        self.expect('"')?;
+        let mut value = String::new();
+        loop {
+            match self.chars.next() {
+                Some('"') => return Ok(value),
+                Some('\\') => value.push(self.escape()?),
+                Some(c) if c < ' ' => return Err(self.error("control character in string")),
+                Some(c) => value.push(c),
+                None => return Err(self.error("unterminated string")),
+            }
+        }
+    }
+
+    fn escape(&mut self) -> Result<char, SystemError> {
+        match self.chars.next() {
+            Some('"') => Ok('"'),
+            Some('\\') => Ok('\\'),
+            Some('/') => Ok('/'),
+            Some('b') => Ok('\u{8}'),
+            Some('f') => Ok('\u{c}'),
+            Some('n') => Ok('\n'),
+            Some('r') => Ok('\r'),
+            Some('t') => Ok('\t'),
+            Some('u') => {
+                let high = self.hex4()?;
+                let code = if (0xD800..0xDC00).contains(&high) {
+                    if self.chars.next() != Some('\\') || self.chars.next() != Some('u') {
+                        return Err(self.error("unpaired surrogate"));
+                    }
+                    let low = self.hex4()?;
+                    if !(0xDC00..0xE000).contains(&low) {
+                        return Err(self.error("unpaired surrogate"));
+                    }
+                    0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+                } else {
+                    high
+                };
+                char::from_u32(code).ok_or_else(|| self.error("invalid unicode escape"))
+            }
+            _ => Err(self.error("invalid escape")),
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, SystemError> {
+        let digits: String = (0..4).filter_map(|_| self.chars.next()).collect();
+        if digits.len() != 4 {
+            return Err(self.error("truncated unicode escape"));
+        }
+        u32::from_str_radix(&digits, 16).map_err(|_| self.error("invalid unicode escape"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let secrets =
+            parse("{\"API_KEY\": \"045a2781\", \"TOKEN\":\"a\\\"b\\u00e9\\ud83d\\ude00\"}\n")
+                .unwrap();
+        assert_eq!(secrets["API_KEY"], "045a2781");
+        assert_eq!(secrets["TOKEN"], "a\"b\u{e9}\u{1f600}");
+        assert!(parse(" {} ").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_parse_rejects_invalid() {
+        for json in [
+            "",
+            "{\"API_KEY\": 1}",
+            "{\"API_KEY\": \"a\"",
+            "{\"API_KEY\" \"a\"}",
+            "{\"API_KEY\": \"a\"} x",
+            "{\"API_KEY\": \"\\ud83d\"}",
+        ] {
+            assert!(parse(json).is_err(), "{}", json);
+        }
+    }
+}
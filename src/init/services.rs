@@ -0,0 +1,796 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::config::Config;
+use crate::env_manifest::{is_valid_key, parse_value};
+use crate::lifecycle::EndAction;
+use std::io;
+use std::process::{Child, ExitStatus};
+use std::thread;
+use std::time::{Duration, Instant};
+use system::{dmesg, SystemError};
+
+// Optional manifest of services for init to supervise in place of run.sh,
+// baked into the image. It is a TOML array of tables, one `[[service]]` per
+// process, with these keys (values on one line):
+// - `name` (required): letters, digits, `-` and `_`
+// - `exec` (required): path of the program
+// - `args`: list of arguments, e.g. `["--port", "3000"]`
+// - `env`: list of `KEY=VALUE` set over the /env.manifest variables
+// - `restart_policy`: `always`, `on-failure` (default) or `never`
+// - `max_restarts`: restarts allowed over the enclave's life (default 3)
+// - `depends_on`: list of services to start first
+// - `critical`: `true` to end the enclave when the service stops for good
+// Services start in dependency order and are restarted independently. Once a
+// critical service needs a restart it has no budget left for, init takes
+// `nautilus.services.critical_action`: `reboot` (default) or `poweroff`.
+// Other services that stop stay stopped. The state of every service is logged
+// every `nautilus.services.status_secs` (default 60). Without a manifest init
+// runs run.sh as before.
+pub const SERVICES_MANIFEST_PATH: &str = "/services.manifest";
+
+// How often services are polled for exit
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RestartPolicy {
+    Always,
+    OnFailure,
+    Never,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Service {
+    pub name: String,
+    pub exec: String,
+    pub args: Vec<String>,
+    pub env: Vec<(String, String)>,
+    pub restart_policy: RestartPolicy,
+    pub max_restarts: u32,
+    pub depends_on: Vec<String>,
+    pub critical: bool,
+}
+
+impl Service {
+    fn new(name: String, exec: String) -> Self {
+        Self {
+            name,
+            exec,
+            args: Vec::new(),
+            env: Vec::new(),
+            restart_policy: RestartPolicy::OnFailure,
+            max_restarts: 3,
+            depends_on: Vec::new(),
+            critical: false,
+        }
+    }
+}
+
+fn line_error(line: usize, message: &str) -> SystemError {
+    SystemError {
+        message: format!("{} line {}: {}", SERVICES_MANIFEST_PATH, line, message),
+    }
+}
+
+// A `[[service]]` table being read, with the line of its header
+#[derive(Default)]
+struct Table {
+    line: usize,
+    keys: Vec<(usize, String, String)>,
+}
+
+// Parse the manifest into services in start order, naming the first offending
+// line on error
+pub fn parse(text: &str) -> Result<Vec<Service>, SystemError> {
+    let mut tables: Vec<Table> = Vec::new();
+    for (i, raw) in text.lines().enumerate() {
+        let line = i + 1;
+        let entry = raw.trim();
+        if entry.is_empty() || entry.starts_with('#') {
+            continue;
+        }
+        if entry.starts_with('[') {
+            let header = entry.split('#').next().unwrap_or("").trim_end();
+            if header != "[[service]]" {
+                return Err(line_error(line, "expected [[service]]"));
+            }
+            tables.push(Table {
+                line,
+                ..Table::default()
+            });
+            continue;
+        }
+        let table = tables
+            .last_mut()
+            .ok_or_else(|| line_error(line, "expected [[service]] before the first key"))?;
+        let (key, value) = entry
+            .split_once('=')
+            .ok_or_else(|| line_error(line, "expected key = value"))?;
+        table
+            .keys
+            .push((line, key.trim().to_string(), value.trim().to_string()));
+    }
+    let services = tables
+        .iter()
+        .map(parse_table)
+        .collect::<Result<Vec<_>, _>>()?;
+    start_order(services)
+}
+
+fn parse_table(table: &Table) -> Result<Service, SystemError> {
+    let find = |key: &str| table.keys.iter().find(|(_, k, _)| k == key);
+    let string = |key: &str| match find(key) {
+        Some((line, _, value)) => parse_value(value)
+            .map(Some)
+            .map_err(|e| line_error(*line, e)),
+        None => Ok(None),
+    };
+    let name = string("name")?.ok_or_else(|| line_error(table.line, "service has no name"))?;
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c == '-' || c == '_' || c.is_ascii_alphanumeric())
+    {
+        return Err(line_error(
+            table.line,
+            &format!("invalid service name {:?}", name),
+        ));
+    }
+    let exec = string("exec")?
+        .ok_or_else(|| line_error(table.line, &format!("service {} has no exec", name)))?;
+    let mut service = Service::new(name, exec);
+
+    for (line, key, value) in &table.keys {
+        let line = *line;
+        if table.keys.iter().filter(|(_, k, _)| k == key).count() > 1 {
+            return Err(line_error(line, &format!("duplicate key {}", key)));
+        }
+        // Unquoted scalars may be followed by a comment
+        let bare = value.split('#').next().unwrap_or("").trim();
+        match key.as_str() {
+            "name" | "exec" => {}
+            "args" => service.args = parse_list(value).map_err(|e| line_error(line, e))?,
+            "env" => {
+                for var in parse_list(value).map_err(|e| line_error(line, e))? {
+                    match var.split_once('=') {
+                        Some((key, value)) if is_valid_key(key) => {
+                            service.env.push((key.to_string(), value.to_string()))
+                        }
+                        _ => {
+                            return Err(line_error(
+                                line,
+                                &format!("expected KEY=VALUE, got {:?}", var),
+                            ))
+                        }
+                    }
+                }
+            }
+            "restart_policy" => {
+                service.restart_policy = match parse_value(value)
+                    .map_err(|e| line_error(line, e))?
+                    .as_str()
+                {
+                    "always" => RestartPolicy::Always,
+                    "on-failure" => RestartPolicy::OnFailure,
+                    "never" => RestartPolicy::Never,
+                    policy => {
+                        return Err(line_error(
+                            line,
+                            &format!("invalid restart_policy {:?}", policy),
+                        ))
+                    }
+                }
+            }
+            "max_restarts" => {
+                service.max_restarts = bare
+                    .parse()
+                    .map_err(|_| line_error(line, &format!("invalid max_restarts {}", bare)))?
+            }
+            "depends_on" => {
+                service.depends_on = parse_list(value).map_err(|e| line_error(line, e))?
+            }
+            "critical" => {
+                service.critical = match bare {
+                    "true" => true,
+                    "false" => false,
+                    _ => return Err(line_error(line, &format!("invalid critical {}", bare))),
+                }
+            }
+            _ => return Err(line_error(line, &format!("unknown key {}", key))),
+        }
+    }
+    Ok(service)
+}
+
+// Length of the quoted string at the start of `value`, quotes included
+fn quoted_len(value: &str) -> Option<usize> {
+    let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+    let mut chars = value.char_indices().skip(1);
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' if quote == '"' => {
+                chars.next();
+            }
+            c if c == quote => return Some(i + 1),
+            _ => {}
+        }
+    }
+    None
+}
+
+// Parse a one-line list of quoted strings, e.g. `["a", 'b']`
+fn parse_list(value: &str) -> Result<Vec<String>, &'static str> {
+    let mut rest = value
+        .strip_prefix('[')
+        .ok_or("expected a list of quoted strings")?
+        .trim_start();
+    let mut items = Vec::new();
+    loop {
+        if let Some(after) = rest.strip_prefix(']') {
+            let trailing = after.trim_start();
+            return if trailing.is_empty() || trailing.starts_with('#') {
+                Ok(items)
+            } else {
+                Err("unexpected text after closing bracket")
+            };
+        }
+        if !rest.starts_with(['"', '\'']) {
+            return Err("expected a quoted string");
+        }
+        let len = quoted_len(rest).ok_or("unterminated quoted value")?;
+        items.push(parse_value(&rest[..len])?);
+        rest = rest[len..].trim_start();
+        match rest.strip_prefix(',') {
+            Some(after) => rest = after.trim_start(),
+            None if rest.starts_with(']') => {}
+            None => return Err("expected , or ] after list item"),
+        }
+    }
+}
+
+// Order services so each starts after its dependencies, keeping manifest order
+// otherwise, or fail on an unknown or circular dependency
+pub fn start_order(services: Vec<Service>) -> Result<Vec<Service>, SystemError> {
+    let error = |message: String| SystemError {
+        message: format!("{}: {}", SERVICES_MANIFEST_PATH, message),
+    };
+    for (i, service) in services.iter().enumerate() {
+        if services[..i].iter().any(|s| s.name == service.name) {
+            return Err(error(format!("duplicate service {}", service.name)));
+        }
+        for dependency in &service.depends_on {
+            if !services.iter().any(|s| &s.name == dependency) {
+                return Err(error(format!(
+                    "service {} depends on unknown service {}",
+                    service.name, dependency
+                )));
+            }
+        }
+    }
+    let mut pending = services;
+    let mut ordered: Vec<Service> = Vec::with_capacity(pending.len());
+    while !pending.is_empty() {
+        let ready = pending.iter().position(|service| {
+            service
+                .depends_on
+                .iter()
+                .all(|dependency| ordered.iter().any(|s| &s.name == dependency))
+        });
+        match ready {
+            Some(i) => ordered.push(pending.remove(i)),
+            None => {
+                let names: Vec<&str> = pending.iter().map(|s| s.name.as_str()).collect();
+                return Err(error(format!(
+                    "circular dependency between {}",
+                    names.join(", ")
+                )));
+            }
+        }
+    }
+    Ok(ordered)
+}
+
+// What to do once a service exits with `code` (None if it was killed by a
+// signal or could not be run), given the restarts already made
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Next {
+    Restart,
+    Stop,
+    // A critical service is stopping for good
+    EndEnclave,
+}
+
+pub fn after_exit(service: &Service, code: Option<i32>, restarts: u32) -> Next {
+    let failed = code != Some(0);
+    let wants_restart = match service.restart_policy {
+        RestartPolicy::Always => true,
+        RestartPolicy::OnFailure => failed,
+        RestartPolicy::Never => false,
+    };
+    if wants_restart && restarts < service.max_restarts {
+        Next::Restart
+    } else if service.critical && (wants_restart || failed) {
+        Next::EndEnclave
+    } else {
+        Next::Stop
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Settings {
+    pub status_interval: Duration,
+    pub critical_action: EndAction,
+}
+
+impl Settings {
+    pub fn from_config(config: &Config) -> Result<Self, SystemError> {
+        let critical_action = match config.get("nautilus.services.critical_action") {
+            None | Some("reboot") => EndAction::Reboot,
+            Some("poweroff") => EndAction::Poweroff,
+            Some(value) => {
+                return Err(SystemError {
+                    message: format!("Invalid nautilus.services.critical_action: {}", value),
+                })
+            }
+        };
+        let status_secs = match config.get("nautilus.services.status_secs") {
+            None => 60,
+            Some(value) => {
+                value
+                    .parse()
+                    .ok()
+                    .filter(|secs| *secs > 0)
+                    .ok_or_else(|| SystemError {
+                        message: format!("Invalid nautilus.services.status_secs: {}", value),
+                    })?
+            }
+        };
+        Ok(Self {
+            status_interval: Duration::from_secs(status_secs),
+            critical_action,
+        })
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            status_interval: Duration::from_secs(60),
+            critical_action: EndAction::Reboot,
+        }
+    }
+}
+
+// A started service, so supervision can be tested with fake processes
+pub trait Process {
+    fn try_wait(&mut self) -> io::Result<Option<ExitStatus>>;
+}
+
+impl Process for Child {
+    fn try_wait(&mut self) -> io::Result<Option<ExitStatus>> {
+        Child::try_wait(self)
+    }
+}
+
+pub trait Launcher {
+    type Process: Process;
+    fn launch(&mut self, service: &Service) -> io::Result<Self::Process>;
+}
+
+enum State<P> {
+    Running(P),
+    // Exited or failed to start, with the status if there is one, and not yet
+    // restarted or stopped
+    Exited(Option<ExitStatus>),
+    Stopped(Option<ExitStatus>),
+}
+
+pub struct Supervisor<L: Launcher> {
+    services: Vec<Service>,
+    states: Vec<State<L::Process>>,
+    restarts: Vec<u32>,
+    launcher: L,
+}
+
+impl<L: Launcher> Supervisor<L> {
+    // Start `services`, which must already be in start order
+    pub fn start(services: Vec<Service>, mut launcher: L) -> Self {
+        let states = services
+            .iter()
+            .map(|service| launch(&mut launcher, service))
+            .collect();
+        Self {
+            restarts: vec![0; services.len()],
+            services,
+            states,
+            launcher,
+        }
+    }
+
+    // Reap exited services and restart or stop them. Returns the critical
+    // service that stopped for good, if any.
+    pub fn poll(&mut self) -> Option<&str> {
+        for i in 0..self.services.len() {
+            let service = &self.services[i];
+            if let State::Running(process) = &mut self.states[i] {
+                match process.try_wait() {
+                    Ok(None) => continue,
+                    Ok(Some(status)) => {
+                        dmesg(format!(
+                            "Service {} exited with status: {}",
+                            service.name, status
+                        ));
+                        self.states[i] = State::Exited(Some(status));
+                    }
+                    Err(e) => {
+                        eprintln!("Error waiting for service {}: {}", service.name, e);
+                        self.states[i] = State::Exited(None);
+                    }
+                }
+            }
+            let status = match self.states[i] {
+                State::Exited(status) => status,
+                _ => continue,
+            };
+            let code = status.and_then(|status| status.code());
+            match after_exit(service, code, self.restarts[i]) {
+                Next::Restart => {
+                    self.restarts[i] += 1;
+                    dmesg(format!(
+                        "Restarting service {} ({}/{})",
+                        service.name, self.restarts[i], service.max_restarts
+                    ));
+                    self.states[i] = launch(&mut self.launcher, service);
+                }
+                Next::Stop => {
+                    dmesg(format!("Service {} stopped", service.name));
+                    self.states[i] = State::Stopped(status);
+                }
+                Next::EndEnclave => {
+                    dmesg(format!(
+                        "Critical service {} stopped after {} restart(s)",
+                        service.name, self.restarts[i]
+                    ));
+                    self.states[i] = State::Stopped(status);
+                    return Some(&self.services[i].name);
+                }
+            }
+        }
+        None
+    }
+
+    // One-line state of every service
+    pub fn status(&self) -> String {
+        let states: Vec<String> = self
+            .services
+            .iter()
+            .zip(&self.states)
+            .zip(&self.restarts)
+            .map(|((service, state), restarts)| {
+                let state = match state {
+                    State::Running(_) => "running".to_string(),
+                    State::Exited(_) => "exited".to_string(),
+                    State::Stopped(Some(status)) => format!("stopped ({})", status),
+                    State::Stopped(None) => "stopped".to_string(),
+                };
+                format!("{} {}, {} restart(s)", service.name, state, restarts)
+            })
+            .collect();
+        states.join("; ")
+    }
+}
+
+fn launch<L: Launcher>(launcher: &mut L, service: &Service) -> State<L::Process> {
+    match launcher.launch(service) {
+        Ok(process) => {
+            dmesg(format!("Started service {}", service.name));
+            State::Running(process)
+        }
+        Err(e) => {
+            eprintln!("Failed to start service {}: {}", service.name, e);
+            State::Exited(None)
+        }
+    }
+}
+
+// Supervise the services until a critical one stops for good, returning
+// what to do with the enclave then
+pub fn run<L: Launcher>(supervisor: &mut Supervisor<L>, settings: &Settings) -> EndAction {
+    let mut last_status = Instant::now();
+    loop {
+        if supervisor.poll().is_some() {
+            dmesg(format!("Services: {}", supervisor.status()));
+            return settings.critical_action;
+        }
+        if last_status.elapsed() >= settings.status_interval {
+            dmesg(format!("Services: {}", supervisor.status()));
+            last_status = Instant::now();
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::{BTreeMap, VecDeque};
+    use std::os::unix::process::ExitStatusExt;
+
+    const MANIFEST: &str = r#"
+# Weather example
+[[service]]
+name = "server"
+exec = "/nautilus-server"
+args = ["--port", '3000']
+env = ["RUST_LOG=info", "GREETING=a=b"]
+restart_policy = "always"
+max_restarts = 5 # per enclave
+depends_on = ["forwarder"]
+critical = true
+
+[[service]]
+name = "forwarder"
+exec = "/forwarder"
+"#;
+
+    fn names(services: &[Service]) -> Vec<&str> {
+        services.iter().map(|s| s.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        let services = parse(MANIFEST).unwrap();
+        assert_eq!(names(&services), ["forwarder", "server"]);
+        assert_eq!(
+            services[0],
+            Service::new("forwarder".into(), "/forwarder".into())
+        );
+        let server = &services[1];
+        assert_eq!(server.args, ["--port", "3000"]);
+        assert_eq!(
+            server.env,
+            [
+                ("RUST_LOG".to_string(), "info".to_string()),
+                ("GREETING".to_string(), "a=b".to_string())
+            ]
+        );
+        assert_eq!(server.restart_policy, RestartPolicy::Always);
+        assert_eq!(server.max_restarts, 5);
+        assert!(server.critical);
+    }
+
+    #[test]
+    fn test_parse_errors_name_line() {
+        for (manifest, line) in [
+            ("name = \"a\"\n", 1),
+            ("[service]\n", 1),
+            ("[[service]]\nexec = \"/a\"\n", 1),
+            ("[[service]]\nname = \"a b\"\nexec = \"/a\"\n", 1),
+            (
+                "[[service]]\nname = \"a\"\nexec = \"/a\"\nargs = [\"x\" \"y\"]\n",
+                4,
+            ),
+            (
+                "[[service]]\nname = \"a\"\nexec = \"/a\"\nargs = [\"x\"\n",
+                4,
+            ),
+            (
+                "[[service]]\nname = \"a\"\nexec = \"/a\"\nenv = [\"1A=b\"]\n",
+                4,
+            ),
+            (
+                "[[service]]\nname = \"a\"\nexec = \"/a\"\nrestart_policy = \"sometimes\"\n",
+                4,
+            ),
+            (
+                "[[service]]\nname = \"a\"\nexec = \"/a\"\nmax_restarts = -1\n",
+                4,
+            ),
+            (
+                "[[service]]\nname = \"a\"\nexec = \"/a\"\ncritical = yes\n",
+                4,
+            ),
+            (
+                "[[service]]\nname = \"a\"\nexec = \"/a\"\nuser = \"root\"\n",
+                4,
+            ),
+            (
+                "[[service]]\nname = \"a\"\nexec = \"/a\"\nexec = \"/b\"\n",
+                3,
+            ),
+        ] {
+            let err = parse(manifest).unwrap_err();
+            assert!(
+                err.message.contains(&format!("line {}:", line)),
+                "{}: {}",
+                manifest,
+                err.message
+            );
+        }
+    }
+
+    fn service(name: &str, depends_on: &[&str]) -> Service {
+        Service {
+            depends_on: depends_on.iter().map(|d| d.to_string()).collect(),
+            ..Service::new(name.to_string(), format!("/{}", name))
+        }
+    }
+
+    #[test]
+    fn test_start_order() {
+        let services = vec![
+            service("server", &["forwarder", "cache"]),
+            service("metrics", &[]),
+            service("cache", &["forwarder"]),
+            service("forwarder", &[]),
+        ];
+        assert_eq!(
+            names(&start_order(services).unwrap()),
+            ["metrics", "forwarder", "cache", "server"]
+        );
+
+        let unknown = start_order(vec![service("server", &["db"])]).unwrap_err();
+        assert!(unknown.message.contains("unknown service db"));
+        let circular = start_order(vec![
+            service("metrics", &[]),
+            service("a", &["b"]),
+            service("b", &["a"]),
+        ])
+        .unwrap_err();
+        assert!(circular
+            .message
+            .ends_with("circular dependency between a, b"));
+        assert!(start_order(vec![service("a", &[]), service("a", &[])]).is_err());
+    }
+
+    #[test]
+    fn test_after_exit() {
+        let mut server = service("server", &[]);
+        server.max_restarts = 1;
+        assert_eq!(after_exit(&server, Some(1), 0), Next::Restart);
+        assert_eq!(after_exit(&server, None, 0), Next::Restart);
+        assert_eq!(after_exit(&server, Some(0), 0), Next::Stop);
+        assert_eq!(after_exit(&server, Some(1), 1), Next::Stop);
+
+        server.critical = true;
+        assert_eq!(after_exit(&server, Some(1), 1), Next::EndEnclave);
+        assert_eq!(after_exit(&server, Some(0), 1), Next::Stop);
+        server.restart_policy = RestartPolicy::Always;
+        assert_eq!(after_exit(&server, Some(0), 0), Next::Restart);
+        assert_eq!(after_exit(&server, Some(0), 1), Next::EndEnclave);
+        server.restart_policy = RestartPolicy::Never;
+        assert_eq!(after_exit(&server, Some(0), 0), Next::Stop);
+        assert_eq!(after_exit(&server, Some(1), 0), Next::EndEnclave);
+    }
+
+    // Exits with `code` once polled `polls` times, or runs forever without one
+    struct FakeProcess {
+        polls: u32,
+        code: Option<i32>,
+    }
+
+    impl Process for FakeProcess {
+        fn try_wait(&mut self) -> io::Result<Option<ExitStatus>> {
+            match self.code {
+                Some(code) if self.polls == 0 => Ok(Some(ExitStatus::from_raw(code << 8))),
+                _ => {
+                    self.polls = self.polls.saturating_sub(1);
+                    Ok(None)
+                }
+            }
+        }
+    }
+
+    fn exits(polls: u32, code: i32) -> FakeProcess {
+        FakeProcess {
+            polls,
+            code: Some(code),
+        }
+    }
+
+    // Hands out each service's scripted runs in turn, recording launches
+    #[derive(Default)]
+    struct FakeLauncher {
+        // None for a run that fails to start
+        runs: BTreeMap<String, VecDeque<Option<FakeProcess>>>,
+        launched: Vec<String>,
+    }
+
+    impl Launcher for &mut FakeLauncher {
+        type Process = FakeProcess;
+
+        fn launch(&mut self, service: &Service) -> io::Result<FakeProcess> {
+            self.launched.push(service.name.clone());
+            match self
+                .runs
+                .get_mut(&service.name)
+                .and_then(VecDeque::pop_front)
+            {
+                Some(Some(process)) => Ok(process),
+                Some(None) => Err(io::Error::from_raw_os_error(libc::ENOENT)),
+                None => Ok(FakeProcess {
+                    polls: 0,
+                    code: None,
+                }),
+            }
+        }
+    }
+
+    #[test]
+    fn test_restart_bookkeeping() {
+        let mut metrics = service("metrics", &[]);
+        metrics.max_restarts = 1;
+        let mut server = service("server", &["metrics"]);
+        server.critical = true;
+        server.max_restarts = 2;
+        let services = start_order(vec![server, metrics, service("forwarder", &[])]).unwrap();
+
+        let mut launcher = FakeLauncher::default();
+        // metrics fails twice and then stays stopped
+        launcher.runs.insert(
+            "metrics".into(),
+            [Some(exits(0, 1)), Some(exits(1, 1))].into(),
+        );
+        // server fails, can't be restarted once, then fails again with its
+        // budget used up
+        launcher.runs.insert(
+            "server".into(),
+            [Some(exits(2, 1)), None, Some(exits(1, 1))].into(),
+        );
+
+        let mut supervisor = Supervisor::start(services, &mut launcher);
+        assert_eq!(supervisor.poll(), None);
+        assert_eq!(
+            supervisor.status(),
+            "metrics running, 1 restart(s); server running, 0 restart(s); \
+             forwarder running, 0 restart(s)"
+        );
+        assert_eq!(supervisor.poll(), None);
+        assert_eq!(supervisor.poll(), None);
+        assert_eq!(
+            supervisor.status(),
+            "metrics stopped (exit status: 1), 1 restart(s); server exited, 1 restart(s); \
+             forwarder running, 0 restart(s)"
+        );
+        assert_eq!(supervisor.poll(), None);
+        assert_eq!(supervisor.poll(), None);
+        assert_eq!(supervisor.poll(), Some("server"));
+        assert_eq!(
+            supervisor.status(),
+            "metrics stopped (exit status: 1), 1 restart(s); \
+             server stopped (exit status: 1), 2 restart(s); forwarder running, 0 restart(s)"
+        );
+        drop(supervisor);
+        assert_eq!(
+            launcher.launched,
+            [
+                "metrics",
+                "server",
+                "forwarder",
+                "metrics",
+                "server",
+                "server"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_settings() {
+        let settings = Settings::from_config(&Config::parse(
+            "nautilus.services.status_secs=5 nautilus.services.critical_action=poweroff",
+        ))
+        .unwrap();
+        assert_eq!(settings.status_interval, Duration::from_secs(5));
+        assert_eq!(settings.critical_action, EndAction::Poweroff);
+        assert_eq!(
+            Settings::from_config(&Config::default()).unwrap(),
+            Settings::default()
+        );
+        for cmdline in [
+            "nautilus.services.status_secs=0",
+            "nautilus.services.critical_action=restart",
+        ] {
+            assert!(Settings::from_config(&Config::parse(cmdline)).is_err());
+        }
+    }
+}
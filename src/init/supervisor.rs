@@ -0,0 +1,842 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::applog::{self, AppOutput, Ring};
+use crate::boottime::{self, BootTimer};
+use crate::cabundle::{self, CA_BUNDLE_PATH};
+use crate::caps::{self, CapSet};
+use crate::cgroup::{Cgroup, CgroupLimits, CGROUP_ROOT};
+use crate::config::Config;
+use crate::env_manifest::{self, ENV_MANIFEST_PATH};
+use crate::exec::AppExec;
+use crate::lifecycle::{self, EndAction, ExitPolicy};
+use crate::netwait::NetWait;
+use crate::rlimit::{self, Limit};
+use crate::services::{self, Launcher, Service, SERVICES_MANIFEST_PATH};
+use crate::sysops::SysOps;
+use crate::watchdog::{self, Action, Outcome, WatchdogConfig};
+use crate::{forward, logsink, preflight, secrets};
+use std::cell::Cell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::Path;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use system::{dmesg, interface_up, SystemError};
+
+#[cfg(feature = "seccomp")]
+use crate::seccomp::{self, Filter};
+
+// Without the `seccomp` feature there is never a filter to install
+#[cfg(not(feature = "seccomp"))]
+enum Filter {}
+
+// What nautilus-supervisor runs once init has booted: the application's
+// environment, the forwarders, and the services or run.sh with the watchdog.
+// Init restarts the supervisor if it dies, so everything here must cope with
+// running again in the same boot.
+
+// Set the environment inherited by run.sh. The CA bundle is checked before
+// it is exported, see cabundle.rs, and an invalid one is still exported
+// unless `nautilus.ca_bundle_strict=1` stops the boot.
+fn init_env(sys: &dyn SysOps, config: &Config) -> Result<(), SystemError> {
+    let expected = cabundle::Expected::from_config(config)?;
+    let checked = sys
+        .read_file(CA_BUNDLE_PATH)
+        .map_err(|e| SystemError {
+            message: format!("Failed to read {}: {}", CA_BUNDLE_PATH, e),
+        })
+        .and_then(|bundle| cabundle::verify(&bundle, &expected));
+    match checked {
+        Ok(count) => dmesg(format!(
+            "Verified {} certificate(s) in {}{}",
+            count,
+            CA_BUNDLE_PATH,
+            if expected.sha256.is_some() {
+                " against nautilus.ca_bundle_sha256"
+            } else {
+                ""
+            }
+        )),
+        Err(e) if expected.strict => return Err(e),
+        Err(e) => {
+            eprintln!("{}", e);
+            dmesg("WARNING: outbound TLS connections are likely to fail".to_string());
+        }
+    }
+
+    // Set the SSL_CERT_FILE environment variable
+    sys.set_env("SSL_CERT_FILE", CA_BUNDLE_PATH);
+    sys.set_env("PATH", "/bin:/sbin:/usr/bin:/usr/sbin:/");
+
+    println!("SSL_CERT_FILE set to ca-certificates.crt");
+    init_identity_env(sys, config)
+}
+
+// Export who the enclave is to the application: NAUTILUS_CID is the local
+// vsock CID, NAUTILUS_INSTANCE the `nautilus.instance` name given at launch,
+// and NAUTILUS_INIT_VERSION the version of this init. Without /dev/vsock,
+// e.g. outside an enclave, NAUTILUS_CID is left unset.
+fn init_identity_env(sys: &dyn SysOps, config: &Config) -> Result<(), SystemError> {
+    match sys.local_cid() {
+        Ok(cid) => sys.set_env("NAUTILUS_CID", &cid.to_string()),
+        Err(e) => dmesg(format!("{}, leaving NAUTILUS_CID unset", e.message)),
+    }
+    if let Some(instance) = config.get("nautilus.instance") {
+        let valid = !instance.is_empty()
+            && instance.len() <= 64
+            && instance
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.'));
+        if !valid {
+            return Err(SystemError {
+                message: format!("Invalid nautilus.instance: {}", instance),
+            });
+        }
+        sys.set_env("NAUTILUS_INSTANCE", instance);
+    }
+    sys.set_env("NAUTILUS_INIT_VERSION", env!("CARGO_PKG_VERSION"));
+    Ok(())
+}
+
+// Read /env.manifest if present, receiving secrets over vsock when it references any
+fn load_env_manifest(
+    sys: &dyn SysOps,
+    config: &Config,
+    cmdline_env: &BTreeSet<String>,
+) -> Result<BTreeMap<String, String>, SystemError> {
+    let manifest = match sys.read_file(ENV_MANIFEST_PATH) {
+        Ok(manifest) => manifest,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(e) => {
+            return Err(SystemError {
+                message: format!("Failed to read {}: {}", ENV_MANIFEST_PATH, e),
+            })
+        }
+    };
+    let vars = env_manifest::parse(&manifest)?;
+    let secrets = match config.get("nautilus.secrets_port") {
+        Some(port) if env_manifest::needs_secrets(&vars, cmdline_env) => {
+            let port = port.parse().map_err(|_| SystemError {
+                message: format!("Invalid nautilus.secrets_port: {}", port),
+            })?;
+            dmesg(format!("Waiting for secrets on vsock port {}", port));
+            Some(secrets::receive(port)?)
+        }
+        _ => None,
+    };
+    let env = env_manifest::resolve(&vars, secrets.as_ref(), cmdline_env)?;
+    dmesg(format!(
+        "Loaded {} variable(s) from {}",
+        env.len(),
+        ENV_MANIFEST_PATH
+    ));
+    Ok(env)
+}
+
+// Load the application environment from /env.manifest. An invalid manifest is
+// ignored with a warning, or fatal with `nautilus.env_manifest_strict=1`.
+// Along with the environment, whether it was resolved in full rather than
+// replaced by an empty one after an error
+fn init_env_manifest(
+    sys: &dyn SysOps,
+    config: &Config,
+    cmdline_env: &BTreeSet<String>,
+) -> Result<(BTreeMap<String, String>, bool), SystemError> {
+    let strict = config.get_bool("nautilus.env_manifest_strict", false)?;
+    match load_env_manifest(sys, config, cmdline_env) {
+        Ok(env) => Ok((env, true)),
+        Err(e) if strict => Err(e),
+        Err(e) => {
+            eprintln!("{}", e);
+            dmesg(format!("WARNING: ignoring {}", ENV_MANIFEST_PATH));
+            Ok((BTreeMap::new(), false))
+        }
+    }
+}
+
+// Where the application environment is kept once resolved, so a restarted
+// supervisor doesn't wait for secrets the parent has already sent. /run is a
+// tmpfs, so it stays in enclave memory. It holds resolved secrets, so only
+// its owner can read it, and it is replaced atomically so a supervisor that
+// dies writing it doesn't leave a truncated copy to be reused.
+pub const APP_ENV_PATH: &str = "/run/app.env";
+
+// Variables as NUL-terminated `KEY=VALUE` entries, as in /proc/<pid>/environ,
+// since no value can contain NUL
+fn format_app_env(env: &BTreeMap<String, String>) -> String {
+    let mut text = String::new();
+    for (key, value) in env {
+        text.push_str(&format!("{}={}\0", key, value));
+    }
+    text
+}
+
+fn parse_app_env(text: &str) -> BTreeMap<String, String> {
+    text.split('\0')
+        .filter_map(|entry| entry.split_once('='))
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
+
+// Set the supervisor's environment, inherited by everything it starts, and
+// resolve the application's from /env.manifest, or from APP_ENV_PATH when an
+// earlier supervisor already did. Timed as boot steps when `timer` is set.
+fn init_app_env(
+    sys: &dyn SysOps,
+    config: &Config,
+    cmdline_env: &BTreeSet<String>,
+    timer: &mut Option<BootTimer>,
+) -> Result<BTreeMap<String, String>, SystemError> {
+    init_env(sys, config)?;
+    if let Some(timer) = timer {
+        timer.step("environment");
+    }
+    let app_env = match sys.read_file(APP_ENV_PATH) {
+        Ok(cached) => {
+            dmesg(format!(
+                "Reusing application environment from {}",
+                APP_ENV_PATH
+            ));
+            parse_app_env(&cached)
+        }
+        Err(_) => {
+            let (app_env, complete) = init_env_manifest(sys, config, cmdline_env)?;
+            // After an ignored error the next supervisor tries again
+            if complete {
+                if let Err(e) = sys.write_private(APP_ENV_PATH, &format_app_env(&app_env)) {
+                    eprintln!("Failed to write {}: {}", APP_ENV_PATH, e);
+                }
+            }
+            app_env
+        }
+    };
+    if let Some(timer) = timer {
+        timer.step("env_manifest");
+    }
+    Ok(app_env)
+}
+
+// Create the application cgroup with the limits configured on the cmdline
+fn init_cgroup(config: &Config) -> Option<Cgroup> {
+    let limits = match CgroupLimits::from_config(config) {
+        Ok(limits) => limits,
+        Err(e) => {
+            eprintln!("{}", e);
+            return None;
+        }
+    };
+    match Cgroup::create(Path::new(CGROUP_ROOT), &limits) {
+        Ok(cgroup) => {
+            dmesg(format!(
+                "Created cgroup {} (memory.max={}, pids.max={})",
+                cgroup.path().display(),
+                limits.memory_max.as_deref().unwrap_or("max"),
+                limits.pids_max.as_deref().unwrap_or("max"),
+            ));
+            Some(cgroup)
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            None
+        }
+    }
+}
+
+// Start the vsock/TCP forwarders configured on the cmdline
+fn init_forwarding(config: &Config) {
+    match forward::rules_from_config(config) {
+        Ok(rules) => rules.into_iter().for_each(forward::spawn),
+        Err(e) => eprintln!("{}", e),
+    }
+}
+
+// Wait for egress with `nautilus.net_wait`, once the forwarders are up.
+// Timed as a boot step when `timer` is set.
+fn init_net_wait(config: &Config, timer: &mut Option<BootTimer>) {
+    match NetWait::from_config(config) {
+        Ok(Some(net_wait)) => {
+            net_wait.run();
+            if let Some(timer) = timer {
+                timer.step("net_wait");
+            }
+        }
+        Ok(None) => {}
+        Err(e) => eprintln!("{}", e),
+    }
+}
+
+// How to start the application, falling back to run.sh when the cmdline
+// options are invalid. Without run.sh to do it, init brings up loopback
+// itself for the server and the forwarders' 127.0.0.x addresses.
+fn init_app_exec(config: &Config) -> AppExec {
+    let app = AppExec::from_config(config).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        AppExec::Script
+    });
+    if let AppExec::Direct { .. } = app {
+        match interface_up("lo") {
+            Ok(()) => dmesg("Brought up loopback interface".to_string()),
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+    app
+}
+
+// Limits, capabilities, seccomp filter and cgroup applied to everything init
+// starts for the application
+#[derive(Clone, Copy)]
+struct Confinement<'a> {
+    cgroup: Option<&'a Cgroup>,
+    limits: &'a [Limit],
+    caps: CapSet,
+    filter: Option<&'a Arc<Filter>>,
+}
+
+// Spawn `command` (run.sh or a service) under `confinement`. With
+// `own_session` it leads a new session so the whole process group can be
+// signalled. With `capture` its stdout and stderr are piped back to init.
+fn spawn_app(
+    sys: &dyn SysOps,
+    mut command: Command,
+    confinement: &Confinement,
+    own_session: bool,
+    capture: bool,
+) -> std::io::Result<Child> {
+    let Confinement {
+        cgroup,
+        limits,
+        caps,
+        filter,
+    } = *confinement;
+    let limits = limits.to_vec();
+    // Safety: setrlimit and prctl are async-signal-safe and nothing is allocated
+    unsafe {
+        command.pre_exec(move || rlimit::apply(&limits, &mut rlimit::Libc));
+    }
+    if capture {
+        command.stdout(Stdio::piped()).stderr(Stdio::piped());
+    }
+
+    // Keep cgroup.procs open until spawn so the child can join the cgroup before exec
+    let procs = cgroup.and_then(|cgroup| match cgroup.procs_file() {
+        Ok(file) => Some(file),
+        Err(e) => {
+            eprintln!("{}", e);
+            None
+        }
+    });
+    if let Some(procs) = &procs {
+        let fd = procs.as_raw_fd();
+        // Safety: only async-signal-safe calls are made between fork and exec
+        unsafe {
+            command.pre_exec(move || {
+                if libc::write(fd, b"0".as_ptr() as _, 1) < 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+    }
+    if own_session {
+        // Safety: setsid is async-signal-safe
+        unsafe {
+            command.pre_exec(|| {
+                if libc::setsid() < 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+    }
+    let last_cap = caps::last_cap();
+    // Safety: prctl, capget and capset are async-signal-safe and nothing is allocated
+    unsafe {
+        command.pre_exec(move || caps::apply(caps, last_cap, &mut caps::Libc));
+    }
+    // Installed last, so the filter only has to allow the exec that follows
+    #[cfg(feature = "seccomp")]
+    if let Some(filter) = filter {
+        let filter = filter.clone();
+        // Safety: prctl is async-signal-safe and the program was built before fork
+        unsafe {
+            command.pre_exec(move || filter.install());
+        }
+    }
+    #[cfg(not(feature = "seccomp"))]
+    let _ = filter;
+    sys.spawn(command)
+}
+
+// The seccomp filter selected on the kernel command line, if any
+#[cfg(feature = "seccomp")]
+fn init_seccomp(config: &Config) -> Option<Arc<Filter>> {
+    let mode = seccomp::Mode::from_config(config).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        seccomp::Mode::Off
+    });
+    let filter = Filter::new(mode)?;
+    dmesg(format!("Application seccomp filter: {:?}", filter.mode()));
+    Some(Arc::new(filter))
+}
+
+#[cfg(not(feature = "seccomp"))]
+fn init_seccomp(config: &Config) -> Option<Arc<Filter>> {
+    if let Some(mode) = config.get("nautilus.seccomp").filter(|mode| *mode != "off") {
+        eprintln!(
+            "nautilus.seccomp={} ignored, init was built without the seccomp feature",
+            mode
+        );
+    }
+    None
+}
+
+// Read /services.manifest if present. An invalid manifest is ignored with a
+// warning, leaving run.sh to start the application.
+fn init_services(sys: &dyn SysOps) -> Option<Vec<Service>> {
+    let manifest = match sys.read_file(SERVICES_MANIFEST_PATH) {
+        Ok(manifest) => manifest,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return None,
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", SERVICES_MANIFEST_PATH, e);
+            return None;
+        }
+    };
+    match services::parse(&manifest) {
+        Ok(services) => {
+            dmesg(format!(
+                "Loaded {} service(s) from {}",
+                services.len(),
+                SERVICES_MANIFEST_PATH
+            ));
+            Some(services)
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            dmesg(format!(
+                "WARNING: ignoring {}, running run.sh",
+                SERVICES_MANIFEST_PATH
+            ));
+            None
+        }
+    }
+}
+
+// Starts services with the same confinement as run.sh, their own variables
+// set over the /env.manifest ones
+struct ServiceLauncher<'a> {
+    sys: &'a dyn SysOps,
+    app_env: &'a BTreeMap<String, String>,
+    confinement: Confinement<'a>,
+}
+
+impl Launcher for ServiceLauncher<'_> {
+    type Process = Child;
+
+    fn launch(&mut self, service: &Service) -> std::io::Result<Child> {
+        let mut command = Command::new(&service.exec);
+        command
+            .args(&service.args)
+            .envs(self.app_env)
+            .envs(service.env.iter().map(|(key, value)| (key, value)));
+        spawn_app(self.sys, command, &self.confinement, false, false)
+    }
+}
+
+// Log how the application exited, noting OOM kills in its cgroup since
+// `oom_kills` were counted
+fn log_exit(
+    name: &str,
+    status: std::io::Result<ExitStatus>,
+    cgroup: Option<&Cgroup>,
+    oom_kills: u64,
+) {
+    match status {
+        Ok(status) => {
+            let new_oom_kills = cgroup
+                .map_or(0, Cgroup::oom_kills)
+                .saturating_sub(oom_kills);
+            if !status.success() && new_oom_kills > 0 {
+                dmesg(format!(
+                    "{} exited with status: {} after {} OOM kill(s) (signal: {:?})",
+                    name,
+                    status,
+                    new_oom_kills,
+                    status.signal(),
+                ));
+            } else {
+                dmesg(format!("{} exited with status: {}", name, status));
+            }
+            #[cfg(feature = "seccomp")]
+            if seccomp::killed_by_filter(status.signal(), status.code()) {
+                dmesg(format!(
+                    "{} or a child was killed by SIGSYS for a system call outside the \
+                     seccomp allowlist, boot with nautilus.seccomp=audit to log it",
+                    name
+                ));
+            }
+        }
+        Err(e) => eprintln!("Error waiting for {}: {}", name, e),
+    }
+}
+
+// run.sh or the server binary, restarted according to the exit policy and
+// the watchdog
+struct Application<'a> {
+    exec: &'a AppExec,
+    env: &'a BTreeMap<String, String>,
+    confinement: Confinement<'a>,
+    watchdog: Option<&'a WatchdogConfig>,
+    policy: &'a ExitPolicy,
+    ring: &'a Arc<Mutex<Ring>>,
+    // Reported once the application is first spawned
+    boot_timer: Cell<Option<BootTimer>>,
+}
+
+impl Application<'_> {
+    // Run the application until it exits for good or the watchdog gives up on
+    // it, returning what to do with the enclave
+    fn run(&self, sys: &dyn SysOps) -> EndAction {
+        let name = self.exec.name();
+        let cgroup = self.confinement.cgroup;
+        let own_session = self.watchdog.is_some();
+        let mut restarts = 0;
+        loop {
+            let oom_kills = cgroup.map_or(0, Cgroup::oom_kills);
+            let spawned = spawn_app(
+                sys,
+                self.exec.command(self.env),
+                &self.confinement,
+                own_session,
+                true,
+            )
+            .or_else(|e| {
+                // Without the pipes the output still reaches the console, unprefixed
+                eprintln!("Failed to capture {} output, retrying without: {}", name, e);
+                spawn_app(
+                    sys,
+                    self.exec.command(self.env),
+                    &self.confinement,
+                    own_session,
+                    false,
+                )
+            });
+            let code = match spawned {
+                Ok(mut child) => {
+                    dmesg(format!("Spawned {}", name));
+                    if let Some(mut timer) = self.boot_timer.take() {
+                        timer.step("app_spawn");
+                        boottime::report(sys, &timer.finish());
+                    }
+                    let output = AppOutput::capture(&mut child, self.ring);
+                    let outcome = match self.watchdog {
+                        Some(config) => watchdog::supervise(&mut child, config),
+                        // Wait for the child process to finish
+                        None => Outcome::Exited(child.wait()),
+                    };
+                    match outcome {
+                        Outcome::Exited(status) => {
+                            output.drain(applog::DRAIN_TIMEOUT);
+                            let code = status.as_ref().ok().and_then(ExitStatus::code);
+                            log_exit(name, status, cgroup, oom_kills);
+                            code
+                        }
+                        Outcome::Hung(Action::Restart) => {
+                            applog::dump(self.ring);
+                            watchdog::terminate(&mut child, watchdog::TERM_TIMEOUT);
+                            output.drain(applog::DRAIN_TIMEOUT);
+                            dmesg(format!("Watchdog: restarting {}", name));
+                            continue;
+                        }
+                        Outcome::Hung(Action::Reboot) => {
+                            applog::dump(self.ring);
+                            return EndAction::Reboot;
+                        }
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Failed to execute {}: {}", name, e);
+                    None
+                }
+            };
+            match lifecycle::decide(self.policy, code, restarts) {
+                EndAction::Restart => {
+                    restarts += 1;
+                    dmesg(format!(
+                        "Restarting {} ({}/{})",
+                        name, restarts, self.policy.restart_budget
+                    ));
+                }
+                action => return action,
+            }
+        }
+    }
+}
+
+// Run the services or the application until they end for good, returning
+// what init should do with the enclave. `timer` carries init's boot timings,
+// reported once the application is spawned; it is None after a restart. A
+// setup failure, such as an invalid manifest with
+// `nautilus.env_manifest_strict=1` or a file missing from the image (see
+// preflight.rs), reboots as a failed boot step does.
+pub fn run(sys: &dyn SysOps, mut timer: Option<BootTimer>) -> EndAction {
+    let config = Config::load(sys);
+    logsink::start(&config, "supervisor");
+    let cmdline_env: BTreeSet<String> = std::env::vars_os()
+        .filter_map(|(key, _)| key.into_string().ok())
+        .collect();
+    let app_env = match init_app_env(sys, &config, &cmdline_env, &mut timer) {
+        Ok(app_env) => app_env,
+        Err(e) => {
+            eprintln!("{}", e);
+            dmesg("Supervisor setup failed, rebooting".to_string());
+            return EndAction::Reboot;
+        }
+    };
+    init_forwarding(&config);
+    init_net_wait(&config, &mut timer);
+    let cgroup = init_cgroup(&config);
+    let watchdog = WatchdogConfig::from_config(&config).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        None
+    });
+
+    let policy = ExitPolicy::from_config(&config).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        ExitPolicy::default()
+    });
+
+    let limits = rlimit::from_config(&config).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        rlimit::defaults()
+    });
+    let applied: Vec<String> = limits.iter().map(Limit::to_string).collect();
+    dmesg(format!(
+        "Application rlimits: {}, non-dumpable",
+        applied.join(" ")
+    ));
+
+    let rules = forward::rules_from_config(&config).unwrap_or_default();
+    let caps = caps::from_config(&config, &rules).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        CapSet::default()
+    });
+    dmesg(format!("Application capabilities: {}", caps));
+
+    let filter = init_seccomp(&config);
+    let confinement = Confinement {
+        cgroup: cgroup.as_ref(),
+        limits: &limits,
+        caps,
+        filter: filter.as_ref(),
+    };
+
+    if let Some(services) = init_services(sys) {
+        let settings = services::Settings::from_config(&config).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            services::Settings::default()
+        });
+        let launcher = ServiceLauncher {
+            sys,
+            app_env: &app_env,
+            confinement,
+        };
+        let mut supervisor = services::Supervisor::start(services, launcher);
+        if let Some(mut timer) = timer {
+            timer.step("app_spawn");
+            boottime::report(sys, &timer.finish());
+        }
+        return services::run(&mut supervisor, &settings);
+    }
+
+    let exec = init_app_exec(&config);
+    if let Err(e) =
+        preflight::checks(&config, &exec).and_then(|checks| preflight::run(sys, &checks))
+    {
+        eprintln!("{}", e);
+        dmesg("Supervisor setup failed, rebooting".to_string());
+        return EndAction::Reboot;
+    }
+    let ring_lines = applog::ring_lines(&config).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        0
+    });
+    let ring = Arc::new(Mutex::new(Ring::new(ring_lines)));
+    let app = Application {
+        exec: &exec,
+        env: &app_env,
+        confinement,
+        watchdog: watchdog.as_ref(),
+        policy: &policy,
+        ring: &ring,
+        boot_timer: Cell::new(timer),
+    };
+    app.run(sys)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sysops::fake::Recorder;
+
+    #[test]
+    fn test_strict_ca_bundle() {
+        let sys = Recorder::with_files(&[
+            ("/proc/cmdline", "nautilus.ca_bundle_strict=1"),
+            (CA_BUNDLE_PATH, ""),
+        ]);
+        assert_eq!(run(&sys, None), EndAction::Reboot);
+        assert!(sys.calls_to("setenv").is_empty());
+        assert!(sys.calls_to("spawn").is_empty());
+
+        // Without strict the bundle is exported anyway
+        let sys = Recorder::with_files(&[(CA_BUNDLE_PATH, "")]);
+        init_env(&sys, &Config::default()).unwrap();
+        assert_eq!(sys.calls_to("setenv SSL_CERT_FILE").len(), 1);
+    }
+
+    #[test]
+    fn test_strict_env_manifest() {
+        let sys = Recorder::with_files(&[
+            ("/proc/cmdline", "nautilus.env_manifest_strict=1"),
+            (ENV_MANIFEST_PATH, "NOT A VARIABLE\n"),
+        ]);
+        assert_eq!(run(&sys, None), EndAction::Reboot);
+        assert!(sys.calls_to("spawn").is_empty());
+    }
+
+    #[test]
+    fn test_app_env_reused_after_restart() {
+        let sys = Recorder::with_files(&[(ENV_MANIFEST_PATH, "A=1\nB='x=y'\n")]);
+        let mut timer = Some(BootTimer::default());
+        let env = init_app_env(&sys, &Config::default(), &BTreeSet::new(), &mut timer).unwrap();
+        let expected: BTreeMap<String, String> = [("A", "1"), ("B", "x=y")]
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        assert_eq!(env, expected);
+        let steps: Vec<String> = timer
+            .unwrap()
+            .finish()
+            .steps
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(steps, ["environment", "env_manifest"]);
+
+        // A restarted supervisor doesn't read the manifest or wait for secrets
+        sys.files.borrow_mut().remove(ENV_MANIFEST_PATH);
+        sys.calls.borrow_mut().clear();
+        let env = init_app_env(&sys, &Config::default(), &BTreeSet::new(), &mut None).unwrap();
+        assert_eq!(env, expected);
+        assert!(sys.calls_to("read /env.manifest").is_empty());
+    }
+
+    #[test]
+    fn test_app_env_not_cached_after_ignored_error() {
+        let sys = Recorder::with_files(&[(ENV_MANIFEST_PATH, "NOT A VARIABLE\n")]);
+        let env = init_app_env(&sys, &Config::default(), &BTreeSet::new(), &mut None).unwrap();
+        assert!(env.is_empty());
+        assert!(sys.calls_to("write_private").is_empty());
+
+        // A restarted supervisor reads the fixed manifest
+        sys.files
+            .borrow_mut()
+            .insert(ENV_MANIFEST_PATH.to_string(), "A=1\n".to_string());
+        let env = init_app_env(&sys, &Config::default(), &BTreeSet::new(), &mut None).unwrap();
+        assert_eq!(env.get("A").map(String::as_str), Some("1"));
+        assert_eq!(
+            sys.calls_to("write_private"),
+            [format!("write_private {}", APP_ENV_PATH)]
+        );
+    }
+
+    #[test]
+    fn test_identity_env() {
+        let sys = Recorder::default();
+        init_identity_env(&sys, &Config::parse("nautilus.instance=weather-1")).unwrap();
+        assert_eq!(
+            sys.calls_to("setenv"),
+            [
+                "setenv NAUTILUS_CID=16".to_string(),
+                "setenv NAUTILUS_INSTANCE=weather-1".to_string(),
+                format!("setenv NAUTILUS_INIT_VERSION={}", env!("CARGO_PKG_VERSION")),
+            ]
+        );
+
+        // Without /dev/vsock or an instance name those are left unset
+        let mut sys = Recorder::default();
+        sys.failing.insert("/dev/vsock".to_string());
+        init_identity_env(&sys, &Config::default()).unwrap();
+        assert_eq!(
+            sys.calls_to("setenv"),
+            [format!(
+                "setenv NAUTILUS_INIT_VERSION={}",
+                env!("CARGO_PKG_VERSION")
+            )]
+        );
+
+        let sys = Recorder::default();
+        assert!(init_identity_env(&sys, &Config::parse("nautilus.instance=a/b")).is_err());
+    }
+
+    // Run the application with `exits` as the codes of successive runs
+    fn run_app(exits: &[i32], restart_budget: u32) -> (EndAction, Recorder) {
+        let sys = Recorder::default();
+        sys.exits.borrow_mut().extend(exits);
+        let policy = ExitPolicy {
+            restart_budget,
+            ..ExitPolicy::default()
+        };
+        let ring = Arc::new(Mutex::new(Ring::new(0)));
+        let app = Application {
+            exec: &AppExec::Script,
+            env: &BTreeMap::new(),
+            confinement: Confinement {
+                cgroup: None,
+                limits: &[],
+                caps: CapSet::default(),
+                filter: None,
+            },
+            watchdog: None,
+            policy: &policy,
+            ring: &ring,
+            boot_timer: Cell::new(Some(BootTimer::default())),
+        };
+        (app.run(&sys), sys)
+    }
+
+    #[test]
+    fn test_app_shutdown_decisions() {
+        // A clean exit powers off
+        let (action, sys) = run_app(&[0], 0);
+        assert_eq!(action, EndAction::Poweroff);
+        assert_eq!(sys.calls_to("spawn").len(), 1);
+        let timings = sys.files.borrow()[boottime::BOOT_TIMINGS_PATH].clone();
+        assert!(timings.contains(r#"{"name":"app_spawn","ms":"#));
+
+        // Failures restart until the budget is used up, then reboot
+        let (action, sys) = run_app(&[1, 1, 0], 2);
+        assert_eq!(action, EndAction::Poweroff);
+        assert_eq!(sys.calls_to("spawn").len(), 3);
+        let (action, sys) = run_app(&[1, 1, 1, 0], 2);
+        assert_eq!(action, EndAction::Reboot);
+        assert_eq!(sys.calls_to("spawn").len(), 3);
+        // Timings are only written for the first spawn
+        assert_eq!(sys.calls_to("write").len(), 1);
+
+        // The operator's shutdown code powers off despite the budget
+        let (action, _) = run_app(&[42], 2);
+        assert_eq!(action, EndAction::Poweroff);
+    }
+
+    #[test]
+    fn test_app_spawn_failure() {
+        // Each run retries once without captured output before counting as a failure
+        let (action, sys) = run_app(&[], 1);
+        assert_eq!(action, EndAction::Reboot);
+        assert_eq!(sys.calls_to("spawn"), ["spawn /sh"; 4]);
+        assert!(sys.calls_to("write").is_empty());
+    }
+}
@@ -0,0 +1,158 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fs::OpenOptions;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use system::{dmesg, SystemError};
+
+// Kernel tunables, set from `sysctl.<key>=<value>` cmdline options once /proc is
+// mounted, e.g. `sysctl.net.core.somaxconn=4096` writes 4096 to
+// /proc/sys/net/core/somaxconn. Values can't hold whitespace on the cmdline,
+// so commas are written as spaces, e.g.
+// `sysctl.net.ipv4.ip_local_port_range=32768,60999`. Keys the kernel doesn't
+// know and failed writes are logged and skipped, or fatal with
+// `nautilus.sysctl_strict=1`.
+
+pub const PROC_SYS: &str = "/proc/sys";
+
+// Path of a key under `root`, or None if the key isn't made of plain
+// dot or slash separated names
+pub fn key_path(root: &Path, key: &str) -> Option<PathBuf> {
+    let mut path = root.to_path_buf();
+    for name in key.split(['.', '/']) {
+        if name.is_empty()
+            || !name
+                .bytes()
+                .all(|b| b == b'_' || b == b'-' || b.is_ascii_alphanumeric())
+        {
+            return None;
+        }
+        path.push(name);
+    }
+    Some(path)
+}
+
+fn write(root: &Path, key: &str, value: &str) -> Result<(), SystemError> {
+    let path = key_path(root, key).ok_or_else(|| SystemError {
+        message: format!("Invalid sysctl key: {}", key),
+    })?;
+    // Never create files, a missing one is a key this kernel doesn't have
+    let mut file = OpenOptions::new()
+        .write(true)
+        .truncate(true)
+        .open(&path)
+        .map_err(|e| SystemError {
+            message: match e.kind() {
+                ErrorKind::NotFound => format!("Unknown sysctl key: {}", key),
+                _ => format!("Failed to open {}: {}", path.display(), e),
+            },
+        })?;
+    file.write_all(value.replace(',', " ").as_bytes())
+        .map_err(|e| SystemError {
+            message: format!("Failed to set {} to {}: {}", key, value, e),
+        })
+}
+
+// Write each setting under `root` in order, logging every outcome. A failure
+// stops at that setting with `strict`, and is skipped otherwise.
+pub fn apply<'a>(
+    root: &Path,
+    settings: impl IntoIterator<Item = (&'a str, &'a str)>,
+    strict: bool,
+) -> Result<(), SystemError> {
+    for (key, value) in settings {
+        match write(root, key, value) {
+            Ok(()) => dmesg(format!("Set sysctl {} = {}", key, value)),
+            Err(e) if strict => return Err(e),
+            Err(e) => {
+                eprintln!("{}", e);
+                dmesg(format!("WARNING: skipping sysctl {}", key));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn proc_sys(name: &str) -> TempDir {
+        let dir = TempDir::new(name);
+        for key in [
+            "net/core/somaxconn",
+            "vm/overcommit_memory",
+            "net/ipv4/ip_local_port_range",
+        ] {
+            let path = dir.path().join(key);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "0\n").unwrap();
+        }
+        dir
+    }
+
+    fn read(dir: &TempDir, key: &str) -> String {
+        std::fs::read_to_string(dir.path().join(key)).unwrap()
+    }
+
+    #[test]
+    fn test_key_path() {
+        let root = Path::new("/proc/sys");
+        assert_eq!(
+            key_path(root, "net.core.somaxconn"),
+            Some(PathBuf::from("/proc/sys/net/core/somaxconn"))
+        );
+        assert_eq!(
+            key_path(root, "net/ipv4/tcp_fin_timeout"),
+            Some(PathBuf::from("/proc/sys/net/ipv4/tcp_fin_timeout"))
+        );
+        assert_eq!(
+            key_path(root, "net.ipv4.conf.eth-0.forwarding"),
+            Some(PathBuf::from("/proc/sys/net/ipv4/conf/eth-0/forwarding"))
+        );
+        for key in [
+            "",
+            "vm..overcommit_memory",
+            "..",
+            "/vm",
+            "vm.",
+            "net.core.*",
+        ] {
+            assert_eq!(key_path(root, key), None, "{}", key);
+        }
+    }
+
+    #[test]
+    fn test_apply() {
+        let dir = proc_sys("sysctl-apply");
+        let settings = [
+            ("net.core.somaxconn", "4096"),
+            ("net.core.no_such_key", "1"),
+            ("vm..overcommit_memory", "1"),
+            ("vm.overcommit_memory", "1"),
+            ("net.ipv4.ip_local_port_range", "32768,60999"),
+        ];
+        apply(dir.path(), settings, false).unwrap();
+        assert_eq!(read(&dir, "net/core/somaxconn"), "4096");
+        assert_eq!(read(&dir, "vm/overcommit_memory"), "1");
+        assert_eq!(read(&dir, "net/ipv4/ip_local_port_range"), "32768 60999");
+        assert!(!dir.path().join("net/core/no_such_key").exists());
+    }
+
+    #[test]
+    fn test_apply_strict() {
+        let dir = proc_sys("sysctl-strict");
+        let settings = [
+            ("net.core.somaxconn", "4096"),
+            ("net.core.no_such_key", "1"),
+            ("vm.overcommit_memory", "1"),
+        ];
+        let err = apply(dir.path(), settings, true).unwrap_err();
+        assert_eq!(err.message, "Unknown sysctl key: net.core.no_such_key");
+        // Settings before the failure were written, later ones were not
+        assert_eq!(read(&dir, "net/core/somaxconn"), "4096");
+        assert_eq!(read(&dir, "vm/overcommit_memory"), "0\n");
+    }
+}
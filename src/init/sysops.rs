@@ -0,0 +1,322 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use libc::{c_int, c_ulong};
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::process::{Child, Command};
+use system::SystemError;
+
+// Filesystem, process and power operations made by the boot steps and the
+// application loop, so their order and failure handling can be tested
+// without a kernel to boot. `Kernel` makes the real calls.
+pub trait SysOps {
+    fn mount(
+        &self,
+        src: &str,
+        target: &str,
+        fstype: &str,
+        flags: c_ulong,
+        data: &str,
+    ) -> Result<(), SystemError>;
+    fn exists(&self, path: &str) -> bool;
+    fn is_executable(&self, path: &str) -> bool;
+    fn create_dir_all(&self, path: &str) -> io::Result<()>;
+    fn read_file(&self, path: &str) -> io::Result<String>;
+    fn write_file(&self, path: &str, contents: &str) -> io::Result<()>;
+    // Replace `path` with `contents` atomically, readable only by its owner
+    fn write_private(&self, path: &str, contents: &str) -> io::Result<()>;
+    fn freopen(&self, filename: &str, mode: &str, fd: c_int) -> Result<(), SystemError>;
+    fn sethostname(&self, name: &str) -> Result<(), SystemError>;
+    fn set_env(&self, key: &str, value: &str);
+    fn init_platform(&self);
+    fn seed_entropy(&self, size: usize) -> Result<usize, SystemError>;
+    fn local_cid(&self) -> Result<u32, SystemError>;
+    fn spawn(&self, command: Command) -> io::Result<Child>;
+    fn reboot(&self);
+    fn poweroff(&self);
+}
+
+pub struct Kernel;
+
+impl SysOps for Kernel {
+    fn mount(
+        &self,
+        src: &str,
+        target: &str,
+        fstype: &str,
+        flags: c_ulong,
+        data: &str,
+    ) -> Result<(), SystemError> {
+        system::mount(src, target, fstype, flags, data)
+    }
+
+    fn exists(&self, path: &str) -> bool {
+        std::fs::exists(path).unwrap_or(false)
+    }
+
+    fn is_executable(&self, path: &str) -> bool {
+        std::fs::metadata(path)
+            .map(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+            .unwrap_or(false)
+    }
+
+    fn create_dir_all(&self, path: &str) -> io::Result<()> {
+        std::fs::create_dir_all(path)
+    }
+
+    fn read_file(&self, path: &str) -> io::Result<String> {
+        std::fs::read_to_string(path)
+    }
+
+    fn write_file(&self, path: &str, contents: &str) -> io::Result<()> {
+        std::fs::write(path, contents)
+    }
+
+    fn write_private(&self, path: &str, contents: &str) -> io::Result<()> {
+        use std::io::Write;
+        use std::os::unix::fs::OpenOptionsExt;
+        // Created afresh, so a copy left by a writer that died can't lend it
+        // other permissions, then renamed so readers never see it partial
+        let tmp = format!("{}.tmp", path);
+        let _ = std::fs::remove_file(&tmp);
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&tmp)?;
+        file.write_all(contents.as_bytes())?;
+        std::fs::rename(&tmp, path)
+    }
+
+    fn freopen(&self, filename: &str, mode: &str, fd: c_int) -> Result<(), SystemError> {
+        system::freopen(filename, mode, fd)
+    }
+
+    fn sethostname(&self, name: &str) -> Result<(), SystemError> {
+        system::sethostname(name)
+    }
+
+    fn set_env(&self, key: &str, value: &str) {
+        std::env::set_var(key, value);
+    }
+
+    fn init_platform(&self) {
+        aws::init_platform();
+    }
+
+    fn seed_entropy(&self, size: usize) -> Result<usize, SystemError> {
+        system::seed_entropy(size, aws::get_entropy)
+    }
+
+    fn local_cid(&self) -> Result<u32, SystemError> {
+        system::local_cid()
+    }
+
+    fn spawn(&self, mut command: Command) -> io::Result<Child> {
+        command.spawn()
+    }
+
+    fn reboot(&self) {
+        system::reboot();
+    }
+
+    fn poweroff(&self) {
+        system::poweroff();
+    }
+}
+
+// Records every operation in order, backed by an in-memory filesystem.
+// Operations on paths in `failing` fail, only paths in `executable` are
+// executable, and each spawn runs a shell exiting with the next code from
+// `exits`, failing once they run out.
+#[cfg(test)]
+pub mod fake {
+    use super::*;
+    use std::cell::RefCell;
+    use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+    #[derive(Default)]
+    pub struct Recorder {
+        pub calls: RefCell<Vec<String>>,
+        pub files: RefCell<BTreeMap<String, String>>,
+        pub failing: BTreeSet<String>,
+        pub executable: BTreeSet<String>,
+        pub exits: RefCell<VecDeque<i32>>,
+    }
+
+    impl Recorder {
+        pub fn with_files(files: &[(&str, &str)]) -> Self {
+            Self {
+                files: RefCell::new(
+                    files
+                        .iter()
+                        .map(|(path, contents)| (path.to_string(), contents.to_string()))
+                        .collect(),
+                ),
+                ..Self::default()
+            }
+        }
+
+        // Recorded calls starting with `prefix`
+        pub fn calls_to(&self, prefix: &str) -> Vec<String> {
+            self.calls
+                .borrow()
+                .iter()
+                .filter(|call| call.starts_with(prefix))
+                .cloned()
+                .collect()
+        }
+
+        fn record(&self, call: String, path: &str) -> Result<(), SystemError> {
+            self.calls.borrow_mut().push(call);
+            if self.failing.contains(path) {
+                return Err(SystemError {
+                    message: format!("Failed on {}", path),
+                });
+            }
+            Ok(())
+        }
+    }
+
+    fn io_err(e: SystemError) -> io::Error {
+        io::Error::other(e.message)
+    }
+
+    impl SysOps for Recorder {
+        fn mount(
+            &self,
+            src: &str,
+            target: &str,
+            fstype: &str,
+            flags: c_ulong,
+            data: &str,
+        ) -> Result<(), SystemError> {
+            self.record(
+                format!("mount {} {} {} {:#x} {}", src, target, fstype, flags, data),
+                target,
+            )
+        }
+
+        fn exists(&self, path: &str) -> bool {
+            self.files.borrow().contains_key(path)
+        }
+
+        fn is_executable(&self, path: &str) -> bool {
+            self.exists(path) && self.executable.contains(path)
+        }
+
+        fn create_dir_all(&self, path: &str) -> io::Result<()> {
+            self.record(format!("mkdir {}", path), path).map_err(io_err)
+        }
+
+        fn read_file(&self, path: &str) -> io::Result<String> {
+            self.record(format!("read {}", path), path)
+                .map_err(io_err)?;
+            self.files
+                .borrow()
+                .get(path)
+                .cloned()
+                .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))
+        }
+
+        fn write_file(&self, path: &str, contents: &str) -> io::Result<()> {
+            self.record(format!("write {}", path), path)
+                .map_err(io_err)?;
+            self.files
+                .borrow_mut()
+                .insert(path.to_string(), contents.to_string());
+            Ok(())
+        }
+
+        fn write_private(&self, path: &str, contents: &str) -> io::Result<()> {
+            self.record(format!("write_private {}", path), path)
+                .map_err(io_err)?;
+            self.files
+                .borrow_mut()
+                .insert(path.to_string(), contents.to_string());
+            Ok(())
+        }
+
+        fn freopen(&self, filename: &str, mode: &str, fd: c_int) -> Result<(), SystemError> {
+            let path = format!("{}:{}", filename, fd);
+            self.record(format!("freopen {} {} {}", filename, mode, fd), &path)
+        }
+
+        fn sethostname(&self, name: &str) -> Result<(), SystemError> {
+            self.record(format!("sethostname {}", name), name)
+        }
+
+        fn set_env(&self, key: &str, value: &str) {
+            self.calls
+                .borrow_mut()
+                .push(format!("setenv {}={}", key, value));
+        }
+
+        fn init_platform(&self) {
+            self.calls.borrow_mut().push("init_platform".to_string());
+        }
+
+        fn seed_entropy(&self, size: usize) -> Result<usize, SystemError> {
+            self.record(format!("seed_entropy {}", size), "/dev/urandom")?;
+            Ok(size)
+        }
+
+        fn local_cid(&self) -> Result<u32, SystemError> {
+            self.record("local_cid".to_string(), "/dev/vsock")?;
+            Ok(16)
+        }
+
+        fn spawn(&self, command: Command) -> io::Result<Child> {
+            self.calls
+                .borrow_mut()
+                .push(format!("spawn {}", command.get_program().to_string_lossy()));
+            let code = self
+                .exits
+                .borrow_mut()
+                .pop_front()
+                .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+            Command::new("sh")
+                .arg("-c")
+                .arg(format!("exit {}", code))
+                .spawn()
+        }
+
+        fn reboot(&self) {
+            self.calls.borrow_mut().push("reboot".to_string());
+        }
+
+        fn poweroff(&self) {
+            self.calls.borrow_mut().push("poweroff".to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn test_write_private() {
+        let dir = TempDir::new("write-private");
+        let path = dir.path().join("app.env");
+        let path = path.to_str().unwrap();
+        // A copy left by a writer that died, readable by anyone
+        std::fs::write(format!("{}.tmp", path), "A=0\0").unwrap();
+        std::fs::set_permissions(
+            format!("{}.tmp", path),
+            std::fs::Permissions::from_mode(0o644),
+        )
+        .unwrap();
+
+        Kernel.write_private(path, "A=1\0").unwrap();
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "A=1\0");
+        let mode = std::fs::metadata(path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert!(!std::path::Path::new(&format!("{}.tmp", path)).exists());
+
+        Kernel.write_private(path, "A=2\0").unwrap();
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "A=2\0");
+    }
+}
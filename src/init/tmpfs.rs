@@ -0,0 +1,178 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::config::Config;
+use system::SystemError;
+
+// Size-capped tmpfs mounts. Left to the kernel default each tmpfs may grow to
+// half of RAM, so a runaway writer to /tmp could take most of a 512 MB
+// enclave. They are mounted once the cmdline config has been read, and each
+// accepts, under `nautilus.tmpfs.<name>` with name `tmp`, `run` or `shm`:
+// - `.size`: `<n>[k|m|g]` bytes or `<n>%` of RAM, e.g. `nautilus.tmpfs.tmp.size=64m`
+// - `.mode`: octal permissions of the mount's root, e.g. `1777`
+// - `.options`: comma-separated extras among `exec` (allow executing files),
+//   `noatime` and `nr_inodes=<n>`
+// All are mounted nodev and nosuid, and noexec unless `exec` is given. /run
+// holds the generated /etc files and /tmp would otherwise be on the read-only
+// root, so failing to mount either stops boot. Without /dev/shm only POSIX
+// shared memory is missing, which is a warning.
+
+// A tmpfs mount and its defaults
+pub struct Mount {
+    pub name: &'static str,
+    pub source: &'static str,
+    pub target: &'static str,
+    size: &'static str,
+    mode: &'static str,
+    pub required: bool,
+}
+
+pub const MOUNTS: [Mount; 3] = [
+    Mount {
+        name: "shm",
+        source: "shm",
+        target: "/dev/shm",
+        size: "128m",
+        mode: "0755",
+        required: false,
+    },
+    Mount {
+        name: "run",
+        source: "tmpfs",
+        target: "/run",
+        size: "16m",
+        mode: "0755",
+        required: true,
+    },
+    Mount {
+        name: "tmp",
+        source: "tmpfs",
+        target: "/tmp",
+        size: "64m",
+        mode: "1777",
+        required: true,
+    },
+];
+
+// Arguments for mount(2)
+#[derive(Debug, PartialEq)]
+pub struct Options {
+    pub flags: libc::c_ulong,
+    pub data: String,
+}
+
+impl Mount {
+    // Mount options from the config, or an error naming the invalid key
+    pub fn options(&self, config: &Config) -> Result<Options, SystemError> {
+        let key = |option: &str| format!("nautilus.tmpfs.{}.{}", self.name, option);
+        let invalid = |key: String, value: &str| SystemError {
+            message: format!("Invalid {}: {}", key, value),
+        };
+
+        let size_key = key("size");
+        let size = config.get(&size_key).unwrap_or(self.size);
+        if !valid_size(size) {
+            return Err(invalid(size_key, size));
+        }
+        let mode_key = key("mode");
+        let mode = config.get(&mode_key).unwrap_or(self.mode);
+        if !(3..=4).contains(&mode.len()) || !mode.bytes().all(|b| (b'0'..=b'7').contains(&b)) {
+            return Err(invalid(mode_key, mode));
+        }
+
+        let mut flags = libc::MS_NODEV | libc::MS_NOSUID | libc::MS_NOEXEC;
+        let mut data = format!("size={},mode={}", size, mode);
+        let options_key = key("options");
+        for option in config
+            .get(&options_key)
+            .unwrap_or("")
+            .split(',')
+            .filter(|o| !o.is_empty())
+        {
+            match option.split_once('=') {
+                None if option == "exec" => flags &= !libc::MS_NOEXEC,
+                None if option == "noatime" => flags |= libc::MS_NOATIME,
+                Some(("nr_inodes", n))
+                    if !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()) =>
+                {
+                    data.push_str(&format!(",nr_inodes={}", n))
+                }
+                _ => return Err(invalid(options_key, option)),
+            }
+        }
+        Ok(Options { flags, data })
+    }
+}
+
+fn valid_size(size: &str) -> bool {
+    let digits = size
+        .strip_suffix(['k', 'm', 'g', 'K', 'M', 'G', '%'])
+        .unwrap_or(size);
+    !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) && digits != "0"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(name: &str, cmdline: &str) -> Result<Options, SystemError> {
+        let mount = MOUNTS.iter().find(|m| m.name == name).unwrap();
+        mount.options(&Config::parse(cmdline))
+    }
+
+    #[test]
+    fn test_defaults() {
+        let base = libc::MS_NODEV | libc::MS_NOSUID | libc::MS_NOEXEC;
+        for (name, data) in [
+            ("tmp", "size=64m,mode=1777"),
+            ("run", "size=16m,mode=0755"),
+            ("shm", "size=128m,mode=0755"),
+        ] {
+            assert_eq!(
+                options(name, "").unwrap(),
+                Options {
+                    flags: base,
+                    data: data.to_string()
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn test_configured_options() {
+        let tmp = options(
+            "tmp",
+            "nautilus.tmpfs.tmp.size=10% nautilus.tmpfs.tmp.mode=700 \
+             nautilus.tmpfs.tmp.options=exec,noatime,nr_inodes=4096 nautilus.tmpfs.run.size=1m",
+        )
+        .unwrap();
+        assert_eq!(tmp.data, "size=10%,mode=700,nr_inodes=4096");
+        assert_eq!(
+            tmp.flags,
+            libc::MS_NODEV | libc::MS_NOSUID | libc::MS_NOATIME
+        );
+    }
+
+    #[test]
+    fn test_invalid_options() {
+        for cmdline in [
+            "nautilus.tmpfs.tmp.size=64mb",
+            "nautilus.tmpfs.tmp.size=0",
+            "nautilus.tmpfs.tmp.size=m",
+            "nautilus.tmpfs.tmp.mode=0999",
+            "nautilus.tmpfs.tmp.mode=17777",
+            "nautilus.tmpfs.tmp.options=suid",
+            "nautilus.tmpfs.tmp.options=nr_inodes=",
+            "nautilus.tmpfs.tmp.options=size=1g",
+        ] {
+            match options("tmp", cmdline) {
+                Ok(_) => panic!("{} should be rejected", cmdline),
+                Err(e) => assert!(
+                    e.message.starts_with("Invalid nautilus.tmpfs.tmp."),
+                    "{}",
+                    e
+                ),
+            }
+        }
+    }
+}
@@ -0,0 +1,411 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::config::Config;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::path::PathBuf;
+use std::process::{Child, ExitStatus};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use system::{dmesg, SystemError};
+
+// Liveness watchdog for the application, enabled with `nautilus.watchdog`.
+// Every `nautilus.watchdog.interval_secs` it probes the server with an HTTP GET
+// on `nautilus.watchdog.port`, or, if `nautilus.watchdog.heartbeat=<path>` is
+// set, checks that the server touched that file within the interval. After
+// `nautilus.watchdog.failures` consecutive failures it takes
+// `nautilus.watchdog.action`: `restart` the application or `reboot` the enclave.
+// Probing starts after `nautilus.watchdog.grace_secs` to let the server start.
+
+// How often the child is polled for exit between probes
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// How long a restarted application gets to exit after SIGTERM
+pub const TERM_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Probe {
+    Http { port: u16 },
+    Heartbeat { path: PathBuf },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    Restart,
+    Reboot,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct WatchdogConfig {
+    pub interval: Duration,
+    pub grace: Duration,
+    pub failures: u32,
+    pub action: Action,
+    pub probe: Probe,
+}
+
+fn parse<T: std::str::FromStr>(config: &Config, key: &str, default: T) -> Result<T, SystemError> {
+    match config.get(key) {
+        None => Ok(default),
+        Some(value) => value.parse().map_err(|_| SystemError {
+            message: format!("Invalid {}: {}", key, value),
+        }),
+    }
+}
+
+impl WatchdogConfig {
+    // Watchdog settings, or None if the watchdog is disabled
+    pub fn from_config(config: &Config) -> Result<Option<Self>, SystemError> {
+        if !config.get_bool("nautilus.watchdog", false)? {
+            return Ok(None);
+        }
+        let action = match config.get("nautilus.watchdog.action") {
+            None | Some("restart") => Action::Restart,
+            Some("reboot") => Action::Reboot,
+            Some(value) => {
+                return Err(SystemError {
+                    message: format!("Invalid nautilus.watchdog.action: {}", value),
+                })
+            }
+        };
+        let probe = match config.get("nautilus.watchdog.heartbeat") {
+            Some(path) => Probe::Heartbeat {
+                path: PathBuf::from(path),
+            },
+            None => Probe::Http {
+                port: parse(config, "nautilus.watchdog.port", 3000)?,
+            },
+        };
+        let failures = parse(config, "nautilus.watchdog.failures", 3)?;
+        let interval = parse(config, "nautilus.watchdog.interval_secs", 10)?;
+        if failures == 0 || interval == 0 {
+            return Err(SystemError {
+                message: "nautilus.watchdog.failures and interval_secs must be positive"
+                    .to_string(),
+            });
+        }
+        Ok(Some(Self {
+            interval: Duration::from_secs(interval),
+            grace: Duration::from_secs(parse(config, "nautilus.watchdog.grace_secs", 30)?),
+            failures,
+            action,
+            probe,
+        }))
+    }
+}
+
+// Liveness check of the application
+pub trait Prober {
+    fn probe(&mut self) -> Result<(), String>;
+}
+
+// Expects a 2xx response to `GET /` on a local port
+pub struct HttpProber {
+    addr: SocketAddr,
+    timeout: Duration,
+}
+
+impl HttpProber {
+    pub fn new(port: u16, timeout: Duration) -> Self {
+        Self {
+            addr: SocketAddr::from(([127, 0, 0, 1], port)),
+            timeout,
+        }
+    }
+}
+
+impl Prober for HttpProber {
+    fn probe(&mut self) -> Result<(), String> {
+        let mut stream =
+            TcpStream::connect_timeout(&self.addr, self.timeout).map_err(|e| e.to_string())?;
+        stream
+            .set_read_timeout(Some(self.timeout))
+            .and_then(|()| stream.set_write_timeout(Some(self.timeout)))
+            .and_then(|()| stream.write_all(b"GET / HTTP/1.0\r\nHost: localhost\r\n\r\n"))
+            .map_err(|e| e.to_string())?;
+        let mut status = [0u8; 12];
+        stream.read_exact(&mut status).map_err(|e| e.to_string())?;
+        // "HTTP/1.1 200"
+        match status.get(9) {
+            Some(b'2') if status.starts_with(b"HTTP/") => Ok(()),
+            _ => Err(format!(
+                "unexpected response {:?}",
+                String::from_utf8_lossy(&status)
+            )),
+        }
+    }
+}
+
+// Expects the file at `path` to have been modified within `max_age`
+pub struct HeartbeatProber {
+    path: PathBuf,
+    max_age: Duration,
+}
+
+impl HeartbeatProber {
+    pub fn new(path: PathBuf, max_age: Duration) -> Self {
+        Self { path, max_age }
+    }
+}
+
+impl Prober for HeartbeatProber {
+    fn probe(&mut self) -> Result<(), String> {
+        let modified = std::fs::metadata(&self.path)
+            .and_then(|metadata| metadata.modified())
+            .map_err(|e| format!("{}: {}", self.path.display(), e))?;
+        let age = SystemTime::now()
+            .duration_since(modified)
+            .unwrap_or(Duration::ZERO);
+        if age <= self.max_age {
+            Ok(())
+        } else {
+            Err(format!("heartbeat is {}s old", age.as_secs()))
+        }
+    }
+}
+
+pub fn prober(probe: &Probe, interval: Duration) -> Box<dyn Prober> {
+    match probe {
+        Probe::Http { port } => Box::new(HttpProber::new(*port, interval)),
+        Probe::Heartbeat { path } => Box::new(HeartbeatProber::new(path.clone(), interval)),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Decision {
+    Healthy,
+    Failing(u32),
+    Act(Action),
+}
+
+// Counts consecutive probe failures, logging state transitions
+pub struct Watchdog {
+    failures: u32,
+    threshold: u32,
+    action: Action,
+}
+
+impl Watchdog {
+    pub fn new(config: &WatchdogConfig) -> Self {
+        Self {
+            failures: 0,
+            threshold: config.failures,
+            action: config.action,
+        }
+    }
+
+    // Probe once and decide what to do
+    pub fn check(&mut self, prober: &mut dyn Prober) -> Decision {
+        match prober.probe() {
+            Ok(()) => {
+                if self.failures > 0 {
+                    dmesg(format!(
+                        "Watchdog: application recovered after {} failed probe(s)",
+                        self.failures
+                    ));
+                }
+                self.failures = 0;
+                Decision::Healthy
+            }
+            Err(e) => {
+                self.failures += 1;
+                dmesg(format!(
+                    "Watchdog: probe failed ({}/{}): {}",
+                    self.failures, self.threshold, e
+                ));
+                if self.failures >= self.threshold {
+                    dmesg(format!(
+                        "Watchdog: application hung, action {:?}",
+                        self.action
+                    ));
+                    self.failures = 0;
+                    Decision::Act(self.action)
+                } else {
+                    Decision::Failing(self.failures)
+                }
+            }
+        }
+    }
+}
+
+pub enum Outcome {
+    Exited(std::io::Result<ExitStatus>),
+    Hung(Action),
+}
+
+// Sleep for `duration`, returning early with the status if the child exits
+fn wait_for(child: &mut Child, duration: Duration) -> Option<std::io::Result<ExitStatus>> {
+    let deadline = Instant::now() + duration;
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) => return Some(Ok(status)),
+            Ok(None) => {}
+            Err(e) => return Some(Err(e)),
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return None;
+        }
+        thread::sleep(POLL_INTERVAL.min(deadline - now));
+    }
+}
+
+// Wait for the child to exit, probing it until the watchdog decides to act
+pub fn supervise(child: &mut Child, config: &WatchdogConfig) -> Outcome {
+    let mut prober = prober(&config.probe, config.interval);
+    let mut watchdog = Watchdog::new(config);
+    if let Some(status) = wait_for(child, config.grace) {
+        return Outcome::Exited(status);
+    }
+    loop {
+        if let Decision::Act(action) = watchdog.check(prober.as_mut()) {
+            return Outcome::Hung(action);
+        }
+        if let Some(status) = wait_for(child, config.interval) {
+            return Outcome::Exited(status);
+        }
+    }
+}
+
+// Send SIGTERM to the child's process group, then SIGKILL if it hasn't exited
+// within `timeout`. The child must have been started in its own session.
+pub fn terminate(child: &mut Child, timeout: Duration) {
+    use libc::{kill, SIGKILL, SIGTERM};
+    let pgid = child.id() as libc::pid_t;
+    unsafe {
+        kill(-pgid, SIGTERM);
+    }
+    if wait_for(child, timeout).is_none() {
+        dmesg("Watchdog: application ignored SIGTERM, killing it".to_string());
+        unsafe {
+            kill(-pgid, SIGKILL);
+        }
+        let _ = child.wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use std::collections::VecDeque;
+    use std::net::TcpListener;
+
+    struct FakeProber(VecDeque<bool>);
+
+    impl Prober for FakeProber {
+        fn probe(&mut self) -> Result<(), String> {
+            match self.0.pop_front() {
+                Some(true) => Ok(()),
+                _ => Err("no response".to_string()),
+            }
+        }
+    }
+
+    fn config(failures: u32, action: Action) -> WatchdogConfig {
+        WatchdogConfig {
+            interval: Duration::from_secs(1),
+            grace: Duration::ZERO,
+            failures,
+            action,
+            probe: Probe::Http { port: 3000 },
+        }
+    }
+
+    #[test]
+    fn test_acts_after_consecutive_failures() {
+        let mut prober = FakeProber(VecDeque::from([true, false, false, false]));
+        let mut watchdog = Watchdog::new(&config(3, Action::Reboot));
+        let decisions: Vec<Decision> = (0..4).map(|_| watchdog.check(&mut prober)).collect();
+        assert_eq!(
+            decisions,
+            [
+                Decision::Healthy,
+                Decision::Failing(1),
+                Decision::Failing(2),
+                Decision::Act(Action::Reboot),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_recovery_resets_failures() {
+        let mut prober = FakeProber(VecDeque::from([false, false, true, false, false, false]));
+        let mut watchdog = Watchdog::new(&config(3, Action::Restart));
+        let decisions: Vec<Decision> = (0..6).map(|_| watchdog.check(&mut prober)).collect();
+        assert_eq!(decisions[2], Decision::Healthy);
+        assert_eq!(decisions[4], Decision::Failing(2));
+        assert_eq!(decisions[5], Decision::Act(Action::Restart));
+    }
+
+    #[test]
+    fn test_from_config() {
+        assert_eq!(
+            WatchdogConfig::from_config(&Config::parse("")).unwrap(),
+            None
+        );
+        let config = WatchdogConfig::from_config(&Config::parse(
+            "nautilus.watchdog nautilus.watchdog.action=reboot nautilus.watchdog.failures=5 \
+             nautilus.watchdog.heartbeat=/run/heartbeat",
+        ))
+        .unwrap()
+        .unwrap();
+        assert_eq!(config.action, Action::Reboot);
+        assert_eq!(config.failures, 5);
+        assert_eq!(config.interval, Duration::from_secs(10));
+        assert_eq!(
+            config.probe,
+            Probe::Heartbeat {
+                path: PathBuf::from("/run/heartbeat")
+            }
+        );
+        for cmdline in [
+            "nautilus.watchdog nautilus.watchdog.action=halt",
+            "nautilus.watchdog nautilus.watchdog.failures=0",
+            "nautilus.watchdog nautilus.watchdog.port=http",
+        ] {
+            assert!(WatchdogConfig::from_config(&Config::parse(cmdline)).is_err());
+        }
+    }
+
+    fn http_server(response: &'static str) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request = [0u8; 64];
+                let _ = stream.read(&mut request);
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+        port
+    }
+
+    #[test]
+    fn test_http_prober() {
+        let timeout = Duration::from_secs(1);
+        let ok = http_server("HTTP/1.1 200 OK\r\ncontent-length: 5\r\n\r\nPong!");
+        assert!(HttpProber::new(ok, timeout).probe().is_ok());
+        let error = http_server("HTTP/1.1 500 Internal Server Error\r\n\r\n");
+        assert!(HttpProber::new(error, timeout).probe().is_err());
+        let closed = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = closed.local_addr().unwrap().port();
+        drop(closed);
+        assert!(HttpProber::new(port, timeout).probe().is_err());
+    }
+
+    #[test]
+    fn test_heartbeat_prober() {
+        let dir = TempDir::new("watchdog-heartbeat");
+        let path = dir.path().join("heartbeat");
+        let mut prober = HeartbeatProber::new(path.clone(), Duration::from_secs(60));
+        assert!(prober.probe().is_err());
+        std::fs::write(&path, "").unwrap();
+        assert!(prober.probe().is_ok());
+        let mut stale = HeartbeatProber::new(path, Duration::ZERO);
+        thread::sleep(Duration::from_millis(10));
+        assert!(stale.probe().is_err());
+    }
+}
@@ -0,0 +1,79 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::types::{ErrorResponse, TlsFailure};
+use reqwest::StatusCode;
+use std::fmt;
+
+/// Errors from [crate::NautilusClient], with the server's errors told apart
+/// by status the way the server assigns them.
+#[derive(Debug)]
+pub enum ClientError {
+    /// The base URL isn't a valid URL.
+    InvalidUrl(String),
+    /// The request failed before a response arrived, e.g. the connection
+    /// was refused.
+    Transport(reqwest::Error),
+    /// The server rejected the request (400), e.g. an invalid payload, an
+    /// upstream error or an unsupported API version.
+    BadRequest(String),
+    /// What the request asked for doesn't exist upstream (404), e.g. an
+    /// unknown location.
+    NotFound(String),
+    /// The TLS handshake between the enclave and its upstream failed (502).
+    UpstreamTls { reason: TlsFailure, message: String },
+    /// Any other error status, e.g. 404 without an error body for a route
+    /// the server doesn't serve.
+    Status { status: u16, message: String },
+    /// The response couldn't be decoded.
+    InvalidResponse(String),
+    /// No public key is pinned, so the response can't be verified.
+    NoPublicKey,
+    /// The response's signature doesn't verify against the pinned key.
+    InvalidSignature,
+}
+
+impl ClientError {
+    /// The error for an unsuccessful `status` with `body`.
+    pub(crate) fn from_response(status: StatusCode, body: &[u8]) -> Self {
+        let (message, tls_failure, from_handler) =
+            match serde_json::from_slice::<ErrorResponse>(body) {
+                Ok(response) => (response.error, response.tls_failure, true),
+                Err(_) => (String::from_utf8_lossy(body).into_owned(), None, false),
+            };
+        match (status, tls_failure) {
+            (StatusCode::BAD_REQUEST, _) => Self::BadRequest(message),
+            (StatusCode::NOT_FOUND, _) if from_handler => Self::NotFound(message),
+            (StatusCode::BAD_GATEWAY, Some(reason)) => Self::UpstreamTls { reason, message },
+            _ => Self::Status {
+                status: status.as_u16(),
+                message,
+            },
+        }
+    }
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidUrl(e) => write!(f, "Invalid base URL: {}", e),
+            Self::Transport(e) => write!(f, "Request failed: {}", e),
+            Self::BadRequest(message) => write!(f, "Rejected by the enclave: {}", message),
+            Self::NotFound(message) => write!(f, "Not found: {}", message),
+            Self::UpstreamTls { message, .. } => write!(f, "{}", message),
+            Self::Status { status, message } => write!(f, "Status {}: {}", status, message),
+            Self::InvalidResponse(e) => write!(f, "Invalid response: {}", e),
+            Self::NoPublicKey => write!(f, "No public key pinned to verify responses with"),
+            Self::InvalidSignature => write!(f, "Signature doesn't match the pinned public key"),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Transport(e) => Some(e),
+            _ => None,
+        }
+    }
+}
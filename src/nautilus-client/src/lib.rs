@@ -0,0 +1,168 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Client for the HTTP API of a Nautilus enclave. Signed responses are
+//! verified against a public key pinned by the caller, typically the one
+//! registered on chain after verifying the enclave's attestation.
+
+use fastcrypto::ed25519::{Ed25519PublicKey, Ed25519Signature};
+use fastcrypto::encoding::{Encoding, Hex};
+use fastcrypto::traits::{ToFromBytes, VerifyingKey};
+use reqwest::{RequestBuilder, StatusCode, Url};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use types::{GetAttestationResponse, ProcessDataRequest};
+
+pub use error::ClientError;
+pub use types::{
+    Attestation, HealthResponse, IntentMessage, SignedResponse, SigningFormat, TlsFailure,
+};
+
+pub mod error;
+pub mod types;
+
+/// Header declaring the API version the client was built against, so a
+/// server that no longer serves it fails the request instead of answering in
+/// another shape.
+pub const API_VERSION_HEADER: &str = "x-nautilus-api";
+
+/// API version this client implements.
+pub const API_VERSION: u32 = 1;
+
+/// Client for one enclave.
+#[derive(Debug, Clone)]
+pub struct NautilusClient {
+    base_url: String,
+    http: reqwest::Client,
+    public_key: Option<Ed25519PublicKey>,
+}
+
+impl NautilusClient {
+    /// Client for the enclave served at `base_url`, e.g.
+    /// `http://<instance>:3000`. Pin a public key with
+    /// [NautilusClient::with_public_key] before calling signing endpoints.
+    pub fn new(base_url: &str) -> Result<Self, ClientError> {
+        Url::parse(base_url).map_err(|e| ClientError::InvalidUrl(e.to_string()))?;
+        Ok(Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            http: reqwest::Client::new(),
+            public_key: None,
+        })
+    }
+
+    /// Verify signed responses against `public_key`.
+    pub fn with_public_key(mut self, public_key: Ed25519PublicKey) -> Self {
+        self.public_key = Some(public_key);
+        self
+    }
+
+    /// Send requests with `http`, e.g. to set timeouts or client
+    /// certificates.
+    pub fn with_http_client(mut self, http: reqwest::Client) -> Self {
+        self.http = http;
+        self
+    }
+
+    /// A fresh or cached attestation document from the enclave.
+    pub async fn get_attestation(&self) -> Result<Attestation, ClientError> {
+        let response: GetAttestationResponse = self.send(self.get("/get_attestation")).await?;
+        let document = Hex::decode(&response.attestation)
+            .map_err(|e| ClientError::InvalidResponse(format!("attestation: {}", e)))?;
+        Ok(Attestation {
+            document,
+            generated_at_ms: response.generated_at_ms,
+        })
+    }
+
+    /// The enclave's public key and connectivity. Returned even when the
+    /// enclave reports itself unhealthy (503), see
+    /// [HealthResponse::nsm_available].
+    pub async fn health(&self) -> Result<HealthResponse, ClientError> {
+        let (status, body) = self.send_raw(self.get("/health_check")).await?;
+        match status {
+            StatusCode::OK | StatusCode::SERVICE_UNAVAILABLE => decode(&body),
+            _ => Err(ClientError::from_response(status, &body)),
+        }
+    }
+
+    /// Send `payload` to `/process_data`, signed under the endpoint's default
+    /// intent scope, and return the response once its signature is verified.
+    pub async fn process_data<T, R>(&self, payload: &T) -> Result<SignedResponse<R>, ClientError>
+    where
+        T: Serialize,
+        R: Serialize + DeserializeOwned,
+    {
+        self.process_data_with_intent(payload, None).await
+    }
+
+    /// [NautilusClient::process_data] signed under `intent`, which the
+    /// enclave must have registered.
+    pub async fn process_data_with_intent<T, R>(
+        &self,
+        payload: &T,
+        intent: Option<u8>,
+    ) -> Result<SignedResponse<R>, ClientError>
+    where
+        T: Serialize,
+        R: Serialize + DeserializeOwned,
+    {
+        let public_key = self.public_key.as_ref().ok_or(ClientError::NoPublicKey)?;
+        let request = ProcessDataRequest { payload, intent };
+        let response: SignedResponse<R> = self
+            .send(self.http.post(self.url("/process_data")).json(&request))
+            .await?;
+        verify(public_key, &response)?;
+        Ok(response)
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    fn get(&self, path: &str) -> RequestBuilder {
+        self.http.get(self.url(path))
+    }
+
+    async fn send<R: DeserializeOwned>(&self, request: RequestBuilder) -> Result<R, ClientError> {
+        let (status, body) = self.send_raw(request).await?;
+        if !status.is_success() {
+            return Err(ClientError::from_response(status, &body));
+        }
+        decode(&body)
+    }
+
+    async fn send_raw(
+        &self,
+        request: RequestBuilder,
+    ) -> Result<(StatusCode, Vec<u8>), ClientError> {
+        let response = request
+            .header(API_VERSION_HEADER, API_VERSION)
+            .send()
+            .await
+            .map_err(ClientError::Transport)?;
+        let status = response.status();
+        let body = response.bytes().await.map_err(ClientError::Transport)?;
+        Ok((status, body.to_vec()))
+    }
+}
+
+fn decode<R: DeserializeOwned>(body: &[u8]) -> Result<R, ClientError> {
+    serde_json::from_slice(body).map_err(|e| ClientError::InvalidResponse(e.to_string()))
+}
+
+/// Check the signature of `response` over its intent message serialized in
+/// its `signing_format`, the bytes the enclave signed.
+pub fn verify<R: Serialize>(
+    public_key: &Ed25519PublicKey,
+    response: &SignedResponse<R>,
+) -> Result<(), ClientError> {
+    let signed = nautilus_verifier::signing_bytes(response.signing_format, &response.response)
+        .map_err(|e| ClientError::InvalidResponse(e.to_string()))?;
+    let signature = Hex::decode(&response.signature)
+        .ok()
+        .and_then(|bytes| Ed25519Signature::from_bytes(&bytes).ok())
+        .ok_or(ClientError::InvalidSignature)?;
+    public_key
+        .verify(&signed, &signature)
+        .map_err(|_| ClientError::InvalidSignature)
+}
@@ -0,0 +1,117 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+pub use nautilus_verifier::SigningFormat;
+
+// These mirror the server's wire types in nautilus-server's common.rs, which
+// can't be shared since that crate needs the NSM. Field order and the skipped
+// fields matter: they define the BCS bytes the enclave signs.
+
+/// A message signed by the enclave, see `IntentMessage` in the server.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IntentMessage<T> {
+    /// Intent scope the message was signed under, as its `u8` value.
+    pub intent: u8,
+    /// Layout of the signed bytes, when the message has optional fields.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<u8>,
+    pub timestamp_ms: u64,
+    /// Time after which the message should be rejected, when the enclave
+    /// signs expiries.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at_ms: Option<u64>,
+    /// Position of the message among those signed under the same scope, when
+    /// the enclave signs sequence numbers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sequence: Option<u64>,
+    pub data: T,
+}
+
+/// A signed response, returned once its signature has been verified against
+/// the pinned public key.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedResponse<T> {
+    pub response: IntentMessage<T>,
+    /// Hex encoded Ed25519 signature of the bytes of `response` in
+    /// `signing_format`.
+    pub signature: String,
+    /// The same response as a JWT, when the enclave signs them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jwt: Option<String>,
+    /// How `response` was serialized for signing, BCS when the enclave
+    /// doesn't say.
+    #[serde(default)]
+    pub signing_format: SigningFormat,
+}
+
+/// Request body of `/process_data`.
+#[derive(Debug, Serialize)]
+pub(crate) struct ProcessDataRequest<'a, T> {
+    pub payload: &'a T,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub intent: Option<u8>,
+}
+
+/// Response of `/get_attestation`, hex encoded.
+#[derive(Debug, Deserialize)]
+pub(crate) struct GetAttestationResponse {
+    pub attestation: String,
+    pub generated_at_ms: u64,
+}
+
+/// An attestation document from the enclave's NSM. It is returned as
+/// received: verify it with the AWS Nitro root certificate before trusting
+/// the public key it commits to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attestation {
+    /// The COSE_Sign1 document.
+    pub document: Vec<u8>,
+    /// When the document was generated, in milliseconds since the UNIX epoch.
+    pub generated_at_ms: u64,
+}
+
+/// Response of `/health_check`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HealthResponse {
+    /// Hex encoded public key of the enclave.
+    pub pk: String,
+    /// Whether each allowed endpoint was reachable.
+    pub endpoints_status: BTreeMap<String, bool>,
+    /// Whether the NSM answered, when the enclave checks it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nsm_available: Option<bool>,
+    /// Hex encoded git commit the enclave's server was built from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub build_commit: Option<String>,
+    /// Milliseconds since each data source last returned signed data, `None`
+    /// before its first success.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub ms_since_last_successful_fetch: BTreeMap<String, Option<u64>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cid: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub init_version: Option<String>,
+}
+
+/// Why the TLS handshake between the enclave and its upstream failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TlsFailure {
+    Expired,
+    HostnameMismatch,
+    UntrustedRoot,
+    Handshake,
+}
+
+/// Body the server returns with an error status.
+#[derive(Debug, Deserialize)]
+pub(crate) struct ErrorResponse {
+    pub error: String,
+    #[serde(default)]
+    pub tls_failure: Option<TlsFailure>,
+}
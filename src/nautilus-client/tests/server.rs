@@ -0,0 +1,163 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Drives the client against the server's router, with a mock weather API
+//! as the upstream and a seeded attestation standing in for the NSM.
+
+use fastcrypto::ed25519::{Ed25519KeyPair, Ed25519PublicKey};
+use fastcrypto::encoding::{Encoding, Hex};
+use fastcrypto::traits::{KeyPair, ToFromBytes};
+use nautilus_client::{ClientError, NautilusClient};
+use nautilus_server::app::{WeatherRequest, WeatherResponse};
+use nautilus_server::common::{wall_clock_ms, SigningFormat};
+use nautilus_server::config::Config;
+use nautilus_server::test_utils::{
+    mock_weather_upstream, test_config, TestEnclave, MOCK_TEMPERATURE, UNKNOWN_LOCATION,
+};
+use tokio::net::TcpListener;
+
+/// Start an enclave whose upstream is the mock weather API and that signs in
+/// `signing_format`, returning it and its public key.
+async fn spawn_enclave(signing_format: SigningFormat) -> (TestEnclave, Ed25519PublicKey) {
+    let upstream = mock_weather_upstream(wall_clock_ms() / 1000).await;
+    let enclave = TestEnclave::start(Config {
+        signing_format,
+        ..test_config(upstream)
+    })
+    .await;
+    let public_key = Ed25519PublicKey::from_bytes(&enclave.public_key).unwrap();
+    (enclave, public_key)
+}
+
+fn weather_request(location: &str) -> WeatherRequest {
+    WeatherRequest {
+        location: location.to_string(),
+        lang: None,
+        params: Default::default(),
+    }
+}
+
+#[test]
+fn test_api_version_matches_server() {
+    use nautilus_server::api_version::{API_VERSION_HEADER, CURRENT_API_VERSION};
+    assert_eq!(nautilus_client::API_VERSION_HEADER, API_VERSION_HEADER);
+    assert_eq!(nautilus_client::API_VERSION, CURRENT_API_VERSION);
+}
+
+#[tokio::test]
+async fn test_verified_round_trip() {
+    let (enclave, public_key) = spawn_enclave(SigningFormat::Bcs).await;
+    let client = NautilusClient::new(&format!("{}/", enclave.url))
+        .unwrap()
+        .with_public_key(public_key.clone());
+
+    let health = client.health().await.unwrap();
+    assert_eq!(health.pk, Hex::encode(public_key.as_bytes()));
+    assert!(health.endpoints_status.is_empty());
+    assert_eq!(health.ms_since_last_successful_fetch["weather"], None);
+
+    let attestation = client.get_attestation().await.unwrap();
+    assert_eq!(attestation.document, enclave.document);
+
+    let signed = client
+        .process_data::<_, WeatherResponse>(&weather_request("Paris"))
+        .await
+        .unwrap();
+    assert_eq!(signed.response.intent, 0);
+    assert_eq!(signed.signing_format, nautilus_client::SigningFormat::Bcs);
+    assert_eq!(signed.response.data.location, "Paris");
+    assert_eq!(signed.response.data.temperature, MOCK_TEMPERATURE);
+    assert!(signed.response.expires_at_ms.is_none());
+    let health = client.health().await.unwrap();
+    assert!(health.ms_since_last_successful_fetch["weather"].is_some());
+    // Tampering with the data breaks the signature
+    let mut tampered = signed.clone();
+    tampered.response.data.temperature = MOCK_TEMPERATURE + 1;
+    assert!(matches!(
+        nautilus_client::verify(&public_key, &tampered),
+        Err(ClientError::InvalidSignature)
+    ));
+}
+
+#[tokio::test]
+async fn test_verified_round_trip_jcs() {
+    let (enclave, public_key) = spawn_enclave(SigningFormat::Jcs).await;
+    let client = NautilusClient::new(&enclave.url)
+        .unwrap()
+        .with_public_key(public_key.clone());
+    let signed = client
+        .process_data::<_, WeatherResponse>(&weather_request("Paris"))
+        .await
+        .unwrap();
+    assert_eq!(signed.signing_format, nautilus_client::SigningFormat::Jcs);
+    assert_eq!(signed.response.data.temperature, MOCK_TEMPERATURE);
+    // The signature covers canonical JSON, not BCS
+    let mut as_bcs = signed.clone();
+    as_bcs.signing_format = nautilus_client::SigningFormat::Bcs;
+    assert!(matches!(
+        nautilus_client::verify(&public_key, &as_bcs),
+        Err(ClientError::InvalidSignature)
+    ));
+}
+
+#[tokio::test]
+async fn test_typed_errors() {
+    let (enclave, _) = spawn_enclave(SigningFormat::Bcs).await;
+    let other_key = Ed25519KeyPair::generate(&mut rand::thread_rng())
+        .public()
+        .clone();
+    let client = NautilusClient::new(&enclave.url).unwrap();
+    let request = weather_request("Paris");
+
+    let result = client.process_data::<_, WeatherResponse>(&request).await;
+    assert!(
+        matches!(result, Err(ClientError::NoPublicKey)),
+        "{:?}",
+        result
+    );
+
+    let client = client.with_public_key(other_key);
+    let result = client.process_data::<_, WeatherResponse>(&request).await;
+    assert!(
+        matches!(result, Err(ClientError::InvalidSignature)),
+        "{:?}",
+        result
+    );
+
+    let result = client
+        .process_data::<_, WeatherResponse>(&weather_request(UNKNOWN_LOCATION))
+        .await;
+    match result {
+        Err(ClientError::NotFound(message)) => assert_eq!(
+            message,
+            format!("No matching location found: {}", UNKNOWN_LOCATION)
+        ),
+        result => panic!("{:?}", result),
+    }
+
+    let result = client
+        .process_data_with_intent::<_, WeatherResponse>(&request, Some(9))
+        .await;
+    match result {
+        Err(ClientError::BadRequest(message)) => {
+            assert!(message.starts_with("Unknown intent scope 9"), "{}", message)
+        }
+        result => panic!("{:?}", result),
+    }
+
+    // Nothing listens on the port once the listener is dropped
+    let closed = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let closed_url = format!("http://{}", closed.local_addr().unwrap());
+    drop(closed);
+    let result = NautilusClient::new(&closed_url).unwrap().health().await;
+    assert!(
+        matches!(result, Err(ClientError::Transport(_))),
+        "{:?}",
+        result
+    );
+
+    assert!(matches!(
+        NautilusClient::new("not a url"),
+        Err(ClientError::InvalidUrl(_))
+    ));
+}
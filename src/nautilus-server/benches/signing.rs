@@ -0,0 +1,230 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Throughput of the signing path, for sizing how many signed responses an
+//! enclave produces per second. Nothing here needs the network or an
+//! enclave: upstream data is fixed and the NSM's document is mocked.
+//!
+//! Record a baseline before changing the signing path, then compare:
+//!
+//! ```sh
+//! cargo bench --features test-utils -- --save-baseline main
+//! cargo bench --features test-utils -- --baseline main
+//! ```
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use fastcrypto::ed25519::Ed25519KeyPair;
+use fastcrypto::encoding::{Base64, Encoding, Hex};
+use fastcrypto::secp256k1::Secp256k1KeyPair;
+use fastcrypto::traits::{KeyPair, Signer};
+use nautilus_server::api_keys::ApiKeys;
+use nautilus_server::app::{sign_payload, WeatherResponse};
+use nautilus_server::attestation::AttestationDocument;
+use nautilus_server::common::{
+    to_signed_response, AttestationCache, IntentMessage, IntentScope, SigningFormat,
+};
+use nautilus_server::config::Config;
+use nautilus_server::github::GithubCommit;
+use nautilus_server::price::PriceResponse;
+use nautilus_server::test_utils::{mock_document, mock_payload};
+use nautilus_server::transaction::TransactionApproval;
+use nautilus_server::AppState;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use serde::Serialize;
+use std::time::Duration;
+
+/// Timestamp of the fixtures in `vectors/test_vectors.json`.
+const TIMESTAMP_MS: u64 = 1744038900000;
+
+/// Payloads signed per batch.
+const BATCH: usize = 25;
+
+fn weather() -> WeatherResponse {
+    WeatherResponse {
+        location: "San Francisco".to_string(),
+        temperature: 13,
+    }
+}
+
+fn price() -> PriceResponse {
+    PriceResponse {
+        base: "BTC".to_string(),
+        quote: "USD".to_string(),
+        rate: 6412345000000,
+        decimals: 8,
+    }
+}
+
+fn github_commit() -> GithubCommit {
+    GithubCommit {
+        repo: "octocat/Hello-World".to_string(),
+        sha: "6dcb09b5b57875f334f61aebed695e2e4193db5e".to_string(),
+        author: "Monalisa Octocat".to_string(),
+        authored_at_ms: 1302796849000,
+    }
+}
+
+/// A transaction of a typical size, a few hundred bytes.
+fn transaction() -> TransactionApproval {
+    TransactionApproval {
+        tx_bytes: vec![0xee; 512],
+    }
+}
+
+fn keypair() -> Ed25519KeyPair {
+    Ed25519KeyPair::generate(&mut StdRng::seed_from_u64(0))
+}
+
+fn bench_bcs<T: Serialize + std::fmt::Debug>(
+    c: &mut Criterion,
+    name: &str,
+    payload: T,
+    scope: IntentScope,
+) {
+    let message = IntentMessage::new(payload, TIMESTAMP_MS, scope);
+    c.bench_function(&format!("bcs/{}", name), |b| {
+        b.iter(|| bcs::to_bytes(black_box(&message)).unwrap())
+    });
+}
+
+fn serialization(c: &mut Criterion) {
+    bench_bcs(c, "weather", weather(), IntentScope::Weather);
+    bench_bcs(c, "price", price(), IntentScope::Price);
+    bench_bcs(
+        c,
+        "github_commit",
+        github_commit(),
+        IntentScope::GithubCommit,
+    );
+    bench_bcs(c, "transaction", transaction(), IntentScope::SuiTransaction);
+}
+
+/// Ed25519 is what the enclave signs with. Secp256k1 signs the same bytes,
+/// for comparison with a signer Sui accounts also use.
+fn signing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("sign");
+    let kp = keypair();
+    for format in [SigningFormat::Bcs, SigningFormat::Jcs] {
+        group.bench_function(format!("ed25519/{:?}", format), |b| {
+            b.iter(|| {
+                to_signed_response(
+                    &kp,
+                    black_box(weather()),
+                    TIMESTAMP_MS,
+                    None,
+                    None,
+                    IntentScope::Weather,
+                    format,
+                )
+            })
+        });
+    }
+    let signing_payload = bcs::to_bytes(&IntentMessage::new(
+        weather(),
+        TIMESTAMP_MS,
+        IntentScope::Weather,
+    ))
+    .unwrap();
+    group.bench_function("ed25519/bytes", |b| {
+        b.iter(|| kp.sign(black_box(&signing_payload)))
+    });
+    let secp = Secp256k1KeyPair::generate(&mut StdRng::seed_from_u64(0));
+    group.bench_function("secp256k1/bytes", |b| {
+        b.iter(|| secp.sign(black_box(&signing_payload)))
+    });
+    group.finish();
+}
+
+/// A batch signed the way the handlers sign, through the server's state, so
+/// sequence numbers and stats are included. /process_data_batch caps
+/// batches lower, so this bounds what one request can cost.
+fn batch_signing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("batch");
+    group.throughput(Throughput::Elements(BATCH as u64));
+    for (name, config) in [
+        ("plain", Config::default()),
+        (
+            "sequenced_jwt",
+            Config {
+                sign_sequence: true,
+                sign_jwt: true,
+                ..Config::default()
+            },
+        ),
+    ] {
+        let state = AppState::new(keypair(), ApiKeys::single(String::new()), config).unwrap();
+        group.bench_function(format!("{}/{}", name, BATCH), |b| {
+            b.iter(|| {
+                (0..BATCH)
+                    .map(|_| sign_payload(&state, weather(), TIMESTAMP_MS, IntentScope::Weather))
+                    .collect::<Vec<_>>()
+            })
+        });
+    }
+    group.finish();
+}
+
+fn encoding(c: &mut Criterion) {
+    let kp = keypair();
+    let signature = kp.sign(b"nautilus");
+    let document = mock_document(&mock_payload(kp.public().as_ref(), 0xaa, TIMESTAMP_MS), 0);
+    for (name, bytes) in [
+        ("signature", signature.as_ref().to_vec()),
+        ("document", document),
+    ] {
+        let mut group = c.benchmark_group(format!("encode/{}", name));
+        group.throughput(Throughput::Bytes(bytes.len() as u64));
+        group.bench_function("hex", |b| b.iter(|| Hex::encode(black_box(&bytes))));
+        group.bench_function("base64", |b| b.iter(|| Base64::encode(black_box(&bytes))));
+        group.finish();
+    }
+}
+
+/// The work around the NSM: building a document of its shape, parsing it as
+/// verifiers do, and serving it from the attestation cache. The NSM's own
+/// latency can only be measured inside an enclave.
+fn attestation(c: &mut Criterion) {
+    let mut group = c.benchmark_group("attestation");
+    let public_key = keypair().public().as_ref().to_vec();
+    group.bench_function("mock_document", |b| {
+        b.iter(|| mock_document(&mock_payload(black_box(&public_key), 0xaa, TIMESTAMP_MS), 0))
+    });
+    let document = mock_document(&mock_payload(&public_key, 0xaa, TIMESTAMP_MS), 0);
+    group.bench_function("parse", |b| {
+        b.iter(|| AttestationDocument::parse(black_box(&document)).unwrap())
+    });
+    let ttl = Duration::from_secs(3600);
+    group.bench_function("cache_hit", |b| {
+        let cache = AttestationCache::new(ttl, ttl);
+        cache.seed(document.clone(), TIMESTAMP_MS);
+        b.iter(|| cache.get_or_refresh(|| unreachable!()).unwrap())
+    });
+    group.bench_function("cache_miss", |b| {
+        b.iter_batched(
+            || AttestationCache::new(ttl, ttl),
+            |cache| {
+                cache
+                    .get_or_refresh(|| {
+                        Ok(mock_document(
+                            &mock_payload(&public_key, 0xaa, TIMESTAMP_MS),
+                            0,
+                        ))
+                    })
+                    .unwrap()
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    serialization,
+    signing,
+    batch_signing,
+    encoding,
+    attestation
+);
+criterion_main!(benches);
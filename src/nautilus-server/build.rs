@@ -0,0 +1,39 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Set `GIT_COMMIT_HASH` for `nautilus_server::BUILD_COMMIT` from the
+//! environment, or else from `git rev-parse HEAD`. Empty when neither is
+//! available, e.g. building from a source archive.
+
+use std::process::Command;
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=GIT_COMMIT_HASH");
+    let commit = match std::env::var("GIT_COMMIT_HASH")
+        .ok()
+        .filter(|c| !c.is_empty())
+    {
+        Some(commit) => commit,
+        None => {
+            if let Some(git_dir) = git(&["rev-parse", "--absolute-git-dir"]) {
+                // HEAD moves on checkout, the branch ref on commit
+                println!("cargo:rerun-if-changed={}/HEAD", git_dir);
+                if let Some(head_ref) = git(&["symbolic-ref", "-q", "HEAD"]) {
+                    println!("cargo:rerun-if-changed={}/{}", git_dir, head_ref);
+                }
+            }
+            git(&["rev-parse", "HEAD"]).unwrap_or_default()
+        }
+    };
+    println!("cargo:rustc-env=GIT_COMMIT_HASH={}", commit.trim());
+}
+
+/// Output of a successful git command, trimmed.
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let output = String::from_utf8(output.stdout).ok()?;
+    Some(output.trim().to_string())
+}
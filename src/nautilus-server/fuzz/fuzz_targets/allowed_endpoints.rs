@@ -0,0 +1,23 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! `allowed_endpoints.yaml`, which a fork may ship in any shape the parser
+//! accepts. Every endpoint it yields must have a usable health check.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nautilus_server::health::parse_allowed_endpoints;
+
+fuzz_target!(|data: &[u8]| {
+    let yaml = match std::str::from_utf8(data) {
+        Ok(yaml) => yaml,
+        Err(_) => return,
+    };
+    if let Ok(parsed) = parse_allowed_endpoints(yaml) {
+        for endpoint in parsed.endpoints {
+            assert!(!endpoint.host.is_empty());
+            assert!(endpoint.check.path.starts_with('/'));
+        }
+    }
+});
@@ -0,0 +1,30 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Attestation documents as `nautilus-verify` and the server read them, raw
+//! COSE_Sign1 or PEM. Any input must parse or fail with an error, never
+//! panic.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nautilus_server::attestation::AttestationDocument;
+use nautilus_server::pem::attestation_from_pem;
+use nautilus_server::reattest::AttestedIdentity;
+
+fn parse(document: &[u8]) {
+    if let Ok(parsed) = AttestationDocument::parse(document) {
+        parsed.summary();
+        parsed.parsed_user_data();
+    }
+    let _ = AttestedIdentity::parse(document);
+}
+
+fuzz_target!(|data: &[u8]| {
+    parse(data);
+    if let Ok(pem) = std::str::from_utf8(data) {
+        if let Ok(document) = attestation_from_pem(pem) {
+            parse(&document);
+        }
+    }
+});
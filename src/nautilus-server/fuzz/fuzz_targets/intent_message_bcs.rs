@@ -0,0 +1,100 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The BCS bytes the enclave signs for each payload type. The first input
+//! byte picks the payload, the rest is the BCS of an intent message whose
+//! `version`, `expires_at_ms` and `sequence` are encoded as options. Whatever
+//! message that decodes to with the layout version of its fields must sign to
+//! the bytes verifiers rebuild from them, and those bytes must decode back to
+//! the message.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nautilus_server::app::{
+    WeatherFields, WeatherResponse, WeatherResponseWithAttestationDigest,
+    WeatherResponseWithUpstreamLatency,
+};
+use nautilus_server::common::{IntentMessage, IntentScope};
+use nautilus_server::github::GithubCommit;
+use nautilus_server::price::PriceResponse;
+use nautilus_server::transaction::TransactionApproval;
+use nautilus_verifier::compression::CompressedPayload;
+use nautilus_verifier::{intent_message_bcs, layout_version, signing_bytes, SigningFormat};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+fn round_trip<T: Serialize + DeserializeOwned>(data: &[u8]) {
+    let message: IntentMessage<T> = match bcs::from_bytes(data) {
+        Ok(message) => message,
+        Err(_) => return,
+    };
+    // The enclave only signs messages in the layout of their fields
+    if message.version != layout_version(message.expires_at_ms, message.sequence) {
+        return;
+    }
+    let signed = bcs::to_bytes(&message).unwrap();
+    let data_bcs = bcs::to_bytes(&message.data).unwrap();
+    assert_eq!(
+        signed,
+        intent_message_bcs(
+            message.intent as u8,
+            message.timestamp_ms,
+            message.expires_at_ms,
+            message.sequence,
+            &data_bcs,
+        )
+    );
+
+    // Only the fields present are signed, in order, the optional ones as
+    // options the way Move encodes them
+    type Opt = Option<u64>;
+    let decoded =
+        match (message.expires_at_ms, message.sequence) {
+            (None, None) => bcs::from_bytes::<(IntentScope, u64, T)>(&signed)
+                .map(|(intent, timestamp_ms, data)| (intent, None, timestamp_ms, None, None, data)),
+            (Some(_), None) => bcs::from_bytes::<(IntentScope, Option<u8>, u64, Opt, T)>(&signed)
+                .map(|(intent, version, timestamp_ms, expires_at_ms, data)| {
+                    (intent, version, timestamp_ms, expires_at_ms, None, data)
+                }),
+            (None, Some(_)) => bcs::from_bytes::<(IntentScope, Option<u8>, u64, Opt, T)>(&signed)
+                .map(|(intent, version, timestamp_ms, sequence, data)| {
+                    (intent, version, timestamp_ms, None, sequence, data)
+                }),
+            (Some(_), Some(_)) => {
+                bcs::from_bytes::<(IntentScope, Option<u8>, u64, Opt, Opt, T)>(&signed).map(
+                    |(intent, version, timestamp_ms, expires_at_ms, sequence, data)| {
+                        (intent, version, timestamp_ms, expires_at_ms, sequence, data)
+                    },
+                )
+            }
+        }
+        .unwrap();
+    assert_eq!(decoded.0, message.intent);
+    assert_eq!(decoded.1, message.version);
+    assert_eq!(decoded.2, message.timestamp_ms);
+    assert_eq!(decoded.3, message.expires_at_ms);
+    assert_eq!(decoded.4, message.sequence);
+    assert_eq!(bcs::to_bytes(&decoded.5).unwrap(), data_bcs);
+
+    // Every message that can be signed over BCS can be signed over JCS
+    signing_bytes(SigningFormat::Jcs, &message).unwrap();
+}
+
+fuzz_target!(|data: &[u8]| {
+    let (payload, data) = match data.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+    match payload % 9 {
+        0 => round_trip::<WeatherResponse>(data),
+        1 => round_trip::<WeatherResponseWithAttestationDigest>(data),
+        2 => round_trip::<WeatherResponseWithUpstreamLatency>(data),
+        3 => round_trip::<WeatherFields>(data),
+        4 => round_trip::<PriceResponse>(data),
+        5 => round_trip::<GithubCommit>(data),
+        6 => round_trip::<CompressedPayload>(data),
+        7 => round_trip::<TransactionApproval>(data),
+        _ => round_trip::<Vec<u8>>(data),
+    }
+});
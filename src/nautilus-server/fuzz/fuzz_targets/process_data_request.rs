@@ -0,0 +1,32 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! `/process_data` and `/process_data_batch` bodies, deserialized and
+//! checked as far as the server goes before calling the upstream.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nautilus_server::app::{BatchWeatherRequest, WeatherRequest};
+use nautilus_server::common::{IntentScope, ProcessDataRequest};
+
+/// `UPSTREAM_PARAMS` of a server that passes some parameters through.
+fn allowed() -> Vec<String> {
+    vec!["aqi".to_string(), "days".to_string()]
+}
+
+fn check(request: &WeatherRequest) {
+    let _ = request.upstream_query(&[]);
+    let _ = request.upstream_query(&allowed());
+}
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(request) = serde_json::from_slice::<ProcessDataRequest<WeatherRequest>>(data) {
+        let _ = request.intent.map(IntentScope::try_from);
+        check(&request.payload);
+    }
+    if let Ok(request) = serde_json::from_slice::<ProcessDataRequest<BatchWeatherRequest>>(data) {
+        let _ = request.intent.map(IntentScope::try_from);
+        request.payload.requests.iter().for_each(check);
+    }
+});
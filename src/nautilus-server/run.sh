@@ -6,7 +6,6 @@
 # - Sets up Python and library paths
 # - Configures loopback network and /etc/hosts
 # - Waits for secrets.json to be passed from the parent instance. 
-# - Forwards VSOCK port 3000 to localhost:3000
 # - Optionally pulls secrets and sets in environmen variables.
 # - Launches nautilus-server
 
@@ -20,8 +19,10 @@ echo "Script completed."
 busybox ip addr add 127.0.0.1/32 dev lo
 busybox ip link set dev lo up
 
+# Init has already written /etc/hosts with localhost, the hostname and any
+# `nautilus.hosts` entries, so records are appended to it.
 # Add a hosts record, pointing target site calls to local loopback
-echo "127.0.0.1   localhost" > /etc/hosts
+echo "127.0.0.1   localhost" >> /etc/hosts
 
 
 
@@ -44,7 +45,7 @@ echo "$JSON_RESPONSE" | jq -r 'to_entries[] | "\(.key)=\(.value)"' > /tmp/kvpair
 # Traffic-forwarder-block
 
 
-# Listens on Local VSOCK Port 3000 and forwards to localhost 3000
-socat VSOCK-LISTEN:3000,reuseaddr,fork TCP:localhost:3000 &
+# VSOCK port 3000 is forwarded to localhost:3000 by init, see
+# `nautilus.vsock_forward` on the kernel command line in Containerfile.
 
 /nautilus-server
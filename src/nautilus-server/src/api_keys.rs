@@ -0,0 +1,335 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::app::check_api_key;
+use crate::common::bearer_matches;
+use crate::AppState;
+use crate::EnclaveError;
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::Json;
+use fastcrypto::encoding::{Encoding, Hex};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use tracing::{info, warn};
+
+/// Header identifying the tenant a request is made for, used by
+/// [ApiKeyPolicy::Tenant].
+pub const TENANT_HEADER: &str = "x-tenant-id";
+
+/// How a request's upstream API key is chosen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiKeyPolicy {
+    /// Cycle through the keys, one request each.
+    RoundRobin,
+    /// Use the key configured for the request's [TENANT_HEADER].
+    Tenant,
+}
+
+impl FromStr for ApiKeyPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "round_robin" => Ok(Self::RoundRobin),
+            "tenant" => Ok(Self::Tenant),
+            _ => Err(format!("expected `round_robin` or `tenant`, got `{}`", s)),
+        }
+    }
+}
+
+/// Upstream API keys, e.g. for api.weatherapi.com, and the policy for
+/// choosing one per request. Several keys spread requests across quotas or
+/// give each tenant its own. Keys never appear in responses, errors or logs.
+/// Each key can be replaced while serving, see [rotate_api_key].
+pub struct ApiKeys {
+    keys: RwLock<Vec<ApiKey>>,
+    policy: ApiKeyPolicy,
+    next: AtomicUsize,
+}
+
+struct ApiKey {
+    tenant: Option<String>,
+    key: String,
+}
+
+impl ApiKeys {
+    /// A single key used for every request.
+    pub fn single(key: String) -> Self {
+        Self {
+            keys: RwLock::new(vec![ApiKey { tenant: None, key }]),
+            policy: ApiKeyPolicy::RoundRobin,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Parse a comma separated list of `key` or `tenant=key` entries, as set in
+    /// `API_KEYS`. [ApiKeyPolicy::Tenant] requires every entry to name a
+    /// distinct tenant.
+    pub fn parse(list: &str, policy: ApiKeyPolicy) -> Result<Self, EnclaveError> {
+        let mut keys: Vec<ApiKey> = Vec::new();
+        for (i, entry) in list.split(',').map(str::trim).enumerate() {
+            let key = match entry.split_once('=') {
+                Some((tenant, key)) => ApiKey {
+                    tenant: Some(tenant.trim().to_string()),
+                    key: key.trim().to_string(),
+                },
+                None => ApiKey {
+                    tenant: None,
+                    key: entry.to_string(),
+                },
+            };
+            // Entries are identified by position so keys stay out of errors.
+            let invalid = |e: &str| {
+                EnclaveError::GenericError(format!("Invalid API_KEYS entry {}: {}", i + 1, e))
+            };
+            if key.key.is_empty() || key.tenant.as_deref() == Some("") {
+                return Err(invalid("empty key or tenant"));
+            }
+            if policy == ApiKeyPolicy::Tenant {
+                match &key.tenant {
+                    None => return Err(invalid("missing tenant")),
+                    Some(tenant) if keys.iter().any(|k| k.tenant.as_ref() == Some(tenant)) => {
+                        return Err(invalid("duplicate tenant"))
+                    }
+                    Some(_) => {}
+                }
+            }
+            keys.push(key);
+        }
+        Ok(Self {
+            keys: RwLock::new(keys),
+            policy,
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    /// Choose the key for a request made for `tenant`.
+    pub fn select(&self, tenant: Option<&str>) -> Result<String, EnclaveError> {
+        let keys = self.keys.read().expect("should not be poisoned");
+        match self.policy {
+            ApiKeyPolicy::RoundRobin => {
+                let i = self.next.fetch_add(1, Ordering::Relaxed) % keys.len();
+                Ok(keys[i].key.clone())
+            }
+            ApiKeyPolicy::Tenant => {
+                let tenant = tenant.ok_or_else(|| {
+                    EnclaveError::GenericError(format!("Missing {} header", TENANT_HEADER))
+                })?;
+                keys.iter()
+                    .find(|k| k.tenant.as_deref() == Some(tenant))
+                    .map(|k| k.key.clone())
+                    .ok_or_else(|| {
+                        EnclaveError::GenericError(format!("Unknown tenant `{}`", tenant))
+                    })
+            }
+        }
+    }
+
+    /// Check that exactly one entry is configured for `tenant`, None meaning
+    /// the entry without a tenant, so [ApiKeys::replace] can swap its key.
+    pub fn check_entry(&self, tenant: Option<&str>) -> Result<(), EnclaveError> {
+        entry_index(&self.keys.read().expect("should not be poisoned"), tenant).map(|_| ())
+    }
+
+    /// Replace the key of the entry for `tenant`. Requests already holding
+    /// the old key finish with it.
+    pub fn replace(&self, tenant: Option<&str>, key: String) -> Result<(), EnclaveError> {
+        if key.is_empty() {
+            return Err(EnclaveError::GenericError("Empty API key".to_string()));
+        }
+        let mut keys = self.keys.write().expect("should not be poisoned");
+        let i = entry_index(&keys, tenant)?;
+        keys[i].key = key;
+        Ok(())
+    }
+}
+
+fn entry_index(keys: &[ApiKey], tenant: Option<&str>) -> Result<usize, EnclaveError> {
+    let mut matching = keys
+        .iter()
+        .enumerate()
+        .filter(|(_, k)| k.tenant.as_deref() == tenant)
+        .map(|(i, _)| i);
+    let name = || {
+        tenant.map_or("the entry without a tenant".to_string(), |t| {
+            format!("tenant `{}`", t)
+        })
+    };
+    match (matching.next(), matching.next()) {
+        (Some(i), None) => Ok(i),
+        (None, _) => Err(EnclaveError::GenericError(format!(
+            "No API key configured for {}",
+            name()
+        ))),
+        (Some(_), Some(_)) => Err(EnclaveError::GenericError(format!(
+            "Several API keys are configured for {}, rotate them by tenant",
+            name()
+        ))),
+    }
+}
+
+/// Short identifier of `key` for logs and responses: the first 8 hex digits
+/// of its SHA-256, which doesn't reveal the key.
+pub fn fingerprint(key: &str) -> String {
+    Hex::encode(Sha256::digest(key.as_bytes()))[..8].to_string()
+}
+
+/// Request to replace an upstream API key.
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+pub struct RotateApiKeyRequest {
+    /// The new key.
+    pub api_key: String,
+    /// Tenant whose key is replaced. Omit to replace the key configured
+    /// without a tenant, e.g. `API_KEY`.
+    #[serde(default)]
+    pub tenant: Option<String>,
+}
+
+/// Result of a successful rotation.
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+pub struct RotateApiKeyResponse {
+    /// Tenant whose key was replaced, if any.
+    pub tenant: Option<String>,
+    /// [fingerprint] of the key now in use.
+    pub fingerprint: String,
+}
+
+/// Endpoint that replaces an upstream API key, authorized by
+/// `Authorization: Bearer <API_KEY_ROTATION_TOKEN>`. The new key is first
+/// used for a test call to the upstream, and the old key stays in use unless
+/// that call succeeds, so a bad key never reaches requests.
+pub async fn rotate_api_key(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(request): Json<RotateApiKeyRequest>,
+) -> Result<Json<RotateApiKeyResponse>, EnclaveError> {
+    authorize_rotation(&headers, state.config.api_key_rotation_token.as_deref())?;
+    let tenant = request.tenant.as_deref();
+    state.api_keys.check_entry(tenant)?;
+    let fingerprint = fingerprint(&request.api_key);
+    let tenant_name = tenant.unwrap_or("-");
+    if let Err(e) = check_api_key(&state, &request.api_key).await {
+        warn!(
+            "Rejected API key {} for tenant {}, keeping the current key: {}",
+            fingerprint, tenant_name, e
+        );
+        return Err(e);
+    }
+    state.api_keys.replace(tenant, request.api_key)?;
+    info!(
+        "Rotated API key for tenant {} to {}",
+        tenant_name, fingerprint
+    );
+    Ok(Json(RotateApiKeyResponse {
+        tenant: request.tenant,
+        fingerprint,
+    }))
+}
+
+/// Check the bearer token in `headers` against `token`. Rotation is refused
+/// when no token is configured.
+fn authorize_rotation(headers: &HeaderMap, token: Option<&str>) -> Result<(), EnclaveError> {
+    let token = token.ok_or_else(|| {
+        EnclaveError::GenericError("API key rotation requires API_KEY_ROTATION_TOKEN".to_string())
+    })?;
+    if !bearer_matches(headers, token) {
+        return Err(EnclaveError::Unauthorized(
+            "Invalid API key rotation token".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_round_robin_selection() {
+        let keys = ApiKeys::parse("k1, k2,acme=k3", ApiKeyPolicy::RoundRobin).unwrap();
+        let selected: Vec<String> = (0..7).map(|_| keys.select(Some("acme")).unwrap()).collect();
+        assert_eq!(selected, ["k1", "k2", "k3", "k1", "k2", "k3", "k1"]);
+
+        let single = ApiKeys::single("only".to_string());
+        assert_eq!(single.select(None).unwrap(), "only");
+        assert_eq!(single.select(None).unwrap(), "only");
+    }
+
+    #[test]
+    fn test_tenant_selection() {
+        let keys = ApiKeys::parse("acme=k1,globex=k2", ApiKeyPolicy::Tenant).unwrap();
+        assert_eq!(keys.select(Some("globex")).unwrap(), "k2");
+        assert_eq!(keys.select(Some("acme")).unwrap(), "k1");
+        assert_eq!(keys.select(Some("acme")).unwrap(), "k1");
+        assert!(keys.select(Some("initech")).is_err());
+        assert!(keys.select(None).is_err());
+    }
+
+    #[test]
+    fn test_replace() {
+        let keys = ApiKeys::parse("acme=k1,globex=k2", ApiKeyPolicy::Tenant).unwrap();
+        keys.replace(Some("acme"), "k3".to_string()).unwrap();
+        assert_eq!(keys.select(Some("acme")).unwrap(), "k3");
+        assert_eq!(keys.select(Some("globex")).unwrap(), "k2");
+        assert!(keys.replace(Some("initech"), "k4".to_string()).is_err());
+        assert!(keys.replace(None, "k4".to_string()).is_err());
+        assert!(keys.replace(Some("acme"), String::new()).is_err());
+
+        let single = ApiKeys::single("old".to_string());
+        single.replace(None, "new".to_string()).unwrap();
+        assert_eq!(single.select(None).unwrap(), "new");
+
+        // Entries without a tenant can only be told apart when there is one
+        let keys = ApiKeys::parse("k1,k2", ApiKeyPolicy::RoundRobin).unwrap();
+        assert!(keys.check_entry(None).is_err());
+    }
+
+    #[test]
+    fn test_authorize_rotation() {
+        let headers = |value: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                axum::http::header::AUTHORIZATION,
+                axum::http::HeaderValue::from_static(value),
+            );
+            headers
+        };
+        assert!(authorize_rotation(&headers("Bearer s3cret"), Some("s3cret")).is_ok());
+        for headers in [headers("Bearer other"), headers("s3cret"), HeaderMap::new()] {
+            assert!(matches!(
+                authorize_rotation(&headers, Some("s3cret")),
+                Err(EnclaveError::Unauthorized(_))
+            ));
+        }
+        // Disabled without a token, even for an empty bearer
+        assert!(authorize_rotation(&headers("Bearer "), None).is_err());
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        assert_eq!(fingerprint("k1"), fingerprint("k1"));
+        assert_ne!(fingerprint("k1"), fingerprint("k2"));
+        assert_eq!(fingerprint("k1").len(), 8);
+    }
+
+    #[test]
+    fn test_parse_errors_hide_keys() {
+        for (list, policy) in [
+            ("k1,,k2", ApiKeyPolicy::RoundRobin),
+            ("acme=secret,secret2", ApiKeyPolicy::Tenant),
+            ("acme=secret,acme=secret2", ApiKeyPolicy::Tenant),
+            ("=secret", ApiKeyPolicy::RoundRobin),
+        ] {
+            match ApiKeys::parse(list, policy) {
+                Ok(_) => panic!("{} should be rejected", list),
+                Err(e) => assert!(!e.to_string().contains("secret"), "{}", e),
+            }
+        }
+    }
+}
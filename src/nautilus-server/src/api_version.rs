@@ -0,0 +1,84 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::EnclaveError;
+use axum::extract::Request;
+use axum::http::{HeaderMap, HeaderValue};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+
+/// Header in which clients declare the API version they expect. The version
+/// served is echoed in the response.
+pub const API_VERSION_HEADER: &str = "x-nautilus-api";
+
+/// Version assumed for requests without [API_VERSION_HEADER].
+pub const CURRENT_API_VERSION: u32 = 1;
+
+/// Versions whose request and response shapes this server implements. When a
+/// change breaks clients, bump [CURRENT_API_VERSION] and keep the old version
+/// here for as long as the server still serves it.
+pub const SUPPORTED_API_VERSIONS: &[u32] = &[CURRENT_API_VERSION];
+
+/// Middleware that rejects requests declaring an API version the server
+/// doesn't support with a 400, so a client built against another version
+/// fails clearly during an upgrade instead of misreading responses.
+pub async fn require_api_version(request: Request, next: Next) -> Response {
+    let version = match api_version(request.headers()) {
+        Ok(version) => version,
+        Err(e) => return e.into_response(),
+    };
+    let mut response = next.run(request).await;
+    response
+        .headers_mut()
+        .insert(API_VERSION_HEADER, HeaderValue::from(version));
+    response
+}
+
+/// The version declared in `headers`, or [CURRENT_API_VERSION] if there is
+/// none.
+fn api_version(headers: &HeaderMap) -> Result<u32, EnclaveError> {
+    let value = match headers.get(API_VERSION_HEADER) {
+        Some(value) => value,
+        None => return Ok(CURRENT_API_VERSION),
+    };
+    let supported = SUPPORTED_API_VERSIONS
+        .iter()
+        .map(u32::to_string)
+        .collect::<Vec<_>>()
+        .join(", ");
+    value
+        .to_str()
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+        .filter(|version| SUPPORTED_API_VERSIONS.contains(version))
+        .ok_or_else(|| {
+            EnclaveError::GenericError(format!(
+                "Unsupported {} version {:?}, this server supports: {}",
+                API_VERSION_HEADER,
+                String::from_utf8_lossy(value.as_bytes()),
+                supported
+            ))
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn headers(version: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(API_VERSION_HEADER, HeaderValue::from_static(version));
+        headers
+    }
+
+    #[test]
+    fn test_api_version() {
+        assert_eq!(api_version(&HeaderMap::new()).unwrap(), CURRENT_API_VERSION);
+        assert_eq!(api_version(&headers("1")).unwrap(), 1);
+        assert_eq!(api_version(&headers(" 1 ")).unwrap(), 1);
+        for version in ["0", "2", "v1", "1.0", ""] {
+            let err = api_version(&headers(version)).unwrap_err().to_string();
+            assert!(err.ends_with("this server supports: 1"), "{}", err);
+        }
+    }
+}
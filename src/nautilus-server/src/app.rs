@@ -1,15 +1,32 @@
 // Copyright (c), Mysten Labs, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
+use crate::api_keys::TENANT_HEADER;
 use crate::common::IntentMessage;
-use crate::common::{to_signed_response, IntentScope, ProcessDataRequest, ProcessedDataResponse};
+use crate::common::{
+    bucket_timestamp, current_timestamp_ms, to_signed_response, BootAttestation, IntentScope,
+    ProcessDataRequest, ProcessedDataResponse, SigningFormat,
+};
+use crate::expiry::expiry_headers;
+#[cfg(feature = "grpc")]
+use crate::grpc::GrpcUpstream;
+use crate::jwt;
+use crate::upstream_tls;
 use crate::AppState;
 use crate::EnclaveError;
-use axum::extract::State;
+use axum::extract::{Query, State};
+use axum::http::{HeaderMap, StatusCode};
 use axum::Json;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::str::FromStr;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tracing::warn;
 /// ====
 /// Core Nautilus server logic, replace it with your own
 /// relavant structs and process_data endpoint.
@@ -17,39 +34,687 @@ use std::sync::Arc;
 
 /// Inner type T for IntentMessage<T>
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
 pub struct WeatherResponse {
     pub location: String,
     pub temperature: u64,
 }
 
+/// Inner type T for IntentMessage<T> when responses commit to the digest of
+/// the enclave's boot attestation, see `SIGN_ATTESTATION_DIGEST`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+pub struct WeatherResponseWithAttestationDigest {
+    pub location: String,
+    pub temperature: u64,
+    /// SHA-384 digest of the attestation document returned by `/get_attestation`.
+    pub attestation_digest: Vec<u8>,
+}
+
+/// Inner type T for IntentMessage<T> when responses commit to the measured
+/// upstream latency, see `SIGN_UPSTREAM_LATENCY`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+pub struct WeatherResponseWithUpstreamLatency {
+    pub location: String,
+    pub temperature: u64,
+    /// Milliseconds from sending the upstream request until its body was
+    /// read, as measured inside the enclave.
+    pub upstream_latency_ms: u64,
+}
+
+/// An upstream field selected by `SIGNED_FIELDS`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+pub struct SignedField {
+    /// The configured path, e.g. `current.temp_c`.
+    pub path: String,
+    /// The field's value as canonical JSON, see [canonical_json].
+    pub value: String,
+}
+
+/// Inner type T for IntentMessage<T> when responses commit to the upstream
+/// fields selected by `SIGNED_FIELDS`, in the configured order.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+pub struct WeatherFields {
+    pub fields: Vec<SignedField>,
+}
+
+/// Signed weather payload, with or without extra commitments.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+pub enum WeatherPayload {
+    Fields(WeatherFields),
+    WithAttestationDigest(WeatherResponseWithAttestationDigest),
+    WithUpstreamLatency(WeatherResponseWithUpstreamLatency),
+    Plain(WeatherResponse),
+}
+
 /// Inner type T for ProcessDataRequest<T>
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
 pub struct WeatherRequest {
     pub location: String,
+    /// Language of the location name, one of [SUPPORTED_LANGUAGES]. The name
+    /// is signed as returned, so verifiers comparing it must know the
+    /// language that was requested. Defaults to English.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lang: Option<String>,
+    /// Extra query parameters for the upstream request, e.g. `lang`. Only
+    /// names listed in `UPSTREAM_PARAMS` are accepted.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub params: BTreeMap<String, String>,
 }
 
-pub async fn process_data(
-    State(state): State<Arc<AppState>>,
-    Json(request): Json<ProcessDataRequest<WeatherRequest>>,
-) -> Result<Json<ProcessedDataResponse<IntentMessage<WeatherResponse>>>, EnclaveError> {
-    let url = format!(
-        "https://api.weatherapi.com/v1/current.json?key={}&q={}",
-        state.api_key, request.payload.location
-    );
-    let response = reqwest::get(url.clone()).await.map_err(|e| {
-        EnclaveError::GenericError(format!("Failed to get weather response: {}", e))
+/// Header in which clients declare the largest response body, in bytes, they
+/// can handle. See `HONOR_MAX_RESPONSE_BYTES`.
+pub const MAX_RESPONSE_BYTES_HEADER: &str = "x-max-response-bytes";
+
+/// Most requests a single `/process_data_batch` call may hold.
+pub const MAX_BATCH_SIZE: usize = 16;
+
+/// Inner type T for ProcessDataRequest<T> on `/process_data_batch`.
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+pub struct BatchWeatherRequest {
+    /// Up to [MAX_BATCH_SIZE] requests, signed independently and returned
+    /// in the same order.
+    pub requests: Vec<WeatherRequest>,
+}
+
+/// Language codes accepted by the weather API's `lang` parameter, besides
+/// the default `en`.
+pub const SUPPORTED_LANGUAGES: [&str; 40] = [
+    "ar", "bn", "bg", "zh", "zh_tw", "cs", "da", "nl", "fi", "fr", "de", "el", "hi", "hu", "it",
+    "ja", "jv", "ko", "zh_cmn", "mr", "pl", "pt", "pa", "ro", "ru", "sr", "si", "sk", "es", "sv",
+    "ta", "te", "tr", "uk", "ur", "vi", "zh_wuu", "zh_hsn", "zh_yue", "zu",
+];
+
+/// The `lang` parameter for a requested language, or None for English.
+fn upstream_lang(lang: Option<&str>) -> Result<Option<&str>, EnclaveError> {
+    match lang {
+        None | Some("en") => Ok(None),
+        Some(lang) if SUPPORTED_LANGUAGES.contains(&lang) => Ok(Some(lang)),
+        Some(lang) => Err(EnclaveError::GenericError(format!(
+            "Unsupported language `{}`",
+            lang
+        ))),
+    }
+}
+
+/// Query parameters set by the server, which clients can never override.
+const RESERVED_PARAMS: [&str; 3] = ["key", "q", "lang"];
+
+/// Check the client's pass-through parameters against `allowed`.
+fn upstream_params<'a>(
+    params: &'a BTreeMap<String, String>,
+    allowed: &[String],
+) -> Result<Vec<(&'a str, &'a str)>, EnclaveError> {
+    params
+        .iter()
+        .map(|(name, value)| {
+            if RESERVED_PARAMS.contains(&name.as_str()) || !allowed.contains(name) {
+                return Err(EnclaveError::GenericError(format!(
+                    "Query parameter `{}` is not allowed",
+                    name
+                )));
+            }
+            Ok((name.as_str(), value.as_str()))
+        })
+        .collect()
+}
+
+impl WeatherRequest {
+    /// The query parameters the request adds to the upstream call, `lang`
+    /// and the pass-through `params`, once both are checked. `allowed` is
+    /// `UPSTREAM_PARAMS`.
+    pub fn upstream_query(&self, allowed: &[String]) -> Result<Vec<(&str, &str)>, EnclaveError> {
+        let lang = upstream_lang(self.lang.as_deref())?;
+        let mut query: Vec<(&str, &str)> = lang.map(|lang| ("lang", lang)).into_iter().collect();
+        query.extend(upstream_params(&self.params, allowed)?);
+        Ok(query)
+    }
+}
+
+/// How `temp_c` in the weather response is read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TemperatureParsing {
+    /// Only JSON numbers are accepted.
+    Strict,
+    /// Strings holding a number, as some providers return, are accepted too.
+    Lenient,
+}
+
+impl FromStr for TemperatureParsing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "strict" => Ok(Self::Strict),
+            "lenient" => Ok(Self::Lenient),
+            _ => Err(format!("expected `strict` or `lenient`, got `{}`", s)),
+        }
+    }
+}
+
+/// Read the temperature from the `temp_c` value of a weather response.
+/// Missing, non-numeric or negative values are rejected rather than signed
+/// as zero, since the signed temperature is unsigned.
+fn parse_temperature(value: &Value, parsing: TemperatureParsing) -> Result<u64, EnclaveError> {
+    let temperature = match (value, parsing) {
+        (Value::Number(number), _) => number.as_f64(),
+        (Value::String(text), TemperatureParsing::Lenient) => text.trim().parse::<f64>().ok(),
+        _ => None,
+    };
+    match temperature {
+        Some(temperature) if temperature.is_finite() && temperature >= 0.0 => {
+            Ok(temperature as u64)
+        }
+        _ => Err(EnclaveError::GenericError(format!(
+            "Invalid temperature in weather response: {}",
+            value
+        ))),
+    }
+}
+
+/// Which of the weather response's timestamps is signed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimestampSource {
+    /// `current.last_updated_epoch`.
+    Epoch,
+    /// `current.last_updated`, the location's local wall-clock time,
+    /// normalized to UTC. See [local_last_updated].
+    LocalTime,
+}
+
+impl FromStr for TimestampSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "epoch" => Ok(Self::Epoch),
+            "local_time" => Ok(Self::LocalTime),
+            _ => Err(format!("expected `epoch` or `local_time`, got `{}`", s)),
+        }
+    }
+}
+
+/// Seconds since the UNIX epoch of a weather API local time such as
+/// `2025-04-07 08:15` or `2025-04-07 8:15`, read as if it were UTC.
+fn parse_local_time(text: &str) -> Option<i64> {
+    let (date, time) = text.trim().split_once(' ')?;
+    let mut date = date.splitn(3, '-').map(str::parse::<i64>);
+    let (year, month, day) = (date.next()?.ok()?, date.next()?.ok()?, date.next()?.ok()?);
+    let (hour, minute) = time.trim().split_once(':')?;
+    let (hour, minute) = (hour.parse::<i64>().ok()?, minute.parse::<i64>().ok()?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 {
+        return None;
+    }
+    // Days from the civil date, counting years from March so the leap day
+    // comes last (http://howardhinnant.github.io/date_algorithms.html).
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+    Some(days * 86_400 + hour * 3600 + minute * 60)
+}
+
+/// `current.last_updated` in seconds since the UNIX epoch, or None when the
+/// response doesn't have it. The text carries no timezone, so the location's
+/// UTC offset is taken from `location.localtime` and `location.localtime_epoch`,
+/// rounded to the quarter hour since `localtime` drops the seconds.
+fn local_last_updated(json: &Value) -> Result<Option<u64>, EnclaveError> {
+    let last_updated = match json["current"]["last_updated"].as_str() {
+        Some(last_updated) => last_updated,
+        None => return Ok(None),
+    };
+    let invalid =
+        |field: &str| EnclaveError::GenericError(format!("Invalid {} in weather response", field));
+    let local = parse_local_time(last_updated).ok_or_else(|| invalid("current.last_updated"))?;
+    let localtime = json["location"]["localtime"]
+        .as_str()
+        .and_then(parse_local_time)
+        .ok_or_else(|| invalid("location.localtime"))?;
+    let localtime_epoch = json["location"]["localtime_epoch"]
+        .as_i64()
+        .ok_or_else(|| invalid("location.localtime_epoch"))?;
+    let offset = ((localtime - localtime_epoch) as f64 / 900.0).round() as i64 * 900;
+    u64::try_from(local - offset)
+        .map(Some)
+        .map_err(|_| invalid("current.last_updated"))
+}
+
+/// The time the weather response was last updated, in seconds since the
+/// UNIX epoch, from the field `source` selects. When the response has both
+/// `last_updated_epoch` and a `last_updated` that can be normalized, they
+/// must agree within `tolerance`: a disagreement means the upstream data is
+/// inconsistent, and is refused rather than signed.
+fn last_updated_secs(
+    json: &Value,
+    source: TimestampSource,
+    tolerance: Duration,
+) -> Result<u64, EnclaveError> {
+    let epoch = json["current"]["last_updated_epoch"].as_u64();
+    let local = match source {
+        TimestampSource::Epoch => local_last_updated(json).ok().flatten(),
+        TimestampSource::LocalTime => Some(local_last_updated(json)?.ok_or_else(|| {
+            EnclaveError::GenericError("Weather response has no current.last_updated".to_string())
+        })?),
+    };
+    if let (Some(epoch), Some(local)) = (epoch, local) {
+        if epoch.abs_diff(local) > tolerance.as_secs() {
+            return Err(EnclaveError::GenericError(format!(
+                "Weather response timestamps disagree: last_updated_epoch is {} but last_updated is {} ({}s apart, tolerance {}s)",
+                epoch,
+                local,
+                epoch.abs_diff(local),
+                tolerance.as_secs()
+            )));
+        }
+    }
+    Ok(match (source, local) {
+        (TimestampSource::LocalTime, Some(local)) => local,
+        _ => epoch.unwrap_or(0),
+    })
+}
+
+/// Serialize `value` as compact JSON with object keys sorted at every
+/// level, so the same data always yields the same text whatever order the
+/// upstream sent it in.
+pub fn canonical_json(value: &Value) -> String {
+    match value {
+        Value::Array(items) => {
+            let items: Vec<String> = items.iter().map(canonical_json).collect();
+            format!("[{}]", items.join(","))
+        }
+        Value::Object(map) => {
+            let mut entries: Vec<(&String, &Value)> = map.iter().collect();
+            entries.sort_by_key(|(key, _)| *key);
+            let entries: Vec<String> = entries
+                .into_iter()
+                .map(|(key, value)| {
+                    format!("{}:{}", Value::from(key.as_str()), canonical_json(value))
+                })
+                .collect();
+            format!("{{{}}}", entries.join(","))
+        }
+        scalar => scalar.to_string(),
+    }
+}
+
+/// Look up a dotted path such as `location.name` or `alerts.0.headline`,
+/// where numeric segments index into arrays.
+pub(crate) fn field_at<'a>(json: &'a Value, path: &str) -> Option<&'a Value> {
+    let pointer: String = path
+        .split('.')
+        .map(|segment| format!("/{}", segment.replace('~', "~0").replace('/', "~1")))
+        .collect();
+    json.pointer(&pointer)
+}
+
+/// The fields at `paths` in an upstream response, canonicalized. A path the
+/// response doesn't have is an error rather than being left out, so a
+/// signature never silently covers less than was configured.
+pub fn select_fields(json: &Value, paths: &[String]) -> Result<Vec<SignedField>, EnclaveError> {
+    paths
+        .iter()
+        .map(|path| match field_at(json, path) {
+            Some(value) => Ok(SignedField {
+                path: path.clone(),
+                value: canonical_json(value),
+            }),
+            None => Err(EnclaveError::GenericError(format!(
+                "Upstream response has no field `{}`, see SIGNED_FIELDS",
+                path
+            ))),
+        })
+        .collect()
+}
+
+/// Weather payload for the chosen intent scope. Scopes that commit to the
+/// boot attestation are only available when it was requested at boot, and
+/// [IntentScope::WeatherFields] only when `SIGNED_FIELDS` is set.
+fn weather_payload(
+    location: String,
+    temperature: u64,
+    upstream_latency_ms: u64,
+    fields: Vec<SignedField>,
+    scope: IntentScope,
+    boot_attestation: Option<&BootAttestation>,
+) -> Result<WeatherPayload, EnclaveError> {
+    match scope {
+        IntentScope::Weather => Ok(WeatherPayload::Plain(WeatherResponse {
+            location,
+            temperature,
+        })),
+        IntentScope::WeatherWithAttestationDigest => match boot_attestation {
+            Some(boot_attestation) => Ok(WeatherPayload::WithAttestationDigest(
+                WeatherResponseWithAttestationDigest {
+                    location,
+                    temperature,
+                    attestation_digest: boot_attestation.digest.clone(),
+                },
+            )),
+            None => Err(EnclaveError::GenericError(format!(
+                "Intent scope {} requires the boot attestation, see BOOT_ATTESTATION",
+                scope as u8
+            ))),
+        },
+        IntentScope::WeatherWithUpstreamLatency => Ok(WeatherPayload::WithUpstreamLatency(
+            WeatherResponseWithUpstreamLatency {
+                location,
+                temperature,
+                upstream_latency_ms,
+            },
+        )),
+        IntentScope::WeatherFields if fields.is_empty() => {
+            Err(EnclaveError::GenericError(format!(
+                "Intent scope {} requires upstream fields, see SIGNED_FIELDS",
+                scope as u8
+            )))
+        }
+        IntentScope::WeatherFields => Ok(WeatherPayload::Fields(WeatherFields { fields })),
+        IntentScope::Price => Err(EnclaveError::GenericError(format!(
+            "Intent scope {} is only signed by /process_price",
+            scope as u8
+        ))),
+        IntentScope::Compressed => Err(EnclaveError::GenericError(format!(
+            "Intent scope {} is only signed for schemas with compression, see SCHEMAS_PATH",
+            scope as u8
+        ))),
+        IntentScope::GithubCommit => Err(EnclaveError::GenericError(format!(
+            "Intent scope {} is only signed by /process_github_commit",
+            scope as u8
+        ))),
+        IntentScope::SuiTransaction => Err(EnclaveError::GenericError(format!(
+            "Intent scope {} is only signed by /process_transaction",
+            scope as u8
+        ))),
+    }
+}
+
+/// BCS signing payload of a fixed weather reading. It must match `test_serde`
+/// in `move/enclave/sources/enclave.move`, and is checked against
+/// `BCS_GOLDEN_HEX` at startup when that is set.
+pub fn serde_fixture() -> Vec<u8> {
+    let payload = WeatherResponse {
+        location: "San Francisco".to_string(),
+        temperature: 13,
+    };
+    let intent_msg = IntentMessage::new(payload, 1744038900000, IntentScope::Weather);
+    bcs::to_bytes(&intent_msg).expect("should not fail")
+}
+
+/// Weather reading fetched from the upstream data source.
+#[derive(Clone)]
+struct UpstreamReading {
+    location: String,
+    temperature: u64,
+    last_updated_epoch: u64,
+    latency_ms: u64,
+    /// The fields selected by `SIGNED_FIELDS`, empty when it isn't set.
+    fields: Vec<SignedField>,
+}
+
+/// `err` followed by its sources, since reqwest only displays the outermost
+/// error, e.g. "error sending request" rather than why the connection failed.
+fn with_causes(err: &dyn std::error::Error) -> String {
+    let mut message = err.to_string();
+    let mut source = err.source();
+    while let Some(cause) = source {
+        message = format!("{}: {}", message, cause);
+        source = cause.source();
+    }
+    message
+}
+
+/// Error for a failed request to `url`: [EnclaveError::UpstreamTls] when the
+/// TLS handshake failed, with the full error logged here, otherwise
+/// `context` and the error's causes.
+pub(crate) fn upstream_error(
+    state: &AppState,
+    url: &reqwest::Url,
+    context: &str,
+    err: reqwest::Error,
+) -> EnclaveError {
+    let err = err.without_url();
+    let detail = with_causes(&err);
+    match upstream_tls::classify(&err) {
+        Some(reason) => {
+            let host = url.host_str().unwrap_or_default().to_string();
+            warn!("TLS handshake with {} failed: {}: {}", host, reason, detail);
+            EnclaveError::UpstreamTls {
+                host,
+                reason,
+                detail: state.config.upstream_tls_detail.then_some(detail),
+            }
+        }
+        None => EnclaveError::GenericError(format!("{}: {}", context, detail)),
+    }
+}
+
+/// Current weather endpoint of api.weatherapi.com, the default
+/// `WEATHER_API_URL`.
+pub const WEATHER_API_URL: &str = "https://api.weatherapi.com/v1/current.json";
+
+/// Name of the weather API in `/health_check`'s
+/// `ms_since_last_successful_fetch`.
+pub const WEATHER_SOURCE: &str = "weather";
+
+/// Location queried by [check_api_key].
+const API_KEY_CHECK_LOCATION: &str = "London";
+
+/// Make a test call to api.weatherapi.com with `api_key`, failing unless the
+/// upstream accepts the key and returns a reading.
+pub async fn check_api_key(state: &AppState, api_key: &str) -> Result<(), EnclaveError> {
+    let mut url = state.config.weather_api_url.clone();
+    url.query_pairs_mut()
+        .append_pair("key", api_key)
+        .append_pair("q", API_KEY_CHECK_LOCATION);
+    let response = state.upstream_client.get(url).send().await.map_err(|e| {
+        upstream_error(
+            state,
+            &state.config.weather_api_url,
+            "Failed to check API key",
+            e,
+        )
+    })?;
+    let status = response.status();
+    let json = response.json::<Value>().await.unwrap_or(Value::Null);
+    check_api_key_response(status, &json)
+}
+
+fn check_api_key_response(status: StatusCode, json: &Value) -> Result<(), EnclaveError> {
+    if !status.is_success() {
+        // weatherapi.com explains rejected keys in `error.message`
+        return Err(EnclaveError::GenericError(format!(
+            "Upstream rejected the API key with status {}: {}",
+            status,
+            json["error"]["message"]
+                .as_str()
+                .unwrap_or("no reason given")
+        )));
+    }
+    if json["current"]["temp_c"].is_null() {
+        return Err(EnclaveError::GenericError(
+            "Upstream returned no reading for the API key check".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// weatherapi.com error codes, from `error.code`, that mean what the client
+/// asked for doesn't exist rather than that the upstream failed, with the
+/// message returned for each.
+const UPSTREAM_NOT_FOUND_CODES: &[(u64, &str)] = &[(1006, "No matching location found")];
+
+/// The 404 for an upstream error object in `json` whose code is listed in
+/// [UPSTREAM_NOT_FOUND_CODES], naming the requested `location`.
+fn mapped_upstream_error(json: &Value, location: &str) -> Option<EnclaveError> {
+    let code = json["error"]["code"].as_u64()?;
+    UPSTREAM_NOT_FOUND_CODES
+        .iter()
+        .find(|(mapped, _)| *mapped == code)
+        .map(|(_, message)| EnclaveError::NotFound(format!("{}: {}", message, location)))
+}
+
+/// Fetch the current weather for `request` from api.weatherapi.com.
+async fn fetch_rest(
+    state: &AppState,
+    request: &WeatherRequest,
+    tenant: Option<&str>,
+) -> Result<UpstreamReading, EnclaveError> {
+    let query = request.upstream_query(&state.config.upstream_params)?;
+    let api_key = state.api_keys.select(tenant)?;
+    let mut url = state.config.weather_api_url.clone();
+    url.query_pairs_mut()
+        .append_pair("key", &api_key)
+        .append_pair("q", &request.location)
+        .extend_pairs(query);
+    // reqwest errors include the URL, which would reveal the key.
+    let fetch_started = Instant::now();
+    let response = state.upstream_client.get(url).send().await.map_err(|e| {
+        upstream_error(
+            state,
+            &state.config.weather_api_url,
+            "Failed to get weather response",
+            e,
+        )
     })?;
     let json = response.json::<Value>().await.map_err(|e| {
-        EnclaveError::GenericError(format!("Failed to parse weather response: {}", e))
+        EnclaveError::GenericError(format!(
+            "Failed to parse weather response: {}",
+            e.without_url()
+        ))
     })?;
-    let location = json["location"]["name"].as_str().unwrap_or("Unknown");
-    let temperature = json["current"]["temp_c"].as_f64().unwrap_or(0.0) as u64;
-    let last_updated_epoch = json["current"]["last_updated_epoch"].as_u64().unwrap_or(0);
-    let last_updated_timestamp_ms = last_updated_epoch * 1000_u64;
-    let current_timestamp = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .map_err(|e| EnclaveError::GenericError(format!("Failed to get current timestamp: {}", e)))?
-        .as_millis() as u64;
+    let latency_ms = fetch_started.elapsed().as_millis() as u64;
+    if state.config.map_upstream_errors {
+        if let Some(err) = mapped_upstream_error(&json, &request.location) {
+            return Err(err);
+        }
+    }
+    rest_reading(state, &json, latency_ms)
+}
+
+/// Read a response of api.weatherapi.com, fetched in `latency_ms`.
+fn rest_reading(
+    state: &AppState,
+    json: &Value,
+    latency_ms: u64,
+) -> Result<UpstreamReading, EnclaveError> {
+    let fields = select_fields(json, &state.config.signed_fields)?;
+    Ok(UpstreamReading {
+        location: json["location"]["name"]
+            .as_str()
+            .unwrap_or("Unknown")
+            .to_string(),
+        temperature: parse_temperature(
+            &json["current"]["temp_c"],
+            state.config.temperature_parsing,
+        )?,
+        last_updated_epoch: last_updated_secs(
+            json,
+            state.config.timestamp_source,
+            state.config.timestamp_tolerance,
+        )?,
+        latency_ms,
+        fields,
+    })
+}
+
+/// Fetch the current weather for `request` from the gRPC upstream. `lang`
+/// and `params` are specific to the REST API, so they are refused rather
+/// than ignored.
+#[cfg(feature = "grpc")]
+async fn fetch_grpc(
+    upstream: &GrpcUpstream,
+    request: &WeatherRequest,
+) -> Result<UpstreamReading, EnclaveError> {
+    if request.lang.is_some() || !request.params.is_empty() {
+        return Err(EnclaveError::GenericError(
+            "lang and params are not supported by the gRPC upstream".to_string(),
+        ));
+    }
+    let fetch_started = Instant::now();
+    let reading = upstream.fetch(&request.location).await?;
+    Ok(UpstreamReading {
+        location: reading.location,
+        temperature: reading.temperature as u64,
+        last_updated_epoch: reading.last_updated_epoch,
+        latency_ms: fetch_started.elapsed().as_millis() as u64,
+        fields: Vec::new(),
+    })
+}
+
+/// Intent scope for a request, falling back to the configured default.
+fn intent_scope(state: &AppState, intent: Option<u8>) -> Result<IntentScope, EnclaveError> {
+    match intent {
+        Some(intent) => IntentScope::try_from(intent),
+        None if state.config.sign_attestation_digest => {
+            Ok(IntentScope::WeatherWithAttestationDigest)
+        }
+        None if state.config.sign_upstream_latency => Ok(IntentScope::WeatherWithUpstreamLatency),
+        None if !state.config.signed_fields.is_empty() => Ok(IntentScope::WeatherFields),
+        None => Ok(IntentScope::Weather),
+    }
+}
+
+/// Fetch the current weather for `request`, counting the outcome in the
+/// stats and recording a success for `/health_check`.
+async fn fetch_reading(
+    state: &AppState,
+    request: &WeatherRequest,
+    tenant: Option<&str>,
+) -> Result<UpstreamReading, EnclaveError> {
+    let reading = fetch_upstream(state, request, tenant).await;
+    state.stats.record_upstream(reading.is_ok());
+    if reading.is_ok() {
+        state.last_fetches.record(WEATHER_SOURCE);
+    }
+    reading
+}
+
+/// Fetch the current weather for `request` from the configured upstream.
+async fn fetch_upstream(
+    state: &AppState,
+    request: &WeatherRequest,
+    tenant: Option<&str>,
+) -> Result<UpstreamReading, EnclaveError> {
+    #[cfg(feature = "mock-weather")]
+    if let Some(mock) = &state.config.mock_weather {
+        let json = mock.response(&request.location, crate::common::wall_clock_ms() / 1000);
+        return rest_reading(state, &json, 0);
+    }
+    #[cfg(feature = "grpc")]
+    if let Some(upstream) = &state.config.grpc_upstream {
+        if !state.config.signed_fields.is_empty() {
+            return Err(EnclaveError::GenericError(
+                "SIGNED_FIELDS is not supported by the gRPC upstream".to_string(),
+            ));
+        }
+        if state.config.timestamp_source != TimestampSource::Epoch {
+            return Err(EnclaveError::GenericError(
+                "TIMESTAMP_SOURCE=local_time is not supported by the gRPC upstream".to_string(),
+            ));
+        }
+        return fetch_grpc(upstream, request).await;
+    }
+    fetch_rest(state, request, tenant).await
+}
+
+/// The payload signed for `reading` under `scope`, with the time the upstream
+/// observed it. Readings over an hour old are refused.
+async fn reading_payload(
+    state: &AppState,
+    reading: UpstreamReading,
+    scope: IntentScope,
+) -> Result<(WeatherPayload, u64), EnclaveError> {
+    let last_updated_timestamp_ms = reading.last_updated_epoch * 1000_u64;
+    let current_timestamp = current_timestamp_ms(&state.config).await?;
 
     // 1 hour in milliseconds = 60 * 60 * 1000 = 3_600_000
     if last_updated_timestamp_ms + 3_600_000 < current_timestamp {
@@ -58,61 +723,1179 @@ pub async fn process_data(
         ));
     }
 
-    Ok(Json(to_signed_response(
+    let payload = weather_payload(
+        reading.location,
+        reading.temperature,
+        reading.latency_ms,
+        reading.fields,
+        scope,
+        state.boot_attestation.as_ref(),
+    )?;
+
+    Ok((payload, last_updated_timestamp_ms))
+}
+
+/// Sign `payload` under `scope` at `source_timestamp_ms`, the time the
+/// upstream observed the data, in the layout and formats the enclave is
+/// configured for: bucketed, with an expiry or sequence number, and as a JWT.
+pub fn sign_payload<T: Serialize + Clone>(
+    state: &AppState,
+    payload: T,
+    source_timestamp_ms: u64,
+    scope: IntentScope,
+) -> ProcessedDataResponse<IntentMessage<T>> {
+    // Taken last, so a request that fails doesn't leave a gap
+    let sequence = state
+        .config
+        .sign_sequence
+        .then(|| state.sequences.next(scope));
+    let response = sign_with_sequence(state, payload, source_timestamp_ms, scope, sequence);
+    state.stats.record_signed(scope as u8);
+    response
+}
+
+/// Sign each of `payloads`, a payload and its source timestamp, as
+/// [sign_payload] does, and pass the responses to `check`. With
+/// `SIGN_SEQUENCE` they get consecutive sequence numbers, which are only
+/// taken once `check` accepts the responses, so a refused request uses none.
+fn sign_checked<T: Serialize + Clone>(
+    state: &AppState,
+    payloads: &[(T, u64)],
+    scope: IntentScope,
+    check: impl Fn(&[ProcessedDataResponse<IntentMessage<T>>]) -> Result<(), EnclaveError>,
+) -> Result<Vec<ProcessedDataResponse<IntentMessage<T>>>, EnclaveError> {
+    let sign = |first: Option<u64>| -> Result<Vec<_>, EnclaveError> {
+        let responses: Vec<_> = payloads
+            .iter()
+            .zip(0u64..)
+            .map(|((payload, timestamp_ms), i)| {
+                let sequence = first.map(|first| first + i);
+                sign_with_sequence(state, payload.clone(), *timestamp_ms, scope, sequence)
+            })
+            .collect();
+        check(&responses)?;
+        Ok(responses)
+    };
+    let responses = if state.config.sign_sequence {
+        state
+            .sequences
+            .next_with(scope, payloads.len() as u64, |first| sign(Some(first)))?
+    } else {
+        sign(None)?
+    };
+    for _ in &responses {
+        state.stats.record_signed(scope as u8);
+    }
+    Ok(responses)
+}
+
+/// [sign_payload] with a sequence number already taken, or none.
+fn sign_with_sequence<T: Serialize + Clone>(
+    state: &AppState,
+    payload: T,
+    source_timestamp_ms: u64,
+    scope: IntentScope,
+    sequence: Option<u64>,
+) -> ProcessedDataResponse<IntentMessage<T>> {
+    let timestamp_ms = bucket_timestamp(source_timestamp_ms, state.config.timestamp_bucket_ms);
+    let expires_at_ms = match state.config.sign_expiry_ms {
+        0 => None,
+        expiry_ms => Some(timestamp_ms.saturating_add(expiry_ms)),
+    };
+    let mut response = to_signed_response(
         &state.eph_kp,
-        WeatherResponse {
-            location: location.to_string(),
-            temperature,
+        payload,
+        timestamp_ms,
+        expires_at_ms,
+        sequence,
+        scope,
+        state.config.signing_format,
+    );
+    if state.config.sign_jwt {
+        response.jwt = Some(jwt::encode(&state.eph_kp, &response.response));
+    }
+    response
+}
+
+/// A signed weather response and its expiry headers, see `EXPIRY_HEADERS`.
+pub type SignedWeatherResponse = (
+    HeaderMap,
+    Json<ProcessedDataResponse<IntentMessage<WeatherPayload>>>,
+);
+
+pub async fn process_data(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(request): Json<ProcessDataRequest<WeatherRequest>>,
+) -> Result<SignedWeatherResponse, EnclaveError> {
+    process_weather_request(&state, &headers, request).await
+}
+
+/// Query parameters for `GET /process_data`, the same fields as a
+/// `ProcessDataRequest<WeatherRequest>` body. Upstream `params` can only be
+/// sent with POST.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+pub struct WeatherQuery {
+    pub location: String,
+    #[serde(default)]
+    pub lang: Option<String>,
+    /// Intent scope to sign under, as its `u8` value.
+    #[serde(default)]
+    pub intent: Option<u8>,
+}
+
+impl From<WeatherQuery> for ProcessDataRequest<WeatherRequest> {
+    fn from(query: WeatherQuery) -> Self {
+        ProcessDataRequest {
+            payload: WeatherRequest {
+                location: query.location,
+                lang: query.lang,
+                params: BTreeMap::new(),
+            },
+            intent: query.intent,
+        }
+    }
+}
+
+/// `GET /process_data`, for clients that can't send a body, served when
+/// `PROCESS_DATA_GET` is set. Handled exactly like the POST form.
+pub async fn process_data_get(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<WeatherQuery>,
+) -> Result<SignedWeatherResponse, EnclaveError> {
+    process_weather_request(&state, &headers, query.into()).await
+}
+
+/// Fetch and sign the weather for `request`, shared by both forms of
+/// `/process_data`.
+async fn process_weather_request(
+    state: &AppState,
+    headers: &HeaderMap,
+    request: ProcessDataRequest<WeatherRequest>,
+) -> Result<SignedWeatherResponse, EnclaveError> {
+    let scope = intent_scope(state, request.intent)?;
+    let max_bytes = max_response_bytes(state, headers)?;
+    let tenant = headers
+        .get(TENANT_HEADER)
+        .and_then(|tenant| tenant.to_str().ok());
+    let reading = fetch_reading(state, &request.payload, tenant).await?;
+    let payload = reading_payload(state, reading, scope).await?;
+    let response = sign_checked(state, &[payload], scope, |responses| {
+        check_response_size(&responses[0], max_bytes)
+    })?
+    .remove(0);
+    let headers = response_headers(state, [&response]).await?;
+    Ok((headers, Json(response)))
+}
+
+/// The client's [MAX_RESPONSE_BYTES_HEADER], read before any upstream call
+/// so an invalid value fails the request early. `None` without the header or
+/// when `HONOR_MAX_RESPONSE_BYTES` is off.
+fn max_response_bytes(
+    state: &AppState,
+    headers: &HeaderMap,
+) -> Result<Option<usize>, EnclaveError> {
+    if !state.config.honor_max_response_bytes {
+        return Ok(None);
+    }
+    headers
+        .get(MAX_RESPONSE_BYTES_HEADER)
+        .map(|value| {
+            value
+                .to_str()
+                .ok()
+                .and_then(|value| value.trim().parse().ok())
+                .ok_or_else(|| {
+                    EnclaveError::GenericError(format!(
+                        "Invalid {}: {:?}, expected a number of bytes",
+                        MAX_RESPONSE_BYTES_HEADER,
+                        String::from_utf8_lossy(value.as_bytes())
+                    ))
+                })
+        })
+        .transpose()
+}
+
+/// Fail if the JSON body of `response` is larger than `max_bytes`, rather
+/// than send the client a response it can't handle. Run through
+/// [sign_checked], so a refused response uses no sequence number.
+fn check_response_size<T: Serialize>(
+    response: &T,
+    max_bytes: Option<usize>,
+) -> Result<(), EnclaveError> {
+    let max_bytes = match max_bytes {
+        Some(max_bytes) => max_bytes,
+        None => return Ok(()),
+    };
+    let size = serde_json::to_vec(response)
+        .map_err(|e| EnclaveError::GenericError(e.to_string()))?
+        .len();
+    if size > max_bytes {
+        return Err(EnclaveError::GenericError(format!(
+            "Signed response is {} bytes, more than the {} of {} bytes",
+            size, MAX_RESPONSE_BYTES_HEADER, max_bytes
+        )));
+    }
+    Ok(())
+}
+
+/// Expiry headers for `responses` with `EXPIRY_HEADERS`, following the one
+/// that expires first. Empty otherwise.
+async fn response_headers<'a>(
+    state: &AppState,
+    responses: impl IntoIterator<Item = &'a ProcessedDataResponse<IntentMessage<WeatherPayload>>>,
+) -> Result<HeaderMap, EnclaveError> {
+    if !state.config.expiry_headers {
+        return Ok(HeaderMap::new());
+    }
+    let expires_at_ms = responses
+        .into_iter()
+        .filter_map(|response| response.response.expires_at_ms)
+        .min();
+    match expires_at_ms {
+        Some(expires_at_ms) => Ok(expiry_headers(
+            expires_at_ms,
+            current_timestamp_ms(&state.config).await?,
+        )),
+        None => Ok(HeaderMap::new()),
+    }
+}
+
+/// For each request in a batch, the index of the request whose upstream
+/// reading it is signed from. With `dedup`, repeats of an earlier identical
+/// request reuse its reading; otherwise every request is fetched itself.
+fn batch_sources(requests: &[WeatherRequest], dedup: bool) -> Vec<usize> {
+    requests
+        .iter()
+        .enumerate()
+        .map(|(i, request)| {
+            if dedup {
+                requests.iter().position(|r| r == request).unwrap_or(i)
+            } else {
+                i
+            }
+        })
+        .collect()
+}
+
+/// Run `fetch` on each item with at most `max_fanout` running at once, so
+/// one request can't take all of the upstream's capacity. Results are in the
+/// order of `items`. The first error is returned and the other fetches are
+/// cancelled. With a limit of 1 the items are fetched one after another, in
+/// order.
+pub async fn fetch_bounded<T, R, F, Fut>(
+    items: Vec<T>,
+    max_fanout: usize,
+    fetch: F,
+) -> Result<Vec<R>, EnclaveError>
+where
+    F: Fn(T) -> Fut,
+    Fut: Future<Output = Result<R, EnclaveError>> + Send + 'static,
+    R: Send + 'static,
+{
+    if max_fanout <= 1 {
+        let mut results = Vec::with_capacity(items.len());
+        for item in items {
+            results.push(fetch(item).await?);
+        }
+        return Ok(results);
+    }
+    let permits = Arc::new(Semaphore::new(max_fanout));
+    let mut tasks = JoinSet::new();
+    let count = items.len();
+    for (i, item) in items.into_iter().enumerate() {
+        let permits = permits.clone();
+        let fetch = fetch(item);
+        tasks.spawn(async move {
+            let _permit = permits.acquire_owned().await;
+            (i, fetch.await)
+        });
+    }
+    let mut results: Vec<Option<R>> = (0..count).map(|_| None).collect();
+    while let Some(joined) = tasks.join_next().await {
+        let (i, result) =
+            joined.map_err(|e| EnclaveError::GenericError(format!("Fetch task failed: {}", e)))?;
+        results[i] = Some(result?);
+    }
+    Ok(results
+        .into_iter()
+        .map(|result| result.expect("every task was joined"))
+        .collect())
+}
+
+/// Fetch and sign several weather requests, under the same intent scope. If
+/// any request fails the whole batch fails. See `BATCH_DEDUP` for repeated
+/// requests and `BATCH_MAX_FANOUT` for how many are fetched at once.
+pub async fn process_data_batch(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(request): Json<ProcessDataRequest<BatchWeatherRequest>>,
+) -> Result<
+    (
+        HeaderMap,
+        Json<Vec<ProcessedDataResponse<IntentMessage<WeatherPayload>>>>,
+    ),
+    EnclaveError,
+> {
+    let requests = request.payload.requests;
+    if requests.is_empty() || requests.len() > MAX_BATCH_SIZE {
+        return Err(EnclaveError::GenericError(format!(
+            "A batch must hold between 1 and {} requests, got {}",
+            MAX_BATCH_SIZE,
+            requests.len()
+        )));
+    }
+    let scope = intent_scope(&state, request.intent)?;
+    let max_bytes = max_response_bytes(&state, &headers)?;
+    let tenant = headers
+        .get(TENANT_HEADER)
+        .and_then(|tenant| tenant.to_str().ok());
+    let sources = batch_sources(&requests, state.config.batch_dedup);
+    // Each request that is its own source is fetched, in request order
+    let fetched: Vec<usize> = (0..requests.len()).filter(|&i| sources[i] == i).collect();
+    let readings = fetch_bounded(
+        fetched.iter().map(|&i| requests[i].clone()).collect(),
+        state.config.batch_max_fanout,
+        |request| {
+            let state = state.clone();
+            let tenant = tenant.map(str::to_string);
+            async move { fetch_reading(&state, &request, tenant.as_deref()).await }
         },
-        last_updated_timestamp_ms,
-        IntentScope::Weather,
-    )))
+    )
+    .await?;
+    let mut payloads = Vec::with_capacity(requests.len());
+    for source in sources {
+        let position = fetched
+            .binary_search(&source)
+            .expect("every source is fetched");
+        let reading = readings[position].clone();
+        payloads.push(reading_payload(&state, reading, scope).await?);
+    }
+    let responses = sign_checked(&state, &payloads, scope, |responses| {
+        check_response_size(&responses, max_bytes)
+    })?;
+    let headers = response_headers(&state, &responses).await?;
+    Ok((headers, Json(responses)))
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::common::IntentMessage;
+    use crate::api_keys::ApiKeys;
+    use crate::common::{wall_clock_ms, IntentMessage};
+    use crate::config::Config;
+    use crate::test_utils::{mock_weather_upstream, UNKNOWN_LOCATION};
     use axum::{extract::State, Json};
     use fastcrypto::{ed25519::Ed25519KeyPair, traits::KeyPair};
 
     #[tokio::test]
     async fn test_process_data() {
-        let state = Arc::new(AppState {
-            eph_kp: Ed25519KeyPair::generate(&mut rand::thread_rng()),
-            api_key: "045a27812dbe456392913223221306".to_string(),
-        });
+        let state = Arc::new(
+            AppState::new(
+                Ed25519KeyPair::generate(&mut rand::thread_rng()),
+                ApiKeys::single("045a27812dbe456392913223221306".to_string()),
+                Config::default(),
+            )
+            .unwrap(),
+        );
         let signed_weather_response = process_data(
             State(state),
+            HeaderMap::new(),
             Json(ProcessDataRequest {
                 payload: WeatherRequest {
                     location: "San Francisco".to_string(),
+                    lang: None,
+                    params: BTreeMap::new(),
                 },
+                intent: None,
             }),
         )
         .await
         .unwrap();
+        assert!(matches!(
+            &signed_weather_response.1.response.data,
+            WeatherPayload::Plain(weather) if weather.location == "San Francisco"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_expiry_headers_follow_signed_expiry() {
+        use crate::expiry::http_date;
+        use axum::http::header::{CACHE_CONTROL, EXPIRES};
+        let state = AppState::new(
+            Ed25519KeyPair::generate(&mut rand::thread_rng()),
+            ApiKeys::single(String::new()),
+            Config {
+                sign_expiry_ms: 60_000,
+                expiry_headers: true,
+                ..Config::default()
+            },
+        )
+        .unwrap();
+        let now_ms = current_timestamp_ms(&state.config).await.unwrap();
+        let signed = |expires_at_ms: u64| {
+            to_signed_response(
+                &state.eph_kp,
+                WeatherPayload::Plain(WeatherResponse {
+                    location: "Paris".to_string(),
+                    temperature: 20,
+                }),
+                now_ms,
+                Some(expires_at_ms),
+                None,
+                IntentScope::Weather,
+                SigningFormat::Bcs,
+            )
+        };
+        let later = signed(now_ms + 60_000);
+        let sooner = signed(now_ms + 30_000);
+
+        let headers = response_headers(&state, [&later]).await.unwrap();
+        let max_age: u64 = headers[CACHE_CONTROL]
+            .to_str()
+            .unwrap()
+            .strip_prefix("max-age=")
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!((59..=60).contains(&max_age), "{}", max_age);
+        assert_eq!(headers[EXPIRES], http_date(now_ms + 60_000).as_str());
+
+        // A batch is cached no longer than its first response to expire
+        let headers = response_headers(&state, [&later, &sooner]).await.unwrap();
+        assert_eq!(headers[EXPIRES], http_date(now_ms + 30_000).as_str());
+
+        let state = AppState {
+            config: Config::default(),
+            ..state
+        };
+        assert!(response_headers(&state, [&later]).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_fetch_bounded() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::time::Duration;
+
+        for max_fanout in [1, 3] {
+            let running = Arc::new(AtomicUsize::new(0));
+            let peak = Arc::new(AtomicUsize::new(0));
+            let results = fetch_bounded((0..10).collect(), max_fanout, |i: u32| {
+                let running = running.clone();
+                let peak = peak.clone();
+                async move {
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(5)).await;
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    Ok(i * 2)
+                }
+            })
+            .await
+            .unwrap();
+            assert_eq!(results, (0..10).map(|i| i * 2).collect::<Vec<_>>());
+            assert_eq!(peak.load(Ordering::SeqCst), max_fanout);
+        }
+
+        let failed = fetch_bounded(vec![1, 2, 3], 2, |i: u32| async move {
+            if i == 2 {
+                return Err(EnclaveError::GenericError("upstream down".to_string()));
+            }
+            Ok(i)
+        })
+        .await;
+        assert!(matches!(failed, Err(EnclaveError::GenericError(e)) if e == "upstream down"));
+    }
+
+    #[test]
+    fn test_batch_sources() {
+        let request = |location: &str| WeatherRequest {
+            location: location.to_string(),
+            lang: None,
+            params: BTreeMap::new(),
+        };
+        let mut requests = vec![
+            request("Paris"),
+            request("London"),
+            request("Paris"),
+            request("London"),
+            request("Rome"),
+        ];
+        requests.push(WeatherRequest {
+            lang: Some("fr".to_string()),
+            ..request("Paris")
+        });
+        assert_eq!(batch_sources(&requests, false), [0, 1, 2, 3, 4, 5]);
+        // Only identical requests share a reading, so the French one is
+        // fetched separately.
+        assert_eq!(batch_sources(&requests, true), [0, 1, 0, 1, 4, 5]);
+    }
+
+    #[test]
+    fn test_weather_payload_scope() {
+        let plain = weather_payload(
+            "Paris".to_string(),
+            20,
+            150,
+            Vec::new(),
+            IntentScope::Weather,
+            None,
+        )
+        .unwrap();
+        assert!(matches!(plain, WeatherPayload::Plain(_)));
+
+        let boot_attestation = BootAttestation {
+            document: vec![1, 2, 3],
+            digest: vec![0xab; 48],
+            generated_at_ms: 1744038900000,
+        };
+        let with_digest = weather_payload(
+            "Paris".to_string(),
+            20,
+            150,
+            Vec::new(),
+            IntentScope::WeatherWithAttestationDigest,
+            Some(&boot_attestation),
+        )
+        .unwrap();
+        assert!(matches!(
+            with_digest,
+            WeatherPayload::WithAttestationDigest(weather) if weather.attestation_digest == boot_attestation.digest
+        ));
+
+        assert!(weather_payload(
+            "Paris".to_string(),
+            20,
+            150,
+            Vec::new(),
+            IntentScope::WeatherWithAttestationDigest,
+            None
+        )
+        .is_err());
+
+        let with_latency = weather_payload(
+            "Paris".to_string(),
+            20,
+            150,
+            Vec::new(),
+            IntentScope::WeatherWithUpstreamLatency,
+            None,
+        )
+        .unwrap();
+        assert!(matches!(
+            with_latency,
+            WeatherPayload::WithUpstreamLatency(weather) if weather.upstream_latency_ms == 150
+        ));
+    }
+
+    #[test]
+    fn test_weather_query() {
+        let parse = |uri: &str| Query::<WeatherQuery>::try_from_uri(&uri.parse().unwrap());
+        let Query(query) =
+            parse("/process_data?location=San%20Francisco&lang=fr&intent=2").unwrap();
+        let request = ProcessDataRequest::from(query);
+        assert_eq!(
+            request.payload,
+            WeatherRequest {
+                location: "San Francisco".to_string(),
+                lang: Some("fr".to_string()),
+                params: BTreeMap::new(),
+            }
+        );
+        assert_eq!(request.intent, Some(2));
+
+        assert!(parse("/process_data?lang=fr").is_err());
+        assert!(parse("/process_data?location=Paris&key=x").is_err());
+        assert!(parse("/process_data?location=Paris&intent=x").is_err());
+    }
+
+    #[test]
+    fn test_upstream_params() {
+        let allowed = vec!["lang".to_string(), "key".to_string()];
+        let params = BTreeMap::from([("lang".to_string(), "fr".to_string())]);
+        assert_eq!(
+            upstream_params(&params, &allowed).unwrap(),
+            [("lang", "fr")]
+        );
+        assert!(upstream_params(&BTreeMap::new(), &[]).unwrap().is_empty());
+        // Reserved names are rejected even when listed.
+        for name in ["aqi", "key", "q", "lang"] {
+            let params = BTreeMap::from([(name.to_string(), "x".to_string())]);
+            assert!(upstream_params(&params, &allowed).is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_upstream_lang() {
+        assert_eq!(upstream_lang(None).unwrap(), None);
+        assert_eq!(upstream_lang(Some("en")).unwrap(), None);
+        assert_eq!(upstream_lang(Some("fr")).unwrap(), Some("fr"));
+        assert_eq!(upstream_lang(Some("zh_tw")).unwrap(), Some("zh_tw"));
+        assert!(upstream_lang(Some("FR")).is_err());
+        assert!(upstream_lang(Some("fr&key=x")).is_err());
+    }
+
+    #[test]
+    fn test_upstream_query() {
+        let request = WeatherRequest {
+            location: "Paris".to_string(),
+            lang: Some("fr".to_string()),
+            params: BTreeMap::from([("aqi".to_string(), "yes".to_string())]),
+        };
+        assert_eq!(
+            request.upstream_query(&["aqi".to_string()]).unwrap(),
+            [("lang", "fr"), ("aqi", "yes")]
+        );
+        assert!(request.upstream_query(&[]).is_err());
+    }
+
+    #[test]
+    fn test_parse_local_time() {
+        assert_eq!(parse_local_time("2025-04-07 08:21"), Some(1744014060));
+        assert_eq!(parse_local_time("2025-04-07 8:21"), Some(1744014060));
+        assert_eq!(parse_local_time("2000-02-29 23:59"), Some(951868740));
+        assert_eq!(parse_local_time("1970-01-01 00:00"), Some(0));
+        for invalid in [
+            "2025-04-07",
+            "2025-13-01 00:00",
+            "2025-04-07 24:00",
+            "yesterday",
+        ] {
+            assert_eq!(parse_local_time(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_last_updated_secs() {
+        use serde_json::json;
+        // San Francisco at 15:15 UTC, seven hours behind during DST
+        let response = |last_updated_epoch: u64, last_updated: &str| {
+            json!({
+                "location": {
+                    "name": "San Francisco",
+                    "localtime_epoch": 1744039290,
+                    "localtime": "2025-04-07 8:21",
+                },
+                "current": {
+                    "last_updated_epoch": last_updated_epoch,
+                    "last_updated": last_updated,
+                },
+            })
+        };
+        let tolerance = Duration::from_secs(60);
+        let consistent = response(1744038900, "2025-04-07 08:15");
+        for source in [TimestampSource::Epoch, TimestampSource::LocalTime] {
+            assert_eq!(
+                last_updated_secs(&consistent, source, tolerance).unwrap(),
+                1744038900
+            );
+        }
+        // Within the tolerance each source signs its own value
+        let close = response(1744038930, "2025-04-07 08:15");
+        assert_eq!(
+            last_updated_secs(&close, TimestampSource::Epoch, tolerance).unwrap(),
+            1744038930
+        );
+        assert_eq!(
+            last_updated_secs(&close, TimestampSource::LocalTime, tolerance).unwrap(),
+            1744038900
+        );
+
+        // An hour apart, e.g. a DST rule applied on one side only
+        let mismatched = response(1744038900, "2025-04-07 09:15");
+        for source in [TimestampSource::Epoch, TimestampSource::LocalTime] {
+            let err = last_updated_secs(&mismatched, source, tolerance).unwrap_err();
+            assert!(err.to_string().contains("3600s apart"), "{}", err);
+        }
+        assert!(last_updated_secs(
+            &mismatched,
+            TimestampSource::Epoch,
+            Duration::from_secs(3600)
+        )
+        .is_ok());
+
+        // Only the epoch, as the mock upstreams send
+        let epoch_only = json!({ "current": { "last_updated_epoch": 1744038900 } });
+        assert_eq!(
+            last_updated_secs(&epoch_only, TimestampSource::Epoch, tolerance).unwrap(),
+            1744038900
+        );
+        assert!(last_updated_secs(&epoch_only, TimestampSource::LocalTime, tolerance).is_err());
+        // The local time can't be normalized without the location's offset
+        let mut no_offset = consistent.clone();
+        no_offset["location"] = json!({ "name": "San Francisco" });
+        assert!(last_updated_secs(&no_offset, TimestampSource::Epoch, tolerance).is_ok());
+        assert!(last_updated_secs(&no_offset, TimestampSource::LocalTime, tolerance).is_err());
+    }
+
+    #[test]
+    fn test_parse_temperature() {
+        use serde_json::json;
+        for parsing in [TemperatureParsing::Strict, TemperatureParsing::Lenient] {
+            assert_eq!(parse_temperature(&json!(13), parsing).unwrap(), 13);
+            assert_eq!(parse_temperature(&json!(13.7), parsing).unwrap(), 13);
+            for invalid in [
+                json!("warm"),
+                json!(null),
+                json!(true),
+                json!("NaN"),
+                json!([13]),
+                json!(-5),
+                json!("-5"),
+            ] {
+                assert!(parse_temperature(&invalid, parsing).is_err(), "{}", invalid);
+            }
+        }
+        assert!(parse_temperature(&json!("13.7"), TemperatureParsing::Strict).is_err());
         assert_eq!(
-            signed_weather_response.response.data.location,
-            "San Francisco"
+            parse_temperature(&json!(" 13.7 "), TemperatureParsing::Lenient).unwrap(),
+            13
         );
     }
 
     #[test]
     fn test_serde() {
-        // test result should be consistent with test_serde in `move/enclave/sources/enclave.move`.
+        // The Move tests check the same vector, see `crate::vectors`.
+        use fastcrypto::encoding::{Encoding, Hex};
+        assert_eq!(
+            Hex::encode(serde_fixture()),
+            crate::vectors::committed("weather").signing_payload
+        );
+    }
+
+    #[test]
+    fn test_serde_with_attestation_digest() {
+        use fastcrypto::encoding::{Encoding, Hex};
+        let payload = WeatherPayload::WithAttestationDigest(WeatherResponseWithAttestationDigest {
+            location: "San Francisco".to_string(),
+            temperature: 13,
+            attestation_digest: vec![0xab; 48],
+        });
+        let timestamp = 1744038900000;
+        let intent_msg = IntentMessage::new(
+            payload,
+            timestamp,
+            IntentScope::WeatherWithAttestationDigest,
+        );
+        let signing_payload = bcs::to_bytes(&intent_msg).expect("should not fail");
+        assert_eq!(
+            Hex::encode(signing_payload),
+            crate::vectors::committed("weather_with_attestation_digest").signing_payload
+        );
+    }
+
+    #[test]
+    fn test_serde_with_expiry() {
+        // The Move tests check the same vector, see `crate::vectors`.
         use fastcrypto::encoding::{Encoding, Hex};
         let payload = WeatherResponse {
             location: "San Francisco".to_string(),
             temperature: 13,
         };
-        let timestamp = 1744038900000;
-        let intent_msg = IntentMessage::new(payload, timestamp, IntentScope::Weather);
+        let mut intent_msg = IntentMessage::new(payload, 1744038900000, IntentScope::Weather);
+        assert!(serde_json::to_value(&intent_msg)
+            .unwrap()
+            .get("expires_at_ms")
+            .is_none());
+        intent_msg.version = Some(nautilus_verifier::intent::EXPIRY_LAYOUT);
+        intent_msg.expires_at_ms = Some(1744038960000);
         let signing_payload = bcs::to_bytes(&intent_msg).expect("should not fail");
+        assert_eq!(
+            Hex::encode(signing_payload),
+            crate::vectors::committed("weather_expiring").signing_payload
+        );
+        let json = serde_json::to_value(&intent_msg).unwrap();
+        assert_eq!(json["expires_at_ms"], 1744038960000_u64);
+        assert_eq!(json["version"], nautilus_verifier::intent::EXPIRY_LAYOUT);
+    }
+
+    #[test]
+    fn test_serde_with_sequence() {
+        // The Move tests check the same vector, see `crate::vectors`.
+        use fastcrypto::encoding::{Encoding, Hex};
+        let payload = WeatherResponse {
+            location: "San Francisco".to_string(),
+            temperature: 13,
+        };
+        let mut intent_msg = IntentMessage::new(payload, 1744038900000, IntentScope::Weather);
+        assert!(serde_json::to_value(&intent_msg)
+            .unwrap()
+            .get("sequence")
+            .is_none());
+        intent_msg.version = Some(nautilus_verifier::intent::SEQUENCE_LAYOUT);
+        intent_msg.sequence = Some(7);
+        let signing_payload = bcs::to_bytes(&intent_msg).expect("should not fail");
+        assert_eq!(
+            Hex::encode(signing_payload),
+            crate::vectors::committed("weather_sequenced").signing_payload
+        );
+        let json = serde_json::to_value(&intent_msg).unwrap();
+        assert_eq!(json["sequence"], 7);
+        assert_eq!(json["version"], nautilus_verifier::intent::SEQUENCE_LAYOUT);
+    }
+
+    #[test]
+    fn test_serde_with_upstream_latency() {
+        use fastcrypto::encoding::{Encoding, Hex};
+        let payload = WeatherPayload::WithUpstreamLatency(WeatherResponseWithUpstreamLatency {
+            location: "San Francisco".to_string(),
+            temperature: 13,
+            upstream_latency_ms: 250,
+        });
+        let intent_msg = IntentMessage::new(
+            payload,
+            1744038900000,
+            IntentScope::WeatherWithUpstreamLatency,
+        );
+        let signing_payload = bcs::to_bytes(&intent_msg).expect("should not fail");
+        assert_eq!(
+            Hex::encode(signing_payload),
+            crate::vectors::committed("weather_with_upstream_latency").signing_payload
+        );
+    }
+
+    #[test]
+    fn test_select_fields() {
+        let json = serde_json::json!({
+            "location": { "name": "San Francisco", "lat": 37.78 },
+            "current": {
+                "temp_c": 13,
+                "condition": { "text": "Sunny", "code": 1000 }
+            },
+            "alerts": [{ "headline": "Fog" }]
+        });
+        let paths = vec![
+            "current.condition".to_string(),
+            "location.name".to_string(),
+            "alerts.0.headline".to_string(),
+        ];
+        let fields = select_fields(&json, &paths).unwrap();
+        assert_eq!(
+            fields
+                .iter()
+                .map(|field| (field.path.as_str(), field.value.as_str()))
+                .collect::<Vec<_>>(),
+            [
+                ("current.condition", r#"{"code":1000,"text":"Sunny"}"#),
+                ("location.name", r#""San Francisco""#),
+                ("alerts.0.headline", r#""Fog""#),
+            ]
+        );
+
+        for missing in [
+            "current.humidity",
+            "alerts.1.headline",
+            "location.name.first",
+        ] {
+            let err = select_fields(&json, &[missing.to_string()]).unwrap_err();
+            assert!(err.to_string().contains(missing), "{}", err);
+        }
+
+        assert!(weather_payload(
+            "Paris".to_string(),
+            20,
+            150,
+            Vec::new(),
+            IntentScope::WeatherFields,
+            None
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_serde_with_fields() {
+        use fastcrypto::encoding::{Encoding, Hex};
+        let json = serde_json::json!({
+            "location": { "name": "San Francisco" },
+            "current": { "condition": { "text": "Sunny", "code": 1000 } }
+        });
+        let paths = vec!["current.condition".to_string(), "location.name".to_string()];
+        let fields = select_fields(&json, &paths).unwrap();
+        let payload = weather_payload(
+            "San Francisco".to_string(),
+            13,
+            250,
+            fields,
+            IntentScope::WeatherFields,
+            None,
+        )
+        .unwrap();
+        let intent_msg = IntentMessage::new(payload, 1744038900000, IntentScope::WeatherFields);
+        let signing_payload = bcs::to_bytes(&intent_msg).expect("should not fail");
+        assert_eq!(
+            Hex::encode(signing_payload),
+            crate::vectors::committed("weather_fields").signing_payload
+        );
+    }
+
+    #[test]
+    fn test_check_api_key_response() {
+        use serde_json::json;
+        let reading = json!({ "current": { "temp_c": 13.0 } });
+        assert!(check_api_key_response(StatusCode::OK, &reading).is_ok());
+        assert!(check_api_key_response(StatusCode::OK, &json!({})).is_err());
+
+        let rejected =
+            json!({ "error": { "code": 2008, "message": "API key has been disabled." } });
+        let err = check_api_key_response(StatusCode::FORBIDDEN, &rejected)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("403") && err.contains("disabled"), "{}", err);
+        assert!(check_api_key_response(StatusCode::UNAUTHORIZED, &Value::Null).is_err());
+    }
+
+    #[cfg(feature = "mock-weather")]
+    #[tokio::test]
+    async fn test_mock_weather() {
+        use crate::mock_weather::MockWeather;
+        use fastcrypto::traits::ToFromBytes;
+        use nautilus_verifier::{verify_response, SignedResponse};
+
+        let state = |location: Option<&str>| {
+            let eph_kp = Ed25519KeyPair::generate(&mut rand::thread_rng());
+            let public_key = eph_kp.public().as_bytes().to_vec();
+            let config = Config {
+                // Nothing listens there, so any upstream call would fail
+                weather_api_url: "http://127.0.0.1:9/v1/current.json".parse().unwrap(),
+                mock_weather: Some(MockWeather {
+                    location: location.map(str::to_string),
+                    temperature: 17,
+                }),
+                ..Config::default()
+            };
+            let state = AppState::new(eph_kp, ApiKeys::single(String::new()), config);
+            (Arc::new(state.unwrap()), public_key)
+        };
+        let request = || {
+            Json(ProcessDataRequest {
+                payload: WeatherRequest {
+                    location: "Paris".to_string(),
+                    lang: None,
+                    params: BTreeMap::new(),
+                },
+                intent: None,
+            })
+        };
+
+        for (location, expected) in [(None, "Paris"), (Some("Atlantis"), "Atlantis")] {
+            let (state, public_key) = state(location);
+            let (_, Json(response)) = process_data(State(state), HeaderMap::new(), request())
+                .await
+                .unwrap();
+            // Signed like a real reading
+            let signed: SignedResponse<WeatherResponse> =
+                serde_json::from_value(serde_json::to_value(&response).unwrap()).unwrap();
+            assert_eq!(signed.response.data.location, expected);
+            assert_eq!(signed.response.data.temperature, 17);
+            assert_eq!(verify_response(&public_key, &signed), Ok(()));
+        }
+    }
+
+    #[cfg(feature = "openapi")]
+    #[tokio::test]
+    async fn test_responses_match_schemas() {
+        use crate::test_utils::{test_config, TestEnclave};
+        use crate::ErrorResponse;
+        use schemars::JsonSchema;
+
+        let upstream = mock_weather_upstream(wall_clock_ms() / 1000).await;
+        let enclave = TestEnclave::start(test_config(upstream)).await;
+
+        // Schemas as a client would fetch them
+        let (_, schemas) = enclave.get("/schemas").await;
+        let validate = |name: String, body: &serde_json::Value| {
+            let schema = jsonschema::JSONSchema::compile(&schemas[&name]).unwrap();
+            if let Err(errors) = schema.validate(body) {
+                let errors: Vec<String> = errors.map(|e| e.to_string()).collect();
+                panic!("{} doesn't match {}: {:?}", body, name, errors);
+            }
+        };
+
+        let (status, body) = enclave
+            .post(
+                "/process_data",
+                &serde_json::json!({ "payload": { "location": "Paris" } }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        validate(
+            <ProcessedDataResponse<IntentMessage<WeatherPayload>>>::schema_name(),
+            &body,
+        );
+
+        let (status, body) = enclave
+            .post(
+                "/process_data",
+                &serde_json::json!({ "payload": { "location": "Paris", "lang": "xx" } }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        validate(ErrorResponse::schema_name(), &body);
+    }
+
+    #[tokio::test]
+    async fn test_max_response_bytes() {
+        let weather_api_url = mock_weather_upstream(wall_clock_ms() / 1000).await;
+        let state = |honor_max_response_bytes| {
+            Arc::new(
+                AppState::new(
+                    Ed25519KeyPair::generate(&mut rand::thread_rng()),
+                    ApiKeys::single("key".to_string()),
+                    Config {
+                        weather_api_url: weather_api_url.clone(),
+                        honor_max_response_bytes,
+                        ..Config::default()
+                    },
+                )
+                .unwrap(),
+            )
+        };
+        let batch = || {
+            let requests = ["Paris", "London", "Tokyo", "Lima"]
+                .map(|location| WeatherRequest {
+                    location: location.to_string(),
+                    lang: None,
+                    params: BTreeMap::new(),
+                })
+                .to_vec();
+            Json(ProcessDataRequest {
+                payload: BatchWeatherRequest { requests },
+                intent: None,
+            })
+        };
+        let limit = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(MAX_RESPONSE_BYTES_HEADER, value.parse().unwrap());
+            headers
+        };
+
+        // Four signed responses don't fit in 256 bytes
+        let err = process_data_batch(State(state(true)), limit("256"), batch())
+            .await
+            .err()
+            .unwrap();
+        assert!(
+            err.to_string()
+                .ends_with("more than the x-max-response-bytes of 256 bytes"),
+            "{}",
+            err
+        );
+        let (_, Json(responses)) = process_data_batch(State(state(true)), limit("65536"), batch())
+            .await
+            .unwrap();
+        assert_eq!(responses.len(), 4);
+        assert!(serde_json::to_vec(&responses).unwrap().len() > 256);
+        // No limit without the header, or when it isn't honored
+        assert!(
+            process_data_batch(State(state(true)), HeaderMap::new(), batch())
+                .await
+                .is_ok()
+        );
+        assert!(
+            process_data_batch(State(state(false)), limit("256"), batch())
+                .await
+                .is_ok()
+        );
+
+        // A refused response uses no sequence number
+        let sequenced = Arc::new(
+            AppState::new(
+                Ed25519KeyPair::generate(&mut rand::thread_rng()),
+                ApiKeys::single("key".to_string()),
+                Config {
+                    weather_api_url: weather_api_url.clone(),
+                    sign_sequence: true,
+                    ..Config::default()
+                },
+            )
+            .unwrap(),
+        );
+        assert!(
+            process_data_batch(State(sequenced.clone()), limit("256"), batch())
+                .await
+                .is_err()
+        );
+        let request = Json(ProcessDataRequest {
+            payload: WeatherRequest {
+                location: "Paris".to_string(),
+                lang: None,
+                params: BTreeMap::new(),
+            },
+            intent: None,
+        });
+        assert!(process_data(State(sequenced.clone()), limit("64"), request)
+            .await
+            .is_err());
+        let (_, Json(responses)) =
+            process_data_batch(State(sequenced.clone()), limit("65536"), batch())
+                .await
+                .unwrap();
+        let sequences: Vec<_> = responses
+            .iter()
+            .map(|response| response.response.sequence)
+            .collect();
+        assert_eq!(sequences, [Some(0), Some(1), Some(2), Some(3)]);
+
+        let err = process_data_batch(State(state(true)), limit("lots"), batch())
+            .await
+            .err()
+            .unwrap();
         assert!(
-            signing_payload
-                == Hex::decode("0020b1d110960100000d53616e204672616e636973636f0d00000000000000")
-                    .unwrap()
+            err.to_string().starts_with("Invalid x-max-response-bytes"),
+            "{}",
+            err
         );
     }
+
+    #[tokio::test]
+    async fn test_unknown_location_is_not_found() {
+        use axum::response::IntoResponse;
+        let weather_api_url = mock_weather_upstream(wall_clock_ms() / 1000).await;
+        for map_upstream_errors in [true, false] {
+            let config = Config {
+                weather_api_url: weather_api_url.clone(),
+                map_upstream_errors,
+                ..Config::default()
+            };
+            let state = Arc::new(
+                AppState::new(
+                    Ed25519KeyPair::generate(&mut rand::thread_rng()),
+                    ApiKeys::single("key".to_string()),
+                    config,
+                )
+                .unwrap(),
+            );
+            let request = ProcessDataRequest {
+                payload: WeatherRequest {
+                    location: UNKNOWN_LOCATION.to_string(),
+                    lang: None,
+                    params: BTreeMap::new(),
+                },
+                intent: None,
+            };
+            let err = process_data(State(state), HeaderMap::new(), Json(request))
+                .await
+                .err()
+                .unwrap();
+            let message = err.to_string();
+            let status = err.into_response().status();
+            if map_upstream_errors {
+                assert_eq!(status, StatusCode::NOT_FOUND);
+                assert_eq!(
+                    message,
+                    format!("No matching location found: {}", UNKNOWN_LOCATION)
+                );
+            } else {
+                assert_eq!(status, StatusCode::BAD_REQUEST);
+                assert!(message.starts_with("Invalid temperature"), "{}", message);
+            }
+        }
+        // Other upstream errors are left alone
+        let disabled = serde_json::json!({ "error": { "code": 2008, "message": "disabled" } });
+        assert!(mapped_upstream_error(&disabled, "Paris").is_none());
+        assert!(mapped_upstream_error(&Value::Null, "Paris").is_none());
+    }
 }
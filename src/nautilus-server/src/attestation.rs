@@ -0,0 +1,7 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Attestation document parsing and checks, from nautilus-verifier so
+//! `nautilus-verify` and browser verifiers read documents the same way.
+
+pub use nautilus_verifier::attestation::*;
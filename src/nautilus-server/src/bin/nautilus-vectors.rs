@@ -0,0 +1,55 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Regenerate the signing test vectors shared with the Move tests, see
+//! `nautilus_server::vectors`.
+
+use nautilus_server::vectors::{generate, to_json, to_move, JSON_PATH, MOVE_PATH};
+use std::path::Path;
+use std::process::ExitCode;
+
+const USAGE: &str = "\
+Usage: nautilus-vectors [--check]
+
+Writes the test vectors of every intent scope to vectors/test_vectors.json
+and move/enclave/sources/test_vectors.move. With --check, writes nothing and
+exits with 1 if either file is out of date.
+";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let check = match args.as_slice() {
+        [] => false,
+        [flag] if flag == "--check" => true,
+        _ => {
+            eprint!("{}", USAGE);
+            return ExitCode::from(1);
+        }
+    };
+    let vectors = generate();
+    let crate_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let mut stale = false;
+    for (path, contents) in [
+        (JSON_PATH, to_json(&vectors)),
+        (MOVE_PATH, to_move(&vectors)),
+    ] {
+        let path = crate_dir.join(path);
+        let current = std::fs::read_to_string(&path).unwrap_or_default();
+        if current == contents {
+            println!("{} is up to date", path.display());
+        } else if check {
+            eprintln!("{} is out of date", path.display());
+            stale = true;
+        } else if let Err(e) = std::fs::write(&path, contents) {
+            eprintln!("{}: {}", path.display(), e);
+            return ExitCode::from(1);
+        } else {
+            println!("Wrote {}", path.display());
+        }
+    }
+    if stale {
+        eprintln!("Run `cargo run --bin nautilus-vectors` to regenerate");
+        return ExitCode::from(1);
+    }
+    ExitCode::SUCCESS
+}
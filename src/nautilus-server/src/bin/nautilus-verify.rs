@@ -0,0 +1,288 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Fetch attestation documents from an enclave and check them against
+//! expected PCRs, with the same parsing as the server's re-attestation.
+
+use fastcrypto::encoding::{Encoding, Hex};
+use nautilus_server::attestation::{
+    check, AttestationDocument, CheckFailure, Expectations, BUILD_COMMIT_LEN,
+};
+use nautilus_server::common::GetAttestationResponse;
+use nautilus_server::pcrs::PCR_LEN;
+use nautilus_server::pem::attestation_from_pem;
+use serde_json::json;
+use std::process::ExitCode;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const USAGE: &str = "\
+Usage: nautilus-verify [--json] <command>
+
+Commands:
+  fetch --url <URL> [--out <FILE>]   Save the document served at <URL>/get_attestation
+                                     to <FILE>, attestation.cbor by default
+  inspect --file <FILE>              Print the PCRs, module ID, timestamp, public key
+                                     and build commit
+  check --file <FILE> [--pcr0 <HEX>] [--pcr1 <HEX>] [--pcr2 <HEX>] [--max-age <SECONDS>]
+        [--build-commit <HEX>]
+                                     Exit nonzero unless the document shows the given
+                                     PCRs and build commit and is at most <SECONDS> old
+
+<FILE> holds the raw CBOR document, or PEM as served by /get_attestation?format=pem.
+
+Exit codes: 0 passed, 1 usage or I/O error, 2 invalid document, 3 PCR mismatch,
+4 document too old, 5 build commit mismatch.
+";
+
+#[derive(Debug)]
+enum Command {
+    Fetch {
+        url: String,
+        out: String,
+    },
+    Inspect {
+        file: String,
+    },
+    Check {
+        file: String,
+        expected: Expectations,
+    },
+}
+
+/// Why the tool exits nonzero.
+#[derive(Debug)]
+struct Failure {
+    code: u8,
+    reason: &'static str,
+    message: String,
+}
+
+impl Failure {
+    fn usage(message: impl Into<String>) -> Self {
+        Self {
+            code: 1,
+            reason: "usage",
+            message: message.into(),
+        }
+    }
+
+    fn io(message: impl Into<String>) -> Self {
+        Self {
+            code: 1,
+            reason: "io",
+            message: message.into(),
+        }
+    }
+}
+
+impl From<CheckFailure> for Failure {
+    fn from(failure: CheckFailure) -> Self {
+        let code = match failure {
+            CheckFailure::PcrMismatch { .. } => 3,
+            CheckFailure::TooOld { .. } => 4,
+            CheckFailure::BuildCommitMismatch { .. } => 5,
+        };
+        Self {
+            code,
+            reason: failure.reason(),
+            message: failure.to_string(),
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let json = match args.iter().position(|arg| arg == "--json") {
+        Some(index) => {
+            args.remove(index);
+            true
+        }
+        None => false,
+    };
+    let result = match parse_args(&args) {
+        Ok(command) => run(command, json).await,
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(failure) => {
+            if json {
+                println!(
+                    "{}",
+                    json!({"ok": false, "reason": failure.reason, "message": failure.message})
+                );
+            } else {
+                eprintln!("{}: {}", failure.reason, failure.message);
+                if failure.reason == "usage" {
+                    eprint!("\n{}", USAGE);
+                }
+            }
+            ExitCode::from(failure.code)
+        }
+    }
+}
+
+fn parse_args(args: &[String]) -> Result<Command, Failure> {
+    let (command, options) = args
+        .split_first()
+        .ok_or_else(|| Failure::usage("no command given"))?;
+    let mut values = std::collections::HashMap::new();
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        let value = options
+            .next()
+            .ok_or_else(|| Failure::usage(format!("{} needs a value", option)))?;
+        if values.insert(option.as_str(), value.clone()).is_some() {
+            return Err(Failure::usage(format!("{} given twice", option)));
+        }
+    }
+    let allowed: &[&str] = match command.as_str() {
+        "fetch" => &["--url", "--out"],
+        "inspect" => &["--file"],
+        "check" => &[
+            "--file",
+            "--pcr0",
+            "--pcr1",
+            "--pcr2",
+            "--max-age",
+            "--build-commit",
+        ],
+        _ => return Err(Failure::usage(format!("unknown command {:?}", command))),
+    };
+    if let Some(option) = values.keys().find(|option| !allowed.contains(*option)) {
+        return Err(Failure::usage(format!(
+            "unknown option {} for {}",
+            option, command
+        )));
+    }
+    let required = |option: &str| {
+        values
+            .get(option)
+            .cloned()
+            .ok_or_else(|| Failure::usage(format!("{} needs {}", command, option)))
+    };
+    match command.as_str() {
+        "fetch" => Ok(Command::Fetch {
+            url: required("--url")?,
+            out: values
+                .get("--out")
+                .cloned()
+                .unwrap_or_else(|| "attestation.cbor".to_string()),
+        }),
+        "inspect" => Ok(Command::Inspect {
+            file: required("--file")?,
+        }),
+        _ => {
+            let file = required("--file")?;
+            let mut expected = Expectations::default();
+            for index in 0..3 {
+                let option = format!("--pcr{}", index);
+                if let Some(value) = values.get(option.as_str()) {
+                    let pcr = Hex::decode(value)
+                        .ok()
+                        .filter(|pcr| pcr.len() == PCR_LEN)
+                        .ok_or_else(|| {
+                            Failure::usage(format!(
+                                "{} is not {} hex encoded bytes",
+                                option, PCR_LEN
+                            ))
+                        })?;
+                    expected.pcrs.insert(index, pcr);
+                }
+            }
+            if let Some(value) = values.get("--max-age") {
+                let seconds = value.parse().map_err(|_| {
+                    Failure::usage(format!("--max-age {:?} is not a number of seconds", value))
+                })?;
+                expected.max_age = Some(Duration::from_secs(seconds));
+            }
+            if let Some(value) = values.get("--build-commit") {
+                let commit = Hex::decode(value)
+                    .ok()
+                    .and_then(|commit| commit.try_into().ok())
+                    .ok_or_else(|| {
+                        Failure::usage(format!(
+                            "--build-commit is not {} hex encoded bytes",
+                            BUILD_COMMIT_LEN
+                        ))
+                    })?;
+                expected.build_commit = Some(commit);
+            }
+            Ok(Command::Check { file, expected })
+        }
+    }
+}
+
+async fn run(command: Command, json: bool) -> Result<(), Failure> {
+    match command {
+        Command::Fetch { url, out } => {
+            let url = format!("{}/get_attestation", url.trim_end_matches('/'));
+            let response = reqwest::get(&url)
+                .await
+                .and_then(|response| response.error_for_status())
+                .map_err(|e| Failure::io(format!("GET {}: {}", url, e)))?
+                .json::<GetAttestationResponse>()
+                .await
+                .map_err(|e| Failure::io(format!("GET {}: {}", url, e)))?;
+            let document = Hex::decode(&response.attestation)
+                .map_err(|e| Failure::io(format!("GET {}: attestation: {}", url, e)))?;
+            std::fs::write(&out, &document).map_err(|e| Failure::io(format!("{}: {}", out, e)))?;
+            if json {
+                println!(
+                    "{}",
+                    json!({
+                        "ok": true,
+                        "file": out,
+                        "bytes": document.len(),
+                        "generated_at_ms": response.generated_at_ms,
+                    })
+                );
+            } else {
+                println!(
+                    "Saved {} bytes to {}, generated at {} ms",
+                    document.len(),
+                    out,
+                    response.generated_at_ms
+                );
+            }
+        }
+        Command::Inspect { file } => {
+            let summary = read_document(&file)?.summary();
+            if json {
+                println!("{}", serde_json::to_string_pretty(&summary).unwrap());
+            } else {
+                print!("{}", summary);
+            }
+        }
+        Command::Check { file, expected } => {
+            let document = read_document(&file)?;
+            let now_ms = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64;
+            check(&document, &expected, now_ms)?;
+            if json {
+                println!("{}", json!({"ok": true}));
+            } else {
+                println!("OK: {} passed", file);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn read_document(file: &str) -> Result<AttestationDocument, Failure> {
+    let invalid = |message: String| Failure {
+        code: 2,
+        reason: "invalid_document",
+        message: format!("{}: {}", file, message),
+    };
+    let mut bytes = std::fs::read(file).map_err(|e| Failure::io(format!("{}: {}", file, e)))?;
+    // Documents saved from `/get_attestation?format=pem`
+    if bytes.starts_with(b"-----BEGIN ") {
+        let pem = String::from_utf8_lossy(&bytes).into_owned();
+        bytes = attestation_from_pem(&pem).map_err(|e| invalid(e.to_string()))?;
+    }
+    AttestationDocument::parse(&bytes).map_err(|e| invalid(e.to_string()))
+}
@@ -1,50 +1,145 @@
 // Copyright (c), Mysten Labs, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
+use crate::config::{ClockBeforeEpoch, Config, KeySeedSource};
+use crate::health::{parse_allowed_endpoints, Endpoint, ALLOWED_ENDPOINTS_PATH};
 use crate::AppState;
 use crate::EnclaveError;
-use axum::{extract::State, Json};
+use axum::extract::{Query, State};
+use axum::http::header::{ACCEPT, CONTENT_TYPE};
+use axum::http::{HeaderMap, HeaderName, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
 use fastcrypto::traits::Signer;
 use fastcrypto::{encoding::Encoding, traits::ToFromBytes};
 use fastcrypto::{encoding::Hex, traits::KeyPair as FcKeyPair};
+use nautilus_verifier::{intent, signing_bytes};
 use nsm_api::api::{Request as NsmRequest, Response as NsmResponse};
 use nsm_api::driver;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use serde_bytes::ByteBuf;
 use serde_repr::Deserialize_repr;
 use serde_repr::Serialize_repr;
-use std::collections::HashMap;
+use sha2::{Digest, Sha256, Sha384};
+use std::collections::BTreeMap;
 use std::fmt::Debug;
-use std::sync::Arc;
-use std::time::Duration;
-use tracing::info;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::task::JoinSet;
+use tracing::{info, warn};
 
-use fastcrypto::ed25519::Ed25519KeyPair;
+pub use nautilus_verifier::SigningFormat;
+
+use fastcrypto::ed25519::{Ed25519KeyPair, Ed25519PrivateKey};
 /// ==== COMMON TYPES ====
 
 /// Intent message wrapper struct containing the intent scope and timestamp.
 /// This standardizes the serialized payload for signing.
+///
+/// Without an expiry the BCS bytes are `intent | timestamp_ms | data`,
+/// verified by `verify_signature` in `enclave.move`. With one they are
+/// `intent | 0x01 | EXPIRY_LAYOUT | timestamp_ms | 0x01 | expires_at_ms |
+/// data`, the encoding of `Option<u8>` and `Option<u64>` on both sides,
+/// verified by `verify_signature_with_expiry`. The layout version after the
+/// intent keeps these bytes from verifying under any other layout, see
+/// `nautilus_verifier::intent::EXPIRY_LAYOUT`. With a sequence number they are
+/// `intent | 0x01 | SEQUENCE_LAYOUT | timestamp_ms | 0x01 | sequence | data`,
+/// verified by `verify_signature_with_sequence`. An enclave signs every
+/// message in one layout, chosen by `SIGN_EXPIRY_MS` or `SIGN_SEQUENCE`.
 #[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
 pub struct IntentMessage<T: Serialize> {
     pub intent: IntentScope,
+    /// Layout of the signed bytes, set by [to_signed_response] from the
+    /// optional fields present. Omitted for the plain layout.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<u8>,
     pub timestamp_ms: u64,
+    /// Time after which verifiers should reject the message. Omitted from
+    /// the JSON and the signed bytes when the enclave doesn't sign expiries.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at_ms: Option<u64>,
+    /// Position of the message among those the enclave signed under the same
+    /// intent scope since it started, counting from zero. Omitted from the
+    /// JSON and the signed bytes unless the enclave signs sequence numbers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sequence: Option<u64>,
     pub data: T,
 }
 
-/// Intent scope enum. Add new scope here if needed, each corresponds to a
-/// scope for signing. Replace in with your own intent per message type being signed by the enclave.
-#[derive(Serialize_repr, Deserialize_repr, Debug)]
+/// Intent scope enum. Add new scope here if needed, with its value in
+/// `nautilus_verifier::intent`, each corresponds to a scope for signing.
+/// Replace in with your own intent per message type being signed by the enclave.
+#[derive(Serialize_repr, Deserialize_repr, Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 pub enum IntentScope {
-    Weather = 0,
+    Weather = intent::WEATHER,
+    /// Weather data that also commits to the digest of the enclave's boot
+    /// attestation document.
+    WeatherWithAttestationDigest = intent::WEATHER_WITH_ATTESTATION_DIGEST,
+    /// Weather data that also commits to how long the upstream fetch took.
+    WeatherWithUpstreamLatency = intent::WEATHER_WITH_UPSTREAM_LATENCY,
+    /// The upstream fields selected by `SIGNED_FIELDS`, canonicalized.
+    WeatherFields = intent::WEATHER_FIELDS,
+    /// An exchange rate signed by `/process_price`.
+    Price = intent::PRICE,
+    /// A commitment to a compressed payload, see
+    /// `nautilus_verifier::compression`.
+    Compressed = intent::COMPRESSED,
+    /// A GitHub commit signed by `/process_github_commit`.
+    GithubCommit = intent::GITHUB_COMMIT,
+    /// Sui transaction bytes approved by `/process_transaction`.
+    SuiTransaction = intent::SUI_TRANSACTION,
+}
+
+impl IntentScope {
+    /// Every registered scope. Keep in sync when adding a variant.
+    pub const ALL: [IntentScope; 8] = [
+        IntentScope::Weather,
+        IntentScope::WeatherWithAttestationDigest,
+        IntentScope::WeatherWithUpstreamLatency,
+        IntentScope::WeatherFields,
+        IntentScope::Price,
+        IntentScope::Compressed,
+        IntentScope::GithubCommit,
+        IntentScope::SuiTransaction,
+    ];
+}
+
+/// Look up a caller-supplied scope, rejecting values that aren't registered.
+impl TryFrom<u8> for IntentScope {
+    type Error = EnclaveError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Self::ALL
+            .into_iter()
+            .find(|scope| *scope as u8 == value)
+            .ok_or_else(|| {
+                let registered: Vec<String> = Self::ALL
+                    .iter()
+                    .map(|scope| format!("{} ({:?})", *scope as u8, scope))
+                    .collect();
+                EnclaveError::GenericError(format!(
+                    "Unknown intent scope {}, registered scopes are {}",
+                    value,
+                    registered.join(", ")
+                ))
+            })
+    }
 }
 
 impl<T: Serialize + Debug> IntentMessage<T> {
     pub fn new(data: T, timestamp_ms: u64, intent: IntentScope) -> Self {
         Self {
             data,
+            version: None,
             timestamp_ms,
+            expires_at_ms: None,
+            sequence: None,
             intent,
         }
     }
@@ -52,172 +147,1380 @@ impl<T: Serialize + Debug> IntentMessage<T> {
 
 /// Wrapper struct containing the response (the intent message) and signature.
 #[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
 pub struct ProcessedDataResponse<T> {
     pub response: T,
     pub signature: String,
+    /// The same response as a JWT signed by the enclave key, with
+    /// `SIGN_JWT`. See [crate::jwt::encode] for the signed bytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jwt: Option<String>,
+    /// How `response` was serialized into the bytes `signature` covers.
+    /// Responses without it were signed over BCS.
+    #[serde(default)]
+    pub signing_format: SigningFormat,
+    /// Hex encoded compressed payload that `response` commits to, when it is
+    /// signed under [IntentScope::Compressed].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compressed: Option<String>,
 }
 
 /// Wrapper struct containing the request payload.
 #[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
 pub struct ProcessDataRequest<T> {
     pub payload: T,
+    /// Intent scope to sign under, as its `u8` value. Must be registered in
+    /// [IntentScope]; the endpoint's default scope is used when omitted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub intent: Option<u8>,
 }
 
-/// Sign the bcs bytes of the the payload with keypair.
+/// Sign the bytes of the payload in `format` with keypair, committing to
+/// `expires_at_ms` and `sequence` when there are any.
 pub fn to_signed_response<T: Serialize + Clone>(
     kp: &Ed25519KeyPair,
     payload: T,
     timestamp_ms: u64,
+    expires_at_ms: Option<u64>,
+    sequence: Option<u64>,
     intent: IntentScope,
+    format: SigningFormat,
 ) -> ProcessedDataResponse<IntentMessage<T>> {
     let intent_msg = IntentMessage {
         intent,
+        version: nautilus_verifier::layout_version(expires_at_ms, sequence),
         timestamp_ms,
+        expires_at_ms,
+        sequence,
         data: payload.clone(),
     };
 
-    let signing_payload = bcs::to_bytes(&intent_msg).expect("should not fail");
+    let signing_payload = signing_bytes(format, &intent_msg).expect("should not fail");
     let sig = kp.sign(&signing_payload);
     ProcessedDataResponse {
         response: intent_msg,
         signature: Hex::encode(sig),
+        jwt: None,
+        signing_format: format,
+        compressed: None,
+    }
+}
+
+/// Round `timestamp_ms` down to the start of its `bucket_ms` window, so that
+/// identical readings within a window produce identical signatures. A bucket
+/// of zero leaves the timestamp unchanged.
+pub fn bucket_timestamp(timestamp_ms: u64, bucket_ms: u64) -> u64 {
+    if bucket_ms == 0 {
+        timestamp_ms
+    } else {
+        timestamp_ms - timestamp_ms % bucket_ms
+    }
+}
+
+/// Interval at which [ClockBeforeEpoch::Retry] re-reads the clock.
+const CLOCK_RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Current time in milliseconds since the UNIX epoch. A clock before the epoch
+/// means it has not been set yet, which is handled according to `config`.
+pub async fn current_timestamp_ms(config: &Config) -> Result<u64, EnclaveError> {
+    timestamp_ms_with(
+        SystemTime::now,
+        config.clock_before_epoch,
+        config.clock_retry_timeout,
+    )
+    .await
+}
+
+async fn timestamp_ms_with(
+    now: impl Fn() -> SystemTime,
+    policy: ClockBeforeEpoch,
+    retry_timeout: Duration,
+) -> Result<u64, EnclaveError> {
+    let deadline = Instant::now() + retry_timeout;
+    loop {
+        match now().duration_since(UNIX_EPOCH) {
+            Ok(elapsed) => return Ok(elapsed.as_millis() as u64),
+            Err(e) if policy == ClockBeforeEpoch::Retry && Instant::now() < deadline => {
+                info!(
+                    "System clock is {:?} before the UNIX epoch, waiting for it to be set",
+                    e.duration()
+                );
+                tokio::time::sleep(CLOCK_RETRY_INTERVAL).await;
+            }
+            Err(e) => {
+                return Err(EnclaveError::GenericError(format!(
+                    "System clock not initialized: {:?} before the UNIX epoch",
+                    e.duration()
+                )))
+            }
+        }
+    }
+}
+
+/// Compare a BCS serialization fixture with the expected hex (with or without
+/// a `0x` prefix), so a change in the encoding that the Move verifier would
+/// reject is caught before the server takes traffic.
+pub fn check_bcs_golden(fixture: &[u8], expected_hex: &str) -> Result<(), EnclaveError> {
+    let expected_hex = expected_hex.trim();
+    let expected = Hex::decode(expected_hex.strip_prefix("0x").unwrap_or(expected_hex))
+        .map_err(|e| EnclaveError::GenericError(format!("Invalid BCS_GOLDEN_HEX: {}", e)))?;
+    if fixture != expected.as_slice() {
+        return Err(EnclaveError::GenericError(format!(
+            "BCS golden value mismatch: expected {}, serialized {}",
+            Hex::encode(expected),
+            Hex::encode(fixture)
+        )));
+    }
+    Ok(())
+}
+
+/// ==== KEY GENERATION ====
+
+/// Generate the enclave's ephemeral keypair. With [KeySeedSource::Nsm] the key
+/// is derived from a seed drawn from the NSM hardware RNG; if NSM is
+/// unavailable the OS RNG is used instead. The source used is logged.
+pub fn generate_keypair(source: KeySeedSource) -> Ed25519KeyPair {
+    keypair_with(source, nsm_random_seed)
+}
+
+fn keypair_with(
+    source: KeySeedSource,
+    nsm_seed: impl FnOnce() -> Result<[u8; 32], EnclaveError>,
+) -> Ed25519KeyPair {
+    if source == KeySeedSource::Nsm {
+        match nsm_seed() {
+            Ok(seed) => {
+                info!("Generating ephemeral keypair from NSM random seed");
+                return Ed25519KeyPair::generate(&mut StdRng::from_seed(seed));
+            }
+            Err(e) => info!("NSM random unavailable, falling back to OS RNG: {:?}", e),
+        }
     }
+    info!("Generating ephemeral keypair from OS RNG");
+    Ed25519KeyPair::generate(&mut rand::thread_rng())
+}
+
+/// The enclave's keypair: loaded from [Config::signing_key_path] when set,
+/// otherwise generated with [generate_keypair]. Either way its public key is
+/// the one attested.
+pub fn init_keypair(config: &Config) -> Result<Ed25519KeyPair, EnclaveError> {
+    match &config.signing_key_path {
+        Some(path) => load_signing_key(path),
+        None => Ok(generate_keypair(config.key_seed_source)),
+    }
+}
+
+/// Load the signing key from `path`, see [parse_signing_key].
+pub fn load_signing_key(path: &str) -> Result<Ed25519KeyPair, EnclaveError> {
+    let contents = std::fs::read_to_string(path).map_err(|e| {
+        EnclaveError::GenericError(format!("Failed to read SIGNING_KEY_PATH {}: {}", path, e))
+    })?;
+    let kp = parse_signing_key(&contents).map_err(|e| {
+        EnclaveError::GenericError(format!("Invalid signing key in {}: {}", path, e))
+    })?;
+    info!(
+        "Loaded signing key from {}, public key {}",
+        path,
+        Hex::encode(kp.public().as_bytes())
+    );
+    Ok(kp)
+}
+
+/// Parse a hex encoded 32-byte Ed25519 private key, with or without a `0x`
+/// prefix and surrounding whitespace. Errors never include the key material.
+pub fn parse_signing_key(contents: &str) -> Result<Ed25519KeyPair, String> {
+    let contents = contents.trim();
+    let hex = contents.strip_prefix("0x").unwrap_or(contents);
+    if hex.len() != 64 {
+        return Err(format!("expected 64 hex digits, got {}", hex.len()));
+    }
+    let bytes = Hex::decode(hex).map_err(|_| "not hex".to_string())?;
+    let private_key =
+        Ed25519PrivateKey::from_bytes(&bytes).map_err(|_| "not an Ed25519 key".to_string())?;
+    Ok(private_key.into())
+}
+
+/// Request 32 random bytes from the NSM driver.
+fn nsm_random_seed() -> Result<[u8; 32], EnclaveError> {
+    let mut seed = [0u8; 32];
+    seed.copy_from_slice(&nsm_random(32)?);
+    Ok(seed)
+}
+
+/// Request `len` random bytes from the NSM driver, which returns them in
+/// chunks.
+pub(crate) fn nsm_random(len: usize) -> Result<Vec<u8>, EnclaveError> {
+    let fd = driver::nsm_init();
+    if fd < 0 {
+        return Err(EnclaveError::GenericError(
+            "Failed to open NSM device".to_string(),
+        ));
+    }
+    let mut random_bytes = Vec::with_capacity(len);
+    while random_bytes.len() < len {
+        match driver::nsm_process_request(fd, NsmRequest::GetRandom) {
+            NsmResponse::GetRandom { random } if !random.is_empty() => {
+                random_bytes.extend_from_slice(&random)
+            }
+            response => {
+                driver::nsm_exit(fd);
+                return Err(EnclaveError::GenericError(format!(
+                    "unexpected NSM response: {:?}",
+                    response
+                )));
+            }
+        }
+    }
+    driver::nsm_exit(fd);
+    random_bytes.truncate(len);
+    Ok(random_bytes)
 }
 
 /// ==== HEALTHCHECK, GET ATTESTASTION ENDPOINT IMPL ====
 
 /// Response for get attestation.
 #[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
 pub struct GetAttestationResponse {
     /// Attestation document serialized in Hex.
     pub attestation: String,
+    /// When the document was generated, in milliseconds since the UNIX epoch.
+    pub generated_at_ms: u64,
 }
 
-/// Endpoint that returns an attestation committed
-/// to the enclave's public key.
-pub async fn get_attestation(
-    State(state): State<Arc<AppState>>,
-) -> Result<Json<GetAttestationResponse>, EnclaveError> {
-    info!("get attestation called");
+/// Query parameters for get attestation.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+pub struct GetAttestationQuery {
+    /// Hex encoded nonce to include in a freshly generated document, proving
+    /// it was generated after the caller chose the nonce. At most
+    /// [MAX_NONCE_LEN] bytes.
+    #[serde(default)]
+    pub nonce: Option<String>,
+    /// Encoding of the document, hex JSON unless set. PEM can also be asked
+    /// for with an `Accept` header.
+    #[serde(default)]
+    pub format: Option<AttestationFormat>,
+}
 
-    let pk = state.eph_kp.public();
+/// Encoding of the document served by `/get_attestation`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum AttestationFormat {
+    /// [GetAttestationResponse] with the document hex encoded.
+    #[default]
+    Hex,
+    /// The document, then its certificate chain, as PEM blocks. See
+    /// [crate::pem::attestation_pem].
+    Pem,
+}
+
+impl AttestationFormat {
+    /// The format asked for by `query`, or else by the `Accept` header.
+    /// PEM is refused when `ATTESTATION_PEM` is disabled.
+    fn negotiate(
+        query: Option<Self>,
+        headers: &HeaderMap,
+        pem_enabled: bool,
+    ) -> Result<Self, EnclaveError> {
+        match query {
+            Some(Self::Pem) if !pem_enabled => Err(EnclaveError::GenericError(
+                "PEM attestations are disabled".to_string(),
+            )),
+            Some(format) => Ok(format),
+            None if pem_enabled && accepts_pem(headers) => Ok(Self::Pem),
+            None => Ok(Self::Hex),
+        }
+    }
+}
+
+/// Whether the `Accept` header lists the PEM content type.
+fn accepts_pem(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|media_type| {
+            let essence = media_type.split(';').next().unwrap_or_default().trim();
+            essence.eq_ignore_ascii_case(crate::pem::PEM_CONTENT_TYPE)
+        })
+}
+
+/// Header carrying `generated_at_ms` in PEM responses.
+pub const GENERATED_AT_HEADER: &str = "x-generated-at-ms";
+
+/// Largest nonce NSM accepts in an attestation request.
+pub const MAX_NONCE_LEN: usize = 512;
+
+/// Attestation document generated at boot and cached, so that signed
+/// responses can commit to its digest. A verifier that has checked this
+/// document once can confirm later responses came from the same attested
+/// enclave by comparing the SHA-384 of the document with the signed digest.
+pub struct BootAttestation {
+    /// Raw attestation document.
+    pub document: Vec<u8>,
+    /// SHA-384 digest of the document.
+    pub digest: Vec<u8>,
+    /// When the document was generated, in milliseconds since the UNIX epoch.
+    pub generated_at_ms: u64,
+}
+
+impl BootAttestation {
+    pub fn new(kp: &Ed25519KeyPair, user_data: Option<&[u8]>) -> Result<Self, EnclaveError> {
+        let document = nsm_attestation(kp.public().as_bytes(), user_data, None)?;
+        let digest = Sha384::digest(&document).to_vec();
+        info!("Cached boot attestation, digest {}", Hex::encode(&digest));
+        Ok(Self {
+            document,
+            digest,
+            generated_at_ms: wall_clock_ms(),
+        })
+    }
+
+    /// Request the attestation up to `attempts` times, since NSM may not be
+    /// ready immediately after boot, sleeping `backoff` before the first retry
+    /// and doubling it after each failure.
+    pub fn fetch(
+        kp: &Ed25519KeyPair,
+        user_data: Option<&[u8]>,
+        attempts: u32,
+        backoff: Duration,
+    ) -> Result<Self, EnclaveError> {
+        with_retries(attempts, backoff, std::thread::sleep, || {
+            Self::new(kp, user_data)
+        })
+    }
+}
+
+/// Call `f` until it succeeds or has failed `attempts` times, logging each
+/// failure and passing the backoff between attempts to `sleep`.
+fn with_retries<T>(
+    attempts: u32,
+    mut backoff: Duration,
+    mut sleep: impl FnMut(Duration),
+    mut f: impl FnMut() -> Result<T, EnclaveError>,
+) -> Result<T, EnclaveError> {
+    let mut attempt = 1;
+    loop {
+        match f() {
+            Ok(value) => return Ok(value),
+            Err(e) if attempt < attempts => {
+                info!(
+                    "Boot attestation attempt {}/{} failed: {}, retrying in {:?}",
+                    attempt, attempts, e, backoff
+                );
+                sleep(backoff);
+                backoff *= 2;
+                attempt += 1;
+            }
+            Err(e) => {
+                info!(
+                    "Boot attestation attempt {}/{} failed: {}, giving up",
+                    attempt, attempts, e
+                );
+                return Err(EnclaveError::GenericError(format!(
+                    "Failed to obtain attestation after {} attempt(s): {}",
+                    attempts, e
+                )));
+            }
+        }
+    }
+}
+
+/// Request an attestation document committed to `public_key`, and to
+/// `user_data` and `nonce` if given, from the NSM driver.
+pub fn nsm_attestation(
+    public_key: &[u8],
+    user_data: Option<&[u8]>,
+    nonce: Option<Vec<u8>>,
+) -> Result<Vec<u8>, EnclaveError> {
     let fd = driver::nsm_init();
+    if fd < 0 {
+        return Err(EnclaveError::GenericError(
+            "Failed to open NSM device".to_string(),
+        ));
+    }
 
     // Send attestation request to NSM driver with public key set.
     let request = NsmRequest::Attestation {
-        user_data: None,
-        nonce: None,
-        public_key: Some(ByteBuf::from(pk.as_bytes().to_vec())),
+        user_data: user_data.map(|user_data| ByteBuf::from(user_data.to_vec())),
+        nonce: nonce.map(ByteBuf::from),
+        public_key: Some(ByteBuf::from(public_key.to_vec())),
     };
 
     let response = driver::nsm_process_request(fd, request);
+    driver::nsm_exit(fd);
     match response {
-        NsmResponse::Attestation { document } => {
-            driver::nsm_exit(fd);
-            Ok(Json(GetAttestationResponse {
-                attestation: Hex::encode(document),
-            }))
-        }
-        _ => {
-            driver::nsm_exit(fd);
-            Err(EnclaveError::GenericError(
-                "unexpected response".to_string(),
-            ))
+        NsmResponse::Attestation { document } => Ok(document),
+        _ => Err(EnclaveError::GenericError(
+            "unexpected response".to_string(),
+        )),
+    }
+}
+
+/// Current wall clock time in milliseconds, or zero if the clock is before the
+/// UNIX epoch.
+pub fn wall_clock_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Whether `headers` carry `Authorization: Bearer <token>`. Digests are
+/// compared so the comparison time doesn't depend on the token.
+pub fn bearer_matches(headers: &HeaderMap, token: &str) -> bool {
+    let presented = headers
+        .get(axum::http::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .unwrap_or_default();
+    Sha256::digest(presented.as_bytes()) == Sha256::digest(token.as_bytes())
+}
+
+/// Sequence numbers of signed messages, counted separately for each intent
+/// scope. They live as long as the process, so they restart from zero along
+/// with the ephemeral key, and a verifier tracks them per enclave key. A
+/// provisioned key outlives the process, which is why `SIGN_SEQUENCE` can't
+/// be combined with `SIGNING_KEY_PATH`.
+pub struct SequenceCounters {
+    next: [AtomicU64; IntentScope::ALL.len()],
+}
+
+impl Default for SequenceCounters {
+    fn default() -> Self {
+        Self {
+            next: std::array::from_fn(|_| AtomicU64::new(0)),
+        }
+    }
+}
+
+impl SequenceCounters {
+    /// Take the next sequence number for `scope`.
+    pub fn next(&self, scope: IntentScope) -> u64 {
+        self.counter(scope).fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Take `count` consecutive sequence numbers for `scope` once `sign`
+    /// succeeds with the first of them. If another request took those
+    /// numbers meanwhile, `sign` runs again with the next free ones. When
+    /// `sign` fails no number is taken, so a response refused after signing
+    /// leaves no gap.
+    pub fn next_with<T, E>(
+        &self,
+        scope: IntentScope,
+        count: u64,
+        mut sign: impl FnMut(u64) -> Result<T, E>,
+    ) -> Result<T, E> {
+        let counter = self.counter(scope);
+        let mut first = counter.load(Ordering::Relaxed);
+        loop {
+            let signed = sign(first)?;
+            match counter.compare_exchange(
+                first,
+                first + count,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return Ok(signed),
+                Err(current) => first = current,
+            }
+        }
+    }
+
+    fn counter(&self, scope: IntentScope) -> &AtomicU64 {
+        let index = IntentScope::ALL
+            .iter()
+            .position(|registered| *registered == scope)
+            .expect("every scope is registered");
+        &self.next[index]
+    }
+}
+
+/// Attestation document reused across `/get_attestation` calls. The entry is
+/// refreshed once it is older than `ttl`, and always once it is older than
+/// `max_age`, so that no TTL setting can serve a document indefinitely after
+/// the clock or PCRs may have changed. A zero TTL disables caching.
+pub struct AttestationCache {
+    ttl: Duration,
+    max_age: Duration,
+    entry: Mutex<Option<CachedAttestation>>,
+}
+
+#[derive(Clone)]
+struct CachedAttestation {
+    document: Vec<u8>,
+    generated_at_ms: u64,
+    cached_at: Instant,
+}
+
+impl AttestationCache {
+    pub fn new(ttl: Duration, max_age: Duration) -> Self {
+        Self {
+            ttl,
+            max_age,
+            entry: Mutex::new(None),
+        }
+    }
+
+    /// Start with a document generated earlier, e.g. the boot attestation.
+    pub fn seed(&self, document: Vec<u8>, generated_at_ms: u64) {
+        *self.entry.lock().unwrap() = Some(CachedAttestation {
+            document,
+            generated_at_ms,
+            cached_at: Instant::now(),
+        });
+    }
+
+    /// The cached document and its generation time, calling `generate` for a
+    /// new document when there is no fresh entry.
+    pub fn get_or_refresh(
+        &self,
+        generate: impl FnOnce() -> Result<Vec<u8>, EnclaveError>,
+    ) -> Result<(Vec<u8>, u64), EnclaveError> {
+        self.get_or_refresh_at(Instant::now(), wall_clock_ms(), generate)
+    }
+
+    fn get_or_refresh_at(
+        &self,
+        now: Instant,
+        now_ms: u64,
+        generate: impl FnOnce() -> Result<Vec<u8>, EnclaveError>,
+    ) -> Result<(Vec<u8>, u64), EnclaveError> {
+        let mut entry = self.entry.lock().unwrap();
+        let limit = self.ttl.min(self.max_age);
+        if let Some(cached) = entry.as_ref() {
+            if now.duration_since(cached.cached_at) < limit {
+                return Ok((cached.document.clone(), cached.generated_at_ms));
+            }
+        }
+        let document = generate()?;
+        if !limit.is_zero() {
+            *entry = Some(CachedAttestation {
+                document: document.clone(),
+                generated_at_ms: now_ms,
+                cached_at: now,
+            });
+        }
+        Ok((document, now_ms))
+    }
+}
+
+/// Endpoint that returns an attestation committed
+/// to the enclave's public key. When responses are signed with the
+/// attestation digest, the cached boot attestation is returned so that
+/// verifiers see the document the digest refers to. Otherwise documents are
+/// reused for `ATTESTATION_CACHE_TTL_SECS`. A request with a nonce always gets
+/// a new document including it.
+///
+/// The document is hex encoded in JSON by default. With `?format=pem`, or an
+/// `Accept` header asking for `application/x-pem-file`, it's served as PEM
+/// along with its certificate chain, with the generation time in the
+/// `x-generated-at-ms` header.
+pub async fn get_attestation(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<GetAttestationQuery>,
+    headers: HeaderMap,
+) -> Result<Response, EnclaveError> {
+    info!("get attestation called");
+
+    let format =
+        AttestationFormat::negotiate(query.format, &headers, state.config.attestation_pem)?;
+    let nonce = query.nonce.as_deref().map(parse_nonce).transpose()?;
+    let (document, generated_at_ms) = match (nonce, &state.boot_attestation) {
+        (Some(nonce), _) => {
+            state.nonce_tracker.check(&nonce)?;
+            let document = nsm_attestation(
+                state.eph_kp.public().as_bytes(),
+                state.attestation_user_data().as_deref(),
+                Some(nonce),
+            )?;
+            state.stats.record_attestation();
+            (document, wall_clock_ms())
+        }
+        (None, Some(boot_attestation)) if state.config.sign_attestation_digest => (
+            boot_attestation.document.clone(),
+            boot_attestation.generated_at_ms,
+        ),
+        (None, _) => {
+            let mut hit = true;
+            let cached = state.attestation_cache.get_or_refresh(|| {
+                hit = false;
+                let document = nsm_attestation(
+                    state.eph_kp.public().as_bytes(),
+                    state.attestation_user_data().as_deref(),
+                    None,
+                )?;
+                state.stats.record_attestation();
+                Ok(document)
+            });
+            state.stats.record_attestation_cache(hit);
+            cached?
+        }
+    };
+    Ok(match format {
+        AttestationFormat::Hex => Json(GetAttestationResponse {
+            attestation: Hex::encode(document),
+            generated_at_ms,
+        })
+        .into_response(),
+        AttestationFormat::Pem => (
+            [
+                (CONTENT_TYPE, crate::pem::PEM_CONTENT_TYPE.to_string()),
+                (
+                    HeaderName::from_static(GENERATED_AT_HEADER),
+                    generated_at_ms.to_string(),
+                ),
+            ],
+            crate::pem::attestation_pem(&document),
+        )
+            .into_response(),
+    })
+}
+
+/// Decode a hex nonce from a `/get_attestation` query.
+fn parse_nonce(nonce: &str) -> Result<Vec<u8>, EnclaveError> {
+    let nonce = Hex::decode(nonce)
+        .map_err(|_| EnclaveError::GenericError("Invalid nonce: expected hex".to_string()))?;
+    if nonce.is_empty() || nonce.len() > MAX_NONCE_LEN {
+        return Err(EnclaveError::GenericError(format!(
+            "Invalid nonce: expected 1 to {} bytes, got {}",
+            MAX_NONCE_LEN,
+            nonce.len()
+        )));
+    }
+    Ok(nonce)
+}
+
+/// Identity of the enclave, as exported by init. Each field is None when
+/// init didn't set it, e.g. outside an enclave or with an older init.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+pub struct EnclaveIdentity {
+    /// Local vsock CID of the enclave. Env: `NAUTILUS_CID`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cid: Option<u32>,
+    /// Name given to the enclave with `nautilus.instance` on the kernel
+    /// command line. Env: `NAUTILUS_INSTANCE`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+    /// Version of init that booted the enclave. Env: `NAUTILUS_INIT_VERSION`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub init_version: Option<String>,
+}
+
+impl EnclaveIdentity {
+    /// Read the variables init exports, ignoring any that are unset or not
+    /// valid, since the identity is informational.
+    pub fn from_env() -> Self {
+        Self {
+            cid: std::env::var("NAUTILUS_CID")
+                .ok()
+                .and_then(|cid| cid.parse().ok()),
+            instance: std::env::var("NAUTILUS_INSTANCE").ok(),
+            init_version: std::env::var("NAUTILUS_INIT_VERSION").ok(),
         }
     }
 }
 
 /// Health check response.
 #[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
 pub struct HealthCheckResponse {
     /// Hex encoded public key booted on enclave.
     pub pk: String,
-    /// Status of endpoint connectivity checks
-    pub endpoints_status: HashMap<String, bool>,
+    /// Status of endpoint connectivity checks, including results from
+    /// earlier checks when each check probes a subset of the endpoints.
+    /// Sorted by endpoint, so responses can be diffed across calls.
+    pub endpoints_status: BTreeMap<String, bool>,
+    /// Whether the NSM answered a `DescribeNSM` request, with
+    /// `HEALTH_REQUIRE_NSM`. Omitted otherwise.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nsm_available: Option<bool>,
+    /// Hex encoded git commit the server was built from, as attested with
+    /// `ATTEST_BUILD_COMMIT`. Omitted when the build had none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub build_commit: Option<String>,
+    /// Milliseconds since each configured data source last returned data
+    /// that was signed, `null` until its first success. Unlike
+    /// `endpoints_status`, this catches an upstream that is reachable but
+    /// failing.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub ms_since_last_successful_fetch: BTreeMap<String, Option<u64>>,
+    /// Identity of the enclave, see [EnclaveIdentity].
+    #[serde(flatten)]
+    pub identity: EnclaveIdentity,
 }
 
 /// Endpoint that health checks the enclave connectivity to all
 /// domains and returns the enclave's public key.
+/// Connectivity to the allowed endpoints. With `HEALTH_REQUIRE_NSM` the NSM
+/// is probed too, and the status is 503 when it doesn't answer, since an
+/// enclave that can't attest shouldn't receive traffic.
 pub async fn health_check(
     State(state): State<Arc<AppState>>,
-) -> Result<Json<HealthCheckResponse>, EnclaveError> {
+) -> Result<(StatusCode, Json<HealthCheckResponse>), EnclaveError> {
     let pk = state.eph_kp.public();
+    let (hosts, results) = probe_endpoints(&state).await;
+    let endpoints_status = state.probe_rotation.merge(&hosts, results);
+    let nsm_available = state.config.health_require_nsm.then(|| match nsm_probe() {
+        Ok(()) => true,
+        Err(e) => {
+            warn!("Health check failed, NSM is unavailable: {}", e);
+            false
+        }
+    });
+    let status = match nsm_available {
+        Some(false) => StatusCode::SERVICE_UNAVAILABLE,
+        _ => StatusCode::OK,
+    };
 
-    // Create HTTP client with timeout
-    let client = Client::builder()
-        .timeout(Duration::from_secs(5))
-        .build()
-        .map_err(|e| EnclaveError::GenericError(format!("Failed to create HTTP client: {}", e)))?;
-
-    // Load allowed endpoints from YAML file
-    let endpoints_status = match std::fs::read_to_string("allowed_endpoints.yaml") {
-        Ok(yaml_content) => {
-            match serde_yaml::from_str::<serde_yaml::Value>(&yaml_content) {
-                Ok(yaml_value) => {
-                    let mut status_map = HashMap::new();
-
-                    if let Some(endpoints) =
-                        yaml_value.get("endpoints").and_then(|e| e.as_sequence())
-                    {
-                        for endpoint in endpoints {
-                            if let Some(endpoint_str) = endpoint.as_str() {
-                                // Check connectivity to each endpoint
-                                let url = if endpoint_str.contains(".amazonaws.com") {
-                                    format!("https://{}/ping", endpoint_str)
-                                } else {
-                                    format!("https://{}", endpoint_str)
-                                };
-
-                                let is_reachable = match client.get(&url).send().await {
-                                    Ok(response) => {
-                                        if endpoint_str.contains(".amazonaws.com") {
-                                            // For AWS endpoints, check if response body contains "healthy"
-                                            match response.text().await {
-                                                Ok(body) => body.to_lowercase().contains("healthy"),
-                                                Err(e) => {
-                                                    info!(
-                                                        "Failed to read response body from {}: {}",
-                                                        endpoint_str, e
-                                                    );
-                                                    false
-                                                }
-                                            }
-                                        } else {
-                                            // For non-AWS endpoints, check for 200 status
-                                            response.status().is_success()
-                                        }
-                                    }
-                                    Err(e) => {
-                                        info!("Failed to connect to {}: {}", endpoint_str, e);
-                                        false
-                                    }
-                                };
-
-                                status_map.insert(endpoint_str.to_string(), is_reachable);
-                                info!(
-                                    "Checked endpoint {}: reachable = {}",
-                                    endpoint_str, is_reachable
-                                );
-                            }
-                        }
-                    }
-
-                    status_map
-                }
-                Err(e) => {
-                    info!("Failed to parse YAML: {}", e);
-                    HashMap::new()
-                }
+    Ok((
+        status,
+        Json(HealthCheckResponse {
+            pk: Hex::encode(pk.as_bytes()),
+            endpoints_status,
+            nsm_available,
+            build_commit: crate::build_commit().map(Hex::encode),
+            ms_since_last_successful_fetch: state.last_fetches.elapsed_ms(),
+            identity: state.config.identity.clone(),
+        }),
+    ))
+}
+
+/// Check that the NSM device opens and answers a `DescribeNSM` request.
+pub(crate) fn nsm_probe() -> Result<(), EnclaveError> {
+    let fd = driver::nsm_init();
+    if fd < 0 {
+        return Err(EnclaveError::GenericError(
+            "Failed to open NSM device".to_string(),
+        ));
+    }
+    let response = driver::nsm_process_request(fd, NsmRequest::DescribeNSM);
+    driver::nsm_exit(fd);
+    match response {
+        NsmResponse::DescribeNSM { .. } => Ok(()),
+        response => Err(EnclaveError::GenericError(format!(
+            "unexpected NSM response: {:?}",
+            response
+        ))),
+    }
+}
+
+/// Response for the allowed endpoints config.
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+pub struct AllowedEndpointsResponse {
+    /// Contents of `allowed_endpoints.yaml` as read at startup.
+    pub yaml: String,
+    /// Hex encoded SHA-256 of `yaml`.
+    pub sha256: String,
+    /// Whether attestation documents carry `sha256` as their `user_data`,
+    /// see `ATTEST_ENDPOINTS_DIGEST`.
+    pub attested: bool,
+}
+
+/// Endpoint that returns the allowed endpoints config read at startup and
+/// its digest, so verifiers can see what the digest in an attestation's
+/// `user_data` covers. Health checks re-read the file, so edits made while
+/// the server runs are not reflected here.
+pub async fn allowed_endpoints(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<AllowedEndpointsResponse>, EnclaveError> {
+    let digest = state.endpoints_digest.as_ref().ok_or_else(|| {
+        EnclaveError::GenericError(format!("{} was not loaded", ALLOWED_ENDPOINTS_PATH))
+    })?;
+    Ok(Json(AllowedEndpointsResponse {
+        yaml: digest.yaml.clone(),
+        sha256: Hex::encode(&digest.sha256),
+        attested: state.config.attest_endpoints_digest,
+    }))
+}
+
+/// Probe this check's share of the allowed endpoints, see `ProbeRotation`.
+/// Returns every endpoint checked by health checks, and the results of the
+/// ones probed now.
+pub async fn probe_endpoints(state: &AppState) -> (Vec<String>, Vec<(String, bool)>) {
+    // Probe this check's share of the endpoints concurrently; the shared
+    // client's resolver bounds and caches the DNS lookups they trigger.
+    let endpoints: Vec<Endpoint> = load_allowed_endpoints(state.config.allowed_endpoints_strict)
+        .into_iter()
+        .take(state.config.max_allowed_endpoints)
+        .filter(|endpoint| !endpoint.check.skip)
+        .collect();
+    let hosts: Vec<String> = endpoints.iter().map(|e| e.host.clone()).collect();
+    let mut probes = JoinSet::new();
+    for host in state.probe_rotation.next_batch(&hosts) {
+        let client = state.probe_client.clone();
+        let endpoint = match endpoints.iter().find(|e| e.host == host) {
+            Some(endpoint) => endpoint.clone(),
+            None => continue,
+        };
+        probes.spawn(async move {
+            let is_reachable = probe_endpoint(&client, &endpoint).await;
+            (host, is_reachable)
+        });
+    }
+
+    let mut results = Vec::new();
+    while let Some(result) = probes.join_next().await {
+        match result {
+            Ok((endpoint, is_reachable)) => {
+                info!(
+                    "Checked endpoint {}: reachable = {}",
+                    endpoint, is_reachable
+                );
+                results.push((endpoint, is_reachable));
             }
+            Err(e) => info!("Endpoint probe task failed: {}", e),
         }
+    }
+    (hosts, results)
+}
+
+/// Load allowed endpoints from allowed_endpoints.yaml. Entries that can't be
+/// interpreted are logged and skipped, or with `strict` discard the whole file.
+pub fn load_allowed_endpoints(strict: bool) -> Vec<Endpoint> {
+    let yaml_content = match std::fs::read_to_string(ALLOWED_ENDPOINTS_PATH) {
+        Ok(yaml_content) => yaml_content,
         Err(e) => {
             info!("Failed to read allowed_endpoints.yaml: {}", e);
-            HashMap::new()
+            return Vec::new();
         }
     };
+    match parse_allowed_endpoints(&yaml_content) {
+        Ok(parsed) => {
+            for warning in &parsed.warnings {
+                warn!("allowed_endpoints.yaml: {}", warning);
+            }
+            if strict && !parsed.warnings.is_empty() {
+                warn!("Ignoring allowed_endpoints.yaml, ALLOWED_ENDPOINTS_STRICT is set");
+                return Vec::new();
+            }
+            parsed.endpoints
+        }
+        Err(e) => {
+            info!("Failed to parse allowed_endpoints.yaml: {}", e);
+            Vec::new()
+        }
+    }
+}
 
-    Ok(Json(HealthCheckResponse {
-        pk: Hex::encode(pk.as_bytes()),
-        endpoints_status,
-    }))
+/// Check connectivity to a single endpoint.
+async fn probe_endpoint(client: &Client, endpoint: &Endpoint) -> bool {
+    let url = format!("https://{}{}", endpoint.host, endpoint.check.path);
+    match client.get(&url).send().await {
+        Ok(response) => {
+            let status = response.status().as_u16();
+            if !endpoint.check.needs_body() {
+                return endpoint.check.passes(status, None);
+            }
+            match response.text().await {
+                Ok(body) => endpoint.check.passes(status, Some(&body)),
+                Err(e) => {
+                    info!("Failed to read response body from {}: {}", endpoint.host, e);
+                    false
+                }
+            }
+        }
+        Err(e) => {
+            info!("Failed to connect to {}: {}", endpoint.host, e);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_load_signing_key() {
+        use crate::vectors::{test_keypair, TEST_PRIVATE_KEY};
+
+        let expected = Hex::encode(test_keypair().public().as_bytes());
+        let path = std::env::temp_dir().join(format!("signing_key_{}", std::process::id()));
+        std::fs::write(&path, format!("0x{}\n", Hex::encode(TEST_PRIVATE_KEY))).unwrap();
+        let config = Config {
+            signing_key_path: Some(path.to_str().unwrap().to_string()),
+            ..Config::default()
+        };
+        let kp = init_keypair(&config).unwrap();
+        assert_eq!(Hex::encode(kp.public().as_bytes()), expected);
+        // The same key as the committed test vectors are signed with
+        assert_eq!(expected, crate::vectors::generate().public_key);
+
+        // Corrupt key material stops the server without echoing it
+        std::fs::write(&path, &Hex::encode(TEST_PRIVATE_KEY)[..63]).unwrap();
+        let e = init_keypair(&config).unwrap_err().to_string();
+        assert!(e.contains("expected 64 hex digits, got 63"), "{}", e);
+        assert!(!e.contains(&"07".repeat(8)), "{}", e);
+        std::fs::remove_file(&path).unwrap();
+        assert!(init_keypair(&config).is_err());
+
+        assert!(parse_signing_key(&"zz".repeat(32)).is_err());
+        assert!(parse_signing_key(&format!(" {}\n", "07".repeat(32))).is_ok());
+    }
+
+    #[test]
+    fn test_sequence_counters() {
+        let counters = SequenceCounters::default();
+        assert_eq!(counters.next(IntentScope::Weather), 0);
+        assert_eq!(counters.next(IntentScope::Weather), 1);
+        // Each scope counts on its own
+        assert_eq!(counters.next(IntentScope::WeatherFields), 0);
+        assert_eq!(counters.next(IntentScope::Weather), 2);
+
+        // Concurrent signers never share a number
+        let counters = Arc::new(SequenceCounters::default());
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let counters = counters.clone();
+                std::thread::spawn(move || {
+                    (0..100)
+                        .map(|_| counters.next(IntentScope::Weather))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        let mut taken: Vec<u64> = threads
+            .into_iter()
+            .flat_map(|thread| thread.join().unwrap())
+            .collect();
+        taken.sort();
+        assert_eq!(taken, (0..400).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_sequence_counters_next_with() {
+        let counters = SequenceCounters::default();
+        assert_eq!(
+            counters.next_with(IntentScope::Weather, 3, |first| Ok::<_, ()>(first)),
+            Ok(0)
+        );
+        // A failed signing takes no number
+        assert_eq!(
+            counters.next_with(IntentScope::Weather, 1, |_| Err::<u64, _>("too large")),
+            Err("too large")
+        );
+        assert_eq!(counters.next(IntentScope::Weather), 3);
+
+        // Numbers taken while signing are skipped, and signing runs again
+        let mut attempts = Vec::new();
+        let first = counters.next_with(IntentScope::Weather, 2, |first| {
+            attempts.push(first);
+            if attempts.len() == 1 {
+                counters.next(IntentScope::Weather);
+            }
+            Ok::<_, ()>(first)
+        });
+        assert_eq!(first, Ok(5));
+        assert_eq!(attempts, [4, 5]);
+        assert_eq!(counters.next(IntentScope::Weather), 7);
+    }
+
+    #[test]
+    fn test_bucket_timestamp() {
+        assert_eq!(bucket_timestamp(1744038912345, 0), 1744038912345);
+        assert_eq!(bucket_timestamp(1744038912345, 60_000), 1744038900000);
+        assert_eq!(bucket_timestamp(1744038900000, 60_000), 1744038900000);
+    }
+
+    fn before_epoch() -> SystemTime {
+        UNIX_EPOCH - Duration::from_secs(1)
+    }
+
+    #[test]
+    fn test_keypair_seed_source() {
+        let seeded = |seed: [u8; 32]| Ed25519KeyPair::generate(&mut StdRng::from_seed(seed));
+
+        // The NSM seed determines the key
+        let kp = keypair_with(KeySeedSource::Nsm, || Ok([7; 32]));
+        assert_eq!(kp.public().as_bytes(), seeded([7; 32]).public().as_bytes());
+
+        // Without the NSM the OS RNG is used
+        let kp = keypair_with(KeySeedSource::Nsm, || {
+            Err(EnclaveError::GenericError(
+                "Failed to open NSM device".to_string(),
+            ))
+        });
+        let other = keypair_with(KeySeedSource::Nsm, || {
+            Err(EnclaveError::GenericError(
+                "Failed to open NSM device".to_string(),
+            ))
+        });
+        assert_ne!(kp.public().as_bytes(), other.public().as_bytes());
+
+        // The NSM isn't asked with the default source
+        let kp = keypair_with(KeySeedSource::Os, || panic!("NSM asked for a seed"));
+        assert_ne!(kp.public().as_bytes(), seeded([7; 32]).public().as_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_timestamp_before_epoch_fails() {
+        let err = timestamp_ms_with(before_epoch, ClockBeforeEpoch::Fail, Duration::from_secs(1))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("clock not initialized"));
+        let ts = timestamp_ms_with(
+            || UNIX_EPOCH + Duration::from_millis(1744038900000),
+            ClockBeforeEpoch::Fail,
+            Duration::ZERO,
+        )
+        .await
+        .unwrap();
+        assert_eq!(ts, 1744038900000);
+    }
+
+    #[tokio::test]
+    async fn test_timestamp_before_epoch_retries() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        let reads = AtomicUsize::new(0);
+        let clock = || {
+            if reads.fetch_add(1, Ordering::SeqCst) < 2 {
+                before_epoch()
+            } else {
+                UNIX_EPOCH + Duration::from_millis(1744038900000)
+            }
+        };
+        let ts = timestamp_ms_with(clock, ClockBeforeEpoch::Retry, Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(ts, 1744038900000);
+        assert_eq!(reads.load(Ordering::SeqCst), 3);
+
+        let err = timestamp_ms_with(
+            before_epoch,
+            ClockBeforeEpoch::Retry,
+            Duration::from_millis(100),
+        )
+        .await
+        .unwrap_err();
+        assert!(err.to_string().contains("clock not initialized"));
+    }
+
+    #[test]
+    fn test_with_retries_backs_off() {
+        let mut calls = 0;
+        let mut sleeps = Vec::new();
+        let value = with_retries(
+            4,
+            Duration::from_millis(100),
+            |d| sleeps.push(d),
+            || {
+                calls += 1;
+                if calls < 3 {
+                    Err(EnclaveError::GenericError("NSM not ready".to_string()))
+                } else {
+                    Ok(calls)
+                }
+            },
+        )
+        .unwrap();
+        assert_eq!(value, 3);
+        assert_eq!(
+            sleeps,
+            [Duration::from_millis(100), Duration::from_millis(200)]
+        );
+    }
+
+    #[test]
+    fn test_with_retries_gives_up() {
+        let mut calls = 0;
+        let mut sleeps = 0;
+        let err = with_retries(
+            3,
+            Duration::ZERO,
+            |_| sleeps += 1,
+            || -> Result<(), EnclaveError> {
+                calls += 1;
+                Err(EnclaveError::GenericError("NSM not ready".to_string()))
+            },
+        )
+        .unwrap_err();
+        assert_eq!((calls, sleeps), (3, 2));
+        assert!(err
+            .to_string()
+            .contains("after 3 attempt(s): NSM not ready"));
+    }
+
+    #[test]
+    fn test_intent_scope_from_u8() {
+        for scope in IntentScope::ALL {
+            assert_eq!(IntentScope::try_from(scope as u8).unwrap(), scope);
+        }
+        let err = IntentScope::try_from(8).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unknown intent scope 8, registered scopes are 0 (Weather), 1 (WeatherWithAttestationDigest), 2 (WeatherWithUpstreamLatency), 3 (WeatherFields), 4 (Price), 5 (Compressed), 6 (GithubCommit), 7 (SuiTransaction)"
+        );
+    }
+
+    #[test]
+    fn test_signing_format() {
+        use fastcrypto::ed25519::Ed25519Signature;
+        use fastcrypto::traits::VerifyingKey;
+        use serde_json::{json, Value};
+
+        let kp = Ed25519KeyPair::generate(&mut StdRng::from_seed([0; 32]));
+        // Keys in reverse order, which canonicalization must undo
+        let payload = json!({"temperature": 13, "location": "Paris"});
+        let sign = |format| {
+            to_signed_response(
+                &kp,
+                payload.clone(),
+                1_744_038_900_000,
+                None,
+                Some(7),
+                IntentScope::Weather,
+                format,
+            )
+        };
+
+        let signed = sign(SigningFormat::Jcs);
+        let canonical = r#"{"data":{"location":"Paris","temperature":13},"intent":0,"sequence":7,"timestamp_ms":1744038900000,"version":2}"#;
+        assert_eq!(
+            signing_bytes(SigningFormat::Jcs, &signed.response).unwrap(),
+            canonical.as_bytes()
+        );
+        let signature =
+            Ed25519Signature::from_bytes(&Hex::decode(&signed.signature).unwrap()).unwrap();
+        assert!(kp.public().verify(canonical.as_bytes(), &signature).is_ok());
+        // Deterministic: the same message always signs the same bytes
+        assert_eq!(sign(SigningFormat::Jcs).signature, signed.signature);
+        assert_ne!(sign(SigningFormat::Bcs).signature, signed.signature);
+
+        // The format is named in the envelope, and BCS is assumed without it
+        let envelope = serde_json::to_value(&signed).unwrap();
+        assert_eq!(envelope["signing_format"], "jcs");
+        let legacy: ProcessedDataResponse<Value> =
+            serde_json::from_value(json!({"response": {}, "signature": ""})).unwrap();
+        assert_eq!(legacy.signing_format, SigningFormat::Bcs);
+    }
+
+    #[test]
+    fn test_responses_verify_with_nautilus_verifier() {
+        // What a browser or wasm verifier deserializes and checks
+        #[derive(Serialize, Deserialize, Clone)]
+        struct Reading {
+            location: String,
+            temperature: u64,
+        }
+        let kp = Ed25519KeyPair::generate(&mut StdRng::from_seed([1; 32]));
+        for format in [SigningFormat::Bcs, SigningFormat::Jcs] {
+            for (expires_at_ms, sequence) in [
+                (None, None),
+                (Some(1_744_038_960_000), None),
+                (None, Some(3)),
+            ] {
+                let signed = to_signed_response(
+                    &kp,
+                    Reading {
+                        location: "Paris".to_string(),
+                        temperature: 13,
+                    },
+                    1_744_038_900_000,
+                    expires_at_ms,
+                    sequence,
+                    IntentScope::Price,
+                    format,
+                );
+                let json = serde_json::to_string(&signed).unwrap();
+                let mut received: nautilus_verifier::SignedResponse<Reading> =
+                    serde_json::from_str(&json).unwrap();
+                assert_eq!(received.signing_format, format);
+                assert_eq!(
+                    nautilus_verifier::verify_response(kp.public().as_bytes(), &received),
+                    Ok(())
+                );
+                received.response.data.temperature = 30;
+                assert_eq!(
+                    nautilus_verifier::verify_response(kp.public().as_bytes(), &received),
+                    Err(nautilus_verifier::Error::InvalidSignature)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_attestation_cache_max_age() {
+        // The hard maximum age applies even with a longer TTL.
+        let cache = AttestationCache::new(Duration::from_secs(3600), Duration::from_secs(60));
+        let start = Instant::now();
+        let generate = |document: u8| move || Ok(vec![document]);
+        let at = |secs| start + Duration::from_secs(secs);
+
+        let first = cache.get_or_refresh_at(at(0), 1_000, generate(1)).unwrap();
+        assert_eq!(first, (vec![1], 1_000));
+        let cached = cache
+            .get_or_refresh_at(at(59), 60_000, generate(2))
+            .unwrap();
+        assert_eq!(cached, (vec![1], 1_000));
+        let refreshed = cache
+            .get_or_refresh_at(at(60), 61_000, generate(3))
+            .unwrap();
+        assert_eq!(refreshed, (vec![3], 61_000));
+    }
+
+    #[test]
+    fn test_attestation_cache_ttl() {
+        let cache = AttestationCache::new(Duration::from_secs(10), Duration::from_secs(60));
+        let start = Instant::now();
+        cache.seed(vec![0], 5);
+        let cached = cache
+            .get_or_refresh_at(start, 0, || panic!("seeded entry should be used"))
+            .unwrap();
+        assert_eq!(cached, (vec![0], 5));
+        let refreshed = cache
+            .get_or_refresh_at(start + Duration::from_secs(11), 11_000, || Ok(vec![1]))
+            .unwrap();
+        assert_eq!(refreshed, (vec![1], 11_000));
+
+        let disabled = AttestationCache::new(Duration::ZERO, Duration::from_secs(60));
+        disabled
+            .get_or_refresh_at(start, 0, || Ok(vec![1]))
+            .unwrap();
+        let refreshed = disabled
+            .get_or_refresh_at(start, 0, || Ok(vec![2]))
+            .unwrap();
+        assert_eq!(refreshed.0, vec![2]);
+    }
+
+    #[test]
+    fn test_parse_nonce() {
+        assert_eq!(parse_nonce("0a0b").unwrap(), vec![0x0a, 0x0b]);
+        assert!(parse_nonce("").is_err());
+        assert!(parse_nonce("zz").is_err());
+        assert!(parse_nonce(&"00".repeat(MAX_NONCE_LEN)).is_ok());
+        assert!(parse_nonce(&"00".repeat(MAX_NONCE_LEN + 1)).is_err());
+    }
+
+    #[test]
+    fn test_attestation_format() {
+        let accept = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(ACCEPT, value.parse().unwrap());
+            headers
+        };
+        let none = HeaderMap::new();
+        let pem = accept("text/plain, Application/X-PEM-File;q=0.9");
+        let negotiate = AttestationFormat::negotiate;
+
+        assert_eq!(
+            negotiate(None, &none, true).unwrap(),
+            AttestationFormat::Hex
+        );
+        assert_eq!(negotiate(None, &pem, true).unwrap(), AttestationFormat::Pem);
+        assert_eq!(
+            negotiate(None, &accept("application/json"), true).unwrap(),
+            AttestationFormat::Hex
+        );
+        // The query wins over Accept
+        assert_eq!(
+            negotiate(Some(AttestationFormat::Hex), &pem, true).unwrap(),
+            AttestationFormat::Hex
+        );
+        assert_eq!(
+            negotiate(Some(AttestationFormat::Pem), &none, true).unwrap(),
+            AttestationFormat::Pem
+        );
+        // Disabled, Accept is ignored and an explicit request refused
+        assert_eq!(
+            negotiate(None, &pem, false).unwrap(),
+            AttestationFormat::Hex
+        );
+        assert!(negotiate(Some(AttestationFormat::Pem), &none, false).is_err());
+    }
+
+    #[test]
+    fn test_check_bcs_golden() {
+        let fixture = [0x00, 0x20, 0xb1];
+        assert!(check_bcs_golden(&fixture, "0020b1").is_ok());
+        assert!(check_bcs_golden(&fixture, "0x0020b1\n").is_ok());
+        assert!(check_bcs_golden(&fixture, "0020b2").is_err());
+        assert!(check_bcs_golden(&fixture, "0020").is_err());
+        assert!(check_bcs_golden(&fixture, "not hex").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_health_check_requires_nsm() {
+        use crate::api_keys::ApiKeys;
+        let state = |health_require_nsm| {
+            Arc::new(
+                AppState::new(
+                    Ed25519KeyPair::generate(&mut rand::thread_rng()),
+                    ApiKeys::single(String::new()),
+                    Config {
+                        health_require_nsm,
+                        ..Config::default()
+                    },
+                )
+                .unwrap(),
+            )
+        };
+        let (status, Json(response)) = health_check(State(state(false))).await.unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert!(response.nsm_available.is_none());
+        // Nothing was fetched yet
+        assert_eq!(
+            response.ms_since_last_successful_fetch,
+            [("weather".to_string(), None)].into_iter().collect()
+        );
+
+        // There is no NSM outside an enclave
+        let (status, Json(response)) = health_check(State(state(true))).await.unwrap();
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.nsm_available, Some(false));
+    }
+
+    #[test]
+    fn test_health_check_identity() {
+        let response = HealthCheckResponse {
+            pk: "00".to_string(),
+            endpoints_status: BTreeMap::new(),
+            nsm_available: None,
+            build_commit: None,
+            ms_since_last_successful_fetch: BTreeMap::new(),
+            identity: EnclaveIdentity {
+                cid: Some(16),
+                instance: Some("weather-1".to_string()),
+                init_version: None,
+            },
+        };
+        let serialized = serde_json::to_string(&response).unwrap();
+        assert_eq!(
+            serialized,
+            r#"{"pk":"00","endpoints_status":{},"cid":16,"instance":"weather-1"}"#
+        );
+        let parsed: HealthCheckResponse = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(parsed.identity, response.identity);
+    }
+
+    #[test]
+    fn test_health_check_response_order() {
+        let endpoints = [
+            "kms.us-east-1.amazonaws.com",
+            "api.weatherapi.com",
+            "sts.amazonaws.com",
+        ];
+        let serialized = [endpoints, [endpoints[2], endpoints[0], endpoints[1]]].map(|order| {
+            let response = HealthCheckResponse {
+                pk: "00".to_string(),
+                endpoints_status: order.iter().map(|e| (e.to_string(), true)).collect(),
+                nsm_available: None,
+                build_commit: None,
+                ms_since_last_successful_fetch: BTreeMap::new(),
+                identity: EnclaveIdentity::default(),
+            };
+            serde_json::to_string(&response).unwrap()
+        });
+        assert_eq!(serialized[0], serialized[1]);
+        assert_eq!(
+            serialized[0],
+            r#"{"pk":"00","endpoints_status":{"api.weatherapi.com":true,"kms.us-east-1.amazonaws.com":true,"sts.amazonaws.com":true}}"#
+        );
+    }
 }
@@ -0,0 +1,684 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::api_keys::ApiKeyPolicy;
+use crate::app::{TemperatureParsing, TimestampSource, WEATHER_API_URL};
+#[cfg(feature = "grpc")]
+use crate::common::load_allowed_endpoints;
+use crate::common::{EnclaveIdentity, SigningFormat};
+use crate::dns::AddressFamily;
+#[cfg(feature = "github")]
+use crate::github::GithubUpstream;
+#[cfg(feature = "grpc")]
+use crate::grpc::GrpcUpstream;
+use crate::health::{EndpointsOverflow, RotationOrder};
+#[cfg(feature = "mock-weather")]
+use crate::mock_weather::MockWeather;
+#[cfg(feature = "mtls")]
+use crate::mtls::Mtls;
+use crate::nonces::NonceReuse;
+use crate::pcrs::ExpectedPcrs;
+use crate::price::PriceUpstream;
+use crate::routes::TrailingSlash;
+#[cfg(feature = "sui-transactions")]
+use crate::transaction::TransactionPolicy;
+use crate::EnclaveError;
+use std::fmt::Display;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Server configuration, read from environment variables at startup. Inside the
+/// enclave these can be supplied alongside API_KEY in the secrets JSON that
+/// run.sh exports.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// How long health-check probes reuse resolved endpoint addresses.
+    /// Zero disables caching. Env: `DNS_CACHE_TTL_SECS`.
+    pub dns_cache_ttl: Duration,
+    /// Maximum number of DNS lookups health-check probes run at once.
+    /// Env: `DNS_MAX_CONCURRENT_LOOKUPS`.
+    pub dns_max_concurrent_lookups: usize,
+    /// Address families the upstream and health-check clients connect over,
+    /// matching the egress available to the enclave.
+    /// Env: `ADDRESS_FAMILY` (`v4`, `v6` or `dual`).
+    pub address_family: AddressFamily,
+    /// Ignore all of `allowed_endpoints.yaml` when any entry can't be
+    /// interpreted, instead of skipping just that entry.
+    /// Env: `ALLOWED_ENDPOINTS_STRICT`.
+    pub allowed_endpoints_strict: bool,
+    /// Most endpoints `allowed_endpoints.yaml` may list, bounding the cost of
+    /// health checks. Env: `MAX_ALLOWED_ENDPOINTS`.
+    pub max_allowed_endpoints: usize,
+    /// What a longer list does at startup. Env: `ALLOWED_ENDPOINTS_OVERFLOW`
+    /// (`fail` or `truncate`).
+    pub allowed_endpoints_overflow: EndpointsOverflow,
+    /// How many endpoints each health check probes, rotating through the
+    /// list across checks. Zero probes every endpoint on every check.
+    /// Env: `HEALTH_CHECK_SUBSET_SIZE`.
+    pub health_check_subset_size: usize,
+    /// Order health checks rotate through the endpoints in.
+    /// Env: `HEALTH_CHECK_ROTATION` (`round_robin` or `shuffled`).
+    pub health_check_rotation: RotationOrder,
+    /// How long a probe result is reported by later health checks that don't
+    /// probe that endpoint. Env: `HEALTH_CHECK_RESULT_TTL_SECS`.
+    pub health_check_result_ttl: Duration,
+    /// Report `/health_check` as unhealthy (503) when the NSM doesn't answer,
+    /// even if every endpoint is reachable. Env: `HEALTH_REQUIRE_NSM`.
+    pub health_require_nsm: bool,
+    /// URL that endpoint reachability changes are POSTed to, from a
+    /// background task probing the endpoints. Unset disables the task.
+    /// Env: `HEALTH_WEBHOOK_URL`.
+    pub health_webhook_url: Option<String>,
+    /// How often the webhook task probes the endpoints.
+    /// Env: `HEALTH_WEBHOOK_INTERVAL_SECS`.
+    pub health_webhook_interval: Duration,
+    /// Consecutive probes that must agree on an endpoint's new state before
+    /// the webhook is notified. Env: `HEALTH_WEBHOOK_THRESHOLD`.
+    pub health_webhook_threshold: u32,
+    /// Randomness used to generate the ephemeral keypair. Env: `KEY_SEED_SOURCE`
+    /// (`os` or `nsm`).
+    pub key_seed_source: KeySeedSource,
+    /// File holding the hex encoded 32-byte Ed25519 private key to sign with,
+    /// e.g. provisioned by the secret manager, instead of generating an
+    /// ephemeral keypair, so the key survives restarts. `key_seed_source` is
+    /// then unused. The server refuses to start if the file can't be read or
+    /// doesn't hold a valid key, or with `sign_sequence`, whose numbers would
+    /// repeat under the same key after a restart. Env: `SIGNING_KEY_PATH`.
+    pub signing_key_path: Option<String>,
+    /// Granularity the signed timestamp is rounded down to, in milliseconds.
+    /// Zero signs the exact upstream timestamp. This changes the signed bytes.
+    /// Env: `TIMESTAMP_BUCKET_MS`.
+    pub timestamp_bucket_ms: u64,
+    /// How long after its signed timestamp a weather response expires, in
+    /// milliseconds. When non-zero, `expires_at_ms` is signed in every
+    /// response, which changes the signed layout. Zero signs no expiry.
+    /// Env: `SIGN_EXPIRY_MS`.
+    pub sign_expiry_ms: u64,
+    /// Sign a sequence number in every weather response, counted per intent
+    /// scope from zero at startup, so verifiers can detect dropped or
+    /// reordered responses. Changes the signed layout, and can't be combined
+    /// with `sign_expiry_ms` or `signing_key_path`. Env: `SIGN_SEQUENCE`.
+    pub sign_sequence: bool,
+    /// Also return each weather response as a JWT signed with EdDSA by the
+    /// enclave key, for verifiers that consume JWTs. The BCS signature is
+    /// still returned. Env: `SIGN_JWT`.
+    pub sign_jwt: bool,
+    /// Serialization of the signed intent messages: `bcs`, as verified by
+    /// `enclave.move`, or `jcs` for canonical JSON. Every response names it
+    /// in `signing_format`. Env: `SIGNING_FORMAT`.
+    pub signing_format: SigningFormat,
+    /// Send `Cache-Control: max-age` and `Expires` headers with weather
+    /// responses, matching their signed `expires_at_ms`, so HTTP clients and
+    /// caches can tell when they expire without parsing the payload. Requires
+    /// `sign_expiry_ms`. Env: `EXPIRY_HEADERS`.
+    pub expiry_headers: bool,
+    /// Sign weather data under `IntentScope::WeatherWithAttestationDigest`,
+    /// committing to the SHA-384 digest of the attestation document generated
+    /// at boot. Env: `SIGN_ATTESTATION_DIGEST`.
+    pub sign_attestation_digest: bool,
+    /// Sign weather data under `IntentScope::WeatherWithUpstreamLatency`,
+    /// committing to how long the upstream fetch took. Can't be combined
+    /// with `sign_attestation_digest`. Env: `SIGN_UPSTREAM_LATENCY`.
+    pub sign_upstream_latency: bool,
+    /// Sign the listed upstream fields under `IntentScope::WeatherFields`
+    /// instead of the location and temperature. Paths are dotted, with
+    /// numeric segments indexing arrays, e.g. `location.name,current.temp_c`.
+    /// Each value is signed as canonical JSON, and a response missing any of
+    /// them is refused. Can't be combined with `sign_attestation_digest` or
+    /// `sign_upstream_latency`. Env: `SIGNED_FIELDS` (comma separated).
+    pub signed_fields: Vec<String>,
+    /// Request the attestation document at boot and serve it from
+    /// `/get_attestation`, for deployments that register the enclave right
+    /// after startup. Implied by `sign_attestation_digest`.
+    /// Env: `BOOT_ATTESTATION`.
+    pub boot_attestation: bool,
+    /// How long `/get_attestation` reuses a document. Zero requests a new
+    /// document for every call. Env: `ATTESTATION_CACHE_TTL_SECS`.
+    pub attestation_cache_ttl: Duration,
+    /// Age after which a cached document is always replaced, whatever the
+    /// TTL. Env: `ATTESTATION_CACHE_MAX_AGE_SECS`.
+    pub attestation_cache_max_age: Duration,
+    /// How many recent `/get_attestation` nonces are remembered to detect
+    /// reuse. Zero disables the check. Env: `ATTESTATION_NONCE_WINDOW`.
+    pub attestation_nonce_window: usize,
+    /// What a reused nonce does. Env: `ATTESTATION_NONCE_REUSE` (`warn` or
+    /// `reject`).
+    pub attestation_nonce_reuse: NonceReuse,
+    /// Let `/get_attestation` serve PEM, with `?format=pem` or an `Accept`
+    /// header asking for `application/x-pem-file`. When disabled, Accept is
+    /// ignored and `format=pem` is refused. Env: `ATTESTATION_PEM`.
+    pub attestation_pem: bool,
+    /// Put the SHA-256 of `allowed_endpoints.yaml`, as read at startup, in
+    /// the `user_data` of every attestation document, binding the declared
+    /// outbound policy to the enclave's attested key. The server refuses to
+    /// start without the file. Env: `ATTEST_ENDPOINTS_DIGEST`.
+    pub attest_endpoints_digest: bool,
+    /// Put the git commit the server was built from in the `user_data` of
+    /// every attestation document, after the endpoints digest if both are
+    /// set, see `nautilus_verifier::attestation::UserData`. The server
+    /// refuses to start if the build had no commit. Env:
+    /// `ATTEST_BUILD_COMMIT`.
+    pub attest_build_commit: bool,
+    /// How many times the boot attestation is requested before giving up.
+    /// Env: `BOOT_ATTESTATION_ATTEMPTS`.
+    pub boot_attestation_attempts: u32,
+    /// Delay before the first retry of the boot attestation, doubled after
+    /// each failed attempt. Env: `BOOT_ATTESTATION_BACKOFF_MS`.
+    pub boot_attestation_backoff: Duration,
+    /// Refuse to start when the boot attestation can't be obtained. When
+    /// false the server starts without it, unless `sign_attestation_digest`
+    /// needs it. Env: `BOOT_ATTESTATION_REQUIRED`.
+    pub boot_attestation_required: bool,
+    /// Expected hex of the BCS encoded serialization fixture, e.g. the value
+    /// asserted by `test_serde` in Move. When set, the server refuses to start
+    /// if its own encoding differs. Env: `BCS_GOLDEN_HEX`.
+    pub bcs_golden_hex: Option<String>,
+    /// What to do when the system clock reads before the UNIX epoch, which
+    /// happens if the enclave serves requests before its clock is set.
+    /// Env: `CLOCK_BEFORE_EPOCH` (`fail` or `retry`).
+    pub clock_before_epoch: ClockBeforeEpoch,
+    /// How long [ClockBeforeEpoch::Retry] waits for the clock to be set.
+    /// Env: `CLOCK_RETRY_TIMEOUT_MS`.
+    pub clock_retry_timeout: Duration,
+    /// Requests taking at least this long are logged with their route and
+    /// request ID. Zero disables the log. Env: `SLOW_REQUEST_THRESHOLD_MS`.
+    pub slow_request_threshold: Duration,
+    /// Log one in this many successful requests, and every failed one, with
+    /// their route, duration, status and request ID. Zero disables the log.
+    /// Env: `REQUEST_LOG_SAMPLE`.
+    pub request_log_sample: u64,
+    /// Requests whose headers together take more bytes than this, counting
+    /// each as `name: value` and a line break, are rejected with a 431.
+    /// hyper stops reading a request head at twice this plus room for the
+    /// request line. Zero disables the limit, leaving hyper's default read
+    /// buffer. Env: `MAX_HEADER_BYTES`.
+    pub max_header_bytes: usize,
+    /// Requests with more headers than this are rejected with a 431. hyper
+    /// stops parsing at twice this many. Zero disables the limit, leaving
+    /// hyper's default of 100. Env: `MAX_HEADER_COUNT`.
+    pub max_header_count: usize,
+    /// How `/process_data` chooses among the keys in `API_KEYS`.
+    /// Env: `API_KEY_POLICY` (`round_robin` or `tenant`).
+    pub api_key_policy: ApiKeyPolicy,
+    /// Whether `/process_data` accepts a `temp_c` sent as a numeric string.
+    /// Env: `TEMPERATURE_PARSING` (`strict` or `lenient`).
+    pub temperature_parsing: TemperatureParsing,
+    /// Which weather response timestamp `/process_data` signs. Env:
+    /// `TIMESTAMP_SOURCE` (`epoch` or `local_time`).
+    pub timestamp_source: TimestampSource,
+    /// How far `last_updated_epoch` and `last_updated` may be apart when a
+    /// response has both. Env: `TIMESTAMP_TOLERANCE_SECS`.
+    pub timestamp_tolerance: Duration,
+    /// Query parameters clients may pass through to the upstream API in
+    /// `WeatherRequest::params`. Env: `UPSTREAM_PARAMS` (comma separated).
+    pub upstream_params: Vec<String>,
+    /// Also accept `GET /process_data?location=<name>`, with optional `lang`
+    /// and `intent`, for clients that can only send GET. Env:
+    /// `PROCESS_DATA_GET`.
+    pub process_data_get: bool,
+    /// Fetch repeated requests in a `/process_data_batch` call once and sign
+    /// that reading for each occurrence, saving upstream calls. When false
+    /// every occurrence is fetched, and signed with its own timestamp.
+    /// Env: `BATCH_DEDUP`.
+    pub batch_dedup: bool,
+    /// Most upstream fetches one `/process_data_batch` call runs at once. One
+    /// fetches the batch sequentially. Env: `BATCH_MAX_FANOUT`.
+    pub batch_max_fanout: usize,
+    /// Reject `/process_data` and `/process_data_batch` calls whose signed
+    /// response is larger than the client's `X-Max-Response-Bytes` header.
+    /// When false the header is ignored. Env: `HONOR_MAX_RESPONSE_BYTES`.
+    pub honor_max_response_bytes: bool,
+    /// gRPC method queried for weather data instead of the REST API.
+    /// Env: `GRPC_UPSTREAM`, `GRPC_METHOD` and `GRPC_FIELDS`, see
+    /// [GrpcUpstream::from_env].
+    #[cfg(feature = "grpc")]
+    pub grpc_upstream: Option<GrpcUpstream>,
+    /// Fake readings signed instead of querying any upstream, for offline
+    /// development. Env: `MOCK_WEATHER` and related variables, see
+    /// [MockWeather::from_env]. Without the `mock-weather` feature the
+    /// server refuses to start with `MOCK_WEATHER=true`.
+    #[cfg(feature = "mock-weather")]
+    pub mock_weather: Option<MockWeather>,
+    /// Price API signed by `/process_price`, which isn't served when unset.
+    /// Env: `PRICE_URL` and related variables, see
+    /// [PriceUpstream::from_env].
+    pub price_upstream: Option<PriceUpstream>,
+    /// GitHub API read by `/process_github_commit`, which isn't served when
+    /// unset. Env: `GITHUB_TOKEN` and `GITHUB_API_URL`, see
+    /// [GithubUpstream::from_env].
+    #[cfg(feature = "github")]
+    pub github_upstream: Option<GithubUpstream>,
+    /// Checks `/process_transaction` applies before signing Sui
+    /// transactions. The route isn't served when unset. Env:
+    /// `SIGN_TRANSACTIONS` and related variables, see
+    /// [TransactionPolicy::from_env].
+    #[cfg(feature = "sui-transactions")]
+    pub transaction_policy: Option<TransactionPolicy>,
+    /// Serve over TLS and reject clients without a certificate from the
+    /// trusted CA. Plain HTTP when unset. Env: `TLS_CLIENT_CA_PATH`,
+    /// `TLS_CERT_PATH` and `TLS_KEY_PATH`, see [Mtls::from_env].
+    #[cfg(feature = "mtls")]
+    pub mtls: Option<Mtls>,
+    /// PCRs the enclave image is expected to measure, served by
+    /// `/expected_pcrs`. Env: `EXPECTED_PCRS` (`PCR0=<hex>,PCR1=<hex>,PCR2=<hex>`).
+    pub expected_pcrs: Option<ExpectedPcrs>,
+    /// YAML file of schemas for `/sign/:schema`. No schemas are registered
+    /// when unset. Env: `SCHEMAS_PATH`.
+    pub schemas_path: Option<String>,
+    /// Whether `/process_data/` and the like match the route without the
+    /// trailing slash. Env: `TRAILING_SLASH` (`strict` or `lenient`).
+    pub trailing_slash: TrailingSlash,
+    /// Include the TLS library's error text in responses when the handshake
+    /// with the upstream fails, not just the reason. It is always logged.
+    /// Env: `UPSTREAM_TLS_DETAIL`.
+    pub upstream_tls_detail: bool,
+    /// Answer weatherapi.com errors that mean the request can't be served,
+    /// such as code 1006 for an unknown location, with a 404 and a clean
+    /// message, see `UPSTREAM_NOT_FOUND_CODES` in `app.rs`. When false they
+    /// fail like any other unusable reading. Env: `MAP_UPSTREAM_ERRORS`.
+    pub map_upstream_errors: bool,
+    /// Current weather endpoint queried by `/process_data`, e.g. a mock
+    /// upstream in tests. Env: `WEATHER_API_URL`.
+    pub weather_api_url: reqwest::Url,
+    /// Bearer token authorizing `/rotate_api_key`. The route isn't served
+    /// when unset. Env: `API_KEY_ROTATION_TOKEN`.
+    pub api_key_rotation_token: Option<String>,
+    /// Bearer token authorizing the admin endpoints, currently `/stats`.
+    /// They aren't served when unset. Env: `ADMIN_TOKEN`.
+    pub admin_token: Option<String>,
+    /// How often a fresh attestation is requested in the background, to
+    /// prove the enclave is still live and detect its public key or PCRs
+    /// changing. Unset or zero disables it. Env: `REATTEST_INTERVAL_SECS`.
+    pub reattest_interval: Option<Duration>,
+    /// How often fresh entropy is drawn from the NSM and mixed into the
+    /// kernel RNG, which init only seeds at boot. Unset or zero disables it.
+    /// Env: `RESEED_INTERVAL_SECS`.
+    pub reseed_interval: Option<Duration>,
+    /// Identity of the enclave reported by `/health_check`, exported by init.
+    /// Env: `NAUTILUS_CID`, `NAUTILUS_INSTANCE` and `NAUTILUS_INIT_VERSION`.
+    pub identity: EnclaveIdentity,
+}
+
+/// Source of the seed for the ephemeral keypair.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeySeedSource {
+    /// The operating system RNG (`rand::thread_rng`).
+    Os,
+    /// The NSM hardware RNG, falling back to the OS RNG if NSM is unavailable.
+    Nsm,
+}
+
+impl FromStr for KeySeedSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "os" => Ok(Self::Os),
+            "nsm" => Ok(Self::Nsm),
+            _ => Err(format!("expected `os` or `nsm`, got `{}`", s)),
+        }
+    }
+}
+
+/// Behavior when the system clock is before the UNIX epoch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockBeforeEpoch {
+    /// Fail the request with a "clock not initialized" error.
+    Fail,
+    /// Wait up to `clock_retry_timeout` for the clock to be set, then fail.
+    Retry,
+}
+
+impl FromStr for ClockBeforeEpoch {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fail" => Ok(Self::Fail),
+            "retry" => Ok(Self::Retry),
+            _ => Err(format!("expected `fail` or `retry`, got `{}`", s)),
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            dns_cache_ttl: Duration::from_secs(30),
+            dns_max_concurrent_lookups: 8,
+            address_family: AddressFamily::Dual,
+            allowed_endpoints_strict: false,
+            max_allowed_endpoints: 100,
+            allowed_endpoints_overflow: EndpointsOverflow::Fail,
+            health_check_subset_size: 0,
+            health_check_rotation: RotationOrder::RoundRobin,
+            health_check_result_ttl: Duration::from_secs(300),
+            health_require_nsm: false,
+            health_webhook_url: None,
+            health_webhook_interval: Duration::from_secs(60),
+            health_webhook_threshold: 3,
+            key_seed_source: KeySeedSource::Os,
+            signing_key_path: None,
+            timestamp_bucket_ms: 0,
+            sign_expiry_ms: 0,
+            sign_sequence: false,
+            sign_jwt: false,
+            signing_format: SigningFormat::Bcs,
+            expiry_headers: false,
+            sign_attestation_digest: false,
+            sign_upstream_latency: false,
+            signed_fields: Vec::new(),
+            boot_attestation: false,
+            attestation_cache_ttl: Duration::ZERO,
+            attestation_cache_max_age: Duration::from_secs(3600),
+            attestation_nonce_window: 0,
+            attestation_nonce_reuse: NonceReuse::Warn,
+            attestation_pem: true,
+            attest_endpoints_digest: false,
+            attest_build_commit: false,
+            boot_attestation_attempts: 3,
+            boot_attestation_backoff: Duration::from_millis(200),
+            boot_attestation_required: true,
+            bcs_golden_hex: None,
+            clock_before_epoch: ClockBeforeEpoch::Fail,
+            clock_retry_timeout: Duration::from_millis(1000),
+            slow_request_threshold: Duration::from_millis(2000),
+            request_log_sample: 0,
+            max_header_bytes: 8192,
+            max_header_count: 64,
+            api_key_policy: ApiKeyPolicy::RoundRobin,
+            temperature_parsing: TemperatureParsing::Strict,
+            timestamp_source: TimestampSource::Epoch,
+            timestamp_tolerance: Duration::from_secs(60),
+            upstream_params: Vec::new(),
+            process_data_get: false,
+            batch_dedup: false,
+            batch_max_fanout: 4,
+            honor_max_response_bytes: true,
+            #[cfg(feature = "grpc")]
+            grpc_upstream: None,
+            #[cfg(feature = "mock-weather")]
+            mock_weather: None,
+            price_upstream: None,
+            #[cfg(feature = "github")]
+            github_upstream: None,
+            #[cfg(feature = "sui-transactions")]
+            transaction_policy: None,
+            #[cfg(feature = "mtls")]
+            mtls: None,
+            expected_pcrs: None,
+            schemas_path: None,
+            trailing_slash: TrailingSlash::Strict,
+            upstream_tls_detail: false,
+            map_upstream_errors: true,
+            weather_api_url: reqwest::Url::parse(WEATHER_API_URL).expect("should not fail"),
+            api_key_rotation_token: None,
+            admin_token: None,
+            reattest_interval: None,
+            reseed_interval: None,
+            identity: EnclaveIdentity::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Load the configuration, using defaults for unset variables.
+    pub fn from_env() -> Result<Self, EnclaveError> {
+        let default = Self::default();
+        let dns_max_concurrent_lookups = env_or(
+            "DNS_MAX_CONCURRENT_LOOKUPS",
+            default.dns_max_concurrent_lookups,
+        )?;
+        let boot_attestation_attempts = env_or(
+            "BOOT_ATTESTATION_ATTEMPTS",
+            default.boot_attestation_attempts,
+        )?;
+        if boot_attestation_attempts == 0 {
+            return Err(EnclaveError::GenericError(
+                "BOOT_ATTESTATION_ATTEMPTS must be at least 1".to_string(),
+            ));
+        }
+        let health_webhook_interval = env_or(
+            "HEALTH_WEBHOOK_INTERVAL_SECS",
+            default.health_webhook_interval.as_secs(),
+        )?;
+        let health_webhook_threshold =
+            env_or("HEALTH_WEBHOOK_THRESHOLD", default.health_webhook_threshold)?;
+        if health_webhook_interval == 0 || health_webhook_threshold == 0 {
+            return Err(EnclaveError::GenericError(
+                "HEALTH_WEBHOOK_INTERVAL_SECS and HEALTH_WEBHOOK_THRESHOLD must be at least 1"
+                    .to_string(),
+            ));
+        }
+        #[cfg(not(feature = "mock-weather"))]
+        if env_or("MOCK_WEATHER", false)? {
+            return Err(EnclaveError::GenericError(
+                "MOCK_WEATHER requires a server built with --features mock-weather".to_string(),
+            ));
+        }
+        let batch_max_fanout = env_or("BATCH_MAX_FANOUT", default.batch_max_fanout)?;
+        if batch_max_fanout == 0 {
+            return Err(EnclaveError::GenericError(
+                "BATCH_MAX_FANOUT must be at least 1".to_string(),
+            ));
+        }
+        if dns_max_concurrent_lookups == 0 {
+            return Err(EnclaveError::GenericError(
+                "DNS_MAX_CONCURRENT_LOOKUPS must be at least 1".to_string(),
+            ));
+        }
+        let sign_expiry_ms = env_or("SIGN_EXPIRY_MS", default.sign_expiry_ms)?;
+        let sign_sequence = env_or("SIGN_SEQUENCE", default.sign_sequence)?;
+        if sign_sequence && sign_expiry_ms != 0 {
+            return Err(EnclaveError::GenericError(
+                "SIGN_SEQUENCE can't be combined with SIGN_EXPIRY_MS".to_string(),
+            ));
+        }
+        let signing_key_path = std::env::var("SIGNING_KEY_PATH")
+            .ok()
+            .filter(|path| !path.is_empty());
+        // Counters restart from zero with the process, so under a key that
+        // survives restarts sequence numbers would repeat
+        if sign_sequence && signing_key_path.is_some() {
+            return Err(EnclaveError::GenericError(
+                "SIGN_SEQUENCE can't be combined with SIGNING_KEY_PATH".to_string(),
+            ));
+        }
+        let expiry_headers = env_or("EXPIRY_HEADERS", default.expiry_headers)?;
+        if expiry_headers && sign_expiry_ms == 0 {
+            return Err(EnclaveError::GenericError(
+                "EXPIRY_HEADERS requires SIGN_EXPIRY_MS".to_string(),
+            ));
+        }
+        let sign_attestation_digest =
+            env_or("SIGN_ATTESTATION_DIGEST", default.sign_attestation_digest)?;
+        let sign_upstream_latency = env_or("SIGN_UPSTREAM_LATENCY", default.sign_upstream_latency)?;
+        if sign_attestation_digest && sign_upstream_latency {
+            return Err(EnclaveError::GenericError(
+                "Set only one of SIGN_ATTESTATION_DIGEST and SIGN_UPSTREAM_LATENCY".to_string(),
+            ));
+        }
+        let signed_fields: Vec<String> = std::env::var("SIGNED_FIELDS")
+            .map(|fields| {
+                fields
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|field| !field.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default();
+        if !signed_fields.is_empty() && (sign_attestation_digest || sign_upstream_latency) {
+            return Err(EnclaveError::GenericError(
+                "SIGNED_FIELDS can't be combined with SIGN_ATTESTATION_DIGEST or SIGN_UPSTREAM_LATENCY"
+                    .to_string(),
+            ));
+        }
+        if let Some(field) = signed_fields
+            .iter()
+            .find(|field| field.split('.').any(str::is_empty))
+        {
+            return Err(EnclaveError::GenericError(format!(
+                "Invalid SIGNED_FIELDS path `{}`",
+                field
+            )));
+        }
+        let allowed_endpoints_strict =
+            env_or("ALLOWED_ENDPOINTS_STRICT", default.allowed_endpoints_strict)?;
+        Ok(Self {
+            dns_cache_ttl: Duration::from_secs(env_or(
+                "DNS_CACHE_TTL_SECS",
+                default.dns_cache_ttl.as_secs(),
+            )?),
+            dns_max_concurrent_lookups,
+            address_family: env_or("ADDRESS_FAMILY", default.address_family)?,
+            allowed_endpoints_strict,
+            max_allowed_endpoints: env_or("MAX_ALLOWED_ENDPOINTS", default.max_allowed_endpoints)?,
+            allowed_endpoints_overflow: env_or(
+                "ALLOWED_ENDPOINTS_OVERFLOW",
+                default.allowed_endpoints_overflow,
+            )?,
+            health_check_subset_size: env_or(
+                "HEALTH_CHECK_SUBSET_SIZE",
+                default.health_check_subset_size,
+            )?,
+            health_check_rotation: env_or("HEALTH_CHECK_ROTATION", default.health_check_rotation)?,
+            health_check_result_ttl: Duration::from_secs(env_or(
+                "HEALTH_CHECK_RESULT_TTL_SECS",
+                default.health_check_result_ttl.as_secs(),
+            )?),
+            health_require_nsm: env_or("HEALTH_REQUIRE_NSM", default.health_require_nsm)?,
+            health_webhook_url: std::env::var("HEALTH_WEBHOOK_URL").ok(),
+            health_webhook_interval: Duration::from_secs(health_webhook_interval),
+            health_webhook_threshold,
+            key_seed_source: env_or("KEY_SEED_SOURCE", default.key_seed_source)?,
+            signing_key_path,
+            timestamp_bucket_ms: env_or("TIMESTAMP_BUCKET_MS", default.timestamp_bucket_ms)?,
+            sign_expiry_ms,
+            sign_sequence,
+            sign_jwt: env_or("SIGN_JWT", default.sign_jwt)?,
+            signing_format: env_or("SIGNING_FORMAT", default.signing_format)?,
+            expiry_headers,
+            sign_attestation_digest,
+            sign_upstream_latency,
+            signed_fields,
+            boot_attestation: env_or("BOOT_ATTESTATION", default.boot_attestation)?,
+            attestation_cache_ttl: Duration::from_secs(env_or(
+                "ATTESTATION_CACHE_TTL_SECS",
+                default.attestation_cache_ttl.as_secs(),
+            )?),
+            attestation_cache_max_age: Duration::from_secs(env_or(
+                "ATTESTATION_CACHE_MAX_AGE_SECS",
+                default.attestation_cache_max_age.as_secs(),
+            )?),
+            attestation_nonce_window: env_or(
+                "ATTESTATION_NONCE_WINDOW",
+                default.attestation_nonce_window,
+            )?,
+            attestation_nonce_reuse: env_or(
+                "ATTESTATION_NONCE_REUSE",
+                default.attestation_nonce_reuse,
+            )?,
+            attestation_pem: env_or("ATTESTATION_PEM", default.attestation_pem)?,
+            attest_endpoints_digest: env_or(
+                "ATTEST_ENDPOINTS_DIGEST",
+                default.attest_endpoints_digest,
+            )?,
+            attest_build_commit: env_or("ATTEST_BUILD_COMMIT", default.attest_build_commit)?,
+            boot_attestation_attempts,
+            boot_attestation_backoff: Duration::from_millis(env_or(
+                "BOOT_ATTESTATION_BACKOFF_MS",
+                default.boot_attestation_backoff.as_millis() as u64,
+            )?),
+            boot_attestation_required: env_or(
+                "BOOT_ATTESTATION_REQUIRED",
+                default.boot_attestation_required,
+            )?,
+            bcs_golden_hex: std::env::var("BCS_GOLDEN_HEX").ok(),
+            clock_before_epoch: env_or("CLOCK_BEFORE_EPOCH", default.clock_before_epoch)?,
+            clock_retry_timeout: Duration::from_millis(env_or(
+                "CLOCK_RETRY_TIMEOUT_MS",
+                default.clock_retry_timeout.as_millis() as u64,
+            )?),
+            slow_request_threshold: Duration::from_millis(env_or(
+                "SLOW_REQUEST_THRESHOLD_MS",
+                default.slow_request_threshold.as_millis() as u64,
+            )?),
+            request_log_sample: env_or("REQUEST_LOG_SAMPLE", default.request_log_sample)?,
+            max_header_bytes: env_or("MAX_HEADER_BYTES", default.max_header_bytes)?,
+            max_header_count: env_or("MAX_HEADER_COUNT", default.max_header_count)?,
+            api_key_policy: env_or("API_KEY_POLICY", default.api_key_policy)?,
+            temperature_parsing: env_or("TEMPERATURE_PARSING", default.temperature_parsing)?,
+            timestamp_source: env_or("TIMESTAMP_SOURCE", default.timestamp_source)?,
+            timestamp_tolerance: Duration::from_secs(env_or(
+                "TIMESTAMP_TOLERANCE_SECS",
+                default.timestamp_tolerance.as_secs(),
+            )?),
+            upstream_params: std::env::var("UPSTREAM_PARAMS")
+                .map(|params| {
+                    params
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|param| !param.is_empty())
+                        .map(str::to_string)
+                        .collect()
+                })
+                .unwrap_or_default(),
+            process_data_get: env_or("PROCESS_DATA_GET", default.process_data_get)?,
+            batch_dedup: env_or("BATCH_DEDUP", default.batch_dedup)?,
+            batch_max_fanout,
+            honor_max_response_bytes: env_or(
+                "HONOR_MAX_RESPONSE_BYTES",
+                default.honor_max_response_bytes,
+            )?,
+            #[cfg(feature = "grpc")]
+            grpc_upstream: GrpcUpstream::from_env(&load_allowed_endpoints(
+                allowed_endpoints_strict,
+            ))?,
+            #[cfg(feature = "mock-weather")]
+            mock_weather: MockWeather::from_env()?,
+            price_upstream: PriceUpstream::from_env()?,
+            #[cfg(feature = "github")]
+            github_upstream: GithubUpstream::from_env()?,
+            #[cfg(feature = "sui-transactions")]
+            transaction_policy: TransactionPolicy::from_env()?,
+            #[cfg(feature = "mtls")]
+            mtls: Mtls::from_env()?,
+            expected_pcrs: match std::env::var("EXPECTED_PCRS") {
+                Ok(pcrs) => Some(pcrs.parse().map_err(|e| {
+                    EnclaveError::GenericError(format!("Invalid EXPECTED_PCRS: {}", e))
+                })?),
+                Err(_) => None,
+            },
+            schemas_path: std::env::var("SCHEMAS_PATH").ok(),
+            trailing_slash: env_or("TRAILING_SLASH", default.trailing_slash)?,
+            upstream_tls_detail: env_or("UPSTREAM_TLS_DETAIL", default.upstream_tls_detail)?,
+            map_upstream_errors: env_or("MAP_UPSTREAM_ERRORS", default.map_upstream_errors)?,
+            weather_api_url: env_or("WEATHER_API_URL", default.weather_api_url)?,
+            api_key_rotation_token: std::env::var("API_KEY_ROTATION_TOKEN")
+                .ok()
+                .filter(|token| !token.is_empty()),
+            admin_token: std::env::var("ADMIN_TOKEN")
+                .ok()
+                .filter(|token| !token.is_empty()),
+            reattest_interval: Some(Duration::from_secs(env_or("REATTEST_INTERVAL_SECS", 0)?))
+                .filter(|interval| !interval.is_zero()),
+            reseed_interval: Some(Duration::from_secs(env_or("RESEED_INTERVAL_SECS", 0)?))
+                .filter(|interval| !interval.is_zero()),
+            identity: EnclaveIdentity::from_env(),
+        })
+    }
+}
+
+/// Parse an environment variable, falling back to `default` when unset.
+pub(crate) fn env_or<T: FromStr>(name: &str, default: T) -> Result<T, EnclaveError>
+where
+    T::Err: Display,
+{
+    match std::env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map_err(|e| EnclaveError::GenericError(format!("Invalid {}: {}", name, e))),
+        Err(_) => Ok(default),
+    }
+}
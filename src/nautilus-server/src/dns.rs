@@ -0,0 +1,305 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+
+/// Which address families outbound connections may use, to match the
+/// egress the enclave's proxy provides.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressFamily {
+    V4,
+    V6,
+    /// Either, in the order the system resolver returns them.
+    Dual,
+}
+
+impl AddressFamily {
+    fn allows(self, addr: &SocketAddr) -> bool {
+        match self {
+            Self::V4 => addr.is_ipv4(),
+            Self::V6 => addr.is_ipv6(),
+            Self::Dual => true,
+        }
+    }
+}
+
+impl FromStr for AddressFamily {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "v4" => Ok(Self::V4),
+            "v6" => Ok(Self::V6),
+            "dual" => Ok(Self::Dual),
+            _ => Err(format!("expected `v4`, `v6` or `dual`, got `{}`", s)),
+        }
+    }
+}
+
+/// Addresses a [Lookup] resolves a host to.
+pub(crate) type Resolved = Pin<Box<dyn Future<Output = io::Result<Vec<SocketAddr>>> + Send>>;
+
+/// Resolves a host to its addresses, without caching.
+pub(crate) type Lookup = fn(String) -> Resolved;
+
+/// DNS resolver shared by the upstream and health-check clients. It bounds
+/// how many lookups run at once so probing many endpoints doesn't storm the
+/// resolver, and caches resolved addresses for a short TTL so consecutive
+/// health checks don't re-resolve every endpoint. Only addresses of the
+/// configured [AddressFamily] are returned.
+#[derive(Clone)]
+pub struct CachingResolver {
+    ttl: Duration,
+    family: AddressFamily,
+    permits: Arc<Semaphore>,
+    cache: Arc<Mutex<HashMap<String, (Instant, Vec<SocketAddr>)>>>,
+    system: Lookup,
+}
+
+impl CachingResolver {
+    pub fn new(ttl: Duration, max_concurrent_lookups: usize, family: AddressFamily) -> Self {
+        Self::with_lookup(ttl, max_concurrent_lookups, family, system_lookup)
+    }
+
+    /// A resolver that calls `system` instead of the system resolver.
+    pub(crate) fn with_lookup(
+        ttl: Duration,
+        max_concurrent_lookups: usize,
+        family: AddressFamily,
+        system: Lookup,
+    ) -> Self {
+        Self {
+            ttl,
+            family,
+            permits: Arc::new(Semaphore::new(max_concurrent_lookups)),
+            cache: Arc::new(Mutex::new(HashMap::new())),
+            system,
+        }
+    }
+
+    /// Resolve `host` with the system resolver, or return cached addresses.
+    /// The system resolver reads the `/etc/hosts` and `/etc/resolv.conf`
+    /// init writes from `nautilus.host` and `nautilus.dns`, so pinned hosts
+    /// never reach the forwarder, and a lookup that does waits up to
+    /// `nautilus.dns_timeout` per attempt, before the result is cached for
+    /// `DNS_CACHE_TTL_SECS`.
+    pub async fn lookup(&self, host: &str) -> io::Result<Vec<SocketAddr>> {
+        self.lookup_with(host, self.system).await
+    }
+
+    async fn lookup_with<F, Fut>(&self, host: &str, resolve: F) -> io::Result<Vec<SocketAddr>>
+    where
+        F: FnOnce(String) -> Fut,
+        Fut: Future<Output = io::Result<Vec<SocketAddr>>>,
+    {
+        if let Some(addrs) = self.cached(host) {
+            return Ok(addrs);
+        }
+        let _permit = self
+            .permits
+            .acquire()
+            .await
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        // Another probe may have resolved the same host while we were waiting.
+        if let Some(addrs) = self.cached(host) {
+            return Ok(addrs);
+        }
+        let resolved = resolve(host.to_string()).await?;
+        let addrs: Vec<SocketAddr> = resolved
+            .iter()
+            .filter(|addr| self.family.allows(addr))
+            .copied()
+            .collect();
+        if addrs.is_empty() && !resolved.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "{} has no {} address, only {}; see ADDRESS_FAMILY",
+                    host,
+                    if self.family == AddressFamily::V4 {
+                        "IPv4"
+                    } else {
+                        "IPv6"
+                    },
+                    resolved
+                        .iter()
+                        .map(|addr| addr.ip().to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            ));
+        }
+        if !self.ttl.is_zero() {
+            self.cache
+                .lock()
+                .unwrap()
+                .insert(host.to_string(), (Instant::now() + self.ttl, addrs.clone()));
+        }
+        Ok(addrs)
+    }
+
+    fn cached(&self, host: &str) -> Option<Vec<SocketAddr>> {
+        let mut cache = self.cache.lock().unwrap();
+        match cache.get(host) {
+            Some((expires_at, addrs)) if *expires_at > Instant::now() => Some(addrs.clone()),
+            Some(_) => {
+                cache.remove(host);
+                None
+            }
+            None => None,
+        }
+    }
+}
+
+fn system_lookup(host: String) -> Resolved {
+    Box::pin(async move {
+        tokio::net::lookup_host((host.as_str(), 0))
+            .await
+            .map(|addrs| addrs.collect())
+    })
+}
+
+impl Resolve for CachingResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let resolver = self.clone();
+        Box::pin(async move {
+            resolver
+                .lookup(name.as_str())
+                .await
+                .map(|addrs| Box::new(addrs.into_iter()) as Addrs)
+                .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    async fn counting_lookup(
+        resolver: &CachingResolver,
+        host: &str,
+        lookups: &AtomicUsize,
+    ) -> Vec<SocketAddr> {
+        resolver
+            .lookup_with(host, |_| async {
+                lookups.fetch_add(1, Ordering::SeqCst);
+                Ok(vec![SocketAddr::from(([127, 0, 0, 1], 0))])
+            })
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_consecutive_health_checks_reuse_resolution() {
+        let resolver = CachingResolver::new(Duration::from_secs(60), 4, AddressFamily::Dual);
+        let lookups = AtomicUsize::new(0);
+        // Two health checks probing the same endpoints.
+        for _ in 0..2 {
+            for host in ["api.weatherapi.com", "kms.us-east-1.amazonaws.com"] {
+                counting_lookup(&resolver, host, &lookups).await;
+            }
+        }
+        assert_eq!(lookups.load(Ordering::SeqCst), 2);
+    }
+
+    /// Lookups made by [counted_lookup], for the one test that uses it.
+    static COUNTED_LOOKUPS: AtomicUsize = AtomicUsize::new(0);
+
+    fn counted_lookup(_host: String) -> Resolved {
+        COUNTED_LOOKUPS.fetch_add(1, Ordering::SeqCst);
+        Box::pin(async { Ok(vec![SocketAddr::from(([127, 0, 0, 1], 0))]) })
+    }
+
+    #[tokio::test]
+    async fn test_health_checks_reuse_probe_client_resolution() {
+        use crate::api_keys::ApiKeys;
+        use crate::common::health_check;
+        use crate::config::Config;
+        use crate::AppState;
+        use axum::extract::State;
+        use fastcrypto::{ed25519::Ed25519KeyPair, traits::KeyPair};
+
+        let config = Config::default();
+        let resolver = CachingResolver::with_lookup(
+            config.dns_cache_ttl,
+            config.dns_max_concurrent_lookups,
+            config.address_family,
+            counted_lookup,
+        );
+        let state = Arc::new(
+            AppState::new_with(
+                Ed25519KeyPair::generate(&mut rand::thread_rng()),
+                ApiKeys::single(String::new()),
+                config,
+                resolver,
+            )
+            .unwrap(),
+        );
+        // The probes of allowed_endpoints.yaml resolve to 127.0.0.1, where
+        // nothing listens, so they fail fast once resolved
+        health_check(State(state.clone())).await.unwrap();
+        let first = COUNTED_LOOKUPS.load(Ordering::SeqCst);
+        assert!(first > 0);
+        health_check(State(state)).await.unwrap();
+        assert_eq!(COUNTED_LOOKUPS.load(Ordering::SeqCst), first);
+    }
+
+    #[tokio::test]
+    async fn test_zero_ttl_disables_cache() {
+        let resolver = CachingResolver::new(Duration::ZERO, 4, AddressFamily::Dual);
+        let lookups = AtomicUsize::new(0);
+        counting_lookup(&resolver, "api.weatherapi.com", &lookups).await;
+        counting_lookup(&resolver, "api.weatherapi.com", &lookups).await;
+        assert_eq!(lookups.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_failed_lookup_is_not_cached() {
+        let resolver = CachingResolver::new(Duration::from_secs(60), 4, AddressFamily::Dual);
+        let result = resolver
+            .lookup_with("missing.invalid", |_| async {
+                Err(io::Error::new(io::ErrorKind::NotFound, "no such host"))
+            })
+            .await;
+        assert!(result.is_err());
+        let lookups = AtomicUsize::new(0);
+        counting_lookup(&resolver, "missing.invalid", &lookups).await;
+        assert_eq!(lookups.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_address_family() {
+        let v4 = SocketAddr::from(([192, 0, 2, 1], 0));
+        let v6 = SocketAddr::from(([0x2001, 0xdb8, 0, 0, 0, 0, 0, 1], 0));
+        let lookup = |family, addrs: Vec<SocketAddr>| async move {
+            CachingResolver::new(Duration::ZERO, 4, family)
+                .lookup_with("api.weatherapi.com", |_| async { Ok(addrs) })
+                .await
+        };
+        assert_eq!(
+            lookup(AddressFamily::Dual, vec![v6, v4]).await.unwrap(),
+            [v6, v4]
+        );
+        assert_eq!(lookup(AddressFamily::V4, vec![v6, v4]).await.unwrap(), [v4]);
+        assert_eq!(lookup(AddressFamily::V6, vec![v6, v4]).await.unwrap(), [v6]);
+
+        // IPv6-only egress and an upstream with only IPv4 addresses
+        let err = lookup(AddressFamily::V6, vec![v4]).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "api.weatherapi.com has no IPv6 address, only 192.0.2.1; see ADDRESS_FAMILY"
+        );
+        assert!(lookup(AddressFamily::V4, vec![v6]).await.is_err());
+    }
+}
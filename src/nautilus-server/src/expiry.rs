@@ -0,0 +1,82 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use axum::http::header::{CACHE_CONTROL, EXPIRES};
+use axum::http::{HeaderMap, HeaderValue};
+
+/// `Cache-Control` and `Expires` headers for a response whose signed message
+/// expires at `expires_at_ms`, sent at `now_ms`. `max-age` is rounded down so
+/// a cache never keeps the response past its signed expiry.
+pub fn expiry_headers(expires_at_ms: u64, now_ms: u64) -> HeaderMap {
+    let max_age = expires_at_ms.saturating_sub(now_ms) / 1000;
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        CACHE_CONTROL,
+        HeaderValue::from_str(&format!("max-age={}", max_age)).expect("should not fail"),
+    );
+    headers.insert(
+        EXPIRES,
+        HeaderValue::from_str(&http_date(expires_at_ms)).expect("should not fail"),
+    );
+    headers
+}
+
+/// Format milliseconds since the UNIX epoch as an HTTP date, e.g.
+/// `Mon, 07 Apr 2025 15:16:00 GMT`, truncated to the second.
+pub fn http_date(ms: u64) -> String {
+    const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let secs = ms / 1000;
+    let days = secs / 86_400;
+    let secs_of_day = secs % 86_400;
+    // Civil date from days since the epoch, counting years from March so the
+    // leap day comes last (http://howardhinnant.github.io/date_algorithms.html).
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = (month_from_march + 2) % 12;
+    let year = era * 400 + year_of_era + u64::from(month < 2);
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        WEEKDAYS[(days % 7) as usize],
+        day,
+        MONTHS[month as usize],
+        year,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_http_date() {
+        assert_eq!(http_date(0), "Thu, 01 Jan 1970 00:00:00 GMT");
+        assert_eq!(http_date(951_782_400_000), "Tue, 29 Feb 2000 00:00:00 GMT");
+        assert_eq!(
+            http_date(1_744_038_960_999),
+            "Mon, 07 Apr 2025 15:16:00 GMT"
+        );
+    }
+
+    #[test]
+    fn test_expiry_headers() {
+        let headers = expiry_headers(1_744_038_960_000, 1_744_038_900_500);
+        assert_eq!(headers[CACHE_CONTROL], "max-age=59");
+        assert_eq!(headers[EXPIRES], "Mon, 07 Apr 2025 15:16:00 GMT");
+
+        // Already expired
+        let headers = expiry_headers(1_744_038_960_000, 1_744_038_961_000);
+        assert_eq!(headers[CACHE_CONTROL], "max-age=0");
+    }
+}
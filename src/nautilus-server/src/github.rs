@@ -0,0 +1,517 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use serde::{Deserialize, Serialize};
+
+/// ==== GITHUB COMMIT PROOFS ====
+
+/// Name of the GitHub API in `/health_check`'s
+/// `ms_since_last_successful_fetch`.
+pub const GITHUB_SOURCE: &str = "github";
+
+/// Inner type T for ProcessDataRequest<T> on `/process_github_commit`.
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+pub struct GithubCommitRequest {
+    /// `owner/name`, e.g. `MystenLabs/nautilus`.
+    pub repo: String,
+    /// Commit SHA, full or abbreviated to at least 7 hex digits.
+    pub sha: String,
+}
+
+/// Inner type T for IntentMessage<T> signed under
+/// [IntentScope::GithubCommit](crate::common::IntentScope::GithubCommit):
+/// the commit `sha` exists in `repo` and was authored by `author` at
+/// `authored_at_ms`, as GitHub reported it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+pub struct GithubCommit {
+    /// `owner/name` as requested.
+    pub repo: String,
+    /// Full commit SHA, 40 lowercase hex digits.
+    pub sha: String,
+    /// Author name recorded in the commit.
+    pub author: String,
+    /// Author date recorded in the commit, in milliseconds since the UNIX
+    /// epoch. Set by whoever made the commit, so it proves nothing about
+    /// when the commit was pushed.
+    pub authored_at_ms: u64,
+}
+
+#[cfg(feature = "github")]
+pub use upstream::{
+    check_repo, check_sha, parse_date, process_github_commit, GithubUpstream, GITHUB_API_URL,
+    GITHUB_API_VERSION,
+};
+
+/// The GitHub API client and the `/process_github_commit` handler, only
+/// built with the `github` feature.
+#[cfg(feature = "github")]
+mod upstream {
+    use super::{GithubCommit, GithubCommitRequest, GITHUB_SOURCE};
+    use crate::app::{field_at, sign_payload, upstream_error};
+    use crate::common::{
+        current_timestamp_ms, IntentMessage, IntentScope, ProcessDataRequest, ProcessedDataResponse,
+    };
+    use crate::config::env_or;
+    use crate::AppState;
+    use crate::EnclaveError;
+    use axum::extract::State;
+    use axum::Json;
+    use serde_json::Value;
+    use std::sync::Arc;
+
+    /// REST API queried unless `GITHUB_API_URL` is set.
+    pub const GITHUB_API_URL: &str = "https://api.github.com";
+
+    /// REST API version requested in the `X-GitHub-Api-Version` header.
+    pub const GITHUB_API_VERSION: &str = "2022-11-28";
+
+    /// GitHub API queried by `/process_github_commit`.
+    #[derive(Debug, Clone)]
+    pub struct GithubUpstream {
+        /// Base URL of the REST API, e.g. a mock upstream in tests.
+        pub api_url: reqwest::Url,
+        /// Token sent as `Authorization: Bearer <token>`. A fine-grained token
+        /// with read access to the repositories' contents is enough.
+        pub token: String,
+    }
+
+    impl GithubUpstream {
+        /// The upstream configured by `GITHUB_TOKEN`, normally supplied in the
+        /// secrets JSON like `API_KEY`, and `GITHUB_API_URL` (default
+        /// [GITHUB_API_URL]), or None when `GITHUB_TOKEN` is unset.
+        pub fn from_env() -> Result<Option<Self>, EnclaveError> {
+            let token = match std::env::var("GITHUB_TOKEN") {
+                Ok(token) if !token.is_empty() => token,
+                _ => return Ok(None),
+            };
+            let default = reqwest::Url::parse(GITHUB_API_URL).expect("should not fail");
+            Ok(Some(Self {
+                api_url: env_or("GITHUB_API_URL", default)?,
+                token,
+            }))
+        }
+
+        /// The URL of `sha` in `repo`. Both are checked first, so they can't
+        /// add path segments or a query.
+        fn commit_url(&self, repo: &str, sha: &str) -> Result<reqwest::Url, EnclaveError> {
+            let url = format!(
+                "{}/repos/{}/commits/{}",
+                self.api_url.as_str().trim_end_matches('/'),
+                repo,
+                sha
+            );
+            reqwest::Url::parse(&url)
+                .map_err(|e| EnclaveError::GenericError(format!("Invalid GITHUB_API_URL: {}", e)))
+        }
+    }
+
+    /// Check `repo` is `owner/name` as GitHub allows them.
+    pub fn check_repo(repo: &str) -> Result<(), EnclaveError> {
+        let invalid = || {
+            EnclaveError::GenericError(format!("Invalid repo `{}`: expected `owner/name`", repo))
+        };
+        let (owner, name) = repo.split_once('/').ok_or_else(invalid)?;
+        let owner_ok = !owner.is_empty()
+            && owner.len() <= 39
+            && owner
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-');
+        let name_ok = !name.is_empty()
+            && name.len() <= 100
+            && name != "."
+            && name != ".."
+            && name
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b"-_.".contains(&b));
+        if !owner_ok || !name_ok {
+            return Err(invalid());
+        }
+        Ok(())
+    }
+
+    /// `sha` lowercased, once checked to be 7 to 40 hex digits.
+    pub fn check_sha(sha: &str) -> Result<String, EnclaveError> {
+        if sha.len() < 7 || sha.len() > 40 || !sha.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(EnclaveError::GenericError(format!(
+                "Invalid commit SHA `{}`: expected 7 to 40 hex digits",
+                sha
+            )));
+        }
+        Ok(sha.to_ascii_lowercase())
+    }
+
+    /// `text`, a date as GitHub formats them (`2011-04-14T16:00:49Z`), in
+    /// milliseconds since the UNIX epoch.
+    pub fn parse_date(text: &str) -> Result<u64, String> {
+        let invalid = || format!("expected `YYYY-MM-DDTHH:MM:SSZ`, got `{}`", text);
+        let bytes = text.as_bytes();
+        if bytes.len() != 20
+            || !text.is_ascii()
+            || [4, 7].iter().any(|i| bytes[*i] != b'-')
+            || bytes[10] != b'T'
+            || [13, 16].iter().any(|i| bytes[*i] != b':')
+            || bytes[19] != b'Z'
+        {
+            return Err(invalid());
+        }
+        let number = |range: std::ops::Range<usize>| -> Result<u64, String> {
+            let digits = &text[range];
+            if !digits.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(invalid());
+            }
+            digits.parse().map_err(|_| invalid())
+        };
+        let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+        let (hour, minute, second) = (number(11..13)?, number(14..16)?, number(17..19)?);
+        if year < 1970
+            || !(1..=12).contains(&month)
+            || !(1..=days_in_month(year, month)).contains(&day)
+            || hour > 23
+            || minute > 59
+            || second > 59
+        {
+            return Err(invalid());
+        }
+        let days = days_since_epoch(year, month, day);
+        Ok((((days * 24 + hour) * 60 + minute) * 60 + second) * 1000)
+    }
+
+    fn is_leap_year(year: u64) -> bool {
+        year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+    }
+
+    fn days_in_month(year: u64, month: u64) -> u64 {
+        match month {
+            2 if is_leap_year(year) => 29,
+            2 => 28,
+            4 | 6 | 9 | 11 => 30,
+            _ => 31,
+        }
+    }
+
+    /// Days from 1970-01-01 to the given date, which is no earlier.
+    fn days_since_epoch(year: u64, month: u64, day: u64) -> u64 {
+        let years: u64 = (1970..year)
+            .map(|year| if is_leap_year(year) { 366 } else { 365 })
+            .sum();
+        let months: u64 = (1..month).map(|month| days_in_month(year, month)).sum();
+        years + months + day - 1
+    }
+
+    /// The commit in a `GET /repos/{owner}/{repo}/commits/{ref}` response,
+    /// checked to be the one `sha` abbreviates.
+    fn parse_commit(json: &Value, repo: &str, sha: &str) -> Result<GithubCommit, EnclaveError> {
+        let field = |path: &str| {
+            field_at(json, path).and_then(Value::as_str).ok_or_else(|| {
+                EnclaveError::GenericError(format!("GitHub response has no string `{}`", path))
+            })
+        };
+        let full_sha = field("sha")?;
+        if full_sha.len() != 40
+            || !full_sha.bytes().all(|b| b.is_ascii_hexdigit())
+            || !full_sha.to_ascii_lowercase().starts_with(sha)
+        {
+            return Err(EnclaveError::GenericError(format!(
+                "GitHub returned commit `{}` for `{}`",
+                full_sha, sha
+            )));
+        }
+        let authored_at_ms = parse_date(field("commit.author.date")?)
+            .map_err(|e| EnclaveError::GenericError(format!("Invalid commit date: {}", e)))?;
+        Ok(GithubCommit {
+            repo: repo.to_string(),
+            sha: full_sha.to_ascii_lowercase(),
+            author: field("commit.author.name")?.to_string(),
+            authored_at_ms,
+        })
+    }
+
+    /// Endpoint that fetches a commit from the GitHub API, authenticated with
+    /// `GITHUB_TOKEN`, and signs its SHA, author and date under
+    /// [IntentScope::GithubCommit] at the current time. A commit or repository
+    /// the token can't see is a 404. Only served with the `github` feature and
+    /// `GITHUB_TOKEN` set.
+    pub async fn process_github_commit(
+        State(state): State<Arc<AppState>>,
+        Json(request): Json<ProcessDataRequest<GithubCommitRequest>>,
+    ) -> Result<Json<ProcessedDataResponse<IntentMessage<GithubCommit>>>, EnclaveError> {
+        let upstream = state.config.github_upstream.as_ref().ok_or_else(|| {
+            EnclaveError::GenericError("No GitHub token configured, see GITHUB_TOKEN".to_string())
+        })?;
+        let scope = IntentScope::GithubCommit;
+        if let Some(intent) = request.intent.filter(|intent| *intent != scope as u8) {
+            return Err(EnclaveError::GenericError(format!(
+                "GitHub commits are signed under intent {}, not {}",
+                scope as u8, intent
+            )));
+        }
+        let GithubCommitRequest { repo, sha } = request.payload;
+        check_repo(&repo)?;
+        let sha = check_sha(&sha)?;
+
+        let url = upstream.commit_url(&repo, &sha)?;
+        let response = state
+            .upstream_client
+            .get(url.clone())
+            .bearer_auth(&upstream.token)
+            .header(reqwest::header::ACCEPT, "application/vnd.github+json")
+            .header(reqwest::header::USER_AGENT, "nautilus-server")
+            .header("X-GitHub-Api-Version", GITHUB_API_VERSION)
+            .send()
+            .await
+            .map_err(|e| upstream_error(&state, &url, "Failed to get commit", e))?;
+        match response.status() {
+            // GitHub answers 404 for repositories the token can't read too, and
+            // 422 for a SHA that matches no commit
+            reqwest::StatusCode::NOT_FOUND | reqwest::StatusCode::UNPROCESSABLE_ENTITY => {
+                return Err(EnclaveError::NotFound(format!(
+                    "No commit {} found in {}",
+                    sha, repo
+                )));
+            }
+            status if !status.is_success() => {
+                return Err(EnclaveError::GenericError(format!(
+                    "GitHub API returned {}",
+                    status
+                )));
+            }
+            _ => {}
+        }
+        let json = response
+            .json::<Value>()
+            .await
+            .map_err(|e| upstream_error(&state, &url, "Failed to read commit", e))?;
+        let payload = parse_commit(&json, &repo, &sha)?;
+        state.last_fetches.record(GITHUB_SOURCE);
+
+        let current_timestamp = current_timestamp_ms(&state.config).await?;
+        Ok(Json(sign_payload(
+            &state,
+            payload,
+            current_timestamp,
+            scope,
+        )))
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+        use fastcrypto::encoding::{Encoding, Hex};
+
+        #[test]
+        fn test_parse_date() {
+            for (text, expected) in [
+                ("1970-01-01T00:00:00Z", 0),
+                ("2011-04-14T16:00:49Z", 1302796849000),
+                ("2000-02-29T23:59:59Z", 951868799000),
+                ("2025-04-07T15:15:00Z", 1744038900000),
+            ] {
+                assert_eq!(parse_date(text), Ok(expected), "{}", text);
+            }
+            for text in [
+                "",
+                "2011-04-14",
+                "2011-04-14T16:00:49",
+                "2011-04-14T16:00:49+02:00",
+                "2011-04-14 16:00:49Z",
+                "1969-12-31T23:59:59Z",
+                "2011-13-01T00:00:00Z",
+                "2011-02-29T00:00:00Z",
+                "2011-04-14T24:00:00Z",
+                "2011-04-1aT16:00:49Z",
+                "+011-04-14T16:00:49Z",
+            ] {
+                assert!(parse_date(text).is_err(), "{}", text);
+            }
+        }
+
+        #[test]
+        fn test_check_request() {
+            for repo in ["MystenLabs/nautilus", "a/b", "octo-cat/.github", "o/a_b.c"] {
+                assert!(check_repo(repo).is_ok(), "{}", repo);
+            }
+            for repo in [
+                "",
+                "nautilus",
+                "/nautilus",
+                "MystenLabs/",
+                "MystenLabs/nautilus/commits",
+                "MystenLabs/..",
+                "Mysten_Labs/nautilus",
+                "MystenLabs/nautilus?per_page=1",
+            ] {
+                assert!(check_repo(repo).is_err(), "{}", repo);
+            }
+            assert_eq!(check_sha("6DCB09B").unwrap(), "6dcb09b");
+            for sha in ["6dcb09", "6dcb09g", &"a".repeat(41), "../../x"] {
+                assert!(check_sha(sha).is_err(), "{}", sha);
+            }
+        }
+
+        #[tokio::test]
+        async fn test_process_github_commit() {
+            use crate::api_keys::ApiKeys;
+            use crate::config::Config;
+            use axum::extract::Path;
+            use axum::http::{HeaderMap, StatusCode};
+            use axum::routing::get;
+            use fastcrypto::ed25519::{Ed25519KeyPair, Ed25519Signature};
+            use fastcrypto::traits::{KeyPair, ToFromBytes, VerifyingKey};
+
+            const SHA: &str = "6dcb09b5b57875f334f61aebed695e2e4193db5e";
+            // Answers like the GitHub API, for the token `t0ken` only
+            let app = axum::Router::new().route(
+                "/repos/:owner/:repo/commits/:sha",
+                get(
+                    |Path((owner, repo, sha)): Path<(String, String, String)>,
+                     headers: HeaderMap| async move {
+                        let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+                        if header("authorization") != Some("Bearer t0ken") {
+                            let body = serde_json::json!({"message": "Bad credentials"});
+                            return (StatusCode::UNAUTHORIZED, Json(body));
+                        }
+                        assert_eq!(header("accept"), Some("application/vnd.github+json"));
+                        assert_eq!(header("x-github-api-version"), Some(GITHUB_API_VERSION));
+                        assert!(header("user-agent").is_some());
+                        if (owner.as_str(), repo.as_str()) != ("octocat", "Hello-World") {
+                            let body = serde_json::json!({"message": "Not Found"});
+                            return (StatusCode::NOT_FOUND, Json(body));
+                        }
+                        if !SHA.starts_with(&sha) {
+                            let body = serde_json::json!({"message": "No commit found for SHA"});
+                            return (StatusCode::UNPROCESSABLE_ENTITY, Json(body));
+                        }
+                        let body = serde_json::json!({
+                            "sha": SHA,
+                            "commit": {
+                                "author": {
+                                    "name": "Monalisa Octocat",
+                                    "email": "support@github.com",
+                                    "date": "2011-04-14T16:00:49Z",
+                                },
+                                "message": "Fix all the bugs",
+                            },
+                            "author": null,
+                        });
+                        (StatusCode::OK, Json(body))
+                    },
+                ),
+            );
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            tokio::spawn(async move { axum::serve(listener, app).await });
+
+            let kp = Ed25519KeyPair::generate(&mut rand::thread_rng());
+            let public_key = kp.public().clone();
+            let state_with_token = |token: &str, kp| {
+                let config = Config {
+                    github_upstream: Some(GithubUpstream {
+                        api_url: reqwest::Url::parse(&format!("http://{}", addr)).unwrap(),
+                        token: token.to_string(),
+                    }),
+                    ..Config::default()
+                };
+                Arc::new(AppState::new(kp, ApiKeys::single(String::new()), config).unwrap())
+            };
+            let state = state_with_token("t0ken", kp);
+            let request = |repo: &str, sha: &str, intent| {
+                Json(ProcessDataRequest {
+                    payload: GithubCommitRequest {
+                        repo: repo.to_string(),
+                        sha: sha.to_string(),
+                    },
+                    intent,
+                })
+            };
+
+            // An abbreviated SHA is signed in full
+            let signed = process_github_commit(
+                State(state.clone()),
+                request("octocat/Hello-World", "6DCB09B5", None),
+            )
+            .await
+            .unwrap()
+            .0;
+            assert_eq!(
+                signed.response.data,
+                GithubCommit {
+                    repo: "octocat/Hello-World".to_string(),
+                    sha: SHA.to_string(),
+                    author: "Monalisa Octocat".to_string(),
+                    authored_at_ms: 1302796849000,
+                }
+            );
+            assert_eq!(signed.response.intent, IntentScope::GithubCommit);
+            let signature =
+                Ed25519Signature::from_bytes(&Hex::decode(&signed.signature).unwrap()).unwrap();
+            let signed_bytes = bcs::to_bytes(&signed.response).unwrap();
+            assert!(public_key.verify(&signed_bytes, &signature).is_ok());
+
+            for (repo, sha, error) in [
+                ("octocat/Spoon-Knife", SHA, "No commit 6dcb09b5"),
+                ("octocat/Hello-World", "1234567", "No commit 1234567"),
+            ] {
+                let err = process_github_commit(State(state.clone()), request(repo, sha, None))
+                    .await
+                    .err()
+                    .unwrap();
+                assert!(matches!(err, EnclaveError::NotFound(_)), "{:?}", err);
+                assert!(err.to_string().starts_with(error), "{}", err);
+            }
+            for (repo, sha, intent, error) in [
+                ("octocat", SHA, None, "Invalid repo"),
+                ("octocat/Hello-World", "HEAD", None, "Invalid commit SHA"),
+                (
+                    "octocat/Hello-World",
+                    SHA,
+                    Some(0),
+                    "GitHub commits are signed under intent 6",
+                ),
+            ] {
+                let err = process_github_commit(State(state.clone()), request(repo, sha, intent))
+                    .await
+                    .err()
+                    .unwrap()
+                    .to_string();
+                assert!(err.starts_with(error), "{}", err);
+            }
+
+            // Without the right token the upstream refuses, and nothing is signed
+            let kp = Ed25519KeyPair::generate(&mut rand::thread_rng());
+            let err = process_github_commit(
+                State(state_with_token("wrong", kp)),
+                request("octocat/Hello-World", SHA, None),
+            )
+            .await
+            .err()
+            .unwrap()
+            .to_string();
+            assert_eq!(err, "GitHub API returned 401 Unauthorized");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::{IntentMessage, IntentScope};
+    use fastcrypto::encoding::{Encoding, Hex};
+
+    #[test]
+    fn test_serde_github_commit() {
+        let payload = GithubCommit {
+            repo: "octocat/Hello-World".to_string(),
+            sha: "6dcb09b5b57875f334f61aebed695e2e4193db5e".to_string(),
+            author: "Monalisa Octocat".to_string(),
+            authored_at_ms: 1302796849000,
+        };
+        let intent_msg = IntentMessage::new(payload, 1744038900000, IntentScope::GithubCommit);
+        let signing_payload = bcs::to_bytes(&intent_msg).expect("should not fail");
+        assert_eq!(
+            Hex::encode(signing_payload),
+            crate::vectors::committed("github_commit").signing_payload
+        );
+    }
+}
@@ -0,0 +1,698 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::health::Endpoint;
+use crate::EnclaveError;
+use bytes::{Buf, BufMut};
+use http::uri::{Authority, PathAndQuery};
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+use tonic::client::Grpc;
+use tonic::codec::{Codec, DecodeBuf, Decoder, EncodeBuf, Encoder};
+use tonic::transport::{self, Certificate, ClientTlsConfig};
+use tonic::{Request, Status};
+
+/// How long a gRPC call, including the connection, may take.
+const CALL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Largest response message read from the upstream. A reading is a few dozen
+/// bytes, so anything near this is refused before it is buffered.
+const MAX_RESPONSE_BYTES: usize = 64 * 1024;
+
+/// Names of the gRPC status codes, indexed by code.
+const STATUS_NAMES: [&str; 17] = [
+    "OK",
+    "CANCELLED",
+    "UNKNOWN",
+    "INVALID_ARGUMENT",
+    "DEADLINE_EXCEEDED",
+    "NOT_FOUND",
+    "ALREADY_EXISTS",
+    "PERMISSION_DENIED",
+    "RESOURCE_EXHAUSTED",
+    "FAILED_PRECONDITION",
+    "ABORTED",
+    "OUT_OF_RANGE",
+    "UNIMPLEMENTED",
+    "INTERNAL",
+    "UNAVAILABLE",
+    "DATA_LOSS",
+    "UNAUTHENTICATED",
+];
+
+/// A unary gRPC method serving weather readings, used by `/process_data` in
+/// place of the REST API. The call is made over TLS, checked against the
+/// bundled web PKI roots, since the parent instance forwarding the traffic is
+/// untrusted. Messages are read and written by field number, so no generated
+/// code is needed:
+/// - the request carries the location as a string in field
+///   `request_location`
+/// - the response's `location` field is a string, `temperature` a `double`,
+///   `float` or integer, and `last_updated_epoch` an integer of seconds
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GrpcUpstream {
+    /// `host:port` of the server.
+    pub authority: String,
+    /// Method path, `/<package>.<Service>/<Method>`.
+    pub path: String,
+    pub fields: FieldMapping,
+    /// PEM certificate of a CA trusted in addition to the bundled roots,
+    /// e.g. for a test server. Never set from the environment.
+    pub extra_ca_pem: Option<String>,
+}
+
+/// Field numbers mapping the gRPC messages to the signed payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FieldMapping {
+    pub request_location: u32,
+    pub location: u32,
+    pub temperature: u32,
+    pub last_updated_epoch: u32,
+}
+
+impl Default for FieldMapping {
+    fn default() -> Self {
+        Self {
+            request_location: 1,
+            location: 1,
+            temperature: 2,
+            last_updated_epoch: 3,
+        }
+    }
+}
+
+impl FromStr for FieldMapping {
+    type Err = String;
+
+    /// Parse `name=number` pairs separated by commas, e.g.
+    /// `temperature=4,last_updated_epoch=7`. Unlisted fields keep their
+    /// defaults.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut mapping = Self::default();
+        for pair in s.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+            let (name, number) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("expected `name=number`, got `{}`", pair))?;
+            let number = number
+                .trim()
+                .parse::<u32>()
+                .ok()
+                .filter(|number| (1..1 << 29).contains(number))
+                .ok_or_else(|| format!("invalid field number in `{}`", pair))?;
+            match name.trim() {
+                "request_location" => mapping.request_location = number,
+                "location" => mapping.location = number,
+                "temperature" => mapping.temperature = number,
+                "last_updated_epoch" => mapping.last_updated_epoch = number,
+                name => return Err(format!("unknown field `{}`", name)),
+            }
+        }
+        Ok(mapping)
+    }
+}
+
+impl GrpcUpstream {
+    /// The upstream configured by `GRPC_UPSTREAM` (`host:port`, optionally
+    /// prefixed with `https://`), `GRPC_METHOD` and `GRPC_FIELDS` (see
+    /// [FieldMapping]), or None when `GRPC_UPSTREAM` is unset. The host must
+    /// be one of `endpoints`, the hosts of `allowed_endpoints.yaml`.
+    pub fn from_env(endpoints: &[Endpoint]) -> Result<Option<Self>, EnclaveError> {
+        let authority = match std::env::var("GRPC_UPSTREAM") {
+            Ok(authority) => parse_authority(&authority)?,
+            Err(_) => return Ok(None),
+        };
+        let path = std::env::var("GRPC_METHOD").map_err(|_| {
+            EnclaveError::GenericError("GRPC_METHOD must be set with GRPC_UPSTREAM".to_string())
+        })?;
+        if !path.starts_with('/')
+            || path[1..].split('/').count() != 2
+            || path.parse::<PathAndQuery>().is_err()
+        {
+            return Err(EnclaveError::GenericError(format!(
+                "Invalid GRPC_METHOD: expected `/<package>.<Service>/<Method>`, got `{}`",
+                path
+            )));
+        }
+        let fields = match std::env::var("GRPC_FIELDS") {
+            Ok(fields) => fields
+                .parse()
+                .map_err(|e| EnclaveError::GenericError(format!("Invalid GRPC_FIELDS: {}", e)))?,
+            Err(_) => FieldMapping::default(),
+        };
+        let upstream = Self {
+            authority,
+            path,
+            fields,
+            extra_ca_pem: None,
+        };
+        upstream.check_allowed(endpoints)?;
+        Ok(Some(upstream))
+    }
+
+    /// Refuse an upstream whose host isn't in `endpoints`, as egress is only
+    /// meant to reach the hosts listed in `allowed_endpoints.yaml`.
+    pub fn check_allowed(&self, endpoints: &[Endpoint]) -> Result<(), EnclaveError> {
+        let host = self.host();
+        if endpoints
+            .iter()
+            .any(|endpoint| endpoint.host.eq_ignore_ascii_case(host))
+        {
+            Ok(())
+        } else {
+            Err(EnclaveError::GenericError(format!(
+                "GRPC_UPSTREAM host {} is not listed in allowed_endpoints.yaml",
+                host
+            )))
+        }
+    }
+
+    /// The host of [Self::authority], without its port.
+    fn host(&self) -> &str {
+        match self.authority.rsplit_once(':') {
+            Some((host, port)) if port.parse::<u16>().is_ok() => host,
+            _ => &self.authority,
+        }
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+    }
+
+    /// Fetch the reading for `location`.
+    pub async fn fetch(&self, location: &str) -> Result<GrpcReading, GrpcError> {
+        let mut request = Vec::new();
+        encode_string(&mut request, self.fields.request_location, location);
+        let response = tokio::time::timeout(CALL_TIMEOUT, self.call(request))
+            .await
+            .map_err(|_| GrpcError::Transport("timed out".to_string()))??;
+        GrpcReading::decode(&response, &self.fields)
+    }
+
+    /// Make the unary call, returning the response message.
+    async fn call(&self, message: Vec<u8>) -> Result<Vec<u8>, GrpcError> {
+        let failed = |e: &dyn Error| GrpcError::Transport(describe(e));
+        let mut tls = ClientTlsConfig::new().with_webpki_roots();
+        if let Some(ca) = &self.extra_ca_pem {
+            tls = tls.ca_certificate(Certificate::from_pem(ca));
+        }
+        // The scheme must be https: with http tonic ignores the TLS config
+        let channel = transport::Endpoint::from_shared(format!("https://{}", self.authority))
+            .map_err(|e| failed(&e))?
+            .tls_config(tls)
+            .map_err(|e| failed(&e))?
+            .connect()
+            .await
+            .map_err(|e| failed(&e))?;
+        let path = self.path.parse::<PathAndQuery>().map_err(|e| failed(&e))?;
+        let mut client = Grpc::new(channel).max_decoding_message_size(MAX_RESPONSE_BYTES);
+        client.ready().await.map_err(|e| failed(&e))?;
+        let response = client
+            .unary(Request::new(message), path, RawCodec)
+            .await
+            .map_err(|status| match status.source() {
+                // Raised by tonic for a failed exchange, not sent by the server
+                Some(source) => failed(source),
+                None => GrpcError::Status {
+                    code: status.code() as u32,
+                    message: status.message().to_string(),
+                },
+            })?;
+        Ok(response.into_inner())
+    }
+}
+
+/// The `host:port` of `GRPC_UPSTREAM`, refusing any scheme but `https`.
+fn parse_authority(upstream: &str) -> Result<String, EnclaveError> {
+    let authority = match upstream.split_once("://") {
+        Some(("https", authority)) => authority,
+        Some((scheme, _)) => {
+            return Err(EnclaveError::GenericError(format!(
+                "Invalid GRPC_UPSTREAM: only TLS upstreams are supported, got scheme `{}`",
+                scheme
+            )))
+        }
+        None => upstream,
+    };
+    authority
+        .parse::<Authority>()
+        .ok()
+        .filter(|parsed| parsed.as_str() == authority && !parsed.host().is_empty())
+        .map(|parsed| parsed.to_string())
+        .ok_or_else(|| {
+            EnclaveError::GenericError(format!(
+                "Invalid GRPC_UPSTREAM: expected `host:port`, got `{}`",
+                upstream
+            ))
+        })
+}
+
+/// `e` and its sources, as tonic's own messages leave out the cause.
+fn describe(e: &dyn Error) -> String {
+    let mut message = e.to_string();
+    let mut source = e.source();
+    while let Some(cause) = source {
+        message.push_str(": ");
+        message.push_str(&cause.to_string());
+        source = cause.source();
+    }
+    message
+}
+
+/// Passes messages through as bytes, since they are encoded and decoded by
+/// field number rather than by generated code.
+#[derive(Debug, Clone, Copy, Default)]
+struct RawCodec;
+
+impl Codec for RawCodec {
+    type Encode = Vec<u8>;
+    type Decode = Vec<u8>;
+    type Encoder = RawCodec;
+    type Decoder = RawCodec;
+
+    fn encoder(&mut self) -> Self::Encoder {
+        RawCodec
+    }
+
+    fn decoder(&mut self) -> Self::Decoder {
+        RawCodec
+    }
+}
+
+impl Encoder for RawCodec {
+    type Item = Vec<u8>;
+    type Error = Status;
+
+    fn encode(&mut self, item: Vec<u8>, dst: &mut EncodeBuf<'_>) -> Result<(), Status> {
+        dst.put_slice(&item);
+        Ok(())
+    }
+}
+
+impl Decoder for RawCodec {
+    type Item = Vec<u8>;
+    type Error = Status;
+
+    fn decode(&mut self, src: &mut DecodeBuf<'_>) -> Result<Option<Vec<u8>>, Status> {
+        let mut message = vec![0; src.remaining()];
+        src.copy_to_slice(&mut message);
+        Ok(Some(message))
+    }
+}
+
+/// Why a gRPC call failed. The server answering with an error status is kept
+/// apart from failing to reach it or to read its answer.
+#[derive(Debug, PartialEq, Eq)]
+pub enum GrpcError {
+    /// The server could not be reached, the TLS handshake failed, or the
+    /// HTTP/2 exchange failed.
+    Transport(String),
+    /// The server answered with a non-OK gRPC status.
+    Status { code: u32, message: String },
+    /// The response message doesn't match the field mapping.
+    Decode(String),
+}
+
+impl fmt::Display for GrpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GrpcError::Transport(e) => write!(f, "Failed to reach gRPC upstream: {}", e),
+            GrpcError::Status { code, message } => write!(
+                f,
+                "gRPC upstream returned {} ({}): {}",
+                STATUS_NAMES.get(*code as usize).unwrap_or(&"UNKNOWN"),
+                code,
+                message
+            ),
+            GrpcError::Decode(e) => write!(f, "Failed to parse gRPC response: {}", e),
+        }
+    }
+}
+
+impl From<GrpcError> for EnclaveError {
+    fn from(e: GrpcError) -> Self {
+        EnclaveError::GenericError(e.to_string())
+    }
+}
+
+fn encode_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn encode_string(buf: &mut Vec<u8>, field: u32, value: &str) {
+    encode_varint(buf, (field as u64) << 3 | 2);
+    encode_varint(buf, value.len() as u64);
+    buf.extend_from_slice(value.as_bytes());
+}
+
+/// A protobuf field value, by wire type.
+#[derive(Debug, Clone, PartialEq)]
+enum Field<'a> {
+    Varint(u64),
+    Fixed64([u8; 8]),
+    Bytes(&'a [u8]),
+    Fixed32([u8; 4]),
+}
+
+fn decode_varint(data: &mut &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = data.split_first()?;
+        *data = rest;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// The last value of each field in a message, as protobuf merges repeated
+/// scalar fields.
+fn decode_fields(mut data: &[u8]) -> Result<Vec<(u32, Field)>, GrpcError> {
+    let malformed = || GrpcError::Decode("malformed protobuf message".to_string());
+    let mut fields: Vec<(u32, Field)> = Vec::new();
+    while !data.is_empty() {
+        let key = decode_varint(&mut data).ok_or_else(malformed)?;
+        let number = (key >> 3) as u32;
+        let field = match key & 7 {
+            0 => Field::Varint(decode_varint(&mut data).ok_or_else(malformed)?),
+            1 if data.len() >= 8 => {
+                let (value, rest) = data.split_at(8);
+                data = rest;
+                Field::Fixed64(value.try_into().expect("8 bytes"))
+            }
+            2 => {
+                let len = decode_varint(&mut data).ok_or_else(malformed)? as usize;
+                if data.len() < len {
+                    return Err(malformed());
+                }
+                let (value, rest) = data.split_at(len);
+                data = rest;
+                Field::Bytes(value)
+            }
+            5 if data.len() >= 4 => {
+                let (value, rest) = data.split_at(4);
+                data = rest;
+                Field::Fixed32(value.try_into().expect("4 bytes"))
+            }
+            _ => return Err(malformed()),
+        };
+        fields.retain(|(n, _)| *n != number);
+        fields.push((number, field));
+    }
+    Ok(fields)
+}
+
+/// A weather reading decoded from a gRPC response.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GrpcReading {
+    pub location: String,
+    pub temperature: f64,
+    pub last_updated_epoch: u64,
+}
+
+impl GrpcReading {
+    fn decode(message: &[u8], mapping: &FieldMapping) -> Result<Self, GrpcError> {
+        let fields = decode_fields(message)?;
+        let field = |number: u32, name: &str| {
+            fields
+                .iter()
+                .find(|(n, _)| *n == number)
+                .map(|(_, field)| field)
+                .ok_or_else(|| GrpcError::Decode(format!("missing {} (field {})", name, number)))
+        };
+        let wrong_type = |name: &str| GrpcError::Decode(format!("unexpected type for {}", name));
+        let location = match field(mapping.location, "location")? {
+            Field::Bytes(bytes) => String::from_utf8(bytes.to_vec())
+                .map_err(|_| GrpcError::Decode("location is not UTF-8".to_string()))?,
+            _ => return Err(wrong_type("location")),
+        };
+        let temperature = match field(mapping.temperature, "temperature")? {
+            Field::Fixed64(bytes) => f64::from_le_bytes(*bytes),
+            Field::Fixed32(bytes) => f32::from_le_bytes(*bytes) as f64,
+            Field::Varint(value) => *value as i64 as f64,
+            Field::Bytes(_) => return Err(wrong_type("temperature")),
+        };
+        if !temperature.is_finite() {
+            return Err(GrpcError::Decode(format!(
+                "invalid temperature {}",
+                temperature
+            )));
+        }
+        let last_updated_epoch = match field(mapping.last_updated_epoch, "last_updated_epoch")? {
+            Field::Varint(value) => *value,
+            _ => return Err(wrong_type("last_updated_epoch")),
+        };
+        Ok(Self {
+            location,
+            temperature,
+            last_updated_epoch,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bytes::Bytes;
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+    use rustls::pki_types::PrivateKeyDer;
+    use rustls::ServerConfig;
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+    use tokio_rustls::TlsAcceptor;
+
+    fn encode_double(buf: &mut Vec<u8>, field: u32, value: f64) {
+        encode_varint(buf, (field as u64) << 3 | 1);
+        buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn encode_uint(buf: &mut Vec<u8>, field: u32, value: u64) {
+        encode_varint(buf, (field as u64) << 3);
+        encode_varint(buf, value);
+    }
+
+    /// Length-prefixed gRPC message: an uncompressed flag, then the
+    /// big-endian length.
+    fn frame(message: &[u8]) -> Bytes {
+        let mut framed = Vec::with_capacity(message.len() + 5);
+        framed.push(0);
+        framed.extend_from_slice(&(message.len() as u32).to_be_bytes());
+        framed.extend_from_slice(message);
+        Bytes::from(framed)
+    }
+
+    /// What the mock server answers.
+    #[derive(Clone, Copy)]
+    enum Answer {
+        /// The location in field 1 of the request, echoed in field 1.
+        Echo,
+        /// A non-OK status.
+        Status(u32, &'static str),
+        /// A message of this many bytes.
+        Oversized(usize),
+    }
+
+    /// Serve one gRPC call over TLS for `localhost`, returning the server's
+    /// `host:port` and the PEM of the CA that issued its certificate.
+    async fn mock_server(answer: Answer) -> (String, String) {
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+        let key = KeyPair::generate().unwrap();
+        let cert = CertificateParams::new(vec!["localhost".to_string()])
+            .unwrap()
+            .signed_by(&key, &ca, &ca_key)
+            .unwrap();
+        let mut config =
+            ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_no_client_auth()
+                .with_single_cert(
+                    vec![cert.der().clone()],
+                    PrivateKeyDer::Pkcs8(key.serialize_der().into()),
+                )
+                .unwrap();
+        config.alpn_protocols = vec![b"h2".to_vec()];
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (tcp, _) = listener.accept().await.unwrap();
+            // A client that doesn't trust the CA aborts the handshake
+            let Ok(tls) = acceptor.accept(tcp).await else {
+                return;
+            };
+            let mut connection = h2::server::handshake(tls).await.unwrap();
+            let (request, mut respond) = connection.accept().await.unwrap().unwrap();
+            tokio::spawn(async move { while connection.accept().await.is_some() {} });
+            assert_eq!(request.uri().path(), "/weather.Weather/Current");
+            let mut body = request.into_body();
+            let mut data = Vec::new();
+            while let Some(chunk) = body.data().await {
+                data.extend_from_slice(&chunk.unwrap());
+            }
+            let headers = http::Response::builder().header("content-type", "application/grpc");
+            let message = match answer {
+                Answer::Echo => {
+                    let location = match decode_fields(&data[5..]).unwrap()[..] {
+                        [(1, Field::Bytes(location))] => {
+                            String::from_utf8(location.to_vec()).unwrap()
+                        }
+                        ref fields => panic!("unexpected request {:?}", fields),
+                    };
+                    let mut message = Vec::new();
+                    encode_string(&mut message, 1, &location);
+                    encode_double(&mut message, 2, 13.4);
+                    encode_uint(&mut message, 3, 1744038900);
+                    message
+                }
+                Answer::Status(code, message) => {
+                    let response = headers
+                        .header("grpc-status", code.to_string())
+                        .header("grpc-message", message)
+                        .body(())
+                        .unwrap();
+                    respond.send_response(response, true).unwrap();
+                    return;
+                }
+                Answer::Oversized(len) => vec![0; len],
+            };
+            let mut stream = respond
+                .send_response(headers.body(()).unwrap(), false)
+                .unwrap();
+            // The client resets the stream of an oversized message
+            let _ = stream.send_data(frame(&message), false);
+            let mut trailers = http::HeaderMap::new();
+            trailers.insert("grpc-status", http::HeaderValue::from_static("0"));
+            let _ = stream.send_trailers(trailers);
+        });
+        (format!("localhost:{}", port), ca.pem())
+    }
+
+    fn upstream(authority: String, ca: Option<String>) -> GrpcUpstream {
+        GrpcUpstream {
+            authority,
+            path: "/weather.Weather/Current".to_string(),
+            fields: FieldMapping::default(),
+            extra_ca_pem: ca,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fetch_from_mock_server() {
+        let (authority, ca) = mock_server(Answer::Echo).await;
+        let upstream = upstream(authority, Some(ca));
+        assert_eq!(
+            upstream.fetch("San Francisco").await.unwrap(),
+            GrpcReading {
+                location: "San Francisco".to_string(),
+                temperature: 13.4,
+                last_updated_epoch: 1744038900,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_status_and_transport_errors() {
+        let (authority, ca) = mock_server(Answer::Status(5, "no such location")).await;
+        let rejecting = upstream(authority, Some(ca));
+        assert_eq!(
+            rejecting.fetch("Atlantis").await.unwrap_err(),
+            GrpcError::Status {
+                code: 5,
+                message: "no such location".to_string()
+            }
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let closed = upstream(listener.local_addr().unwrap().to_string(), None);
+        drop(listener);
+        assert!(matches!(
+            closed.fetch("San Francisco").await.unwrap_err(),
+            GrpcError::Transport(_)
+        ));
+
+        // Without the test CA the server's certificate isn't trusted
+        let (authority, _) = mock_server(Answer::Echo).await;
+        let untrusted = upstream(authority, None);
+        assert!(matches!(
+            untrusted.fetch("San Francisco").await.unwrap_err(),
+            GrpcError::Transport(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_oversized_response() {
+        let (authority, ca) = mock_server(Answer::Oversized(MAX_RESPONSE_BYTES + 1)).await;
+        let upstream = upstream(authority, Some(ca));
+        assert!(matches!(
+            upstream.fetch("San Francisco").await.unwrap_err(),
+            GrpcError::Status { code: 11, .. }
+        ));
+    }
+
+    #[test]
+    fn test_upstream_config() {
+        assert_eq!(
+            parse_authority("https://grpc.example.com:443").unwrap(),
+            "grpc.example.com:443"
+        );
+        assert_eq!(
+            parse_authority("grpc.example.com:8443").unwrap(),
+            "grpc.example.com:8443"
+        );
+        assert!(parse_authority("http://grpc.example.com:80").is_err());
+        assert!(parse_authority("grpc.example.com:443/path").is_err());
+
+        let upstream = upstream("grpc.example.com:443".to_string(), None);
+        let endpoint = |host: &str| Endpoint {
+            host: host.to_string(),
+            check: Default::default(),
+        };
+        assert!(upstream
+            .check_allowed(&[endpoint("api.weatherapi.com"), endpoint("grpc.example.com")])
+            .is_ok());
+        assert!(upstream
+            .check_allowed(&[endpoint("api.weatherapi.com")])
+            .is_err());
+    }
+
+    #[test]
+    fn test_decode_mapping() {
+        let mapping: FieldMapping = "temperature=4, last_updated_epoch=7".parse().unwrap();
+        let mut message = Vec::new();
+        encode_string(&mut message, 1, "Paris");
+        // Superseded by the later value, as protobuf merges repeated scalars
+        encode_uint(&mut message, 4, 99);
+        message.extend_from_slice(&[4 << 3 | 5]);
+        message.extend_from_slice(&21.5f32.to_le_bytes());
+        encode_uint(&mut message, 7, 1744038900);
+        encode_string(&mut message, 9, "ignored");
+        assert_eq!(
+            GrpcReading::decode(&message, &mapping).unwrap(),
+            GrpcReading {
+                location: "Paris".to_string(),
+                temperature: 21.5,
+                last_updated_epoch: 1744038900,
+            }
+        );
+
+        assert!(matches!(
+            GrpcReading::decode(&message, &FieldMapping::default()),
+            Err(GrpcError::Decode(_))
+        ));
+        assert!(matches!(
+            GrpcReading::decode(&message[..message.len() - 2], &mapping),
+            Err(GrpcError::Decode(_))
+        ));
+        assert!("humidity=5".parse::<FieldMapping>().is_err());
+        assert!("location=0".parse::<FieldMapping>().is_err());
+    }
+}
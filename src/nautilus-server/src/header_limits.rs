@@ -0,0 +1,224 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::config::Config;
+use crate::{AppState, EnclaveError};
+use axum::extract::{Request, State};
+use axum::http::HeaderMap;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use hyper::server::conn::http1;
+use hyper_util::rt::TokioExecutor;
+use hyper_util::server::conn::auto;
+use std::sync::Arc;
+
+/// Bytes a header takes on an HTTP/1.1 request besides its name and value:
+/// the `: ` separator and the line break.
+const HEADER_OVERHEAD: usize = 4;
+
+/// Room for the request line in hyper's read buffer, on top of the headers.
+const REQUEST_LINE_BYTES: usize = 8192;
+
+/// Smallest read buffer hyper accepts.
+const MIN_BUF_SIZE: usize = 8192;
+
+/// Bytes HTTP/2 counts for each header besides its name and value.
+const HTTP2_HEADER_OVERHEAD: usize = 32;
+
+/// Header lines hyper parses by default.
+const DEFAULT_MAX_HEADERS: usize = 100;
+
+/// Bounds on the headers of a request. hyper enforces them while reading
+/// the request head, with headroom, so a client can't make the enclave
+/// buffer more than that; see [HeaderLimits::http1]. Requests over the
+/// limits but within the headroom reach [limit_headers], which answers with
+/// a 431 naming the limit. The request body is bounded separately, by
+/// axum's default body limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeaderLimits {
+    /// Most bytes of all headers together, counting each as `name: value`
+    /// and a line break. Zero disables the limit.
+    pub max_bytes: usize,
+    /// Most header lines, counting repeated headers once per value. Zero
+    /// disables the limit.
+    pub max_count: usize,
+}
+
+impl HeaderLimits {
+    /// The limits set by `MAX_HEADER_BYTES` and `MAX_HEADER_COUNT`.
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            max_bytes: config.max_header_bytes,
+            max_count: config.max_header_count,
+        }
+    }
+
+    /// Size of hyper's read buffer, which must hold the request line and all
+    /// headers: twice `max_bytes` plus the request line. None, when the
+    /// limit is disabled, keeps hyper's default.
+    pub fn max_buf_size(&self) -> Option<usize> {
+        (self.max_bytes > 0).then(|| (2 * self.max_bytes + REQUEST_LINE_BYTES).max(MIN_BUF_SIZE))
+    }
+
+    /// Most header lines hyper parses: twice `max_count`. None, when the
+    /// limit is disabled, keeps hyper's default of 100.
+    pub fn max_headers(&self) -> Option<usize> {
+        (self.max_count > 0).then(|| 2 * self.max_count)
+    }
+
+    /// Most bytes of headers hyper accepts on an HTTP/2 request, which
+    /// counts each header as its name and value plus 32: twice `max_bytes`,
+    /// plus that overhead for [HeaderLimits::max_headers] headers. HTTP/2
+    /// has no separate bound on the count. None, when `max_bytes` is
+    /// disabled, keeps hyper's default of 16 KiB.
+    pub fn max_header_list_size(&self) -> Option<u32> {
+        (self.max_bytes > 0).then(|| {
+            let headers = self.max_headers().unwrap_or(DEFAULT_MAX_HEADERS);
+            u32::try_from(2 * self.max_bytes + HTTP2_HEADER_OVERHEAD * headers).unwrap_or(u32::MAX)
+        })
+    }
+
+    /// An HTTP/1.1 connection builder that refuses request heads past
+    /// [HeaderLimits::max_buf_size] or [HeaderLimits::max_headers] with a
+    /// bare 431, before the request reaches the router.
+    pub fn http1(&self) -> http1::Builder {
+        let mut builder = http1::Builder::new();
+        if let Some(size) = self.max_buf_size() {
+            builder.max_buf_size(size);
+        }
+        if let Some(count) = self.max_headers() {
+            builder.max_headers(count);
+        }
+        builder
+    }
+
+    /// A connection builder serving HTTP/1.1 as [HeaderLimits::http1] does,
+    /// and HTTP/2 with headers bounded by
+    /// [HeaderLimits::max_header_list_size].
+    pub fn auto(&self) -> auto::Builder<TokioExecutor> {
+        let mut builder = auto::Builder::new(TokioExecutor::new());
+        if let Some(size) = self.max_buf_size() {
+            builder.http1().max_buf_size(size);
+        }
+        if let Some(count) = self.max_headers() {
+            builder.http1().max_headers(count);
+        }
+        if let Some(size) = self.max_header_list_size() {
+            builder.http2().max_header_list_size(size);
+        }
+        builder
+    }
+
+    /// Check `headers` against the limits.
+    pub fn check(&self, headers: &HeaderMap) -> Result<(), EnclaveError> {
+        let count = headers.len();
+        if self.max_count > 0 && count > self.max_count {
+            return Err(EnclaveError::HeadersTooLarge(format!(
+                "Request has {} headers, at most {} are allowed",
+                count, self.max_count
+            )));
+        }
+        let bytes: usize = headers
+            .iter()
+            .map(|(name, value)| name.as_str().len() + value.len() + HEADER_OVERHEAD)
+            .sum();
+        if self.max_bytes > 0 && bytes > self.max_bytes {
+            return Err(EnclaveError::HeadersTooLarge(format!(
+                "Request headers take {} bytes, at most {} are allowed",
+                bytes, self.max_bytes
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Middleware that rejects requests whose headers exceed `MAX_HEADER_BYTES`
+/// or `MAX_HEADER_COUNT` with a 431 saying by how much, before any handler
+/// runs. The headers are already in memory by then; the bound on that is
+/// the connection's, from [HeaderLimits::http1].
+pub async fn limit_headers(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    match HeaderLimits::from_config(&state.config).check(request.headers()) {
+        Ok(()) => next.run(request).await,
+        Err(e) => e.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::http::{HeaderName, HeaderValue};
+
+    fn headers(count: usize, value_len: usize) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for i in 0..count {
+            headers.append(
+                HeaderName::from_bytes(format!("x-h{}", i % 2).as_bytes()).unwrap(),
+                HeaderValue::from_str(&"a".repeat(value_len)).unwrap(),
+            );
+        }
+        headers
+    }
+
+    #[test]
+    fn test_check() {
+        let limits = HeaderLimits {
+            max_bytes: 100,
+            max_count: 4,
+        };
+        // Each header takes its 4 byte name, the value, `: ` and a line break
+        limits.check(&HeaderMap::new()).unwrap();
+        limits.check(&headers(4, 17)).unwrap();
+        let err = limits.check(&headers(4, 18)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Request headers take 104 bytes, at most 100 are allowed"
+        );
+        // Repeated headers count once per value
+        let err = limits.check(&headers(5, 1)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Request has 5 headers, at most 4 are allowed"
+        );
+
+        let unlimited = HeaderLimits {
+            max_bytes: 0,
+            max_count: 0,
+        };
+        unlimited.check(&headers(200, 1000)).unwrap();
+    }
+
+    #[test]
+    fn test_transport_limits() {
+        let limits = HeaderLimits {
+            max_bytes: 8192,
+            max_count: 64,
+        };
+        assert_eq!(limits.max_buf_size(), Some(24576));
+        assert_eq!(limits.max_headers(), Some(128));
+        assert_eq!(limits.max_header_list_size(), Some(2 * 8192 + 32 * 128));
+        // hyper refuses buffers below 8 KiB
+        let small = HeaderLimits {
+            max_bytes: 10,
+            max_count: 1,
+        };
+        assert_eq!(small.max_buf_size(), Some(MIN_BUF_SIZE));
+        // Without a count limit, room is left for hyper's default count
+        let bytes_only = HeaderLimits {
+            max_bytes: 1000,
+            max_count: 0,
+        };
+        assert_eq!(bytes_only.max_header_list_size(), Some(2000 + 32 * 100));
+
+        let unlimited = HeaderLimits {
+            max_bytes: 0,
+            max_count: 0,
+        };
+        assert_eq!(unlimited.max_buf_size(), None);
+        assert_eq!(unlimited.max_headers(), None);
+        assert_eq!(unlimited.max_header_list_size(), None);
+    }
+}
@@ -0,0 +1,629 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use rand::seq::SliceRandom;
+use serde::Deserialize;
+use serde_yaml::Value;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// An endpoint from `allowed_endpoints.yaml` and how it is health checked.
+/// Entries are either a bare host, whose check is inferred from the hostname,
+/// or a map with an explicit check:
+///
+/// ```yaml
+/// endpoints:
+///   - api.weatherapi.com
+///   - host: s3.us-east-1.amazonaws.com
+///     health:
+///       path: /
+///       status: [200, 403]
+///   - host: kms.us-east-1.amazonaws.com
+///     health:
+///       path: /ping
+///       any_status: true
+///       body_contains: healthy
+///   - host: secretsmanager.us-east-1.amazonaws.com
+///     health:
+///       skip: true
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Endpoint {
+    pub host: String,
+    pub check: HealthCheck,
+}
+
+/// Request made to an endpoint and what counts as healthy.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HealthCheck {
+    /// Path requested with GET. Defaults to `/`.
+    #[serde(default = "default_path")]
+    pub path: String,
+    /// Accepted status codes. When empty any 2xx is accepted.
+    #[serde(default)]
+    pub status: Vec<u16>,
+    /// Accept any status, e.g. for services that reject unauthenticated
+    /// requests but whose response still proves connectivity.
+    #[serde(default)]
+    pub any_status: bool,
+    /// Text the response body must contain, ignoring case.
+    #[serde(default)]
+    pub body_contains: Option<String>,
+    /// Leave the endpoint out of health checks.
+    #[serde(default)]
+    pub skip: bool,
+}
+
+fn default_path() -> String {
+    "/".to_string()
+}
+
+impl Default for HealthCheck {
+    fn default() -> Self {
+        Self {
+            path: default_path(),
+            status: Vec::new(),
+            any_status: false,
+            body_contains: None,
+            skip: false,
+        }
+    }
+}
+
+impl HealthCheck {
+    /// Check for an endpoint listed by host only. KMS serves `/ping`
+    /// answering "healthy"; other AWS services reject anonymous requests, so
+    /// any response from them counts. Other hosts must answer `/` with a 2xx.
+    pub fn inferred(host: &str) -> Self {
+        if !host.contains(".amazonaws.com") {
+            Self::default()
+        } else if host.starts_with("kms.") {
+            Self {
+                path: "/ping".to_string(),
+                any_status: true,
+                body_contains: Some("healthy".to_string()),
+                ..Self::default()
+            }
+        } else {
+            Self {
+                any_status: true,
+                ..Self::default()
+            }
+        }
+    }
+
+    /// Whether the response body must be read to decide.
+    pub fn needs_body(&self) -> bool {
+        self.body_contains.is_some()
+    }
+
+    /// Whether a response with `status` and `body` is healthy.
+    pub fn passes(&self, status: u16, body: Option<&str>) -> bool {
+        let status_ok = self.any_status
+            || if self.status.is_empty() {
+                (200..300).contains(&status)
+            } else {
+                self.status.contains(&status)
+            };
+        let body_ok = match &self.body_contains {
+            Some(needle) => {
+                body.is_some_and(|body| body.to_lowercase().contains(&needle.to_lowercase()))
+            }
+            None => true,
+        };
+        status_ok && body_ok
+    }
+}
+
+/// What happens when `allowed_endpoints.yaml` lists more endpoints than
+/// allowed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EndpointsOverflow {
+    /// Refuse to start.
+    Fail,
+    /// Start with a warning, and only health check the first endpoints.
+    Truncate,
+}
+
+impl FromStr for EndpointsOverflow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fail" => Ok(Self::Fail),
+            "truncate" => Ok(Self::Truncate),
+            _ => Err(format!("expected `fail` or `truncate`, got `{}`", s)),
+        }
+    }
+}
+
+/// How many of `count` endpoints are used when at most `max` are allowed.
+pub fn endpoint_limit(
+    count: usize,
+    max: usize,
+    overflow: EndpointsOverflow,
+) -> Result<usize, String> {
+    if count <= max {
+        return Ok(count);
+    }
+    match overflow {
+        EndpointsOverflow::Truncate => Ok(max),
+        EndpointsOverflow::Fail => Err(format!(
+            "{} lists {} endpoints, more than MAX_ALLOWED_ENDPOINTS ({})",
+            ALLOWED_ENDPOINTS_PATH, count, max
+        )),
+    }
+}
+
+/// Endpoints read from `allowed_endpoints.yaml`, and a warning for each entry
+/// that couldn't be interpreted and was left out or read only in part.
+#[derive(Debug, Default)]
+pub struct ParsedEndpoints {
+    pub endpoints: Vec<Endpoint>,
+    pub warnings: Vec<String>,
+}
+
+/// Path of the allowed endpoints file, relative to the server's working
+/// directory.
+pub const ALLOWED_ENDPOINTS_PATH: &str = "allowed_endpoints.yaml";
+
+/// `allowed_endpoints.yaml` as read at startup, with the SHA-256 of its
+/// bytes. The digest covers the file exactly as shipped in the image,
+/// comments and formatting included, so verifiers can reproduce it with
+/// `sha256sum allowed_endpoints.yaml`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EndpointsDigest {
+    pub yaml: String,
+    pub sha256: Vec<u8>,
+}
+
+impl EndpointsDigest {
+    pub fn new(yaml: String) -> Self {
+        let sha256 = Sha256::digest(yaml.as_bytes()).to_vec();
+        Self { yaml, sha256 }
+    }
+
+    /// Read [ALLOWED_ENDPOINTS_PATH], or None if it can't be read.
+    pub fn load() -> Option<Self> {
+        std::fs::read_to_string(ALLOWED_ENDPOINTS_PATH)
+            .ok()
+            .map(Self::new)
+    }
+}
+
+/// Parse the contents of `allowed_endpoints.yaml`. The file is an
+/// `endpoints` list, or just the list, whose entries are bare hosts or maps
+/// with a `host` and optional `health` check, in any mix. Entries that can't
+/// be interpreted are skipped with a warning instead of failing the file, so
+/// a file written for a newer format still yields the entries this version
+/// understands.
+pub fn parse_allowed_endpoints(yaml: &str) -> Result<ParsedEndpoints, String> {
+    let file: Value = serde_yaml::from_str(yaml).map_err(|e| e.to_string())?;
+    let entries: &[Value] = match &file {
+        Value::Sequence(entries) => entries.as_slice(),
+        Value::Mapping(map) => match map.get("endpoints") {
+            Some(Value::Sequence(entries)) => entries.as_slice(),
+            Some(Value::Null) | None => &[],
+            Some(_) => return Err("`endpoints` must be a list".to_string()),
+        },
+        Value::Null => &[],
+        _ => return Err("expected an `endpoints` list".to_string()),
+    };
+    let mut parsed = ParsedEndpoints::default();
+    for (i, entry) in entries.iter().enumerate() {
+        let mut warn = |message: String| {
+            parsed
+                .warnings
+                .push(format!("endpoint entry {}: {}", i + 1, message))
+        };
+        match parse_entry(entry, &mut warn) {
+            Ok(endpoint) => parsed.endpoints.push(endpoint),
+            Err(e) => warn(format!("{}, skipped", e)),
+        }
+    }
+    Ok(parsed)
+}
+
+/// Interpret one entry, reporting fields it ignores through `warn`.
+fn parse_entry(entry: &Value, warn: &mut impl FnMut(String)) -> Result<Endpoint, String> {
+    let (host, health) = match entry {
+        Value::String(host) => (host.clone(), None),
+        Value::Mapping(map) => {
+            let host = match map.get("host") {
+                Some(Value::String(host)) => host.clone(),
+                Some(_) => return Err("`host` must be a string".to_string()),
+                None => return Err("missing `host`".to_string()),
+            };
+            for key in map.keys() {
+                match key.as_str() {
+                    Some("host" | "health") => {}
+                    Some(key) => warn(format!("{}: ignoring unknown field `{}`", host, key)),
+                    None => warn(format!("{}: ignoring unknown field {:?}", host, key)),
+                }
+            }
+            (host, map.get("health").filter(|health| !health.is_null()))
+        }
+        _ => return Err("expected a host or a map with `host`".to_string()),
+    };
+    if host.is_empty() {
+        return Err("empty `host`".to_string());
+    }
+    let check = match health {
+        Some(health) => serde_yaml::from_value::<HealthCheck>(health.clone())
+            .map_err(|e| format!("{}: invalid health check: {}", host, e))?,
+        None => HealthCheck::inferred(&host),
+    };
+    if !check.path.starts_with('/') {
+        return Err(format!(
+            "{}: health check path must start with `/`: {}",
+            host, check.path
+        ));
+    }
+    Ok(Endpoint { host, check })
+}
+
+/// Order in which [ProbeRotation] walks the endpoint list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RotationOrder {
+    /// The order of `allowed_endpoints.yaml`.
+    RoundRobin,
+    /// A fresh random permutation for every pass over the list.
+    Shuffled,
+}
+
+impl FromStr for RotationOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "round_robin" => Ok(Self::RoundRobin),
+            "shuffled" => Ok(Self::Shuffled),
+            _ => Err(format!("expected `round_robin` or `shuffled`, got `{}`", s)),
+        }
+    }
+}
+
+/// Spreads endpoint probes across health checks so a long endpoint list
+/// doesn't make every check expensive. Each check probes the next
+/// `subset_size` endpoints of the current pass and reports them merged with
+/// results cached from earlier checks, so every endpoint is probed once per
+/// `ceil(endpoints / subset_size)` checks. Cached results older than `window`
+/// are dropped rather than reported stale.
+pub struct ProbeRotation {
+    subset_size: usize,
+    window: Duration,
+    order: RotationOrder,
+    state: Mutex<RotationState>,
+}
+
+#[derive(Default)]
+struct RotationState {
+    /// Endpoints not yet probed in the current pass, next one last.
+    pending: Vec<String>,
+    /// Last result per endpoint and when it was recorded.
+    results: HashMap<String, (Instant, bool)>,
+}
+
+impl ProbeRotation {
+    /// A `subset_size` of zero probes every endpoint on every check.
+    pub fn new(subset_size: usize, window: Duration, order: RotationOrder) -> Self {
+        Self {
+            subset_size,
+            window,
+            order,
+            state: Mutex::new(RotationState::default()),
+        }
+    }
+
+    /// Endpoints to probe in this health check.
+    pub fn next_batch(&self, endpoints: &[String]) -> Vec<String> {
+        if self.subset_size == 0 || self.subset_size >= endpoints.len() {
+            return endpoints.to_vec();
+        }
+        let mut state = self.state.lock().unwrap();
+        // Endpoints removed from the list since the pass started are skipped.
+        let current: HashSet<&String> = endpoints.iter().collect();
+        state.pending.retain(|endpoint| current.contains(endpoint));
+        let mut batch = Vec::with_capacity(self.subset_size);
+        while batch.len() < self.subset_size {
+            if state.pending.is_empty() {
+                state.pending = self.new_pass(endpoints, &batch);
+                if state.pending.is_empty() {
+                    break;
+                }
+            }
+            batch.extend(state.pending.pop());
+        }
+        batch
+    }
+
+    /// Record this check's results and return the status of every listed
+    /// endpoint with a result inside the window, sorted by endpoint.
+    pub fn merge(
+        &self,
+        endpoints: &[String],
+        results: impl IntoIterator<Item = (String, bool)>,
+    ) -> BTreeMap<String, bool> {
+        self.merge_at(endpoints, results, Instant::now())
+    }
+
+    fn merge_at(
+        &self,
+        endpoints: &[String],
+        results: impl IntoIterator<Item = (String, bool)>,
+        now: Instant,
+    ) -> BTreeMap<String, bool> {
+        let mut state = self.state.lock().unwrap();
+        for (endpoint, is_reachable) in results {
+            state.results.insert(endpoint, (now, is_reachable));
+        }
+        let current: HashSet<&String> = endpoints.iter().collect();
+        state.results.retain(|endpoint, (checked_at, _)| {
+            current.contains(endpoint) && now.duration_since(*checked_at) <= self.window
+        });
+        state
+            .results
+            .iter()
+            .map(|(endpoint, (_, is_reachable))| (endpoint.clone(), *is_reachable))
+            .collect()
+    }
+
+    /// Endpoints for a new pass, excluding those already in `batch`, in
+    /// reverse so the next endpoint can be popped off the end.
+    fn new_pass(&self, endpoints: &[String], batch: &[String]) -> Vec<String> {
+        let mut pass: Vec<String> = endpoints
+            .iter()
+            .filter(|endpoint| !batch.contains(endpoint))
+            .cloned()
+            .collect();
+        match self.order {
+            RotationOrder::RoundRobin => pass.reverse(),
+            RotationOrder::Shuffled => pass.shuffle(&mut rand::thread_rng()),
+        }
+        pass
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use fastcrypto::encoding::{Encoding, Hex};
+
+    fn endpoints(n: usize) -> Vec<String> {
+        (0..n)
+            .map(|i| format!("endpoint{}.example.com", i))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_allowed_endpoints() {
+        let yaml = r#"
+endpoints:
+  - api.weatherapi.com
+  - kms.us-east-1.amazonaws.com
+  - s3.us-east-1.amazonaws.com
+  - host: secretsmanager.us-east-1.amazonaws.com
+  - host: status.example.com
+    health:
+      path: /healthz
+      status: [200, 204]
+      body_contains: ok
+  - host: internal.example.com
+    health:
+      skip: true
+"#;
+        let parsed = parse_allowed_endpoints(yaml).unwrap();
+        assert!(parsed.warnings.is_empty(), "{:?}", parsed.warnings);
+        let endpoints = parsed.endpoints;
+        let hosts: Vec<&str> = endpoints.iter().map(|e| e.host.as_str()).collect();
+        assert_eq!(
+            hosts,
+            [
+                "api.weatherapi.com",
+                "kms.us-east-1.amazonaws.com",
+                "s3.us-east-1.amazonaws.com",
+                "secretsmanager.us-east-1.amazonaws.com",
+                "status.example.com",
+                "internal.example.com",
+            ]
+        );
+        assert_eq!(endpoints[0].check, HealthCheck::default());
+        assert_eq!(endpoints[1].check.path, "/ping");
+        assert!(endpoints[2].check.any_status && !endpoints[2].check.needs_body());
+        assert_eq!(
+            endpoints[3].check,
+            HealthCheck::inferred("secretsmanager.us-east-1.amazonaws.com")
+        );
+        assert_eq!(
+            endpoints[4].check,
+            HealthCheck {
+                path: "/healthz".to_string(),
+                status: vec![200, 204],
+                body_contains: Some("ok".to_string()),
+                ..HealthCheck::default()
+            }
+        );
+        assert!(endpoints[5].check.skip);
+    }
+
+    #[test]
+    fn test_parse_mixed_formats_with_bad_entries() {
+        let yaml = r#"
+endpoints:
+  - api.weatherapi.com
+  - host: status.example.com
+    health: { path: healthz }
+  - host: retry.example.com
+    health: { retries: 3 }
+  - host: s3.us-east-1.amazonaws.com
+    port: 443
+  - { health: { skip: true } }
+  - 443
+  - host: kms.us-east-1.amazonaws.com
+    health:
+"#;
+        let parsed = parse_allowed_endpoints(yaml).unwrap();
+        let hosts: Vec<&str> = parsed.endpoints.iter().map(|e| e.host.as_str()).collect();
+        assert_eq!(
+            hosts,
+            [
+                "api.weatherapi.com",
+                "s3.us-east-1.amazonaws.com",
+                "kms.us-east-1.amazonaws.com",
+            ]
+        );
+        assert_eq!(parsed.endpoints[2].check.path, "/ping");
+        let entries: Vec<&str> = parsed
+            .warnings
+            .iter()
+            .map(|w| w.split(':').next().unwrap())
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                "endpoint entry 2",
+                "endpoint entry 3",
+                "endpoint entry 4",
+                "endpoint entry 5",
+                "endpoint entry 6",
+            ]
+        );
+        assert!(
+            parsed.warnings[1].contains("retries"),
+            "{}",
+            parsed.warnings[1]
+        );
+        assert!(
+            parsed.warnings[2].contains("port"),
+            "{}",
+            parsed.warnings[2]
+        );
+
+        // The list alone, without the `endpoints` key
+        let parsed = parse_allowed_endpoints("- a.example.com\n- host: b.example.com\n").unwrap();
+        assert_eq!(parsed.endpoints.len(), 2);
+        assert!(parse_allowed_endpoints("endpoints: a.example.com").is_err());
+        assert!(parse_allowed_endpoints("endpoints: [").is_err());
+    }
+
+    #[test]
+    fn test_health_check_passes() {
+        let default = HealthCheck::default();
+        assert!(default.passes(200, None));
+        assert!(default.passes(204, None));
+        assert!(!default.passes(403, None));
+
+        // Non-KMS AWS services reject anonymous requests but are reachable.
+        let s3 = HealthCheck::inferred("s3.us-east-1.amazonaws.com");
+        assert!(s3.passes(403, None));
+
+        let kms = HealthCheck::inferred("kms.us-east-1.amazonaws.com");
+        assert!(kms.passes(200, Some("Healthy")));
+        assert!(!kms.passes(200, Some("unavailable")));
+        assert!(!kms.passes(200, None));
+
+        let explicit = HealthCheck {
+            status: vec![403],
+            ..HealthCheck::default()
+        };
+        assert!(explicit.passes(403, None));
+        assert!(!explicit.passes(200, None));
+    }
+
+    #[test]
+    fn test_rotation_covers_all_endpoints() {
+        let endpoints = endpoints(10);
+        for order in [RotationOrder::RoundRobin, RotationOrder::Shuffled] {
+            let rotation = ProbeRotation::new(3, Duration::from_secs(300), order);
+            let mut status = BTreeMap::new();
+            // ceil(10 / 3) checks cover every endpoint.
+            for _ in 0..4 {
+                let batch = rotation.next_batch(&endpoints);
+                assert_eq!(batch.len(), 3);
+                let unique: HashSet<&String> = batch.iter().collect();
+                assert_eq!(unique.len(), 3);
+                status = rotation.merge(&endpoints, batch.into_iter().map(|e| (e, true)));
+            }
+            assert_eq!(status.len(), endpoints.len());
+        }
+    }
+
+    #[test]
+    fn test_round_robin_order() {
+        let endpoints = endpoints(5);
+        let rotation = ProbeRotation::new(2, Duration::from_secs(300), RotationOrder::RoundRobin);
+        assert_eq!(rotation.next_batch(&endpoints), endpoints[0..2]);
+        assert_eq!(rotation.next_batch(&endpoints), endpoints[2..4]);
+        // The last endpoint of the pass is followed by the start of the next.
+        assert_eq!(
+            rotation.next_batch(&endpoints),
+            [endpoints[4].clone(), endpoints[0].clone()]
+        );
+    }
+
+    #[test]
+    fn test_zero_subset_probes_all() {
+        let endpoints = endpoints(4);
+        let rotation = ProbeRotation::new(0, Duration::from_secs(300), RotationOrder::Shuffled);
+        assert_eq!(rotation.next_batch(&endpoints), endpoints);
+        assert_eq!(rotation.next_batch(&endpoints), endpoints);
+    }
+
+    #[test]
+    fn test_merge_drops_expired_and_removed_endpoints() {
+        let endpoints = endpoints(3);
+        let rotation = ProbeRotation::new(1, Duration::from_secs(60), RotationOrder::RoundRobin);
+        let start = Instant::now();
+        rotation.merge_at(&endpoints, [(endpoints[0].clone(), true)], start);
+        let status = rotation.merge_at(
+            &endpoints,
+            [(endpoints[1].clone(), false)],
+            start + Duration::from_secs(30),
+        );
+        assert_eq!(status.get(&endpoints[0]), Some(&true));
+        assert_eq!(status.get(&endpoints[1]), Some(&false));
+
+        let status = rotation.merge_at(&endpoints, [], start + Duration::from_secs(61));
+        assert_eq!(status.len(), 1);
+        assert!(status.contains_key(&endpoints[1]));
+
+        let status = rotation.merge_at(&endpoints[2..], [], start + Duration::from_secs(62));
+        assert!(status.is_empty());
+    }
+
+    #[test]
+    fn test_endpoints_digest() {
+        let yaml = "endpoints:\n  - api.weatherapi.com\n";
+        let digest = EndpointsDigest::new(yaml.to_string());
+        assert_eq!(digest, EndpointsDigest::new(yaml.to_string()));
+        assert_eq!(
+            Hex::encode(&digest.sha256),
+            "3b7513f37e647424ea582343d34a854517e8fbfd54fe957a4916e1a8559b0161"
+        );
+        assert_ne!(
+            digest.sha256,
+            EndpointsDigest::new("endpoints:\n  - evil.example.com\n".to_string()).sha256
+        );
+    }
+
+    #[test]
+    fn test_endpoint_limit() {
+        use EndpointsOverflow::*;
+        assert_eq!(endpoint_limit(3, 3, Fail), Ok(3));
+        assert_eq!(endpoint_limit(2, 3, Truncate), Ok(2));
+        assert_eq!(endpoint_limit(5, 3, Truncate), Ok(3));
+        assert_eq!(
+            endpoint_limit(5, 3, Fail),
+            Err(
+                "allowed_endpoints.yaml lists 5 endpoints, more than MAX_ALLOWED_ENDPOINTS (3)"
+                    .to_string()
+            )
+        );
+        assert_eq!("truncate".parse(), Ok(Truncate));
+        assert!("warn".parse::<EndpointsOverflow>().is_err());
+    }
+}
@@ -0,0 +1,125 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::common::IntentMessage;
+use fastcrypto::ed25519::Ed25519KeyPair;
+use fastcrypto::traits::Signer;
+use serde::Serialize;
+
+/// JOSE header of every JWT the enclave signs.
+pub const JWT_HEADER: &str = r#"{"alg":"EdDSA","typ":"JWT"}"#;
+
+/// Claims of a signed JWT: the fields of the intent message, plus the
+/// registered `iat` and `exp` claims in seconds so generic verifiers can
+/// check freshness.
+#[derive(Serialize)]
+struct Claims<'a, T: Serialize> {
+    #[serde(flatten)]
+    message: &'a IntentMessage<T>,
+    iat: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    exp: Option<u64>,
+}
+
+/// `message` as a compact JWS (RFC 7515) signed with EdDSA (RFC 8037). The
+/// signature is Ed25519 over the ASCII bytes `base64url(JWT_HEADER) || '.' ||
+/// base64url(claims JSON)`, so any JWT library can verify it with the
+/// enclave's public key. These bytes differ from the BCS signing payload.
+pub fn encode<T: Serialize>(kp: &Ed25519KeyPair, message: &IntentMessage<T>) -> String {
+    let claims = Claims {
+        message,
+        iat: message.timestamp_ms / 1000,
+        exp: message.expires_at_ms.map(|ms| ms / 1000),
+    };
+    let claims = serde_json::to_vec(&claims).expect("should not fail");
+    let signing_input = format!(
+        "{}.{}",
+        base64url(JWT_HEADER.as_bytes()),
+        base64url(&claims)
+    );
+    let sig = kp.sign(signing_input.as_bytes());
+    format!("{}.{}", signing_input, base64url(sig.as_ref()))
+}
+
+/// Unpadded base64url (RFC 4648 section 5), as JWS requires.
+pub fn base64url(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, byte)| n | ((*byte as u32) << (16 - 8 * i)));
+        for i in 0..=chunk.len() {
+            out.push(ALPHABET[((n >> (18 - 6 * i)) & 0x3f) as usize] as char);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::app::WeatherResponse;
+    use crate::common::IntentScope;
+    use fastcrypto::traits::{KeyPair, ToFromBytes};
+    use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
+    use serde_json::{json, Value};
+
+    #[test]
+    fn test_base64url() {
+        for (input, expected) in [
+            ("", ""),
+            ("f", "Zg"),
+            ("fo", "Zm8"),
+            ("foo", "Zm9v"),
+            ("foob", "Zm9vYg"),
+            ("fooba", "Zm9vYmE"),
+            ("foobar", "Zm9vYmFy"),
+        ] {
+            assert_eq!(base64url(input.as_bytes()), expected);
+        }
+        assert_eq!(base64url(&[0xfb, 0xff]), "-_8");
+    }
+
+    #[test]
+    fn test_verify_with_jsonwebtoken() {
+        let kp = Ed25519KeyPair::generate(&mut rand::thread_rng());
+        let mut message = IntentMessage::new(
+            WeatherResponse {
+                location: "San Francisco".to_string(),
+                temperature: 13,
+            },
+            1744038900000,
+            IntentScope::Weather,
+        );
+        message.expires_at_ms = Some(u64::MAX / 2);
+        let token = encode(&kp, &message);
+
+        let key = DecodingKey::from_ed_der(kp.public().as_bytes());
+        let decoded = decode::<Value>(&token, &key, &Validation::new(Algorithm::EdDSA)).unwrap();
+        assert_eq!(
+            decoded.claims,
+            json!({
+                "intent": 0,
+                "timestamp_ms": 1744038900000_u64,
+                "expires_at_ms": u64::MAX / 2,
+                "data": {"location": "San Francisco", "temperature": 13},
+                "iat": 1744038900,
+                "exp": u64::MAX / 2 / 1000,
+            })
+        );
+
+        // Another key, or altered claims, fail verification
+        let other = Ed25519KeyPair::generate(&mut rand::thread_rng());
+        let other_key = DecodingKey::from_ed_der(other.public().as_bytes());
+        assert!(decode::<Value>(&token, &other_key, &Validation::new(Algorithm::EdDSA)).is_err());
+        let mut parts: Vec<&str> = token.split('.').collect();
+        message.data.temperature = 14;
+        let altered = encode(&other, &message);
+        parts[1] = altered.split('.').nth(1).unwrap();
+        assert!(
+            decode::<Value>(&parts.join("."), &key, &Validation::new(Algorithm::EdDSA)).is_err()
+        );
+    }
+}
@@ -1,39 +1,381 @@
 // Copyright (c), Mysten Labs, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
+use api_keys::ApiKeys;
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
 use axum::response::Response;
 use axum::Json;
+use common::{
+    load_allowed_endpoints, AttestationCache, BootAttestation, IntentScope, SequenceCounters,
+};
+use config::Config;
+use dns::CachingResolver;
 use fastcrypto::ed25519::Ed25519KeyPair;
-use serde_json::json;
+use fastcrypto::encoding::{Encoding, Hex};
+use fastcrypto::traits::{KeyPair, ToFromBytes};
+use health::{endpoint_limit, EndpointsDigest, ProbeRotation, ALLOWED_ENDPOINTS_PATH};
+use nautilus_verifier::attestation::{UserData, BUILD_COMMIT_LEN};
+use nonces::NonceTracker;
+use reattest::Reattestation;
+use reqwest::Client;
+use schema::SchemaRegistry;
+use serde::{Deserialize, Serialize};
+use stats::{LastFetches, Stats};
+use std::sync::Arc;
+use std::time::Duration;
+use timing::RequestLogSampler;
+use tracing::{info, warn};
+use upstream_tls::TlsFailure;
 
+pub mod api_keys;
+pub mod api_version;
 pub mod app;
+pub mod attestation;
 pub mod common;
+pub mod config;
+pub mod dns;
+pub mod expiry;
+pub mod github;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod header_limits;
+pub mod health;
+pub mod jwt;
+#[cfg(feature = "mock-weather")]
+pub mod mock_weather;
+#[cfg(feature = "mtls")]
+pub mod mtls;
+pub mod nonces;
+#[cfg(feature = "openapi")]
+pub mod openapi;
+pub mod pcrs;
+pub mod pem;
+pub mod price;
+pub mod reattest;
+pub mod reseed;
+pub mod routes;
+pub mod schema;
+pub mod stats;
+#[cfg(feature = "sui")]
+pub mod sui;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod test_utils;
+pub mod timing;
+pub mod transaction;
+pub mod upstream_tls;
+pub mod vectors;
+pub mod webhook;
+
+/// Git commit the server was built from, hex encoded, as set by `build.rs`
+/// from `GIT_COMMIT_HASH` or the checkout. Empty if the build had neither.
+pub const BUILD_COMMIT: &str = env!("GIT_COMMIT_HASH");
+
+/// [BUILD_COMMIT] as bytes, or None if it isn't a SHA-1 commit hash.
+pub fn build_commit() -> Option<[u8; BUILD_COMMIT_LEN]> {
+    Hex::decode(BUILD_COMMIT).ok()?.try_into().ok()
+}
 
 /// App state, at minimum needs to maintain the ephemeral keypair.  
 pub struct AppState {
     /// Ephemeral keypair on boot
     pub eph_kp: Ed25519KeyPair,
-    /// API key when querying api.weatherapi.com
-    pub api_key: String,
+    /// API keys when querying api.weatherapi.com
+    pub api_keys: ApiKeys,
+    /// Server configuration
+    pub config: Config,
+    /// HTTP client for api.weatherapi.com
+    pub upstream_client: Client,
+    /// HTTP client shared by health-check probes
+    pub probe_client: Client,
+    /// Which endpoints each health check probes, and earlier results
+    pub probe_rotation: ProbeRotation,
+    /// Attestation cached at boot, served by `/get_attestation`
+    pub boot_attestation: Option<BootAttestation>,
+    /// Sequence numbers signed with `SIGN_SEQUENCE`
+    pub sequences: SequenceCounters,
+    /// Attestations reused across `/get_attestation` calls
+    pub attestation_cache: AttestationCache,
+    /// Nonces recently passed to `/get_attestation`
+    pub nonce_tracker: NonceTracker,
+    /// Schemas accepted by `/sign/:schema`
+    pub schemas: SchemaRegistry,
+    /// `allowed_endpoints.yaml` as read at startup, served by
+    /// `/allowed_endpoints`
+    pub endpoints_digest: Option<EndpointsDigest>,
+    /// Attestations requested every `REATTEST_INTERVAL_SECS`, served by
+    /// `/periodic_attestation`
+    pub reattestation: Reattestation,
+    /// Counters served by `/stats`
+    pub stats: Stats,
+    /// Last successful fetch from each data source, reported by
+    /// `/health_check`
+    pub last_fetches: LastFetches,
+    /// Requests logged with `REQUEST_LOG_SAMPLE`
+    pub request_log: RequestLogSampler,
+}
+
+impl AppState {
+    pub fn new(
+        eph_kp: Ed25519KeyPair,
+        api_keys: ApiKeys,
+        config: Config,
+    ) -> Result<Self, EnclaveError> {
+        let resolver = CachingResolver::new(
+            config.dns_cache_ttl,
+            config.dns_max_concurrent_lookups,
+            config.address_family,
+        );
+        Self::new_with(eph_kp, api_keys, config, resolver)
+    }
+
+    /// [AppState::new] with the resolver the HTTP clients use.
+    pub(crate) fn new_with(
+        eph_kp: Ed25519KeyPair,
+        api_keys: ApiKeys,
+        config: Config,
+        resolver: CachingResolver,
+    ) -> Result<Self, EnclaveError> {
+        // Both clients share the resolver, so they agree on address family
+        let resolver = Arc::new(resolver);
+        let build_error = |e: reqwest::Error| {
+            EnclaveError::GenericError(format!("Failed to create HTTP client: {}", e))
+        };
+        let upstream_client = Client::builder()
+            .dns_resolver(resolver.clone())
+            .build()
+            .map_err(build_error)?;
+        let probe_client = Client::builder()
+            .timeout(Duration::from_secs(5))
+            .dns_resolver(resolver)
+            .build()
+            .map_err(build_error)?;
+        let probe_rotation = ProbeRotation::new(
+            config.health_check_subset_size,
+            config.health_check_result_ttl,
+            config.health_check_rotation,
+        );
+        let endpoint_count = load_allowed_endpoints(config.allowed_endpoints_strict).len();
+        let endpoint_limit = endpoint_limit(
+            endpoint_count,
+            config.max_allowed_endpoints,
+            config.allowed_endpoints_overflow,
+        )
+        .map_err(EnclaveError::GenericError)?;
+        if endpoint_limit < endpoint_count {
+            warn!(
+                "Health checks only probe the first {} of {} allowed endpoints, see MAX_ALLOWED_ENDPOINTS",
+                endpoint_limit, endpoint_count
+            );
+        }
+        #[cfg(feature = "mock-weather")]
+        if let Some(mock_weather) = &config.mock_weather {
+            // An enclave's responses must come from the real upstream
+            if common::nsm_probe().is_ok() {
+                return Err(EnclaveError::GenericError(
+                    "MOCK_WEATHER can't be used inside an enclave".to_string(),
+                ));
+            }
+            warn!(
+                "MOCK_WEATHER is set: /process_data signs fake readings {:?} without querying \
+                 the weather API. Never use it in production",
+                mock_weather
+            );
+        }
+        let endpoints_digest = EndpointsDigest::load();
+        if config.attest_endpoints_digest && endpoints_digest.is_none() {
+            return Err(EnclaveError::GenericError(format!(
+                "ATTEST_ENDPOINTS_DIGEST requires {}",
+                ALLOWED_ENDPOINTS_PATH
+            )));
+        }
+        if config.attest_build_commit && build_commit().is_none() {
+            return Err(EnclaveError::GenericError(format!(
+                "ATTEST_BUILD_COMMIT requires a git commit hash, the server was built with {:?}; \
+                 build from a git checkout or set GIT_COMMIT_HASH",
+                BUILD_COMMIT
+            )));
+        }
+        let user_data = attestation_user_data(&config, endpoints_digest.as_ref());
+        let boot_attestation = if config.boot_attestation || config.sign_attestation_digest {
+            match BootAttestation::fetch(
+                &eph_kp,
+                user_data.as_deref(),
+                config.boot_attestation_attempts,
+                config.boot_attestation_backoff,
+            ) {
+                Ok(boot_attestation) => Some(boot_attestation),
+                Err(e) if config.boot_attestation_required || config.sign_attestation_digest => {
+                    return Err(e)
+                }
+                Err(e) => {
+                    info!("Starting without boot attestation: {}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        let attestation_cache = AttestationCache::new(
+            config.attestation_cache_ttl,
+            config.attestation_cache_max_age,
+        );
+        if let Some(boot_attestation) = &boot_attestation {
+            attestation_cache.seed(
+                boot_attestation.document.clone(),
+                boot_attestation.generated_at_ms,
+            );
+        }
+        let nonce_tracker = NonceTracker::new(
+            config.attestation_nonce_window,
+            config.attestation_nonce_reuse,
+        );
+        let schemas = SchemaRegistry::load(config.schemas_path.as_deref())?;
+        let stats = Stats::new(
+            IntentScope::ALL
+                .iter()
+                .map(|scope| *scope as u8)
+                .chain(schemas.intents()),
+        );
+        if boot_attestation.is_some() {
+            stats.record_attestation();
+        }
+        let last_fetches = LastFetches::new(data_sources(&config));
+        let reattestation = Reattestation::new(eph_kp.public().as_bytes());
+        let request_log = RequestLogSampler::new(config.request_log_sample);
+        Ok(Self {
+            eph_kp,
+            api_keys,
+            config,
+            upstream_client,
+            probe_client,
+            probe_rotation,
+            boot_attestation,
+            sequences: SequenceCounters::default(),
+            attestation_cache,
+            nonce_tracker,
+            schemas,
+            endpoints_digest,
+            reattestation,
+            stats,
+            last_fetches,
+            request_log,
+        })
+    }
+
+    /// `user_data` for attestation documents, laid out as [UserData]: the
+    /// allowed endpoints digest with `ATTEST_ENDPOINTS_DIGEST` followed by
+    /// the build commit with `ATTEST_BUILD_COMMIT`, or none without either.
+    pub fn attestation_user_data(&self) -> Option<Vec<u8>> {
+        attestation_user_data(&self.config, self.endpoints_digest.as_ref())
+    }
+}
+
+/// Upstreams the configuration fetches signed data from, as reported by
+/// `/health_check`.
+fn data_sources(config: &Config) -> Vec<&'static str> {
+    let mut sources = vec![app::WEATHER_SOURCE];
+    if config.price_upstream.is_some() {
+        sources.push(price::PRICE_SOURCE);
+    }
+    #[cfg(feature = "github")]
+    if config.github_upstream.is_some() {
+        sources.push(github::GITHUB_SOURCE);
+    }
+    sources
+}
+
+/// See [AppState::attestation_user_data], which needs the state built.
+fn attestation_user_data(
+    config: &Config,
+    endpoints_digest: Option<&EndpointsDigest>,
+) -> Option<Vec<u8>> {
+    UserData {
+        endpoints_digest: endpoints_digest
+            .filter(|_| config.attest_endpoints_digest)
+            .and_then(|digest| digest.sha256.as_slice().try_into().ok()),
+        build_commit: build_commit().filter(|_| config.attest_build_commit),
+    }
+    .to_bytes()
 }
 
 /// Implement IntoResponse for EnclaveError.
 impl IntoResponse for EnclaveError {
     fn into_response(self) -> Response {
-        let (status, error_message) = match self {
-            EnclaveError::GenericError(e) => (StatusCode::BAD_REQUEST, e),
+        let tls_failure = match &self {
+            EnclaveError::UpstreamTls { reason, .. } => Some(*reason),
+            EnclaveError::GenericError(_)
+            | EnclaveError::NotFound(_)
+            | EnclaveError::Unauthorized(_)
+            | EnclaveError::HeadersTooLarge(_) => None,
+        };
+        let status = match &self {
+            EnclaveError::GenericError(_) => StatusCode::BAD_REQUEST,
+            EnclaveError::NotFound(_) => StatusCode::NOT_FOUND,
+            EnclaveError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            EnclaveError::HeadersTooLarge(_) => StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+            EnclaveError::UpstreamTls { .. } => StatusCode::BAD_GATEWAY,
         };
-        let body = Json(json!({
-            "error": error_message,
-        }));
+        let body = Json(ErrorResponse {
+            error: self.to_string(),
+            tls_failure,
+        });
         (status, body).into_response()
     }
 }
 
+/// Body returned for any [EnclaveError].
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+pub struct ErrorResponse {
+    pub error: String,
+    /// Why the TLS handshake with the upstream failed, for
+    /// [EnclaveError::UpstreamTls].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls_failure: Option<TlsFailure>,
+}
+
 /// Enclave errors enum.
 #[derive(Debug)]
 pub enum EnclaveError {
     GenericError(String),
+    /// What the request asked for doesn't exist upstream, e.g. an unknown
+    /// location, see `MAP_UPSTREAM_ERRORS`.
+    NotFound(String),
+    /// The request's bearer token is missing or doesn't match the one a
+    /// protected route was configured with.
+    Unauthorized(String),
+    /// The request's headers exceed `MAX_HEADER_BYTES` or `MAX_HEADER_COUNT`.
+    HeadersTooLarge(String),
+    /// The TLS handshake with `host` failed. `detail` is the TLS library's
+    /// error, only set with `UPSTREAM_TLS_DETAIL` since it is logged anyway.
+    UpstreamTls {
+        host: String,
+        reason: TlsFailure,
+        detail: Option<String>,
+    },
+}
+
+impl std::fmt::Display for EnclaveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EnclaveError::GenericError(e)
+            | EnclaveError::NotFound(e)
+            | EnclaveError::Unauthorized(e)
+            | EnclaveError::HeadersTooLarge(e) => write!(f, "{}", e),
+            EnclaveError::UpstreamTls {
+                host,
+                reason,
+                detail,
+            } => {
+                write!(f, "TLS handshake with {} failed: {}", host, reason)?;
+                match detail {
+                    Some(detail) => write!(f, " ({})", detail),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
 }
+
+impl std::error::Error for EnclaveError {}
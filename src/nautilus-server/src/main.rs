@@ -2,45 +2,90 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use anyhow::Result;
-use axum::{routing::get, routing::post, Router};
-use fastcrypto::{ed25519::Ed25519KeyPair, traits::KeyPair};
-use nautilus_server::app::process_data;
-use nautilus_server::common::{get_attestation, health_check};
+use nautilus_server::api_keys::ApiKeys;
+use nautilus_server::app::serde_fixture;
+use nautilus_server::common::{check_bcs_golden, init_keypair};
+use nautilus_server::config::Config;
+use nautilus_server::header_limits::HeaderLimits;
+use nautilus_server::reattest::spawn_reattestation;
+use nautilus_server::reseed::spawn_reseeding;
+use nautilus_server::routes::{router, serve};
+use nautilus_server::webhook::spawn_health_webhook;
 use nautilus_server::AppState;
 use std::sync::Arc;
-use tower_http::cors::{Any, CorsLayer};
 use tracing::info;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let eph_kp = Ed25519KeyPair::generate(&mut rand::thread_rng());
+    init_tracing()?;
+    let config = Config::from_env()?;
+    if let Some(expected_hex) = &config.bcs_golden_hex {
+        if let Err(e) = check_bcs_golden(&serde_fixture(), expected_hex) {
+            info!("BCS golden value check failed: {}", e);
+            return Err(e.into());
+        }
+        info!("BCS golden value check passed");
+    }
+    let eph_kp = init_keypair(&config)?;
 
     // This value can be stored with secret-manager. To do that, follow the prompt `sh configure_enclave.sh`
     // Answer `y` to `Do you want to use a secret?` and finish.
     // Then uncomment this code instead to fetch from env var API_KEY, which is fetched from secret manager.
-    let api_key = std::env::var("API_KEY").expect("API_KEY must be set");
-    // let api_key = "045a27812dbe456392913223221306".to_string();
+    // To spread requests over several keys, set API_KEYS instead (`key,...` or
+    // `tenant=key,...`) and choose how each request picks one with API_KEY_POLICY.
+    let api_keys = match std::env::var("API_KEYS") {
+        Ok(keys) => ApiKeys::parse(&keys, config.api_key_policy)?,
+        Err(_) => {
+            let api_key = std::env::var("API_KEY").unwrap_or_else(|_| {
+                // Mock readings are signed without querying the weather API
+                #[cfg(feature = "mock-weather")]
+                if config.mock_weather.is_some() {
+                    return String::new();
+                }
+                panic!("API_KEY must be set")
+            });
+            // let api_key = "045a27812dbe456392913223221306".to_string();
+            ApiKeys::single(api_key)
+        }
+    };
 
-    let state = Arc::new(AppState { eph_kp, api_key });
+    let state = Arc::new(AppState::new(eph_kp, api_keys, config)?);
+    spawn_health_webhook(state.clone());
+    spawn_reattestation(state.clone());
+    spawn_reseeding(&state.config);
 
-    // Define your own restricted CORS policy here if needed.
-    let cors = CorsLayer::new().allow_methods(Any).allow_headers(Any);
-
-    let app = Router::new()
-        .route("/", get(ping))
-        .route("/get_attestation", get(get_attestation))
-        .route("/process_data", post(process_data))
-        .route("/health_check", get(health_check))
-        .with_state(state)
-        .layer(cors);
+    let trailing_slash = state.config.trailing_slash;
+    let limits = HeaderLimits::from_config(&state.config);
+    #[cfg(feature = "mtls")]
+    let mtls = state.config.mtls.clone();
+    let app = router(state);
 
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await?;
     info!("listening on {}", listener.local_addr().unwrap());
-    axum::serve(listener, app.into_make_service())
+    #[cfg(feature = "mtls")]
+    if let Some(mtls) = mtls {
+        info!("requiring client certificates");
+        return nautilus_server::mtls::serve(listener, app, trailing_slash, limits, mtls)
+            .await
+            .map_err(|e| anyhow::anyhow!("Server error: {}", e));
+    }
+    serve(listener, app, trailing_slash, limits)
         .await
         .map_err(|e| anyhow::anyhow!("Server error: {}", e))
 }
 
-async fn ping() -> &'static str {
-    "Pong!"
+// Log to stdout, which init copies to the console and to its log sink, as
+// plain text or with LOG_FORMAT=json as one JSON object per line. Colors are
+// left out since the console is a serial port.
+fn init_tracing() -> Result<()> {
+    let subscriber = tracing_subscriber::fmt().with_ansi(false);
+    match std::env::var("LOG_FORMAT").as_deref() {
+        Ok("json") => subscriber.json().init(),
+        Ok("text") | Err(_) => subscriber.init(),
+        Ok(other) => anyhow::bail!(
+            "Invalid LOG_FORMAT: expected `text` or `json`, got `{}`",
+            other
+        ),
+    }
+    Ok(())
 }
@@ -0,0 +1,57 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::config::env_or;
+use crate::EnclaveError;
+use serde_json::{json, Value};
+
+/// Fake readings signed by `/process_data` in place of the weather API's,
+/// for developing clients without an API key or network access. Responses
+/// are signed by the enclave key as usual, so verification can be exercised
+/// end to end. Only built with the `mock-weather` feature, and refused
+/// inside an enclave.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MockWeather {
+    /// Location name signed, or the requested location when unset.
+    /// Env: `MOCK_WEATHER_LOCATION`.
+    pub location: Option<String>,
+    /// Temperature signed, in degrees Celsius. Env:
+    /// `MOCK_WEATHER_TEMPERATURE`.
+    pub temperature: u64,
+}
+
+impl Default for MockWeather {
+    fn default() -> Self {
+        Self {
+            location: None,
+            temperature: 20,
+        }
+    }
+}
+
+impl MockWeather {
+    /// Read the mock readings, or None unless `MOCK_WEATHER` is true.
+    pub fn from_env() -> Result<Option<Self>, EnclaveError> {
+        if !env_or("MOCK_WEATHER", false)? {
+            return Ok(None);
+        }
+        let default = Self::default();
+        Ok(Some(Self {
+            location: std::env::var("MOCK_WEATHER_LOCATION")
+                .ok()
+                .filter(|location| !location.is_empty()),
+            temperature: env_or("MOCK_WEATHER_TEMPERATURE", default.temperature)?,
+        }))
+    }
+
+    /// The weather API response this stands for when `location` is
+    /// requested, last updated at `now_secs` so it's never too old to sign.
+    /// It's read like a real response, so `SIGNED_FIELDS` and
+    /// `TEMPERATURE_PARSING` apply.
+    pub fn response(&self, location: &str, now_secs: u64) -> Value {
+        json!({
+            "location": {"name": self.location.as_deref().unwrap_or(location)},
+            "current": {"temp_c": self.temperature, "last_updated_epoch": now_secs},
+        })
+    }
+}
@@ -0,0 +1,320 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::header_limits::HeaderLimits;
+use crate::routes::{accept, TrailingSlash};
+use crate::EnclaveError;
+use axum::extract::Request;
+use axum::Router;
+use fastcrypto::encoding::{Encoding, Hex};
+use hyper::body::Incoming;
+use hyper_util::rt::TokioIo;
+use rustls::pki_types::CertificateDer;
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig};
+use sha2::{Digest, Sha256};
+use std::fmt::Display;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::time::timeout;
+use tokio_rustls::TlsAcceptor;
+use tower::Service;
+use tower_http::normalize_path::NormalizePath;
+use tracing::{info, warn};
+
+/// How long a client may take to complete the TLS handshake, so a stalled
+/// one doesn't hold its connection open.
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Identity of the client on an mTLS connection, added to each of its
+/// requests as an extension. Handlers can take
+/// `Option<Extension<ClientIdentity>>` to log or authorize the caller.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientIdentity {
+    /// Hex encoded SHA-256 of the client's leaf certificate (DER).
+    pub fingerprint: String,
+}
+
+impl ClientIdentity {
+    fn from_certificate(cert: &CertificateDer) -> Self {
+        Self {
+            fingerprint: Hex::encode(Sha256::digest(cert.as_ref())),
+        }
+    }
+}
+
+/// TLS listener configuration that requires every client to present a
+/// certificate issued by a trusted CA. Connections without one fail the
+/// handshake, so no request from them reaches the router.
+#[derive(Debug, Clone)]
+pub struct Mtls {
+    config: Arc<ServerConfig>,
+}
+
+impl Mtls {
+    /// Read the configuration from `TLS_CLIENT_CA_PATH`, the PEM bundle of
+    /// trusted client CAs, and `TLS_CERT_PATH` and `TLS_KEY_PATH`, the PEM
+    /// certificate chain and private key the enclave serves. None when
+    /// `TLS_CLIENT_CA_PATH` is unset.
+    pub fn from_env() -> Result<Option<Self>, EnclaveError> {
+        if std::env::var("TLS_CLIENT_CA_PATH").is_err() {
+            return Ok(None);
+        }
+        let read = |name: &str| {
+            let path = std::env::var(name).map_err(|_| {
+                EnclaveError::GenericError(format!("{} must be set with TLS_CLIENT_CA_PATH", name))
+            })?;
+            std::fs::read(&path).map_err(|e| {
+                EnclaveError::GenericError(format!("Failed to read {} {}: {}", name, path, e))
+            })
+        };
+        Self::from_pem(
+            &read("TLS_CERT_PATH")?,
+            &read("TLS_KEY_PATH")?,
+            &read("TLS_CLIENT_CA_PATH")?,
+        )
+        .map(Some)
+    }
+
+    pub fn from_pem(
+        cert_pem: &[u8],
+        key_pem: &[u8],
+        client_ca_pem: &[u8],
+    ) -> Result<Self, EnclaveError> {
+        let invalid = |name: &str, e: &dyn Display| {
+            EnclaveError::GenericError(format!("Invalid {}: {}", name, e))
+        };
+        let certs = rustls_pemfile::certs(&mut &cert_pem[..])
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| invalid("TLS_CERT_PATH", &e))?;
+        let key = rustls_pemfile::private_key(&mut &key_pem[..])
+            .map_err(|e| invalid("TLS_KEY_PATH", &e))?
+            .ok_or_else(|| invalid("TLS_KEY_PATH", &"no private key"))?;
+        let mut roots = RootCertStore::empty();
+        for ca in rustls_pemfile::certs(&mut &client_ca_pem[..]) {
+            let ca = ca.map_err(|e| invalid("TLS_CLIENT_CA_PATH", &e))?;
+            roots
+                .add(ca)
+                .map_err(|e| invalid("TLS_CLIENT_CA_PATH", &e))?;
+        }
+        if roots.is_empty() {
+            return Err(invalid("TLS_CLIENT_CA_PATH", &"no certificates"));
+        }
+
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let verifier =
+            WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+                .build()
+                .map_err(|e| invalid("TLS_CLIENT_CA_PATH", &e))?;
+        let config = ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(|e| invalid("TLS configuration", &e))?
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(certs, key)
+            .map_err(|e| invalid("TLS_CERT_PATH", &e))?;
+        Ok(Self {
+            config: Arc::new(config),
+        })
+    }
+}
+
+/// Serve `app` on `listener` over TLS, requiring client certificates as set
+/// up in `mtls`. Each request carries the client's [ClientIdentity].
+/// Rejected handshakes are logged and don't affect other connections.
+/// Request headers are bounded by `limits` over both HTTP/1.1 and HTTP/2,
+/// see [HeaderLimits::auto].
+pub async fn serve(
+    listener: TcpListener,
+    app: Router,
+    trailing_slash: TrailingSlash,
+    limits: HeaderLimits,
+    mtls: Mtls,
+) -> std::io::Result<()> {
+    let acceptor = TlsAcceptor::from(mtls.config);
+    loop {
+        let (stream, peer) = accept(&listener).await;
+        let acceptor = acceptor.clone();
+        let app = app.clone();
+        tokio::spawn(async move {
+            let stream = match timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                Ok(Ok(stream)) => stream,
+                Ok(Err(e)) => {
+                    warn!("Rejected TLS connection from {}: {}", peer, e);
+                    return;
+                }
+                Err(_) => {
+                    warn!("TLS handshake with {} timed out", peer);
+                    return;
+                }
+            };
+            // The verifier requires a certificate, so there is always one
+            let identity = stream
+                .get_ref()
+                .1
+                .peer_certificates()
+                .and_then(|certs| certs.first())
+                .map(ClientIdentity::from_certificate);
+            if let Some(identity) = &identity {
+                info!(
+                    "mTLS client {} connected from {}",
+                    identity.fingerprint, peer
+                );
+            }
+            let service = hyper::service::service_fn(move |mut request: Request<Incoming>| {
+                if let Some(identity) = &identity {
+                    request.extensions_mut().insert(identity.clone());
+                }
+                let mut app = app.clone();
+                async move {
+                    match trailing_slash {
+                        TrailingSlash::Strict => app.call(request).await,
+                        TrailingSlash::Lenient => {
+                            NormalizePath::trim_trailing_slash(app).call(request).await
+                        }
+                    }
+                }
+            });
+            if let Err(e) = limits
+                .auto()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                warn!("Connection from {} failed: {}", peer, e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::routing::get;
+    use axum::Extension;
+    use rcgen::{
+        BasicConstraints, Certificate, CertificateParams, ExtendedKeyUsagePurpose, IsCa, KeyPair,
+    };
+    use rustls::pki_types::{PrivateKeyDer, ServerName};
+    use rustls::ClientConfig;
+    use std::net::SocketAddr;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+    use tokio_rustls::TlsConnector;
+
+    fn ca() -> (Certificate, KeyPair) {
+        let key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        (params.self_signed(&key).unwrap(), key)
+    }
+
+    fn leaf(
+        name: &str,
+        usage: ExtendedKeyUsagePurpose,
+        (ca, ca_key): &(Certificate, KeyPair),
+    ) -> (Certificate, KeyPair) {
+        let key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(vec![name.to_string()]).unwrap();
+        params.extended_key_usages = vec![usage];
+        (params.signed_by(&key, ca, ca_key).unwrap(), key)
+    }
+
+    async fn whoami(identity: Option<Extension<ClientIdentity>>) -> String {
+        identity.map_or("anonymous".to_string(), |Extension(identity)| {
+            identity.fingerprint
+        })
+    }
+
+    /// `GET /whoami` with `client`, returning the raw HTTP response, or
+    /// whatever was read before the connection failed.
+    async fn get_whoami(
+        addr: SocketAddr,
+        server_ca: &Certificate,
+        (client, client_key): &(Certificate, KeyPair),
+    ) -> String {
+        let mut roots = RootCertStore::empty();
+        roots.add(server_ca.der().clone()).unwrap();
+        let config =
+            ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_root_certificates(roots)
+                .with_client_auth_cert(
+                    vec![client.der().clone()],
+                    PrivateKeyDer::Pkcs8(client_key.serialize_der().into()),
+                )
+                .unwrap();
+        let tcp = TcpStream::connect(addr).await.unwrap();
+        let server_name = ServerName::try_from("localhost").unwrap();
+        let mut tls = match TlsConnector::from(Arc::new(config))
+            .connect(server_name, tcp)
+            .await
+        {
+            Ok(tls) => tls,
+            Err(_) => return String::new(),
+        };
+        let request = b"GET /whoami HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+        if tls.write_all(request).await.is_err() {
+            return String::new();
+        }
+        let mut response = Vec::new();
+        let mut buf = [0u8; 4096];
+        loop {
+            match tls.read(&mut buf).await {
+                Ok(0) | Err(_) => break,
+                Ok(n) => response.extend_from_slice(&buf[..n]),
+            }
+        }
+        String::from_utf8_lossy(&response).into_owned()
+    }
+
+    #[tokio::test]
+    async fn test_client_certificates() {
+        let server_ca = ca();
+        let client_ca = ca();
+        let (server, server_key) =
+            leaf("localhost", ExtendedKeyUsagePurpose::ServerAuth, &server_ca);
+        let mtls = Mtls::from_pem(
+            server.pem().as_bytes(),
+            server_key.serialize_pem().as_bytes(),
+            client_ca.0.pem().as_bytes(),
+        )
+        .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = Router::new().route("/whoami", get(whoami));
+        let limits = HeaderLimits::from_config(&crate::config::Config::default());
+        tokio::spawn(serve(listener, app, TrailingSlash::Strict, limits, mtls));
+
+        // A client certificate from the trusted CA is accepted and identified
+        let trusted = leaf("client", ExtendedKeyUsagePurpose::ClientAuth, &client_ca);
+        let response = get_whoami(addr, &server_ca.0, &trusted).await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        let fingerprint = Hex::encode(Sha256::digest(trusted.0.der().as_ref()));
+        assert!(response.ends_with(&fingerprint), "{}", response);
+
+        // One from another CA never reaches the router
+        let untrusted = leaf("client", ExtendedKeyUsagePurpose::ClientAuth, &ca());
+        let response = get_whoami(addr, &server_ca.0, &untrusted).await;
+        assert!(!response.contains("HTTP/1.1"), "{}", response);
+    }
+
+    #[test]
+    fn test_invalid_pem() {
+        let server_ca = ca();
+        let (server, server_key) =
+            leaf("localhost", ExtendedKeyUsagePurpose::ServerAuth, &server_ca);
+        let cert = server.pem();
+        let key = server_key.serialize_pem();
+        let err = |cert: &str, key: &str, ca: &str| match Mtls::from_pem(
+            cert.as_bytes(),
+            key.as_bytes(),
+            ca.as_bytes(),
+        ) {
+            Ok(_) => panic!("should be rejected"),
+            Err(e) => e.to_string(),
+        };
+        assert!(err(&cert, &key, "").contains("TLS_CLIENT_CA_PATH"));
+        assert!(err(&cert, "", &server_ca.0.pem()).contains("TLS_KEY_PATH"));
+    }
+}
@@ -0,0 +1,121 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::EnclaveError;
+use sha2::{Digest, Sha256};
+use std::collections::{HashSet, VecDeque};
+use std::str::FromStr;
+use std::sync::Mutex;
+use tracing::warn;
+
+/// What happens when an attestation nonce is seen again within the window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NonceReuse {
+    /// Log the reuse and serve the attestation anyway.
+    Warn,
+    /// Refuse the request.
+    Reject,
+}
+
+impl FromStr for NonceReuse {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "warn" => Ok(Self::Warn),
+            "reject" => Ok(Self::Reject),
+            _ => Err(format!("expected `warn` or `reject`, got `{}`", s)),
+        }
+    }
+}
+
+/// The most recent nonces passed to `/get_attestation`, to catch clients that
+/// reuse one by mistake and so lose the freshness it should prove. Only the
+/// SHA-256 of the last `window` nonces is kept, in memory; older ones are
+/// forgotten. A zero window disables the check.
+pub struct NonceTracker {
+    window: usize,
+    policy: NonceReuse,
+    seen: Mutex<Seen>,
+}
+
+#[derive(Default)]
+struct Seen {
+    order: VecDeque<[u8; 32]>,
+    set: HashSet<[u8; 32]>,
+}
+
+impl NonceTracker {
+    pub fn new(window: usize, policy: NonceReuse) -> Self {
+        Self {
+            window,
+            policy,
+            seen: Mutex::new(Seen::default()),
+        }
+    }
+
+    /// Record `nonce`, failing under [NonceReuse::Reject] if it is already in
+    /// the window.
+    pub fn check(&self, nonce: &[u8]) -> Result<(), EnclaveError> {
+        if self.window == 0 {
+            return Ok(());
+        }
+        let digest: [u8; 32] = Sha256::digest(nonce).into();
+        let mut seen = self.seen.lock().unwrap();
+        if seen.set.contains(&digest) {
+            return match self.policy {
+                NonceReuse::Warn => {
+                    warn!(
+                        "Attestation nonce reused within the last {} requests",
+                        self.window
+                    );
+                    Ok(())
+                }
+                NonceReuse::Reject => Err(EnclaveError::GenericError(
+                    "Attestation nonce was already used, send a fresh one".to_string(),
+                )),
+            };
+        }
+        if seen.order.len() == self.window {
+            if let Some(oldest) = seen.order.pop_front() {
+                seen.set.remove(&oldest);
+            }
+        }
+        seen.order.push_back(digest);
+        seen.set.insert(digest);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_repeated_nonce() {
+        let warn = NonceTracker::new(2, NonceReuse::Warn);
+        assert!(warn.check(b"n1").is_ok());
+        assert!(warn.check(b"n1").is_ok());
+
+        let reject = NonceTracker::new(2, NonceReuse::Reject);
+        assert!(reject.check(b"n1").is_ok());
+        assert!(reject.check(b"n2").is_ok());
+        assert!(reject.check(b"n1").is_err());
+        assert!(reject.check(b"n2").is_err());
+    }
+
+    #[test]
+    fn test_window_forgets_old_nonces() {
+        let tracker = NonceTracker::new(2, NonceReuse::Reject);
+        for nonce in [b"n1", b"n2", b"n3"] {
+            tracker.check(nonce).unwrap();
+        }
+        assert!(tracker.check(b"n1").is_ok());
+        assert!(tracker.check(b"n3").is_err());
+        assert_eq!(tracker.seen.lock().unwrap().set.len(), 2);
+
+        let disabled = NonceTracker::new(0, NonceReuse::Reject);
+        assert!(disabled.check(b"n1").is_ok());
+        assert!(disabled.check(b"n1").is_ok());
+    }
+}
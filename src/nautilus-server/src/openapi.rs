@@ -0,0 +1,658 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::api_keys::{RotateApiKeyRequest, RotateApiKeyResponse, TENANT_HEADER};
+use crate::api_version::{API_VERSION_HEADER, CURRENT_API_VERSION, SUPPORTED_API_VERSIONS};
+use crate::app::{
+    BatchWeatherRequest, WeatherFields, WeatherPayload, WeatherRequest, WeatherResponse,
+    WeatherResponseWithAttestationDigest, WeatherResponseWithUpstreamLatency, MAX_BATCH_SIZE,
+};
+use crate::common::{
+    AllowedEndpointsResponse, AttestationFormat, GetAttestationResponse, HealthCheckResponse,
+    IntentMessage, IntentScope, ProcessDataRequest, ProcessedDataResponse, GENERATED_AT_HEADER,
+    MAX_NONCE_LEN,
+};
+use crate::github::{GithubCommit, GithubCommitRequest};
+use crate::pcrs::ExpectedPcrsResponse;
+use crate::pem::PEM_CONTENT_TYPE;
+use crate::price::{PriceRequest, PriceResponse};
+use crate::reattest::PeriodicAttestationResponse;
+use crate::schema::TypedIntentMessage;
+use crate::stats::StatsResponse;
+use crate::transaction::{TransactionApproval, TransactionRequest};
+use crate::ErrorResponse;
+use axum::Json;
+use nautilus_verifier::compression::CompressedPayload;
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::schema::{InstanceType, Schema, SchemaObject};
+use schemars::JsonSchema;
+use serde_json::{json, Map, Value};
+
+/// Endpoint that returns the OpenAPI document for this server.
+pub async fn openapi_spec() -> Json<Value> {
+    Json(spec())
+}
+
+/// Endpoint that returns the JSON Schema of every request and response body.
+pub async fn json_schemas() -> Json<Value> {
+    Json(schemas())
+}
+
+/// Standalone JSON Schema (draft 7) documents of the request and response
+/// bodies, keyed by schema name, for clients that generate code without an
+/// OpenAPI toolchain. Generic types are listed once per payload they carry,
+/// and each document's `$id` depends only on its name, see [schema_id].
+pub fn schemas() -> Value {
+    let mut schemas = Map::new();
+    add_schema::<ProcessDataRequest<WeatherRequest>>(&mut schemas);
+    add_schema::<ProcessDataRequest<BatchWeatherRequest>>(&mut schemas);
+    add_schema::<ProcessDataRequest<PriceRequest>>(&mut schemas);
+    add_schema::<ProcessDataRequest<GithubCommitRequest>>(&mut schemas);
+    add_schema::<ProcessDataRequest<TransactionRequest>>(&mut schemas);
+    add_schema::<ProcessDataRequest<Value>>(&mut schemas);
+    add_schema::<IntentMessage<WeatherResponse>>(&mut schemas);
+    add_schema::<IntentMessage<WeatherResponseWithAttestationDigest>>(&mut schemas);
+    add_schema::<IntentMessage<WeatherResponseWithUpstreamLatency>>(&mut schemas);
+    add_schema::<IntentMessage<WeatherFields>>(&mut schemas);
+    add_schema::<IntentMessage<PriceResponse>>(&mut schemas);
+    add_schema::<IntentMessage<CompressedPayload>>(&mut schemas);
+    add_schema::<IntentMessage<GithubCommit>>(&mut schemas);
+    add_schema::<IntentMessage<TransactionApproval>>(&mut schemas);
+    add_schema::<ProcessedDataResponse<IntentMessage<WeatherPayload>>>(&mut schemas);
+    add_schema::<Vec<ProcessedDataResponse<IntentMessage<WeatherPayload>>>>(&mut schemas);
+    add_schema::<ProcessedDataResponse<IntentMessage<PriceResponse>>>(&mut schemas);
+    add_schema::<ProcessedDataResponse<IntentMessage<GithubCommit>>>(&mut schemas);
+    add_schema::<ProcessedDataResponse<IntentMessage<TransactionApproval>>>(&mut schemas);
+    add_schema::<ProcessedDataResponse<TypedIntentMessage>>(&mut schemas);
+    add_schema::<GetAttestationResponse>(&mut schemas);
+    add_schema::<HealthCheckResponse>(&mut schemas);
+    add_schema::<ExpectedPcrsResponse>(&mut schemas);
+    add_schema::<AllowedEndpointsResponse>(&mut schemas);
+    add_schema::<PeriodicAttestationResponse>(&mut schemas);
+    add_schema::<RotateApiKeyRequest>(&mut schemas);
+    add_schema::<RotateApiKeyResponse>(&mut schemas);
+    add_schema::<StatsResponse>(&mut schemas);
+    add_schema::<ErrorResponse>(&mut schemas);
+    Value::Object(schemas)
+}
+
+/// `$id` of the schema named `name` in [schemas].
+pub fn schema_id(name: &str) -> String {
+    format!("{}/schemas/{}.json", env!("CARGO_PKG_REPOSITORY"), name)
+}
+
+fn add_schema<T: JsonSchema>(schemas: &mut Map<String, Value>) {
+    let name = T::schema_name();
+    let root = SchemaSettings::draft07()
+        .into_generator()
+        .into_root_schema_for::<T>();
+    let mut schema = serde_json::to_value(root).expect("should not fail");
+    schema["$id"] = json!(schema_id(&name));
+    schemas.insert(name, schema);
+}
+
+/// Build the OpenAPI 3.0 document describing the server's routes. Schemas are
+/// generated from the request and response types, so they follow any changes
+/// to `app.rs`; a route added in `main.rs` must also be listed here.
+pub fn spec() -> Value {
+    let mut gen = SchemaSettings::openapi3().into_generator();
+    let error = json_response("Error", gen.subschema_for::<ErrorResponse>());
+
+    let mut paths = Map::new();
+    paths.insert(
+        "/".to_string(),
+        operation(
+            "get",
+            "ping",
+            "Liveness probe",
+            None,
+            json!({
+                "200": {
+                    "description": "Server is up",
+                    "content": { "text/plain": { "schema": { "type": "string" } } },
+                },
+            }),
+        ),
+    );
+    let mut get_attestation = operation(
+        "get",
+        "get_attestation",
+        "Attestation document committed to the enclave's public key",
+        None,
+        json!({
+            "200": {
+                "description": "Attestation document, hex encoded in JSON or as PEM with its certificate chain",
+                "headers": {
+                    GENERATED_AT_HEADER: {
+                        "description": "When the document was generated, in PEM responses",
+                        "schema": { "type": "integer" },
+                    },
+                },
+                "content": {
+                    "application/json": {
+                        "schema": gen.subschema_for::<GetAttestationResponse>(),
+                    },
+                    PEM_CONTENT_TYPE: { "schema": { "type": "string" } },
+                },
+            },
+            "400": error,
+        }),
+    );
+    get_attestation["get"]["parameters"] = json!([
+        {
+            "name": "nonce",
+            "in": "query",
+            "description": format!(
+                "Hex encoded nonce, at most {} bytes, included in a newly generated document",
+                MAX_NONCE_LEN
+            ),
+            "required": false,
+            "schema": { "type": "string" },
+        },
+        {
+            "name": "format",
+            "in": "query",
+            "description": "Encoding of the document. PEM can also be asked for with Accept",
+            "required": false,
+            "schema": gen.subschema_for::<AttestationFormat>(),
+        },
+    ]);
+    paths.insert("/get_attestation".to_string(), get_attestation);
+    let request = gen.subschema_for::<ProcessDataRequest<WeatherRequest>>();
+    let mut process_data = operation(
+        "post",
+        "process_data",
+        "Fetch and sign weather data",
+        Some(json!({
+            "required": true,
+            "content": { "application/json": { "schema": request } },
+        })),
+        json!({
+            "200": json_response(
+                "Signed intent message and its hex encoded Ed25519 signature",
+                gen.subschema_for::<ProcessedDataResponse<IntentMessage<WeatherPayload>>>(),
+            ),
+            "400": error,
+            "404": json_response(
+                "Unknown location, with MAP_UPSTREAM_ERRORS",
+                gen.subschema_for::<ErrorResponse>(),
+            ),
+        }),
+    );
+    let tenant = json!({
+        "name": TENANT_HEADER,
+        "in": "header",
+        "description": "Tenant whose upstream API key is used, when API_KEY_POLICY is `tenant`",
+        "required": false,
+        "schema": { "type": "string" },
+    });
+    process_data["post"]["parameters"] = json!([tenant.clone()]);
+    let get_process_data = operation(
+        "get",
+        "process_data_get",
+        "Fetch and sign weather data, with the request in the query. Only served when PROCESS_DATA_GET is set",
+        None,
+        process_data["post"]["responses"].clone(),
+    );
+    process_data["get"] = get_process_data["get"].clone();
+    process_data["get"]["parameters"] = json!([
+        {
+            "name": "location",
+            "in": "query",
+            "required": true,
+            "schema": { "type": "string" },
+        },
+        {
+            "name": "lang",
+            "in": "query",
+            "description": "Language of the location name",
+            "required": false,
+            "schema": { "type": "string" },
+        },
+        {
+            "name": "intent",
+            "in": "query",
+            "description": "Intent scope to sign under",
+            "required": false,
+            "schema": gen.subschema_for::<IntentScope>(),
+        },
+        tenant.clone(),
+    ]);
+    paths.insert("/process_data".to_string(), process_data);
+    let request = gen.subschema_for::<ProcessDataRequest<BatchWeatherRequest>>();
+    let mut process_data_batch = operation(
+        "post",
+        "process_data_batch",
+        &format!(
+            "Fetch and sign weather data for up to {} requests",
+            MAX_BATCH_SIZE
+        ),
+        Some(json!({
+            "required": true,
+            "content": { "application/json": { "schema": request } },
+        })),
+        json!({
+            "200": json_response(
+                "Signed intent messages, in request order",
+                gen.subschema_for::<Vec<ProcessedDataResponse<IntentMessage<WeatherPayload>>>>(),
+            ),
+            "400": error,
+        }),
+    );
+    process_data_batch["post"]["parameters"] = json!([tenant]);
+    paths.insert("/process_data_batch".to_string(), process_data_batch);
+    paths.insert(
+        "/health_check".to_string(),
+        operation(
+            "get",
+            "health_check",
+            "Public key and connectivity to allowed endpoints",
+            None,
+            json!({
+                "200": json_response("Health status", gen.subschema_for::<HealthCheckResponse>()),
+                "400": error,
+                "503": json_response(
+                    "NSM unavailable, with HEALTH_REQUIRE_NSM",
+                    gen.subschema_for::<HealthCheckResponse>(),
+                ),
+            }),
+        ),
+    );
+    paths.insert(
+        "/expected_pcrs".to_string(),
+        operation(
+            "get",
+            "expected_pcrs",
+            "PCRs the enclave image is expected to measure, from EXPECTED_PCRS",
+            None,
+            json!({
+                "200": json_response(
+                    "Hex encoded expected PCRs and whether this enclave matches them",
+                    gen.subschema_for::<ExpectedPcrsResponse>(),
+                ),
+                "400": error,
+            }),
+        ),
+    );
+    paths.insert(
+        "/allowed_endpoints".to_string(),
+        operation(
+            "get",
+            "allowed_endpoints",
+            "The allowed_endpoints.yaml read at startup and its SHA-256",
+            None,
+            json!({
+                "200": json_response(
+                    "Allowed endpoints config, its digest and whether attestations carry it",
+                    gen.subschema_for::<AllowedEndpointsResponse>(),
+                ),
+                "400": error,
+            }),
+        ),
+    );
+    let request = gen.subschema_for::<ProcessDataRequest<Value>>();
+    let mut sign_typed_data = operation(
+        "post",
+        "sign_typed_data",
+        "Validate a payload against a registered schema and sign it",
+        Some(json!({
+            "required": true,
+            "content": { "application/json": { "schema": request } },
+        })),
+        json!({
+            "200": json_response(
+                "Signed intent message and its hex encoded Ed25519 signature",
+                gen.subschema_for::<ProcessedDataResponse<TypedIntentMessage>>(),
+            ),
+            "400": error,
+        }),
+    );
+    sign_typed_data["post"]["parameters"] = json!([{
+        "name": "schema",
+        "in": "path",
+        "required": true,
+        "schema": { "type": "string" },
+    }]);
+    paths.insert("/sign/{schema}".to_string(), sign_typed_data);
+    let request = gen.subschema_for::<ProcessDataRequest<PriceRequest>>();
+    paths.insert(
+        "/process_price".to_string(),
+        operation(
+            "post",
+            "process_price",
+            "Fetch and sign an exchange rate. Only served when PRICE_URL is set",
+            Some(json!({
+                "required": true,
+                "content": { "application/json": { "schema": request } },
+            })),
+            json!({
+                "200": json_response(
+                    "Signed rate with explicit decimals and its hex encoded Ed25519 signature",
+                    gen.subschema_for::<ProcessedDataResponse<IntentMessage<PriceResponse>>>(),
+                ),
+                "400": error,
+            }),
+        ),
+    );
+    let request = gen.subschema_for::<ProcessDataRequest<GithubCommitRequest>>();
+    paths.insert(
+        "/process_github_commit".to_string(),
+        operation(
+            "post",
+            "process_github_commit",
+            "Fetch and sign a GitHub commit's SHA, author and date. Only served with the github feature and GITHUB_TOKEN set",
+            Some(json!({
+                "required": true,
+                "content": { "application/json": { "schema": request } },
+            })),
+            json!({
+                "200": json_response(
+                    "Signed commit and its hex encoded Ed25519 signature",
+                    gen.subschema_for::<ProcessedDataResponse<IntentMessage<GithubCommit>>>(),
+                ),
+                "400": error,
+                "404": json_response(
+                    "No such commit, or a repository the token can't read",
+                    gen.subschema_for::<ErrorResponse>(),
+                ),
+            }),
+        ),
+    );
+    let request = gen.subschema_for::<ProcessDataRequest<TransactionRequest>>();
+    paths.insert(
+        "/process_transaction".to_string(),
+        operation(
+            "post",
+            "process_transaction",
+            "Check Sui transaction bytes against the TRANSACTION_* policy and sign them. Only served with the sui-transactions feature and SIGN_TRANSACTIONS=true",
+            Some(json!({
+                "required": true,
+                "content": { "application/json": { "schema": request } },
+            })),
+            json!({
+                "200": json_response(
+                    "Approved transaction bytes and their hex encoded Ed25519 signature",
+                    gen.subschema_for::<ProcessedDataResponse<IntentMessage<TransactionApproval>>>(),
+                ),
+                "400": json_response(
+                    "Not a programmable transaction, or one the policy refuses",
+                    gen.subschema_for::<ErrorResponse>(),
+                ),
+            }),
+        ),
+    );
+    let request = gen.subschema_for::<RotateApiKeyRequest>();
+    let mut rotate_api_key = operation(
+        "post",
+        "rotate_api_key",
+        "Replace an upstream API key after a test call with it succeeds. Only served when API_KEY_ROTATION_TOKEN is set",
+        Some(json!({
+            "required": true,
+            "content": { "application/json": { "schema": request } },
+        })),
+        json!({
+            "200": json_response(
+                "Fingerprint of the key now in use",
+                gen.subschema_for::<RotateApiKeyResponse>(),
+            ),
+            "400": error,
+            "401": json_response(
+                "Missing or wrong bearer token",
+                gen.subschema_for::<ErrorResponse>(),
+            ),
+        }),
+    );
+    rotate_api_key["post"]["parameters"] = json!([{
+        "name": "Authorization",
+        "in": "header",
+        "description": "`Bearer <API_KEY_ROTATION_TOKEN>`",
+        "required": true,
+        "schema": { "type": "string" },
+    }]);
+    paths.insert("/rotate_api_key".to_string(), rotate_api_key);
+    let mut stats = operation(
+        "get",
+        "stats",
+        "Counters since startup. Only served when ADMIN_TOKEN is set",
+        None,
+        json!({
+            "200": json_response(
+                "Cumulative counters, reset when the enclave restarts",
+                gen.subschema_for::<StatsResponse>(),
+            ),
+            "400": error,
+            "401": json_response(
+                "Missing or wrong bearer token",
+                gen.subschema_for::<ErrorResponse>(),
+            ),
+        }),
+    );
+    stats["get"]["parameters"] = json!([{
+        "name": "Authorization",
+        "in": "header",
+        "description": "`Bearer <ADMIN_TOKEN>`",
+        "required": true,
+        "schema": { "type": "string" },
+    }]);
+    paths.insert("/stats".to_string(), stats);
+    paths.insert(
+        "/periodic_attestation".to_string(),
+        operation(
+            "get",
+            "periodic_attestation",
+            "Latest attestation generated in the background. Only served when REATTEST_INTERVAL_SECS is set",
+            None,
+            json!({
+                "200": json_response(
+                    "Attestation, its digest and any change of public key or PCRs since startup",
+                    gen.subschema_for::<PeriodicAttestationResponse>(),
+                ),
+                "400": error,
+            }),
+        ),
+    );
+    paths.insert(
+        "/schemas".to_string(),
+        operation(
+            "get",
+            "json_schemas",
+            "JSON Schema documents of the request and response bodies",
+            None,
+            json!({
+                "200": {
+                    "description": "Draft 7 JSON Schema documents keyed by name, each with a stable `$id`",
+                    "content": { "application/json": { "schema": { "type": "object" } } },
+                },
+            }),
+        ),
+    );
+    paths.insert(
+        "/openapi.json".to_string(),
+        operation(
+            "get",
+            "openapi_spec",
+            "This document",
+            None,
+            json!({
+                "200": {
+                    "description": "OpenAPI document",
+                    "content": { "application/json": { "schema": { "type": "object" } } },
+                },
+            }),
+        ),
+    );
+
+    // Every route checks the declared API version
+    let api_version = json!({
+        "name": API_VERSION_HEADER,
+        "in": "header",
+        "description": format!(
+            "API version the client expects, {} when absent; other versions are rejected",
+            CURRENT_API_VERSION
+        ),
+        "required": false,
+        "schema": { "type": "integer", "enum": SUPPORTED_API_VERSIONS },
+    });
+    for operation in paths.values_mut().filter_map(Value::as_object_mut) {
+        for operation in operation.values_mut() {
+            match operation["parameters"].as_array_mut() {
+                Some(parameters) => parameters.push(api_version.clone()),
+                None => operation["parameters"] = json!([api_version.clone()]),
+            }
+        }
+    }
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": env!("CARGO_PKG_NAME"),
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths,
+        "components": { "schemas": gen.take_definitions() },
+    })
+}
+
+fn operation(
+    method: &str,
+    operation_id: &str,
+    summary: &str,
+    request_body: Option<Value>,
+    responses: Value,
+) -> Value {
+    let mut operation = json!({
+        "operationId": operation_id,
+        "summary": summary,
+        "responses": responses,
+    });
+    if let Some(request_body) = request_body {
+        operation["requestBody"] = request_body;
+    }
+    json!({ method: operation })
+}
+
+fn json_response(description: &str, schema: Schema) -> Value {
+    json!({
+        "description": description,
+        "content": { "application/json": { "schema": schema } },
+    })
+}
+
+/// Intent scopes are serialized as their `u8` discriminant.
+impl JsonSchema for IntentScope {
+    fn schema_name() -> String {
+        "IntentScope".to_string()
+    }
+
+    fn json_schema(_gen: &mut SchemaGenerator) -> Schema {
+        SchemaObject {
+            instance_type: Some(InstanceType::Integer.into()),
+            enum_values: Some(
+                IntentScope::ALL
+                    .iter()
+                    .map(|scope| json!(*scope as u8))
+                    .collect(),
+            ),
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn collect_refs(value: &Value, refs: &mut Vec<String>) {
+        match value {
+            Value::Object(map) => {
+                for (key, value) in map {
+                    match (key.as_str(), value) {
+                        ("$ref", Value::String(r)) => refs.push(r.clone()),
+                        _ => collect_refs(value, refs),
+                    }
+                }
+            }
+            Value::Array(values) => values.iter().for_each(|v| collect_refs(v, refs)),
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn test_spec_covers_routes() {
+        let spec = spec();
+        let paths = spec["paths"].as_object().unwrap();
+        for path in [
+            "/",
+            "/get_attestation",
+            "/process_data",
+            "/process_data_batch",
+            "/health_check",
+            "/expected_pcrs",
+            "/allowed_endpoints",
+            "/sign/{schema}",
+            "/process_price",
+            "/process_github_commit",
+            "/process_transaction",
+            "/rotate_api_key",
+            "/periodic_attestation",
+            "/stats",
+            "/schemas",
+            "/openapi.json",
+        ] {
+            assert!(paths.contains_key(path), "missing {}", path);
+            for operation in paths[path].as_object().unwrap().values() {
+                let parameters = operation["parameters"].as_array().unwrap();
+                assert!(parameters.iter().any(|p| p["name"] == API_VERSION_HEADER));
+            }
+        }
+    }
+
+    #[test]
+    fn test_schemas() {
+        let schemas = schemas();
+        let schemas = schemas.as_object().unwrap();
+        for name in [
+            <ProcessDataRequest<WeatherRequest>>::schema_name(),
+            <IntentMessage<WeatherResponse>>::schema_name(),
+            <ProcessedDataResponse<IntentMessage<PriceResponse>>>::schema_name(),
+            ErrorResponse::schema_name(),
+        ] {
+            assert!(schemas.contains_key(&name), "missing {}", name);
+        }
+        for (name, schema) in schemas {
+            assert_eq!(schema["$id"], schema_id(name));
+            assert_eq!(schema["$schema"], "http://json-schema.org/draft-07/schema#");
+            // Each document stands alone, so its references are local
+            let mut refs = Vec::new();
+            collect_refs(schema, &mut refs);
+            for r in refs {
+                let definition = r.strip_prefix("#/definitions/").unwrap();
+                assert!(schema["definitions"].get(definition).is_some(), "{}", r);
+            }
+            assert!(
+                jsonschema::JSONSchema::compile(schema).is_ok(),
+                "{} is not a valid schema",
+                name
+            );
+        }
+        // Regenerating gives the same documents
+        assert_eq!(Value::Object(schemas.clone()), super::schemas());
+    }
+
+    #[test]
+    fn test_spec_refs_resolve() {
+        let spec = spec();
+        let mut refs = Vec::new();
+        collect_refs(&spec, &mut refs);
+        assert!(!refs.is_empty());
+        for r in refs {
+            let name = r.strip_prefix("#/components/schemas/").unwrap();
+            assert!(
+                spec["components"]["schemas"].get(name).is_some(),
+                "unresolved {}",
+                r
+            );
+        }
+    }
+}
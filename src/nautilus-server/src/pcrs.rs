@@ -0,0 +1,155 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::AppState;
+use crate::EnclaveError;
+use axum::extract::State;
+use axum::Json;
+use fastcrypto::encoding::{Encoding, Hex};
+use nsm_api::api::{Request as NsmRequest, Response as NsmResponse};
+use nsm_api::driver;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Length of a SHA-384 PCR value.
+pub const PCR_LEN: usize = 48;
+
+/// PCR0, PCR1 and PCR2 the enclave image is expected to measure, as written
+/// to `out/nitro.pcrs` by the build.
+///
+/// These can't be compiled into the server: the server binary is part of the
+/// measured ramdisk, so embedding them would change the measurements they
+/// describe. They are supplied at runtime instead, in `EXPECTED_PCRS`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpectedPcrs {
+    pub pcrs: [Vec<u8>; 3],
+}
+
+impl FromStr for ExpectedPcrs {
+    type Err = String;
+
+    /// Parse `PCR0=<hex>,PCR1=<hex>,PCR2=<hex>`, in any order.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut pcrs: [Option<Vec<u8>>; 3] = Default::default();
+        for entry in s.split(',').map(str::trim) {
+            let (name, value) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("expected `PCR<n>=<hex>`, got `{}`", entry))?;
+            let index = match name {
+                "PCR0" => 0,
+                "PCR1" => 1,
+                "PCR2" => 2,
+                _ => return Err(format!("expected PCR0, PCR1 or PCR2, got `{}`", name)),
+            };
+            let value = Hex::decode(value)
+                .ok()
+                .filter(|value| value.len() == PCR_LEN)
+                .ok_or_else(|| format!("{} is not {} hex encoded bytes", name, PCR_LEN))?;
+            if pcrs[index].replace(value).is_some() {
+                return Err(format!("{} is set twice", name));
+            }
+        }
+        match pcrs {
+            [Some(pcr0), Some(pcr1), Some(pcr2)] => Ok(Self {
+                pcrs: [pcr0, pcr1, pcr2],
+            }),
+            _ => Err("expected PCR0, PCR1 and PCR2".to_string()),
+        }
+    }
+}
+
+/// Response for expected PCRs.
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+pub struct ExpectedPcrsResponse {
+    /// Hex encoded expected PCR0.
+    pub pcr0: String,
+    /// Hex encoded expected PCR1.
+    pub pcr1: String,
+    /// Hex encoded expected PCR2.
+    pub pcr2: String,
+    /// Whether this enclave measured the expected values. None when its PCRs
+    /// can't be read, e.g. outside an enclave.
+    pub matches: Option<bool>,
+}
+
+/// Endpoint that returns the PCRs the enclave image is expected to produce,
+/// from `EXPECTED_PCRS`, and whether the running enclave matches them. The
+/// expected values are supplied by the operator and are not attested, so
+/// verifiers should still check the PCRs in the attestation document
+/// against their own build.
+pub async fn expected_pcrs(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<ExpectedPcrsResponse>, EnclaveError> {
+    let expected = state.config.expected_pcrs.as_ref().ok_or_else(|| {
+        EnclaveError::GenericError("No expected PCRs configured, see EXPECTED_PCRS".to_string())
+    })?;
+    let matches = nsm_pcrs().ok().map(|measured| measured == expected.pcrs);
+    let [pcr0, pcr1, pcr2] = expected.pcrs.clone().map(Hex::encode);
+    Ok(Json(ExpectedPcrsResponse {
+        pcr0,
+        pcr1,
+        pcr2,
+        matches,
+    }))
+}
+
+/// Read PCR0, PCR1 and PCR2 from the NSM driver.
+fn nsm_pcrs() -> Result<[Vec<u8>; 3], EnclaveError> {
+    let fd = driver::nsm_init();
+    if fd < 0 {
+        return Err(EnclaveError::GenericError(
+            "Failed to open NSM device".to_string(),
+        ));
+    }
+    let mut pcrs: [Vec<u8>; 3] = Default::default();
+    for (index, pcr) in pcrs.iter_mut().enumerate() {
+        match driver::nsm_process_request(
+            fd,
+            NsmRequest::DescribePCR {
+                index: index as u16,
+            },
+        ) {
+            NsmResponse::DescribePCR { data, .. } => *pcr = data,
+            response => {
+                driver::nsm_exit(fd);
+                return Err(EnclaveError::GenericError(format!(
+                    "unexpected NSM response: {:?}",
+                    response
+                )));
+            }
+        }
+    }
+    driver::nsm_exit(fd);
+    Ok(pcrs)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_expected_pcrs() {
+        let (pcr0, pcr1, pcr2) = ("aa".repeat(48), "bb".repeat(48), "cc".repeat(48));
+        let expected = ExpectedPcrs {
+            pcrs: [vec![0xaa; 48], vec![0xbb; 48], vec![0xcc; 48]],
+        };
+        let value = format!("PCR0={},PCR1={},PCR2={}", pcr0, pcr1, pcr2);
+        assert_eq!(value.parse::<ExpectedPcrs>().unwrap(), expected);
+        let value = format!("PCR2={}, PCR0={}, PCR1={}", pcr2, pcr0, pcr1);
+        assert_eq!(value.parse::<ExpectedPcrs>().unwrap(), expected);
+
+        for invalid in [
+            format!("PCR0={},PCR1={}", pcr0, pcr1),
+            format!("PCR0={},PCR1={},PCR1={}", pcr0, pcr1, pcr1),
+            format!("PCR0={},PCR1={},PCR3={}", pcr0, pcr1, pcr2),
+            format!("PCR0={},PCR1={},PCR2={}", pcr0, pcr1, "cc".repeat(32)),
+            format!("PCR0={},PCR1={},PCR2=zz", pcr0, pcr1),
+            format!("{} PCR0", pcr0),
+            String::new(),
+        ] {
+            assert!(invalid.parse::<ExpectedPcrs>().is_err(), "{}", invalid);
+        }
+    }
+}
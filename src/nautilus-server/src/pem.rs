@@ -0,0 +1,167 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! PEM (RFC 7468) encoding of attestation documents and their certificate
+//! chain, for verifiers built on OpenSSL and similar tooling.
+
+use crate::attestation::AttestationDocument;
+use crate::EnclaveError;
+use fastcrypto::encoding::{Base64, Encoding};
+
+/// Label of the attestation document's block.
+pub const ATTESTATION_LABEL: &str = "ATTESTATION DOCUMENT";
+
+/// Label of each certificate's block.
+pub const CERTIFICATE_LABEL: &str = "CERTIFICATE";
+
+/// Content type of PEM responses.
+pub const PEM_CONTENT_TYPE: &str = "application/x-pem-file";
+
+/// Width of the base64 lines, as RFC 7468 requires of generators.
+const LINE_LEN: usize = 64;
+
+/// A decoded PEM block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PemBlock {
+    pub label: String,
+    pub contents: Vec<u8>,
+}
+
+/// `contents` as a PEM block labelled `label`.
+pub fn encode(label: &str, contents: &[u8]) -> String {
+    let base64 = Base64::encode(contents);
+    let mut pem = format!("-----BEGIN {}-----\n", label);
+    for line in base64.as_bytes().chunks(LINE_LEN) {
+        pem.push_str(std::str::from_utf8(line).expect("base64 is ASCII"));
+        pem.push('\n');
+    }
+    pem.push_str(&format!("-----END {}-----\n", label));
+    pem
+}
+
+/// Every PEM block in `pem`, in order. Text between blocks is ignored, as
+/// RFC 7468 allows.
+pub fn decode(pem: &str) -> Result<Vec<PemBlock>, EnclaveError> {
+    let invalid = |message: String| EnclaveError::GenericError(format!("Invalid PEM: {}", message));
+    let mut blocks = Vec::new();
+    let mut lines = pem.lines().map(str::trim);
+    while let Some(line) = lines.next() {
+        let label = match line
+            .strip_prefix("-----BEGIN ")
+            .and_then(|line| line.strip_suffix("-----"))
+        {
+            Some(label) => label,
+            None => continue,
+        };
+        let end = format!("-----END {}-----", label);
+        let mut base64 = String::new();
+        loop {
+            match lines.next() {
+                Some(line) if line == end => break,
+                Some(line) => base64.push_str(line),
+                None => return Err(invalid(format!("missing {}", end))),
+            }
+        }
+        let contents = Base64::decode(&base64)
+            .map_err(|_| invalid(format!("{} block is not base64", label)))?;
+        blocks.push(PemBlock {
+            label: label.to_string(),
+            contents,
+        });
+    }
+    Ok(blocks)
+}
+
+/// The attestation `document` as a PEM block, followed, if the document can
+/// be parsed, by its certificate chain from the signing certificate up to
+/// the AWS Nitro root.
+pub fn attestation_pem(document: &[u8]) -> String {
+    let mut pem = encode(ATTESTATION_LABEL, document);
+    if let Ok(parsed) = AttestationDocument::parse(document) {
+        let chain = parsed
+            .certificate
+            .iter()
+            .chain(parsed.cabundle.iter().rev());
+        for certificate in chain {
+            pem.push_str(&encode(CERTIFICATE_LABEL, certificate));
+        }
+    }
+    pem
+}
+
+/// The attestation document of `pem`, as written by [attestation_pem].
+pub fn attestation_from_pem(pem: &str) -> Result<Vec<u8>, EnclaveError> {
+    decode(pem)?
+        .into_iter()
+        .find(|block| block.label == ATTESTATION_LABEL)
+        .map(|block| block.contents)
+        .ok_or_else(|| {
+            EnclaveError::GenericError(format!("Invalid PEM: no {} block", ATTESTATION_LABEL))
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{mock_document, mock_payload};
+    use serde_bytes::ByteBuf;
+
+    #[test]
+    fn test_round_trip() {
+        for len in [1, 2, 3, 47, 48, 49, 96, 1000] {
+            let contents: Vec<u8> = (0..len).map(|i| (i * 7) as u8).collect();
+            let pem = encode(CERTIFICATE_LABEL, &contents);
+            assert!(pem.lines().all(|line| line.len() <= LINE_LEN), "{}", pem);
+            assert_eq!(
+                decode(&pem).unwrap(),
+                [PemBlock {
+                    label: CERTIFICATE_LABEL.to_string(),
+                    contents,
+                }]
+            );
+        }
+    }
+
+    #[test]
+    fn test_attestation_pem() {
+        let mut payload = mock_payload(&[7; 32], 0xaa, 0);
+        payload.certificate = Some(ByteBuf::from(vec![3; 500]));
+        payload.cabundle = vec![ByteBuf::from(vec![1; 400]), ByteBuf::from(vec![2; 450])];
+        let document = mock_document(&payload, 0);
+
+        let pem = attestation_pem(&document);
+        assert!(pem.starts_with("-----BEGIN ATTESTATION DOCUMENT-----\n"));
+        let blocks = decode(&pem).unwrap();
+        assert_eq!(blocks[0].contents, document);
+        // Leaf first, then up to the root
+        let chain: Vec<(&str, u8)> = blocks[1..]
+            .iter()
+            .map(|block| (block.label.as_str(), block.contents[0]))
+            .collect();
+        assert_eq!(
+            chain,
+            [
+                (CERTIFICATE_LABEL, 3),
+                (CERTIFICATE_LABEL, 2),
+                (CERTIFICATE_LABEL, 1)
+            ]
+        );
+        assert_eq!(attestation_from_pem(&pem).unwrap(), document);
+
+        // Documents that can't be parsed are still encoded, without a chain
+        assert_eq!(decode(&attestation_pem(b"not cbor")).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_invalid() {
+        let pem = encode(ATTESTATION_LABEL, b"document");
+        let truncated = pem.replace("-----END ATTESTATION DOCUMENT-----\n", "");
+        assert!(decode(&truncated).is_err());
+        let garbled = pem.replace("ZG9j", "Z!9j");
+        assert!(decode(&garbled).is_err());
+        assert!(attestation_from_pem(&encode(CERTIFICATE_LABEL, b"certificate")).is_err());
+        // Text around blocks is ignored
+        let annotated = format!("Enclave attestation\n{}\n", pem);
+        assert_eq!(attestation_from_pem(&annotated).unwrap(), b"document");
+    }
+}
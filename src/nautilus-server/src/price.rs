@@ -0,0 +1,419 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::app::{field_at, sign_payload, upstream_error};
+use crate::common::{
+    current_timestamp_ms, IntentMessage, IntentScope, ProcessDataRequest, ProcessedDataResponse,
+};
+use crate::config::env_or;
+use crate::AppState;
+use crate::EnclaveError;
+use axum::extract::State;
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// ==== PRICE SIGNING ====
+
+/// Most decimals a rate can be signed with, so that one unit of the quote
+/// asset still fits a `u64`.
+pub const MAX_DECIMALS: u8 = 18;
+
+/// Name of the price API in `/health_check`'s
+/// `ms_since_last_successful_fetch`.
+pub const PRICE_SOURCE: &str = "price";
+
+/// Unit of the source timestamp in price responses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimestampUnit {
+    Seconds,
+    Millis,
+}
+
+impl FromStr for TimestampUnit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "s" => Ok(Self::Seconds),
+            "ms" => Ok(Self::Millis),
+            _ => Err(format!("expected `s` or `ms`, got `{}`", s)),
+        }
+    }
+}
+
+/// Price API queried by `/process_price`. In the URL and field paths,
+/// `{base}` and `{quote}` are replaced by the requested pair.
+#[derive(Debug, Clone)]
+pub struct PriceUpstream {
+    /// e.g. `https://api.exchange.example/v1/rates?from={base}&to={quote}`.
+    pub url: String,
+    /// Path of the rate in the response, e.g. `rates.{quote}`. The rate may
+    /// be a number or a decimal string.
+    pub rate_field: String,
+    /// Path of the time the upstream observed the rate, an integer.
+    pub timestamp_field: String,
+    pub timestamp_unit: TimestampUnit,
+    /// Decimals of the signed rate, at most [MAX_DECIMALS].
+    pub decimals: u8,
+    /// Oldest source timestamp accepted, relative to the enclave's clock.
+    pub max_age: Duration,
+}
+
+impl PriceUpstream {
+    /// The upstream configured by `PRICE_URL`, `PRICE_RATE_FIELD`,
+    /// `PRICE_TIMESTAMP_FIELD`, `PRICE_TIMESTAMP_UNIT` (`s` or `ms`, default
+    /// `s`), `PRICE_DECIMALS` (default 8) and `PRICE_MAX_AGE_SECS` (default
+    /// 3600), or None when `PRICE_URL` is unset.
+    pub fn from_env() -> Result<Option<Self>, EnclaveError> {
+        let url = match std::env::var("PRICE_URL") {
+            Ok(url) => url,
+            Err(_) => return Ok(None),
+        };
+        let required = |name: &str| {
+            std::env::var(name).map_err(|_| {
+                EnclaveError::GenericError(format!("{} must be set with PRICE_URL", name))
+            })
+        };
+        let upstream = Self {
+            rate_field: required("PRICE_RATE_FIELD")?,
+            timestamp_field: required("PRICE_TIMESTAMP_FIELD")?,
+            timestamp_unit: env_or("PRICE_TIMESTAMP_UNIT", TimestampUnit::Seconds)?,
+            decimals: env_or("PRICE_DECIMALS", 8)?,
+            max_age: Duration::from_secs(env_or("PRICE_MAX_AGE_SECS", 3600)?),
+            url,
+        };
+        upstream.validate()?;
+        Ok(Some(upstream))
+    }
+
+    fn validate(&self) -> Result<(), EnclaveError> {
+        if self.decimals > MAX_DECIMALS {
+            return Err(EnclaveError::GenericError(format!(
+                "Invalid PRICE_DECIMALS: at most {}, got {}",
+                MAX_DECIMALS, self.decimals
+            )));
+        }
+        self.url_for("BTC", "USD").map(|_| ())
+    }
+
+    fn url_for(&self, base: &str, quote: &str) -> Result<reqwest::Url, EnclaveError> {
+        reqwest::Url::parse(&fill(&self.url, base, quote))
+            .map_err(|e| EnclaveError::GenericError(format!("Invalid PRICE_URL: {}", e)))
+    }
+}
+
+/// `template` with the pair filled in.
+fn fill(template: &str, base: &str, quote: &str) -> String {
+    template.replace("{base}", base).replace("{quote}", quote)
+}
+
+/// Inner type T for ProcessDataRequest<T> on `/process_price`. Asset codes
+/// are 1 to 16 ASCII letters or digits, e.g. `BTC` and `USD`.
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+pub struct PriceRequest {
+    pub base: String,
+    pub quote: String,
+}
+
+/// Inner type T for IntentMessage<T> signed under [IntentScope::Price]. One
+/// `base` is worth `rate / 10^decimals` of `quote`, exactly as the upstream
+/// reported it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+pub struct PriceResponse {
+    pub base: String,
+    pub quote: String,
+    pub rate: u64,
+    pub decimals: u8,
+}
+
+fn check_asset(name: &str, asset: &str) -> Result<(), EnclaveError> {
+    if asset.is_empty() || asset.len() > 16 || !asset.bytes().all(|b| b.is_ascii_alphanumeric()) {
+        return Err(EnclaveError::GenericError(format!(
+            "Invalid {} asset `{}`: expected 1 to 16 ASCII letters or digits",
+            name, asset
+        )));
+    }
+    Ok(())
+}
+
+/// `text`, a non-negative decimal such as `64123.45` or `1.5e-3`, as a
+/// count of `10^-decimals`. Digits past `decimals` must be zeros, so the
+/// signed rate is never rounded.
+pub fn parse_fixed(text: &str, decimals: u8) -> Result<u64, String> {
+    let invalid = || format!("expected a non-negative decimal, got `{}`", text);
+    let (mantissa, exponent) = match text.find(['e', 'E']) {
+        Some(i) => (
+            &text[..i],
+            text[i + 1..].parse::<i64>().map_err(|_| invalid())?,
+        ),
+        None => (text, 0),
+    };
+    let (int, frac) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    if (int.is_empty() && frac.is_empty())
+        || !int.bytes().chain(frac.bytes()).all(|b| b.is_ascii_digit())
+    {
+        return Err(invalid());
+    }
+    // The value is `digits * 10^shift` in units of 10^-decimals
+    let digits = format!("{}{}", int, frac);
+    let digits = digits.trim_start_matches('0');
+    let shift = exponent + decimals as i64 - frac.len() as i64;
+    let out_of_range = || format!("`{}` is too large", text);
+    if digits.is_empty() {
+        return Ok(0);
+    }
+    if shift >= 0 {
+        let scale = u32::try_from(shift)
+            .ok()
+            .and_then(|shift| 10u64.checked_pow(shift))
+            .ok_or_else(out_of_range)?;
+        let digits: u64 = digits.parse().map_err(|_| out_of_range())?;
+        digits.checked_mul(scale).ok_or_else(out_of_range)
+    } else {
+        let kept = digits.len().saturating_sub(shift.unsigned_abs() as usize);
+        let (kept, dropped) = digits.split_at(kept);
+        if dropped.bytes().any(|b| b != b'0') {
+            return Err(format!("`{}` has more than {} decimals", text, decimals));
+        }
+        match kept {
+            "" => Ok(0),
+            kept => kept.parse().map_err(|_| out_of_range()),
+        }
+    }
+}
+
+/// The rate at `rate_field`, a number or a decimal string. Numbers are read
+/// through their shortest exact representation, so a rate the upstream sent
+/// as `0.1` is `0.1` rather than the nearest binary float.
+fn parse_rate(value: &Value, decimals: u8) -> Result<u64, String> {
+    match value {
+        Value::Number(number) => parse_fixed(&number.to_string(), decimals),
+        Value::String(text) => parse_fixed(text.trim(), decimals),
+        _ => Err(format!("expected a number, got {}", value)),
+    }
+}
+
+/// The source timestamp at `timestamp_field`, in milliseconds.
+fn parse_timestamp(value: &Value, unit: TimestampUnit) -> Result<u64, String> {
+    let timestamp = match value {
+        Value::String(text) => text.trim().parse().ok(),
+        _ => value.as_u64(),
+    }
+    .ok_or_else(|| format!("expected an integer timestamp, got {}", value))?;
+    match unit {
+        TimestampUnit::Seconds => timestamp
+            .checked_mul(1000)
+            .ok_or_else(|| format!("timestamp {} is out of range", timestamp)),
+        TimestampUnit::Millis => Ok(timestamp),
+    }
+}
+
+/// Endpoint that fetches the rate of a pair from `PRICE_URL` and signs it
+/// under [IntentScope::Price], at the time the upstream observed it. Only
+/// served when `PRICE_URL` is set.
+pub async fn process_price(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<ProcessDataRequest<PriceRequest>>,
+) -> Result<Json<ProcessedDataResponse<IntentMessage<PriceResponse>>>, EnclaveError> {
+    let upstream = state.config.price_upstream.as_ref().ok_or_else(|| {
+        EnclaveError::GenericError("No price upstream configured, see PRICE_URL".to_string())
+    })?;
+    let scope = IntentScope::Price;
+    if let Some(intent) = request.intent.filter(|intent| *intent != scope as u8) {
+        return Err(EnclaveError::GenericError(format!(
+            "Prices are signed under intent {}, not {}",
+            scope as u8, intent
+        )));
+    }
+    let PriceRequest { base, quote } = request.payload;
+    check_asset("base", &base)?;
+    check_asset("quote", &quote)?;
+
+    // reqwest errors include the URL, which may hold an API key.
+    let url = upstream.url_for(&base, &quote)?;
+    let response = state.upstream_client.get(url.clone()).send().await;
+    let json = match response {
+        Ok(response) => response.json::<Value>().await,
+        Err(e) => Err(e),
+    }
+    .map_err(|e| upstream_error(&state, &url, "Failed to get price response", e))?;
+    let field = |template: &str| {
+        let path = fill(template, &base, &quote);
+        field_at(&json, &path).ok_or_else(|| {
+            EnclaveError::GenericError(format!("Price response has no field `{}`", path))
+        })
+    };
+    let rate = parse_rate(field(&upstream.rate_field)?, upstream.decimals).map_err(|e| {
+        EnclaveError::GenericError(format!("Invalid rate in price response: {}", e))
+    })?;
+    let source_timestamp_ms =
+        parse_timestamp(field(&upstream.timestamp_field)?, upstream.timestamp_unit)
+            .map_err(|e| EnclaveError::GenericError(format!("Invalid price timestamp: {}", e)))?;
+
+    let current_timestamp = current_timestamp_ms(&state.config).await?;
+    if source_timestamp_ms.saturating_add(upstream.max_age.as_millis() as u64) < current_timestamp {
+        return Err(EnclaveError::GenericError(
+            "Price API timestamp is too old".to_string(),
+        ));
+    }
+    state.last_fetches.record(PRICE_SOURCE);
+
+    let payload = PriceResponse {
+        base,
+        quote,
+        rate,
+        decimals: upstream.decimals,
+    };
+    Ok(Json(sign_payload(
+        &state,
+        payload,
+        source_timestamp_ms,
+        scope,
+    )))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api_keys::ApiKeys;
+    use crate::config::Config;
+    use fastcrypto::ed25519::{Ed25519KeyPair, Ed25519Signature};
+    use fastcrypto::encoding::{Encoding, Hex};
+    use fastcrypto::traits::{KeyPair, ToFromBytes, VerifyingKey};
+
+    #[test]
+    fn test_serde_price() {
+        let payload = PriceResponse {
+            base: "BTC".to_string(),
+            quote: "USD".to_string(),
+            rate: 6_412_345_000_000,
+            decimals: 8,
+        };
+        let intent_msg = IntentMessage::new(payload, 1744038900000, IntentScope::Price);
+        let signing_payload = bcs::to_bytes(&intent_msg).expect("should not fail");
+        assert_eq!(
+            Hex::encode(signing_payload),
+            crate::vectors::committed("price").signing_payload
+        );
+    }
+
+    #[test]
+    fn test_parse_fixed() {
+        for (text, decimals, expected) in [
+            ("64123.45", 8, 6_412_345_000_000),
+            ("0.1", 2, 10),
+            ("1.50", 1, 15),
+            ("007", 0, 7),
+            (".5", 1, 5),
+            ("1.5e-3", 4, 15),
+            ("2E2", 0, 200),
+            ("0", 18, 0),
+            ("0.000", 0, 0),
+            ("18.446744073709551615", 18, u64::MAX),
+        ] {
+            assert_eq!(parse_fixed(text, decimals), Ok(expected), "{}", text);
+        }
+        for (text, decimals) in [
+            ("1.005", 2),
+            ("1e-9", 8),
+            ("18.446744073709551616", 18),
+            ("1e30", 0),
+            ("-1", 2),
+            ("", 2),
+            (".", 2),
+            ("1.2.3", 2),
+            ("1e", 2),
+            ("NaN", 2),
+        ] {
+            assert!(parse_fixed(text, decimals).is_err(), "{}", text);
+        }
+        // Numbers are read as written rather than as the nearest float
+        let rate: Value = serde_json::from_str("0.1").unwrap();
+        assert_eq!(parse_rate(&rate, 18), Ok(100_000_000_000_000_000));
+        assert_eq!(parse_rate(&Value::from("1.25"), 2), Ok(125));
+        assert!(parse_rate(&Value::Null, 2).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_process_price() {
+        use axum::routing::get;
+        let now_secs = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let app = axum::Router::new().route(
+            "/latest",
+            get(move || async move {
+                Json(serde_json::json!({
+                    "rates": { "USD": "64123.45" },
+                    "time": now_secs,
+                }))
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let kp = Ed25519KeyPair::generate(&mut rand::thread_rng());
+        let public_key = kp.public().clone();
+        let config = Config {
+            price_upstream: Some(PriceUpstream {
+                url: format!("http://{}/latest?from={{base}}&to={{quote}}", addr),
+                rate_field: "rates.{quote}".to_string(),
+                timestamp_field: "time".to_string(),
+                timestamp_unit: TimestampUnit::Seconds,
+                decimals: 8,
+                max_age: Duration::from_secs(60),
+            }),
+            ..Config::default()
+        };
+        let state = Arc::new(AppState::new(kp, ApiKeys::single(String::new()), config).unwrap());
+        let request = |base: &str, intent| {
+            Json(ProcessDataRequest {
+                payload: PriceRequest {
+                    base: base.to_string(),
+                    quote: "USD".to_string(),
+                },
+                intent,
+            })
+        };
+
+        let signed = process_price(State(state.clone()), request("BTC", None))
+            .await
+            .unwrap()
+            .0;
+        assert_eq!(
+            signed.response.data,
+            PriceResponse {
+                base: "BTC".to_string(),
+                quote: "USD".to_string(),
+                rate: 6_412_345_000_000,
+                decimals: 8,
+            }
+        );
+        assert_eq!(signed.response.intent, IntentScope::Price);
+        assert_eq!(signed.response.timestamp_ms, now_secs * 1000);
+        let signature =
+            Ed25519Signature::from_bytes(&Hex::decode(&signed.signature).unwrap()).unwrap();
+        let signed_bytes = bcs::to_bytes(&signed.response).unwrap();
+        assert!(public_key.verify(&signed_bytes, &signature).is_ok());
+
+        for (base, intent, error) in [
+            ("BTC/ETH", None, "Invalid base asset"),
+            ("BTC", Some(0), "Prices are signed under intent 4"),
+        ] {
+            let err = process_price(State(state.clone()), request(base, intent))
+                .await
+                .err()
+                .unwrap()
+                .to_string();
+            assert!(err.starts_with(error), "{}", err);
+        }
+    }
+}
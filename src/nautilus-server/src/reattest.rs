@@ -0,0 +1,294 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::attestation::AttestationDocument;
+use crate::common::{nsm_attestation, wall_clock_ms};
+use crate::AppState;
+use crate::EnclaveError;
+use axum::extract::State;
+use axum::Json;
+use fastcrypto::encoding::{Encoding, Hex};
+use fastcrypto::traits::{KeyPair, ToFromBytes};
+use serde::{Deserialize, Serialize};
+use serde_bytes::ByteBuf;
+use sha2::{Digest, Sha384};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+/// The fields of an attestation document that must stay the same for the
+/// life of the enclave.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AttestedIdentity {
+    /// PCRs by index.
+    pub pcrs: BTreeMap<usize, ByteBuf>,
+    /// Public key the document commits to.
+    pub public_key: Option<ByteBuf>,
+}
+
+impl AttestedIdentity {
+    /// Read the identity from a COSE_Sign1 attestation document. The
+    /// signature isn't checked: the document came straight from the NSM, and
+    /// this only looks for changes in what the enclave itself reports.
+    pub fn parse(document: &[u8]) -> Result<Self, EnclaveError> {
+        let document = AttestationDocument::parse(document)
+            .map_err(|e| EnclaveError::GenericError(e.to_string()))?;
+        Ok(Self {
+            pcrs: document.pcrs,
+            public_key: document.public_key,
+        })
+    }
+
+    /// What differs in `other`, if anything, e.g. `public key, PCR0`.
+    fn changes(&self, other: &Self) -> Option<String> {
+        let mut changes = Vec::new();
+        if self.public_key != other.public_key {
+            changes.push("public key".to_string());
+        }
+        let indices: BTreeSet<usize> = self.pcrs.keys().chain(other.pcrs.keys()).copied().collect();
+        for index in indices {
+            if self.pcrs.get(&index) != other.pcrs.get(&index) {
+                changes.push(format!("PCR{}", index));
+            }
+        }
+        (!changes.is_empty()).then(|| changes.join(", "))
+    }
+}
+
+/// Response for the latest periodic attestation.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+pub struct PeriodicAttestationResponse {
+    /// Attestation document serialized in Hex.
+    pub attestation: String,
+    /// Hex encoded SHA-384 digest of the document, as logged.
+    pub digest: String,
+    /// When the document was generated, in milliseconds since the UNIX epoch.
+    pub generated_at_ms: u64,
+    /// Periodic attestations generated since startup, this one included.
+    pub count: u64,
+    /// The first unexpected change of public key or PCRs since startup. Once
+    /// set it stays set, since such a change should never happen.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alarm: Option<String>,
+}
+
+#[derive(Default)]
+struct MonitorState {
+    baseline: Option<AttestedIdentity>,
+    latest: Option<PeriodicAttestationResponse>,
+    count: u64,
+    alarm: Option<String>,
+}
+
+/// Periodic attestations of a running enclave, compared against the first
+/// one and against the enclave's own public key.
+pub struct Reattestation {
+    public_key: Vec<u8>,
+    state: Mutex<MonitorState>,
+}
+
+impl Reattestation {
+    pub fn new(public_key: &[u8]) -> Self {
+        Self {
+            public_key: public_key.to_vec(),
+            state: Mutex::new(MonitorState::default()),
+        }
+    }
+
+    /// Record a fresh document, raising the alarm if it reports a different
+    /// public key or PCRs than expected. A document that can't be read is
+    /// logged and otherwise ignored.
+    pub fn record(&self, document: Vec<u8>, generated_at_ms: u64) {
+        let identity = match AttestedIdentity::parse(&document) {
+            Ok(identity) => identity,
+            Err(e) => {
+                warn!("Ignoring periodic attestation: {}", e);
+                return;
+            }
+        };
+        let digest = Hex::encode(Sha384::digest(&document));
+        let mut guard = self.state.lock().unwrap();
+        let state = &mut *guard;
+        state.count += 1;
+        info!("Periodic attestation {}, digest {}", state.count, digest);
+
+        let expected = state.baseline.get_or_insert_with(|| AttestedIdentity {
+            pcrs: identity.pcrs.clone(),
+            public_key: Some(ByteBuf::from(self.public_key.clone())),
+        });
+        if let Some(changes) = expected.changes(&identity) {
+            error!(
+                "ALARM: periodic attestation {} reports a changed {}, digest {}",
+                state.count, changes, digest
+            );
+            if state.alarm.is_none() {
+                state.alarm = Some(format!(
+                    "{} changed in periodic attestation {}",
+                    changes, state.count
+                ));
+            }
+        }
+        state.latest = Some(PeriodicAttestationResponse {
+            attestation: Hex::encode(&document),
+            digest,
+            generated_at_ms,
+            count: state.count,
+            alarm: state.alarm.clone(),
+        });
+    }
+
+    /// The latest periodic attestation, None before the first.
+    pub fn latest(&self) -> Option<PeriodicAttestationResponse> {
+        self.state.lock().unwrap().latest.clone()
+    }
+}
+
+/// Generate an attestation with `generate` every `interval`, recording each
+/// in `state.reattestation`. Generation failures are logged and retried at
+/// the next interval.
+async fn run(
+    state: Arc<AppState>,
+    interval: Duration,
+    mut generate: impl FnMut(&AppState) -> Result<Vec<u8>, EnclaveError>,
+) {
+    let mut interval = tokio::time::interval(interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        match generate(&state) {
+            Ok(document) => state.reattestation.record(document, wall_clock_ms()),
+            Err(e) => warn!("Periodic attestation failed: {}", e),
+        }
+    }
+}
+
+/// Start the background task that requests a new attestation every
+/// `REATTEST_INTERVAL_SECS`, served by `/periodic_attestation`. Does nothing
+/// unless the interval is set.
+pub fn spawn_reattestation(state: Arc<AppState>) -> Option<JoinHandle<()>> {
+    let interval = state.config.reattest_interval?;
+    info!("Re-attesting every {:?}", interval);
+    Some(tokio::spawn(run(state, interval, |state| {
+        let document = nsm_attestation(
+            state.eph_kp.public().as_bytes(),
+            state.attestation_user_data().as_deref(),
+            None,
+        )?;
+        state.stats.record_attestation();
+        Ok(document)
+    })))
+}
+
+/// Endpoint that returns the latest periodic attestation, with the alarm if
+/// the enclave's public key or PCRs ever changed. Only served when
+/// `REATTEST_INTERVAL_SECS` is set.
+pub async fn periodic_attestation(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<PeriodicAttestationResponse>, EnclaveError> {
+    state
+        .reattestation
+        .latest()
+        .map(Json)
+        .ok_or_else(|| EnclaveError::GenericError("No periodic attestation yet".to_string()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api_keys::ApiKeys;
+    use crate::config::Config;
+    use crate::test_utils;
+    use fastcrypto::ed25519::Ed25519KeyPair;
+
+    /// A COSE_Sign1 document as the NSM would return it, with an empty
+    /// protected header and signature numbered `serial`.
+    fn mock_document(public_key: &[u8], pcr0: u8, serial: u8) -> Vec<u8> {
+        test_utils::mock_document(&test_utils::mock_payload(public_key, pcr0, 0), serial)
+    }
+
+    #[test]
+    fn test_parse_document() {
+        let identity = AttestedIdentity::parse(&mock_document(&[7; 32], 0xaa, 0)).unwrap();
+        assert_eq!(identity.public_key.unwrap().as_slice(), [7; 32]);
+        assert_eq!(identity.pcrs[&0].as_slice(), [0xaa; 48]);
+        assert_eq!(identity.pcrs.len(), 3);
+        assert!(AttestedIdentity::parse(b"not cbor").is_err());
+    }
+
+    #[test]
+    fn test_alarm_on_other_public_key() {
+        let reattestation = Reattestation::new(&[1; 32]);
+        reattestation.record(mock_document(&[2; 32], 0xaa, 0), 5);
+        let latest = reattestation.latest().unwrap();
+        assert_eq!(latest.count, 1);
+        assert_eq!(latest.generated_at_ms, 5);
+        assert_eq!(
+            latest.alarm.as_deref(),
+            Some("public key changed in periodic attestation 1")
+        );
+
+        // Unreadable documents are skipped
+        reattestation.record(b"garbage".to_vec(), 6);
+        assert_eq!(reattestation.latest().unwrap().count, 1);
+    }
+
+    #[tokio::test]
+    async fn test_task_detects_pcr_change() {
+        let eph_kp = Ed25519KeyPair::generate(&mut rand::thread_rng());
+        let public_key = eph_kp.public().as_bytes().to_vec();
+        let state = Arc::new(
+            AppState::new(eph_kp, ApiKeys::single(String::new()), Config::default()).unwrap(),
+        );
+        // The mock NSM measures a different PCR0 from its third attestation
+        let mut calls = 0;
+        let mock_nsm = move |_: &AppState| {
+            calls += 1;
+            let pcr0 = if calls < 4 { 0xaa } else { 0xbb };
+            match calls {
+                2 => Err(EnclaveError::GenericError("NSM busy".to_string())),
+                _ => Ok(mock_document(&public_key, pcr0, calls)),
+            }
+        };
+        let task = tokio::spawn(run(state.clone(), Duration::from_millis(10), mock_nsm));
+
+        let mut seen: Vec<PeriodicAttestationResponse> = Vec::new();
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+        loop {
+            if let Some(latest) = state.reattestation.latest() {
+                if seen.last() != Some(&latest) {
+                    seen.push(latest.clone());
+                }
+                if latest.count >= 3 {
+                    break;
+                }
+            }
+            assert!(tokio::time::Instant::now() < deadline, "{:?}", seen);
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        task.abort();
+
+        // The failed call was skipped, and only the changed PCR raised the alarm
+        let first = &seen[0];
+        assert_eq!(first.count, 1);
+        assert!(first.alarm.is_none());
+        assert_eq!(
+            first.digest,
+            Hex::encode(Sha384::digest(Hex::decode(&first.attestation).unwrap()))
+        );
+        assert!(seen
+            .iter()
+            .all(|latest| latest.count < 3 || latest.alarm.is_some()));
+        let latest = seen.last().unwrap();
+        assert_eq!(
+            latest.alarm.as_deref(),
+            Some("PCR0 changed in periodic attestation 3")
+        );
+        assert_eq!(
+            periodic_attestation(State(state)).await.unwrap().0.count,
+            latest.count
+        );
+    }
+}
@@ -0,0 +1,105 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Periodic re-seeding of the kernel RNG from the NSM. Init seeds it once at
+//! boot; for enclaves that run for months, mixing in fresh NSM entropy every
+//! `RESEED_INTERVAL_SECS` keeps the RNG behind `rand::thread_rng` and the
+//! key generation well-seeded as defense in depth.
+
+use crate::common::nsm_random;
+use crate::config::Config;
+use crate::EnclaveError;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+/// Bytes drawn from the NSM at each reseed. The kernel pool is a 256 bit
+/// hash, so more only costs NSM round trips.
+pub const RESEED_BYTES: usize = 64;
+
+/// Every `interval`, starting one interval from now, draw [RESEED_BYTES]
+/// with `draw` and mix them into the RNG with `mix`. Failures are logged and
+/// retried at the next interval.
+async fn run(
+    interval: Duration,
+    mut draw: impl FnMut(usize) -> Result<Vec<u8>, EnclaveError>,
+    mut mix: impl FnMut(&[u8]) -> Result<(), EnclaveError>,
+) {
+    let start = tokio::time::Instant::now() + interval;
+    let mut ticks = tokio::time::interval_at(start, interval);
+    ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        ticks.tick().await;
+        match draw(RESEED_BYTES).and_then(|entropy| mix(&entropy).map(|()| entropy.len())) {
+            Ok(len) => info!("Reseeded the kernel RNG with {} bytes from the NSM", len),
+            Err(e) => warn!("Reseeding the kernel RNG failed: {}", e),
+        }
+    }
+}
+
+/// Mix `entropy` into the kernel RNG by writing it to `/dev/urandom`, as
+/// init does at boot. Writes aren't credited, which since Linux 5.10 makes
+/// no difference to the pool.
+fn mix_into_urandom(entropy: &[u8]) -> Result<(), EnclaveError> {
+    use std::io::Write;
+    std::fs::OpenOptions::new()
+        .write(true)
+        .open("/dev/urandom")
+        .and_then(|mut urandom| urandom.write_all(entropy))
+        .map_err(|e| EnclaveError::GenericError(format!("/dev/urandom: {}", e)))
+}
+
+/// Start the background task that reseeds the kernel RNG from the NSM every
+/// `RESEED_INTERVAL_SECS`. Does nothing unless the interval is set.
+pub fn spawn_reseeding(config: &Config) -> Option<JoinHandle<()>> {
+    let interval = config.reseed_interval?;
+    info!("Reseeding the kernel RNG every {:?}", interval);
+    Some(tokio::spawn(run(interval, nsm_random, mix_into_urandom)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[tokio::test]
+    async fn test_reseeds_on_schedule() {
+        let interval = Duration::from_millis(20);
+        // The mock NSM returns its call number, and is busy on the second call
+        let mut calls = 0u8;
+        let mock_nsm = move |len: usize| {
+            calls += 1;
+            match calls {
+                2 => Err(EnclaveError::GenericError("NSM busy".to_string())),
+                _ => Ok(vec![calls; len]),
+            }
+        };
+        let mixed = Arc::new(Mutex::new(Vec::new()));
+        let mix = {
+            let mixed = mixed.clone();
+            move |entropy: &[u8]| -> Result<(), EnclaveError> {
+                let at = tokio::time::Instant::now();
+                mixed.lock().unwrap().push((at, entropy.to_vec()));
+                Ok(())
+            }
+        };
+        let started = tokio::time::Instant::now();
+        let task = tokio::spawn(run(interval, mock_nsm, mix));
+
+        let deadline = started + Duration::from_secs(5);
+        while mixed.lock().unwrap().len() < 3 {
+            assert!(tokio::time::Instant::now() < deadline);
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        task.abort();
+
+        // Nothing at startup, then one draw per interval, skipping the failure
+        let mixed = mixed.lock().unwrap();
+        let calls: Vec<u8> = mixed.iter().map(|(_, entropy)| entropy[0]).collect();
+        assert_eq!(calls[..3], [1, 3, 4]);
+        for (at, entropy) in mixed.iter() {
+            assert!(*at - started >= interval * entropy[0] as u32);
+            assert_eq!(entropy.len(), RESEED_BYTES);
+        }
+    }
+}
@@ -0,0 +1,666 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::api_keys::rotate_api_key;
+use crate::api_version::require_api_version;
+use crate::app::{process_data, process_data_batch, process_data_get};
+use crate::common::{allowed_endpoints, get_attestation, health_check};
+use crate::header_limits::{limit_headers, HeaderLimits};
+use crate::pcrs::expected_pcrs;
+use crate::price::process_price;
+use crate::reattest::periodic_attestation;
+use crate::schema::sign_typed_data;
+use crate::stats::stats;
+use crate::timing::time_requests;
+use crate::AppState;
+use axum::extract::Request;
+use axum::{middleware, routing::get, routing::post, Router};
+use hyper::body::Incoming;
+use hyper_util::rt::TokioIo;
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tower::Service;
+use tower_http::cors::{Any, CorsLayer};
+use tower_http::normalize_path::NormalizePath;
+use tracing::{debug, error};
+
+/// Wait after a failed accept, e.g. when out of file descriptors, so the
+/// accept loop doesn't spin until some are freed.
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_secs(1);
+
+/// How a path with a trailing slash, such as `/process_data/`, is routed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrailingSlash {
+    /// Only the path as registered matches; anything else is a 404.
+    Strict,
+    /// The trailing slash is removed before routing, so both forms match.
+    Lenient,
+}
+
+impl FromStr for TrailingSlash {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "strict" => Ok(Self::Strict),
+            "lenient" => Ok(Self::Lenient),
+            _ => Err(format!("expected `strict` or `lenient`, got `{}`", s)),
+        }
+    }
+}
+
+/// All endpoints served by the enclave, with their middleware.
+pub fn router(state: Arc<AppState>) -> Router {
+    // Define your own restricted CORS policy here if needed.
+    let cors = CorsLayer::new().allow_methods(Any).allow_headers(Any);
+
+    let process = if state.config.process_data_get {
+        post(process_data).get(process_data_get)
+    } else {
+        post(process_data)
+    };
+    let app = Router::new()
+        .route("/", get(ping))
+        .route("/get_attestation", get(get_attestation))
+        .route("/process_data", process)
+        .route("/process_data_batch", post(process_data_batch))
+        .route("/health_check", get(health_check))
+        .route("/expected_pcrs", get(expected_pcrs))
+        .route("/allowed_endpoints", get(allowed_endpoints))
+        .route("/sign/:schema", post(sign_typed_data));
+    let app = if state.config.api_key_rotation_token.is_some() {
+        app.route("/rotate_api_key", post(rotate_api_key))
+    } else {
+        app
+    };
+    let app = if state.config.price_upstream.is_some() {
+        app.route("/process_price", post(process_price))
+    } else {
+        app
+    };
+    #[cfg(feature = "github")]
+    let app = if state.config.github_upstream.is_some() {
+        app.route(
+            "/process_github_commit",
+            post(crate::github::process_github_commit),
+        )
+    } else {
+        app
+    };
+    #[cfg(feature = "sui-transactions")]
+    let app = if state.config.transaction_policy.is_some() {
+        app.route(
+            "/process_transaction",
+            post(crate::transaction::process_transaction),
+        )
+    } else {
+        app
+    };
+    let app = if state.config.admin_token.is_some() {
+        app.route("/stats", get(stats))
+    } else {
+        app
+    };
+    let app = if state.config.reattest_interval.is_some() {
+        app.route("/periodic_attestation", get(periodic_attestation))
+    } else {
+        app
+    };
+    #[cfg(feature = "openapi")]
+    let app = app
+        .route("/openapi.json", get(crate::openapi::openapi_spec))
+        .route("/schemas", get(crate::openapi::json_schemas));
+    app.route_layer(middleware::from_fn(require_api_version))
+        .route_layer(middleware::from_fn_with_state(state.clone(), time_requests))
+        .layer(middleware::from_fn_with_state(state.clone(), limit_headers))
+        .with_state(state)
+        .layer(cors)
+}
+
+/// Serve `app` on `listener` over HTTP/1.1, with request heads bounded by
+/// `limits` as they are read. With [TrailingSlash::Lenient] the slash is
+/// trimmed here, before `app` sees the request, since the router matches the
+/// path before any layer added to it runs.
+pub async fn serve(
+    listener: TcpListener,
+    app: Router,
+    trailing_slash: TrailingSlash,
+    limits: HeaderLimits,
+) -> std::io::Result<()> {
+    loop {
+        let (stream, peer) = accept(&listener).await;
+        let app = app.clone();
+        tokio::spawn(async move {
+            let service = hyper::service::service_fn(move |request: Request<Incoming>| {
+                let mut app = app.clone();
+                async move {
+                    match trailing_slash {
+                        TrailingSlash::Strict => app.call(request).await,
+                        TrailingSlash::Lenient => {
+                            NormalizePath::trim_trailing_slash(app).call(request).await
+                        }
+                    }
+                }
+            });
+            if let Err(e) = limits
+                .http1()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                debug!("Connection from {} failed: {}", peer, e);
+            }
+        });
+    }
+}
+
+/// The next connection on `listener`. Accept errors are logged and retried
+/// rather than returned, as by `axum::serve`, so running out of file
+/// descriptors or a client giving up before it was accepted doesn't stop the
+/// server.
+pub(crate) async fn accept(listener: &TcpListener) -> (TcpStream, SocketAddr) {
+    loop {
+        match listener.accept().await {
+            Ok(accepted) => return accepted,
+            // Only the connection being accepted failed
+            Err(e) if is_connection_error(&e) => debug!("Failed to accept a connection: {}", e),
+            Err(e) => {
+                error!("Failed to accept a connection: {}", e);
+                tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+            }
+        }
+    }
+}
+
+fn is_connection_error(e: &std::io::Error) -> bool {
+    matches!(
+        e.kind(),
+        ErrorKind::ConnectionAborted | ErrorKind::ConnectionRefused | ErrorKind::ConnectionReset
+    )
+}
+
+async fn ping() -> &'static str {
+    "Pong!"
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api_keys::ApiKeys;
+    use crate::api_version::API_VERSION_HEADER;
+    use crate::config::Config;
+    use fastcrypto::{ed25519::Ed25519KeyPair, traits::KeyPair};
+    use reqwest::{Method, StatusCode};
+
+    /// Every route, by method and an example path.
+    const ROUTES: &[(Method, &str)] = &[
+        (Method::GET, "/get_attestation"),
+        (Method::POST, "/process_data"),
+        (Method::POST, "/process_data_batch"),
+        (Method::GET, "/health_check"),
+        (Method::GET, "/expected_pcrs"),
+        (Method::GET, "/allowed_endpoints"),
+        (Method::POST, "/sign/price"),
+        #[cfg(feature = "openapi")]
+        (Method::GET, "/openapi.json"),
+        #[cfg(feature = "openapi")]
+        (Method::GET, "/schemas"),
+    ];
+
+    /// The routes missing from [ROUTES]: `/`, and those served only when
+    /// configured, which [full_config] all enables.
+    #[cfg(feature = "openapi")]
+    const CONFIGURED_ROUTES: &[(Method, &str)] = &[
+        (Method::GET, "/"),
+        (Method::GET, "/process_data"),
+        (Method::POST, "/rotate_api_key"),
+        (Method::POST, "/process_price"),
+        #[cfg(feature = "github")]
+        (Method::POST, "/process_github_commit"),
+        #[cfg(feature = "sui-transactions")]
+        (Method::POST, "/process_transaction"),
+        (Method::GET, "/stats"),
+        (Method::GET, "/periodic_attestation"),
+    ];
+
+    /// A config under which the router serves every route it has.
+    #[cfg(feature = "openapi")]
+    fn full_config() -> Config {
+        Config {
+            process_data_get: true,
+            api_key_rotation_token: Some("s3cret".to_string()),
+            price_upstream: Some(crate::price::PriceUpstream {
+                url: "https://api.exchange.example/v1/rates?from={base}&to={quote}".to_string(),
+                rate_field: "rates.{quote}".to_string(),
+                timestamp_field: "time".to_string(),
+                timestamp_unit: crate::price::TimestampUnit::Seconds,
+                decimals: 8,
+                max_age: std::time::Duration::from_secs(3600),
+            }),
+            #[cfg(feature = "github")]
+            github_upstream: Some(crate::github::GithubUpstream {
+                api_url: reqwest::Url::parse(crate::github::GITHUB_API_URL).unwrap(),
+                token: "t0ken".to_string(),
+            }),
+            #[cfg(feature = "sui-transactions")]
+            transaction_policy: Some(crate::transaction::TransactionPolicy {
+                max_bytes: crate::transaction::DEFAULT_MAX_TRANSACTION_BYTES,
+                allowed_calls: None,
+            }),
+            admin_token: Some("s3cret".to_string()),
+            reattest_interval: Some(std::time::Duration::from_secs(60)),
+            ..Config::default()
+        }
+    }
+
+    /// Serve the router on a local port, returning its base URL.
+    async fn spawn_server(trailing_slash: TrailingSlash) -> String {
+        spawn_server_with(Config::default(), trailing_slash).await
+    }
+
+    async fn spawn_server_with(config: Config, trailing_slash: TrailingSlash) -> String {
+        let state = Arc::new(
+            AppState::new(
+                Ed25519KeyPair::generate(&mut rand::thread_rng()),
+                ApiKeys::single(String::new()),
+                config,
+            )
+            .unwrap(),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let limits = HeaderLimits::from_config(&state.config);
+        tokio::spawn(serve(listener, router(state), trailing_slash, limits));
+        url
+    }
+
+    /// Status of a request that matched no route, or the 400 from the API
+    /// version check if it did, so no handler runs.
+    async fn status(url: &str, method: &Method, path: &str) -> StatusCode {
+        reqwest::Client::new()
+            .request(method.clone(), format!("{}{}", url, path))
+            .header(API_VERSION_HEADER, "0")
+            .send()
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn test_strict_trailing_slash() {
+        let url = spawn_server(TrailingSlash::Strict).await;
+        assert_eq!(
+            status(&url, &Method::GET, "/").await,
+            StatusCode::BAD_REQUEST
+        );
+        for (method, path) in ROUTES {
+            assert_eq!(
+                status(&url, method, path).await,
+                StatusCode::BAD_REQUEST,
+                "{}",
+                path
+            );
+            let with_slash = format!("{}/", path);
+            assert_eq!(
+                status(&url, method, &with_slash).await,
+                StatusCode::NOT_FOUND,
+                "{}",
+                with_slash
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lenient_trailing_slash() {
+        let url = spawn_server(TrailingSlash::Lenient).await;
+        assert_eq!(
+            status(&url, &Method::GET, "/").await,
+            StatusCode::BAD_REQUEST
+        );
+        for (method, path) in ROUTES {
+            for path in [path.to_string(), format!("{}/", path)] {
+                assert_eq!(
+                    status(&url, method, &path).await,
+                    StatusCode::BAD_REQUEST,
+                    "{}",
+                    path
+                );
+            }
+        }
+        assert_eq!(
+            status(&url, &Method::GET, "/no_such_route/").await,
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rotate_api_key_route() {
+        let url = spawn_server(TrailingSlash::Strict).await;
+        assert_eq!(
+            status(&url, &Method::POST, "/rotate_api_key").await,
+            StatusCode::NOT_FOUND
+        );
+
+        let config = Config {
+            api_key_rotation_token: Some("s3cret".to_string()),
+            ..Config::default()
+        };
+        let url = spawn_server_with(config, TrailingSlash::Strict).await;
+        assert_eq!(
+            status(&url, &Method::POST, "/rotate_api_key").await,
+            StatusCode::BAD_REQUEST
+        );
+        // A wrong token is refused before the upstream is called
+        let response = reqwest::Client::new()
+            .post(format!("{}/rotate_api_key", url))
+            .bearer_auth("wrong")
+            .json(&serde_json::json!({ "api_key": "new" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let body = response.text().await.unwrap();
+        assert!(body.contains("Invalid API key rotation token"), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn test_process_price_route() {
+        let url = spawn_server(TrailingSlash::Strict).await;
+        assert_eq!(
+            status(&url, &Method::POST, "/process_price").await,
+            StatusCode::NOT_FOUND
+        );
+
+        let config = Config {
+            price_upstream: Some(crate::price::PriceUpstream {
+                url: "https://api.exchange.example/v1/rates?from={base}&to={quote}".to_string(),
+                rate_field: "rates.{quote}".to_string(),
+                timestamp_field: "time".to_string(),
+                timestamp_unit: crate::price::TimestampUnit::Seconds,
+                decimals: 8,
+                max_age: std::time::Duration::from_secs(3600),
+            }),
+            ..Config::default()
+        };
+        let url = spawn_server_with(config, TrailingSlash::Strict).await;
+        assert_eq!(
+            status(&url, &Method::POST, "/process_price").await,
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[cfg(feature = "github")]
+    #[tokio::test]
+    async fn test_process_github_commit_route() {
+        let url = spawn_server(TrailingSlash::Strict).await;
+        assert_eq!(
+            status(&url, &Method::POST, "/process_github_commit").await,
+            StatusCode::NOT_FOUND
+        );
+
+        let config = Config {
+            github_upstream: Some(crate::github::GithubUpstream {
+                api_url: reqwest::Url::parse(crate::github::GITHUB_API_URL).unwrap(),
+                token: "t0ken".to_string(),
+            }),
+            ..Config::default()
+        };
+        let url = spawn_server_with(config, TrailingSlash::Strict).await;
+        assert_eq!(
+            status(&url, &Method::POST, "/process_github_commit").await,
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[cfg(feature = "sui-transactions")]
+    #[tokio::test]
+    async fn test_process_transaction_route() {
+        let url = spawn_server(TrailingSlash::Strict).await;
+        assert_eq!(
+            status(&url, &Method::POST, "/process_transaction").await,
+            StatusCode::NOT_FOUND
+        );
+
+        let config = Config {
+            transaction_policy: Some(crate::transaction::TransactionPolicy {
+                max_bytes: crate::transaction::DEFAULT_MAX_TRANSACTION_BYTES,
+                allowed_calls: None,
+            }),
+            ..Config::default()
+        };
+        let url = spawn_server_with(config, TrailingSlash::Strict).await;
+        assert_eq!(
+            status(&url, &Method::POST, "/process_transaction").await,
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    /// The OpenAPI paths are written out by hand, so check them against what
+    /// the router actually serves, both ways.
+    #[cfg(feature = "openapi")]
+    #[tokio::test]
+    async fn test_openapi_matches_routes() {
+        let url = spawn_server_with(full_config(), TrailingSlash::Strict).await;
+        let spec = crate::openapi::spec();
+        let paths = spec["paths"].as_object().unwrap();
+
+        // Every route served has an operation in the spec
+        for (method, path) in ROUTES.iter().chain(CONFIGURED_ROUTES) {
+            assert_eq!(
+                status(&url, method, path).await,
+                StatusCode::BAD_REQUEST,
+                "{} {} isn't served",
+                method,
+                path
+            );
+            let spec_path = if path.starts_with("/sign/") {
+                "/sign/{schema}"
+            } else {
+                *path
+            };
+            let operation = method.as_str().to_lowercase();
+            assert!(
+                paths
+                    .get(spec_path)
+                    .and_then(|item| item.get(&operation))
+                    .is_some(),
+                "{} {} is missing from the spec",
+                method,
+                spec_path
+            );
+        }
+
+        // Every operation in the spec is served, unless its feature is off
+        for (path, item) in paths {
+            if (path == "/process_github_commit" && !cfg!(feature = "github"))
+                || (path == "/process_transaction" && !cfg!(feature = "sui-transactions"))
+            {
+                continue;
+            }
+            for operation in item.as_object().unwrap().keys() {
+                let method = Method::from_bytes(operation.to_uppercase().as_bytes()).unwrap();
+                assert_eq!(
+                    status(&url, &method, &path.replace("{schema}", "price")).await,
+                    StatusCode::BAD_REQUEST,
+                    "{} {} is in the spec but not served",
+                    method,
+                    path
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_header_limits() {
+        let url = spawn_server(TrailingSlash::Strict).await;
+        let get = |headers: Vec<(String, String)>| {
+            let mut request = reqwest::Client::new().get(format!("{}/", url));
+            for (name, value) in headers {
+                request = request.header(name, value);
+            }
+            request.send()
+        };
+        let default = Config::default();
+        assert_eq!(get(vec![]).await.unwrap().status(), StatusCode::OK);
+
+        // One header larger than all headers may be together
+        let oversized = vec![(
+            "x-padding".to_string(),
+            "a".repeat(default.max_header_bytes),
+        )];
+        let response = get(oversized).await.unwrap();
+        assert_eq!(
+            response.status(),
+            StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE
+        );
+        let body = response.text().await.unwrap();
+        assert!(body.contains("Request headers take"), "{}", body);
+
+        // Many small headers
+        let numerous = (0..default.max_header_count)
+            .map(|i| (format!("x-h{}", i), "1".to_string()))
+            .collect();
+        let response = get(numerous).await.unwrap();
+        assert_eq!(
+            response.status(),
+            StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE
+        );
+        let body = response.text().await.unwrap();
+        assert!(body.contains("Request has"), "{}", body);
+
+        // Far past the limit hyper refuses the request while reading it,
+        // without the router's message
+        let flood = (0..2 * default.max_header_count)
+            .map(|i| (format!("x-h{}", i), "1".to_string()))
+            .collect();
+        let response = get(flood).await.unwrap();
+        assert_eq!(
+            response.status(),
+            StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE
+        );
+        assert_eq!(response.text().await.unwrap(), "");
+
+        // Unknown routes are rejected the same way
+        let response = reqwest::Client::new()
+            .get(format!("{}/no_such_route", url))
+            .header("x-padding", "a".repeat(default.max_header_bytes))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(
+            response.status(),
+            StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE
+        );
+
+        // Both limits can be lifted
+        let config = Config {
+            max_header_bytes: 0,
+            max_header_count: 0,
+            ..Config::default()
+        };
+        let url = spawn_server_with(config, TrailingSlash::Strict).await;
+        let response = reqwest::Client::new()
+            .get(format!("{}/", url))
+            .header("x-padding", "a".repeat(default.max_header_bytes))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // A count above hyper's default of 100 headers takes effect
+        let config = Config {
+            max_header_count: 150,
+            ..Config::default()
+        };
+        let url = spawn_server_with(config, TrailingSlash::Strict).await;
+        let mut request = reqwest::Client::new().get(format!("{}/", url));
+        for i in 0..120 {
+            request = request.header(format!("x-h{}", i), "1");
+        }
+        assert_eq!(request.send().await.unwrap().status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_stats_route() {
+        let url = spawn_server(TrailingSlash::Strict).await;
+        assert_eq!(
+            status(&url, &Method::GET, "/stats").await,
+            StatusCode::NOT_FOUND
+        );
+
+        let config = Config {
+            admin_token: Some("s3cret".to_string()),
+            ..Config::default()
+        };
+        let url = spawn_server_with(config, TrailingSlash::Strict).await;
+        let get = |token: &'static str| {
+            reqwest::Client::new()
+                .get(format!("{}/stats", url))
+                .bearer_auth(token)
+                .send()
+        };
+        assert_eq!(
+            get("wrong").await.unwrap().status(),
+            StatusCode::UNAUTHORIZED
+        );
+        let missing = reqwest::Client::new()
+            .get(format!("{}/stats", url))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(missing.status(), StatusCode::UNAUTHORIZED);
+        let stats: crate::stats::StatsResponse = get("s3cret").await.unwrap().json().await.unwrap();
+        assert_eq!(stats.signed_responses.len(), 8);
+        assert!(stats.signed_responses.values().all(|count| *count == 0));
+    }
+
+    #[tokio::test]
+    async fn test_periodic_attestation_route() {
+        let url = spawn_server(TrailingSlash::Strict).await;
+        assert_eq!(
+            status(&url, &Method::GET, "/periodic_attestation").await,
+            StatusCode::NOT_FOUND
+        );
+
+        let config = Config {
+            reattest_interval: Some(std::time::Duration::from_secs(60)),
+            ..Config::default()
+        };
+        let url = spawn_server_with(config, TrailingSlash::Strict).await;
+        assert_eq!(
+            status(&url, &Method::GET, "/periodic_attestation").await,
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[tokio::test]
+    async fn test_process_data_get() {
+        let url = spawn_server(TrailingSlash::Strict).await;
+        assert_eq!(
+            status(&url, &Method::GET, "/process_data?location=Paris").await,
+            StatusCode::METHOD_NOT_ALLOWED
+        );
+
+        let config = Config {
+            process_data_get: true,
+            ..Config::default()
+        };
+        let url = spawn_server_with(config, TrailingSlash::Strict).await;
+        for method in [Method::GET, Method::POST] {
+            assert_eq!(
+                status(&url, &method, "/process_data?location=Paris").await,
+                StatusCode::BAD_REQUEST
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_trailing_slash() {
+        assert_eq!("strict".parse(), Ok(TrailingSlash::Strict));
+        assert_eq!("lenient".parse(), Ok(TrailingSlash::Lenient));
+        assert!("redirect".parse::<TrailingSlash>().is_err());
+    }
+}
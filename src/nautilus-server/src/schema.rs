@@ -0,0 +1,582 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::common::{
+    current_timestamp_ms, IntentScope, ProcessDataRequest, ProcessedDataResponse, SigningFormat,
+};
+use crate::AppState;
+use crate::EnclaveError;
+use axum::extract::{Path, State};
+use axum::Json;
+use fastcrypto::encoding::{Encoding, Hex};
+use fastcrypto::traits::Signer;
+use nautilus_verifier::compression::CompressedPayload;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use tracing::info;
+
+/// ==== TYPED PAYLOAD SIGNING ====
+
+/// Contents of the file at `SCHEMAS_PATH`.
+#[derive(Debug, Deserialize)]
+struct SchemaFile {
+    schemas: Vec<SchemaDef>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SchemaDef {
+    name: String,
+    intent: u8,
+    fields: Vec<FieldDef>,
+    #[serde(default)]
+    compression: Option<Compression>,
+}
+
+/// How a schema's payloads are compressed before signing, see
+/// `nautilus_verifier::compression`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    Zstd,
+}
+
+/// zstd level payloads are compressed at.
+#[cfg(feature = "compression")]
+const ZSTD_LEVEL: i32 = 19;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FieldDef {
+    name: String,
+    #[serde(rename = "type")]
+    ty: String,
+}
+
+/// Move type of a schema field. `bytes` is a `vector<u8>` given as a hex
+/// string, `u128` may be given as a decimal string.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldType {
+    Bool,
+    U8,
+    U16,
+    U32,
+    U64,
+    U128,
+    String,
+    Bytes,
+    Vector(Box<FieldType>),
+}
+
+impl FromStr for FieldType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bool" => Ok(Self::Bool),
+            "u8" => Ok(Self::U8),
+            "u16" => Ok(Self::U16),
+            "u32" => Ok(Self::U32),
+            "u64" => Ok(Self::U64),
+            "u128" => Ok(Self::U128),
+            "string" => Ok(Self::String),
+            "bytes" => Ok(Self::Bytes),
+            _ => match s.strip_prefix("vector<").and_then(|s| s.strip_suffix('>')) {
+                Some(inner) => Ok(Self::Vector(Box::new(inner.parse()?))),
+                None => Err(format!("unknown field type `{}`", s)),
+            },
+        }
+    }
+}
+
+/// A named payload shape and the intent scope its payloads are signed under.
+#[derive(Debug, Clone)]
+pub struct Schema {
+    pub name: String,
+    pub intent: u8,
+    pub fields: Vec<(String, FieldType)>,
+    /// When set, payloads are compressed and the enclave signs a commitment
+    /// to them under [IntentScope::Compressed] instead.
+    pub compression: Option<Compression>,
+}
+
+impl Schema {
+    /// Validate `payload` strictly against the schema and return the BCS
+    /// encoding of its fields in schema order.
+    pub fn encode(&self, payload: &Value) -> Result<Vec<u8>, EnclaveError> {
+        let object = payload.as_object().ok_or_else(|| {
+            EnclaveError::GenericError(format!("Payload for `{}` must be an object", self.name))
+        })?;
+        if let Some(unknown) = object
+            .keys()
+            .find(|key| !self.fields.iter().any(|(name, _)| name == *key))
+        {
+            return Err(EnclaveError::GenericError(format!(
+                "Unknown field `{}` for schema `{}`",
+                unknown, self.name
+            )));
+        }
+        let mut bytes = Vec::new();
+        for (name, ty) in &self.fields {
+            let value = object.get(name).ok_or_else(|| {
+                EnclaveError::GenericError(format!(
+                    "Missing field `{}` for schema `{}`",
+                    name, self.name
+                ))
+            })?;
+            encode_value(ty, value, &mut bytes).map_err(|e| {
+                EnclaveError::GenericError(format!(
+                    "Invalid field `{}` for schema `{}`: {}",
+                    name, self.name, e
+                ))
+            })?;
+        }
+        Ok(bytes)
+    }
+}
+
+fn write_uleb128(mut n: usize, out: &mut Vec<u8>) {
+    while n >= 0x80 {
+        out.push((n as u8 & 0x7f) | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+fn encode_value(ty: &FieldType, value: &Value, out: &mut Vec<u8>) -> Result<(), String> {
+    let uint = |max: u64| {
+        value
+            .as_u64()
+            .filter(|n| *n <= max)
+            .ok_or_else(|| format!("expected an integer up to {}, got {}", max, value))
+    };
+    match ty {
+        FieldType::Bool => out.push(
+            value
+                .as_bool()
+                .ok_or_else(|| format!("expected a bool, got {}", value))? as u8,
+        ),
+        FieldType::U8 => out.push(uint(u8::MAX as u64)? as u8),
+        FieldType::U16 => out.extend_from_slice(&(uint(u16::MAX as u64)? as u16).to_le_bytes()),
+        FieldType::U32 => out.extend_from_slice(&(uint(u32::MAX as u64)? as u32).to_le_bytes()),
+        FieldType::U64 => out.extend_from_slice(&uint(u64::MAX)?.to_le_bytes()),
+        FieldType::U128 => {
+            let n = match value {
+                Value::String(s) => s.parse::<u128>().ok(),
+                _ => value.as_u64().map(u128::from),
+            }
+            .ok_or_else(|| format!("expected a u128, got {}", value))?;
+            out.extend_from_slice(&n.to_le_bytes());
+        }
+        FieldType::String => {
+            let s = value
+                .as_str()
+                .ok_or_else(|| format!("expected a string, got {}", value))?;
+            write_uleb128(s.len(), out);
+            out.extend_from_slice(s.as_bytes());
+        }
+        FieldType::Bytes => {
+            let s = value
+                .as_str()
+                .ok_or_else(|| format!("expected a hex string, got {}", value))?;
+            let bytes = Hex::decode(s.strip_prefix("0x").unwrap_or(s))
+                .map_err(|e| format!("expected a hex string: {}", e))?;
+            write_uleb128(bytes.len(), out);
+            out.extend_from_slice(&bytes);
+        }
+        FieldType::Vector(inner) => {
+            let items = value
+                .as_array()
+                .ok_or_else(|| format!("expected an array, got {}", value))?;
+            write_uleb128(items.len(), out);
+            for item in items {
+                encode_value(inner, item, out)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Schemas registered at deploy time let the enclave sign payloads whose shape
+/// is not compiled into `app.rs`. Each schema names its fields and their Move
+/// types; a payload is accepted only if it has exactly those fields with
+/// values of those types, and is signed as the BCS encoding of the Move struct
+/// `{ intent: u8, timestamp_ms: u64, data: <fields in order> }`, the same
+/// layout as [crate::common::IntentMessage].
+///
+/// A schema with `compression: zstd`, for large payloads, is signed instead
+/// as `{ intent: 5, timestamp_ms: u64, data: CompressedPayload }`, committing
+/// to the zstd compression of the encoded fields that is returned in
+/// `compressed`. This needs the `compression` feature.
+///
+/// Schemas are read from the YAML file at `SCHEMAS_PATH`, e.g.
+///
+/// ```yaml
+/// schemas:
+///   - name: price
+///     intent: 16
+///     fields:
+///       - name: symbol
+///         type: string
+///       - name: price
+///         type: u64
+/// ```
+#[derive(Debug, Default)]
+pub struct SchemaRegistry {
+    schemas: HashMap<String, Schema>,
+}
+
+impl SchemaRegistry {
+    /// Load schemas from a YAML file, or none if `path` is unset.
+    pub fn load(path: Option<&str>) -> Result<Self, EnclaveError> {
+        match path {
+            Some(path) => {
+                let yaml = std::fs::read_to_string(path).map_err(|e| {
+                    EnclaveError::GenericError(format!("Failed to read {}: {}", path, e))
+                })?;
+                let registry = Self::from_yaml(&yaml)?;
+                info!(
+                    "Registered {} schema(s) from {}",
+                    registry.schemas.len(),
+                    path
+                );
+                Ok(registry)
+            }
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Parse schemas, rejecting unknown types, duplicate names, duplicate
+    /// intents and intents used by the built-in [IntentScope]s.
+    pub fn from_yaml(yaml: &str) -> Result<Self, EnclaveError> {
+        let file: SchemaFile = serde_yaml::from_str(yaml)
+            .map_err(|e| EnclaveError::GenericError(format!("Invalid schema file: {}", e)))?;
+        let reserved = IntentScope::ALL.map(|scope| scope as u8);
+        let mut schemas: HashMap<String, Schema> = HashMap::new();
+        for def in file.schemas {
+            let invalid = |e: String| {
+                EnclaveError::GenericError(format!("Invalid schema `{}`: {}", def.name, e))
+            };
+            if reserved.contains(&def.intent)
+                || schemas.values().any(|schema| schema.intent == def.intent)
+            {
+                return Err(invalid(format!("intent {} is already in use", def.intent)));
+            }
+            if schemas.contains_key(&def.name) {
+                return Err(invalid("duplicate name".to_string()));
+            }
+            let mut fields: Vec<(String, FieldType)> = Vec::new();
+            for field in &def.fields {
+                if fields.iter().any(|(name, _)| *name == field.name) {
+                    return Err(invalid(format!("duplicate field `{}`", field.name)));
+                }
+                fields.push((field.name.clone(), field.ty.parse().map_err(invalid)?));
+            }
+            if def.compression.is_some() && cfg!(not(feature = "compression")) {
+                return Err(invalid(
+                    "compression requires the `compression` feature".to_string(),
+                ));
+            }
+            schemas.insert(
+                def.name.clone(),
+                Schema {
+                    name: def.name,
+                    intent: def.intent,
+                    fields,
+                    compression: def.compression,
+                },
+            );
+        }
+        Ok(Self { schemas })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Schema> {
+        self.schemas.get(name)
+    }
+
+    /// The intent scope of every registered schema.
+    pub fn intents(&self) -> impl Iterator<Item = u8> + '_ {
+        self.schemas.values().map(|schema| schema.intent)
+    }
+}
+
+/// Intent message for a payload signed under a registered schema. `data` is
+/// echoed as received; the signature covers its BCS encoding.
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+pub struct TypedIntentMessage {
+    pub intent: u8,
+    pub timestamp_ms: u64,
+    pub data: Value,
+}
+
+/// Endpoint that validates a payload against the named schema and signs it
+/// under the schema's intent scope.
+pub async fn sign_typed_data(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    Json(request): Json<ProcessDataRequest<Value>>,
+) -> Result<Json<ProcessedDataResponse<TypedIntentMessage>>, EnclaveError> {
+    let schema = state
+        .schemas
+        .get(&name)
+        .ok_or_else(|| EnclaveError::GenericError(format!("Unknown schema `{}`", name)))?;
+    if let Some(intent) = request.intent.filter(|intent| *intent != schema.intent) {
+        return Err(EnclaveError::GenericError(format!(
+            "Schema `{}` is signed under intent {}, not {}",
+            name, schema.intent, intent
+        )));
+    }
+    let data = schema.encode(&request.payload)?;
+    let timestamp_ms = current_timestamp_ms(&state.config).await?;
+
+    let (intent, data, payload, compressed) = match schema.compression {
+        None => (schema.intent, data, request.payload, None),
+        Some(compression) => {
+            let (commitment, compressed) = compress(schema.intent, &data, compression)?;
+            let payload = serde_json::to_value(&commitment).expect("should not fail");
+            let data = bcs::to_bytes(&commitment).expect("should not fail");
+            let intent = IntentScope::Compressed as u8;
+            (intent, data, payload, Some(Hex::encode(compressed)))
+        }
+    };
+    let mut signing_payload = vec![intent];
+    signing_payload.extend_from_slice(&timestamp_ms.to_le_bytes());
+    signing_payload.extend_from_slice(&data);
+    let sig = state.eph_kp.sign(&signing_payload);
+    state.stats.record_signed(intent);
+
+    Ok(Json(ProcessedDataResponse {
+        response: TypedIntentMessage {
+            intent,
+            timestamp_ms,
+            data: payload,
+        },
+        signature: Hex::encode(sig),
+        jwt: None,
+        signing_format: SigningFormat::Bcs,
+        compressed,
+    }))
+}
+
+/// Compress the BCS bytes `data` of a payload of `intent`, returning the
+/// commitment to sign and the compressed bytes.
+#[cfg(feature = "compression")]
+fn compress(
+    intent: u8,
+    data: &[u8],
+    compression: Compression,
+) -> Result<(CompressedPayload, Vec<u8>), EnclaveError> {
+    match compression {
+        Compression::Zstd => {
+            let compressed = zstd::encode_all(data, ZSTD_LEVEL).map_err(|e| {
+                EnclaveError::GenericError(format!("Failed to compress payload: {}", e))
+            })?;
+            Ok((
+                CompressedPayload::new(intent, data.len() as u64, &compressed),
+                compressed,
+            ))
+        }
+    }
+}
+
+/// Schemas with compression are rejected at load without the feature.
+#[cfg(not(feature = "compression"))]
+fn compress(
+    _intent: u8,
+    _data: &[u8],
+    _compression: Compression,
+) -> Result<(CompressedPayload, Vec<u8>), EnclaveError> {
+    unreachable!("compression requires the `compression` feature")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api_keys::ApiKeys;
+    use crate::config::Config;
+    use fastcrypto::ed25519::{Ed25519KeyPair, Ed25519Signature};
+    use fastcrypto::traits::{KeyPair, ToFromBytes, VerifyingKey};
+    use serde_json::json;
+
+    const SCHEMAS: &str = "
+schemas:
+  - name: price
+    intent: 16
+    fields:
+      - name: symbol
+        type: string
+      - name: price
+        type: u64
+      - name: sources
+        type: vector<u8>
+      - name: proof
+        type: bytes
+";
+
+    fn price_schema() -> Schema {
+        SchemaRegistry::from_yaml(SCHEMAS)
+            .unwrap()
+            .get("price")
+            .unwrap()
+            .clone()
+    }
+
+    #[test]
+    fn test_conforming_payload() {
+        let payload = json!({
+            "symbol": "SUI",
+            "price": 4_250_000,
+            "sources": [1, 2],
+            "proof": "0xabcd",
+        });
+        let encoded = price_schema().encode(&payload).unwrap();
+        let expected = bcs::to_bytes(&(
+            "SUI".to_string(),
+            4_250_000u64,
+            vec![1u8, 2],
+            vec![0xabu8, 0xcd],
+        ))
+        .unwrap();
+        assert_eq!(encoded, expected);
+    }
+
+    #[test]
+    fn test_non_conforming_payload() {
+        let schema = price_schema();
+        for payload in [
+            json!({ "symbol": "SUI", "price": 1, "sources": [] }),
+            json!({ "symbol": "SUI", "price": 1, "sources": [], "proof": "", "extra": 1 }),
+            json!({ "symbol": "SUI", "price": -1, "sources": [], "proof": "" }),
+            json!({ "symbol": "SUI", "price": 1.5, "sources": [], "proof": "" }),
+            json!({ "symbol": 1, "price": 1, "sources": [], "proof": "" }),
+            json!({ "symbol": "SUI", "price": 1, "sources": [256], "proof": "" }),
+            json!({ "symbol": "SUI", "price": 1, "sources": [], "proof": "xyz" }),
+            json!(["SUI", 1, [], ""]),
+        ] {
+            assert!(schema.encode(&payload).is_err(), "{}", payload);
+        }
+    }
+
+    #[test]
+    fn test_invalid_schemas() {
+        for yaml in [
+            "schemas: [{ name: a, intent: 0, fields: [] }]",
+            "schemas: [{ name: a, intent: 5, fields: [] }]",
+            "schemas: [{ name: a, intent: 16, compression: gzip, fields: [] }]",
+            "schemas: [{ name: a, intent: 16, fields: [] }, { name: b, intent: 16, fields: [] }]",
+            "schemas: [{ name: a, intent: 16, fields: [] }, { name: a, intent: 17, fields: [] }]",
+            "schemas: [{ name: a, intent: 16, fields: [{ name: x, type: f64 }] }]",
+            "schemas: [{ name: a, intent: 16, fields: [{ name: x, type: u8 }, { name: x, type: u8 }] }]",
+        ] {
+            assert!(SchemaRegistry::from_yaml(yaml).is_err(), "{}", yaml);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sign_typed_data() {
+        let mut state = AppState::new(
+            Ed25519KeyPair::generate(&mut rand::thread_rng()),
+            ApiKeys::single(String::new()),
+            Config::default(),
+        )
+        .unwrap();
+        state.schemas = SchemaRegistry::from_yaml(SCHEMAS).unwrap();
+        let pk = state.eph_kp.public().clone();
+        let state = Arc::new(state);
+        let payload = json!({ "symbol": "SUI", "price": 1, "sources": [], "proof": "" });
+
+        let signed = sign_typed_data(
+            State(state.clone()),
+            Path("price".to_string()),
+            Json(ProcessDataRequest {
+                payload: payload.clone(),
+                intent: None,
+            }),
+        )
+        .await
+        .unwrap();
+        let mut signing_payload = vec![16];
+        signing_payload.extend_from_slice(&signed.response.timestamp_ms.to_le_bytes());
+        signing_payload.extend_from_slice(&price_schema().encode(&payload).unwrap());
+        let sig = Ed25519Signature::from_bytes(&Hex::decode(&signed.signature).unwrap()).unwrap();
+        assert!(pk.verify(&signing_payload, &sig).is_ok());
+
+        let wrong_intent = sign_typed_data(
+            State(state.clone()),
+            Path("price".to_string()),
+            Json(ProcessDataRequest {
+                payload: payload.clone(),
+                intent: Some(0),
+            }),
+        )
+        .await;
+        assert!(wrong_intent.is_err());
+
+        let unknown = sign_typed_data(
+            State(state),
+            Path("weather".to_string()),
+            Json(ProcessDataRequest {
+                payload,
+                intent: None,
+            }),
+        )
+        .await;
+        assert!(unknown.is_err());
+    }
+
+    #[cfg(feature = "compression")]
+    #[tokio::test]
+    async fn test_sign_compressed_typed_data() {
+        use nautilus_verifier::compression::decompress;
+        use nautilus_verifier::intent;
+
+        let mut state = AppState::new(
+            Ed25519KeyPair::generate(&mut rand::thread_rng()),
+            ApiKeys::single(String::new()),
+            Config::default(),
+        )
+        .unwrap();
+        state.schemas = SchemaRegistry::from_yaml(
+            "schemas: [{ name: report, intent: 17, compression: zstd, fields: [{ name: body, type: string }] }]",
+        )
+        .unwrap();
+        let pk = state.eph_kp.public().clone();
+        let payload = json!({ "body": "Clear skies over San Francisco. ".repeat(200) });
+
+        let Json(signed) = sign_typed_data(
+            State(Arc::new(state)),
+            Path("report".to_string()),
+            Json(ProcessDataRequest {
+                payload: payload.clone(),
+                intent: None,
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(signed.response.intent, intent::COMPRESSED);
+        let commitment: CompressedPayload =
+            serde_json::from_value(signed.response.data.clone()).unwrap();
+        assert_eq!(commitment.payload_intent, 17);
+
+        // The signature covers the commitment
+        let mut signing_payload = vec![intent::COMPRESSED];
+        signing_payload.extend_from_slice(&signed.response.timestamp_ms.to_le_bytes());
+        signing_payload.extend_from_slice(&bcs::to_bytes(&commitment).unwrap());
+        let sig = Ed25519Signature::from_bytes(&Hex::decode(&signed.signature).unwrap()).unwrap();
+        assert!(pk.verify(&signing_payload, &sig).is_ok());
+
+        // and decompressing gives back the payload's BCS bytes
+        let compressed = Hex::decode(signed.compressed.as_ref().unwrap()).unwrap();
+        let schema = SchemaRegistry::from_yaml(
+            "schemas: [{ name: report, intent: 17, fields: [{ name: body, type: string }] }]",
+        )
+        .unwrap();
+        let expected = schema.get("report").unwrap().encode(&payload).unwrap();
+        assert_eq!(decompress(&commitment, &compressed).unwrap(), expected);
+        assert!(compressed.len() * 10 < expected.len());
+    }
+}
@@ -0,0 +1,250 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::common::bearer_matches;
+use crate::AppState;
+use crate::EnclaveError;
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Cumulative counters for `/stats`. They live as long as the process, so
+/// they start over from zero whenever the enclave restarts.
+pub struct Stats {
+    signed: BTreeMap<u8, AtomicU64>,
+    attestations: AtomicU64,
+    upstream_successes: AtomicU64,
+    upstream_failures: AtomicU64,
+    attestation_cache_hits: AtomicU64,
+    attestation_cache_misses: AtomicU64,
+}
+
+/// Response for stats.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+pub struct StatsResponse {
+    /// Responses signed under each intent scope, keyed by its `u8` value,
+    /// including the scopes of registered schemas.
+    pub signed_responses: BTreeMap<u8, u64>,
+    /// Attestation documents generated by the NSM, at boot, for
+    /// `/get_attestation` or periodically.
+    pub attestations: u64,
+    /// Weather readings fetched from the upstream.
+    pub upstream_successes: u64,
+    /// Upstream fetches that failed, whatever the reason.
+    pub upstream_failures: u64,
+    /// `/get_attestation` calls answered from the attestation cache.
+    pub attestation_cache_hits: u64,
+    /// `/get_attestation` calls that generated a document for the cache.
+    pub attestation_cache_misses: u64,
+}
+
+impl Stats {
+    /// Counters for responses signed under `intents`. Signing under any
+    /// other intent isn't counted.
+    pub fn new(intents: impl IntoIterator<Item = u8>) -> Self {
+        Self {
+            signed: intents
+                .into_iter()
+                .map(|intent| (intent, AtomicU64::new(0)))
+                .collect(),
+            attestations: AtomicU64::new(0),
+            upstream_successes: AtomicU64::new(0),
+            upstream_failures: AtomicU64::new(0),
+            attestation_cache_hits: AtomicU64::new(0),
+            attestation_cache_misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn record_signed(&self, intent: u8) {
+        if let Some(counter) = self.signed.get(&intent) {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn record_attestation(&self) {
+        self.attestations.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_upstream(&self, success: bool) {
+        let counter = if success {
+            &self.upstream_successes
+        } else {
+            &self.upstream_failures
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_attestation_cache(&self, hit: bool) {
+        let counter = if hit {
+            &self.attestation_cache_hits
+        } else {
+            &self.attestation_cache_misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Current value of every counter. Each is read separately, so a
+    /// snapshot taken under load may count an event in one counter and not
+    /// yet in another.
+    pub fn snapshot(&self) -> StatsResponse {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        StatsResponse {
+            signed_responses: self
+                .signed
+                .iter()
+                .map(|(intent, counter)| (*intent, load(counter)))
+                .collect(),
+            attestations: load(&self.attestations),
+            upstream_successes: load(&self.upstream_successes),
+            upstream_failures: load(&self.upstream_failures),
+            attestation_cache_hits: load(&self.attestation_cache_hits),
+            attestation_cache_misses: load(&self.attestation_cache_misses),
+        }
+    }
+}
+
+/// When each data source last returned data that was signed, reported by
+/// `/health_check`. An upstream that answers with errors or unparseable
+/// data while still reachable passes the connectivity checks, but the time
+/// since its last success keeps growing.
+pub struct LastFetches {
+    sources: BTreeMap<&'static str, Mutex<Option<Instant>>>,
+}
+
+impl LastFetches {
+    /// Tracks `sources`. A success from any other source isn't tracked.
+    pub fn new(sources: impl IntoIterator<Item = &'static str>) -> Self {
+        Self {
+            sources: sources
+                .into_iter()
+                .map(|source| (source, Mutex::new(None)))
+                .collect(),
+        }
+    }
+
+    pub fn record(&self, source: &str) {
+        self.record_at(source, Instant::now());
+    }
+
+    fn record_at(&self, source: &str, now: Instant) {
+        if let Some(last) = self.sources.get(source) {
+            *last.lock().unwrap() = Some(now);
+        }
+    }
+
+    /// Milliseconds since each source's last success, or `None` for a
+    /// source that hasn't succeeded since startup.
+    pub fn elapsed_ms(&self) -> BTreeMap<String, Option<u64>> {
+        self.elapsed_ms_at(Instant::now())
+    }
+
+    fn elapsed_ms_at(&self, now: Instant) -> BTreeMap<String, Option<u64>> {
+        self.sources
+            .iter()
+            .map(|(source, last)| {
+                let elapsed = last
+                    .lock()
+                    .unwrap()
+                    .map(|last| now.saturating_duration_since(last).as_millis() as u64);
+                (source.to_string(), elapsed)
+            })
+            .collect()
+    }
+}
+
+/// Admin endpoint that returns the counters since startup, authorized with
+/// `Authorization: Bearer <ADMIN_TOKEN>`. Only served when `ADMIN_TOKEN` is
+/// set.
+pub async fn stats(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<Json<StatsResponse>, EnclaveError> {
+    let token = state.config.admin_token.as_deref().unwrap_or_default();
+    if token.is_empty() || !bearer_matches(&headers, token) {
+        return Err(EnclaveError::Unauthorized(
+            "Invalid admin token".to_string(),
+        ));
+    }
+    Ok(Json(state.stats.snapshot()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::IntentScope;
+
+    #[test]
+    fn test_counters_from_many_threads() {
+        let stats = Arc::new(Stats::new([IntentScope::Weather as u8, 16]));
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let stats = stats.clone();
+                std::thread::spawn(move || {
+                    for i in 0..1000 {
+                        stats.record_signed(if i % 4 == 0 { 16 } else { 0 });
+                        stats.record_upstream(i % 10 != 0);
+                        stats.record_attestation_cache(i % 2 == 0);
+                    }
+                    stats.record_attestation();
+                })
+            })
+            .collect();
+        threads.into_iter().for_each(|t| t.join().unwrap());
+        // An unregistered intent is ignored
+        stats.record_signed(99);
+
+        assert_eq!(
+            stats.snapshot(),
+            StatsResponse {
+                signed_responses: [(0, 6000), (16, 2000)].into_iter().collect(),
+                attestations: 8,
+                upstream_successes: 7200,
+                upstream_failures: 800,
+                attestation_cache_hits: 4000,
+                attestation_cache_misses: 4000,
+            }
+        );
+        assert_eq!(
+            serde_json::to_value(stats.snapshot()).unwrap()["signed_responses"]["16"],
+            2000
+        );
+    }
+
+    #[test]
+    fn test_last_fetches() {
+        let last_fetches = LastFetches::new(["weather", "price"]);
+        let start = Instant::now();
+        assert_eq!(
+            last_fetches.elapsed_ms_at(start),
+            [("price".to_string(), None), ("weather".to_string(), None)]
+                .into_iter()
+                .collect()
+        );
+
+        last_fetches.record_at("weather", start);
+        // An untracked source is ignored
+        last_fetches.record_at("github", start);
+        let later = start + std::time::Duration::from_millis(1500);
+        assert_eq!(
+            last_fetches.elapsed_ms_at(later),
+            [
+                ("price".to_string(), None),
+                ("weather".to_string(), Some(1500))
+            ]
+            .into_iter()
+            .collect()
+        );
+
+        last_fetches.record_at("weather", later);
+        last_fetches.record_at("price", start);
+        let elapsed = last_fetches.elapsed_ms_at(later);
+        assert_eq!(elapsed["weather"], Some(0));
+        assert_eq!(elapsed["price"], Some(1500));
+    }
+}
@@ -0,0 +1,458 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Arguments for the Move calls that register the enclave and submit its
+//! signed data, so they needn't be assembled by hand as in
+//! `register_enclave.sh` and `update_weather.sh`. Pure arguments are BCS
+//! encoded, ready for a Sui SDK's `pure` inputs. With the `sui-sdk` feature,
+//! whole `ProgrammableTransaction`s are built as well.
+
+use crate::app::WeatherResponse;
+use crate::common::{IntentMessage, ProcessedDataResponse, SigningFormat};
+use crate::EnclaveError;
+use fastcrypto::encoding::{Encoding, Hex};
+use serde::Serialize;
+use std::fmt;
+
+/// Length of a Sui object or package ID.
+pub const OBJECT_ID_LEN: usize = 32;
+
+/// A Sui object or package ID.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ObjectId(pub [u8; OBJECT_ID_LEN]);
+
+impl ObjectId {
+    /// Parse a hex ID with its `0x` prefix, padding short IDs such as `0x2`
+    /// with leading zeros as Sui does.
+    pub fn parse(id: &str) -> Result<Self, EnclaveError> {
+        let invalid = || EnclaveError::GenericError(format!("Invalid object ID: {:?}", id));
+        let digits = id.strip_prefix("0x").ok_or_else(invalid)?;
+        if digits.is_empty() || digits.len() > 2 * OBJECT_ID_LEN {
+            return Err(invalid());
+        }
+        let padded = format!("{:0>width$}", digits, width = 2 * OBJECT_ID_LEN);
+        let bytes = Hex::decode(&padded).map_err(|_| invalid())?;
+        Ok(Self(bytes.try_into().map_err(|_| invalid())?))
+    }
+}
+
+impl fmt::Display for ObjectId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{}", Hex::encode(self.0))
+    }
+}
+
+/// The `sui::` framework package, home of `nitro_attestation`.
+pub const SUI_FRAMEWORK: ObjectId = ObjectId(sui_address(2));
+
+/// The shared `Clock` object, `@0x6` in `sui client ptb`.
+pub const CLOCK: ObjectId = ObjectId(sui_address(6));
+
+const fn sui_address(last: u8) -> [u8; OBJECT_ID_LEN] {
+    let mut id = [0; OBJECT_ID_LEN];
+    id[OBJECT_ID_LEN - 1] = last;
+    id
+}
+
+/// Where the enclave and app Move code is published, as passed to
+/// `register_enclave.sh`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Targets {
+    /// Package of `move/enclave`.
+    pub enclave_package: ObjectId,
+    /// Package of the app, e.g. `move/app`.
+    pub app_package: ObjectId,
+    /// App module, e.g. `weather`.
+    pub module: String,
+    /// One-time witness type of the app module, e.g. `WEATHER`.
+    pub otw: String,
+}
+
+impl Targets {
+    pub fn new(
+        enclave_package: &str,
+        app_package: &str,
+        module: &str,
+        otw: &str,
+    ) -> Result<Self, EnclaveError> {
+        for name in [module, otw] {
+            let mut chars = name.chars();
+            let valid = chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+                && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+            if !valid {
+                return Err(EnclaveError::GenericError(format!(
+                    "Invalid Move identifier: {:?}",
+                    name
+                )));
+            }
+        }
+        Ok(Self {
+            enclave_package: ObjectId::parse(enclave_package)?,
+            app_package: ObjectId::parse(app_package)?,
+            module: module.to_string(),
+            otw: otw.to_string(),
+        })
+    }
+
+    /// The type argument of `register_enclave` and the app's functions, e.g.
+    /// `0x...::weather::WEATHER`.
+    pub fn otw_type(&self) -> String {
+        format!("{}::{}::{}", self.app_package, self.module, self.otw)
+    }
+
+    /// `0x2::nitro_attestation::load_nitro_attestation`, whose result is
+    /// passed to [Self::register_enclave].
+    pub fn load_nitro_attestation(&self) -> String {
+        format!(
+            "{}::nitro_attestation::load_nitro_attestation",
+            SUI_FRAMEWORK
+        )
+    }
+
+    /// `enclave::register_enclave<OTW>`.
+    pub fn register_enclave(&self) -> String {
+        format!(
+            "{}::enclave::register_enclave<{}>",
+            self.enclave_package,
+            self.otw_type()
+        )
+    }
+
+    /// The app function that verifies and accepts signed data, with the
+    /// variant for `args`: `update_weather` or, for messages signed with an
+    /// expiry, `update_weather_with_expiry`. `move/app` has no variant for
+    /// sequence numbers.
+    pub fn update_weather(&self, args: &SubmitArgs) -> Result<String, EnclaveError> {
+        Ok(format!(
+            "{}::{}::{}<{}>",
+            self.app_package,
+            self.module,
+            update_weather_function(args)?,
+            self.otw_type()
+        ))
+    }
+}
+
+/// Name of the `update_weather` variant that takes `args`.
+fn update_weather_function(args: &SubmitArgs) -> Result<&'static str, EnclaveError> {
+    match (&args.expires_at_ms, &args.sequence) {
+        (None, None) => Ok("update_weather"),
+        (Some(_), None) => Ok("update_weather_with_expiry"),
+        (_, Some(_)) => Err(EnclaveError::GenericError(
+            "update_weather doesn't take sequence numbers, see verify_signature_with_sequence"
+                .to_string(),
+        )),
+    }
+}
+
+/// The pure argument of `load_nitro_attestation`: the attestation document,
+/// as returned by `/get_attestation` once hex decoded, as a BCS
+/// `vector<u8>`. Its other argument is the [CLOCK], and its result and the
+/// enclave config object are the arguments of `register_enclave`.
+pub fn register_enclave_args(document: &[u8]) -> Vec<u8> {
+    bcs::to_bytes(document).expect("should not fail")
+}
+
+/// A signed payload as the Move function accepting it takes it: one pure
+/// argument per field, BCS encoded, in the order of the Move struct, as
+/// `update_weather` takes `location` and `temperature` to rebuild
+/// `WeatherResponse`. Implement it for your own payload types.
+pub trait MoveArgs {
+    fn move_args(&self) -> Vec<Vec<u8>>;
+}
+
+impl MoveArgs for WeatherResponse {
+    fn move_args(&self) -> Vec<Vec<u8>> {
+        vec![
+            bcs::to_bytes(&self.location).expect("should not fail"),
+            bcs::to_bytes(&self.temperature).expect("should not fail"),
+        ]
+    }
+}
+
+/// Pure arguments, BCS encoded, for submitting a signed response to Move.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubmitArgs {
+    /// The fields of the signed data, see [MoveArgs].
+    pub payload: Vec<Vec<u8>>,
+    /// `timestamp_ms` as a `u64`.
+    pub timestamp_ms: Vec<u8>,
+    /// `expires_at_ms` as a `u64`, if signed.
+    pub expires_at_ms: Option<Vec<u8>>,
+    /// `sequence` as a `u64`, if signed.
+    pub sequence: Option<Vec<u8>>,
+    /// The signature as a `vector<u8>`.
+    pub signature: Vec<u8>,
+}
+
+impl SubmitArgs {
+    /// Pure arguments of a signed response. Only BCS signed responses can be
+    /// checked by `enclave.move`.
+    pub fn new<T: Serialize + MoveArgs>(
+        response: &ProcessedDataResponse<IntentMessage<T>>,
+    ) -> Result<Self, EnclaveError> {
+        if response.signing_format != SigningFormat::Bcs {
+            return Err(EnclaveError::GenericError(
+                "Move verifies BCS signatures, the response was signed over JCS".to_string(),
+            ));
+        }
+        let signature = Hex::decode(&response.signature)
+            .ok()
+            .filter(|signature| signature.len() == 64)
+            .ok_or_else(|| {
+                EnclaveError::GenericError("signature is not 64 hex encoded bytes".to_string())
+            })?;
+        let u64_arg = |value: &u64| bcs::to_bytes(value).expect("should not fail");
+        let message = &response.response;
+        Ok(Self {
+            payload: message.data.move_args(),
+            timestamp_ms: u64_arg(&message.timestamp_ms),
+            expires_at_ms: message.expires_at_ms.as_ref().map(u64_arg),
+            sequence: message.sequence.as_ref().map(u64_arg),
+            signature: bcs::to_bytes(&signature).expect("should not fail"),
+        })
+    }
+
+    /// The arguments in the order of the app's Move function: the payload
+    /// fields, `timestamp_ms`, `expires_at_ms` or `sequence` if signed, then
+    /// the signature. The enclave object, and the [CLOCK] for expiring
+    /// messages, follow as object arguments.
+    pub fn to_vec(&self) -> Vec<Vec<u8>> {
+        let mut args = self.payload.clone();
+        args.push(self.timestamp_ms.clone());
+        args.extend(self.expires_at_ms.clone());
+        args.extend(self.sequence.clone());
+        args.push(self.signature.clone());
+        args
+    }
+}
+
+/// Transactions built with the Sui SDK, with the `sui-sdk` feature. Shared
+/// objects are passed as [ObjectArg]s, since they need their initial shared
+/// version, which only a full node knows.
+#[cfg(feature = "sui-sdk")]
+pub mod sdk {
+    use super::{register_enclave_args, SubmitArgs, Targets, CLOCK, SUI_FRAMEWORK};
+    use crate::EnclaveError;
+    use sui_sdk::types::base_types::{ObjectID, SuiAddress};
+    use sui_sdk::types::programmable_transaction_builder::ProgrammableTransactionBuilder;
+    use sui_sdk::types::transaction::{ObjectArg, ProgrammableTransaction};
+    use sui_sdk::types::{parse_sui_type_tag, Identifier, SUI_CLOCK_OBJECT_SHARED_VERSION};
+
+    fn error(e: impl std::fmt::Display) -> EnclaveError {
+        EnclaveError::GenericError(format!("Failed to build the transaction: {}", e))
+    }
+
+    fn identifier(name: &str) -> Result<Identifier, EnclaveError> {
+        Identifier::new(name).map_err(error)
+    }
+
+    fn clock() -> ObjectArg {
+        ObjectArg::SharedObject {
+            id: ObjectID::new(CLOCK.0),
+            initial_shared_version: SUI_CLOCK_OBJECT_SHARED_VERSION,
+            mutable: false,
+        }
+    }
+
+    /// `load_nitro_attestation` of `document` followed by `register_enclave`
+    /// with the enclave `config`, as `register_enclave.sh` runs them.
+    pub fn register_enclave(
+        targets: &Targets,
+        config: ObjectArg,
+        document: &[u8],
+    ) -> Result<ProgrammableTransaction, EnclaveError> {
+        let mut ptb = ProgrammableTransactionBuilder::new();
+        let document = ptb.pure_bytes(register_enclave_args(document), false);
+        let clock = ptb.obj(clock()).map_err(error)?;
+        let attestation = ptb.programmable_move_call(
+            ObjectID::new(SUI_FRAMEWORK.0),
+            identifier("nitro_attestation")?,
+            identifier("load_nitro_attestation")?,
+            vec![],
+            vec![document, clock],
+        );
+        let config = ptb.obj(config).map_err(error)?;
+        ptb.programmable_move_call(
+            ObjectID::new(targets.enclave_package.0),
+            identifier("enclave")?,
+            identifier("register_enclave")?,
+            vec![parse_sui_type_tag(&targets.otw_type()).map_err(error)?],
+            vec![config, attestation],
+        );
+        Ok(ptb.finish())
+    }
+
+    /// `update_weather`, or `update_weather_with_expiry`, with `args` and
+    /// the `enclave` object, sending the minted NFT to `recipient` as
+    /// `update_weather.sh` does.
+    pub fn update_weather(
+        targets: &Targets,
+        enclave: ObjectArg,
+        args: &SubmitArgs,
+        recipient: SuiAddress,
+    ) -> Result<ProgrammableTransaction, EnclaveError> {
+        let function = super::update_weather_function(args)?;
+        let mut ptb = ProgrammableTransactionBuilder::new();
+        let mut call_args: Vec<_> = args
+            .to_vec()
+            .into_iter()
+            .map(|arg| ptb.pure_bytes(arg, true))
+            .collect();
+        call_args.push(ptb.obj(enclave).map_err(error)?);
+        if args.expires_at_ms.is_some() {
+            call_args.push(ptb.obj(clock()).map_err(error)?);
+        }
+        let nft = ptb.programmable_move_call(
+            ObjectID::new(targets.app_package.0),
+            identifier(&targets.module)?,
+            identifier(function)?,
+            vec![parse_sui_type_tag(&targets.otw_type()).map_err(error)?],
+            call_args,
+        );
+        ptb.transfer_arg(recipient, nft);
+        Ok(ptb.finish())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::IntentScope;
+    use nautilus_verifier::signing_bytes;
+
+    const ENCLAVE_PACKAGE: &str =
+        "0x872852f77545c86a8bd9bdb8adc9e686b8573fc2a0dab0af44864bc1aecdaea9";
+    const APP_PACKAGE: &str = "0x2b70e34684d696a0a2847c793ee1e5b88a23289a7c04dd46249b95a9823367d9";
+
+    fn targets() -> Targets {
+        Targets::new(ENCLAVE_PACKAGE, APP_PACKAGE, "weather", "WEATHER").unwrap()
+    }
+
+    fn response(
+        expires_at_ms: Option<u64>,
+    ) -> ProcessedDataResponse<IntentMessage<WeatherResponse>> {
+        ProcessedDataResponse {
+            response: IntentMessage {
+                intent: IntentScope::Weather,
+                version: nautilus_verifier::layout_version(expires_at_ms, None),
+                timestamp_ms: 1_744_038_900_000,
+                expires_at_ms,
+                sequence: None,
+                data: WeatherResponse {
+                    location: "Paris".to_string(),
+                    temperature: 13,
+                },
+            },
+            signature: "ab".repeat(64),
+            jwt: None,
+            signing_format: SigningFormat::Bcs,
+            compressed: None,
+        }
+    }
+
+    #[test]
+    fn test_object_id() {
+        assert_eq!(ObjectId::parse("0x2").unwrap(), SUI_FRAMEWORK);
+        assert_eq!(
+            CLOCK.to_string(),
+            "0x0000000000000000000000000000000000000000000000000000000000000006"
+        );
+        assert_eq!(
+            ObjectId::parse(ENCLAVE_PACKAGE).unwrap().to_string(),
+            ENCLAVE_PACKAGE
+        );
+        for invalid in ["2", "0x", "0xzz", &format!("0x{}", "1".repeat(65))] {
+            assert!(ObjectId::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_targets() {
+        let targets = targets();
+        assert_eq!(
+            targets.register_enclave(),
+            format!(
+                "{}::enclave::register_enclave<{}::weather::WEATHER>",
+                ENCLAVE_PACKAGE, APP_PACKAGE
+            )
+        );
+        assert_eq!(
+            targets.load_nitro_attestation(),
+            format!(
+                "{}::nitro_attestation::load_nitro_attestation",
+                SUI_FRAMEWORK
+            )
+        );
+        let args = SubmitArgs::new(&response(Some(1))).unwrap();
+        assert_eq!(
+            targets.update_weather(&args).unwrap(),
+            format!(
+                "{0}::weather::update_weather_with_expiry<{0}::weather::WEATHER>",
+                APP_PACKAGE
+            )
+        );
+        assert!(Targets::new(ENCLAVE_PACKAGE, APP_PACKAGE, "weather", "1WEATHER").is_err());
+        assert!(Targets::new(ENCLAVE_PACKAGE, APP_PACKAGE, "weather::x", "WEATHER").is_err());
+    }
+
+    #[test]
+    fn test_register_enclave_args() {
+        // vector<u8>: ULEB128 length, then the bytes
+        assert_eq!(register_enclave_args(&[1, 2, 3]), [3, 1, 2, 3]);
+        let document = vec![0xd2; 200];
+        let args = register_enclave_args(&document);
+        assert_eq!(args[..2], [0xc8, 0x01]);
+        assert_eq!(args[2..], document[..]);
+    }
+
+    #[test]
+    fn test_submit_args() {
+        let args = SubmitArgs::new(&response(None)).unwrap();
+        let encoded: Vec<String> = args.to_vec().iter().map(Hex::encode).collect();
+        assert_eq!(
+            encoded,
+            [
+                // location: String
+                "055061726973".to_string(),
+                // temperature: u64
+                "0d00000000000000".to_string(),
+                // timestamp_ms: u64
+                "20b1d11096010000".to_string(),
+                // sig: vector<u8>
+                format!("40{}", "ab".repeat(64)),
+            ]
+        );
+
+        // Move rebuilds the signed intent message from the same bytes
+        let signed = signing_bytes(SigningFormat::Bcs, &response(None).response).unwrap();
+        let mut rebuilt = vec![IntentScope::Weather as u8];
+        rebuilt.extend_from_slice(&args.timestamp_ms);
+        rebuilt.extend(args.payload.concat());
+        assert_eq!(rebuilt, signed);
+
+        let with_expiry = SubmitArgs::new(&response(Some(1_744_038_960_000))).unwrap();
+        assert_eq!(with_expiry.to_vec().len(), 5);
+        assert_eq!(
+            Hex::encode(&with_expiry.to_vec()[3]),
+            Hex::encode(1_744_038_960_000u64.to_le_bytes())
+        );
+    }
+
+    #[test]
+    fn test_submit_args_rejected() {
+        let mut jcs = response(None);
+        jcs.signing_format = SigningFormat::Jcs;
+        assert!(SubmitArgs::new(&jcs).is_err());
+
+        let mut short = response(None);
+        short.signature = "ab".repeat(63);
+        assert!(SubmitArgs::new(&short).is_err());
+
+        let mut sequenced = response(None);
+        sequenced.response.version = Some(nautilus_verifier::intent::SEQUENCE_LAYOUT);
+        sequenced.response.sequence = Some(7);
+        let args = SubmitArgs::new(&sequenced).unwrap();
+        assert!(targets().update_weather(&args).is_err());
+    }
+}
@@ -0,0 +1,217 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Helpers for testing the server in-process, with the real router in front
+//! of a mock NSM document and mock upstreams. Used by the unit tests and by
+//! `tests/integration.rs`; forks can use them from their own tests with the
+//! `test-utils` feature:
+//!
+//! ```toml
+//! [dev-dependencies]
+//! nautilus-server = { path = "../nautilus-server", features = ["test-utils"] }
+//! ```
+
+use crate::api_keys::ApiKeys;
+use crate::attestation::AttestationDocument;
+use crate::common::{wall_clock_ms, HealthCheckResponse};
+use crate::config::Config;
+use crate::header_limits::HeaderLimits;
+use crate::health::EndpointsOverflow;
+use crate::routes::{router, serve, TrailingSlash};
+use crate::AppState;
+use axum::extract::Query;
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::Json;
+use fastcrypto::ed25519::Ed25519KeyPair;
+use fastcrypto::encoding::{Encoding, Hex};
+use fastcrypto::traits::{KeyPair, ToFromBytes};
+use nautilus_verifier::{verify_response, SignedResponse};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_bytes::ByteBuf;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+
+/// Location [mock_weather_upstream] answers as api.weatherapi.com answers an
+/// unknown one.
+pub const UNKNOWN_LOCATION: &str = "Atlantis";
+
+/// Temperature [mock_weather_upstream] reports at every other location.
+pub const MOCK_TEMPERATURE: u64 = 13;
+
+/// A COSE_Sign1 document as the NSM would return it for `payload`, with an
+/// empty protected header and signature numbered `serial`.
+pub fn mock_document(payload: &AttestationDocument, serial: u8) -> Vec<u8> {
+    let payload = serde_cbor::to_vec(payload).unwrap();
+    serde_cbor::to_vec(&(
+        ByteBuf::new(),
+        BTreeMap::<u8, u8>::new(),
+        ByteBuf::from(payload),
+        ByteBuf::from(vec![serial; 96]),
+    ))
+    .unwrap()
+}
+
+/// The payload of a document from an enclave with `public_key`, PCR0 filled
+/// with `pcr0` and PCR1 and PCR2 with their index.
+pub fn mock_payload(public_key: &[u8], pcr0: u8, timestamp: u64) -> AttestationDocument {
+    AttestationDocument {
+        module_id: "i-0123456789abcdef0-enc0123456789abcdef".to_string(),
+        digest: "SHA384".to_string(),
+        timestamp,
+        pcrs: (0..3)
+            .map(|index| {
+                let value = if index == 0 { pcr0 } else { index as u8 };
+                (index, ByteBuf::from(vec![value; 48]))
+            })
+            .collect(),
+        public_key: Some(ByteBuf::from(public_key.to_vec())),
+        user_data: None,
+        nonce: None,
+        certificate: None,
+        cabundle: Vec::new(),
+    }
+}
+
+/// [Config::default] with `weather_api_url` as the upstream, the attestation
+/// cache on so a seeded document is served, and no allowed endpoint probed
+/// by health checks, so nothing leaves the machine.
+pub fn test_config(weather_api_url: reqwest::Url) -> Config {
+    Config {
+        weather_api_url,
+        attestation_cache_ttl: Duration::from_secs(3600),
+        max_allowed_endpoints: 0,
+        allowed_endpoints_overflow: EndpointsOverflow::Truncate,
+        ..Config::default()
+    }
+}
+
+/// A server running the real router on a local port.
+pub struct TestEnclave {
+    /// Base URL, without a trailing slash.
+    pub url: String,
+    pub state: Arc<AppState>,
+    /// The enclave's ephemeral public key.
+    pub public_key: Vec<u8>,
+    /// The mock document `/get_attestation` serves, attesting `public_key`
+    /// with PCR0 filled with `0xaa`.
+    pub document: Vec<u8>,
+}
+
+impl TestEnclave {
+    /// Start a server with `config` and a fresh key. The NSM only exists
+    /// inside an enclave, so its attestation cache is seeded with a mock
+    /// document instead.
+    pub async fn start(config: Config) -> Self {
+        let eph_kp = Ed25519KeyPair::generate(&mut rand::thread_rng());
+        let public_key = eph_kp.public().as_bytes().to_vec();
+        let state =
+            Arc::new(AppState::new(eph_kp, ApiKeys::single(String::new()), config).unwrap());
+        let generated_at_ms = wall_clock_ms();
+        let document = mock_document(&mock_payload(&public_key, 0xaa, generated_at_ms), 0);
+        state
+            .attestation_cache
+            .seed(document.clone(), generated_at_ms);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(serve(
+            listener,
+            router(state.clone()),
+            TrailingSlash::Strict,
+            HeaderLimits::from_config(&state.config),
+        ));
+        Self {
+            url,
+            state,
+            public_key,
+            document,
+        }
+    }
+
+    /// POST `body` to `path`, returning the status and JSON body, or the
+    /// body as a JSON string if it isn't JSON.
+    pub async fn post(&self, path: &str, body: &Value) -> (StatusCode, Value) {
+        let response = reqwest::Client::new()
+            .post(format!("{}{}", self.url, path))
+            .json(body)
+            .send()
+            .await
+            .unwrap();
+        json_response(response).await
+    }
+
+    /// GET `path`, returning the status and body as for [TestEnclave::post].
+    pub async fn get(&self, path: &str) -> (StatusCode, Value) {
+        let response = reqwest::get(format!("{}{}", self.url, path)).await.unwrap();
+        json_response(response).await
+    }
+
+    /// The public key `/health_check` reports, as a client learns it.
+    pub async fn health_check_public_key(&self) -> Vec<u8> {
+        let (status, body) = self.get("/health_check").await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let health: HealthCheckResponse = serde_json::from_value(body).unwrap();
+        Hex::decode(&health.pk).unwrap()
+    }
+}
+
+async fn json_response(response: reqwest::Response) -> (StatusCode, Value) {
+    let status = StatusCode::from_u16(response.status().as_u16()).unwrap();
+    let text = response.text().await.unwrap();
+    let body = serde_json::from_str(&text).unwrap_or(Value::String(text));
+    (status, body)
+}
+
+/// Parse `body` as a response signed over `T` and check its signature with
+/// `public_key`, panicking if either fails.
+pub fn verify_signed<T: Serialize + DeserializeOwned>(
+    public_key: &[u8],
+    body: &Value,
+) -> SignedResponse<T> {
+    let signed: SignedResponse<T> = serde_json::from_value(body.clone())
+        .unwrap_or_else(|e| panic!("{} isn't a signed response: {}", body, e));
+    verify_response(public_key, &signed)
+        .unwrap_or_else(|e| panic!("{} isn't signed by the enclave: {:?}", body, e));
+    signed
+}
+
+/// Serve a weather API reporting [MOCK_TEMPERATURE] degrees, last updated at
+/// `last_updated_epoch` seconds, at any location but [UNKNOWN_LOCATION],
+/// returning its URL.
+pub async fn mock_weather_upstream(last_updated_epoch: u64) -> reqwest::Url {
+    let app = axum::Router::new().route(
+        "/v1/current.json",
+        get(
+            move |Query(query): Query<HashMap<String, String>>| async move {
+                let location = query.get("q").cloned().unwrap_or_default();
+                if location == UNKNOWN_LOCATION {
+                    // As observed from api.weatherapi.com
+                    return (
+                        StatusCode::BAD_REQUEST,
+                        Json(json!({
+                            "error": { "code": 1006, "message": "No matching location found." }
+                        })),
+                    );
+                }
+                (
+                    StatusCode::OK,
+                    Json(json!({
+                        "location": { "name": location },
+                        "current": {
+                            "temp_c": MOCK_TEMPERATURE as f64,
+                            "last_updated_epoch": last_updated_epoch,
+                        },
+                    })),
+                )
+            },
+        ),
+    );
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/v1/current.json", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    url.parse().unwrap()
+}
@@ -0,0 +1,236 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::AppState;
+use axum::extract::{MatchedPath, Request, State};
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use axum::middleware::Next;
+use axum::response::Response;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Header carrying the request ID. A caller-supplied ID is kept, otherwise one
+/// is generated, and either way it is echoed in the response.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Middleware that times each request and logs those slower than
+/// `SLOW_REQUEST_THRESHOLD_MS` with their route and request ID, to surface
+/// slow upstreams or NSM stalls without logging every request. With
+/// `REQUEST_LOG_SAMPLE` it also logs a sample of requests, see
+/// [RequestLogSampler].
+pub async fn time_requests(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| request.uri().path().to_string());
+    let method = request.method().clone();
+    let request_id = request_id(request.headers());
+    let start = Instant::now();
+    let mut response = next.run(request).await;
+    let elapsed = start.elapsed();
+    if state.request_log.should_log(response.status()) {
+        info!(
+            "Request {} {} {}: {}ms (status {})",
+            request_id,
+            method,
+            route,
+            elapsed.as_millis(),
+            response.status()
+        );
+    }
+    if is_slow(elapsed, state.config.slow_request_threshold) {
+        warn!(
+            "Slow request {} {}: {}ms (status {})",
+            request_id,
+            route,
+            elapsed.as_millis(),
+            response.status()
+        );
+    }
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+}
+
+/// The caller's request ID if it is printable and reasonably short, or a new
+/// random one.
+fn request_id(headers: &HeaderMap) -> String {
+    headers
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= 128)
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("{:016x}", rand::random::<u64>()))
+}
+
+/// Which requests are logged with `REQUEST_LOG_SAMPLE=<n>`: every one that
+/// failed, and one in `n` of the others, starting with the first. Busy
+/// enclaves can log a fraction of their traffic this way without flooding
+/// the serial console, which is slow enough to hold up requests.
+pub struct RequestLogSampler {
+    /// One in how many successful requests are logged, or zero to log none
+    /// at all.
+    every: u64,
+    /// Successful requests seen so far.
+    succeeded: AtomicU64,
+}
+
+impl RequestLogSampler {
+    pub fn new(every: u64) -> Self {
+        Self {
+            every,
+            succeeded: AtomicU64::new(0),
+        }
+    }
+
+    /// Whether to log a request answered with `status`. Errors are never
+    /// sampled out, and don't count towards the sample of the others.
+    pub fn should_log(&self, status: StatusCode) -> bool {
+        if self.every == 0 {
+            return false;
+        }
+        if status.is_client_error() || status.is_server_error() {
+            return true;
+        }
+        self.succeeded.fetch_add(1, Ordering::Relaxed) % self.every == 0
+    }
+}
+
+/// Whether a request taking `elapsed` should be logged. A zero threshold
+/// disables the log.
+fn is_slow(elapsed: Duration, threshold: Duration) -> bool {
+    !threshold.is_zero() && elapsed >= threshold
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api_keys::ApiKeys;
+    use crate::config::Config;
+    use axum::body::Body;
+    use axum::routing::get;
+    use axum::{middleware, Router};
+    use fastcrypto::{ed25519::Ed25519KeyPair, traits::KeyPair};
+    use std::io::Write;
+    use std::sync::Mutex;
+    use tower::Service;
+
+    /// Output of the log subscriber installed by a test.
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_slow_request_logged() {
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let state = Arc::new(
+            AppState::new(
+                Ed25519KeyPair::generate(&mut rand::thread_rng()),
+                ApiKeys::single(String::new()),
+                Config {
+                    slow_request_threshold: Duration::from_millis(20),
+                    ..Config::default()
+                },
+            )
+            .unwrap(),
+        );
+        let mut app = Router::new()
+            .route("/fast", get(|| async {}))
+            .route(
+                "/slow",
+                get(|| tokio::time::sleep(Duration::from_millis(50))),
+            )
+            .route_layer(middleware::from_fn_with_state(state.clone(), time_requests))
+            .with_state(state);
+        for path in ["/fast", "/slow"] {
+            let request = axum::http::Request::get(path)
+                .header(REQUEST_ID_HEADER, format!("id{}", path))
+                .body(Body::empty())
+                .unwrap();
+            app.call(request).await.unwrap();
+        }
+
+        let logs = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        assert!(logs.contains("WARN"), "{}", logs);
+        assert!(logs.contains("Slow request id/slow /slow"), "{}", logs);
+        assert!(!logs.contains("id/fast"), "{}", logs);
+    }
+
+    #[test]
+    fn test_is_slow() {
+        let threshold = Duration::from_secs(2);
+        assert!(!is_slow(Duration::from_millis(1999), threshold));
+        assert!(is_slow(Duration::from_secs(2), threshold));
+        assert!(!is_slow(Duration::from_secs(60), Duration::ZERO));
+    }
+
+    #[test]
+    fn test_request_log_sampler() {
+        let sampler = RequestLogSampler::new(10);
+        let mut logged = 0;
+        let mut errors_logged = 0;
+        for i in 0..10_000 {
+            assert!(sampler.should_log(StatusCode::BAD_REQUEST));
+            errors_logged += 1;
+            if i % 7 == 0 {
+                assert!(sampler.should_log(StatusCode::BAD_GATEWAY));
+                errors_logged += 1;
+            }
+            if sampler.should_log(StatusCode::OK) {
+                logged += 1;
+            }
+        }
+        // Exactly one in ten successes, however many errors are in between
+        assert_eq!(logged, 1_000);
+        assert_eq!(errors_logged, 10_000 + 1_429);
+
+        // The first request is logged, and one in one logs them all
+        assert!(RequestLogSampler::new(100).should_log(StatusCode::OK));
+        let sampler = RequestLogSampler::new(1);
+        assert!((0..100).all(|_| sampler.should_log(StatusCode::OK)));
+
+        // Zero turns the log off, errors included
+        let sampler = RequestLogSampler::new(0);
+        assert!(!sampler.should_log(StatusCode::OK));
+        assert!(!sampler.should_log(StatusCode::INTERNAL_SERVER_ERROR));
+    }
+
+    #[test]
+    fn test_request_id() {
+        let mut headers = HeaderMap::new();
+        let generated = request_id(&headers);
+        assert_eq!(generated.len(), 16);
+        assert_ne!(request_id(&headers), generated);
+
+        headers.insert(REQUEST_ID_HEADER, HeaderValue::from_static("abc-123"));
+        assert_eq!(request_id(&headers), "abc-123");
+
+        headers.insert(REQUEST_ID_HEADER, HeaderValue::from_static(""));
+        assert_eq!(request_id(&headers).len(), 16);
+    }
+}
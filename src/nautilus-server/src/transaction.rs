@@ -0,0 +1,615 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use serde::{Deserialize, Serialize};
+
+/// ==== SUI TRANSACTION APPROVALS ====
+
+/// Inner type T for ProcessDataRequest<T> on `/process_transaction`.
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+pub struct TransactionRequest {
+    /// Base64 BCS bytes of the `TransactionData`, as printed by
+    /// `sui client ... --serialize-unsigned-transaction`.
+    pub tx_bytes: String,
+}
+
+/// Inner type T for IntentMessage<T> signed under
+/// [IntentScope::SuiTransaction](crate::common::IntentScope::SuiTransaction):
+/// the transaction passed the enclave's `TRANSACTION_*` policy.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+pub struct TransactionApproval {
+    /// BCS bytes of the approved `TransactionData`.
+    pub tx_bytes: Vec<u8>,
+}
+
+#[cfg(feature = "sui-transactions")]
+pub use policy::{
+    process_transaction, AllowedCall, TransactionPolicy, DEFAULT_MAX_TRANSACTION_BYTES,
+};
+
+/// The `TRANSACTION_*` policy and the `/process_transaction` handler, only
+/// built with the `sui-transactions` feature.
+#[cfg(feature = "sui-transactions")]
+mod policy {
+    use super::{TransactionApproval, TransactionRequest};
+    use crate::app::sign_payload;
+    use crate::common::{
+        current_timestamp_ms, IntentMessage, IntentScope, ProcessDataRequest, ProcessedDataResponse,
+    };
+    use crate::config::env_or;
+    use crate::sui::{ObjectId, OBJECT_ID_LEN};
+    use crate::AppState;
+    use crate::EnclaveError;
+    use axum::extract::State;
+    use axum::Json;
+    use fastcrypto::encoding::{Base64, Encoding};
+    use serde::{Deserialize, Serialize};
+    use std::fmt;
+    use std::str::FromStr;
+    use std::sync::Arc;
+
+    /// Longest transaction accepted unless `TRANSACTION_MAX_BYTES` is set, the
+    /// size Sui itself accepts.
+    pub const DEFAULT_MAX_TRANSACTION_BYTES: usize = 128 * 1024;
+
+    /// A Move function transactions may call, written
+    /// `<package>::<module>::<function>`, or `<package>::<module>::*` for every
+    /// function of the module.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct AllowedCall {
+        pub package: ObjectId,
+        pub module: String,
+        /// None for every function of the module.
+        pub function: Option<String>,
+    }
+
+    impl FromStr for AllowedCall {
+        type Err = EnclaveError;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            let invalid = || {
+                EnclaveError::GenericError(format!(
+                    "Invalid allowed call `{}`: expected <package>::<module>::<function|*>",
+                    s
+                ))
+            };
+            let mut parts = s.split("::");
+            let (package, module, function) = match (parts.next(), parts.next(), parts.next()) {
+                (Some(package), Some(module), Some(function)) if parts.next().is_none() => {
+                    (package, module, function)
+                }
+                _ => return Err(invalid()),
+            };
+            let identifier = |name: &str| {
+                let mut chars = name.chars();
+                chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+                    && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+            };
+            if !identifier(module) || !(function == "*" || identifier(function)) {
+                return Err(invalid());
+            }
+            Ok(Self {
+                package: ObjectId::parse(package)?,
+                module: module.to_string(),
+                function: (function != "*").then(|| function.to_string()),
+            })
+        }
+    }
+
+    impl AllowedCall {
+        fn allows(&self, call: &ProgrammableMoveCall) -> bool {
+            self.package.0 == call.package
+                && self.module == call.module
+                && match &self.function {
+                    Some(function) => *function == call.function,
+                    None => true,
+                }
+        }
+    }
+
+    /// Checks a transaction must pass for `/process_transaction` to sign it.
+    #[derive(Debug, Clone)]
+    pub struct TransactionPolicy {
+        /// Longest transaction accepted, in BCS bytes.
+        pub max_bytes: usize,
+        /// Move functions the transaction's commands may call, any when None.
+        /// With a list, publishing or upgrading a package is refused too, since
+        /// that runs code no entry names.
+        pub allowed_calls: Option<Vec<AllowedCall>>,
+    }
+
+    impl TransactionPolicy {
+        /// The policy configured by `SIGN_TRANSACTIONS=true`,
+        /// `TRANSACTION_MAX_BYTES` (default [DEFAULT_MAX_TRANSACTION_BYTES]) and
+        /// `TRANSACTION_ALLOWED_CALLS`, a comma-separated list of
+        /// [AllowedCall]s, or None unless `SIGN_TRANSACTIONS` is set. An empty
+        /// list allows no Move call.
+        pub fn from_env() -> Result<Option<Self>, EnclaveError> {
+            if !env_or("SIGN_TRANSACTIONS", false)? {
+                return Ok(None);
+            }
+            let allowed_calls = match std::env::var("TRANSACTION_ALLOWED_CALLS") {
+                Ok(calls) => Some(
+                    calls
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|call| !call.is_empty())
+                        .map(str::parse)
+                        .collect::<Result<_, _>>()?,
+                ),
+                Err(_) => None,
+            };
+            Ok(Some(Self {
+                max_bytes: env_or("TRANSACTION_MAX_BYTES", DEFAULT_MAX_TRANSACTION_BYTES)?,
+                allowed_calls,
+            }))
+        }
+
+        /// Check `tx_bytes` is a programmable transaction within the policy.
+        /// Bytes that don't decode are refused whatever the policy, so the
+        /// enclave never signs something it couldn't review.
+        pub fn check(&self, tx_bytes: &[u8]) -> Result<(), EnclaveError> {
+            if tx_bytes.len() > self.max_bytes {
+                return Err(EnclaveError::GenericError(format!(
+                    "Transaction takes {} bytes, at most {} are allowed",
+                    tx_bytes.len(),
+                    self.max_bytes
+                )));
+            }
+            let TransactionData::V1(data) = bcs::from_bytes(tx_bytes).map_err(|e| {
+                EnclaveError::GenericError(format!("Not a programmable transaction: {}", e))
+            })?;
+            let TransactionKind::ProgrammableTransaction(transaction) = data.kind;
+            let allowed_calls = match &self.allowed_calls {
+                Some(allowed_calls) => allowed_calls,
+                None => return Ok(()),
+            };
+            for command in &transaction.commands {
+                match command {
+                    Command::MoveCall(call) if !allowed_calls.iter().any(|a| a.allows(call)) => {
+                        return Err(EnclaveError::GenericError(format!(
+                            "Transaction calls {}, which TRANSACTION_ALLOWED_CALLS doesn't allow",
+                            call
+                        )));
+                    }
+                    Command::Publish(..) | Command::Upgrade(..) => {
+                        return Err(EnclaveError::GenericError(
+                            "Transaction publishes or upgrades a package, which \
+                             TRANSACTION_ALLOWED_CALLS doesn't allow"
+                                .to_string(),
+                        ));
+                    }
+                    _ => {}
+                }
+            }
+            Ok(())
+        }
+    }
+
+    // The parts of Sui's `TransactionData` the policy reads, with the same BCS
+    // layout as `sui_types::transaction`. Variants added to Sui after these fail
+    // to decode, and so are refused.
+
+    type Address = [u8; OBJECT_ID_LEN];
+
+    /// Object ID, version and digest.
+    type ObjectRef = (Address, u64, Vec<u8>);
+
+    #[derive(Debug, Serialize, Deserialize)]
+    enum TransactionData {
+        V1(TransactionDataV1),
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct TransactionDataV1 {
+        kind: TransactionKind,
+        sender: Address,
+        gas_data: GasData,
+        expiration: TransactionExpiration,
+    }
+
+    // System transactions, the other kinds, can't be submitted by a client
+    #[derive(Debug, Serialize, Deserialize)]
+    enum TransactionKind {
+        ProgrammableTransaction(ProgrammableTransaction),
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct ProgrammableTransaction {
+        inputs: Vec<CallArg>,
+        commands: Vec<Command>,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    enum CallArg {
+        Pure(Vec<u8>),
+        Object(ObjectArg),
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    enum ObjectArg {
+        ImmOrOwnedObject(ObjectRef),
+        SharedObject {
+            id: Address,
+            initial_shared_version: u64,
+            mutable: bool,
+        },
+        Receiving(ObjectRef),
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    enum Command {
+        MoveCall(Box<ProgrammableMoveCall>),
+        TransferObjects(Vec<Argument>, Argument),
+        SplitCoins(Argument, Vec<Argument>),
+        MergeCoins(Argument, Vec<Argument>),
+        Publish(Vec<Vec<u8>>, Vec<Address>),
+        MakeMoveVec(Option<TypeTag>, Vec<Argument>),
+        Upgrade(Vec<Vec<u8>>, Vec<Address>, Address, Argument),
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct ProgrammableMoveCall {
+        package: Address,
+        module: String,
+        function: String,
+        type_arguments: Vec<TypeTag>,
+        arguments: Vec<Argument>,
+    }
+
+    impl fmt::Display for ProgrammableMoveCall {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(
+                f,
+                "{}::{}::{}",
+                ObjectId(self.package),
+                self.module,
+                self.function
+            )
+        }
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    enum Argument {
+        GasCoin,
+        Input(u16),
+        Result(u16),
+        NestedResult(u16, u16),
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    enum TypeTag {
+        Bool,
+        U8,
+        U64,
+        U128,
+        Address,
+        Signer,
+        Vector(Box<TypeTag>),
+        Struct(Box<StructTag>),
+        U16,
+        U32,
+        U256,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct StructTag {
+        address: Address,
+        module: String,
+        name: String,
+        type_params: Vec<TypeTag>,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct GasData {
+        payment: Vec<ObjectRef>,
+        owner: Address,
+        price: u64,
+        budget: u64,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    enum TransactionExpiration {
+        None,
+        Epoch(u64),
+    }
+
+    /// Endpoint that checks Sui transaction bytes against the
+    /// `TRANSACTION_*` policy and signs them under
+    /// [IntentScope::SuiTransaction] at the current time. A transaction the
+    /// policy refuses is a 400 and nothing is signed. Only served with the
+    /// `sui-transactions` feature and `SIGN_TRANSACTIONS=true`.
+    pub async fn process_transaction(
+        State(state): State<Arc<AppState>>,
+        Json(request): Json<ProcessDataRequest<TransactionRequest>>,
+    ) -> Result<Json<ProcessedDataResponse<IntentMessage<TransactionApproval>>>, EnclaveError> {
+        let policy = state.config.transaction_policy.as_ref().ok_or_else(|| {
+            EnclaveError::GenericError(
+                "Transaction signing is disabled, see SIGN_TRANSACTIONS".to_string(),
+            )
+        })?;
+        let scope = IntentScope::SuiTransaction;
+        if let Some(intent) = request.intent.filter(|intent| *intent != scope as u8) {
+            return Err(EnclaveError::GenericError(format!(
+                "Transactions are signed under intent {}, not {}",
+                scope as u8, intent
+            )));
+        }
+        let tx_bytes = Base64::decode(&request.payload.tx_bytes).map_err(|_| {
+            EnclaveError::GenericError("Invalid tx_bytes: expected base64".to_string())
+        })?;
+        policy.check(&tx_bytes)?;
+
+        let current_timestamp = current_timestamp_ms(&state.config).await?;
+        Ok(Json(sign_payload(
+            &state,
+            TransactionApproval { tx_bytes },
+            current_timestamp,
+            scope,
+        )))
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+        use fastcrypto::encoding::Hex;
+
+        fn package(last: u8) -> Address {
+            let mut id = [0; OBJECT_ID_LEN];
+            id[OBJECT_ID_LEN - 1] = last;
+            id
+        }
+
+        /// A transaction splitting a coin off the gas coin and passing it to
+        /// `function` of `0x2::coin`, then running `extra`.
+        fn transaction(function: &str, extra: Vec<Command>) -> Vec<u8> {
+            let mut commands = vec![
+                Command::SplitCoins(Argument::GasCoin, vec![Argument::Input(0)]),
+                Command::MoveCall(Box::new(ProgrammableMoveCall {
+                    package: package(2),
+                    module: "coin".to_string(),
+                    function: function.to_string(),
+                    type_arguments: vec![TypeTag::Struct(Box::new(StructTag {
+                        address: package(2),
+                        module: "sui".to_string(),
+                        name: "SUI".to_string(),
+                        type_params: vec![],
+                    }))],
+                    arguments: vec![Argument::Result(0), Argument::Input(1)],
+                })),
+            ];
+            commands.extend(extra);
+            let data = TransactionData::V1(TransactionDataV1 {
+                kind: TransactionKind::ProgrammableTransaction(ProgrammableTransaction {
+                    inputs: vec![
+                        CallArg::Pure(bcs::to_bytes(&1000u64).unwrap()),
+                        CallArg::Object(ObjectArg::SharedObject {
+                            id: package(6),
+                            initial_shared_version: 1,
+                            mutable: false,
+                        }),
+                    ],
+                    commands,
+                }),
+                sender: [0xab; OBJECT_ID_LEN],
+                gas_data: GasData {
+                    payment: vec![([0xcd; OBJECT_ID_LEN], 7, vec![0xef; 32])],
+                    owner: [0xab; OBJECT_ID_LEN],
+                    price: 1000,
+                    budget: 10_000_000,
+                },
+                expiration: TransactionExpiration::None,
+            });
+            bcs::to_bytes(&data).unwrap()
+        }
+
+        #[test]
+        fn test_allowed_call() {
+            let call: AllowedCall = "0x2::coin::join".parse().unwrap();
+            assert_eq!(call.package.0, package(2));
+            assert_eq!(call.module, "coin");
+            assert_eq!(call.function.as_deref(), Some("join"));
+            let call: AllowedCall = "0x2::coin::*".parse().unwrap();
+            assert_eq!(call.function, None);
+
+            for call in [
+                "",
+                "0x2::coin",
+                "0x2::coin::join::x",
+                "2::coin::join",
+                "0x2::::join",
+                "0x2::coin::",
+                "0x2::1coin::join",
+                "0x2::coin::jo-in",
+                "0x2::coin::j*",
+            ] {
+                assert!(call.parse::<AllowedCall>().is_err(), "{}", call);
+            }
+        }
+
+        #[test]
+        fn test_check() {
+            let any = TransactionPolicy {
+                max_bytes: DEFAULT_MAX_TRANSACTION_BYTES,
+                allowed_calls: None,
+            };
+            let tx_bytes = transaction("split", vec![]);
+            any.check(&tx_bytes).unwrap();
+            let publish = transaction(
+                "split",
+                vec![Command::Publish(vec![vec![0xa1, 0x1c]], vec![package(1)])],
+            );
+            any.check(&publish).unwrap();
+
+            // Bytes that aren't a transaction are refused even without a policy
+            let mut trailing = tx_bytes.clone();
+            trailing.push(0);
+            for bytes in [
+                &tx_bytes[..tx_bytes.len() - 1],
+                &trailing[..],
+                &[1, 0][..],
+                &[][..],
+            ] {
+                let err = any.check(bytes).unwrap_err().to_string();
+                assert!(err.starts_with("Not a programmable transaction"), "{}", err);
+            }
+
+            let small = TransactionPolicy {
+                max_bytes: tx_bytes.len() - 1,
+                allowed_calls: None,
+            };
+            assert_eq!(
+                small.check(&tx_bytes).unwrap_err().to_string(),
+                format!(
+                    "Transaction takes {} bytes, at most {} are allowed",
+                    tx_bytes.len(),
+                    tx_bytes.len() - 1
+                )
+            );
+
+            let allowed = |calls: &[&str]| TransactionPolicy {
+                max_bytes: DEFAULT_MAX_TRANSACTION_BYTES,
+                allowed_calls: Some(calls.iter().map(|call| call.parse().unwrap()).collect()),
+            };
+            allowed(&["0x2::coin::split"]).check(&tx_bytes).unwrap();
+            allowed(&["0x2::coin::*"]).check(&tx_bytes).unwrap();
+            for calls in [&[][..], &["0x2::coin::join"][..], &["0x3::coin::*"][..]] {
+                assert_eq!(
+                    allowed(calls).check(&tx_bytes).unwrap_err().to_string(),
+                    format!(
+                        "Transaction calls {}::coin::split, which TRANSACTION_ALLOWED_CALLS doesn't allow",
+                        ObjectId(package(2))
+                    )
+                );
+            }
+            let err = allowed(&["0x2::coin::*"])
+                .check(&publish)
+                .unwrap_err()
+                .to_string();
+            assert!(err.starts_with("Transaction publishes"), "{}", err);
+        }
+
+        /// The decoder reads what the Sui SDK builds.
+        #[cfg(feature = "sui-sdk")]
+        #[test]
+        fn test_check_sdk_transaction() {
+            use crate::sui::{sdk, Targets};
+            use sui_sdk::types::base_types::{random_object_ref, SuiAddress};
+            use sui_sdk::types::transaction::{ObjectArg, TransactionData};
+
+            let targets = Targets::new("0x5", "0x6", "weather", "WEATHER").unwrap();
+            let config = ObjectArg::ImmOrOwnedObject(random_object_ref());
+            let pt = sdk::register_enclave(&targets, config, &[0xaa; 16]).unwrap();
+            let data = TransactionData::new_programmable(
+                SuiAddress::ZERO,
+                vec![random_object_ref()],
+                pt,
+                10_000_000,
+                1000,
+            );
+            let tx_bytes = bcs::to_bytes(&data).unwrap();
+            let policy = |calls: &str| TransactionPolicy {
+                max_bytes: DEFAULT_MAX_TRANSACTION_BYTES,
+                allowed_calls: Some(calls.split(',').map(|call| call.parse().unwrap()).collect()),
+            };
+            policy("0x2::nitro_attestation::load_nitro_attestation,0x5::enclave::register_enclave")
+                .check(&tx_bytes)
+                .unwrap();
+            let err = policy("0x5::enclave::*")
+                .check(&tx_bytes)
+                .unwrap_err()
+                .to_string();
+            assert!(
+                err.contains("::nitro_attestation::load_nitro_attestation"),
+                "{}",
+                err
+            );
+        }
+
+        #[tokio::test]
+        async fn test_process_transaction() {
+            use crate::api_keys::ApiKeys;
+            use crate::config::Config;
+            use fastcrypto::ed25519::{Ed25519KeyPair, Ed25519Signature};
+            use fastcrypto::traits::{KeyPair, ToFromBytes, VerifyingKey};
+
+            let kp = Ed25519KeyPair::generate(&mut rand::thread_rng());
+            let public_key = kp.public().clone();
+            let config = Config {
+                transaction_policy: Some(TransactionPolicy {
+                    max_bytes: DEFAULT_MAX_TRANSACTION_BYTES,
+                    allowed_calls: Some(vec!["0x2::coin::split".parse().unwrap()]),
+                }),
+                ..Config::default()
+            };
+            let state =
+                Arc::new(AppState::new(kp, ApiKeys::single(String::new()), config).unwrap());
+            let request = |tx_bytes: String, intent| {
+                Json(ProcessDataRequest {
+                    payload: TransactionRequest { tx_bytes },
+                    intent,
+                })
+            };
+
+            // An approved transaction is signed as is
+            let tx_bytes = transaction("split", vec![]);
+            let signed = process_transaction(
+                State(state.clone()),
+                request(Base64::encode(&tx_bytes), None),
+            )
+            .await
+            .unwrap()
+            .0;
+            assert_eq!(signed.response.data, TransactionApproval { tx_bytes });
+            assert_eq!(signed.response.intent, IntentScope::SuiTransaction);
+            let signature =
+                Ed25519Signature::from_bytes(&Hex::decode(&signed.signature).unwrap()).unwrap();
+            let signed_bytes = bcs::to_bytes(&signed.response).unwrap();
+            assert!(public_key.verify(&signed_bytes, &signature).is_ok());
+
+            // A transaction the policy refuses isn't
+            for (tx_bytes, intent, error) in [
+                (
+                    Base64::encode(transaction("join", vec![])),
+                    None,
+                    "Transaction calls",
+                ),
+                ("not base64!".to_string(), None, "Invalid tx_bytes"),
+                (
+                    Base64::encode(transaction("split", vec![])),
+                    Some(0),
+                    "Transactions are signed under intent 7",
+                ),
+            ] {
+                let err = process_transaction(State(state.clone()), request(tx_bytes, intent))
+                    .await
+                    .err()
+                    .unwrap()
+                    .to_string();
+                assert!(err.starts_with(error), "{}", err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::{IntentMessage, IntentScope};
+    use fastcrypto::encoding::{Encoding, Hex};
+
+    #[test]
+    fn test_serde_transaction_approval() {
+        let payload = TransactionApproval {
+            tx_bytes: vec![0xee; 16],
+        };
+        let intent_msg = IntentMessage::new(payload, 1744038900000, IntentScope::SuiTransaction);
+        let signing_payload = bcs::to_bytes(&intent_msg).expect("should not fail");
+        assert_eq!(
+            Hex::encode(signing_payload),
+            crate::vectors::committed("sui_transaction").signing_payload
+        );
+    }
+}
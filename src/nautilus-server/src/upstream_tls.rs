@@ -0,0 +1,239 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+
+/// Why a TLS handshake with an upstream failed, as reported to clients in
+/// place of the TLS library's error text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum TlsFailure {
+    /// The upstream's certificate has expired or is not yet valid.
+    Expired,
+    /// The certificate doesn't cover the upstream's host name.
+    HostnameMismatch,
+    /// The certificate doesn't chain to a root the enclave trusts, e.g. it
+    /// is self-signed or its issuer is missing from the CA bundle.
+    UntrustedRoot,
+    /// Any other handshake failure, e.g. no common protocol version.
+    Handshake,
+}
+
+impl fmt::Display for TlsFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Expired => "certificate expired or not yet valid",
+            Self::HostnameMismatch => "certificate does not match the host name",
+            Self::UntrustedRoot => "certificate not issued by a trusted root",
+            Self::Handshake => "handshake failed",
+        })
+    }
+}
+
+/// Phrases identifying each failure in the messages of OpenSSL, which backs
+/// reqwest's default TLS, and of rustls.
+const PATTERNS: &[(TlsFailure, &[&str])] = &[
+    (
+        TlsFailure::Expired,
+        &[
+            "certificate has expired",
+            "certificate is not yet valid",
+            "expired",
+            "notvalidyet",
+        ],
+    ),
+    (
+        TlsFailure::HostnameMismatch,
+        &[
+            "hostname mismatch",
+            "notvalidforname",
+            "not valid for name",
+            "ip address mismatch",
+        ],
+    ),
+    (
+        TlsFailure::UntrustedRoot,
+        &[
+            "self-signed certificate",
+            "self signed certificate",
+            "unable to get local issuer certificate",
+            "unable to get issuer certificate",
+            "unknownissuer",
+        ],
+    ),
+    (
+        TlsFailure::Handshake,
+        &[
+            "certificate verify failed",
+            "ssl routines",
+            "handshake",
+            "invalid peer certificate",
+        ],
+    ),
+];
+
+/// The TLS failure behind `err`, found in its chain of sources, or None when
+/// it didn't fail in a TLS handshake.
+pub fn classify(err: &dyn Error) -> Option<TlsFailure> {
+    let mut messages = Vec::new();
+    let mut source = Some(err);
+    while let Some(cause) = source {
+        messages.push(cause.to_string().to_lowercase());
+        source = cause.source();
+    }
+    PATTERNS.iter().find_map(|(failure, phrases)| {
+        messages
+            .iter()
+            .any(|message| phrases.iter().any(|phrase| message.contains(phrase)))
+            .then_some(*failure)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rcgen::{date_time_ymd, BasicConstraints, Certificate, CertificateParams, IsCa, KeyPair};
+    use rustls::pki_types::PrivateKeyDer;
+    use rustls::ServerConfig;
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+    use tokio_rustls::TlsAcceptor;
+
+    fn ca() -> (Certificate, KeyPair) {
+        let key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        (params.self_signed(&key).unwrap(), key)
+    }
+
+    /// Serve TLS with a certificate for `name` issued by `ca`, expired when
+    /// `expired` is set, returning the server's address.
+    async fn mock_upstream(
+        name: &str,
+        expired: bool,
+        (ca, ca_key): &(Certificate, KeyPair),
+    ) -> SocketAddr {
+        let key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(vec![name.to_string()]).unwrap();
+        if expired {
+            params.not_before = date_time_ymd(2020, 1, 1);
+            params.not_after = date_time_ymd(2021, 1, 1);
+        }
+        let cert = params.signed_by(&key, ca, ca_key).unwrap();
+        let config =
+            ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_no_client_auth()
+                .with_single_cert(
+                    vec![cert.der().clone()],
+                    PrivateKeyDer::Pkcs8(key.serialize_der().into()),
+                )
+                .unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                // The client aborts the handshake, so this fails too
+                let _ = acceptor.accept(stream).await;
+            }
+        });
+        addr
+    }
+
+    /// Classification of the error from requesting `https://localhost/` on
+    /// `addr`, trusting `ca` when given.
+    async fn fetch_failure(addr: SocketAddr, ca: Option<&Certificate>) -> Option<TlsFailure> {
+        let mut client = reqwest::Client::builder().resolve("localhost", addr);
+        if let Some(ca) = ca {
+            client = client
+                .add_root_certificate(reqwest::Certificate::from_pem(ca.pem().as_bytes()).unwrap());
+        }
+        let err = client
+            .build()
+            .unwrap()
+            .get(format!("https://localhost:{}/", addr.port()))
+            .send()
+            .await
+            .expect_err("handshake should fail");
+        classify(&err)
+    }
+
+    #[tokio::test]
+    async fn test_expired_certificate() {
+        let ca = ca();
+        let addr = mock_upstream("localhost", true, &ca).await;
+        assert_eq!(
+            fetch_failure(addr, Some(&ca.0)).await,
+            Some(TlsFailure::Expired)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_hostname_mismatch_and_untrusted_root() {
+        let ca = ca();
+        let addr = mock_upstream("api.example.com", false, &ca).await;
+        assert_eq!(
+            fetch_failure(addr, Some(&ca.0)).await,
+            Some(TlsFailure::HostnameMismatch)
+        );
+
+        let addr = mock_upstream("localhost", false, &ca).await;
+        assert_eq!(
+            fetch_failure(addr, None).await,
+            Some(TlsFailure::UntrustedRoot)
+        );
+    }
+
+    #[test]
+    fn test_classify_messages() {
+        let io = |message: &str| std::io::Error::other(message.to_string());
+        for (message, expected) in [
+            (
+                "error:0A000086:SSL routines:tls_post_process_server_certificate:certificate verify failed:../ssl/statem/statem_clnt.c:1889: (certificate has expired)",
+                Some(TlsFailure::Expired),
+            ),
+            (
+                "error:0A000086:SSL routines::certificate verify failed: (Hostname mismatch)",
+                Some(TlsFailure::HostnameMismatch),
+            ),
+            (
+                "invalid peer certificate: UnknownIssuer",
+                Some(TlsFailure::UntrustedRoot),
+            ),
+            (
+                "error:0A00042E:SSL routines::tlsv1 alert protocol version",
+                Some(TlsFailure::Handshake),
+            ),
+            ("Connection refused (os error 111)", None),
+        ] {
+            assert_eq!(classify(&io(message)), expected, "{}", message);
+        }
+        assert_eq!(
+            serde_json::to_string(&TlsFailure::HostnameMismatch).unwrap(),
+            "\"hostname_mismatch\""
+        );
+    }
+
+    #[test]
+    fn test_error_hides_detail() {
+        let error = |detail: Option<&str>| {
+            crate::EnclaveError::UpstreamTls {
+                host: "api.weatherapi.com".to_string(),
+                reason: TlsFailure::Expired,
+                detail: detail.map(str::to_string),
+            }
+            .to_string()
+        };
+        assert_eq!(
+            error(None),
+            "TLS handshake with api.weatherapi.com failed: certificate expired or not yet valid"
+        );
+        assert!(error(Some("certificate has expired")).ends_with("(certificate has expired)"));
+    }
+}
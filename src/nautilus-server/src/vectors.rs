@@ -0,0 +1,374 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Signing test vectors shared by the Rust and Move tests. For every intent
+//! scope and message layout there is a fixed sample payload, the BCS bytes
+//! the enclave signs for it, and their signature under [TEST_PRIVATE_KEY].
+//! The `nautilus-vectors` binary writes them to [JSON_PATH] and, as Move
+//! constants, to [MOVE_PATH]. Tests on both sides read those files, and the
+//! tests here fail when they no longer match [generate].
+
+use crate::app::{
+    SignedField, WeatherFields, WeatherResponse, WeatherResponseWithAttestationDigest,
+    WeatherResponseWithUpstreamLatency,
+};
+use crate::common::{IntentMessage, IntentScope};
+use crate::github::GithubCommit;
+use crate::price::PriceResponse;
+use crate::transaction::TransactionApproval;
+use fastcrypto::ed25519::{Ed25519KeyPair, Ed25519PrivateKey};
+use fastcrypto::encoding::{Encoding, Hex};
+use fastcrypto::traits::{KeyPair, Signer, ToFromBytes};
+use nautilus_verifier::compression::{CompressedPayload, ZSTD};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+
+/// Private key the vectors are signed with. Never use it for anything else.
+pub const TEST_PRIVATE_KEY: [u8; 32] = [7; 32];
+
+/// Timestamp of every vector, in milliseconds since the UNIX epoch.
+pub const TIMESTAMP_MS: u64 = 1744038900000;
+
+/// Expiry of the vectors signed with `SIGN_EXPIRY_MS`.
+pub const EXPIRES_AT_MS: u64 = 1744038960000;
+
+/// Sequence number of the vectors signed with `SIGN_SEQUENCE`.
+pub const SEQUENCE: u64 = 7;
+
+/// Where `nautilus-vectors` writes the JSON, relative to this crate.
+pub const JSON_PATH: &str = "vectors/test_vectors.json";
+
+/// Where `nautilus-vectors` writes the Move module, relative to this crate.
+pub const MOVE_PATH: &str = "../../move/enclave/sources/test_vectors.move";
+
+/// A signed sample message.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TestVector {
+    /// Unique name, also the prefix of the vector's Move constants.
+    pub name: String,
+    /// Intent scope signed under.
+    pub intent: u8,
+    /// Rust type of the message's `data`.
+    pub payload_type: String,
+    pub timestamp_ms: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sequence: Option<u64>,
+    /// Hex encoded BCS bytes of the intent message.
+    pub signing_payload: String,
+    /// Hex encoded Ed25519 signature of `signing_payload`.
+    pub signature: String,
+}
+
+/// Every vector, with the public key of [TEST_PRIVATE_KEY].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TestVectors {
+    /// Hex encoded Ed25519 public key.
+    pub public_key: String,
+    pub vectors: Vec<TestVector>,
+}
+
+impl TestVectors {
+    /// The vector named `name`.
+    pub fn get(&self, name: &str) -> Option<&TestVector> {
+        self.vectors.iter().find(|vector| vector.name == name)
+    }
+}
+
+/// Layouts of the intent message, see [IntentMessage].
+const LAYOUTS: [(&str, Option<u64>, Option<u64>); 3] = [
+    ("", None, None),
+    ("_expiring", Some(EXPIRES_AT_MS), None),
+    ("_sequenced", None, Some(SEQUENCE)),
+];
+
+/// The keypair of [TEST_PRIVATE_KEY].
+pub fn test_keypair() -> Ed25519KeyPair {
+    Ed25519PrivateKey::from_bytes(&TEST_PRIVATE_KEY)
+        .expect("should not fail")
+        .into()
+}
+
+/// The vectors of `data`, one per layout.
+fn sign<T: Serialize>(
+    kp: &Ed25519KeyPair,
+    name: &str,
+    payload_type: &str,
+    intent: IntentScope,
+    data: T,
+) -> Vec<TestVector> {
+    let mut intent_msg = IntentMessage {
+        intent,
+        version: None,
+        timestamp_ms: TIMESTAMP_MS,
+        expires_at_ms: None,
+        sequence: None,
+        data,
+    };
+    LAYOUTS
+        .iter()
+        .map(|(suffix, expires_at_ms, sequence)| {
+            intent_msg.version = nautilus_verifier::layout_version(*expires_at_ms, *sequence);
+            intent_msg.expires_at_ms = *expires_at_ms;
+            intent_msg.sequence = *sequence;
+            let signing_payload = bcs::to_bytes(&intent_msg).expect("should not fail");
+            TestVector {
+                name: format!("{}{}", name, suffix),
+                intent: intent as u8,
+                payload_type: payload_type.to_string(),
+                timestamp_ms: TIMESTAMP_MS,
+                expires_at_ms: *expires_at_ms,
+                sequence: *sequence,
+                signature: Hex::encode(kp.sign(&signing_payload)),
+                signing_payload: Hex::encode(signing_payload),
+            }
+        })
+        .collect()
+}
+
+/// The vectors of every registered intent scope. Samples match the fixtures
+/// of the `test_serde` tests in `app.rs`, `price.rs`, `github.rs` and
+/// `transaction.rs`; a new scope fails to compile until it has one here.
+pub fn generate() -> TestVectors {
+    let kp = test_keypair();
+    let mut vectors = Vec::new();
+    for scope in IntentScope::ALL {
+        vectors.extend(match scope {
+            IntentScope::Weather => sign(
+                &kp,
+                "weather",
+                "WeatherResponse",
+                scope,
+                WeatherResponse {
+                    location: "San Francisco".to_string(),
+                    temperature: 13,
+                },
+            ),
+            IntentScope::WeatherWithAttestationDigest => sign(
+                &kp,
+                "weather_with_attestation_digest",
+                "WeatherResponseWithAttestationDigest",
+                scope,
+                WeatherResponseWithAttestationDigest {
+                    location: "San Francisco".to_string(),
+                    temperature: 13,
+                    attestation_digest: vec![0xab; 48],
+                },
+            ),
+            IntentScope::WeatherWithUpstreamLatency => sign(
+                &kp,
+                "weather_with_upstream_latency",
+                "WeatherResponseWithUpstreamLatency",
+                scope,
+                WeatherResponseWithUpstreamLatency {
+                    location: "San Francisco".to_string(),
+                    temperature: 13,
+                    upstream_latency_ms: 250,
+                },
+            ),
+            IntentScope::WeatherFields => sign(
+                &kp,
+                "weather_fields",
+                "WeatherFields",
+                scope,
+                WeatherFields {
+                    fields: vec![
+                        SignedField {
+                            path: "current.condition".to_string(),
+                            value: r#"{"code":1000,"text":"Sunny"}"#.to_string(),
+                        },
+                        SignedField {
+                            path: "location.name".to_string(),
+                            value: r#""San Francisco""#.to_string(),
+                        },
+                    ],
+                },
+            ),
+            IntentScope::Price => sign(
+                &kp,
+                "price",
+                "PriceResponse",
+                scope,
+                PriceResponse {
+                    base: "BTC".to_string(),
+                    quote: "USD".to_string(),
+                    rate: 6_412_345_000_000,
+                    decimals: 8,
+                },
+            ),
+            IntentScope::Compressed => sign(
+                &kp,
+                "compressed",
+                "CompressedPayload",
+                scope,
+                CompressedPayload {
+                    payload_intent: 16,
+                    algorithm: ZSTD,
+                    uncompressed_len: 4096,
+                    compressed_sha256: vec![0xcd; 32],
+                },
+            ),
+            IntentScope::GithubCommit => sign(
+                &kp,
+                "github_commit",
+                "GithubCommit",
+                scope,
+                GithubCommit {
+                    repo: "octocat/Hello-World".to_string(),
+                    sha: "6dcb09b5b57875f334f61aebed695e2e4193db5e".to_string(),
+                    author: "Monalisa Octocat".to_string(),
+                    authored_at_ms: 1302796849000,
+                },
+            ),
+            IntentScope::SuiTransaction => sign(
+                &kp,
+                "sui_transaction",
+                "TransactionApproval",
+                scope,
+                TransactionApproval {
+                    tx_bytes: vec![0xee; 16],
+                },
+            ),
+        });
+    }
+    TestVectors {
+        public_key: Hex::encode(kp.public().as_bytes()),
+        vectors,
+    }
+}
+
+/// `vectors` as pretty-printed JSON, for [JSON_PATH].
+pub fn to_json(vectors: &TestVectors) -> String {
+    serde_json::to_string_pretty(vectors).expect("should not fail") + "\n"
+}
+
+/// Start of the Move module written by [to_move].
+const MOVE_HEADER: &str = "\
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+// Generated by `cargo run --bin nautilus-vectors` in `src/nautilus-server`
+// from `src/vectors.rs`. Do not edit.
+
+#[test_only]
+module enclave::test_vectors;
+";
+
+/// `vectors` as a test-only Move module of constants, for [MOVE_PATH]. Each
+/// vector has a `<NAME>_PAYLOAD` and `<NAME>_SIGNATURE`, returned by
+/// `<name>_payload()` and `<name>_signature()`.
+pub fn to_move(vectors: &TestVectors) -> String {
+    let mut constants = String::new();
+    let mut functions = String::new();
+    writeln!(
+        constants,
+        "const PUBLIC_KEY: vector<u8> = x\"{}\";",
+        vectors.public_key
+    )
+    .unwrap();
+    writeln!(
+        functions,
+        "public fun public_key(): vector<u8> {{ PUBLIC_KEY }}"
+    )
+    .unwrap();
+    for vector in &vectors.vectors {
+        let name = &vector.name;
+        let constant = name.to_uppercase();
+        writeln!(constants).unwrap();
+        writeln!(
+            constants,
+            "// {} under intent scope {}",
+            vector.payload_type, vector.intent
+        )
+        .unwrap();
+        writeln!(
+            constants,
+            "const {}_PAYLOAD: vector<u8> = x\"{}\";",
+            constant, vector.signing_payload
+        )
+        .unwrap();
+        writeln!(
+            constants,
+            "const {}_SIGNATURE: vector<u8> = x\"{}\";",
+            constant, vector.signature
+        )
+        .unwrap();
+        writeln!(functions).unwrap();
+        writeln!(
+            functions,
+            "public fun {}_payload(): vector<u8> {{ {}_PAYLOAD }}",
+            name, constant
+        )
+        .unwrap();
+        writeln!(functions).unwrap();
+        writeln!(
+            functions,
+            "public fun {}_signature(): vector<u8> {{ {}_SIGNATURE }}",
+            name, constant
+        )
+        .unwrap();
+    }
+    format!("{}\n{}\n{}", MOVE_HEADER, constants, functions)
+}
+
+/// The vector named `name` in [JSON_PATH], for tests pinning a signing
+/// payload to the one the Move tests check.
+#[cfg(test)]
+pub(crate) fn committed(name: &str) -> TestVector {
+    let vectors: TestVectors =
+        serde_json::from_str(include_str!("../vectors/test_vectors.json")).unwrap();
+    vectors.get(name).cloned().unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use fastcrypto::ed25519::{Ed25519PublicKey, Ed25519Signature};
+    use fastcrypto::traits::VerifyingKey;
+
+    #[test]
+    fn test_committed_vectors_are_current() {
+        // Run `cargo run --bin nautilus-vectors` after changing a payload
+        let vectors = generate();
+        assert_eq!(
+            to_json(&vectors),
+            include_str!("../vectors/test_vectors.json")
+        );
+        assert_eq!(
+            to_move(&vectors),
+            include_str!("../../../move/enclave/sources/test_vectors.move")
+        );
+        // Regenerating gives the same bytes
+        assert_eq!(to_json(&generate()), to_json(&vectors));
+    }
+
+    #[test]
+    fn test_vectors() {
+        let vectors = generate();
+        let public_key =
+            Ed25519PublicKey::from_bytes(&Hex::decode(&vectors.public_key).unwrap()).unwrap();
+        for vector in &vectors.vectors {
+            let signing_payload = Hex::decode(&vector.signing_payload).unwrap();
+            let signature =
+                Ed25519Signature::from_bytes(&Hex::decode(&vector.signature).unwrap()).unwrap();
+            assert!(
+                public_key.verify(&signing_payload, &signature).is_ok(),
+                "{}",
+                vector.name
+            );
+        }
+        // Every scope in every layout, under unique names
+        assert_eq!(
+            vectors.vectors.len(),
+            IntentScope::ALL.len() * LAYOUTS.len()
+        );
+        for scope in IntentScope::ALL {
+            assert!(vectors.vectors.iter().any(|v| v.intent == scope as u8));
+        }
+        let mut names: Vec<&str> = vectors.vectors.iter().map(|v| v.name.as_str()).collect();
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), vectors.vectors.len());
+    }
+}
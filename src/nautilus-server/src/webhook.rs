@@ -0,0 +1,211 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::common::probe_endpoints;
+use crate::AppState;
+use fastcrypto::encoding::{Encoding, Hex};
+use fastcrypto::traits::{KeyPair, ToFromBytes};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::{Entry, HashMap};
+use std::sync::Arc;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+/// Body POSTed to `HEALTH_WEBHOOK_URL` when an endpoint changes state.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HealthTransition {
+    /// Host from `allowed_endpoints.yaml`.
+    pub endpoint: String,
+    /// The endpoint's new state.
+    pub reachable: bool,
+    /// Hex encoded public key of the enclave reporting it.
+    pub pk: String,
+}
+
+/// Per-endpoint reachability as last reported, and how many consecutive
+/// probes have disagreed with it.
+struct EndpointState {
+    reachable: bool,
+    streak: u32,
+}
+
+/// Turns probe results into state transitions. A transition is reported once
+/// `threshold` consecutive probes agree on the new state, so an endpoint
+/// flapping between probes doesn't alert on every change. The first result
+/// for an endpoint sets its state without reporting a transition.
+pub struct TransitionTracker {
+    threshold: u32,
+    endpoints: HashMap<String, EndpointState>,
+}
+
+impl TransitionTracker {
+    pub fn new(threshold: u32) -> Self {
+        Self {
+            threshold: threshold.max(1),
+            endpoints: HashMap::new(),
+        }
+    }
+
+    /// Record probe results, returning each endpoint that changed state and
+    /// whether it is now reachable.
+    pub fn update(&mut self, results: &[(String, bool)]) -> Vec<(String, bool)> {
+        let mut transitions = Vec::new();
+        for (endpoint, reachable) in results {
+            let state = match self.endpoints.entry(endpoint.clone()) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    entry.insert(EndpointState {
+                        reachable: *reachable,
+                        streak: 0,
+                    });
+                    continue;
+                }
+            };
+            if state.reachable == *reachable {
+                state.streak = 0;
+                continue;
+            }
+            state.streak += 1;
+            if state.streak >= self.threshold {
+                state.reachable = *reachable;
+                state.streak = 0;
+                transitions.push((endpoint.clone(), *reachable));
+            }
+        }
+        transitions
+    }
+}
+
+/// POST one notification per transition to `url`, on behalf of the enclave
+/// with public key `pk`. Failures are logged and not retried; the state
+/// change has been recorded either way.
+async fn notify(client: &Client, url: &str, pk: &str, transitions: Vec<(String, bool)>) {
+    for (endpoint, reachable) in transitions {
+        let transition = HealthTransition {
+            endpoint,
+            reachable,
+            pk: pk.to_string(),
+        };
+        warn!(
+            "Endpoint {} is now {}",
+            transition.endpoint,
+            if transition.reachable {
+                "reachable"
+            } else {
+                "unreachable"
+            }
+        );
+        let result = client
+            .post(url)
+            .json(&transition)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        if let Err(e) = result {
+            // The URL may embed a token, so it is left out.
+            warn!("Failed to send health webhook: {}", e.without_url());
+        }
+    }
+}
+
+/// Start the background task that probes endpoints every
+/// `HEALTH_WEBHOOK_INTERVAL_SECS` and POSTs state transitions to
+/// `HEALTH_WEBHOOK_URL`. Does nothing unless the URL is set. Its probes
+/// count as health checks, so `/health_check` also reports their results.
+pub fn spawn_health_webhook(state: Arc<AppState>) -> Option<JoinHandle<()>> {
+    let url = state.config.health_webhook_url.clone()?;
+    info!(
+        "Sending health transitions to the webhook every {:?}",
+        state.config.health_webhook_interval
+    );
+    Some(tokio::spawn(async move {
+        let pk = Hex::encode(state.eph_kp.public().as_bytes());
+        let mut tracker = TransitionTracker::new(state.config.health_webhook_threshold);
+        let mut interval = tokio::time::interval(state.config.health_webhook_interval);
+        loop {
+            interval.tick().await;
+            let (hosts, results) = probe_endpoints(&state).await;
+            let transitions = tracker.update(&results);
+            state.probe_rotation.merge(&hosts, results);
+            notify(&state.probe_client, &url, &pk, transitions).await;
+        }
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::{extract::State, routing::post, Json, Router};
+    use std::sync::Mutex;
+
+    fn probe(endpoint: &str, reachable: bool) -> Vec<(String, bool)> {
+        vec![(endpoint.to_string(), reachable)]
+    }
+
+    #[test]
+    fn test_tracker_debounces_flapping() {
+        let mut tracker = TransitionTracker::new(2);
+        assert!(tracker.update(&probe("a.com", true)).is_empty());
+        // A single failure between successes is not a transition.
+        assert!(tracker.update(&probe("a.com", false)).is_empty());
+        assert!(tracker.update(&probe("a.com", true)).is_empty());
+        assert!(tracker.update(&probe("a.com", false)).is_empty());
+        assert_eq!(
+            tracker.update(&probe("a.com", false)),
+            [("a.com".to_string(), false)]
+        );
+        assert!(tracker.update(&probe("a.com", false)).is_empty());
+        assert!(tracker.update(&probe("a.com", true)).is_empty());
+        assert_eq!(
+            tracker.update(&probe("a.com", true)),
+            [("a.com".to_string(), true)]
+        );
+    }
+
+    #[test]
+    fn test_tracker_starts_without_transitions() {
+        let mut tracker = TransitionTracker::new(1);
+        assert!(tracker
+            .update(&[("a.com".to_string(), false), ("b.com".to_string(), true)])
+            .is_empty());
+        assert_eq!(
+            tracker.update(&[("a.com".to_string(), true), ("b.com".to_string(), true)]),
+            [("a.com".to_string(), true)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_webhook_fires_once_per_transition() {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let app = Router::new()
+            .route(
+                "/hook",
+                post(
+                    |State(received): State<Arc<Mutex<Vec<HealthTransition>>>>,
+                     Json(transition): Json<HealthTransition>| async move {
+                        received.lock().unwrap().push(transition);
+                    },
+                ),
+            )
+            .with_state(received.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let client = Client::new();
+        let mut tracker = TransitionTracker::new(2);
+        for reachable in [true, true, false, false, false, false] {
+            let transitions = tracker.update(&probe("api.weatherapi.com", reachable));
+            notify(&client, &url, "00", transitions).await;
+        }
+        assert_eq!(
+            *received.lock().unwrap(),
+            [HealthTransition {
+                endpoint: "api.weatherapi.com".to_string(),
+                reachable: false,
+                pk: "00".to_string(),
+            }]
+        );
+    }
+}
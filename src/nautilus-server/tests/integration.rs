@@ -0,0 +1,155 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Runs the real router in-process, with a mock attestation document in
+//! place of the NSM and a mock weather API, and checks responses the way a
+//! client would: signatures against the key `/health_check` reports.
+
+use axum::http::StatusCode;
+use fastcrypto::encoding::{Encoding, Hex};
+use nautilus_server::app::WeatherResponse;
+use nautilus_server::attestation::AttestationDocument;
+use nautilus_server::common::{wall_clock_ms, GetAttestationResponse};
+use nautilus_server::test_utils::{
+    mock_weather_upstream, test_config, verify_signed, TestEnclave, MOCK_TEMPERATURE,
+    UNKNOWN_LOCATION,
+};
+use serde_json::{json, Value};
+
+/// An enclave in front of a weather API whose readings are `age_secs` old.
+async fn enclave(age_secs: u64) -> TestEnclave {
+    let upstream = mock_weather_upstream(wall_clock_ms() / 1000 - age_secs).await;
+    TestEnclave::start(test_config(upstream)).await
+}
+
+fn weather_request(location: &str) -> Value {
+    json!({ "payload": { "location": location } })
+}
+
+fn error_message(body: &Value) -> &str {
+    body["error"].as_str().unwrap()
+}
+
+#[tokio::test]
+async fn test_attestation() {
+    let enclave = enclave(0).await;
+    let (status, body) = enclave.get("/get_attestation").await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let response: GetAttestationResponse = serde_json::from_value(body).unwrap();
+    let document = Hex::decode(&response.attestation).unwrap();
+    assert_eq!(document, enclave.document);
+
+    // The document attests the key responses are signed with
+    let parsed = AttestationDocument::parse(&document).unwrap();
+    assert_eq!(
+        parsed.public_key.unwrap().to_vec(),
+        enclave.health_check_public_key().await
+    );
+    assert_eq!(parsed.pcrs[&0].to_vec(), vec![0xaa; 48]);
+}
+
+#[tokio::test]
+async fn test_process_data() {
+    let enclave = enclave(60).await;
+    let public_key = enclave.health_check_public_key().await;
+    let (status, body) = enclave
+        .post("/process_data", &weather_request("Paris"))
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let signed = verify_signed::<WeatherResponse>(&public_key, &body);
+    assert_eq!(signed.response.data.location, "Paris");
+    assert_eq!(signed.response.data.temperature, MOCK_TEMPERATURE);
+
+    // Health checks report the successful fetch
+    let (_, health) = enclave.get("/health_check").await;
+    assert!(health["ms_since_last_successful_fetch"]["weather"].is_u64());
+
+    // A response altered after signing doesn't verify
+    let mut tampered = body.clone();
+    tampered["response"]["data"]["temperature"] = json!(MOCK_TEMPERATURE + 1);
+    let tampered: nautilus_verifier::SignedResponse<WeatherResponse> =
+        serde_json::from_value(tampered).unwrap();
+    assert!(nautilus_verifier::verify_response(&public_key, &tampered).is_err());
+}
+
+#[tokio::test]
+async fn test_stale_reading() {
+    // Readings over an hour old are refused
+    let enclave = enclave(2 * 3600).await;
+    let (status, body) = enclave
+        .post("/process_data", &weather_request("Paris"))
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    assert!(
+        error_message(&body).contains("Weather API timestamp is too old"),
+        "{}",
+        body
+    );
+}
+
+#[tokio::test]
+async fn test_invalid_requests() {
+    let enclave = enclave(0).await;
+    for (body, expected) in [
+        (
+            json!({ "payload": { "location": "Paris", "lang": "xx" } }),
+            StatusCode::BAD_REQUEST,
+        ),
+        (
+            json!({ "payload": { "location": "Paris" }, "intent": 255 }),
+            StatusCode::BAD_REQUEST,
+        ),
+        (weather_request(UNKNOWN_LOCATION), StatusCode::NOT_FOUND),
+    ] {
+        let (status, response) = enclave.post("/process_data", &body).await;
+        assert_eq!(status, expected, "{} -> {}", body, response);
+        assert!(!error_message(&response).is_empty(), "{}", response);
+    }
+
+    // Not a request at all
+    let (status, _) = enclave
+        .post("/process_data", &json!({ "location": "Paris" }))
+        .await;
+    assert!(status.is_client_error(), "{}", status);
+}
+
+#[tokio::test]
+async fn test_process_data_batch() {
+    let enclave = enclave(60).await;
+    let public_key = enclave.health_check_public_key().await;
+    let batch = |locations: &[&str]| {
+        let requests: Vec<Value> = locations
+            .iter()
+            .map(|location| json!({ "location": location }))
+            .collect();
+        json!({ "payload": { "requests": requests } })
+    };
+
+    let (status, body) = enclave
+        .post("/process_data_batch", &batch(&["Paris", "London"]))
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let responses = body.as_array().unwrap();
+    assert_eq!(responses.len(), 2);
+    for (response, location) in responses.iter().zip(["Paris", "London"]) {
+        let signed = verify_signed::<WeatherResponse>(&public_key, response);
+        assert_eq!(signed.response.data.location, location);
+    }
+
+    // One failed request fails the whole batch, nothing is signed
+    let (status, body) = enclave
+        .post(
+            "/process_data_batch",
+            &batch(&["Paris", UNKNOWN_LOCATION, "London"]),
+        )
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND, "{}", body);
+    assert!(
+        body.get("signature").is_none() && !body.is_array(),
+        "{}",
+        body
+    );
+
+    let (status, body) = enclave.post("/process_data_batch", &batch(&[])).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+}
@@ -0,0 +1,231 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Runs the nautilus-verify binary against fixture documents, and against a
+//! server whose attestation cache is seeded with one.
+
+use fastcrypto::ed25519::Ed25519KeyPair;
+use fastcrypto::traits::KeyPair;
+use nautilus_server::api_keys::ApiKeys;
+use nautilus_server::attestation::AttestationDocument;
+use nautilus_server::config::Config;
+use nautilus_server::header_limits::HeaderLimits;
+use nautilus_server::pem::attestation_pem;
+use nautilus_server::routes::{router, serve, TrailingSlash};
+use nautilus_server::AppState;
+use serde_bytes::ByteBuf;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::net::TcpListener;
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+/// A COSE_Sign1 document with PCR0 filled with `pcr0`, PCR1 and PCR2 with
+/// their index, generated at `timestamp` ms.
+fn fixture(pcr0: u8, timestamp: u64) -> Vec<u8> {
+    let payload = AttestationDocument {
+        module_id: "i-0123456789abcdef0-enc0123456789abcdef".to_string(),
+        digest: "SHA384".to_string(),
+        timestamp,
+        pcrs: (0..3)
+            .map(|index| {
+                let value = if index == 0 { pcr0 } else { index as u8 };
+                (index, ByteBuf::from(vec![value; 48]))
+            })
+            .collect(),
+        public_key: Some(ByteBuf::from(vec![7; 32])),
+        user_data: None,
+        nonce: None,
+        certificate: None,
+        cabundle: Vec::new(),
+    };
+    serde_cbor::to_vec(&(
+        ByteBuf::new(),
+        BTreeMap::<u8, u8>::new(),
+        ByteBuf::from(serde_cbor::to_vec(&payload).unwrap()),
+        ByteBuf::from(vec![0; 96]),
+    ))
+    .unwrap()
+}
+
+/// Write `document` to a file named `name` under the test's scratch dir.
+fn write_fixture(name: &str, document: &[u8]) -> String {
+    let path = scratch(name);
+    std::fs::write(&path, document).unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+fn scratch(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("nautilus-verify-{}", name))
+}
+
+fn run(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_nautilus-verify"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn run_json(args: &[&str]) -> (Option<i32>, Value) {
+    let mut args = args.to_vec();
+    args.push("--json");
+    let output = run(&args);
+    let json = serde_json::from_slice(&output.stdout).unwrap();
+    (output.status.code(), json)
+}
+
+#[test]
+fn test_inspect() {
+    let file = write_fixture("inspect.cbor", &fixture(0xaa, 1_700_000_000_000));
+    let output = run(&["inspect", "--file", &file]);
+    assert!(output.status.success());
+    let text = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        text.contains(&format!("PCR0        {}", "aa".repeat(48))),
+        "{}",
+        text
+    );
+    assert!(text.contains("timestamp:  1700000000000 ms"), "{}", text);
+    assert!(
+        text.contains(&format!("public_key: {}", "07".repeat(32))),
+        "{}",
+        text
+    );
+
+    let (code, json) = run_json(&["inspect", "--file", &file]);
+    assert_eq!(code, Some(0));
+    assert_eq!(json["module_id"], "i-0123456789abcdef0-enc0123456789abcdef");
+    assert_eq!(json["pcrs"]["2"], "02".repeat(48));
+
+    // The same document saved as PEM
+    let pem = attestation_pem(&fixture(0xaa, 1_700_000_000_000));
+    let file = write_fixture("inspect.pem", pem.as_bytes());
+    let (code, pem_json) = run_json(&["inspect", "--file", &file]);
+    assert_eq!(code, Some(0));
+    assert_eq!(pem_json, json);
+}
+
+#[test]
+fn test_check() {
+    let pcr0 = "aa".repeat(48);
+    let pcr1 = "01".repeat(48);
+    let fresh = write_fixture("fresh.cbor", &fixture(0xaa, now_ms()));
+    let (code, json) = run_json(&[
+        "check",
+        "--file",
+        &fresh,
+        "--pcr0",
+        &pcr0,
+        "--pcr1",
+        &pcr1,
+        "--max-age",
+        "60",
+    ]);
+    assert_eq!(
+        (code, json["ok"].as_bool()),
+        (Some(0), Some(true)),
+        "{}",
+        json
+    );
+
+    let mismatched = write_fixture("mismatched.cbor", &fixture(0xbb, now_ms()));
+    let (code, json) = run_json(&["check", "--file", &mismatched, "--pcr0", &pcr0]);
+    assert_eq!(code, Some(3));
+    assert_eq!(json["reason"], "pcr_mismatch");
+    assert!(
+        json["message"]
+            .as_str()
+            .unwrap()
+            .starts_with("PCR0 is bbbb"),
+        "{}",
+        json
+    );
+    let output = run(&["check", "--file", &mismatched, "--pcr0", &pcr0]);
+    assert_eq!(output.status.code(), Some(3));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.starts_with("pcr_mismatch: PCR0 is bbbb"),
+        "{}",
+        stderr
+    );
+
+    let expired = now_ms() - Duration::from_secs(3600).as_millis() as u64;
+    let expired = write_fixture("expired.cbor", &fixture(0xaa, expired));
+    let (code, json) = run_json(&[
+        "check",
+        "--file",
+        &expired,
+        "--pcr0",
+        &pcr0,
+        "--max-age",
+        "60",
+    ]);
+    assert_eq!(code, Some(4));
+    assert_eq!(json["reason"], "too_old");
+
+    // The fixture's user_data carries no build commit
+    let commit = "c0".repeat(20);
+    let (code, json) = run_json(&["check", "--file", &fresh, "--build-commit", &commit]);
+    assert_eq!(code, Some(5));
+    assert_eq!(json["reason"], "build_commit_mismatch");
+    let (code, _) = run_json(&["check", "--file", &fresh, "--build-commit", "c0"]);
+    assert_eq!(code, Some(1));
+
+    let invalid = write_fixture("invalid.cbor", b"not cbor");
+    let (code, json) = run_json(&["check", "--file", &invalid]);
+    assert_eq!(code, Some(2));
+    assert_eq!(json["reason"], "invalid_document");
+
+    let (code, json) = run_json(&["check", "--file", &fresh, "--pcr0", "aa"]);
+    assert_eq!(code, Some(1));
+    assert_eq!(json["reason"], "usage");
+    let (code, _) = run_json(&["check", "--pcr0", &pcr0]);
+    assert_eq!(code, Some(1));
+}
+
+#[tokio::test]
+async fn test_fetch() {
+    let document = fixture(0xaa, 1_700_000_000_000);
+    let state = AppState::new(
+        Ed25519KeyPair::generate(&mut rand::thread_rng()),
+        ApiKeys::single("test-key".to_string()),
+        Config {
+            attestation_cache_ttl: Duration::from_secs(3600),
+            ..Config::default()
+        },
+    )
+    .unwrap();
+    // The NSM only exists inside an enclave, so /get_attestation serves this
+    state
+        .attestation_cache
+        .seed(document.clone(), 1_700_000_000_000);
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    tokio::spawn(serve(
+        listener,
+        router(Arc::new(state)),
+        TrailingSlash::Strict,
+        HeaderLimits::from_config(&Config::default()),
+    ));
+
+    let out = scratch("fetched.cbor");
+    let out = out.to_str().unwrap().to_string();
+    let args = ["fetch", "--url", &url, "--out", &out].map(str::to_string);
+    let output = tokio::task::spawn_blocking(move || {
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        run(&args)
+    })
+    .await
+    .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(std::fs::read(&out).unwrap(), document);
+}
@@ -0,0 +1,463 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::Error;
+use serde::de::IgnoredAny;
+use serde::{Deserialize, Serialize};
+use serde_bytes::ByteBuf;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+
+/// Payload of an NSM attestation document. Only the contents are read: the
+/// COSE signature and certificate chain are left to the verifier registering
+/// the enclave, e.g. `register_enclave` in `enclave.move`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AttestationDocument {
+    /// ID of the enclave that requested the document.
+    pub module_id: String,
+    /// Digest function of the PCRs, `SHA384`.
+    pub digest: String,
+    /// When the NSM generated the document, in milliseconds since the UNIX
+    /// epoch.
+    pub timestamp: u64,
+    /// PCRs by index.
+    pub pcrs: BTreeMap<usize, ByteBuf>,
+    /// Public key the document commits to.
+    #[serde(default)]
+    pub public_key: Option<ByteBuf>,
+    #[serde(default)]
+    pub user_data: Option<ByteBuf>,
+    #[serde(default)]
+    pub nonce: Option<ByteBuf>,
+    /// DER certificate of the key that signed the document.
+    #[serde(default)]
+    pub certificate: Option<ByteBuf>,
+    /// DER certificates from the AWS Nitro root down to the issuer of
+    /// `certificate`.
+    #[serde(default)]
+    pub cabundle: Vec<ByteBuf>,
+}
+
+impl AttestationDocument {
+    /// Read the payload of a COSE_Sign1 attestation document.
+    pub fn parse(document: &[u8]) -> Result<Self, Error> {
+        let invalid = |e: serde_cbor::Error| Error::InvalidAttestation(e.to_string());
+        let (_protected, _unprotected, payload, _signature): (
+            ByteBuf,
+            IgnoredAny,
+            ByteBuf,
+            ByteBuf,
+        ) = serde_cbor::from_slice(document).map_err(invalid)?;
+        serde_cbor::from_slice(&payload).map_err(invalid)
+    }
+
+    /// The document's fields, hex encoded.
+    pub fn summary(&self) -> DocumentSummary {
+        let hex = |bytes: &Option<ByteBuf>| bytes.as_ref().map(hex::encode);
+        DocumentSummary {
+            module_id: self.module_id.clone(),
+            digest: self.digest.clone(),
+            timestamp_ms: self.timestamp,
+            pcrs: self
+                .pcrs
+                .iter()
+                .map(|(index, value)| (*index, hex::encode(value)))
+                .collect(),
+            public_key: hex(&self.public_key),
+            user_data: hex(&self.user_data),
+            build_commit: self
+                .parsed_user_data()
+                .and_then(|user_data| user_data.build_commit)
+                .map(hex::encode),
+            nonce: hex(&self.nonce),
+        }
+    }
+
+    /// The document's `user_data` split per [UserData], if it has that
+    /// layout.
+    pub fn parsed_user_data(&self) -> Option<UserData> {
+        let user_data = self
+            .user_data
+            .as_ref()
+            .map(|user_data| user_data.as_slice());
+        UserData::parse(user_data.unwrap_or_default())
+    }
+}
+
+/// Length of the SHA-256 of `allowed_endpoints.yaml` in `user_data`.
+pub const ENDPOINTS_DIGEST_LEN: usize = 32;
+
+/// Length of the git commit hash (SHA-1) in `user_data`.
+pub const BUILD_COMMIT_LEN: usize = 20;
+
+/// What the server puts in `user_data`, chosen with
+/// `ATTEST_ENDPOINTS_DIGEST` and `ATTEST_BUILD_COMMIT`. The bytes are the
+/// endpoints digest, if any, followed by the build commit, if any, with no
+/// framing:
+///
+/// | Options set | `user_data`                                         |
+/// |-------------|-----------------------------------------------------|
+/// | neither     | absent                                              |
+/// | digest      | 32 bytes: endpoints digest                          |
+/// | commit      | 20 bytes: build commit                              |
+/// | both        | 52 bytes: endpoints digest (0..32), commit (32..52) |
+///
+/// The length alone tells the layouts apart.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UserData {
+    /// SHA-256 of `allowed_endpoints.yaml`.
+    pub endpoints_digest: Option<[u8; ENDPOINTS_DIGEST_LEN]>,
+    /// Git commit the server was built from.
+    pub build_commit: Option<[u8; BUILD_COMMIT_LEN]>,
+}
+
+impl UserData {
+    /// Split `user_data` by its length, or `None` if no layout has it.
+    pub fn parse(user_data: &[u8]) -> Option<Self> {
+        let (digest, commit) = match user_data.len() {
+            0 => (None, None),
+            ENDPOINTS_DIGEST_LEN => (Some(user_data), None),
+            BUILD_COMMIT_LEN => (None, Some(user_data)),
+            len if len == ENDPOINTS_DIGEST_LEN + BUILD_COMMIT_LEN => {
+                let (digest, commit) = user_data.split_at(ENDPOINTS_DIGEST_LEN);
+                (Some(digest), Some(commit))
+            }
+            _ => return None,
+        };
+        Some(Self {
+            endpoints_digest: digest.map(|digest| digest.try_into().unwrap()),
+            build_commit: commit.map(|commit| commit.try_into().unwrap()),
+        })
+    }
+
+    /// The bytes to request the document with, `None` if empty.
+    pub fn to_bytes(&self) -> Option<Vec<u8>> {
+        let mut bytes = Vec::new();
+        if let Some(digest) = &self.endpoints_digest {
+            bytes.extend_from_slice(digest);
+        }
+        if let Some(commit) = &self.build_commit {
+            bytes.extend_from_slice(commit);
+        }
+        Some(bytes).filter(|bytes| !bytes.is_empty())
+    }
+}
+
+/// Hex encoded fields of an [AttestationDocument], as printed by
+/// `nautilus-verify inspect`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DocumentSummary {
+    pub module_id: String,
+    pub digest: String,
+    pub timestamp_ms: u64,
+    pub pcrs: BTreeMap<usize, String>,
+    pub public_key: Option<String>,
+    pub user_data: Option<String>,
+    /// The build commit in `user_data`, see [UserData].
+    #[serde(default)]
+    pub build_commit: Option<String>,
+    pub nonce: Option<String>,
+}
+
+impl fmt::Display for DocumentSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let none = || "none".to_string();
+        writeln!(f, "module_id:  {}", self.module_id)?;
+        writeln!(f, "timestamp:  {} ms", self.timestamp_ms)?;
+        writeln!(f, "digest:     {}", self.digest)?;
+        writeln!(
+            f,
+            "public_key: {}",
+            self.public_key.clone().unwrap_or_else(none)
+        )?;
+        writeln!(
+            f,
+            "user_data:  {}",
+            self.user_data.clone().unwrap_or_else(none)
+        )?;
+        if let Some(build_commit) = &self.build_commit {
+            writeln!(f, "commit:     {}", build_commit)?;
+        }
+        writeln!(f, "nonce:      {}", self.nonce.clone().unwrap_or_else(none))?;
+        for (index, value) in &self.pcrs {
+            writeln!(f, "PCR{:<2}      {}", index, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// What a document must show to pass [check].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Expectations {
+    /// Expected PCR values by index. PCRs not listed aren't checked.
+    pub pcrs: BTreeMap<usize, Vec<u8>>,
+    /// Oldest document accepted, if any.
+    pub max_age: Option<Duration>,
+    /// Git commit the server must have been built from, per [UserData].
+    pub build_commit: Option<[u8; BUILD_COMMIT_LEN]>,
+}
+
+/// Why a document failed [check].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CheckFailure {
+    /// The document doesn't report the expected value of PCR `index`.
+    PcrMismatch {
+        index: usize,
+        expected: Vec<u8>,
+        actual: Option<Vec<u8>>,
+    },
+    /// The document was generated more than `max_age` ago.
+    TooOld { age: Duration, max_age: Duration },
+    /// The document's `user_data` doesn't carry the expected build commit.
+    BuildCommitMismatch {
+        expected: [u8; BUILD_COMMIT_LEN],
+        actual: Option<[u8; BUILD_COMMIT_LEN]>,
+    },
+}
+
+impl CheckFailure {
+    /// Short name of the failure, e.g. for JSON output.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Self::PcrMismatch { .. } => "pcr_mismatch",
+            Self::TooOld { .. } => "too_old",
+            Self::BuildCommitMismatch { .. } => "build_commit_mismatch",
+        }
+    }
+}
+
+impl fmt::Display for CheckFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::PcrMismatch {
+                index,
+                expected,
+                actual: Some(actual),
+            } => write!(
+                f,
+                "PCR{} is {}, expected {}",
+                index,
+                hex::encode(actual),
+                hex::encode(expected)
+            ),
+            Self::PcrMismatch { index, .. } => write!(f, "PCR{} is missing", index),
+            Self::TooOld { age, max_age } => write!(
+                f,
+                "document is {}s old, more than the maximum of {}s",
+                age.as_secs(),
+                max_age.as_secs()
+            ),
+            Self::BuildCommitMismatch {
+                expected,
+                actual: Some(actual),
+            } => write!(
+                f,
+                "build commit is {}, expected {}",
+                hex::encode(actual),
+                hex::encode(expected)
+            ),
+            Self::BuildCommitMismatch { .. } => write!(f, "build commit is missing"),
+        }
+    }
+}
+
+/// Check `document` against `expected` at `now_ms`, PCRs first, then the
+/// build commit. A document from the future counts as new.
+pub fn check(
+    document: &AttestationDocument,
+    expected: &Expectations,
+    now_ms: u64,
+) -> Result<(), CheckFailure> {
+    for (index, value) in &expected.pcrs {
+        let actual = document.pcrs.get(index).map(|actual| actual.to_vec());
+        if actual.as_ref() != Some(value) {
+            return Err(CheckFailure::PcrMismatch {
+                index: *index,
+                expected: value.clone(),
+                actual,
+            });
+        }
+    }
+    if let Some(commit) = expected.build_commit {
+        let actual = document
+            .parsed_user_data()
+            .and_then(|user_data| user_data.build_commit);
+        if actual != Some(commit) {
+            return Err(CheckFailure::BuildCommitMismatch {
+                expected: commit,
+                actual,
+            });
+        }
+    }
+    if let Some(max_age) = expected.max_age {
+        let age = Duration::from_millis(now_ms.saturating_sub(document.timestamp));
+        if age > max_age {
+            return Err(CheckFailure::TooOld { age, max_age });
+        }
+    }
+    Ok(())
+}
+
+/// A COSE_Sign1 document as the NSM would return it for `payload`, with an
+/// empty protected header and signature numbered `serial`. Only for tests.
+#[cfg(test)]
+fn mock_document(payload: &AttestationDocument, serial: u8) -> Vec<u8> {
+    let payload = serde_cbor::to_vec(payload).unwrap();
+    serde_cbor::to_vec(&(
+        ByteBuf::new(),
+        BTreeMap::<u8, u8>::new(),
+        ByteBuf::from(payload),
+        ByteBuf::from(vec![serial; 96]),
+    ))
+    .unwrap()
+}
+
+/// The payload of a document from an enclave with `public_key`, PCR0 filled
+/// with `pcr0` and PCR1 and PCR2 with their index. Only for tests.
+#[cfg(test)]
+fn mock_payload(public_key: &[u8], pcr0: u8, timestamp: u64) -> AttestationDocument {
+    AttestationDocument {
+        module_id: "i-0123456789abcdef0-enc0123456789abcdef".to_string(),
+        digest: "SHA384".to_string(),
+        timestamp,
+        pcrs: (0..3)
+            .map(|index| {
+                let value = if index == 0 { pcr0 } else { index as u8 };
+                (index, ByteBuf::from(vec![value; 48]))
+            })
+            .collect(),
+        public_key: Some(ByteBuf::from(public_key.to_vec())),
+        user_data: None,
+        nonce: None,
+        certificate: None,
+        cabundle: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn document(pcr0: u8, timestamp: u64) -> AttestationDocument {
+        mock_payload(&[7; 32], pcr0, timestamp)
+    }
+
+    #[test]
+    fn test_parse_and_summary() {
+        let parsed = AttestationDocument::parse(&mock_document(&document(0xaa, 5), 0)).unwrap();
+        assert_eq!(parsed, document(0xaa, 5));
+        let summary = parsed.summary();
+        assert_eq!(summary.pcrs[&0], "aa".repeat(48));
+        assert_eq!(summary.public_key, Some("07".repeat(32)));
+        let text = summary.to_string();
+        assert!(text.contains("PCR2 "), "{}", text);
+        assert!(text.contains("nonce:      none"), "{}", text);
+        assert!(AttestationDocument::parse(b"not cbor").is_err());
+    }
+
+    #[test]
+    fn test_check() {
+        let minute = Duration::from_secs(60);
+        let expected = Expectations {
+            pcrs: [(0, vec![0xaa; 48]), (2, vec![2; 48])]
+                .into_iter()
+                .collect(),
+            max_age: Some(minute),
+            build_commit: None,
+        };
+        let now_ms = 1_000_000;
+        assert_eq!(
+            check(&document(0xaa, now_ms - 1000), &expected, now_ms),
+            Ok(())
+        );
+        // From the future
+        assert_eq!(
+            check(&document(0xaa, now_ms + 1000), &expected, now_ms),
+            Ok(())
+        );
+
+        let failure = check(&document(0xbb, now_ms), &expected, now_ms).unwrap_err();
+        assert_eq!(failure.reason(), "pcr_mismatch");
+        assert!(
+            failure.to_string().starts_with("PCR0 is bbbb"),
+            "{}",
+            failure
+        );
+
+        let failure = check(&document(0xaa, now_ms - 61_000), &expected, now_ms).unwrap_err();
+        assert_eq!(
+            failure,
+            CheckFailure::TooOld {
+                age: Duration::from_secs(61),
+                max_age: minute
+            }
+        );
+
+        let mut missing = document(0xaa, now_ms);
+        missing.pcrs.remove(&2);
+        let failure = check(&missing, &expected, now_ms).unwrap_err();
+        assert_eq!(failure.to_string(), "PCR2 is missing");
+    }
+
+    #[test]
+    fn test_user_data() {
+        let both = UserData {
+            endpoints_digest: Some([1; ENDPOINTS_DIGEST_LEN]),
+            build_commit: Some([2; BUILD_COMMIT_LEN]),
+        };
+        let bytes = both.to_bytes().unwrap();
+        assert_eq!(bytes.len(), 52);
+        assert_eq!(bytes[..32], [1; 32]);
+        assert_eq!(UserData::parse(&bytes), Some(both));
+        for user_data in [
+            UserData {
+                endpoints_digest: Some([1; ENDPOINTS_DIGEST_LEN]),
+                build_commit: None,
+            },
+            UserData {
+                endpoints_digest: None,
+                build_commit: Some([2; BUILD_COMMIT_LEN]),
+            },
+        ] {
+            let bytes = user_data.to_bytes().unwrap();
+            assert_eq!(UserData::parse(&bytes), Some(user_data));
+        }
+        assert_eq!(UserData::default().to_bytes(), None);
+        assert_eq!(UserData::parse(&[]), Some(UserData::default()));
+        assert_eq!(UserData::parse(&[0; 33]), None);
+    }
+
+    #[test]
+    fn test_check_build_commit() {
+        let commit = [0xc0; BUILD_COMMIT_LEN];
+        let expected = Expectations {
+            build_commit: Some(commit),
+            ..Default::default()
+        };
+        let mut attested = document(0xaa, 0);
+        attested.user_data = UserData {
+            endpoints_digest: Some([1; ENDPOINTS_DIGEST_LEN]),
+            build_commit: Some(commit),
+        }
+        .to_bytes()
+        .map(ByteBuf::from);
+        assert_eq!(check(&attested, &expected, 0), Ok(()));
+        assert_eq!(
+            attested.summary().build_commit,
+            Some("c0".repeat(BUILD_COMMIT_LEN))
+        );
+
+        let failure = check(&document(0xaa, 0), &expected, 0).unwrap_err();
+        assert_eq!(failure.reason(), "build_commit_mismatch");
+        assert_eq!(failure.to_string(), "build commit is missing");
+
+        attested.user_data = Some(ByteBuf::from(vec![0xbb; BUILD_COMMIT_LEN]));
+        let failure = check(&attested, &expected, 0).unwrap_err();
+        assert!(
+            failure.to_string().starts_with("build commit is bbbb"),
+            "{}",
+            failure
+        );
+    }
+}
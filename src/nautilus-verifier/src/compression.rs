@@ -0,0 +1,131 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Payloads too large to sign and send as they are can be compressed first.
+//! The enclave then signs, under [intent::COMPRESSED], a [CompressedPayload]
+//! committing to the compressed bytes, and returns those bytes with the
+//! signature. The compressed bytes are a single zstd frame of the BCS
+//! encoding of the payload, the `data` the enclave would otherwise sign
+//! under `payload_intent`.
+//!
+//! A verifier checks the signature as for any other message, then
+//! [decompress]es the bytes, which checks them against the commitment, and
+//! decodes the result as the payload of `payload_intent`. On-chain, only the
+//! commitment has to be submitted and verified.
+//!
+//! [intent::COMPRESSED]: crate::intent::COMPRESSED
+
+#[cfg(feature = "compression")]
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// [CompressedPayload::algorithm] of zstd.
+pub const ZSTD: u8 = 1;
+
+/// What the enclave signs for a compressed payload. Field order defines the
+/// BCS bytes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct CompressedPayload {
+    /// Intent scope of the payload that was compressed.
+    pub payload_intent: u8,
+    /// Compression algorithm, [ZSTD].
+    pub algorithm: u8,
+    /// Length of the payload's BCS bytes.
+    pub uncompressed_len: u64,
+    /// SHA-256 of the compressed bytes.
+    pub compressed_sha256: Vec<u8>,
+}
+
+impl CompressedPayload {
+    /// The commitment to `compressed`, the zstd compression of the
+    /// `uncompressed_len` BCS bytes of a payload of `payload_intent`.
+    pub fn new(payload_intent: u8, uncompressed_len: u64, compressed: &[u8]) -> Self {
+        Self {
+            payload_intent,
+            algorithm: ZSTD,
+            uncompressed_len,
+            compressed_sha256: Sha256::digest(compressed).to_vec(),
+        }
+    }
+}
+
+/// The BCS bytes of the payload `payload` commits to, decompressed from
+/// `compressed` once its digest, algorithm and length are checked. Check the
+/// signature of `payload` first.
+#[cfg(feature = "compression")]
+pub fn decompress(payload: &CompressedPayload, compressed: &[u8]) -> Result<Vec<u8>, Error> {
+    use std::io::Read;
+
+    let invalid = Error::InvalidCompression;
+    if payload.algorithm != ZSTD {
+        return Err(invalid(format!("unknown algorithm {}", payload.algorithm)));
+    }
+    if Sha256::digest(compressed).as_slice() != payload.compressed_sha256.as_slice() {
+        return Err(invalid("digest mismatch".to_string()));
+    }
+    let mut source = compressed;
+    let decoder = ruzstd::StreamingDecoder::new(&mut source).map_err(|e| invalid(e.to_string()))?;
+    // Read one byte past the committed length to catch longer payloads
+    let mut bytes = Vec::new();
+    decoder
+        .take(payload.uncompressed_len.saturating_add(1))
+        .read_to_end(&mut bytes)
+        .map_err(|e| invalid(e.to_string()))?;
+    if bytes.len() as u64 != payload.uncompressed_len {
+        return Err(invalid(format!(
+            "expected {} bytes, got {}",
+            payload.uncompressed_len,
+            bytes.len()
+        )));
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_compressed_payload_bcs() {
+        let payload = CompressedPayload::new(16, 576, b"");
+        // payload_intent | algorithm | uncompressed_len | compressed_sha256
+        assert_eq!(
+            hex::encode(bcs::to_bytes(&payload).unwrap()),
+            "1001400200000000000020\
+             e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_decompress() {
+        // `zstd -19` of "Nautilus " repeated 64 times
+        let compressed =
+            hex::decode("28b52ffd604001850000484e617574696c757320010034589523").unwrap();
+        let expected = b"Nautilus ".repeat(64);
+        let payload = CompressedPayload::new(16, expected.len() as u64, &compressed);
+        assert_eq!(decompress(&payload, &compressed).unwrap(), expected);
+
+        let mut tampered = compressed.clone();
+        tampered[10] ^= 1;
+        assert!(decompress(&payload, &tampered).is_err());
+        for payload in [
+            CompressedPayload {
+                algorithm: 2,
+                ..payload.clone()
+            },
+            CompressedPayload {
+                uncompressed_len: 575,
+                ..payload.clone()
+            },
+            CompressedPayload {
+                uncompressed_len: 577,
+                ..payload.clone()
+            },
+        ] {
+            assert!(decompress(&payload, &compressed).is_err(), "{:?}", payload);
+        }
+    }
+}
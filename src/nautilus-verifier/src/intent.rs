@@ -0,0 +1,47 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Intent scopes, the first byte of every signed message. The server's
+//! `IntentScope` takes its values from here, so add new scopes to both.
+
+pub const WEATHER: u8 = 0;
+/// Weather data that also commits to the digest of the enclave's boot
+/// attestation document.
+pub const WEATHER_WITH_ATTESTATION_DIGEST: u8 = 1;
+/// Weather data that also commits to how long the upstream fetch took.
+pub const WEATHER_WITH_UPSTREAM_LATENCY: u8 = 2;
+/// The upstream fields selected by `SIGNED_FIELDS`, canonicalized.
+pub const WEATHER_FIELDS: u8 = 3;
+/// An exchange rate signed by `/process_price`.
+pub const PRICE: u8 = 4;
+/// A commitment to a compressed payload, see [crate::compression].
+pub const COMPRESSED: u8 = 5;
+/// A GitHub commit signed by `/process_github_commit`.
+pub const GITHUB_COMMIT: u8 = 6;
+/// Sui transaction bytes approved by `/process_transaction`.
+pub const SUI_TRANSACTION: u8 = 7;
+
+/// Every registered scope.
+pub const ALL: [u8; 8] = [
+    WEATHER,
+    WEATHER_WITH_ATTESTATION_DIGEST,
+    WEATHER_WITH_UPSTREAM_LATENCY,
+    WEATHER_FIELDS,
+    PRICE,
+    COMPRESSED,
+    GITHUB_COMMIT,
+    SUI_TRANSACTION,
+];
+
+/// Set in the layout version of messages that commit to `expires_at_ms`.
+///
+/// Messages that commit to more than a timestamp carry a layout version right
+/// after the intent scope, made of the flags of the optional fields they
+/// have, so that the bytes of one layout never verify as another's. Messages
+/// in the plain layout `intent | timestamp_ms | data` have none. See
+/// [crate::layout_version].
+pub const EXPIRY_LAYOUT: u8 = 1;
+
+/// Set in the layout version of messages that commit to `sequence`, see
+/// [EXPIRY_LAYOUT].
+pub const SEQUENCE_LAYOUT: u8 = 2;
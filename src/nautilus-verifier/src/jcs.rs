@@ -0,0 +1,143 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use serde::Serialize;
+use serde_json::{Number, Value};
+
+/// `value` as JSON canonicalized per RFC 8785 (JCS): no whitespace, object
+/// keys sorted by their UTF-16 code units at every level, strings with only
+/// the mandatory escapes and numbers in their shortest ECMAScript form. The
+/// same value always yields the same bytes, so verifiers outside Sui can
+/// re-serialize a response and check its signature with any JCS library.
+/// Integers are printed exactly, so those above 2^53 won't match a verifier
+/// that reads numbers as doubles.
+pub fn to_vec<T: Serialize>(value: &T) -> Result<Vec<u8>, serde_json::Error> {
+    let value = serde_json::to_value(value)?;
+    let mut out = String::new();
+    write_value(&mut out, &value);
+    Ok(out.into_bytes())
+}
+
+fn write_value(out: &mut String, value: &Value) {
+    match value {
+        Value::Array(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_value(out, item);
+            }
+            out.push(']');
+        }
+        Value::Object(map) => {
+            let mut entries: Vec<(&String, &Value)> = map.iter().collect();
+            entries.sort_by(|(a, _), (b, _)| a.encode_utf16().cmp(b.encode_utf16()));
+            out.push('{');
+            for (i, (key, value)) in entries.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                // serde_json escapes exactly what RFC 8785 requires
+                out.push_str(&Value::from(key.as_str()).to_string());
+                out.push(':');
+                write_value(out, value);
+            }
+            out.push('}');
+        }
+        Value::Number(number) => out.push_str(&number_text(number)),
+        scalar => out.push_str(&scalar.to_string()),
+    }
+}
+
+/// A number as ECMAScript's `Number.prototype.toString` prints it.
+fn number_text(number: &Number) -> String {
+    if number.is_u64() || number.is_i64() {
+        return number.to_string();
+    }
+    let value = number.as_f64().expect("finite");
+    if value == 0.0 {
+        // Including -0
+        return "0".to_string();
+    }
+    let magnitude = value.abs();
+    if (1e-6..1e21).contains(&magnitude) {
+        // Rust prints the shortest round-tripping digits without an exponent
+        return value.to_string();
+    }
+    // ECMAScript signs positive exponents: 1e+21
+    let text = format!("{:e}", value);
+    match text.split_once('e') {
+        Some((mantissa, exponent)) if !exponent.starts_with('-') => {
+            format!("{}e+{}", mantissa, exponent)
+        }
+        _ => text,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn text(value: Value) -> String {
+        String::from_utf8(to_vec(&value).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_sorted_compact() {
+        assert_eq!(
+            text(json!({"b": [1, {"z": null, "a": true}], "a": "x", "A": 1.5})),
+            r#"{"A":1.5,"a":"x","b":[1,{"a":true,"z":null}]}"#
+        );
+    }
+
+    #[test]
+    fn test_deterministic() {
+        // The same data in any key order yields the same bytes
+        let first: Value = serde_json::from_str(r#"{"b": {"y": 2, "x": 1}, "a": [3, 4]}"#).unwrap();
+        let second: Value = serde_json::from_str(r#"{"a":[3,4],"b":{"x":1,"y":2}}"#).unwrap();
+        assert_eq!(to_vec(&first).unwrap(), to_vec(&second).unwrap());
+        assert_eq!(to_vec(&first).unwrap(), to_vec(&first).unwrap());
+    }
+
+    #[test]
+    fn test_utf16_key_order() {
+        // U+1D11E sorts after U+FB33 by UTF-8 bytes and code points, but
+        // before it by UTF-16 code units (surrogate D834), the order RFC 8785
+        // specifies
+        assert_eq!(
+            text(json!({"\u{fb33}": 2, "\u{1d11e}": 1})),
+            "{\"\u{1d11e}\":1,\"\u{fb33}\":2}"
+        );
+    }
+
+    #[test]
+    fn test_strings() {
+        assert_eq!(
+            text(json!("\u{8}\t\n\u{c}\r\"\\/\u{1f}\u{7f}é€")),
+            "\"\\b\\t\\n\\f\\r\\\"\\\\/\\u001f\u{7f}é€\""
+        );
+    }
+
+    #[test]
+    fn test_numbers() {
+        // ECMAScript formatting, partly from the RFC 8785 examples
+        let cases = [
+            (json!(0), "0"),
+            (json!(-0.0), "0"),
+            (json!(1.0), "1"),
+            (json!(-1.5), "-1.5"),
+            (json!(333333333.3333333), "333333333.3333333"),
+            (json!(1e21), "1e+21"),
+            (json!(1e-7), "1e-7"),
+            (json!(4.5e-7), "4.5e-7"),
+            (json!(0.000001), "0.000001"),
+            (json!(9007199254740991u64), "9007199254740991"),
+            (json!(-5), "-5"),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(text(value.clone()), expected, "{}", value);
+        }
+    }
+}
@@ -0,0 +1,356 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Verification of what a Nautilus enclave signs, without the server's
+//! runtime dependencies (tokio, reqwest, the NSM), so it also builds for
+//! `wasm32-unknown-unknown`, e.g. for a browser dashboard. The server builds
+//! its signed bytes with [signing_bytes], so this crate checks exactly what
+//! the enclave signed. Enable the `wasm` feature for JavaScript bindings.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+#[cfg(feature = "attestation")]
+pub mod attestation;
+pub mod compression;
+pub mod intent;
+pub mod jcs;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+/// Errors from verification.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The public key isn't a valid key for the scheme.
+    InvalidPublicKey,
+    /// The signature isn't hex, or has the wrong length for the scheme.
+    MalformedSignature,
+    /// The signature doesn't match the message and public key.
+    InvalidSignature,
+    /// The message couldn't be serialized in the signing format.
+    Serialization(String),
+    /// The attestation document couldn't be parsed.
+    InvalidAttestation(String),
+    /// Compressed bytes don't match the payload committing to them.
+    InvalidCompression(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidPublicKey => write!(f, "Invalid public key"),
+            Self::MalformedSignature => write!(f, "Malformed signature"),
+            Self::InvalidSignature => write!(f, "Signature doesn't match the public key"),
+            Self::Serialization(e) => write!(f, "Failed to serialize the message: {}", e),
+            Self::InvalidAttestation(e) => write!(f, "Invalid attestation document: {}", e),
+            Self::InvalidCompression(e) => write!(f, "Invalid compressed payload: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// A message signed by the enclave, as in the server's `IntentMessage`.
+/// Field order and the skipped fields define the BCS bytes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IntentMessage<T> {
+    /// Intent scope the message was signed under, one of [intent::ALL].
+    pub intent: u8,
+    /// See [layout_version].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<u8>,
+    pub timestamp_ms: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sequence: Option<u64>,
+    pub data: T,
+}
+
+/// A signed response as the enclave returns it. Other fields, such as `jwt`,
+/// are ignored.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedResponse<T> {
+    pub response: IntentMessage<T>,
+    /// Hex encoded Ed25519 signature of `response` in `signing_format`.
+    pub signature: String,
+    /// BCS when the enclave doesn't say.
+    #[serde(default)]
+    pub signing_format: SigningFormat,
+}
+
+/// Serialization of the intent message that the enclave signs, chosen with
+/// `SIGNING_FORMAT`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum SigningFormat {
+    /// BCS, as verified by `enclave.move`. See the server's `IntentMessage`
+    /// for the layouts.
+    #[default]
+    Bcs,
+    /// JSON canonicalized per RFC 8785, for verifiers outside Sui. See
+    /// [jcs::to_vec].
+    Jcs,
+}
+
+impl FromStr for SigningFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bcs" => Ok(Self::Bcs),
+            "jcs" => Ok(Self::Jcs),
+            _ => Err(format!("expected `bcs` or `jcs`, got `{}`", s)),
+        }
+    }
+}
+
+/// The bytes signed for `message` in `format`. The server signs these, so
+/// any intent message type with the same fields yields the same bytes.
+pub fn signing_bytes<T: Serialize>(format: SigningFormat, message: &T) -> Result<Vec<u8>, Error> {
+    match format {
+        SigningFormat::Bcs => {
+            bcs::to_bytes(message).map_err(|e| Error::Serialization(e.to_string()))
+        }
+        SigningFormat::Jcs => jcs::to_vec(message).map_err(|e| Error::Serialization(e.to_string())),
+    }
+}
+
+/// The layout version of a message with these optional fields, `None` for
+/// the plain layout. See [intent::EXPIRY_LAYOUT].
+pub fn layout_version(expires_at_ms: Option<u64>, sequence: Option<u64>) -> Option<u8> {
+    let version = expires_at_ms.map_or(0, |_| intent::EXPIRY_LAYOUT)
+        | sequence.map_or(0, |_| intent::SEQUENCE_LAYOUT);
+    (version != 0).then_some(version)
+}
+
+/// The BCS bytes of an intent message whose `data` serializes to
+/// `data_bcs`, for callers that encode the payload themselves, e.g. from
+/// JavaScript.
+pub fn intent_message_bcs(
+    intent: u8,
+    timestamp_ms: u64,
+    expires_at_ms: Option<u64>,
+    sequence: Option<u64>,
+    data_bcs: &[u8],
+) -> Vec<u8> {
+    let header = IntentMessage {
+        intent,
+        version: layout_version(expires_at_ms, sequence),
+        timestamp_ms,
+        expires_at_ms,
+        sequence,
+        data: (),
+    };
+    // A struct's BCS bytes are its fields' in order, and `()` adds none
+    let mut bytes = bcs::to_bytes(&header).expect("should not fail");
+    bytes.extend_from_slice(data_bcs);
+    bytes
+}
+
+/// Check an Ed25519 `signature` (64 bytes) of `message` by `public_key`
+/// (32 bytes), the scheme of the enclave key.
+pub fn verify_ed25519(public_key: &[u8], message: &[u8], signature: &[u8]) -> Result<(), Error> {
+    use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+    let public_key: &[u8; 32] = public_key.try_into().map_err(|_| Error::InvalidPublicKey)?;
+    let public_key = VerifyingKey::from_bytes(public_key).map_err(|_| Error::InvalidPublicKey)?;
+    let signature = Signature::from_slice(signature).map_err(|_| Error::MalformedSignature)?;
+    public_key
+        .verify(message, &signature)
+        .map_err(|_| Error::InvalidSignature)
+}
+
+/// Check a secp256k1 ECDSA `signature` (64 bytes, `r || s`) over the SHA-256
+/// of `message` by `public_key` (33 byte compressed or 65 byte uncompressed
+/// SEC1), as Sui does for secp256k1 keys.
+pub fn verify_secp256k1(public_key: &[u8], message: &[u8], signature: &[u8]) -> Result<(), Error> {
+    use k256::ecdsa::signature::Verifier;
+    use k256::ecdsa::{Signature, VerifyingKey};
+    let public_key =
+        VerifyingKey::from_sec1_bytes(public_key).map_err(|_| Error::InvalidPublicKey)?;
+    let signature = Signature::from_slice(signature).map_err(|_| Error::MalformedSignature)?;
+    public_key
+        .verify(message, &signature)
+        .map_err(|_| Error::InvalidSignature)
+}
+
+/// Check the signature of `response` by the enclave's Ed25519
+/// `public_key`, over its intent message in its `signing_format`.
+pub fn verify_response<T: Serialize>(
+    public_key: &[u8],
+    response: &SignedResponse<T>,
+) -> Result<(), Error> {
+    let signature = hex::decode(&response.signature).map_err(|_| Error::MalformedSignature)?;
+    let signed = signing_bytes(response.signing_format, &response.response)?;
+    verify_ed25519(public_key, &signed, &signature)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+    struct Weather {
+        location: String,
+        temperature: u64,
+    }
+
+    fn message() -> IntentMessage<Weather> {
+        IntentMessage {
+            intent: intent::WEATHER,
+            version: Some(intent::SEQUENCE_LAYOUT),
+            timestamp_ms: 1_744_038_900_000,
+            expires_at_ms: None,
+            sequence: Some(7),
+            data: Weather {
+                location: "Paris".to_string(),
+                temperature: 13,
+            },
+        }
+    }
+
+    #[test]
+    fn test_signing_bytes() {
+        let bcs = signing_bytes(SigningFormat::Bcs, &message()).unwrap();
+        assert_eq!(
+            hex::encode(&bcs),
+            "00010220b1d110960100000107000000000000000550617269730d00000000000000"
+        );
+        let data = bcs::to_bytes(&message().data).unwrap();
+        assert_eq!(
+            intent_message_bcs(intent::WEATHER, 1_744_038_900_000, None, Some(7), &data),
+            bcs
+        );
+
+        let jcs = signing_bytes(SigningFormat::Jcs, &message()).unwrap();
+        assert_eq!(
+            String::from_utf8(jcs).unwrap(),
+            r#"{"data":{"location":"Paris","temperature":13},"intent":0,"sequence":7,"timestamp_ms":1744038900000,"version":2}"#
+        );
+    }
+
+    #[test]
+    fn test_expiry_layout() {
+        let message = IntentMessage {
+            version: layout_version(Some(1_744_038_960_000), None),
+            expires_at_ms: Some(1_744_038_960_000),
+            sequence: None,
+            ..message()
+        };
+        let bcs = signing_bytes(SigningFormat::Bcs, &message).unwrap();
+        // The version follows the intent, as the option Move encodes it
+        assert_eq!(&bcs[..3], [intent::WEATHER, 1, intent::EXPIRY_LAYOUT]);
+        let data = bcs::to_bytes(&message.data).unwrap();
+        assert_eq!(
+            intent_message_bcs(
+                intent::WEATHER,
+                1_744_038_900_000,
+                Some(1_744_038_960_000),
+                None,
+                &data
+            ),
+            bcs
+        );
+        assert_eq!(
+            serde_json::to_value(&message).unwrap()["version"],
+            intent::EXPIRY_LAYOUT
+        );
+    }
+
+    #[test]
+    fn test_layouts_do_not_collide() {
+        // A message signed with an expiry doesn't verify as one with a
+        // sequence number of the same value, or the other way around. Also
+        // checked by `test_layouts_do_not_collide` in `enclave.move`.
+        use ed25519_dalek::{Signer, SigningKey};
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let public_key = key.verifying_key().to_bytes();
+        let with_layout = |expires_at_ms, sequence| IntentMessage {
+            version: layout_version(expires_at_ms, sequence),
+            expires_at_ms,
+            sequence,
+            ..message()
+        };
+        let n = 1_744_038_960_000;
+        for (signed, other) in [
+            (with_layout(Some(n), None), with_layout(None, Some(n))),
+            (with_layout(None, Some(n)), with_layout(Some(n), None)),
+        ] {
+            let signature = key.sign(&signing_bytes(SigningFormat::Bcs, &signed).unwrap());
+            let response = |message| SignedResponse {
+                response: message,
+                signature: hex::encode(signature.to_bytes()),
+                signing_format: SigningFormat::Bcs,
+            };
+            assert_eq!(verify_response(&public_key, &response(signed)), Ok(()));
+            assert_eq!(
+                verify_response(&public_key, &response(other)),
+                Err(Error::InvalidSignature)
+            );
+        }
+    }
+
+    #[test]
+    fn test_verify_ed25519() {
+        use ed25519_dalek::{Signer, SigningKey};
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let public_key = key.verifying_key().to_bytes();
+        for format in [SigningFormat::Bcs, SigningFormat::Jcs] {
+            let signed = signing_bytes(format, &message()).unwrap();
+            let response = SignedResponse {
+                response: message(),
+                signature: hex::encode(key.sign(&signed).to_bytes()),
+                signing_format: format,
+            };
+            assert_eq!(verify_response(&public_key, &response), Ok(()));
+            let mut tampered = response.clone();
+            tampered.response.timestamp_ms += 1;
+            assert_eq!(
+                verify_response(&public_key, &tampered),
+                Err(Error::InvalidSignature)
+            );
+        }
+        assert_eq!(
+            verify_ed25519(&[1; 31], b"", &[0; 64]),
+            Err(Error::InvalidPublicKey)
+        );
+        assert_eq!(
+            verify_ed25519(&public_key, b"", &[0; 63]),
+            Err(Error::MalformedSignature)
+        );
+    }
+
+    #[test]
+    fn test_verify_secp256k1() {
+        use k256::ecdsa::signature::Signer;
+        use k256::ecdsa::{Signature, SigningKey};
+        let key = SigningKey::from_slice(&[7; 32]).unwrap();
+        let public_key = key.verifying_key().to_sec1_bytes();
+        let signature: Signature = key.sign(b"hello");
+        let signature = signature.to_bytes();
+        assert_eq!(verify_secp256k1(&public_key, b"hello", &signature), Ok(()));
+        assert_eq!(
+            verify_secp256k1(&public_key, b"hellO", &signature),
+            Err(Error::InvalidSignature)
+        );
+        assert_eq!(
+            verify_secp256k1(&[5; 33], b"hello", &signature),
+            Err(Error::InvalidPublicKey)
+        );
+    }
+
+    #[test]
+    fn test_signing_format() {
+        assert_eq!("jcs".parse(), Ok(SigningFormat::Jcs));
+        assert!("json".parse::<SigningFormat>().is_err());
+        // Responses from enclaves that predate the field are BCS
+        let legacy: SignedResponse<u64> = serde_json::from_value(
+            json!({"response": {"intent": 0, "timestamp_ms": 1, "data": 2}, "signature": ""}),
+        )
+        .unwrap();
+        assert_eq!(legacy.signing_format, SigningFormat::Bcs);
+    }
+}
@@ -0,0 +1,62 @@
+// Copyright (c), Mysten Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! JavaScript bindings, with the `wasm` feature. Errors are thrown as
+//! `Error`s carrying the message of [crate::Error].
+
+use crate::{SignedResponse, SigningFormat};
+use serde_json::Value;
+use wasm_bindgen::prelude::*;
+
+/// Check a signed response as returned by the enclave, given as JSON, with
+/// the enclave's Ed25519 public key. Only responses signed with
+/// `SIGNING_FORMAT=jcs` can be checked from JSON alone: for BCS the payload's
+/// layout is needed, see [intent_message_bcs] and [verify_ed25519].
+#[wasm_bindgen(js_name = verifyResponse)]
+pub fn verify_response(public_key: &[u8], response_json: &str) -> Result<(), JsError> {
+    let response: SignedResponse<Value> = serde_json::from_str(response_json)?;
+    if response.signing_format != SigningFormat::Jcs {
+        return Err(JsError::new(
+            "BCS responses can't be checked from JSON, use intentMessageBcs and verifyEd25519",
+        ));
+    }
+    Ok(crate::verify_response(public_key, &response)?)
+}
+
+/// The BCS bytes of an intent message whose `data` serializes to
+/// `data_bcs`, see [crate::intent_message_bcs].
+#[wasm_bindgen(js_name = intentMessageBcs)]
+pub fn intent_message_bcs(
+    intent: u8,
+    timestamp_ms: u64,
+    expires_at_ms: Option<u64>,
+    sequence: Option<u64>,
+    data_bcs: &[u8],
+) -> Vec<u8> {
+    crate::intent_message_bcs(intent, timestamp_ms, expires_at_ms, sequence, data_bcs)
+}
+
+/// See [crate::verify_ed25519].
+#[wasm_bindgen(js_name = verifyEd25519)]
+pub fn verify_ed25519(public_key: &[u8], message: &[u8], signature: &[u8]) -> Result<(), JsError> {
+    Ok(crate::verify_ed25519(public_key, message, signature)?)
+}
+
+/// See [crate::verify_secp256k1].
+#[wasm_bindgen(js_name = verifySecp256k1)]
+pub fn verify_secp256k1(
+    public_key: &[u8],
+    message: &[u8],
+    signature: &[u8],
+) -> Result<(), JsError> {
+    Ok(crate::verify_secp256k1(public_key, message, signature)?)
+}
+
+/// The fields of an attestation document as JSON, see
+/// [crate::attestation::DocumentSummary].
+#[cfg(feature = "attestation")]
+#[wasm_bindgen(js_name = inspectAttestation)]
+pub fn inspect_attestation(document: &[u8]) -> Result<String, JsError> {
+    let summary = crate::attestation::AttestationDocument::parse(document)?.summary();
+    Ok(serde_json::to_string(&summary)?)
+}
@@ -1,16 +1,18 @@
 // Copyright (c), Mysten Labs, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
-use libc::{c_int, c_ulong, c_void};
+use libc::{c_char, c_int, c_ulong, c_void};
 use std::{
     ffi::CString,
     fmt,
     fs::File,
     mem::{size_of, zeroed},
     os::unix::io::AsRawFd,
+    sync::OnceLock,
 };
 
 // Referenced from: https://git.distrust.co/public/enclaveos/src/branch/master/src/system/src/lib.rs
+#[derive(Debug)]
 pub struct SystemError {
     pub message: String,
 }
@@ -20,13 +22,31 @@ impl fmt::Display for SystemError {
     }
 }
 
+// Called with every dmesg message after it is printed, see set_dmesg_hook
+static DMESG_HOOK: OnceLock<fn(&str)> = OnceLock::new();
+
 // Log dmesg formatted log to console
 pub fn dmesg(message: String) {
     println!("{} {}", boot_time(), message);
+    if let Some(hook) = DMESG_HOOK.get() {
+        hook(&message);
+    }
+}
+
+// Also pass every later dmesg message to `hook`, e.g. to send it somewhere
+// besides the console. Only the first hook set in a process is kept.
+pub fn set_dmesg_hook(hook: fn(&str)) {
+    let _ = DMESG_HOOK.set(hook);
 }
 
 // Dmesg formatted seconds since boot
 pub fn boot_time() -> String {
+    let micros = boot_time_micros();
+    format!("[ {: >4}.{}]", micros / 1_000_000, micros % 1_000_000).to_string()
+}
+
+// Microseconds since boot, including time suspended
+pub fn boot_time_micros() -> u64 {
     use libc::{clock_gettime, timespec, CLOCK_BOOTTIME};
     let mut t = timespec {
         tv_sec: 0,
@@ -35,7 +55,7 @@ pub fn boot_time() -> String {
     unsafe {
         clock_gettime(CLOCK_BOOTTIME, &mut t as *mut timespec);
     }
-    format!("[ {: >4}.{}]", t.tv_sec, t.tv_nsec / 1000).to_string()
+    t.tv_sec as u64 * 1_000_000 + t.tv_nsec as u64 / 1000
 }
 
 // Unconditionally reboot the system now
@@ -46,6 +66,14 @@ pub fn reboot() {
     }
 }
 
+// Unconditionally power off the system now
+pub fn poweroff() {
+    use libc::{reboot, RB_POWER_OFF};
+    unsafe {
+        reboot(RB_POWER_OFF);
+    }
+}
+
 // libc::mount casting/error wrapper
 pub fn mount(
     src: &str,
@@ -98,19 +126,107 @@ pub fn freopen(filename: &str, mode: &str, file: c_int) -> Result<(), SystemErro
         freopen(
             filename_cs.as_ptr(),
             mode_cs.as_ptr(),
-            fdopen(file, mode_cs.as_ptr() as *const i8),
+            fdopen(file, mode_cs.as_ptr()),
         )
     }
     .is_null()
     {
         Err(SystemError {
-            message: format!("Failed to freopen: {}", filename),
+            message: format!(
+                "Failed to freopen {}: {}",
+                filename,
+                std::io::Error::last_os_error()
+            ),
+        })
+    } else {
+        Ok(())
+    }
+}
+
+// libc::sethostname casting/error wrapper
+pub fn sethostname(name: &str) -> Result<(), SystemError> {
+    use libc::sethostname;
+    if unsafe { sethostname(name.as_ptr() as *const c_char, name.len()) } != 0 {
+        Err(SystemError {
+            message: format!("Failed to set hostname: {}", name),
         })
     } else {
         Ok(())
     }
 }
 
+// Bring a network interface up, as `ip link set dev <name> up` does. The
+// kernel assigns 127.0.0.1/8 when the loopback interface comes up.
+pub fn interface_up(name: &str) -> Result<(), SystemError> {
+    use libc::{close, ifreq, ioctl, socket, AF_INET, IFF_UP, SIOCGIFFLAGS, SIOCSIFFLAGS};
+    use libc::{IFNAMSIZ, SOCK_CLOEXEC, SOCK_DGRAM};
+    let error = |action: &str| SystemError {
+        message: format!(
+            "Failed to {} interface {}: {}",
+            action,
+            name,
+            std::io::Error::last_os_error()
+        ),
+    };
+    if name.is_empty() || name.len() >= IFNAMSIZ {
+        return Err(SystemError {
+            message: format!("Invalid interface name: {}", name),
+        });
+    }
+    let fd = unsafe { socket(AF_INET, SOCK_DGRAM | SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(error("open a socket for"));
+    }
+    let result = unsafe {
+        let mut request: ifreq = zeroed();
+        for (dst, src) in request.ifr_name.iter_mut().zip(name.bytes()) {
+            *dst = src as c_char;
+        }
+        if ioctl(fd, SIOCGIFFLAGS as _, &mut request) < 0 {
+            Err(error("read flags of"))
+        } else {
+            request.ifr_ifru.ifru_flags |= IFF_UP as libc::c_short;
+            if ioctl(fd, SIOCSIFFLAGS as _, &request) < 0 {
+                Err(error("bring up"))
+            } else {
+                Ok(())
+            }
+        }
+    };
+    unsafe {
+        close(fd);
+    }
+    result
+}
+
+// Local vsock CID of the enclave, read from /dev/vsock with
+// IOCTL_VM_SOCKETS_GET_LOCAL_CID (_IO(7, 0xb9) in linux/vm_sockets.h)
+pub fn local_cid() -> Result<u32, SystemError> {
+    use libc::ioctl;
+    const IOCTL_VM_SOCKETS_GET_LOCAL_CID: c_ulong = 0x7b9;
+    let file = File::open("/dev/vsock").map_err(|e| SystemError {
+        message: format!("Failed to open /dev/vsock: {}", e),
+    })?;
+    let mut cid: u32 = 0;
+    if unsafe {
+        ioctl(
+            file.as_raw_fd(),
+            IOCTL_VM_SOCKETS_GET_LOCAL_CID as _,
+            &mut cid,
+        )
+    } < 0
+    {
+        Err(SystemError {
+            message: format!(
+                "Failed to read local CID: {}",
+                std::io::Error::last_os_error()
+            ),
+        })
+    } else {
+        Ok(cid)
+    }
+}
+
 // Insert kernel module into memory
 pub fn insmod(path: &str) -> Result<(), SystemError> {
     use libc::{syscall, SYS_finit_module};
@@ -151,6 +267,56 @@ pub fn socket_connect(family: c_int, port: u32, cid: u32) -> Result<c_int, Syste
     }
 }
 
+// Listen on a vsock port for connections from any CID
+pub fn socket_listen(port: u32, backlog: c_int) -> Result<c_int, SystemError> {
+    use libc::{bind, close, listen, sockaddr, sockaddr_vm, socket, AF_VSOCK};
+    use libc::{SOCK_STREAM, VMADDR_CID_ANY};
+    let fd = unsafe { socket(AF_VSOCK, SOCK_STREAM, 0) };
+    if fd < 0 {
+        return Err(SystemError {
+            message: format!("Failed to create vsock socket for port {}", port),
+        });
+    }
+    if unsafe {
+        let mut sa: sockaddr_vm = zeroed();
+        sa.svm_family = AF_VSOCK as _;
+        sa.svm_port = port;
+        sa.svm_cid = VMADDR_CID_ANY;
+        bind(
+            fd,
+            &sa as *const _ as *const sockaddr,
+            size_of::<sockaddr_vm>() as _,
+        ) < 0
+            || listen(fd, backlog) < 0
+    } {
+        unsafe {
+            close(fd);
+        }
+        Err(SystemError {
+            message: format!("Failed to listen on vsock port {}", port),
+        })
+    } else {
+        Ok(fd)
+    }
+}
+
+// Listen on a vsock port and accept a single connection
+pub fn socket_accept(port: u32) -> Result<c_int, SystemError> {
+    use libc::{accept, close};
+    let fd = socket_listen(port, 1)?;
+    let conn = unsafe { accept(fd, std::ptr::null_mut(), std::ptr::null_mut()) };
+    unsafe {
+        close(fd);
+    }
+    if conn < 0 {
+        Err(SystemError {
+            message: format!("Failed to accept vsock connection on port {}", port),
+        })
+    } else {
+        Ok(conn)
+    }
+}
+
 // Seed an entropy sample into the kernel randomness pool.
 pub fn seed_entropy(
     size: usize,
@@ -158,10 +324,7 @@ pub fn seed_entropy(
 ) -> Result<usize, SystemError> {
     use std::io::Write;
 
-    let entropy_sample = match source(size) {
-        Ok(sample) => sample,
-        Err(e) => return Err(e),
-    };
+    let entropy_sample = source(size)?;
 
     use std::fs::OpenOptions;
     let mut random_fd = match OpenOptions::new()
@@ -183,10 +346,8 @@ pub fn seed_entropy(
     // See: https://cdn.kernel.org/pub/linux/kernel/v5.x/ChangeLog-5.10.119
     match random_fd.write_all(&entropy_sample) {
         Ok(()) => Ok(entropy_sample.len()),
-        Err(_) => {
-            return Err(SystemError {
-                message: String::from("Failed to write to /dev/urandom"),
-            });
-        }
+        Err(_) => Err(SystemError {
+            message: String::from("Failed to write to /dev/urandom"),
+        }),
     }
 }